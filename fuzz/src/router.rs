@@ -9,7 +9,9 @@
 
 use bitcoin::blockdata::script::{Script, Builder};
 use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::hash_types::{Txid, BlockHash};
+use bitcoin::network::constants::Network;
 
 use lightning::chain::chaininterface::{ChainError,ChainWatchInterface};
 use lightning::ln::channelmanager::ChannelDetails;
@@ -17,6 +19,7 @@ use lightning::ln::features::InitFeatures;
 use lightning::ln::msgs;
 use lightning::ln::msgs::RoutingMessageHandler;
 use lightning::routing::router::{get_route, RouteHint};
+use lightning::routing::scorer::Scorer;
 use lightning::util::logger::Logger;
 use lightning::util::ser::Readable;
 use lightning::routing::network_graph::{NetGraphMsgHandler, RoutingFees};
@@ -165,7 +168,7 @@ pub fn do_test<Out: test_logger::Output>(data: &[u8], out: Out) {
 	});
 
 	let our_pubkey = get_pubkey!();
-	let net_graph_msg_handler = NetGraphMsgHandler::new(chain_monitor, Arc::clone(&logger));
+	let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.block_hash(), chain_monitor, Arc::clone(&logger));
 
 	loop {
 		match get_slice!(1)[0] {
@@ -236,10 +239,10 @@ pub fn do_test<Out: test_logger::Output>(data: &[u8], out: Out) {
 					}
 					&last_hops_vec[..]
 				};
-				let _ = get_route(&our_pubkey, &net_graph_msg_handler.network_graph.read().unwrap(), &target,
+				let _ = get_route(&our_pubkey, &net_graph_msg_handler.network_graph.read().unwrap(), &target, None,
 					first_hops.map(|c| c.iter().collect::<Vec<_>>()).as_ref().map(|a| a.as_slice()),
 					&last_hops.iter().collect::<Vec<_>>(),
-					slice_to_be64(get_slice!(8)), slice_to_be32(get_slice!(4)), Arc::clone(&logger));
+					slice_to_be64(get_slice!(8)), slice_to_be32(get_slice!(4)), Arc::clone(&logger), &Scorer::default());
 			},
 			_ => return,
 		}
@@ -0,0 +1,382 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Utilities that take care of tasks that (1) need to happen periodically to keep Rust-Lightning
+//! running properly, and (2) either can or should be run in the background.
+//!
+//! See [`BackgroundProcessor`] for details.
+//!
+//! [`BackgroundProcessor`]: struct.BackgroundProcessor.html
+
+#![deny(missing_docs)]
+
+use lightning::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use lightning::chain::keysinterface::{ChannelKeys, KeysInterface};
+use lightning::ln::channelmanager::ChannelManager;
+use lightning::ln::channelmonitor::ManyChannelMonitor;
+use lightning::ln::msgs::{ChannelMessageHandler, RoutingMessageHandler};
+use lightning::ln::peer_handler::{PeerManager, SocketDescriptor};
+use lightning::util::events::{Event, EventsProvider};
+use lightning::util::logger::{Level, Logger, Record};
+use lightning::util::ser::Writeable;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// BackgroundProcessor takes care of tasks that (1) need to happen periodically to keep
+/// Rust-Lightning running properly, and (2) either can or should be run in the background. Its
+/// responsibilities are:
+/// * Monitoring whether the ChannelManager needs to be re-persisted to disk, and if so,
+///   writing it to disk/backups by invoking the callback given to it at startup.
+///   ChannelManager persistence should be done in the background.
+/// * Handing the ChannelManager's pending events to the user-provided event handler, and calling
+///   ChannelManager::process_pending_htlc_forwards once the delay requested by any
+///   PendingHTLCsForwardable event has elapsed.
+/// * Calling PeerManager::process_events so that messages generated by the ChannelManager are
+///   sent to peers.
+/// * Calling ChannelManager::timer_chan_freshness_every_min() and
+///   PeerManager::timer_tick_occured() every minute (can be done in the background).
+///
+/// Note that if ChannelManager persistence fails and the persisted manager becomes out-of-date,
+/// then there is a risk of channels force-closing on startup when the manager realizes it's
+/// outdated. However, as long as ChannelMonitor backups are sound, no funds besides those used
+/// for unilateral chain closure fees are at risk.
+///
+/// Events generated by the ManyChannelMonitor are not handled here and must still be polled for by
+/// the user.
+pub struct BackgroundProcessor {
+	stop_thread: Arc<AtomicBool>,
+	/// May be used to retrieve and handle the error if `BackgroundProcessor`'s thread
+	/// exits due to an error while persisting.
+	pub thread_handle: JoinHandle<Result<(), std::io::Error>>,
+}
+
+#[cfg(not(test))]
+const CHAN_FRESHNESS_TIMER: u64 = 60;
+#[cfg(test)]
+const CHAN_FRESHNESS_TIMER: u64 = 1;
+
+/// The maximum amount of time we block waiting for a ChannelManager update before checking
+/// whether we need to stop, forward HTLCs or fire the timers.
+const PERSISTENCE_POLL_INTERVAL_MS: u64 = 100;
+
+impl BackgroundProcessor {
+	/// Start a background thread that takes care of responsibilities enumerated in the top-level
+	/// documentation.
+	///
+	/// If `persist_manager` returns an error, then this thread will return said error (and
+	/// `start()` will need to be called again to restart the `BackgroundProcessor`). Users should
+	/// wait on [`thread_handle`]'s `join()` method to be able to tell if and when an error is
+	/// returned, or implement `persist_manager` such that an error is never returned to the
+	/// `BackgroundProcessor`.
+	///
+	/// `persist_manager` is responsible for writing out the `ChannelManager` to disk, and/or
+	/// uploading to one or more backup services. See [`ChannelManager::write`] for writing out a
+	/// `ChannelManager`. It is called whenever the `ChannelManager` may have changed, as well as
+	/// once more when the `BackgroundProcessor` is stopped.
+	///
+	/// `event_handler` is called with every event generated by the `ChannelManager`, including
+	/// `PendingHTLCsForwardable` events, which are otherwise handled by the `BackgroundProcessor`
+	/// and need no further action by the user. To have failed payments retried, wrap your event
+	/// handler in a [`PaymentRetrier`] and pass a closure calling its `handle_event` here instead.
+	/// Its `timer_tick_occurred` must then be called by the user roughly once per minute.
+	///
+	/// [`PaymentRetrier`]: ../lightning/ln/payment_retry/struct.PaymentRetrier.html
+	/// [`thread_handle`]: struct.BackgroundProcessor.html#structfield.thread_handle
+	/// [`ChannelManager::write`]: ../lightning/ln/channelmanager/struct.ChannelManager.html#method.write
+	pub fn start<PM, EH, ChanSigner, M, T, K, F, L, Descriptor: 'static + SocketDescriptor + Send, CM, RM>(
+		persist_manager: PM, event_handler: EH,
+		channel_manager: Arc<ChannelManager<ChanSigner, Arc<M>, Arc<T>, Arc<K>, Arc<F>, Arc<L>>>,
+		peer_manager: Arc<PeerManager<Descriptor, Arc<CM>, Arc<RM>, Arc<L>>>, logger: Arc<L>,
+	) -> Self
+	where
+		ChanSigner: 'static + ChannelKeys + Writeable,
+		PM: 'static + Send + Fn(&ChannelManager<ChanSigner, Arc<M>, Arc<T>, Arc<K>, Arc<F>, Arc<L>>) -> Result<(), std::io::Error>,
+		EH: 'static + Send + Fn(Event),
+		M: 'static + ManyChannelMonitor<Keys = ChanSigner>,
+		T: 'static + BroadcasterInterface,
+		K: 'static + KeysInterface<ChanKeySigner = ChanSigner>,
+		F: 'static + FeeEstimator,
+		L: 'static + Logger,
+		CM: 'static + ChannelMessageHandler,
+		RM: 'static + RoutingMessageHandler,
+	{
+		let stop_thread = Arc::new(AtomicBool::new(false));
+		let stop_thread_clone = stop_thread.clone();
+		let handle = thread::spawn(move || -> Result<(), std::io::Error> {
+			let mut last_freshness_call = Instant::now();
+			let mut forward_htlcs_at: Option<Instant> = None;
+			loop {
+				peer_manager.process_events();
+
+				for event in channel_manager.get_and_clear_pending_events() {
+					if let Event::PendingHTLCsForwardable { time_forwardable } = event {
+						let forward_at = Instant::now() + time_forwardable;
+						forward_htlcs_at = Some(match forward_htlcs_at {
+							Some(scheduled) if scheduled < forward_at => scheduled,
+							_ => forward_at,
+						});
+					}
+					event_handler(event);
+				}
+
+				if let Some(forward_at) = forward_htlcs_at {
+					if Instant::now() >= forward_at {
+						forward_htlcs_at = None;
+						channel_manager.process_pending_htlc_forwards();
+					}
+				}
+
+				let updates_available =
+					channel_manager.await_persistable_update_timeout(Duration::from_millis(PERSISTENCE_POLL_INTERVAL_MS));
+				if updates_available {
+					persist_manager(&*channel_manager)?;
+				}
+
+				// Exit the loop if the background processor was requested to stop.
+				if stop_thread.load(Ordering::Acquire) {
+					logger.log(&Record::new(Level::Trace, format_args!("Terminating background processor."), module_path!(), file!(), line!()));
+					return persist_manager(&*channel_manager);
+				}
+
+				if last_freshness_call.elapsed().as_secs() >= CHAN_FRESHNESS_TIMER {
+					logger.log(&Record::new(Level::Trace, format_args!("Calling manager's timer_chan_freshness_every_min"), module_path!(), file!(), line!()));
+					channel_manager.timer_chan_freshness_every_min();
+					peer_manager.timer_tick_occured();
+					last_freshness_call = Instant::now();
+				}
+			}
+		});
+		Self {
+			stop_thread: stop_thread_clone,
+			thread_handle: handle,
+		}
+	}
+
+	/// Stop `BackgroundProcessor`'s thread, persisting the `ChannelManager` a final time.
+	///
+	/// Returns the result of the last persistence attempt, or of whichever earlier persistence
+	/// attempt failed and caused the thread to exit.
+	pub fn stop(self) -> Result<(), std::io::Error> {
+		self.stop_thread.store(true, Ordering::Release);
+		match self.thread_handle.join() {
+			Ok(res) => res,
+			Err(_) => Err(std::io::Error::new(std::io::ErrorKind::Other, "background processor thread panicked")),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::constants::genesis_block;
+	use bitcoin::network::constants::Network;
+	use lightning::chain::chaininterface::{BroadcasterInterface, ChainWatchInterfaceUtil, ConfirmationTarget, FeeEstimator};
+	use lightning::chain::keysinterface::{InMemoryChannelKeys, KeysInterface, KeysManager};
+	use lightning::chain::transaction::OutPoint;
+	use lightning::ln::channelmanager::{ChainParameters, ChannelManager};
+	use lightning::ln::channelmonitor::SimpleManyChannelMonitor;
+	use lightning::ln::features::InitFeatures;
+	use lightning::ln::msgs::ChannelMessageHandler;
+	use lightning::ln::payment_retry::{PaymentRetrier, RetryConfig};
+	use lightning::ln::peer_handler::{MessageHandler, PeerManager, SocketDescriptor};
+	use lightning::routing::network_graph::NetGraphMsgHandler;
+	use lightning::routing::scorer::Scorer;
+	use lightning::util::config::UserConfig;
+	use lightning::util::events::{Event, MessageSendEvent, MessageSendEventsProvider};
+	use lightning::util::logger::{Logger, Record};
+	use lightning::util::ser::Writeable;
+	use bitcoin::blockdata::transaction::Transaction;
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+	use super::{BackgroundProcessor, CHAN_FRESHNESS_TIMER};
+
+	#[derive(Clone, Eq, Hash, PartialEq)]
+	struct TestDescriptor{}
+	impl SocketDescriptor for TestDescriptor {
+		fn send_data(&mut self, _data: &[u8], _resume_read: bool) -> usize {
+			0
+		}
+
+		fn disconnect_socket(&mut self) {}
+	}
+
+	struct TestFeeEstimator;
+	impl FeeEstimator for TestFeeEstimator {
+		fn get_est_sat_per_1000_weight(&self, _: ConfirmationTarget) -> u32 { 253 }
+	}
+
+	struct TestBroadcaster;
+	impl BroadcasterInterface for TestBroadcaster {
+		fn broadcast_transaction(&self, _tx: &Transaction) {}
+	}
+
+	struct TestLogger { lines: Mutex<Vec<String>> }
+	impl TestLogger {
+		fn new() -> Self { Self { lines: Mutex::new(Vec::new()) } }
+		fn contains(&self, line: &str) -> bool {
+			self.lines.lock().unwrap().iter().any(|l| l == line)
+		}
+	}
+	impl Logger for TestLogger {
+		fn log(&self, record: &Record) {
+			self.lines.lock().unwrap().push(format!("{}", record.args));
+		}
+	}
+
+	type ChainMonitor = SimpleManyChannelMonitor<OutPoint, InMemoryChannelKeys, Arc<TestBroadcaster>, Arc<TestFeeEstimator>, Arc<TestLogger>, Arc<ChainWatchInterfaceUtil>>;
+
+	type Manager = ChannelManager<InMemoryChannelKeys, Arc<ChainMonitor>, Arc<TestBroadcaster>, Arc<KeysManager>, Arc<TestFeeEstimator>, Arc<TestLogger>>;
+	type RouteHandler = NetGraphMsgHandler<Arc<ChainWatchInterfaceUtil>, Arc<TestLogger>>;
+	type TestPeerManager = PeerManager<TestDescriptor, Arc<Manager>, Arc<RouteHandler>, Arc<TestLogger>>;
+
+	struct Node {
+		node: Arc<Manager>,
+		net_graph_msg_handler: Arc<RouteHandler>,
+		peer_manager: Arc<TestPeerManager>,
+		logger: Arc<TestLogger>,
+	}
+
+	fn create_nodes(num_nodes: usize) -> Vec<Node> {
+		let mut nodes = Vec::new();
+		for i in 0..num_nodes {
+			let fee_estimator = Arc::new(TestFeeEstimator);
+			let tx_broadcaster = Arc::new(TestBroadcaster);
+			let logger = Arc::new(TestLogger::new());
+			let chain_watch = Arc::new(ChainWatchInterfaceUtil::new(Network::Testnet));
+			let chain_monitor = Arc::new(SimpleManyChannelMonitor::new(chain_watch.clone(), tx_broadcaster.clone(), logger.clone(), fee_estimator.clone()));
+			let seed = [i as u8; 32];
+			let keys_manager = Arc::new(KeysManager::new(&seed, Network::Testnet, 42, 42));
+			let node_secret = keys_manager.get_node_secret();
+			let params = ChainParameters { network: Network::Testnet, latest_hash: genesis_block(Network::Testnet).header.block_hash(), latest_height: 0 };
+			let node = Arc::new(ChannelManager::new(fee_estimator, chain_monitor, tx_broadcaster, logger.clone(), keys_manager, UserConfig::default(), params));
+			let net_graph_msg_handler = Arc::new(NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.block_hash(), chain_watch, logger.clone()));
+			let msg_handler = MessageHandler { chan_handler: node.clone(), route_handler: net_graph_msg_handler.clone() };
+			let peer_manager = Arc::new(PeerManager::new(msg_handler, node_secret, &[i as u8; 32], logger.clone()));
+			nodes.push(Node { node, net_graph_msg_handler, peer_manager, logger });
+		}
+		nodes
+	}
+
+	// Polls `condition` for up to five seconds, returning whether it became true.
+	fn wait_for<C: Fn() -> bool>(condition: C) -> bool {
+		for _ in 0..500 {
+			if condition() { return true; }
+			std::thread::sleep(Duration::from_millis(10));
+		}
+		false
+	}
+
+	#[test]
+	fn test_background_processor_persists_on_update() {
+		let nodes = create_nodes(2);
+		let persisted: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+		let persisted_clone = persisted.clone();
+		let persister = move |node: &Manager| {
+			persisted_clone.lock().unwrap().push(node.encode());
+			Ok(())
+		};
+		let bg_processor = BackgroundProcessor::start(persister, |_| {}, nodes[0].node.clone(), nodes[0].peer_manager.clone(), nodes[0].logger.clone());
+
+		// Opening a channel updates the ChannelManager, which should result in it being persisted
+		// with the new channel included.
+		let expected = {
+			nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+			nodes[0].node.encode()
+		};
+		assert!(wait_for(|| persisted.lock().unwrap().contains(&expected)));
+
+		// Stopping the processor persists one last time.
+		let persist_count = persisted.lock().unwrap().len();
+		assert!(bg_processor.stop().is_ok());
+		assert_eq!(persisted.lock().unwrap().len(), persist_count + 1);
+	}
+
+	#[test]
+	fn test_timer_tick_called() {
+		// Test that ChannelManager's and PeerManager's timers are called every
+		// CHAN_FRESHNESS_TIMER.
+		let nodes = create_nodes(1);
+		let bg_processor = BackgroundProcessor::start(|_: &_| Ok(()), |_| {}, nodes[0].node.clone(), nodes[0].peer_manager.clone(), nodes[0].logger.clone());
+		assert!(wait_for(|| nodes[0].logger.contains("Calling manager's timer_chan_freshness_every_min")));
+		assert!(CHAN_FRESHNESS_TIMER < 5);
+		assert!(bg_processor.stop().is_ok());
+		assert!(nodes[0].logger.contains("Terminating background processor."));
+	}
+
+	#[test]
+	fn test_persist_error() {
+		// Test that if we encounter an error during manager persistence, the thread exits and
+		// returns the error.
+		let nodes = create_nodes(2);
+		let persister = |_: &_| Err(std::io::Error::new(std::io::ErrorKind::Other, "test"));
+		let bg_processor = BackgroundProcessor::start(persister, |_| {}, nodes[0].node.clone(), nodes[0].peer_manager.clone(), nodes[0].logger.clone());
+		nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+		match bg_processor.thread_handle.join() {
+			Ok(Err(e)) => assert_eq!(e.kind(), std::io::ErrorKind::Other),
+			_ => panic!("Expected the background processor to exit with the persistence error"),
+		}
+	}
+
+	#[test]
+	fn test_events_passed_to_handler() {
+		// Test that ChannelManager events reach the user's event handler.
+		let nodes = create_nodes(2);
+		nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+		let open_chan = match nodes[0].node.get_and_clear_pending_msg_events().pop() {
+			Some(MessageSendEvent::SendOpenChannel { msg, .. }) => msg,
+			_ => panic!("Expected open_channel"),
+		};
+		nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_chan);
+		let accept_chan = match nodes[1].node.get_and_clear_pending_msg_events().pop() {
+			Some(MessageSendEvent::SendAcceptChannel { msg, .. }) => msg,
+			_ => panic!("Expected accept_channel"),
+		};
+
+		let events: Arc<Mutex<Vec<Event>>> = Arc::new(Mutex::new(Vec::new()));
+		let events_clone = events.clone();
+		let handler = move |event: Event| events_clone.lock().unwrap().push(event);
+		let bg_processor = BackgroundProcessor::start(|_: &_| Ok(()), handler, nodes[0].node.clone(), nodes[0].peer_manager.clone(), nodes[0].logger.clone());
+
+		// Accepting the channel generates a FundingGenerationReady event on nodes[0].
+		nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_chan);
+		assert!(wait_for(|| events.lock().unwrap().iter().any(|e| match e { Event::FundingGenerationReady { user_channel_id: 42, .. } => true, _ => false })));
+		assert!(bg_processor.stop().is_ok());
+	}
+
+	#[test]
+	fn test_payment_retrier_as_event_handler() {
+		// Test that a PaymentRetrier can sit in front of the user's event handler, passing on the
+		// events which don't concern its payments.
+		let nodes = create_nodes(2);
+		nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+		let open_chan = match nodes[0].node.get_and_clear_pending_msg_events().pop() {
+			Some(MessageSendEvent::SendOpenChannel { msg, .. }) => msg,
+			_ => panic!("Expected open_channel"),
+		};
+		nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_chan);
+		let accept_chan = match nodes[1].node.get_and_clear_pending_msg_events().pop() {
+			Some(MessageSendEvent::SendAcceptChannel { msg, .. }) => msg,
+			_ => panic!("Expected accept_channel"),
+		};
+
+		let events: Arc<Mutex<Vec<Event>>> = Arc::new(Mutex::new(Vec::new()));
+		let events_clone = events.clone();
+		let handler = move |event: Event| events_clone.lock().unwrap().push(event);
+		let retrier = Arc::new(PaymentRetrier::new(nodes[0].node.clone(), nodes[0].net_graph_msg_handler.clone(), nodes[0].logger.clone(), Arc::new(Scorer::default()), handler, RetryConfig::default()));
+		let retrier_clone = retrier.clone();
+		let bg_processor = BackgroundProcessor::start(|_: &_| Ok(()), move |event| retrier_clone.handle_event(event), nodes[0].node.clone(), nodes[0].peer_manager.clone(), nodes[0].logger.clone());
+
+		nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_chan);
+		assert!(wait_for(|| events.lock().unwrap().iter().any(|e| match e { Event::FundingGenerationReady { user_channel_id: 42, .. } => true, _ => false })));
+		retrier.timer_tick_occurred();
+		assert!(bg_processor.stop().is_ok());
+	}
+}
@@ -0,0 +1,194 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Conversions from Bitcoin Core's REST and RPC response formats.
+
+use crate::BlockHeaderData;
+
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::consensus::encode;
+use bitcoin::hash_types::{BlockHash, TxMerkleNode};
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::util::bip158::BlockFilter;
+use bitcoin::util::uint::Uint256;
+
+use serde_json::Value;
+
+use std::io;
+
+fn invalid_data(msg: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Parses a JSON response body.
+pub(crate) fn json_from_bytes(bytes: &[u8]) -> io::Result<Value> {
+	serde_json::from_slice(bytes).map_err(|_| invalid_data("invalid JSON"))
+}
+
+/// Parses a block header in the JSON format returned by Bitcoin Core's `getblockheader` RPC and
+/// `headers` REST endpoint.
+pub(crate) fn header_data_from_json(value: &Value) -> io::Result<BlockHeaderData> {
+	fn get_u32(value: &Value, field: &'static str) -> io::Result<u32> {
+		match value[field].as_u64() {
+			Some(n) if n <= u32::max_value() as u64 => Ok(n as u32),
+			_ => Err(invalid_data(field)),
+		}
+	}
+	fn get_str<'a>(value: &'a Value, field: &'static str) -> io::Result<&'a str> {
+		value[field].as_str().ok_or_else(|| invalid_data(field))
+	}
+
+	let version = match value["version"].as_i64() {
+		Some(n) if n >= i32::min_value() as i64 && n <= i32::max_value() as i64 => n as i32,
+		_ => return Err(invalid_data("version")),
+	};
+	// The genesis block has no previousblockhash.
+	let prev_blockhash = match value.get("previousblockhash") {
+		None => Default::default(),
+		Some(_) => BlockHash::from_hex(get_str(value, "previousblockhash")?).map_err(|_| invalid_data("previousblockhash"))?,
+	};
+	let merkle_root = TxMerkleNode::from_hex(get_str(value, "merkleroot")?).map_err(|_| invalid_data("merkleroot"))?;
+	let bits = u32::from_str_radix(get_str(value, "bits")?, 16).map_err(|_| invalid_data("bits"))?;
+
+	let chainwork_bytes = Vec::<u8>::from_hex(get_str(value, "chainwork")?).map_err(|_| invalid_data("chainwork"))?;
+	if chainwork_bytes.len() != 32 {
+		return Err(invalid_data("chainwork"));
+	}
+	let mut chainwork = [0; 32];
+	chainwork.copy_from_slice(&chainwork_bytes);
+
+	Ok(BlockHeaderData {
+		header: BlockHeader {
+			version,
+			prev_blockhash,
+			merkle_root,
+			time: get_u32(value, "time")?,
+			bits,
+			nonce: get_u32(value, "nonce")?,
+		},
+		height: get_u32(value, "height")?,
+		chainwork: Uint256::from_be_bytes(chainwork),
+	})
+}
+
+/// Parses the best block hash and height from the JSON format returned by Bitcoin Core's
+/// `getblockchaininfo` RPC and `chaininfo` REST endpoint.
+pub(crate) fn best_block_from_json(value: &Value) -> io::Result<(BlockHash, Option<u32>)> {
+	let hash = match value["bestblockhash"].as_str() {
+		Some(hex) => BlockHash::from_hex(hex).map_err(|_| invalid_data("bestblockhash"))?,
+		None => return Err(invalid_data("bestblockhash")),
+	};
+	let height = match value["blocks"] {
+		Value::Null => None,
+		ref blocks => match blocks.as_u64() {
+			Some(n) if n <= u32::max_value() as u64 => Some(n as u32),
+			_ => return Err(invalid_data("blocks")),
+		},
+	};
+	Ok((hash, height))
+}
+
+/// Parses a consensus-encoded block.
+pub(crate) fn block_from_bytes(bytes: &[u8]) -> io::Result<Block> {
+	encode::deserialize(bytes).map_err(|_| invalid_data("invalid block"))
+}
+
+/// Parses a hex-encoded, consensus-encoded block as returned by Bitcoin Core's `getblock` RPC.
+pub(crate) fn block_from_json(value: &Value) -> io::Result<Block> {
+	match value.as_str() {
+		Some(hex) => block_from_bytes(&Vec::<u8>::from_hex(hex).map_err(|_| invalid_data("invalid hex"))?),
+		None => Err(invalid_data("expected a hex string")),
+	}
+}
+
+/// Parses the basic filter returned by Bitcoin Core's `getblockfilter` RPC.
+pub(crate) fn filter_from_json(value: &Value) -> io::Result<BlockFilter> {
+	match value["filter"].as_str() {
+		Some(hex) => Ok(BlockFilter::new(&Vec::<u8>::from_hex(hex).map_err(|_| invalid_data("invalid hex"))?)),
+		None => Err(invalid_data("expected a hex filter")),
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use bitcoin::blockdata::constants::genesis_block;
+	use bitcoin::hashes::hex::ToHex;
+	use bitcoin::network::constants::Network;
+	use serde_json::json;
+
+	/// Converts header data into the JSON format Bitcoin Core would return for it.
+	pub(crate) fn header_data_to_json(header_data: &BlockHeaderData) -> Value {
+		let mut value = json!({
+			"hash": header_data.header.block_hash().to_hex(),
+			"height": header_data.height,
+			"version": header_data.header.version,
+			"merkleroot": header_data.header.merkle_root.to_hex(),
+			"time": header_data.header.time,
+			"nonce": header_data.header.nonce,
+			"bits": format!("{:08x}", header_data.header.bits),
+			"chainwork": chainwork_to_hex(&header_data.chainwork),
+		});
+		if header_data.height > 0 {
+			value["previousblockhash"] = json!(header_data.header.prev_blockhash.to_hex());
+		}
+		value
+	}
+
+	fn chainwork_to_hex(chainwork: &Uint256) -> String {
+		let mut hex = String::new();
+		for word in chainwork.0.iter().rev() {
+			hex.push_str(&format!("{:016x}", word));
+		}
+		hex
+	}
+
+	#[test]
+	fn header_data_round_trips_through_json() {
+		let genesis = genesis_block(Network::Bitcoin);
+		let header_data = BlockHeaderData { header: genesis.header, height: 0, chainwork: genesis.header.work() };
+		let value = header_data_to_json(&header_data);
+		assert_eq!(value["chainwork"], "0000000000000000000000000000000000000000000000000000000100010001");
+		assert_eq!(header_data_from_json(&value).unwrap(), header_data);
+	}
+
+	#[test]
+	fn header_data_from_invalid_json() {
+		let genesis = genesis_block(Network::Bitcoin);
+		let header_data = BlockHeaderData { header: genesis.header, height: 0, chainwork: genesis.header.work() };
+		for field in &["version", "merkleroot", "time", "nonce", "bits", "height", "chainwork"] {
+			let mut value = header_data_to_json(&header_data);
+			value[*field] = json!("zz");
+			assert_eq!(header_data_from_json(&value).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		}
+		let mut value = header_data_to_json(&header_data);
+		value["chainwork"] = json!("0001");
+		assert_eq!(header_data_from_json(&value).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn best_block_from_chain_info() {
+		let genesis_hash = genesis_block(Network::Bitcoin).header.block_hash();
+		let value = json!({ "bestblockhash": genesis_hash.to_hex(), "blocks": 0 });
+		assert_eq!(best_block_from_json(&value).unwrap(), (genesis_hash, Some(0)));
+		let value = json!({ "bestblockhash": genesis_hash.to_hex() });
+		assert_eq!(best_block_from_json(&value).unwrap(), (genesis_hash, None));
+		let value = json!({ "bestblockhash": "foo" });
+		assert_eq!(best_block_from_json(&value).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn block_from_hex() {
+		let genesis = genesis_block(Network::Bitcoin);
+		let hex = encode::serialize(&genesis).to_hex();
+		assert_eq!(block_from_json(&json!(hex)).unwrap(), genesis);
+		assert_eq!(block_from_json(&json!("00")).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		assert_eq!(block_from_json(&json!(42)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+}
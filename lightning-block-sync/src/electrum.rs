@@ -0,0 +1,783 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Chain sync against an Electrum server, which is queried for the transactions paying to or
+//! spending from watched scripts rather than for full blocks.
+//!
+//! [`ElectrumClient`] speaks the Electrum protocol (newline-delimited JSON-RPC over TCP) and can
+//! fetch confirmed transactions along with a merkle proof of their inclusion in a block.
+//!
+//! [`ElectrumChainSync`] then keeps `ConfirmationListener`s (eg a `ChannelManager` and a
+//! `SimpleManyChannelMonitor`) in sync with the server, looking up the history of each script
+//! watched by a `FilteredChainWatchInterface` and notifying listeners of transactions confirmed and
+//! unconfirmed as well as of the best block.
+//!
+//! Note that the server is trusted to serve the best chain and the complete history of each
+//! script: headers are only checked against their proof-of-work target, and transactions against
+//! the merkle root of the block they are said to be confirmed in.
+//!
+//! [`ElectrumClient`]: struct.ElectrumClient.html
+//! [`ElectrumChainSync`]: struct.ElectrumChainSync.html
+
+use crate::{BlockSourceError, BlockSourceResult};
+use crate::filter::FilteredChainWatchInterface;
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use bitcoin::hash_types::{BlockHash, Txid};
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::sha256d;
+
+use lightning::chain::chaininterface::{ChainWatchInterface, ConfirmationListener};
+
+use serde_json::{json, Value};
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Maximum length of a single response line, which may hold a large transaction in hex.
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The timeout for reads from and writes to the server.
+const TCP_STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The version of the Electrum protocol negotiated on connection.
+const PROTOCOL_VERSION: &str = "1.4";
+
+fn invalid_data(msg: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// An error returned by the Electrum server in response to a call.
+///
+/// Returned by `ElectrumClient::call_method` as the inner error of an `io::Error` with kind
+/// `Other`.
+#[derive(Debug)]
+pub struct ElectrumError {
+	/// The error code given by the server.
+	pub code: i64,
+	/// The error message.
+	pub message: String,
+}
+
+impl std::error::Error for ElectrumError {}
+
+impl std::fmt::Display for ElectrumError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "Electrum error {}: {}", self.code, self.message)
+	}
+}
+
+/// A simple client for calling methods on an Electrum server over a single TCP connection.
+pub struct ElectrumClient {
+	stream: Mutex<BufReader<TcpStream>>,
+	id: AtomicUsize,
+}
+
+impl ElectrumClient {
+	/// Connects to the Electrum server at the given address and negotiates the protocol version.
+	pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+		let stream = TcpStream::connect(address)?;
+		stream.set_read_timeout(Some(TCP_STREAM_TIMEOUT))?;
+		stream.set_write_timeout(Some(TCP_STREAM_TIMEOUT))?;
+		let client = Self { stream: Mutex::new(BufReader::new(stream)), id: AtomicUsize::new(0) };
+		client.call_method("server.version", &[json!("lightning-block-sync"), json!(PROTOCOL_VERSION)])?;
+		Ok(client)
+	}
+
+	/// Calls a method, returning its result.
+	///
+	/// Any notifications received while awaiting the response are skipped. Errors returned by the
+	/// server are returned as an `io::Error` wrapping an `ElectrumError`.
+	pub fn call_method(&self, method: &str, params: &[Value]) -> io::Result<Value> {
+		let id = self.id.fetch_add(1, Ordering::AcqRel);
+		let mut request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }).to_string();
+		request.push('\n');
+
+		let mut stream = self.stream.lock().unwrap();
+		stream.get_mut().write_all(request.as_bytes())?;
+		loop {
+			let mut line = String::new();
+			if (&mut *stream).take(MAX_RESPONSE_SIZE).read_line(&mut line)? == 0 {
+				return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+			}
+			if !line.ends_with('\n') {
+				return Err(invalid_data("response too large"));
+			}
+			let mut response: Value = serde_json::from_str(&line).map_err(|_| invalid_data("invalid JSON"))?;
+			if !response.is_object() {
+				return Err(invalid_data("expected JSON object"));
+			}
+			// Notifications carry no id, and responses to earlier calls which failed part way
+			// through may still be in flight.
+			if response["id"].as_u64() != Some(id as u64) {
+				continue;
+			}
+			let error = &response["error"];
+			if !error.is_null() {
+				let electrum_error = ElectrumError {
+					code: error["code"].as_i64().unwrap_or(0),
+					message: error["message"].as_str().unwrap_or("").to_string(),
+				};
+				return Err(io::Error::new(io::ErrorKind::Other, electrum_error));
+			}
+			return match response.get_mut("result") {
+				Some(result) => Ok(result.take()),
+				None => Err(invalid_data("expected JSON result")),
+			};
+		}
+	}
+
+	/// Gets the header and height of the server's best block.
+	pub fn get_tip(&self) -> io::Result<(BlockHeader, u32)> {
+		let result = self.call_method("blockchain.headers.subscribe", &[])?;
+		let height = match result["height"].as_u64() {
+			Some(height) if height <= u32::max_value() as u64 => height as u32,
+			_ => return Err(invalid_data("height")),
+		};
+		Ok((header_from_json(&result["hex"])?, height))
+	}
+
+	/// Gets the header of the block at the given height in the server's best chain.
+	pub fn get_header(&self, height: u32) -> io::Result<BlockHeader> {
+		header_from_json(&self.call_method("blockchain.block.header", &[json!(height)])?)
+	}
+
+	/// Gets the txids of the transactions paying to or spending from the given script, each with
+	/// the height of the block it was confirmed in. Unconfirmed transactions are not included.
+	pub fn get_script_history(&self, script: &Script) -> io::Result<Vec<(Txid, u32)>> {
+		let result = self.call_method("blockchain.scripthash.get_history", &[json!(script_hash(script))])?;
+		let entries = result.as_array().ok_or_else(|| invalid_data("expected JSON array"))?;
+		let mut history = Vec::with_capacity(entries.len());
+		for entry in entries {
+			let txid = entry["tx_hash"].as_str().and_then(|hex| Txid::from_hex(hex).ok()).ok_or_else(|| invalid_data("tx_hash"))?;
+			// Mempool transactions have a height of 0, or -1 if they have unconfirmed inputs.
+			match entry["height"].as_i64() {
+				Some(height) if height > 0 && height <= u32::max_value() as i64 => history.push((txid, height as u32)),
+				Some(_) => {},
+				None => return Err(invalid_data("height")),
+			}
+		}
+		Ok(history)
+	}
+
+	/// Gets the transaction with the given txid.
+	pub fn get_transaction(&self, txid: &Txid) -> io::Result<Transaction> {
+		let result = self.call_method("blockchain.transaction.get", &[json!(txid.to_hex())])?;
+		let tx: Transaction = deserialize_hex(&result)?;
+		if tx.txid() != *txid {
+			return Err(invalid_data("invalid txid"));
+		}
+		Ok(tx)
+	}
+
+	/// Gets the transaction with the given txid, said to be confirmed at the given height, along
+	/// with the header of the block it was confirmed in and its index in that block.
+	///
+	/// The block header must meet its proof-of-work target, and the transaction's inclusion in the
+	/// block is checked against the merkle proof provided by the server.
+	pub fn get_confirmed_transaction(&self, txid: &Txid, height: u32) -> io::Result<(BlockHeader, usize, Transaction)> {
+		let header = self.get_header(height)?;
+		let result = self.call_method("blockchain.transaction.get_merkle", &[json!(txid.to_hex()), json!(height)])?;
+		let pos = result["pos"].as_u64().ok_or_else(|| invalid_data("pos"))? as usize;
+		let branch = result["merkle"].as_array().ok_or_else(|| invalid_data("merkle"))?.iter()
+			.map(|node| node.as_str().and_then(|hex| sha256d::Hash::from_hex(hex).ok()).ok_or_else(|| invalid_data("merkle")))
+			.collect::<io::Result<Vec<_>>>()?;
+		if !verify_merkle_proof(txid, pos, &branch, &header) {
+			return Err(invalid_data("invalid merkle proof"));
+		}
+		Ok((header, pos, self.get_transaction(txid)?))
+	}
+}
+
+/// Computes the hash by which Electrum servers index a script: its SHA256, in reverse byte order.
+fn script_hash(script: &Script) -> String {
+	let mut hash = sha256::Hash::hash(script.as_bytes()).into_inner();
+	hash.reverse();
+	hash.to_hex()
+}
+
+fn deserialize_hex<T: encode::Decodable>(value: &Value) -> io::Result<T> {
+	let bytes = value.as_str().and_then(|hex| Vec::<u8>::from_hex(hex).ok()).ok_or_else(|| invalid_data("expected hex string"))?;
+	encode::deserialize(&bytes).map_err(|_| invalid_data("invalid encoding"))
+}
+
+/// Parses a hex-encoded header, checking that it meets its proof-of-work target.
+fn header_from_json(value: &Value) -> io::Result<BlockHeader> {
+	let header: BlockHeader = deserialize_hex(value)?;
+	if header.validate_pow(&header.target()).is_err() {
+		return Err(invalid_data("block header does not meet its proof-of-work target"));
+	}
+	Ok(header)
+}
+
+/// Checks that the merkle branch proves the inclusion of `txid` at index `pos` in the block with
+/// the given header.
+fn verify_merkle_proof(txid: &Txid, pos: usize, branch: &[sha256d::Hash], header: &BlockHeader) -> bool {
+	if branch.len() >= std::mem::size_of::<usize>() * 8 || pos >> branch.len() != 0 {
+		return false;
+	}
+	let mut node = txid.as_hash();
+	for (level, sibling) in branch.iter().enumerate() {
+		let mut engine = sha256d::Hash::engine();
+		if (pos >> level) & 1 == 0 {
+			engine.input(&node[..]);
+			engine.input(&sibling[..]);
+		} else {
+			engine.input(&sibling[..]);
+			engine.input(&node[..]);
+		}
+		node = sha256d::Hash::from_engine(engine);
+	}
+	node == header.merkle_root.as_hash()
+}
+
+/// Maps an error from the client to a `BlockSourceError`: malformed or invalid responses are
+/// persistent, while connection failures and errors returned by the server may be transient.
+fn map_client_error(e: io::Error) -> BlockSourceError {
+	if e.kind() == io::ErrorKind::InvalidData {
+		BlockSourceError::persistent(e)
+	} else {
+		BlockSourceError::transient(e)
+	}
+}
+
+/// Keeps a set of `ConfirmationListener`s in sync with the best chain of an Electrum server.
+///
+/// Each call to `sync_best_chain` looks up the history of every script watched by a
+/// `FilteredChainWatchInterface` and:
+///  * tells listeners of any transactions previously confirmed (or returned by their
+///    `get_relevant_txids`) which are no longer confirmed in the same block,
+///  * passes on newly-confirmed transactions, grouped by block and in block order, after
+///    verifying their merkle proofs,
+///  * notifies listeners of the server's best block, if it changed.
+///
+/// If listeners register new scripts or outpoints to watch while being notified (eg the outputs of
+/// a commitment transaction they broadcast), the histories are looked up again.
+///
+/// The `FilteredChainWatchInterface` must be the one given to the listeners (eg to the
+/// `SimpleManyChannelMonitor`). Its `watch_all_txn` setting cannot be honored over Electrum and
+/// is ignored.
+pub struct ElectrumChainSync<'a, E: Deref<Target = ElectrumClient>, C: Deref<Target = FilteredChainWatchInterface>> {
+	client: E,
+	chain_watch: C,
+	listeners: Vec<&'a dyn ConfirmationListener>,
+	/// The best block listeners were last notified of, if any.
+	chain_tip: Option<(BlockHash, u32)>,
+	/// Transactions listeners have been told were confirmed, with the block they were confirmed in.
+	confirmed_txs: HashMap<Txid, (BlockHash, u32)>,
+}
+
+impl<'a, E: Deref<Target = ElectrumClient>, C: Deref<Target = FilteredChainWatchInterface>> ElectrumChainSync<'a, E, C> {
+	/// Creates a new ElectrumChainSync with no listeners, which will fetch chain data from
+	/// `client` for the scripts watched by `chain_watch`.
+	pub fn new(client: E, chain_watch: C) -> Self {
+		Self { client, chain_watch, listeners: Vec::new(), chain_tip: None, confirmed_txs: HashMap::new() }
+	}
+
+	/// Registers a listener to be notified by subsequent calls to `sync_best_chain`.
+	pub fn register_listener(&mut self, listener: &'a dyn ConfirmationListener) {
+		self.listeners.push(listener);
+	}
+
+	/// Gets the hash and height of the best block listeners were last notified of, if any.
+	pub fn chain_tip(&self) -> Option<(BlockHash, u32)> {
+		self.chain_tip
+	}
+
+	/// Queries the server for the current state of the chain and notifies the listeners of any
+	/// changes.
+	///
+	/// Returns whether the best block changed. If an error is returned, listeners may have been
+	/// notified of some changes but not of the new best block, and the next call will pick up
+	/// from there.
+	pub fn sync_best_chain(&mut self) -> BlockSourceResult<bool> {
+		let (tip_header, tip_height) = self.client.get_tip().map_err(map_client_error)?;
+		let mut headers = HashMap::new();
+		headers.insert(tip_height, tip_header);
+
+		loop {
+			let reentered = self.chain_watch.reentered();
+			self.sync_transactions(tip_height, &mut headers)?;
+			if self.chain_watch.reentered() == reentered {
+				break;
+			}
+		}
+
+		let tip_hash = tip_header.block_hash();
+		if self.chain_tip == Some((tip_hash, tip_height)) {
+			return Ok(false);
+		}
+		for listener in self.listeners.iter() {
+			listener.best_block_updated(&tip_header, tip_height);
+		}
+		self.chain_tip = Some((tip_hash, tip_height));
+		Ok(true)
+	}
+
+	/// Notifies listeners of transactions unconfirmed and confirmed according to the current
+	/// histories of the watched scripts.
+	fn sync_transactions(&mut self, tip_height: u32, headers: &mut HashMap<u32, BlockHeader>) -> BlockSourceResult<()> {
+		let mut history = HashMap::new();
+		for script in self.chain_watch.watched_scripts() {
+			for (txid, height) in self.client.get_script_history(&script).map_err(map_client_error)? {
+				if height <= tip_height {
+					history.insert(txid, height);
+				}
+			}
+		}
+
+		// Transactions which are no longer confirmed in the block listeners were told of, if at
+		// all, must be unconfirmed before being confirmed again.
+		let mut txids: HashSet<Txid> = self.confirmed_txs.keys().cloned().collect();
+		for listener in self.listeners.iter() {
+			txids.extend(listener.get_relevant_txids());
+		}
+		for txid in txids {
+			let still_confirmed = match (self.confirmed_txs.get(&txid), history.get(&txid)) {
+				(Some(&(block_hash, confirmed_height)), Some(&height)) if height == confirmed_height =>
+					self.header_at(height, headers)?.block_hash() == block_hash,
+				(None, Some(_)) => continue,
+				_ => false,
+			};
+			if !still_confirmed {
+				for listener in self.listeners.iter() {
+					listener.transaction_unconfirmed(&txid);
+				}
+				self.confirmed_txs.remove(&txid);
+			}
+		}
+
+		let mut newly_confirmed = Vec::new();
+		for (txid, height) in history {
+			if !self.confirmed_txs.contains_key(&txid) {
+				let (header, pos, tx) = self.client.get_confirmed_transaction(&txid, height).map_err(map_client_error)?;
+				match headers.get(&height) {
+					Some(known) if *known != header => return Err(BlockSourceError::transient("best chain changed while syncing")),
+					_ => {},
+				}
+				headers.insert(height, header);
+				newly_confirmed.push((height, pos, tx));
+			}
+		}
+		newly_confirmed.sort_unstable_by_key(|(height, pos, _)| (*height, *pos));
+
+		let mut remaining = &newly_confirmed[..];
+		while let Some(&(height, _, _)) = remaining.first() {
+			let block_len = remaining.iter().take_while(|(tx_height, _, _)| *tx_height == height).count();
+			let (block_txn, rest) = remaining.split_at(block_len);
+			remaining = rest;
+
+			let header = headers[&height];
+			let txdata: Vec<(usize, &Transaction)> = block_txn.iter().map(|(_, pos, tx)| (*pos, tx)).collect();
+			for listener in self.listeners.iter() {
+				listener.transactions_confirmed(&header, height, &txdata);
+			}
+			for (_, tx) in txdata {
+				self.confirmed_txs.insert(tx.txid(), (header.block_hash(), height));
+			}
+		}
+		Ok(())
+	}
+
+	fn header_at(&self, height: u32, headers: &mut HashMap<u32, BlockHeader>) -> BlockSourceResult<BlockHeader> {
+		if let Some(header) = headers.get(&height) {
+			return Ok(*header);
+		}
+		let header = self.client.get_header(height).map_err(map_client_error)?;
+		headers.insert(height, header);
+		Ok(header)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{BlockSource, BlockSourceErrorKind};
+	use crate::memory::MemoryBlockSource;
+	use bitcoin::blockdata::block::Block;
+	use bitcoin::blockdata::transaction::{OutPoint, TxIn, TxOut};
+	use bitcoin::blockdata::script::Builder;
+	use bitcoin::network::constants::Network;
+	use std::net::{SocketAddr, TcpListener};
+	use std::sync::Arc;
+	use std::thread;
+
+	/// A stand-in Electrum server which answers the calls made by `ElectrumClient` from the best
+	/// chain of a `MemoryBlockSource`, sending a notification ahead of each response to
+	/// `blockchain.headers.subscribe`. Each request received is recorded.
+	struct ElectrumServer {
+		address: SocketAddr,
+		requests: Arc<Mutex<Vec<Value>>>,
+	}
+
+	impl ElectrumServer {
+		fn serving(source: Arc<MemoryBlockSource>) -> Self {
+			let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+			let address = listener.local_addr().unwrap();
+			let requests = Arc::new(Mutex::new(Vec::new()));
+			let requests_clone = requests.clone();
+			thread::spawn(move || {
+				for stream in listener.incoming() {
+					let mut reader = BufReader::new(stream.unwrap());
+					let mut line = String::new();
+					while reader.read_line(&mut line).unwrap_or(0) != 0 {
+						let request: Value = serde_json::from_str(&line).unwrap();
+						line.clear();
+						requests_clone.lock().unwrap().push(request.clone());
+						let mut response = match handle_request(&source, &request) {
+							Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string(),
+							Err(message) => json!({ "jsonrpc": "2.0", "id": request["id"], "error": { "code": 1, "message": message } }).to_string(),
+						};
+						if request["method"] == "blockchain.headers.subscribe" {
+							response = json!({ "jsonrpc": "2.0", "method": "blockchain.headers.subscribe", "params": [] }).to_string() + "\n" + &response;
+						}
+						response.push('\n');
+						if reader.get_mut().write_all(response.as_bytes()).is_err() { break; }
+					}
+				}
+			});
+			Self { address, requests }
+		}
+
+		fn client(&self) -> ElectrumClient {
+			ElectrumClient::connect(self.address).unwrap()
+		}
+
+		fn methods_called(&self) -> Vec<String> {
+			self.requests.lock().unwrap().iter().map(|request| request["method"].as_str().unwrap().to_string()).collect()
+		}
+	}
+
+	/// Gets the blocks of the source's best chain, indexed by height.
+	fn best_chain(source: &MemoryBlockSource) -> Vec<Block> {
+		let mut block_hash = source.get_best_block().unwrap().0;
+		let mut chain = Vec::new();
+		loop {
+			let block = source.get_block(&block_hash).unwrap();
+			block_hash = block.header.prev_blockhash;
+			chain.push(block);
+			if block_hash == Default::default() {
+				break;
+			}
+		}
+		chain.reverse();
+		chain
+	}
+
+	fn merkle_branch(block: &Block, mut pos: usize) -> Vec<sha256d::Hash> {
+		let mut level: Vec<sha256d::Hash> = block.txdata.iter().map(|tx| tx.txid().as_hash()).collect();
+		let mut branch = Vec::new();
+		while level.len() > 1 {
+			if level.len() % 2 == 1 {
+				level.push(*level.last().unwrap());
+			}
+			branch.push(level[pos ^ 1]);
+			level = level.chunks(2).map(|pair| {
+				let mut engine = sha256d::Hash::engine();
+				engine.input(&pair[0][..]);
+				engine.input(&pair[1][..]);
+				sha256d::Hash::from_engine(engine)
+			}).collect();
+			pos >>= 1;
+		}
+		branch
+	}
+
+	fn handle_request(source: &MemoryBlockSource, request: &Value) -> Result<Value, &'static str> {
+		let chain = best_chain(source);
+		let params = &request["params"];
+		let find_tx = |txid: &str| {
+			chain.iter().enumerate().flat_map(|(height, block)| block.txdata.iter().enumerate().map(move |(pos, tx)| (height, pos, tx)))
+				.find(|(_, _, tx)| tx.txid().to_hex() == txid).ok_or("transaction not found")
+		};
+		match request["method"].as_str().unwrap() {
+			"server.version" => Ok(json!(["ElectrumServer stand-in", PROTOCOL_VERSION])),
+			"blockchain.headers.subscribe" => {
+				let tip = chain.last().unwrap();
+				Ok(json!({ "height": chain.len() - 1, "hex": encode::serialize(&tip.header).to_hex() }))
+			},
+			"blockchain.block.header" => {
+				let block = chain.get(params[0].as_u64().unwrap() as usize).ok_or("height out of range")?;
+				Ok(json!(encode::serialize(&block.header).to_hex()))
+			},
+			"blockchain.scripthash.get_history" => {
+				let script_pays_to_hash = |script: &Script| script_hash(script) == params[0].as_str().unwrap();
+				let mut history = Vec::new();
+				for (height, block) in chain.iter().enumerate().skip(1) {
+					for tx in block.txdata.iter() {
+						let pays = tx.output.iter().any(|output| script_pays_to_hash(&output.script_pubkey));
+						let spends = tx.input.iter().any(|input| find_tx(&input.previous_output.txid.to_hex()).ok()
+							.and_then(|(_, _, prev_tx)| prev_tx.output.get(input.previous_output.vout as usize))
+							.map_or(false, |prev_output| script_pays_to_hash(&prev_output.script_pubkey)));
+						if pays || spends {
+							history.push(json!({ "tx_hash": tx.txid().to_hex(), "height": height }));
+						}
+					}
+				}
+				Ok(json!(history))
+			},
+			"blockchain.transaction.get" => {
+				let (_, _, tx) = find_tx(params[0].as_str().unwrap())?;
+				Ok(json!(encode::serialize(tx).to_hex()))
+			},
+			"blockchain.transaction.get_merkle" => {
+				let (height, pos, _) = find_tx(params[0].as_str().unwrap())?;
+				if height as u64 != params[1].as_u64().unwrap() {
+					return Err("transaction not in block");
+				}
+				let branch: Vec<String> = merkle_branch(&chain[height], pos).iter().map(|node| node.to_hex()).collect();
+				Ok(json!({ "block_height": height, "merkle": branch, "pos": pos }))
+			},
+			_ => Err("unknown method"),
+		}
+	}
+
+	#[derive(Debug, PartialEq)]
+	enum Notification {
+		Confirmed(u32, Vec<(usize, Txid)>),
+		Unconfirmed(Txid),
+		BestBlock(BlockHash, u32),
+	}
+
+	/// Records the notifications it receives and, like a ChannelMonitor, reports the transactions
+	/// it was told were confirmed as relevant. Optionally starts watching the first output of a
+	/// given transaction when it is confirmed.
+	struct RecordingListener<'a> {
+		chain_watch: &'a FilteredChainWatchInterface,
+		notifications: Mutex<Vec<Notification>>,
+		confirmed: Mutex<HashSet<Txid>>,
+		watch_output_of: Option<Txid>,
+	}
+	impl<'a> RecordingListener<'a> {
+		fn new(chain_watch: &'a FilteredChainWatchInterface, watch_output_of: Option<Txid>) -> Self {
+			Self { chain_watch, notifications: Mutex::new(Vec::new()), confirmed: Mutex::new(HashSet::new()), watch_output_of }
+		}
+		fn take(&self) -> Vec<Notification> {
+			self.notifications.lock().unwrap().drain(..).collect()
+		}
+	}
+	impl<'a> ConfirmationListener for RecordingListener<'a> {
+		fn transactions_confirmed(&self, _header: &BlockHeader, height: u32, txdata: &[(usize, &Transaction)]) {
+			self.notifications.lock().unwrap().push(Notification::Confirmed(height, txdata.iter().map(|(pos, tx)| (*pos, tx.txid())).collect()));
+			for (_, tx) in txdata {
+				self.confirmed.lock().unwrap().insert(tx.txid());
+				if self.watch_output_of == Some(tx.txid()) {
+					self.chain_watch.install_watch_outpoint((tx.txid(), 0), &tx.output[0].script_pubkey);
+				}
+			}
+		}
+		fn transaction_unconfirmed(&self, txid: &Txid) {
+			self.notifications.lock().unwrap().push(Notification::Unconfirmed(*txid));
+			self.confirmed.lock().unwrap().remove(txid);
+		}
+		fn best_block_updated(&self, header: &BlockHeader, height: u32) {
+			self.notifications.lock().unwrap().push(Notification::BestBlock(header.block_hash(), height));
+		}
+		fn get_relevant_txids(&self) -> Vec<Txid> {
+			self.confirmed.lock().unwrap().iter().cloned().collect()
+		}
+	}
+
+	fn script(n: u8) -> Script {
+		Builder::new().push_slice(&[n; 20]).into_script()
+	}
+
+	fn spend(outpoint: OutPoint, script_pubkey: Script) -> Transaction {
+		Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn { previous_output: outpoint, script_sig: Script::new(), sequence: 0xffffffff, witness: Vec::new() }],
+			output: vec![TxOut { value: 1000, script_pubkey }],
+		}
+	}
+
+	#[test]
+	fn call_method_skips_notifications() {
+		let source = Arc::new(MemoryBlockSource::new(Network::Regtest));
+		let genesis_hash = source.get_best_block().unwrap().0;
+		let server = ElectrumServer::serving(source.clone());
+		let client = server.client();
+		let (header, height) = client.get_tip().unwrap();
+		assert_eq!((header.block_hash(), height), (genesis_hash, 0));
+		assert_eq!(server.methods_called(), vec!["server.version", "blockchain.headers.subscribe"]);
+	}
+
+	#[test]
+	fn call_method_returning_error() {
+		let server = ElectrumServer::serving(Arc::new(MemoryBlockSource::new(Network::Regtest)));
+		let client = server.client();
+		let e = client.call_method("blockchain.unknown", &[]).unwrap_err();
+		let electrum_error = e.into_inner().unwrap().downcast::<ElectrumError>().unwrap();
+		assert_eq!(electrum_error.code, 1);
+		assert_eq!(electrum_error.message, "unknown method");
+
+		// The connection remains usable.
+		assert_eq!(client.get_tip().unwrap().1, 0);
+	}
+
+	#[test]
+	fn get_confirmed_transaction_checks_merkle_proof() {
+		let source = Arc::new(MemoryBlockSource::new(Network::Regtest));
+		let genesis = source.get_best_block().unwrap().0;
+		let block_1 = source.mine_block(&genesis);
+		let coinbase = block_1.txdata[0].clone();
+		let txn: Vec<Transaction> = (0..4).map(|n| spend(OutPoint { txid: coinbase.txid(), vout: 0 }, script(n))).collect();
+		let block_2 = source.mine_block_with_txdata(&block_1.header.block_hash(), txn.clone());
+		let server = ElectrumServer::serving(source.clone());
+		let client = server.client();
+
+		for (index, tx) in txn.iter().enumerate() {
+			let (header, pos, confirmed_tx) = client.get_confirmed_transaction(&tx.txid(), 2).unwrap();
+			assert_eq!((header, pos, &confirmed_tx), (block_2.header, index + 1, tx));
+			assert_eq!(client.get_script_history(&script(index as u8)).unwrap(), vec![(tx.txid(), 2)]);
+		}
+
+		let branch = merkle_branch(&block_2, 1);
+		assert!(verify_merkle_proof(&txn[0].txid(), 1, &branch, &block_2.header));
+		assert!(!verify_merkle_proof(&txn[0].txid(), 2, &branch, &block_2.header));
+		assert!(!verify_merkle_proof(&txn[1].txid(), 1, &branch, &block_2.header));
+		assert!(!verify_merkle_proof(&txn[0].txid(), 1, &branch, &block_1.header));
+
+		let e = client.get_confirmed_transaction(&txn[0].txid(), 1).unwrap_err();
+		assert_eq!(e.kind(), io::ErrorKind::Other);
+	}
+
+	#[test]
+	fn sync_confirms_and_unconfirms_transactions() {
+		let source = Arc::new(MemoryBlockSource::new(Network::Regtest));
+		let genesis = source.get_best_block().unwrap().0;
+		let block_1 = source.mine_block(&genesis);
+		let coinbase = block_1.txdata[0].clone();
+		let funding_tx = spend(OutPoint { txid: coinbase.txid(), vout: 0 }, script(1));
+		let block_2 = source.mine_block_with_txdata(&block_1.header.block_hash(), vec![funding_tx.clone()]);
+		let block_3 = source.mine_block(&block_2.header.block_hash());
+		let server = ElectrumServer::serving(source.clone());
+		let client = server.client();
+
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		chain_watch.install_watch_tx(&funding_tx.txid(), &script(1));
+		let listener = RecordingListener::new(&chain_watch, None);
+		let mut sync = ElectrumChainSync::new(&client, &chain_watch);
+		sync.register_listener(&listener);
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(sync.chain_tip(), Some((block_3.header.block_hash(), 3)));
+		assert_eq!(listener.take(), vec![
+			Notification::Confirmed(2, vec![(1, funding_tx.txid())]),
+			Notification::BestBlock(block_3.header.block_hash(), 3),
+		]);
+
+		// Nothing changed, so nothing is notified.
+		assert!(!sync.sync_best_chain().unwrap());
+		assert!(listener.take().is_empty());
+
+		// A longer fork without the transaction unconfirms it...
+		let fork_2 = source.mine_block(&block_1.header.block_hash()).header.block_hash();
+		let fork_3 = source.mine_block(&fork_2).header.block_hash();
+		let fork_4 = source.mine_block(&fork_3).header.block_hash();
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![
+			Notification::Unconfirmed(funding_tx.txid()),
+			Notification::BestBlock(fork_4, 4),
+		]);
+
+		// ...and when it is confirmed in a different block, it is unconfirmed first.
+		let fork_5 = source.mine_block_with_txdata(&fork_4, vec![funding_tx.clone()]).header.block_hash();
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![
+			Notification::Confirmed(5, vec![(1, funding_tx.txid())]),
+			Notification::BestBlock(fork_5, 5),
+		]);
+		let other_5 = source.mine_block(&fork_4).header.block_hash();
+		let other_6 = source.mine_block_with_txdata(&other_5, vec![funding_tx.clone()]).header.block_hash();
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![
+			Notification::Unconfirmed(funding_tx.txid()),
+			Notification::Confirmed(6, vec![(1, funding_tx.txid())]),
+			Notification::BestBlock(other_6, 6),
+		]);
+	}
+
+	#[test]
+	fn sync_unconfirms_relevant_transactions_unknown_to_server() {
+		// A listener restored from disk may report transactions as relevant which were confirmed
+		// in blocks since reorged out.
+		let source = Arc::new(MemoryBlockSource::new(Network::Regtest));
+		let genesis = source.get_best_block().unwrap().0;
+		let block_1 = source.mine_block(&genesis).header.block_hash();
+		let server = ElectrumServer::serving(source.clone());
+		let client = server.client();
+
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		let listener = RecordingListener::new(&chain_watch, None);
+		let stale_txid = spend(OutPoint::null(), script(1)).txid();
+		listener.confirmed.lock().unwrap().insert(stale_txid);
+		let mut sync = ElectrumChainSync::new(&client, &chain_watch);
+		sync.register_listener(&listener);
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![
+			Notification::Unconfirmed(stale_txid),
+			Notification::BestBlock(block_1, 1),
+		]);
+	}
+
+	#[test]
+	fn sync_rechecks_history_when_watched_data_grows() {
+		// A listener which starts watching an outpoint when a transaction confirms must be told
+		// of transactions spending it in the same sync.
+		let source = Arc::new(MemoryBlockSource::new(Network::Regtest));
+		let genesis = source.get_best_block().unwrap().0;
+		let block_1 = source.mine_block(&genesis);
+		let coinbase = block_1.txdata[0].clone();
+		let funding_tx = spend(OutPoint { txid: coinbase.txid(), vout: 0 }, script(1));
+		let block_2 = source.mine_block_with_txdata(&block_1.header.block_hash(), vec![funding_tx.clone()]);
+		let commitment_tx = spend(OutPoint { txid: funding_tx.txid(), vout: 0 }, script(2));
+		let block_3 = source.mine_block_with_txdata(&block_2.header.block_hash(), vec![commitment_tx.clone()]);
+		let claim_tx = spend(OutPoint { txid: commitment_tx.txid(), vout: 0 }, script(3));
+		let block_4 = source.mine_block_with_txdata(&block_3.header.block_hash(), vec![claim_tx.clone()]);
+		let server = ElectrumServer::serving(source.clone());
+		let client = server.client();
+
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		chain_watch.install_watch_outpoint((funding_tx.txid(), 0), &script(1));
+		let listener = RecordingListener::new(&chain_watch, Some(commitment_tx.txid()));
+		let mut sync = ElectrumChainSync::new(&client, &chain_watch);
+		sync.register_listener(&listener);
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![
+			Notification::Confirmed(2, vec![(1, funding_tx.txid())]),
+			Notification::Confirmed(3, vec![(1, commitment_tx.txid())]),
+			Notification::Confirmed(4, vec![(1, claim_tx.txid())]),
+			Notification::BestBlock(block_4.header.block_hash(), 4),
+		]);
+	}
+
+	#[test]
+	fn header_must_meet_target() {
+		let source = Arc::new(MemoryBlockSource::new(Network::Regtest));
+		let genesis = source.get_best_block().unwrap().0;
+		// A block with an impossible target can't have met it.
+		let block = source.mine_block(&genesis);
+		let mut header = block.header;
+		header.bits = 0x03000001;
+		let hex = json!(encode::serialize(&header).to_hex());
+		assert_eq!(header_from_json(&hex).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		assert_eq!(map_client_error(header_from_json(&hex).unwrap_err()).kind(), BlockSourceErrorKind::Persistent);
+	}
+}
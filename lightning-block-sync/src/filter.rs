@@ -0,0 +1,423 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Chain sync using compact block filters (BIP 157/158), downloading only blocks which may
+//! contain transactions of interest.
+//!
+//! [`FilteredChainWatchInterface`] is a `ChainWatchInterface` which, in addition to matching full
+//! blocks, can test BIP 158 basic filters against the scripts it has been asked to watch, both
+//! those of watched transactions' outputs and those of watched outpoints (as basic filters include
+//! the scripts of outputs spent by a block, rather than the outpoints themselves).
+//!
+//! [`FilteredChainSync`] then follows the best chain of a [`BlockFilterSource`] like `ChainSync`,
+//! but connects blocks to a `BlockNotifier`'s listeners using their headers alone, unless a block's
+//! filter matches. Listeners are still notified of every block, as they rely on doing so to track
+//! confirmations and timeouts.
+//!
+//! Note that filters are trusted as served by the block source: a source which omits an element
+//! from a filter can hide a transaction from us. Filter headers are not checked.
+//!
+//! [`FilteredChainWatchInterface`]: struct.FilteredChainWatchInterface.html
+//! [`FilteredChainSync`]: struct.FilteredChainSync.html
+//! [`BlockFilterSource`]: trait.BlockFilterSource.html
+
+use crate::{BlockHeaderData, BlockSource, BlockSourceError, BlockSourceResult, HeaderChain, validate_block};
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::hash_types::{BlockHash, Txid};
+use bitcoin::network::constants::Network;
+use bitcoin::util::bip158::BlockFilter;
+
+use lightning::chain::chaininterface::{BlockNotifier, ChainError, ChainListener, ChainWatchInterface};
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A `BlockSource` which can also provide BIP 158 basic block filters.
+pub trait BlockFilterSource: BlockSource {
+	/// Returns the basic filter for the block with the given hash.
+	fn get_filter(&self, header_hash: &BlockHash) -> BlockSourceResult<BlockFilter>;
+}
+
+struct WatchedScripts {
+	watch_all: bool,
+	/// Scripts in the outputs of watched transactions.
+	scripts: HashSet<Script>,
+	/// Watched outpoints, along with the scripts they pay to.
+	outpoints: HashMap<(Txid, u32), Script>,
+}
+
+impl WatchedScripts {
+	fn does_match_tx(&self, tx: &Transaction) -> bool {
+		if self.watch_all {
+			return true;
+		}
+		tx.output.iter().any(|output| self.scripts.contains(&output.script_pubkey)) ||
+			tx.input.iter().any(|input| self.outpoints.contains_key(&(input.previous_output.txid, input.previous_output.vout)))
+	}
+}
+
+/// A `ChainWatchInterface` which can match BIP 158 basic block filters against the data it has
+/// been asked to watch, as well as matching full blocks as `ChainWatchInterfaceUtil` does.
+pub struct FilteredChainWatchInterface {
+	network: Network,
+	watched: Mutex<WatchedScripts>,
+	reentered: AtomicUsize,
+}
+
+impl FilteredChainWatchInterface {
+	/// Creates a new FilteredChainWatchInterface for the given network, watching nothing.
+	pub fn new(network: Network) -> Self {
+		Self {
+			network,
+			watched: Mutex::new(WatchedScripts { watch_all: false, scripts: HashSet::new(), outpoints: HashMap::new() }),
+			reentered: AtomicUsize::new(1),
+		}
+	}
+
+	/// Checks whether the given basic filter for the block with the given hash matches any of the
+	/// watched data, in which case the block should be fetched and passed to `filter_block`.
+	///
+	/// Filters may produce false positives, but never false negatives.
+	pub fn match_filter(&self, block_hash: &BlockHash, filter: &BlockFilter) -> BlockSourceResult<bool> {
+		let watched = self.watched.lock().unwrap();
+		if watched.watch_all {
+			return Ok(true);
+		}
+		if watched.scripts.is_empty() && watched.outpoints.is_empty() {
+			return Ok(false);
+		}
+		let mut query = watched.scripts.iter().chain(watched.outpoints.values()).map(|script| script.as_bytes());
+		filter.match_any(block_hash, &mut query).map_err(BlockSourceError::persistent)
+	}
+
+	/// Gets the scripts which transactions of interest pay to or spend from: those of watched
+	/// transactions' outputs and those of watched outpoints, deduplicated.
+	///
+	/// Note that whether all transactions are being watched (via `watch_all_txn`) is not reflected.
+	pub fn watched_scripts(&self) -> Vec<Script> {
+		let watched = self.watched.lock().unwrap();
+		let scripts: HashSet<&Script> = watched.scripts.iter().chain(watched.outpoints.values()).collect();
+		scripts.into_iter().cloned().collect()
+	}
+}
+
+impl ChainWatchInterface for FilteredChainWatchInterface {
+	fn install_watch_tx(&self, _txid: &Txid, script_pub_key: &Script) {
+		if self.watched.lock().unwrap().scripts.insert(script_pub_key.clone()) {
+			self.reentered.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	fn install_watch_outpoint(&self, outpoint: (Txid, u32), out_script: &Script) {
+		if self.watched.lock().unwrap().outpoints.insert(outpoint, out_script.clone()).is_none() {
+			self.reentered.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	fn watch_all_txn(&self) {
+		let mut watched = self.watched.lock().unwrap();
+		if !watched.watch_all {
+			watched.watch_all = true;
+			self.reentered.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	fn get_chain_utxo(&self, genesis_hash: BlockHash, _unspent_tx_output_identifier: u64) -> Result<(Script, u64), ChainError> {
+		if genesis_hash != genesis_block(self.network).header.block_hash() {
+			return Err(ChainError::NotWatched);
+		}
+		Err(ChainError::NotSupported)
+	}
+
+	fn filter_block(&self, block: &Block) -> Vec<usize> {
+		let watched = self.watched.lock().unwrap();
+		let mut matched_indexes = Vec::new();
+		let mut matched_txids = HashSet::new();
+		for (index, tx) in block.txdata.iter().enumerate() {
+			// As with ChainWatchInterfaceUtil, descendants of matched transactions in the same block
+			// also match.
+			if watched.does_match_tx(tx) || tx.input.iter().any(|input| matched_txids.contains(&input.previous_output.txid)) {
+				matched_txids.insert(tx.txid());
+				matched_indexes.push(index);
+			}
+		}
+		matched_indexes
+	}
+
+	fn reentered(&self) -> usize {
+		self.reentered.load(Ordering::Relaxed)
+	}
+}
+
+/// Keeps the listeners of a `BlockNotifier` in sync with the best chain of a `BlockFilterSource`,
+/// fetching only those blocks whose filters match the data watched by a
+/// `FilteredChainWatchInterface`.
+///
+/// The best chain is followed as by `ChainSync`, including across reorgs. For each block
+/// connected, its filter is tested and, if it matches, the block is fetched and its matching
+/// transactions passed to the listeners. Otherwise, listeners are notified of the block's header
+/// with no transactions. If listeners register new data to watch while a block is connected (eg
+/// the outputs of a commitment transaction they broadcast), the filter is tested again and the
+/// block fetched and re-connected if it now matches, as required by `ChainListener`.
+///
+/// The `BlockNotifier` must have been created with the same `FilteredChainWatchInterface`.
+pub struct FilteredChainSync<'a, B: Deref, CL: Deref + 'a, C: Deref<Target = FilteredChainWatchInterface>, N: Deref<Target = BlockNotifier<'a, CL, C>>>
+	where B::Target: BlockFilterSource, CL::Target: ChainListener + 'a
+{
+	chain: HeaderChain,
+	block_source: B,
+	chain_watch: C,
+	notifier: N,
+}
+
+impl<'a, B: Deref, CL: Deref + 'a, C: Deref<Target = FilteredChainWatchInterface>, N: Deref<Target = BlockNotifier<'a, CL, C>>> FilteredChainSync<'a, B, CL, C, N>
+	where B::Target: BlockFilterSource, CL::Target: ChainListener + 'a
+{
+	/// Creates a new FilteredChainSync which will notify the listeners of `notifier` of any changes
+	/// to the best chain of `block_source` relative to `chain_tip`, which should be the last block
+	/// the listeners have been notified of.
+	pub fn new(chain_tip: BlockHeaderData, block_source: B, chain_watch: C, notifier: N) -> Self {
+		Self { chain: HeaderChain::new(chain_tip), block_source, chain_watch, notifier }
+	}
+
+	/// Gets the header of the last block the listeners were notified of.
+	pub fn chain_tip(&self) -> &BlockHeaderData {
+		&self.chain.chain_tip
+	}
+
+	/// Polls the block source for its best chain and, if it has more work than the current chain,
+	/// notifies the listeners of the blocks disconnected and connected to switch to it.
+	///
+	/// Returns whether the chain tip changed. Note that the tip may have changed even if an error
+	/// is returned.
+	pub fn sync_best_chain(&mut self) -> BlockSourceResult<bool> {
+		let best_tip = match self.chain.poll_best_tip(&*self.block_source)? {
+			Some(best_tip) => best_tip,
+			None => return Ok(false),
+		};
+		let block_source = &*self.block_source;
+		let chain_watch = &*self.chain_watch;
+		let notifier = &*self.notifier;
+		self.chain.sync_to_tip(block_source, best_tip,
+			|header_data| notifier.block_disconnected(&header_data.header, header_data.height),
+			|header_data| connect_filtered_block(block_source, chain_watch, notifier, header_data))
+	}
+}
+
+/// Connects a block to the notifier's listeners, fetching it only if its filter matches the
+/// watched data, including any data registered while the block is being connected.
+fn connect_filtered_block<'a, B: BlockFilterSource + ?Sized, CL: Deref + 'a, C: Deref<Target = FilteredChainWatchInterface>>(block_source: &B, chain_watch: &FilteredChainWatchInterface, notifier: &BlockNotifier<'a, CL, C>, header_data: &BlockHeaderData) -> BlockSourceResult<()>
+	where CL::Target: ChainListener + 'a
+{
+	let block_hash = header_data.header.block_hash();
+	let filter = block_source.get_filter(&block_hash)?;
+	let mut block = None;
+	loop {
+		if block.is_none() && chain_watch.match_filter(&block_hash, &filter)? {
+			let fetched_block = block_source.get_block(&block_hash)?;
+			validate_block(&fetched_block, &block_hash)?;
+			block = Some(fetched_block);
+		}
+		let reentered = match block {
+			Some(ref block) => {
+				let matched_indexes = chain_watch.filter_block(block);
+				let matched_txn: Vec<&Transaction> = matched_indexes.iter().map(|index| &block.txdata[*index]).collect();
+				notifier.block_connected_checked(&block.header, header_data.height, &matched_txn, &matched_indexes)
+			},
+			None => notifier.block_connected_checked(&header_data.header, header_data.height, &[], &[]),
+		};
+		// If new data was registered but the block still doesn't match, there is nothing new to
+		// notify listeners of.
+		if !reentered || (block.is_none() && !chain_watch.match_filter(&block_hash, &filter)?) {
+			return Ok(());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::memory::MemoryBlockSource;
+	use bitcoin::blockdata::block::BlockHeader;
+	use bitcoin::blockdata::script::Builder;
+	use bitcoin::blockdata::transaction::{OutPoint, TxIn, TxOut};
+	use std::cell::Cell;
+	use std::sync::Mutex;
+
+	/// Counts the blocks fetched from the wrapped source.
+	struct CountingSource { source: MemoryBlockSource, blocks_fetched: Cell<usize> }
+	impl BlockSource for CountingSource {
+		fn get_header(&self, header_hash: &BlockHash, height_hint: Option<u32>) -> BlockSourceResult<BlockHeaderData> {
+			self.source.get_header(header_hash, height_hint)
+		}
+		fn get_block(&self, header_hash: &BlockHash) -> BlockSourceResult<Block> {
+			self.blocks_fetched.set(self.blocks_fetched.get() + 1);
+			self.source.get_block(header_hash)
+		}
+		fn get_best_block(&self) -> BlockSourceResult<(BlockHash, Option<u32>)> {
+			self.source.get_best_block()
+		}
+	}
+	impl BlockFilterSource for CountingSource {
+		fn get_filter(&self, header_hash: &BlockHash) -> BlockSourceResult<BlockFilter> {
+			self.source.get_filter(header_hash)
+		}
+	}
+
+	/// Records the transactions it is notified of and, like a ChannelMonitor broadcasting a
+	/// transaction, starts watching an outpoint when a given block is connected.
+	struct WatchingListener<'a> {
+		chain_watch: &'a FilteredChainWatchInterface,
+		connected: Mutex<Vec<(u32, Vec<Txid>)>>,
+		watch_at_height: Option<(u32, (Txid, u32), Script)>,
+	}
+	impl<'a> ChainListener for WatchingListener<'a> {
+		fn block_connected(&self, _header: &BlockHeader, height: u32, txn_matched: &[&Transaction], _indexes_of_txn_matched: &[usize]) {
+			self.connected.lock().unwrap().push((height, txn_matched.iter().map(|tx| tx.txid()).collect()));
+			if let Some((watch_height, outpoint, ref script)) = self.watch_at_height {
+				if watch_height == height {
+					self.chain_watch.install_watch_outpoint(outpoint, script);
+				}
+			}
+		}
+		fn block_disconnected(&self, _header: &BlockHeader, _disconnected_height: u32) {}
+	}
+
+	fn script(n: u8) -> Script {
+		Builder::new().push_slice(&[n; 20]).into_script()
+	}
+
+	fn spend(outpoint: OutPoint, script_pubkey: Script) -> Transaction {
+		Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn { previous_output: outpoint, script_sig: Script::new(), sequence: 0xffffffff, witness: Vec::new() }],
+			output: vec![TxOut { value: 1000, script_pubkey }],
+		}
+	}
+
+	#[test]
+	fn match_filter_against_watched_data() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let block_1 = source.mine_block(&genesis);
+		let coinbase = block_1.txdata[0].clone();
+		let funding_tx = spend(OutPoint { txid: coinbase.txid(), vout: 0 }, script(1));
+		let block = source.mine_block_with_txdata(&block_1.header.block_hash(), vec![funding_tx.clone()]);
+		let block_hash = block.header.block_hash();
+		let filter = source.get_filter(&block_hash).unwrap();
+
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		assert!(!chain_watch.match_filter(&block_hash, &filter).unwrap());
+		chain_watch.install_watch_tx(&Default::default(), &script(2));
+		assert!(!chain_watch.match_filter(&block_hash, &filter).unwrap());
+
+		// The block's outputs pay to the watched script...
+		chain_watch.install_watch_tx(&funding_tx.txid(), &script(1));
+		assert!(chain_watch.match_filter(&block_hash, &filter).unwrap());
+		assert_eq!(chain_watch.filter_block(&block), vec![1]);
+
+		// ...or spend a watched outpoint, matched by the script of the output spent.
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		chain_watch.install_watch_outpoint((coinbase.txid(), 0), &coinbase.output[0].script_pubkey);
+		assert!(chain_watch.match_filter(&block_hash, &filter).unwrap());
+		assert_eq!(chain_watch.filter_block(&block), vec![1]);
+
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		chain_watch.watch_all_txn();
+		assert!(chain_watch.match_filter(&block_hash, &filter).unwrap());
+	}
+
+	#[test]
+	fn sync_fetches_only_matching_blocks() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let genesis_header = source.get_header(&genesis, None).unwrap();
+		let block_1 = source.mine_block(&genesis);
+		let coinbase = block_1.txdata[0].clone();
+		let block_2 = source.mine_block(&block_1.header.block_hash());
+		let funding_tx = spend(OutPoint { txid: coinbase.txid(), vout: 0 }, script(1));
+		let block_3 = source.mine_block_with_txdata(&block_2.header.block_hash(), vec![funding_tx.clone()]);
+		source.mine_block(&block_3.header.block_hash());
+		let source = CountingSource { source, blocks_fetched: Cell::new(0) };
+
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		chain_watch.install_watch_tx(&funding_tx.txid(), &script(1));
+		let listener = WatchingListener { chain_watch: &chain_watch, connected: Mutex::new(Vec::new()), watch_at_height: None };
+		let notifier = BlockNotifier::new(&chain_watch);
+		notifier.register_listener(&listener as &dyn ChainListener);
+		let mut sync = FilteredChainSync::new(genesis_header, &source, &chain_watch, &notifier);
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(sync.chain_tip().height, 4);
+
+		// Every block was connected, but only the one containing the watched transaction was
+		// fetched and had transactions to pass on.
+		assert_eq!(*listener.connected.lock().unwrap(), vec![
+			(1, vec![]), (2, vec![]), (3, vec![funding_tx.txid()]), (4, vec![]),
+		]);
+		assert_eq!(source.blocks_fetched.get(), 1);
+	}
+
+	#[test]
+	fn sync_rechecks_filter_when_watched_data_grows() {
+		// A listener which starts watching an outpoint while a block is connected must be
+		// notified again of any transactions in the same block spending it.
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let genesis_header = source.get_header(&genesis, None).unwrap();
+		let block_1 = source.mine_block(&genesis);
+		let coinbase = block_1.txdata[0].clone();
+		let spending_tx = spend(OutPoint { txid: coinbase.txid(), vout: 0 }, script(1));
+		let block_2 = source.mine_block_with_txdata(&block_1.header.block_hash(), vec![spending_tx.clone()]);
+		let source = CountingSource { source, blocks_fetched: Cell::new(0) };
+
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		let listener = WatchingListener {
+			chain_watch: &chain_watch,
+			connected: Mutex::new(Vec::new()),
+			watch_at_height: Some((2, (coinbase.txid(), 0), coinbase.output[0].script_pubkey.clone())),
+		};
+		let notifier = BlockNotifier::new(&chain_watch);
+		notifier.register_listener(&listener as &dyn ChainListener);
+		let mut sync = FilteredChainSync::new(genesis_header, &source, &chain_watch, &notifier);
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(sync.chain_tip().header.block_hash(), block_2.header.block_hash());
+		assert_eq!(*listener.connected.lock().unwrap(), vec![
+			(1, vec![]), (2, vec![]), (2, vec![spending_tx.txid()]),
+		]);
+		assert_eq!(source.blocks_fetched.get(), 1);
+	}
+
+	#[test]
+	fn sync_across_reorg_with_filters() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let genesis_header = source.get_header(&genesis, None).unwrap();
+		let a1 = source.mine_block(&genesis).header.block_hash();
+		let chain_watch = FilteredChainWatchInterface::new(Network::Regtest);
+		let listener = WatchingListener { chain_watch: &chain_watch, connected: Mutex::new(Vec::new()), watch_at_height: None };
+		let notifier = BlockNotifier::new(&chain_watch);
+		notifier.register_listener(&listener as &dyn ChainListener);
+		let mut sync = FilteredChainSync::new(genesis_header, &source, &chain_watch, &notifier);
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(sync.chain_tip().header.block_hash(), a1);
+
+		let b1 = source.mine_block(&genesis).header.block_hash();
+		let b2 = source.mine_block(&b1).header.block_hash();
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(sync.chain_tip().header.block_hash(), b2);
+		assert_eq!(*listener.connected.lock().unwrap(), vec![(1, vec![]), (1, vec![]), (2, vec![])]);
+	}
+}
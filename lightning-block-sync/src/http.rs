@@ -0,0 +1,500 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A minimal, blocking HTTP/1.1 client sufficient for talking to Bitcoin Core's REST and RPC
+//! interfaces.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Timeout for connecting to the endpoint.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout for each read from or write to the endpoint. Generous as Bitcoin Core may take some
+/// time to respond to requests for large blocks.
+const TCP_STREAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum HTTP message header size in bytes.
+const MAX_HTTP_MESSAGE_HEADER_SIZE: usize = 8192;
+
+/// Maximum HTTP message body size in bytes. Enough for a hex-encoded block in JSON plus some
+/// overhead.
+const MAX_HTTP_MESSAGE_BODY_SIZE: usize = 2 * 4_000_000 + 32_000;
+
+/// Endpoint for interacting with an HTTP-based API.
+#[derive(Clone, Debug)]
+pub struct HttpEndpoint {
+	host: String,
+	port: Option<u16>,
+	path: String,
+}
+
+impl HttpEndpoint {
+	/// Creates an endpoint for the given host and default HTTP port.
+	pub fn for_host(host: String) -> Self {
+		Self {
+			host,
+			port: None,
+			path: String::from("/"),
+		}
+	}
+
+	/// Specifies a port to use with the endpoint.
+	pub fn with_port(mut self, port: u16) -> Self {
+		self.port = Some(port);
+		self
+	}
+
+	/// Specifies a path to use with the endpoint.
+	pub fn with_path(mut self, path: String) -> Self {
+		self.path = path;
+		self
+	}
+
+	/// Returns the endpoint host.
+	pub fn host(&self) -> &str {
+		&self.host
+	}
+
+	/// Returns the endpoint port.
+	pub fn port(&self) -> u16 {
+		self.port.unwrap_or(80)
+	}
+
+	/// Returns the endpoint path.
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+}
+
+impl<'a> ToSocketAddrs for &'a HttpEndpoint {
+	type Iter = <(&'a str, u16) as ToSocketAddrs>::Iter;
+
+	fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+		(self.host(), self.port()).to_socket_addrs()
+	}
+}
+
+/// An HTTP response status code outside the 2xx range, along with the response body.
+///
+/// Returned by `HttpClient` as the inner error of an `io::Error` with kind `Other`.
+#[derive(Debug)]
+pub struct HttpError {
+	/// The response status code, eg "404".
+	pub status_code: String,
+	/// The response body.
+	pub contents: Vec<u8>,
+}
+
+impl std::error::Error for HttpError {}
+
+impl fmt::Display for HttpError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let contents = String::from_utf8_lossy(&self.contents);
+		write!(f, "status_code: {}, contents: {}", self.status_code, contents)
+	}
+}
+
+/// Client for making HTTP requests to a single endpoint.
+///
+/// The connection is kept alive between requests where the server allows it, and is transparently
+/// re-established if it has been closed in the mean time.
+pub struct HttpClient {
+	address: SocketAddr,
+	stream: Option<BufReader<TcpStream>>,
+}
+
+impl HttpClient {
+	/// Opens a connection to an HTTP endpoint.
+	pub fn connect<E: ToSocketAddrs>(endpoint: E) -> io::Result<Self> {
+		let address = match endpoint.to_socket_addrs()?.next() {
+			None => {
+				return Err(io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any addresses"));
+			},
+			Some(address) => address,
+		};
+		let stream = Self::open_stream(&address)?;
+		Ok(Self { address, stream: Some(stream) })
+	}
+
+	fn open_stream(address: &SocketAddr) -> io::Result<BufReader<TcpStream>> {
+		let stream = TcpStream::connect_timeout(address, TCP_CONNECT_TIMEOUT)?;
+		stream.set_read_timeout(Some(TCP_STREAM_TIMEOUT))?;
+		stream.set_write_timeout(Some(TCP_STREAM_TIMEOUT))?;
+		Ok(BufReader::new(stream))
+	}
+
+	/// Sends a `GET` request for a resource identified by `uri` at the `host`, returning the
+	/// response body.
+	///
+	/// Returns an `io::Error` wrapping an `HttpError` if the response status is not 2xx.
+	pub fn get(&mut self, uri: &str, host: &str) -> io::Result<Vec<u8>> {
+		let request = format!(
+			"GET {} HTTP/1.1\r\n\
+			 Host: {}\r\n\
+			 Connection: keep-alive\r\n\
+			 \r\n", uri, host);
+		self.send_request_with_retry(request.as_bytes())
+	}
+
+	/// Sends a `POST` request for a resource identified by `uri` at the `host` using the given HTTP
+	/// authentication credentials, returning the response body.
+	///
+	/// The request body consists of the provided JSON `content`.
+	///
+	/// Returns an `io::Error` wrapping an `HttpError` if the response status is not 2xx.
+	pub fn post(&mut self, uri: &str, host: &str, auth: &str, content: serde_json::Value) -> io::Result<Vec<u8>> {
+		let content = content.to_string();
+		let request = format!(
+			"POST {} HTTP/1.1\r\n\
+			 Host: {}\r\n\
+			 Authorization: {}\r\n\
+			 Connection: keep-alive\r\n\
+			 Content-Type: application/json\r\n\
+			 Content-Length: {}\r\n\
+			 \r\n\
+			 {}", uri, host, auth, content.len(), content);
+		self.send_request_with_retry(request.as_bytes())
+	}
+
+	/// Sends the request, re-connecting and retrying once if the existing connection has since
+	/// been closed by the server.
+	fn send_request_with_retry(&mut self, request: &[u8]) -> io::Result<Vec<u8>> {
+		let reused_connection = self.stream.is_some();
+		match self.send_request(request) {
+			Ok(body) => Ok(body),
+			Err(e) => {
+				self.stream = None;
+				match e.kind() {
+					io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+					io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof if reused_connection => {
+						self.send_request(request)
+					},
+					_ => Err(e),
+				}
+			},
+		}
+	}
+
+	fn send_request(&mut self, request: &[u8]) -> io::Result<Vec<u8>> {
+		if self.stream.is_none() {
+			self.stream = Some(Self::open_stream(&self.address)?);
+		}
+		let reader = self.stream.as_mut().unwrap();
+		reader.get_mut().write_all(request)?;
+		reader.get_mut().flush()?;
+
+		let response = read_response(reader)?;
+		if !response.keep_alive {
+			self.stream = None;
+		}
+		if !response.status_code.starts_with('2') {
+			return Err(io::Error::new(io::ErrorKind::Other, HttpError {
+				status_code: response.status_code,
+				contents: response.body,
+			}));
+		}
+		Ok(response.body)
+	}
+}
+
+struct HttpResponse {
+	status_code: String,
+	keep_alive: bool,
+	body: Vec<u8>,
+}
+
+/// Reads a line terminated by CRLF (or LF), without the terminator, failing if it would exceed
+/// `max_len` bytes or if the stream ends first.
+fn read_line<R: BufRead>(reader: &mut R, max_len: usize) -> io::Result<String> {
+	let mut line = Vec::new();
+	reader.by_ref().take(max_len as u64 + 1).read_until(b'\n', &mut line)?;
+	if line.last() != Some(&b'\n') {
+		if line.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+		}
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP line too long or truncated"));
+	}
+	line.pop();
+	if line.last() == Some(&b'\r') {
+		line.pop();
+	}
+	String::from_utf8(line).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP line"))
+}
+
+fn read_response<R: BufRead>(reader: &mut R) -> io::Result<HttpResponse> {
+	// Skip any informational (1xx) responses.
+	let (status_code, http_version, headers) = loop {
+		let status_line = read_line(reader, MAX_HTTP_MESSAGE_HEADER_SIZE)?;
+		let mut parts = status_line.splitn(3, ' ');
+		let http_version = parts.next().unwrap_or("").to_string();
+		let status_code = match parts.next() {
+			Some(code) if code.len() == 3 && code.chars().all(|c| c.is_ascii_digit()) => code.to_string(),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP status line")),
+		};
+		if !http_version.starts_with("HTTP/1.") {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported HTTP version"));
+		}
+		let mut header_size = 0;
+		let headers = read_headers(reader, &mut header_size)?;
+		if status_code.starts_with('1') {
+			continue;
+		}
+		break (status_code, http_version, headers);
+	};
+
+	let header_value = |name: &str| headers.iter()
+		.find(|(header, _)| header.eq_ignore_ascii_case(name))
+		.map(|(_, value)| value.as_str());
+	let keep_alive = match header_value("Connection") {
+		Some(value) if value.eq_ignore_ascii_case("close") => false,
+		Some(value) if value.eq_ignore_ascii_case("keep-alive") => true,
+		_ => http_version == "HTTP/1.1",
+	};
+
+	let body = match (header_value("Transfer-Encoding"), header_value("Content-Length")) {
+		(Some(encoding), _) if encoding.eq_ignore_ascii_case("chunked") => read_chunked_body(reader)?,
+		(Some(_), _) => return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported transfer encoding")),
+		(None, Some(length)) => {
+			let length: usize = length.trim().parse()
+				.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid content length"))?;
+			if length > MAX_HTTP_MESSAGE_BODY_SIZE {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, "out of range"));
+			}
+			let mut body = vec![0; length];
+			reader.read_exact(&mut body)?;
+			body
+		},
+		(None, None) => {
+			// The body extends until the server closes the connection.
+			let mut body = Vec::new();
+			reader.by_ref().take(MAX_HTTP_MESSAGE_BODY_SIZE as u64 + 1).read_to_end(&mut body)?;
+			if body.len() > MAX_HTTP_MESSAGE_BODY_SIZE {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, "out of range"));
+			}
+			return Ok(HttpResponse { status_code, keep_alive: false, body });
+		},
+	};
+	Ok(HttpResponse { status_code, keep_alive, body })
+}
+
+/// Reads header lines up to and including the empty line terminating them, tracking the total
+/// header size in `header_size`.
+fn read_headers<R: BufRead>(reader: &mut R, header_size: &mut usize) -> io::Result<Vec<(String, String)>> {
+	let mut headers = Vec::new();
+	loop {
+		let line = read_line(reader, MAX_HTTP_MESSAGE_HEADER_SIZE)?;
+		*header_size += line.len() + 2;
+		if *header_size > MAX_HTTP_MESSAGE_HEADER_SIZE {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP headers too large"));
+		}
+		if line.is_empty() {
+			return Ok(headers);
+		}
+		let mut parts = line.splitn(2, ':');
+		match (parts.next(), parts.next()) {
+			(Some(name), Some(value)) => headers.push((name.trim().to_string(), value.trim().to_string())),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP header")),
+		}
+	}
+}
+
+fn read_chunked_body<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+	let mut body = Vec::new();
+	loop {
+		let size_line = read_line(reader, MAX_HTTP_MESSAGE_HEADER_SIZE)?;
+		// Ignore any chunk extensions.
+		let size_hex = size_line.split(';').next().unwrap().trim();
+		let size = usize::from_str_radix(size_hex, 16)
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+		if size == 0 {
+			// Skip any trailers.
+			let mut trailer_size = 0;
+			read_headers(reader, &mut trailer_size)?;
+			return Ok(body);
+		}
+		if body.len() + size > MAX_HTTP_MESSAGE_BODY_SIZE {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "out of range"));
+		}
+		let start = body.len();
+		body.resize(start + size, 0);
+		reader.read_exact(&mut body[start..])?;
+		if !read_line(reader, 2)?.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid chunk terminator"));
+		}
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use std::net::TcpListener;
+	use std::sync::{Arc, Mutex};
+	use std::thread;
+
+	/// A mock HTTP server which serves the given raw responses, in order, one per request, across
+	/// however many connections the client makes. Each request received is recorded.
+	pub(crate) struct HttpServer {
+		address: SocketAddr,
+		requests: Arc<Mutex<Vec<String>>>,
+	}
+
+	impl HttpServer {
+		pub(crate) fn responding_with(responses: Vec<String>) -> Self {
+			Self::responding_with_bytes(responses.into_iter().map(|response| response.into_bytes()).collect())
+		}
+
+		pub(crate) fn responding_with_bytes(responses: Vec<Vec<u8>>) -> Self {
+			let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+			let address = listener.local_addr().unwrap();
+			let requests = Arc::new(Mutex::new(Vec::new()));
+			let requests_clone = requests.clone();
+			thread::spawn(move || {
+				let mut responses = responses.into_iter();
+				for stream in listener.incoming() {
+					let mut reader = BufReader::new(stream.unwrap());
+					while let Ok(request) = read_request(&mut reader) {
+						requests_clone.lock().unwrap().push(request);
+						match responses.next() {
+							Some(response) => {
+								if reader.get_mut().write_all(&response).is_err() { break; }
+								if String::from_utf8_lossy(&response).contains("Connection: close") { break; }
+							},
+							None => return,
+						}
+					}
+				}
+			});
+			Self { address, requests }
+		}
+
+		pub(crate) fn responding_with_ok(body: &str) -> Self {
+			Self::responding_with(vec![ok_response(body)])
+		}
+
+		pub(crate) fn endpoint(&self) -> HttpEndpoint {
+			HttpEndpoint::for_host(self.address.ip().to_string()).with_port(self.address.port())
+		}
+
+		pub(crate) fn requests(&self) -> Vec<String> {
+			self.requests.lock().unwrap().clone()
+		}
+	}
+
+	pub(crate) fn ok_response(body: &str) -> String {
+		format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+	}
+
+	/// Reads a request's head and (Content-Length delimited) body into a string.
+	fn read_request<R: BufRead>(reader: &mut R) -> io::Result<String> {
+		let mut request = read_line(reader, MAX_HTTP_MESSAGE_HEADER_SIZE)?;
+		request.push_str("\r\n");
+		let mut header_size = 0;
+		let mut content_length = 0;
+		for (name, value) in read_headers(reader, &mut header_size)? {
+			if name.eq_ignore_ascii_case("Content-Length") {
+				content_length = value.parse().unwrap();
+			}
+			request.push_str(&format!("{}: {}\r\n", name, value));
+		}
+		request.push_str("\r\n");
+		let mut body = vec![0; content_length];
+		reader.read_exact(&mut body)?;
+		request.push_str(&String::from_utf8(body).unwrap());
+		Ok(request)
+	}
+
+	#[test]
+	fn endpoint_defaults() {
+		let endpoint = HttpEndpoint::for_host("localhost".to_string());
+		assert_eq!(endpoint.host(), "localhost");
+		assert_eq!(endpoint.port(), 80);
+		assert_eq!(endpoint.path(), "/");
+
+		let endpoint = endpoint.with_port(8332).with_path("/rest".to_string());
+		assert_eq!(endpoint.port(), 8332);
+		assert_eq!(endpoint.path(), "/rest");
+	}
+
+	#[test]
+	fn get_with_content_length() {
+		let server = HttpServer::responding_with_ok("foo");
+		let mut client = HttpClient::connect(&server.endpoint()).unwrap();
+		assert_eq!(client.get("/foo", "localhost").unwrap(), b"foo");
+		assert!(server.requests()[0].starts_with("GET /foo HTTP/1.1\r\nHost: localhost\r\n"));
+	}
+
+	#[test]
+	fn get_with_chunked_body() {
+		let response = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3;ext=1\r\nfoo\r\n4\r\nbarz\r\n0\r\nTrailer: x\r\n\r\n";
+		let server = HttpServer::responding_with(vec![response.to_string()]);
+		let mut client = HttpClient::connect(&server.endpoint()).unwrap();
+		assert_eq!(client.get("/foo", "localhost").unwrap(), b"foobarz");
+	}
+
+	#[test]
+	fn get_with_body_until_close() {
+		let response = "HTTP/1.0 200 OK\r\nConnection: close\r\n\r\nfoobar";
+		let server = HttpServer::responding_with(vec![response.to_string(), ok_response("baz")]);
+		let mut client = HttpClient::connect(&server.endpoint()).unwrap();
+		assert_eq!(client.get("/foo", "localhost").unwrap(), b"foobar");
+		// The server closed the connection, so the client must reconnect for the next request.
+		assert_eq!(client.get("/baz", "localhost").unwrap(), b"baz");
+	}
+
+	#[test]
+	fn keep_alive_reuses_connection() {
+		let server = HttpServer::responding_with(vec![ok_response("foo"), ok_response("bar")]);
+		let mut client = HttpClient::connect(&server.endpoint()).unwrap();
+		assert_eq!(client.get("/foo", "localhost").unwrap(), b"foo");
+		assert_eq!(client.get("/bar", "localhost").unwrap(), b"bar");
+		assert_eq!(server.requests().len(), 2);
+	}
+
+	#[test]
+	fn post_sends_auth_and_json() {
+		let server = HttpServer::responding_with_ok("{}");
+		let mut client = HttpClient::connect(&server.endpoint()).unwrap();
+		let content = serde_json::json!({"method": "getblockcount"});
+		assert_eq!(client.post("/", "localhost", "Basic dXNlcjpwYXNz", content.clone()).unwrap(), b"{}");
+		let request = &server.requests()[0];
+		assert!(request.starts_with("POST / HTTP/1.1\r\n"));
+		assert!(request.contains("Authorization: Basic dXNlcjpwYXNz\r\n"));
+		assert!(request.ends_with(&format!("\r\n\r\n{}", content)));
+	}
+
+	#[test]
+	fn error_status_returns_http_error() {
+		let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nnot found";
+		let server = HttpServer::responding_with(vec![response.to_string()]);
+		let mut client = HttpClient::connect(&server.endpoint()).unwrap();
+		let e = client.get("/foo", "localhost").unwrap_err();
+		assert_eq!(e.kind(), io::ErrorKind::Other);
+		let http_error = e.into_inner().unwrap().downcast::<HttpError>().unwrap();
+		assert_eq!(http_error.status_code, "404");
+		assert_eq!(http_error.contents, b"not found");
+	}
+
+	#[test]
+	fn oversized_body_is_rejected() {
+		let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", MAX_HTTP_MESSAGE_BODY_SIZE + 1);
+		let server = HttpServer::responding_with(vec![response]);
+		let mut client = HttpClient::connect(&server.endpoint()).unwrap();
+		assert_eq!(client.get("/foo", "localhost").unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn malformed_status_line_is_rejected() {
+		let server = HttpServer::responding_with(vec!["HTTP/1.1 OK\r\n\r\n".to_string()]);
+		let mut client = HttpClient::connect(&server.endpoint()).unwrap();
+		assert_eq!(client.get("/foo", "localhost").unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+}
@@ -0,0 +1,209 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Utilities for bringing persisted listeners up to date with the best chain on startup.
+//!
+//! After deserialization, a `ChannelManager` and each of its `ChannelMonitor`s may have last seen
+//! different blocks, none of which need be in the block source's current best chain (eg if a
+//! reorg happened while the node was offline). [`synchronize_listeners`] rewinds each listener to
+//! the point its chain forks from the best chain and replays the best chain from there, after
+//! which all listeners are at the same block and can be driven together by a single
+//! [`ChainSync`].
+//!
+//! A `ChannelManager` can be synchronized through a `BlockNotifier` with only the manager
+//! registered, using the block from `ChannelManager::current_best_block`. `ChannelMonitor`s must be
+//! synchronized individually, before being handed to a `ManyChannelMonitor`, by wrapping each in
+//! a [`ChannelMonitorListener`] and using the block from `ChannelMonitor::get_last_block`.
+//!
+//! [`synchronize_listeners`]: fn.synchronize_listeners.html
+//! [`ChainSync`]: ../struct.ChainSync.html
+//! [`ChannelMonitorListener`]: struct.ChannelMonitorListener.html
+
+use crate::{BlockHeaderData, BlockListener, BlockSource, BlockSourceResult, ChainSync, HeaderChain};
+
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::hash_types::BlockHash;
+
+use lightning::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use lightning::chain::keysinterface::ChannelKeys;
+use lightning::ln::channelmonitor::ChannelMonitor;
+use lightning::util::logger::Logger;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/// Brings each listener from the block it last saw to the block source's best block, returning
+/// the header of the best block.
+///
+/// Each listener is given along with the hash of the last block it was notified of. Listeners are
+/// synchronized independently, each being notified of the blocks disconnected from its own chain
+/// down to the fork point with the best chain, and then of each block of the best chain from
+/// there, in order. Unlike `ChainSync::sync_best_chain`, listeners are moved to the block
+/// source's best chain even if their own chain has at least as much work.
+///
+/// The returned header can be used to create a `ChainSync` for all listeners going forward.
+///
+/// If an error is returned, listeners may have been partially synchronized, but as each listener
+/// tracks the last block it was notified of, calling this again with their updated blocks will
+/// resume from where it left off.
+pub fn synchronize_listeners<B: BlockSource>(block_source: &B, listeners: Vec<(BlockHash, &dyn BlockListener)>) -> BlockSourceResult<BlockHeaderData> {
+	let (best_block_hash, best_height_hint) = block_source.get_best_block()?;
+	let best_tip = block_source.get_header(&best_block_hash, best_height_hint)?;
+	best_tip.validate(&best_block_hash)?;
+
+	// Headers are shared between listeners, as most will have seen the same chain.
+	let mut header_cache = HashMap::new();
+	header_cache.insert(best_block_hash, best_tip);
+	for (block_hash, listener) in listeners {
+		let chain_tip = match header_cache.get(&block_hash) {
+			Some(header_data) => *header_data,
+			None => {
+				let header_data = block_source.get_header(&block_hash, None)?;
+				header_data.validate(&block_hash)?;
+				header_cache.insert(block_hash, header_data);
+				header_data
+			},
+		};
+		let mut sync = ChainSync { chain: HeaderChain { chain_tip, header_cache }, block_source, listener };
+		let result = sync.sync_to_tip(best_tip);
+		header_cache = sync.chain.header_cache;
+		result?;
+	}
+	Ok(best_tip)
+}
+
+/// Adapts a deserialized `ChannelMonitor` to a `BlockListener`, so it can be synchronized with
+/// `synchronize_listeners` before being added to a `ManyChannelMonitor`.
+///
+/// All transactions in each connected block are passed to the monitor, as it filters out those
+/// not relevant to its channel itself. Any outputs it begins watching are recorded in the monitor
+/// and registered when it is added to a `SimpleManyChannelMonitor`.
+pub struct ChannelMonitorListener<'a, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+{
+	monitor: RefCell<&'a mut ChannelMonitor<ChanSigner>>,
+	broadcaster: T,
+	fee_estimator: F,
+	logger: L,
+}
+
+impl<'a, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref> ChannelMonitorListener<'a, ChanSigner, T, F, L>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+{
+	/// Wraps the given monitor, which will broadcast any claim transactions using `broadcaster`.
+	pub fn new(monitor: &'a mut ChannelMonitor<ChanSigner>, broadcaster: T, fee_estimator: F, logger: L) -> Self {
+		Self { monitor: RefCell::new(monitor), broadcaster, fee_estimator, logger }
+	}
+}
+
+impl<'a, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref> BlockListener for ChannelMonitorListener<'a, ChanSigner, T, F, L>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+{
+	fn block_connected(&self, block: &Block, height: u32) {
+		let txn: Vec<&Transaction> = block.txdata.iter().collect();
+		self.monitor.borrow_mut().block_connected(&block.header, &txn, height, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+	}
+
+	fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+		self.monitor.borrow_mut().block_disconnected(header, height, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::memory::MemoryBlockSource;
+	use bitcoin::network::constants::Network;
+	use std::cell::RefCell;
+
+	/// Tracks the block it was last notified of, as a persisted listener would.
+	struct TipListener { tip: RefCell<(BlockHash, u32)>, notifications: RefCell<usize> }
+	impl TipListener {
+		fn new(tip: (BlockHash, u32)) -> Self { Self { tip: RefCell::new(tip), notifications: RefCell::new(0) } }
+	}
+	impl BlockListener for TipListener {
+		fn block_connected(&self, block: &Block, height: u32) {
+			assert_eq!(block.header.prev_blockhash, self.tip.borrow().0);
+			*self.tip.borrow_mut() = (block.header.block_hash(), height);
+			*self.notifications.borrow_mut() += 1;
+		}
+		fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+			assert_eq!(header.block_hash(), self.tip.borrow().0);
+			*self.tip.borrow_mut() = (header.prev_blockhash, height - 1);
+			*self.notifications.borrow_mut() += 1;
+		}
+	}
+
+	#[test]
+	fn synchronize_listeners_on_different_forks() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let a1 = source.mine_block(&genesis).header.block_hash();
+		let a2 = source.mine_block(&a1).header.block_hash();
+		let b2 = source.mine_block(&a1).header.block_hash();
+		let b3 = source.mine_block(&b2).header.block_hash();
+		let b4 = source.mine_block(&b3).header.block_hash();
+		assert_eq!(source.get_best_block().unwrap().0, b4);
+
+		// One listener behind on the best chain, one on a stale fork, and one already at the tip.
+		let behind = TipListener::new((genesis, 0));
+		let forked = TipListener::new((a2, 2));
+		let current = TipListener::new((b4, 4));
+		let tip = synchronize_listeners(&source, vec![(genesis, &behind), (a2, &forked), (b4, &current)]).unwrap();
+		assert_eq!(tip.header.block_hash(), b4);
+		for listener in &[&behind, &forked, &current] {
+			assert_eq!(*listener.tip.borrow(), (b4, 4));
+		}
+		assert_eq!(*behind.notifications.borrow(), 4);
+		assert_eq!(*forked.notifications.borrow(), 4);
+		assert_eq!(*current.notifications.borrow(), 0);
+	}
+
+	#[test]
+	fn synchronize_listener_to_chain_with_less_work() {
+		// Unlike ChainSync, a listener on a chain with more work than the block source's best chain
+		// is still moved to the source's chain.
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let a1 = source.mine_block(&genesis).header.block_hash();
+		let a2 = source.mine_block(&a1).header.block_hash();
+		let b1 = source.mine_block(&genesis).header.block_hash();
+		source.set_best_block(&b1);
+
+		let listener = TipListener::new((a2, 2));
+		synchronize_listeners(&source, vec![(a2, &listener)]).unwrap();
+		assert_eq!(*listener.tip.borrow(), (b1, 1));
+	}
+
+	#[test]
+	fn synchronize_listeners_resumes_after_error() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let b1 = source.mine_block(&genesis);
+		let b2 = source.mine_block(&b1.header.block_hash()).header.block_hash();
+		let b1 = source.remove_block_data(&b1.header.block_hash()).unwrap();
+
+		let listener = TipListener::new((genesis, 0));
+		assert!(synchronize_listeners(&source, vec![(genesis, &listener)]).is_err());
+		assert_eq!(*listener.tip.borrow(), (genesis, 0));
+
+		source.restore_block_data(b1);
+		let tip = listener.tip.borrow().0;
+		synchronize_listeners(&source, vec![(tip, &listener)]).unwrap();
+		assert_eq!(*listener.tip.borrow(), (b2, 2));
+	}
+}
@@ -0,0 +1,550 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A lightweight client for keeping in sync with chain activity.
+//!
+//! Defines a [`BlockSource`] trait, which is an interface for fetching block headers and data
+//! from a source of chain data (eg a full node), along with implementations for Bitcoin Core's
+//! REST and RPC interfaces in the [`rest`] and [`rpc`] modules and an in-memory implementation in
+//! the [`memory`] module.
+//!
+//! [`ChainSync`] polls a `BlockSource` for its best chain and, when it has more work than the
+//! chain currently known, finds the fork point with the known chain by walking back through
+//! header ancestry and notifies a [`BlockListener`] of each block disconnected from and then
+//! connected to the best chain, in order. `BlockListener` is implemented for
+//! `lightning::chain::chaininterface::BlockNotifier`, so a `ChainSync` can directly drive all
+//! `ChainListener`s registered with a `BlockNotifier`.
+//!
+//! Both the header ancestry and each block's contents are validated before being passed on:
+//! headers must meet their stated proof-of-work target and connect to their parent with the
+//! expected height and chainwork, and blocks must match their header's hash and merkle root.
+//!
+//! On startup, listeners which were persisted at different blocks (eg a `ChannelManager` and each
+//! of its `ChannelMonitor`s) can each be brought to the block source's best block with
+//! [`init::synchronize_listeners`], after which a single `ChainSync` can keep all of them in sync.
+//!
+//! Light clients can instead use [`filter::FilteredChainSync`], which only fetches blocks whose BIP
+//! 158 compact filters match the scripts and outpoints being watched, or
+//! [`electrum::ElectrumChainSync`], which queries an Electrum server for the transactions paying
+//! to or spending from those scripts and notifies `ConfirmationListener`s of their confirmation.
+//!
+//! [`BlockSource`]: trait.BlockSource.html
+//! [`rest`]: rest/index.html
+//! [`rpc`]: rpc/index.html
+//! [`memory`]: memory/index.html
+//! [`ChainSync`]: struct.ChainSync.html
+//! [`BlockListener`]: trait.BlockListener.html
+//! [`init::synchronize_listeners`]: init/fn.synchronize_listeners.html
+//! [`filter::FilteredChainSync`]: filter/struct.FilteredChainSync.html
+//! [`electrum::ElectrumChainSync`]: electrum/struct.ElectrumChainSync.html
+
+#![deny(missing_docs)]
+
+pub mod electrum;
+pub mod filter;
+pub mod http;
+pub mod init;
+pub mod memory;
+pub mod rest;
+pub mod rpc;
+
+mod convert;
+
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::hash_types::BlockHash;
+use bitcoin::util::uint::Uint256;
+use lightning::chain::chaininterface::{BlockNotifier, ChainListener, ChainWatchInterface};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+
+/// Abstract type for retrieving block headers and data.
+pub trait BlockSource {
+	/// Returns the header for a given hash. A height hint may be provided in case a block source
+	/// cannot easily find headers based on a hash. This is merely a hint and thus the returned
+	/// header must have the same hash as was requested. Otherwise, an error must be returned.
+	///
+	/// Implementations that cannot find headers based on the hash should return a `Transient` error
+	/// when `height_hint` is `None`.
+	fn get_header(&self, header_hash: &BlockHash, height_hint: Option<u32>) -> BlockSourceResult<BlockHeaderData>;
+
+	/// Returns the block for a given hash. A headers-only block source should return a `Transient`
+	/// error.
+	fn get_block(&self, header_hash: &BlockHash) -> BlockSourceResult<Block>;
+
+	/// Returns the hash of the best block and, optionally, its height.
+	///
+	/// When polling a block source, `ChainSync` passes the height as the hint when fetching the best
+	/// block's header.
+	fn get_best_block(&self) -> BlockSourceResult<(BlockHash, Option<u32>)>;
+}
+
+/// Result type for `BlockSource` requests.
+pub type BlockSourceResult<T> = Result<T, BlockSourceError>;
+
+/// Error type for `BlockSource` requests.
+///
+/// Transient errors may be resolved when re-polling, but no attempt will be made to re-poll on
+/// persistent errors.
+#[derive(Debug)]
+pub struct BlockSourceError {
+	kind: BlockSourceErrorKind,
+	error: Box<dyn std::error::Error + Send + Sync>,
+}
+
+/// The kind of `BlockSourceError`, either persistent or transient.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockSourceErrorKind {
+	/// Indicates an error that won't resolve when retrying a request (e.g., invalid data).
+	Persistent,
+
+	/// Indicates an error that may resolve when retrying a request (e.g., unresponsive).
+	Transient,
+}
+
+impl BlockSourceError {
+	/// Creates a new persistent error originated from the given error.
+	pub fn persistent<E>(error: E) -> Self
+	where E: Into<Box<dyn std::error::Error + Send + Sync>> {
+		Self {
+			kind: BlockSourceErrorKind::Persistent,
+			error: error.into(),
+		}
+	}
+
+	/// Creates a new transient error originated from the given error.
+	pub fn transient<E>(error: E) -> Self
+	where E: Into<Box<dyn std::error::Error + Send + Sync>> {
+		Self {
+			kind: BlockSourceErrorKind::Transient,
+			error: error.into(),
+		}
+	}
+
+	/// Returns the kind of error.
+	pub fn kind(&self) -> BlockSourceErrorKind {
+		self.kind
+	}
+
+	/// Converts the error into the underlying error.
+	pub fn into_inner(self) -> Box<dyn std::error::Error + Send + Sync> {
+		self.error
+	}
+}
+
+impl fmt::Display for BlockSourceError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:?} block source error: {}", self.kind, self.error)
+	}
+}
+
+impl std::error::Error for BlockSourceError {}
+
+/// A block header and some associated data. This information should be available from most block
+/// sources (and, notably, is available in Bitcoin Core's RPC and REST interfaces).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockHeaderData {
+	/// The block header itself.
+	pub header: BlockHeader,
+
+	/// The block height where the genesis block has height 0.
+	pub height: u32,
+
+	/// The total chain work in expected number of double-SHA256 hashes required to build a chain
+	/// of equivalent weight.
+	pub chainwork: Uint256,
+}
+
+impl BlockHeaderData {
+	/// Checks that the header hashes to `block_hash` and meets the proof-of-work target it
+	/// commits to.
+	fn validate(&self, block_hash: &BlockHash) -> BlockSourceResult<()> {
+		if self.header.block_hash() != *block_hash {
+			return Err(BlockSourceError::persistent("invalid block hash"));
+		}
+		if self.header.validate_pow(&self.header.target()).is_err() {
+			return Err(BlockSourceError::persistent("block header does not meet its proof-of-work target"));
+		}
+		Ok(())
+	}
+
+	/// Checks that this header directly builds on `parent`, with the height and chainwork that
+	/// implies.
+	fn check_builds_on(&self, parent: &BlockHeaderData) -> BlockSourceResult<()> {
+		if self.header.prev_blockhash != parent.header.block_hash() {
+			return Err(BlockSourceError::persistent("block header does not connect to its parent"));
+		}
+		if self.height != parent.height + 1 {
+			return Err(BlockSourceError::persistent("invalid block height"));
+		}
+		if self.chainwork != parent.chainwork + self.header.work() {
+			return Err(BlockSourceError::persistent("invalid chainwork"));
+		}
+		Ok(())
+	}
+}
+
+/// Checks that a block fetched for `block_hash` has that hash and commits to its transactions.
+fn validate_block(block: &Block, block_hash: &BlockHash) -> BlockSourceResult<()> {
+	if block.header.block_hash() != *block_hash {
+		return Err(BlockSourceError::persistent("invalid block hash"));
+	}
+	if !block.check_merkle_root() {
+		return Err(BlockSourceError::persistent("invalid merkle root"));
+	}
+	Ok(())
+}
+
+/// Receives notifications of blocks connected to and disconnected from the best chain.
+///
+/// Blocks are always disconnected from the tip downwards before blocks of the new best chain are
+/// connected from the fork point upwards.
+pub trait BlockListener {
+	/// Notifies the listener that a block was added at the given height.
+	fn block_connected(&self, block: &Block, height: u32);
+
+	/// Notifies the listener that the block at the given height, with the given header, was
+	/// removed from the best chain.
+	fn block_disconnected(&self, header: &BlockHeader, height: u32);
+}
+
+impl<'a, CL: Deref + 'a, C: Deref> BlockListener for BlockNotifier<'a, CL, C>
+		where CL::Target: ChainListener + 'a, C::Target: ChainWatchInterface {
+	fn block_connected(&self, block: &Block, height: u32) {
+		BlockNotifier::block_connected(self, block, height)
+	}
+
+	fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+		BlockNotifier::block_disconnected(self, header, height)
+	}
+}
+
+/// The number of headers below the chain tip which are kept in `ChainSync`'s header cache, and
+/// thus the deepest reorg which can be handled without re-fetching headers of our own chain from
+/// the block source.
+pub const HEADER_CACHE_DEPTH: u32 = 6 * 24 * 7;
+
+/// Keeps a `BlockListener` in sync with the best chain of a `BlockSource`.
+///
+/// `ChainSync` is created with the tip of the chain the listener has already seen (eg as persisted
+/// along with the listener's own state). Each call to `sync_best_chain` then polls the block
+/// source for its best block and, if that chain has more work than the current tip's, walks back
+/// both chains to their common ancestor. Blocks no longer in the best chain are disconnected from
+/// the current tip downwards, after which each block of the new best chain is fetched and
+/// connected in order.
+///
+/// If a request to the block source fails part way through, the listener is left on the last
+/// block successfully connected (or disconnected), which `chain_tip` reflects, and the next call
+/// to `sync_best_chain` will pick up from there.
+pub struct ChainSync<B: Deref, L: Deref> where B::Target: BlockSource, L::Target: BlockListener {
+	chain: HeaderChain,
+	block_source: B,
+	listener: L,
+}
+
+impl<B: Deref, L: Deref> ChainSync<B, L> where B::Target: BlockSource, L::Target: BlockListener {
+	/// Creates a new ChainSync which will notify `listener` of any changes to the best chain of
+	/// `block_source` relative to `chain_tip`, which should be the last block the listener has
+	/// been notified of.
+	pub fn new(chain_tip: BlockHeaderData, block_source: B, listener: L) -> Self {
+		Self { chain: HeaderChain::new(chain_tip), block_source, listener }
+	}
+
+	/// Creates a new ChainSync whose `listener` last saw the block with the given hash, fetching
+	/// its header from `block_source`.
+	pub fn from_block_hash(block_hash: &BlockHash, block_source: B, listener: L) -> BlockSourceResult<Self> {
+		let chain_tip = block_source.get_header(block_hash, None)?;
+		chain_tip.validate(block_hash)?;
+		Ok(Self::new(chain_tip, block_source, listener))
+	}
+
+	/// Gets the header of the last block the listener was notified of.
+	pub fn chain_tip(&self) -> &BlockHeaderData {
+		&self.chain.chain_tip
+	}
+
+	/// Polls the block source for its best chain and, if it has more work than the current chain,
+	/// notifies the listener of the blocks disconnected and connected to switch to it.
+	///
+	/// Returns whether the chain tip changed. Note that the tip may have changed even if an error
+	/// is returned.
+	pub fn sync_best_chain(&mut self) -> BlockSourceResult<bool> {
+		match self.chain.poll_best_tip(&*self.block_source)? {
+			Some(best_tip) => self.sync_to_tip(best_tip),
+			None => Ok(false),
+		}
+	}
+
+	/// Notifies the listener of the blocks disconnected and connected to switch from the current
+	/// chain tip to `best_tip`, regardless of which has more work.
+	fn sync_to_tip(&mut self, best_tip: BlockHeaderData) -> BlockSourceResult<bool> {
+		let block_source = &*self.block_source;
+		let listener = &*self.listener;
+		self.chain.sync_to_tip(block_source, best_tip,
+			|header_data| listener.block_disconnected(&header_data.header, header_data.height),
+			|header_data| {
+				let block_hash = header_data.header.block_hash();
+				let block = block_source.get_block(&block_hash)?;
+				validate_block(&block, &block_hash)?;
+				listener.block_connected(&block, header_data.height);
+				Ok(())
+			})
+	}
+}
+
+/// The chain a listener was last notified of, along with a cache of the headers needed to find
+/// where it forks from a new best chain. Shared by the different ways of syncing listeners, which
+/// differ only in how each block is connected.
+struct HeaderChain {
+	chain_tip: BlockHeaderData,
+	/// Headers of the current best chain (and any headers seen while searching for a fork point),
+	/// pruned to those within HEADER_CACHE_DEPTH of the tip.
+	header_cache: HashMap<BlockHash, BlockHeaderData>,
+}
+
+impl HeaderChain {
+	fn new(chain_tip: BlockHeaderData) -> Self {
+		let mut header_cache = HashMap::new();
+		header_cache.insert(chain_tip.header.block_hash(), chain_tip);
+		Self { chain_tip, header_cache }
+	}
+
+	/// Polls the block source for its best block, returning its header if it has more work than
+	/// the current chain tip.
+	fn poll_best_tip<S: BlockSource + ?Sized>(&mut self, block_source: &S) -> BlockSourceResult<Option<BlockHeaderData>> {
+		let (best_block_hash, best_height_hint) = block_source.get_best_block()?;
+		if best_block_hash == self.chain_tip.header.block_hash() {
+			return Ok(None);
+		}
+		let best_tip = self.look_up_header(block_source, &best_block_hash, best_height_hint)?;
+		if best_tip.chainwork <= self.chain_tip.chainwork {
+			// Either a stale tip or a competing chain with no more work than ours, neither of
+			// which we switch to.
+			return Ok(None);
+		}
+		Ok(Some(best_tip))
+	}
+
+	/// Switches from the current chain tip to `best_tip`, calling `disconnect` for each block no
+	/// longer in the best chain (from the tip down) and then `connect` for each block of the new
+	/// best chain (from the fork point up). Returns whether the chain tip changed.
+	fn sync_to_tip<S: BlockSource + ?Sized, D, C>(&mut self, block_source: &S, best_tip: BlockHeaderData, mut disconnect: D, mut connect: C) -> BlockSourceResult<bool>
+		where D: FnMut(&BlockHeaderData), C: FnMut(&BlockHeaderData) -> BlockSourceResult<()>
+	{
+		// Walk back both chains until they meet, collecting the blocks to disconnect (from the
+		// current tip down) and those to connect (from the new tip down).
+		let mut blocks_to_disconnect = Vec::new();
+		let mut blocks_to_connect = Vec::new();
+		let mut old_walk = self.chain_tip;
+		let mut new_walk = best_tip;
+		while old_walk.header.block_hash() != new_walk.header.block_hash() {
+			if new_walk.height >= old_walk.height {
+				let parent = self.look_up_parent(block_source, &new_walk)?;
+				blocks_to_connect.push(new_walk);
+				new_walk = parent;
+			} else {
+				let parent = self.look_up_parent(block_source, &old_walk)?;
+				blocks_to_disconnect.push(old_walk);
+				old_walk = parent;
+			}
+		}
+
+		for header_data in blocks_to_disconnect {
+			let parent = self.header_cache[&header_data.header.prev_blockhash];
+			disconnect(&header_data);
+			self.header_cache.remove(&header_data.header.block_hash());
+			self.chain_tip = parent;
+		}
+
+		let mut tip_changed = self.chain_tip.header.block_hash() != best_tip.header.block_hash();
+		for header_data in blocks_to_connect.drain(..).rev() {
+			connect(&header_data)?;
+			self.chain_tip = header_data;
+			tip_changed = true;
+		}
+
+		let min_cached_height = self.chain_tip.height.saturating_sub(HEADER_CACHE_DEPTH);
+		self.header_cache.retain(|_, header_data| header_data.height >= min_cached_height);
+		Ok(tip_changed)
+	}
+
+	/// Gets the header with the given hash, from the cache if possible and otherwise from the block
+	/// source, validating any header fetched.
+	fn look_up_header<S: BlockSource + ?Sized>(&mut self, block_source: &S, block_hash: &BlockHash, height_hint: Option<u32>) -> BlockSourceResult<BlockHeaderData> {
+		if let Some(header_data) = self.header_cache.get(block_hash) {
+			return Ok(*header_data);
+		}
+		let header_data = block_source.get_header(block_hash, height_hint)?;
+		header_data.validate(block_hash)?;
+		self.header_cache.insert(*block_hash, header_data);
+		Ok(header_data)
+	}
+
+	/// Gets the parent of the given header, checking that the header builds on it.
+	fn look_up_parent<S: BlockSource + ?Sized>(&mut self, block_source: &S, header_data: &BlockHeaderData) -> BlockSourceResult<BlockHeaderData> {
+		if header_data.height == 0 {
+			return Err(BlockSourceError::persistent("chains do not share a genesis block"));
+		}
+		let parent = self.look_up_header(block_source, &header_data.header.prev_blockhash, Some(header_data.height - 1))?;
+		header_data.check_builds_on(&parent)?;
+		Ok(parent)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::block::{Block, BlockHeader};
+	use bitcoin::hash_types::BlockHash;
+	use bitcoin::network::constants::Network;
+	use super::{BlockListener, BlockSource, BlockSourceErrorKind, ChainSync};
+	use super::memory::MemoryBlockSource;
+	use std::cell::RefCell;
+
+	#[derive(Debug, PartialEq)]
+	enum Notification {
+		Connected(BlockHash, u32),
+		Disconnected(BlockHash, u32),
+	}
+
+	struct TestListener { notifications: RefCell<Vec<Notification>> }
+	impl TestListener {
+		fn new() -> Self { Self { notifications: RefCell::new(Vec::new()) } }
+		fn take(&self) -> Vec<Notification> { self.notifications.borrow_mut().drain(..).collect() }
+	}
+	impl BlockListener for TestListener {
+		fn block_connected(&self, block: &Block, height: u32) {
+			self.notifications.borrow_mut().push(Notification::Connected(block.header.block_hash(), height));
+		}
+		fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+			self.notifications.borrow_mut().push(Notification::Disconnected(header.block_hash(), height));
+		}
+	}
+
+	#[test]
+	fn sync_from_genesis() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let mut hashes = vec![genesis];
+		for _ in 0..3 {
+			let prev = *hashes.last().unwrap();
+			hashes.push(source.mine_block(&prev).header.block_hash());
+		}
+
+		let listener = TestListener::new();
+		let mut sync = ChainSync::from_block_hash(&genesis, &source, &listener).unwrap();
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![
+			Notification::Connected(hashes[1], 1),
+			Notification::Connected(hashes[2], 2),
+			Notification::Connected(hashes[3], 3),
+		]);
+		assert_eq!(sync.chain_tip().height, 3);
+
+		// Polling again without any new blocks is a no-op.
+		assert!(!sync.sync_best_chain().unwrap());
+		assert!(listener.take().is_empty());
+	}
+
+	#[test]
+	fn sync_across_reorg() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let fork_point = source.mine_block(&genesis).header.block_hash();
+		let a2 = source.mine_block(&fork_point).header.block_hash();
+		let a3 = source.mine_block(&a2).header.block_hash();
+
+		let listener = TestListener::new();
+		let mut sync = ChainSync::from_block_hash(&genesis, &source, &listener).unwrap();
+		sync.sync_best_chain().unwrap();
+		listener.take();
+
+		// A competing chain with the same amount of work is ignored...
+		let b2 = source.mine_block(&fork_point).header.block_hash();
+		let b3 = source.mine_block(&b2).header.block_hash();
+		source.set_best_block(&b3);
+		assert!(!sync.sync_best_chain().unwrap());
+		assert!(listener.take().is_empty());
+
+		// ...until it has more work, at which point we disconnect back to the fork point and
+		// connect the new chain, in order.
+		let b4 = source.mine_block(&b3).header.block_hash();
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![
+			Notification::Disconnected(a3, 3),
+			Notification::Disconnected(a2, 2),
+			Notification::Connected(b2, 2),
+			Notification::Connected(b3, 3),
+			Notification::Connected(b4, 4),
+		]);
+		assert_eq!(sync.chain_tip().header.block_hash(), b4);
+	}
+
+	#[test]
+	fn sync_to_shorter_chain_with_more_work() {
+		// A single block with a harder target outweighs several minimum-difficulty blocks.
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let a1 = source.mine_block(&genesis).header.block_hash();
+		let a2 = source.mine_block(&a1).header.block_hash();
+
+		let listener = TestListener::new();
+		let mut sync = ChainSync::from_block_hash(&genesis, &source, &listener).unwrap();
+		sync.sync_best_chain().unwrap();
+		listener.take();
+
+		let b1 = source.mine_block_with_bits(&genesis, 0x1f7fffff).header.block_hash();
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![
+			Notification::Disconnected(a2, 2),
+			Notification::Disconnected(a1, 1),
+			Notification::Connected(b1, 1),
+		]);
+	}
+
+	#[test]
+	fn sync_resumes_after_unavailable_block() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let b1 = source.mine_block(&genesis).header.block_hash();
+		let b2 = source.mine_block(&b1).header.block_hash();
+
+		let listener = TestListener::new();
+		let mut sync = ChainSync::from_block_hash(&genesis, &source, &listener).unwrap();
+		let block = source.remove_block_data(&b2).unwrap();
+		match sync.sync_best_chain() {
+			Err(e) => assert_eq!(e.kind(), BlockSourceErrorKind::Transient),
+			Ok(_) => panic!("Expected an error fetching a missing block"),
+		}
+		// We connected as far as we could.
+		assert_eq!(listener.take(), vec![Notification::Connected(b1, 1)]);
+		assert_eq!(sync.chain_tip().header.block_hash(), b1);
+
+		source.restore_block_data(block);
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(listener.take(), vec![Notification::Connected(b2, 2)]);
+	}
+
+	#[test]
+	fn sync_rejects_invalid_chain() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis = source.get_best_block().unwrap().0;
+		let b1 = source.mine_block(&genesis).header.block_hash();
+		source.corrupt_chainwork(&b1);
+		source.mine_block(&b1);
+
+		let listener = TestListener::new();
+		let mut sync = ChainSync::from_block_hash(&genesis, &source, &listener).unwrap();
+		match sync.sync_best_chain() {
+			Err(e) => assert_eq!(e.kind(), BlockSourceErrorKind::Persistent),
+			Ok(_) => panic!("Expected an error syncing a chain with invalid chainwork"),
+		}
+		assert!(listener.take().is_empty());
+		assert_eq!(sync.chain_tip().header.block_hash(), genesis);
+	}
+}
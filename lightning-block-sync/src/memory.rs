@@ -0,0 +1,215 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! An in-memory `BlockSource`, useful for testing components which consume chain data without a
+//! full node.
+
+use crate::{BlockHeaderData, BlockSource, BlockSourceError, BlockSourceResult};
+use crate::filter::BlockFilterSource;
+
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+use bitcoin::util::bip158::{self, BlockFilter};
+use bitcoin::hash_types::BlockHash;
+use bitcoin::network::constants::Network;
+use bitcoin::util::uint::Uint256;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+struct StoredBlock {
+	header_data: BlockHeaderData,
+	/// None if the block's data has been removed with `remove_block_data`.
+	block: Option<Block>,
+}
+
+struct ChainState {
+	blocks: HashMap<BlockHash, StoredBlock>,
+	best_block: BlockHash,
+	blocks_mined: u64,
+}
+
+/// A `BlockSource` which serves blocks from memory.
+///
+/// Blocks are added by mining them on top of any known block, allowing arbitrary forks to be
+/// built. As with a full node, the best block is updated whenever a newly-mined block has more
+/// chainwork than the current best block, though it may also be set explicitly to simulate a
+/// source following a different tip.
+pub struct MemoryBlockSource {
+	state: Mutex<ChainState>,
+}
+
+impl MemoryBlockSource {
+	/// Creates a new block source containing only the genesis block of the given network.
+	pub fn new(network: Network) -> Self {
+		let genesis = genesis_block(network);
+		let genesis_hash = genesis.header.block_hash();
+		let header_data = BlockHeaderData {
+			header: genesis.header,
+			height: 0,
+			chainwork: genesis.header.work(),
+		};
+		let mut blocks = HashMap::new();
+		blocks.insert(genesis_hash, StoredBlock { header_data, block: Some(genesis) });
+		Self {
+			state: Mutex::new(ChainState { blocks, best_block: genesis_hash, blocks_mined: 0 }),
+		}
+	}
+
+	/// Mines a block with the minimum regtest difficulty on top of the block with the given hash,
+	/// returning it.
+	///
+	/// Panics if the parent block is not known.
+	pub fn mine_block(&self, prev_blockhash: &BlockHash) -> Block {
+		self.mine_block_with_bits(prev_blockhash, 0x207fffff)
+	}
+
+	/// Mines a block with the given compact difficulty target on top of the block with the given
+	/// hash, returning it. Harder targets take exponentially longer to mine.
+	///
+	/// Panics if the parent block is not known.
+	pub fn mine_block_with_bits(&self, prev_blockhash: &BlockHash, bits: u32) -> Block {
+		self.mine(prev_blockhash, bits, Vec::new())
+	}
+
+	/// Mines a block with the minimum regtest difficulty containing the given transactions, after
+	/// a coinbase transaction, on top of the block with the given hash, returning it.
+	///
+	/// Transactions are not validated, but the outputs they spend should exist in an ancestor
+	/// block (or earlier in `txdata`) for the block's filter to be constructed.
+	///
+	/// Panics if the parent block is not known.
+	pub fn mine_block_with_txdata(&self, prev_blockhash: &BlockHash, txdata: Vec<Transaction>) -> Block {
+		self.mine(prev_blockhash, 0x207fffff, txdata)
+	}
+
+	fn mine(&self, prev_blockhash: &BlockHash, bits: u32, mut txdata: Vec<Transaction>) -> Block {
+		let mut state = self.state.lock().unwrap();
+		let parent = state.blocks.get(prev_blockhash).expect("unknown parent block").header_data;
+		let height = parent.height + 1;
+
+		// Give each block a unique coinbase so that blocks mined on the same parent differ.
+		state.blocks_mined += 1;
+		let coinbase = Transaction {
+			version: 1,
+			lock_time: 0,
+			input: vec![TxIn {
+				previous_output: OutPoint::null(),
+				script_sig: Builder::new().push_int(height as i64).push_int(state.blocks_mined as i64).into_script(),
+				sequence: 0xffffffff,
+				witness: Vec::new(),
+			}],
+			output: vec![TxOut { value: 50_0000_0000, script_pubkey: Builder::new().push_opcode(opcodes::OP_TRUE).into_script() }],
+		};
+		let mut block = Block {
+			header: BlockHeader {
+				version: 0x20000000,
+				prev_blockhash: *prev_blockhash,
+				merkle_root: Default::default(),
+				time: parent.header.time + 600,
+				bits,
+				nonce: 0,
+			},
+			txdata: vec![coinbase],
+		};
+		block.txdata.append(&mut txdata);
+		block.header.merkle_root = block.merkle_root();
+		while block.header.validate_pow(&block.header.target()).is_err() {
+			block.header.nonce += 1;
+		}
+
+		let block_hash = block.header.block_hash();
+		let header_data = BlockHeaderData {
+			header: block.header,
+			height,
+			chainwork: parent.chainwork + block.header.work(),
+		};
+		if header_data.chainwork > state.blocks[&state.best_block].header_data.chainwork {
+			state.best_block = block_hash;
+		}
+		state.blocks.insert(block_hash, StoredBlock { header_data, block: Some(block.clone()) });
+		block
+	}
+
+	/// Sets the block returned as the best block, regardless of its chainwork.
+	///
+	/// Panics if the block is not known.
+	pub fn set_best_block(&self, block_hash: &BlockHash) {
+		let mut state = self.state.lock().unwrap();
+		assert!(state.blocks.contains_key(block_hash), "unknown block");
+		state.best_block = *block_hash;
+	}
+
+	/// Removes the data for the block with the given hash, simulating a pruned or headers-only
+	/// source. Its header remains available. Returns the removed block, if any.
+	pub fn remove_block_data(&self, block_hash: &BlockHash) -> Option<Block> {
+		self.state.lock().unwrap().blocks.get_mut(block_hash).and_then(|stored| stored.block.take())
+	}
+
+	/// Restores data previously removed with `remove_block_data`.
+	pub fn restore_block_data(&self, block: Block) {
+		if let Some(stored) = self.state.lock().unwrap().blocks.get_mut(&block.header.block_hash()) {
+			stored.block = Some(block);
+		}
+	}
+
+	/// Makes the chainwork reported for the given block invalid, simulating a misbehaving source.
+	/// Blocks subsequently mined on top of it will build on the invalid chainwork.
+	pub fn corrupt_chainwork(&self, block_hash: &BlockHash) {
+		if let Some(stored) = self.state.lock().unwrap().blocks.get_mut(block_hash) {
+			stored.header_data.chainwork = stored.header_data.chainwork + Uint256::from_u64(1).unwrap();
+		}
+	}
+}
+
+impl BlockSource for MemoryBlockSource {
+	fn get_header(&self, header_hash: &BlockHash, _height_hint: Option<u32>) -> BlockSourceResult<BlockHeaderData> {
+		match self.state.lock().unwrap().blocks.get(header_hash) {
+			Some(stored) => Ok(stored.header_data),
+			None => Err(BlockSourceError::transient("header not found")),
+		}
+	}
+
+	fn get_block(&self, header_hash: &BlockHash) -> BlockSourceResult<Block> {
+		match self.state.lock().unwrap().blocks.get(header_hash).and_then(|stored| stored.block.as_ref()) {
+			Some(block) => Ok(block.clone()),
+			None => Err(BlockSourceError::transient("block not found")),
+		}
+	}
+
+	fn get_best_block(&self) -> BlockSourceResult<(BlockHash, Option<u32>)> {
+		let state = self.state.lock().unwrap();
+		let best_block = state.best_block;
+		Ok((best_block, Some(state.blocks[&best_block].header_data.height)))
+	}
+}
+
+impl BlockFilterSource for MemoryBlockSource {
+	fn get_filter(&self, header_hash: &BlockHash) -> BlockSourceResult<BlockFilter> {
+		let state = self.state.lock().unwrap();
+		let block = match state.blocks.get(header_hash).and_then(|stored| stored.block.as_ref()) {
+			Some(block) => block,
+			None => return Err(BlockSourceError::transient("block not found")),
+		};
+		// Look up spent outputs among all known blocks, which is good enough for tests.
+		let script_for_coin = |outpoint: &OutPoint| {
+			state.blocks.values()
+				.filter_map(|stored| stored.block.as_ref())
+				.flat_map(|block| block.txdata.iter())
+				.find(|tx| tx.txid() == outpoint.txid)
+				.and_then(|tx| tx.output.get(outpoint.vout as usize))
+				.map(|output| output.script_pubkey.clone())
+				.ok_or(bip158::Error::UtxoMissing(*outpoint))
+		};
+		BlockFilter::new_script_filter(block, script_for_coin).map_err(BlockSourceError::persistent)
+	}
+}
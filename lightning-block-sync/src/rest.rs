@@ -0,0 +1,183 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A `BlockSource` backed by Bitcoin Core's REST interface (enabled with `-rest`).
+
+use crate::{BlockHeaderData, BlockSource, BlockSourceError, BlockSourceResult};
+use crate::convert;
+use crate::http::{HttpClient, HttpEndpoint};
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::hash_types::BlockHash;
+use bitcoin::hashes::hex::ToHex;
+
+use std::io;
+use std::sync::Mutex;
+
+/// A simple REST client for requesting resources using HTTP `GET`.
+pub struct RestClient {
+	endpoint: HttpEndpoint,
+	client: Mutex<HttpClient>,
+}
+
+impl RestClient {
+	/// Creates a new REST client connected to the given endpoint, whose path should be that of the
+	/// REST interface, eg `/rest`.
+	pub fn new(endpoint: HttpEndpoint) -> io::Result<Self> {
+		let client = Mutex::new(HttpClient::connect(&endpoint)?);
+		Ok(Self { endpoint, client })
+	}
+
+	/// Requests a resource using HTTP `GET`, returning the response body.
+	pub fn request_resource(&self, resource_path: &str) -> io::Result<Vec<u8>> {
+		let host = format!("{}:{}", self.endpoint.host(), self.endpoint.port());
+		let uri = format!("{}/{}", self.endpoint.path().trim_end_matches('/'), resource_path);
+		self.client.lock().unwrap().get(&uri, &host)
+	}
+}
+
+/// Maps an error from a request to a `BlockSourceError`: malformed responses are persistent,
+/// while connection failures and error responses (eg for unknown blocks) may be transient.
+fn map_request_error(e: io::Error) -> BlockSourceError {
+	match e.kind() {
+		io::ErrorKind::InvalidData => BlockSourceError::persistent(e),
+		_ => BlockSourceError::transient(e),
+	}
+}
+
+impl BlockSource for RestClient {
+	fn get_header(&self, header_hash: &BlockHash, _height_hint: Option<u32>) -> BlockSourceResult<BlockHeaderData> {
+		let resource_path = format!("headers/1/{}.json", header_hash.to_hex());
+		let response = self.request_resource(&resource_path).map_err(map_request_error)?;
+		let value = convert::json_from_bytes(&response).map_err(BlockSourceError::persistent)?;
+		match value.as_array().and_then(|headers| headers.first()) {
+			Some(header) => convert::header_data_from_json(header).map_err(BlockSourceError::persistent),
+			// Bitcoin Core responds with an empty array for unknown headers.
+			None if value.is_array() => Err(BlockSourceError::transient("header not found")),
+			None => Err(BlockSourceError::persistent("expected a JSON array of headers")),
+		}
+	}
+
+	fn get_block(&self, header_hash: &BlockHash) -> BlockSourceResult<Block> {
+		let resource_path = format!("block/{}.bin", header_hash.to_hex());
+		let response = self.request_resource(&resource_path).map_err(map_request_error)?;
+		convert::block_from_bytes(&response).map_err(BlockSourceError::persistent)
+	}
+
+	fn get_best_block(&self) -> BlockSourceResult<(BlockHash, Option<u32>)> {
+		let response = self.request_resource("chaininfo.json").map_err(map_request_error)?;
+		let value = convert::json_from_bytes(&response).map_err(BlockSourceError::persistent)?;
+		convert::best_block_from_json(&value).map_err(BlockSourceError::persistent)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{BlockSourceErrorKind, ChainSync, BlockListener};
+	use crate::convert::tests::header_data_to_json;
+	use crate::http::tests::{HttpServer, ok_response};
+	use crate::memory::MemoryBlockSource;
+	use bitcoin::blockdata::block::BlockHeader;
+	use bitcoin::consensus::encode;
+	use bitcoin::network::constants::Network;
+	use std::cell::RefCell;
+
+	fn rest_client(server: &HttpServer) -> RestClient {
+		RestClient::new(server.endpoint().with_path("/rest".to_string())).unwrap()
+	}
+
+	#[test]
+	fn get_header_from_json() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let genesis_hash = source.get_best_block().unwrap().0;
+		let header_data = source.get_header(&genesis_hash, None).unwrap();
+		let server = HttpServer::responding_with_ok(&serde_json::json!([header_data_to_json(&header_data)]).to_string());
+		let client = rest_client(&server);
+		assert_eq!(client.get_header(&genesis_hash, None).unwrap(), header_data);
+		assert!(server.requests()[0].starts_with(&format!("GET /rest/headers/1/{}.json HTTP/1.1\r\n", genesis_hash.to_hex())));
+	}
+
+	#[test]
+	fn get_unknown_header() {
+		let server = HttpServer::responding_with_ok("[]");
+		let client = rest_client(&server);
+		let e = client.get_header(&Default::default(), None).unwrap_err();
+		assert_eq!(e.kind(), BlockSourceErrorKind::Transient);
+	}
+
+	#[test]
+	fn get_header_with_malformed_response() {
+		let server = HttpServer::responding_with_ok("{\"foo\": 42}");
+		let client = rest_client(&server);
+		let e = client.get_header(&Default::default(), None).unwrap_err();
+		assert_eq!(e.kind(), BlockSourceErrorKind::Persistent);
+	}
+
+	#[test]
+	fn get_block_with_malformed_response() {
+		let server = HttpServer::responding_with_ok("foo");
+		let client = rest_client(&server);
+		let e = client.get_block(&Default::default()).unwrap_err();
+		assert_eq!(e.kind(), BlockSourceErrorKind::Persistent);
+	}
+
+	#[test]
+	fn get_block_not_found() {
+		let server = HttpServer::responding_with(vec!["HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()]);
+		let client = rest_client(&server);
+		let e = client.get_block(&Default::default()).unwrap_err();
+		assert_eq!(e.kind(), BlockSourceErrorKind::Transient);
+	}
+
+	#[test]
+	fn get_best_block_from_chain_info() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let (genesis_hash, _) = source.get_best_block().unwrap();
+		let server = HttpServer::responding_with_ok(&format!("{{\"chain\": \"regtest\", \"blocks\": 0, \"bestblockhash\": \"{}\"}}", genesis_hash.to_hex()));
+		let client = rest_client(&server);
+		assert_eq!(client.get_best_block().unwrap(), (genesis_hash, Some(0)));
+		assert!(server.requests()[0].starts_with("GET /rest/chaininfo.json HTTP/1.1\r\n"));
+	}
+
+	#[test]
+	fn sync_through_rest_client() {
+		// Serve a short chain, built in memory, over the mock REST interface, and check that
+		// ChainSync connects it. The block is served using chunked encoding.
+		struct Listener(RefCell<Vec<u32>>);
+		impl BlockListener for Listener {
+			fn block_connected(&self, _block: &Block, height: u32) { self.0.borrow_mut().push(height); }
+			fn block_disconnected(&self, _header: &BlockHeader, _height: u32) { panic!(); }
+		}
+
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let (genesis_hash, _) = source.get_best_block().unwrap();
+		let block = source.mine_block(&genesis_hash);
+		let block_hash = block.header.block_hash();
+		let genesis_header = source.get_header(&genesis_hash, None).unwrap();
+		let header = source.get_header(&block_hash, None).unwrap();
+
+		let block_bytes = encode::serialize(&block);
+		let mut block_response = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n", block_bytes.len()).into_bytes();
+		block_response.extend_from_slice(&block_bytes);
+		block_response.extend_from_slice(b"\r\n0\r\n\r\n");
+		let server = HttpServer::responding_with_bytes(vec![
+			ok_response(&serde_json::json!([header_data_to_json(&genesis_header)]).to_string()).into_bytes(),
+			ok_response(&format!("{{\"blocks\": 1, \"bestblockhash\": \"{}\"}}", block_hash.to_hex())).into_bytes(),
+			ok_response(&serde_json::json!([header_data_to_json(&header)]).to_string()).into_bytes(),
+			block_response,
+		]);
+		let client = rest_client(&server);
+		let listener = Listener(RefCell::new(Vec::new()));
+		let mut sync = ChainSync::from_block_hash(&genesis_hash, &client, &listener).unwrap();
+		assert!(sync.sync_best_chain().unwrap());
+		assert_eq!(*listener.0.borrow(), vec![1]);
+		assert_eq!(sync.chain_tip().header.block_hash(), block_hash);
+	}
+}
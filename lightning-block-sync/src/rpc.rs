@@ -0,0 +1,274 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A `BlockSource` backed by Bitcoin Core's JSON-RPC interface.
+
+use crate::{BlockHeaderData, BlockSource, BlockSourceError, BlockSourceResult};
+use crate::convert;
+use crate::filter::BlockFilterSource;
+use crate::http::{HttpClient, HttpEndpoint, HttpError};
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::hash_types::BlockHash;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::util::bip158::BlockFilter;
+
+use serde_json::{json, Value};
+
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The RPC error code Bitcoin Core returns for unknown blocks (RPC_INVALID_ADDRESS_OR_KEY).
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+/// An error returned by the RPC server in response to a call.
+///
+/// Returned by `RpcClient::call_method` as the inner error of an `io::Error` with kind `Other`.
+#[derive(Debug)]
+pub struct RpcError {
+	/// The error code, as defined by Bitcoin Core's `RPCErrorCode`.
+	pub code: i64,
+	/// The error message.
+	pub message: String,
+}
+
+impl std::error::Error for RpcError {}
+
+impl std::fmt::Display for RpcError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "RPC error {}: {}", self.code, self.message)
+	}
+}
+
+/// A simple RPC client for calling methods using HTTP `POST`.
+pub struct RpcClient {
+	basic_auth: String,
+	endpoint: HttpEndpoint,
+	client: Mutex<HttpClient>,
+	id: AtomicUsize,
+}
+
+impl RpcClient {
+	/// Creates a new RPC client connected to the given endpoint with the provided credentials. The
+	/// credentials should be a base64 encoding of a user name and password joined by a colon, as is
+	/// required for HTTP basic access authentication.
+	pub fn new(credentials: &str, endpoint: HttpEndpoint) -> io::Result<Self> {
+		let client = Mutex::new(HttpClient::connect(&endpoint)?);
+		Ok(Self {
+			basic_auth: "Basic ".to_string() + credentials,
+			endpoint,
+			client,
+			id: AtomicUsize::new(0),
+		})
+	}
+
+	/// Creates a new RPC client connected to the given endpoint, authenticating with the given
+	/// user name and password (eg those set with Bitcoin Core's `-rpcuser` and `-rpcpassword`).
+	pub fn with_user_password(user: &str, password: &str, endpoint: HttpEndpoint) -> io::Result<Self> {
+		Self::new(&base64::encode(format!("{}:{}", user, password)), endpoint)
+	}
+
+	/// Calls a method with the response encoded in JSON format, returning its result.
+	///
+	/// Errors returned by the server are returned as an `io::Error` wrapping an `RpcError`.
+	pub fn call_method(&self, method: &str, params: &[Value]) -> io::Result<Value> {
+		let host = format!("{}:{}", self.endpoint.host(), self.endpoint.port());
+		let uri = self.endpoint.path();
+		let content = json!({
+			"method": method,
+			"params": params,
+			"id": &self.id.fetch_add(1, Ordering::AcqRel).to_string()
+		});
+
+		// Bitcoin Core responds to failed calls with an HTTP error status, but still includes the
+		// RPC error in the response body.
+		let response = match self.client.lock().unwrap().post(uri, &host, &self.basic_auth, content) {
+			Ok(response) => response,
+			Err(e) => match e.get_ref().and_then(|inner| inner.downcast_ref::<HttpError>()) {
+				Some(http_error) if convert::json_from_bytes(&http_error.contents).is_ok() => http_error.contents.clone(),
+				_ => return Err(e),
+			},
+		};
+
+		let mut response = convert::json_from_bytes(&response)?;
+		if !response.is_object() {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "expected JSON object"));
+		}
+		let error = &response["error"];
+		if !error.is_null() {
+			let rpc_error = RpcError {
+				code: error["code"].as_i64().unwrap_or(0),
+				message: error["message"].as_str().unwrap_or("").to_string(),
+			};
+			return Err(io::Error::new(io::ErrorKind::Other, rpc_error));
+		}
+		match response.get_mut("result") {
+			Some(result) => Ok(result.take()),
+			None => Err(io::Error::new(io::ErrorKind::InvalidData, "expected JSON result")),
+		}
+	}
+}
+
+/// Maps an error from a call to a `BlockSourceError`: malformed responses are persistent, as are
+/// RPC errors other than for unknown blocks, while connection failures may be transient.
+fn map_call_error(e: io::Error) -> BlockSourceError {
+	if e.kind() == io::ErrorKind::InvalidData {
+		return BlockSourceError::persistent(e);
+	}
+	match e.get_ref().and_then(|inner| inner.downcast_ref::<RpcError>()).map(|rpc_error| rpc_error.code) {
+		Some(code) if code != RPC_INVALID_ADDRESS_OR_KEY => BlockSourceError::persistent(e),
+		_ => BlockSourceError::transient(e),
+	}
+}
+
+impl BlockSource for RpcClient {
+	fn get_header(&self, header_hash: &BlockHash, _height_hint: Option<u32>) -> BlockSourceResult<BlockHeaderData> {
+		let header_hash = json!(header_hash.to_hex());
+		let result = self.call_method("getblockheader", &[header_hash]).map_err(map_call_error)?;
+		convert::header_data_from_json(&result).map_err(BlockSourceError::persistent)
+	}
+
+	fn get_block(&self, header_hash: &BlockHash) -> BlockSourceResult<Block> {
+		let header_hash = json!(header_hash.to_hex());
+		let verbosity = json!(0);
+		let result = self.call_method("getblock", &[header_hash, verbosity]).map_err(map_call_error)?;
+		convert::block_from_json(&result).map_err(BlockSourceError::persistent)
+	}
+
+	fn get_best_block(&self) -> BlockSourceResult<(BlockHash, Option<u32>)> {
+		let result = self.call_method("getblockchaininfo", &[]).map_err(map_call_error)?;
+		convert::best_block_from_json(&result).map_err(BlockSourceError::persistent)
+	}
+}
+
+/// Requires Bitcoin Core to be run with `-blockfilterindex=basic`.
+impl BlockFilterSource for RpcClient {
+	fn get_filter(&self, header_hash: &BlockHash) -> BlockSourceResult<BlockFilter> {
+		let header_hash = json!(header_hash.to_hex());
+		let filter_type = json!("basic");
+		let result = self.call_method("getblockfilter", &[header_hash, filter_type]).map_err(map_call_error)?;
+		convert::filter_from_json(&result).map_err(BlockSourceError::persistent)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::BlockSourceErrorKind;
+	use crate::convert::tests::header_data_to_json;
+	use crate::http::tests::{HttpServer, ok_response};
+	use crate::memory::MemoryBlockSource;
+	use bitcoin::consensus::encode;
+	use bitcoin::network::constants::Network;
+
+	const CREDENTIALS: &str = "dXNlcjpwYXNzd29yZA==";
+
+	fn rpc_response(result: Value) -> String {
+		ok_response(&json!({ "result": result, "error": null, "id": "0" }).to_string())
+	}
+
+	#[test]
+	fn call_method_sends_request() {
+		let server = HttpServer::responding_with(vec![rpc_response(json!(42))]);
+		let client = RpcClient::with_user_password("user", "password", server.endpoint()).unwrap();
+		assert_eq!(client.call_method("getblockcount", &[]).unwrap(), json!(42));
+		let request = &server.requests()[0];
+		assert!(request.starts_with("POST / HTTP/1.1\r\n"));
+		assert!(request.contains(&format!("Authorization: Basic {}\r\n", CREDENTIALS)));
+		let body: Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+		assert_eq!(body["method"], "getblockcount");
+		assert_eq!(body["params"], json!([]));
+	}
+
+	#[test]
+	fn call_method_returning_rpc_error() {
+		let body = json!({ "result": null, "error": { "code": -8, "message": "invalid parameter" }, "id": "0" }).to_string();
+		let response = format!("HTTP/1.1 500 Internal Server Error\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+		let server = HttpServer::responding_with(vec![response]);
+		let client = RpcClient::new(CREDENTIALS, server.endpoint()).unwrap();
+		let e = client.call_method("getblock", &[]).unwrap_err();
+		let rpc_error = e.into_inner().unwrap().downcast::<RpcError>().unwrap();
+		assert_eq!(rpc_error.code, -8);
+		assert_eq!(rpc_error.message, "invalid parameter");
+	}
+
+	#[test]
+	fn call_method_with_unauthorized_response() {
+		// Bitcoin Core responds to bad credentials with an empty body.
+		let response = "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_string();
+		let server = HttpServer::responding_with(vec![response]);
+		let client = RpcClient::new(CREDENTIALS, server.endpoint()).unwrap();
+		let e = client.call_method("getblockcount", &[]).unwrap_err();
+		let http_error = e.into_inner().unwrap().downcast::<HttpError>().unwrap();
+		assert_eq!(http_error.status_code, "401");
+	}
+
+	#[test]
+	fn call_method_with_malformed_response() {
+		let server = HttpServer::responding_with(vec![ok_response("[42]")]);
+		let client = RpcClient::new(CREDENTIALS, server.endpoint()).unwrap();
+		assert_eq!(client.call_method("getblockcount", &[]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn get_header_and_block() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let (genesis_hash, _) = source.get_best_block().unwrap();
+		let block = source.mine_block(&genesis_hash);
+		let block_hash = block.header.block_hash();
+		let header_data = source.get_header(&block_hash, None).unwrap();
+		let server = HttpServer::responding_with(vec![
+			rpc_response(header_data_to_json(&header_data)),
+			rpc_response(json!(encode::serialize(&block).to_hex())),
+			rpc_response(json!({ "chain": "regtest", "blocks": 1, "bestblockhash": block_hash.to_hex() })),
+		]);
+		let client = RpcClient::new(CREDENTIALS, server.endpoint()).unwrap();
+		assert_eq!(client.get_header(&block_hash, None).unwrap(), header_data);
+		assert_eq!(client.get_block(&block_hash).unwrap(), block);
+		assert_eq!(client.get_best_block().unwrap(), (block_hash, Some(1)));
+
+		let requests = server.requests();
+		assert!(requests[0].contains("\"method\":\"getblockheader\""));
+		assert!(requests[1].contains("\"method\":\"getblock\""));
+		assert!(requests[2].contains("\"method\":\"getblockchaininfo\""));
+	}
+
+	#[test]
+	fn get_filter() {
+		let source = MemoryBlockSource::new(Network::Regtest);
+		let (genesis_hash, _) = source.get_best_block().unwrap();
+		let block_hash = source.mine_block(&genesis_hash).header.block_hash();
+		let filter = source.get_filter(&block_hash).unwrap();
+		let server = HttpServer::responding_with(vec![
+			rpc_response(json!({ "filter": filter.content.to_hex(), "header": "00".repeat(32) })),
+		]);
+		let client = RpcClient::new(CREDENTIALS, server.endpoint()).unwrap();
+		assert_eq!(client.get_filter(&block_hash).unwrap().content, filter.content);
+		let request = &server.requests()[0];
+		assert!(request.contains("\"method\":\"getblockfilter\""));
+		assert!(request.contains(&format!("\"params\":[\"{}\",\"basic\"]", block_hash.to_hex())));
+	}
+
+	#[test]
+	fn get_unknown_block() {
+		let body = json!({ "result": null, "error": { "code": -5, "message": "Block not found" }, "id": "0" }).to_string();
+		let response = format!("HTTP/1.1 404 Not Found\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+		let server = HttpServer::responding_with(vec![response]);
+		let client = RpcClient::new(CREDENTIALS, server.endpoint()).unwrap();
+		assert_eq!(client.get_block(&Default::default()).unwrap_err().kind(), BlockSourceErrorKind::Transient);
+	}
+
+	#[test]
+	fn get_header_with_invalid_result() {
+		let server = HttpServer::responding_with(vec![rpc_response(json!({ "height": 1 }))]);
+		let client = RpcClient::new(CREDENTIALS, server.endpoint()).unwrap();
+		assert_eq!(client.get_header(&Default::default(), None).unwrap_err().kind(), BlockSourceErrorKind::Persistent);
+	}
+}
@@ -0,0 +1,337 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Parsing of invoices from their bech32 form, including checking the payee's signature.
+
+use std::{error, fmt};
+use std::str::FromStr;
+
+use bitcoin::bech32;
+use bitcoin::bech32::{u5, FromBase32};
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::secp256k1::key::PublicKey;
+use bitcoin::secp256k1::recovery::{RecoverableSignature, RecoveryId};
+
+use lightning::ln::channelmanager::{PaymentHash, PaymentSecret};
+use lightning::ln::features::InvoiceFeatures;
+use lightning::routing::network_graph::RoutingFees;
+use lightning::routing::router::RouteHint;
+
+use crate::{signature_message, Currency, Fallback, Invoice, InvoiceDescription, ROUTE_HOP_BYTES};
+use crate::ser::{tags, AMOUNT_MULTIPLIERS, MSAT_PER_BTC};
+
+/// The number of u5s the timestamp takes up at the start of the data part.
+const TIMESTAMP_LEN: usize = 7;
+
+/// The number of u5s the signature (including the recovery id) takes up at the end of the data
+/// part.
+const SIGNATURE_LEN: usize = 104;
+
+/// An error which occurred while parsing an invoice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+	/// The invoice was not valid bech32.
+	Bech32Error(bech32::Error),
+	/// The human-readable part did not start with "ln" followed by a known currency.
+	UnknownCurrency,
+	/// The amount in the human-readable part was malformed or too large.
+	InvalidAmount,
+	/// The data part was too short to contain a timestamp and signature.
+	TooShortDataPart,
+	/// A tagged field's length extended past the end of the data part.
+	UnexpectedEndOfTaggedFields,
+	/// A tagged field we understand, identified by its bech32 character, was malformed.
+	MalformedField(char),
+	/// The invoice did not contain a payment hash (p) field.
+	MissingPaymentHash,
+	/// The invoice contained neither a description (d) nor a description hash (h) field.
+	MissingDescription,
+	/// The signature or its recovery id was malformed.
+	MalformedSignature,
+	/// The signature did not match the payee public key (n) field.
+	InvalidSignature,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match self {
+			ParseError::Bech32Error(e) => write!(f, "Invalid bech32: {}", e),
+			ParseError::UnknownCurrency => f.write_str("Unknown currency"),
+			ParseError::InvalidAmount => f.write_str("Invalid amount"),
+			ParseError::TooShortDataPart => f.write_str("Data part too short to contain a timestamp and signature"),
+			ParseError::UnexpectedEndOfTaggedFields => f.write_str("Tagged field extends past the end of the data part"),
+			ParseError::MalformedField(tag) => write!(f, "Malformed {} field", tag),
+			ParseError::MissingPaymentHash => f.write_str("No payment hash"),
+			ParseError::MissingDescription => f.write_str("No description or description hash"),
+			ParseError::MalformedSignature => f.write_str("Malformed signature"),
+			ParseError::InvalidSignature => f.write_str("Signature does not match the payee public key"),
+		}
+	}
+}
+
+impl error::Error for ParseError {}
+
+impl From<bech32::Error> for ParseError {
+	fn from(e: bech32::Error) -> Self {
+		ParseError::Bech32Error(e)
+	}
+}
+
+/// Parses the currency and amount, in millisatoshis, from the human-readable part.
+fn parse_hrp(hrp: &str) -> Result<(Currency, Option<u64>), ParseError> {
+	if !hrp.starts_with("ln") {
+		return Err(ParseError::UnknownCurrency);
+	}
+	let currency_len = hrp[2..].find(|c: char| c.is_ascii_digit()).unwrap_or(hrp.len() - 2);
+	let currency = Currency::from_hrp_prefix(&hrp[2..2 + currency_len]).ok_or(ParseError::UnknownCurrency)?;
+
+	let amount = &hrp[2 + currency_len..];
+	if amount.is_empty() {
+		return Ok((currency, None));
+	}
+	let (digits, multiplier) = match amount.chars().last() {
+		Some(c) if c.is_ascii_digit() => (amount, None),
+		Some(c) => (&amount[..amount.len() - 1], Some(c)),
+		None => unreachable!(),
+	};
+	if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+		return Err(ParseError::InvalidAmount);
+	}
+	let value: u64 = digits.parse().map_err(|_| ParseError::InvalidAmount)?;
+	let amount_msat = match multiplier {
+		None => value.checked_mul(MSAT_PER_BTC),
+		// Each pico-bitcoin is a tenth of a millisatoshi, so the amount must be a multiple of ten.
+		Some('p') if value % 10 == 0 => Some(value / 10),
+		Some(multiplier) => AMOUNT_MULTIPLIERS.iter()
+			.find(|&&(c, _)| c == multiplier)
+			.and_then(|&(_, msat_per_unit)| value.checked_mul(msat_per_unit)),
+	};
+	match amount_msat {
+		Some(amount_msat) => Ok((currency, Some(amount_msat))),
+		None => Err(ParseError::InvalidAmount),
+	}
+}
+
+/// Parses a big-endian integer from u5s, returning None if it does not fit in a u64.
+fn parse_int(data: &[u5]) -> Option<u64> {
+	let mut res: u64 = 0;
+	for value in data.iter() {
+		if res.leading_zeros() < 5 {
+			return None;
+		}
+		res = (res << 5) | value.to_u8() as u64;
+	}
+	Some(res)
+}
+
+/// Parses features from big-endian u5s, in which the last u5 holds bits 0 through 4.
+fn parse_features(data: &[u5]) -> InvoiceFeatures {
+	let mut flags = vec![0u8; (data.len() * 5 + 7) / 8];
+	for (u5_idx, value) in data.iter().rev().enumerate() {
+		for bit in 0..5 {
+			if value.to_u8() & (1 << bit) != 0 {
+				let flag_bit = u5_idx * 5 + bit;
+				flags[flag_bit / 8] |= 1 << (flag_bit % 8);
+			}
+		}
+	}
+	while flags.last() == Some(&0) {
+		flags.pop();
+	}
+	InvoiceFeatures::from_le_bytes(flags)
+}
+
+fn parse_route(bytes: &[u8]) -> Option<Vec<RouteHint>> {
+	if bytes.len() % ROUTE_HOP_BYTES != 0 {
+		return None;
+	}
+	let mut route = Vec::with_capacity(bytes.len() / ROUTE_HOP_BYTES);
+	for hop in bytes.chunks(ROUTE_HOP_BYTES) {
+		let mut short_channel_id = [0; 8];
+		short_channel_id.copy_from_slice(&hop[33..41]);
+		let mut base_msat = [0; 4];
+		base_msat.copy_from_slice(&hop[41..45]);
+		let mut proportional_millionths = [0; 4];
+		proportional_millionths.copy_from_slice(&hop[45..49]);
+		let mut cltv_expiry_delta = [0; 2];
+		cltv_expiry_delta.copy_from_slice(&hop[49..51]);
+		route.push(RouteHint {
+			src_node_id: PublicKey::from_slice(&hop[0..33]).ok()?,
+			short_channel_id: u64::from_be_bytes(short_channel_id),
+			fees: RoutingFees {
+				base_msat: u32::from_be_bytes(base_msat),
+				proportional_millionths: u32::from_be_bytes(proportional_millionths),
+			},
+			cltv_expiry_delta: u16::from_be_bytes(cltv_expiry_delta),
+			htlc_minimum_msat: 0,
+		});
+	}
+	Some(route)
+}
+
+fn parse_fallback(data: &[u5]) -> Option<Fallback> {
+	let (version, program) = data.split_first()?;
+	let program = Vec::<u8>::from_base32(program).ok()?;
+	match version.to_u8() {
+		0..=16 if program.len() >= 2 && program.len() <= 40 => {
+			Some(Fallback::SegWitProgram { version: *version, program })
+		},
+		17 | 18 if program.len() == 20 => {
+			let mut hash = [0; 20];
+			hash.copy_from_slice(&program);
+			if version.to_u8() == 17 { Some(Fallback::PubKeyHash(hash)) } else { Some(Fallback::ScriptHash(hash)) }
+		},
+		// Readers must skip fallbacks they don't understand.
+		_ => None,
+	}
+}
+
+/// Parses the fields of an invoice from its human-readable part and data part (excluding the
+/// signature), and checks the signature or recovers the payee's public key from it.
+pub(crate) fn parse_signed(hrp: String, data: Vec<u5>, signature: RecoverableSignature) -> Result<Invoice, ParseError> {
+	let (currency, amount_msat) = parse_hrp(&hrp)?;
+	if data.len() < TIMESTAMP_LEN {
+		return Err(ParseError::TooShortDataPart);
+	}
+	let timestamp = parse_int(&data[..TIMESTAMP_LEN]).unwrap();
+
+	let mut payment_hash = None;
+	let mut payment_secret = None;
+	let mut description = None;
+	let mut payee_pub_key = None;
+	let mut expiry_time = None;
+	let mut min_final_cltv_expiry = None;
+	let mut fallbacks = Vec::new();
+	let mut private_routes = Vec::new();
+	let mut features = None;
+
+	let mut fields = &data[TIMESTAMP_LEN..];
+	while !fields.is_empty() {
+		if fields.len() < 3 {
+			return Err(ParseError::UnexpectedEndOfTaggedFields);
+		}
+		let tag = fields[0].to_u8();
+		let len = parse_int(&fields[1..3]).unwrap() as usize;
+		if fields.len() < 3 + len {
+			return Err(ParseError::UnexpectedEndOfTaggedFields);
+		}
+		let field = &fields[3..3 + len];
+		fields = &fields[3 + len..];
+
+		let malformed = ParseError::MalformedField(fields_tag_char(tag));
+		// Per BOLT 11, fixed-length fields with the wrong length are skipped, and only the first
+		// instance of each non-repeatable field is used.
+		match tag {
+			tags::PAYMENT_HASH if len == 52 && payment_hash.is_none() => {
+				let bytes = Vec::<u8>::from_base32(field).map_err(|_| malformed)?;
+				let mut hash = [0; 32];
+				hash.copy_from_slice(&bytes);
+				payment_hash = Some(PaymentHash(hash));
+			},
+			tags::PAYMENT_SECRET if len == 52 && payment_secret.is_none() => {
+				let bytes = Vec::<u8>::from_base32(field).map_err(|_| malformed)?;
+				let mut secret = [0; 32];
+				secret.copy_from_slice(&bytes);
+				payment_secret = Some(PaymentSecret(secret));
+			},
+			tags::DESCRIPTION if description.is_none() => {
+				let bytes = Vec::<u8>::from_base32(field).map_err(|_| malformed.clone())?;
+				description = Some(InvoiceDescription::Direct(String::from_utf8(bytes).map_err(|_| malformed)?));
+			},
+			tags::DESCRIPTION_HASH if len == 52 && description.is_none() => {
+				let bytes = Vec::<u8>::from_base32(field).map_err(|_| malformed)?;
+				description = Some(InvoiceDescription::Hash(sha256::Hash::from_slice(&bytes).unwrap()));
+			},
+			tags::PAYEE_PUB_KEY if len == 53 && payee_pub_key.is_none() => {
+				let bytes = Vec::<u8>::from_base32(field).map_err(|_| malformed.clone())?;
+				payee_pub_key = Some(PublicKey::from_slice(&bytes).map_err(|_| malformed)?);
+			},
+			tags::EXPIRY_TIME if expiry_time.is_none() => {
+				expiry_time = Some(parse_int(field).ok_or(malformed)?);
+			},
+			tags::MIN_FINAL_CLTV_EXPIRY if min_final_cltv_expiry.is_none() => {
+				min_final_cltv_expiry = Some(parse_int(field).ok_or(malformed)?);
+			},
+			tags::FALLBACK => {
+				if let Some(fallback) = parse_fallback(field) {
+					fallbacks.push(fallback);
+				}
+			},
+			tags::PRIVATE_ROUTE => {
+				let bytes = Vec::<u8>::from_base32(field).map_err(|_| malformed.clone())?;
+				private_routes.push(parse_route(&bytes).ok_or(malformed)?);
+			},
+			tags::FEATURES if features.is_none() => {
+				features = Some(parse_features(field));
+			},
+			// Unknown fields are skipped, but remain covered by the signature.
+			_ => {},
+		}
+	}
+
+	let payment_hash = payment_hash.ok_or(ParseError::MissingPaymentHash)?;
+	let description = description.ok_or(ParseError::MissingDescription)?;
+
+	let secp_ctx = Secp256k1::verification_only();
+	let message = signature_message(&hrp, &data);
+	let payee_pub_key = match payee_pub_key {
+		Some(pub_key) => {
+			if secp_ctx.verify(&message, &signature.to_standard(), &pub_key).is_err() {
+				return Err(ParseError::InvalidSignature);
+			}
+			pub_key
+		},
+		None => secp_ctx.recover(&message, &signature).map_err(|_| ParseError::MalformedSignature)?,
+	};
+
+	Ok(Invoice {
+		hrp,
+		data,
+		signature,
+		payee_pub_key,
+		currency,
+		amount_msat,
+		timestamp,
+		payment_hash,
+		payment_secret,
+		description,
+		expiry_time,
+		min_final_cltv_expiry,
+		fallbacks,
+		private_routes,
+		features,
+	})
+}
+
+/// Gets the bech32 character of a tag, for error messages.
+fn fields_tag_char(tag: u8) -> char {
+	u5::try_from_u8(tag).unwrap().to_char()
+}
+
+impl FromStr for Invoice {
+	type Err = ParseError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (hrp, mut data) = bech32::decode(s)?;
+		if data.len() < TIMESTAMP_LEN + SIGNATURE_LEN {
+			return Err(ParseError::TooShortDataPart);
+		}
+
+		let signature_bytes = Vec::<u8>::from_base32(&data.split_off(data.len() - SIGNATURE_LEN))
+			.map_err(|_| ParseError::MalformedSignature)?;
+		let recovery_id = RecoveryId::from_i32(signature_bytes[64] as i32)
+			.map_err(|_| ParseError::MalformedSignature)?;
+		let signature = RecoverableSignature::from_compact(&signature_bytes[..64], recovery_id)
+			.map_err(|_| ParseError::MalformedSignature)?;
+
+		parse_signed(hrp, data, signature)
+	}
+}
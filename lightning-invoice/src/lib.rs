@@ -0,0 +1,550 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+#![deny(missing_docs)]
+#![deny(non_upper_case_globals)]
+#![deny(non_camel_case_types)]
+#![deny(non_snake_case)]
+#![deny(unused_mut)]
+
+//! Parsing, serialization and signing of [BOLT #11] Lightning invoices.
+//!
+//! An [`Invoice`] is parsed from its bech32 form (ie `lnbc...`) with `str::parse`, which also
+//! checks the payee's signature, and is written back out with `to_string`. New invoices are
+//! created with an [`InvoiceBuilder`] and signed with the node secret of a `KeysInterface`.
+//!
+//! Once parsed, the invoice's private routes can be handed to `get_route` via
+//! [`Invoice::route_hints`] and its features checked via [`Invoice::features`].
+//!
+//! [BOLT #11]: https://github.com/lightningnetwork/lightning-rfc/blob/master/11-payment-encoding.md
+//! [`Invoice`]: struct.Invoice.html
+//! [`InvoiceBuilder`]: struct.InvoiceBuilder.html
+//! [`Invoice::route_hints`]: struct.Invoice.html#method.route_hints
+//! [`Invoice::features`]: struct.Invoice.html#method.features
+
+use bitcoin::bech32;
+use bitcoin::bech32::u5;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256;
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::secp256k1::key::{PublicKey, SecretKey};
+use bitcoin::secp256k1::recovery::RecoverableSignature;
+
+use lightning::chain::keysinterface::KeysInterface;
+use lightning::ln::channelmanager::{PaymentHash, PaymentSecret};
+use lightning::ln::features::InvoiceFeatures;
+use lightning::routing::router::RouteHint;
+
+mod de;
+mod ser;
+
+pub use de::ParseError;
+
+/// The number of seconds after its timestamp at which an invoice without an expiry (x) field
+/// expires.
+pub const DEFAULT_EXPIRY_TIME: u64 = 3600;
+
+/// The min_final_cltv_expiry of an invoice without a min_final_cltv_expiry (c) field.
+pub const DEFAULT_MIN_FINAL_CLTV_EXPIRY: u64 = 9;
+
+/// The largest timestamp which fits in the 35-bit timestamp of an invoice.
+pub const MAX_TIMESTAMP: u64 = (1 << 35) - 1;
+
+/// The largest number of bytes which fit in a single tagged field.
+const MAX_TAGGED_FIELD_BYTES: usize = 1023 * 5 / 8;
+
+/// The number of bytes each hop of a private route (r) field takes up.
+const ROUTE_HOP_BYTES: usize = 33 + 8 + 4 + 4 + 2;
+
+/// The currency (ie chain) an invoice is payable on, determined by the prefix of its
+/// human-readable part.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Currency {
+	/// Bitcoin mainnet (lnbc)
+	Bitcoin,
+	/// Bitcoin testnet (lntb)
+	BitcoinTestnet,
+	/// Bitcoin regtest (lnbcrt)
+	Regtest,
+	/// Bitcoin simnet (lnsb)
+	Simnet,
+	/// Bitcoin signet (lntbs)
+	Signet,
+}
+
+impl Currency {
+	fn hrp_prefix(&self) -> &'static str {
+		match self {
+			Currency::Bitcoin => "bc",
+			Currency::BitcoinTestnet => "tb",
+			Currency::Regtest => "bcrt",
+			Currency::Simnet => "sb",
+			Currency::Signet => "tbs",
+		}
+	}
+
+	fn from_hrp_prefix(prefix: &str) -> Option<Currency> {
+		match prefix {
+			"bc" => Some(Currency::Bitcoin),
+			"tb" => Some(Currency::BitcoinTestnet),
+			"bcrt" => Some(Currency::Regtest),
+			"sb" => Some(Currency::Simnet),
+			"tbs" => Some(Currency::Signet),
+			_ => None,
+		}
+	}
+}
+
+impl From<Network> for Currency {
+	fn from(network: Network) -> Self {
+		match network {
+			Network::Bitcoin => Currency::Bitcoin,
+			Network::Testnet => Currency::BitcoinTestnet,
+			Network::Regtest => Currency::Regtest,
+		}
+	}
+}
+
+/// The description of what an invoice is paying for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvoiceDescription {
+	/// A short, human-readable description of the purpose of the payment (d field).
+	Direct(String),
+	/// The SHA256 hash of a longer description which is provided to the payer out of band (h
+	/// field).
+	Hash(sha256::Hash),
+}
+
+/// An on-chain address the payer may fall back to if the payment cannot be made over Lightning
+/// (f field).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fallback {
+	/// A segwit output with the given witness version and program.
+	SegWitProgram {
+		/// The witness version, which must be at most 16
+		version: u5,
+		/// The witness program
+		program: Vec<u8>,
+	},
+	/// A P2PKH output paying to the given public key hash.
+	PubKeyHash([u8; 20]),
+	/// A P2SH output paying to the given script hash.
+	ScriptHash([u8; 20]),
+}
+
+/// A parsed BOLT #11 invoice whose signature has been checked.
+///
+/// Use `str::parse` to parse an invoice and `to_string` to serialize it. The serialized form is
+/// always exactly what was signed, including any tagged fields we don't understand.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invoice {
+	/// The human-readable part, as signed.
+	hrp: String,
+	/// The data part, excluding the signature, as signed.
+	data: Vec<u5>,
+	signature: RecoverableSignature,
+	payee_pub_key: PublicKey,
+
+	currency: Currency,
+	amount_msat: Option<u64>,
+	timestamp: u64,
+	payment_hash: PaymentHash,
+	payment_secret: Option<PaymentSecret>,
+	description: InvoiceDescription,
+	expiry_time: Option<u64>,
+	min_final_cltv_expiry: Option<u64>,
+	fallbacks: Vec<Fallback>,
+	private_routes: Vec<Vec<RouteHint>>,
+	features: Option<InvoiceFeatures>,
+}
+
+impl Invoice {
+	/// The currency this invoice is payable in.
+	pub fn currency(&self) -> Currency { self.currency }
+
+	/// The amount requested, in millisatoshis, if any. An invoice without an amount allows the
+	/// payer to pay whatever they wish.
+	pub fn amount_msat(&self) -> Option<u64> { self.amount_msat }
+
+	/// The time at which the invoice was created, in seconds since the UNIX epoch.
+	pub fn timestamp(&self) -> u64 { self.timestamp }
+
+	/// The hash of the payment preimage which will be revealed once the invoice is paid.
+	pub fn payment_hash(&self) -> &PaymentHash { &self.payment_hash }
+
+	/// The payment secret which the payer must include in the final hop's onion, if any.
+	pub fn payment_secret(&self) -> Option<&PaymentSecret> { self.payment_secret.as_ref() }
+
+	/// The description of what the invoice is paying for.
+	pub fn description(&self) -> &InvoiceDescription { &self.description }
+
+	/// The public key of the node to be paid, which either was included in the invoice and
+	/// checked against the signature, or was recovered from the signature.
+	pub fn payee_pub_key(&self) -> &PublicKey { &self.payee_pub_key }
+
+	/// The number of seconds after its timestamp after which the invoice should no longer be paid.
+	pub fn expiry_time(&self) -> u64 { self.expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME) }
+
+	/// The min_final_cltv_expiry the payer should use for the final hop of the payment.
+	pub fn min_final_cltv_expiry(&self) -> u64 {
+		self.min_final_cltv_expiry.unwrap_or(DEFAULT_MIN_FINAL_CLTV_EXPIRY)
+	}
+
+	/// The on-chain addresses the payer may fall back to.
+	pub fn fallbacks(&self) -> &[Fallback] { &self.fallbacks }
+
+	/// The private routes to the payee included in the invoice. Each route is a list of hops, the
+	/// last of which ends at the payee.
+	pub fn private_routes(&self) -> &[Vec<RouteHint>] { &self.private_routes }
+
+	/// The final hop of each private route, suitable for passing to get_route as last_hops.
+	///
+	/// Note that get_route only supports hints for the last hop, so any earlier hops of a private
+	/// route are not included here.
+	pub fn route_hints(&self) -> Vec<RouteHint> {
+		self.private_routes.iter().filter_map(|route| route.last().cloned()).collect()
+	}
+
+	/// The features the payee supports or requires for this payment, if any were included.
+	pub fn features(&self) -> Option<&InvoiceFeatures> { self.features.as_ref() }
+
+	/// The signature of the payee over the invoice.
+	pub fn signature(&self) -> &RecoverableSignature { &self.signature }
+}
+
+/// Calculates the message the payee signs for an invoice with the given human-readable part and
+/// data part (excluding the signature).
+fn signature_message(hrp: &str, data: &[u5]) -> Message {
+	let mut preimage = hrp.as_bytes().to_vec();
+	preimage.extend_from_slice(&bech32::convert_bits(data, 5, 8, true).expect("u5s are always in range"));
+	let hash = sha256::Hash::hash(&preimage);
+	Message::from_slice(&hash[..]).unwrap()
+}
+
+/// An error which occurred while building an invoice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CreationError {
+	/// No payment hash was provided.
+	MissingPaymentHash,
+	/// Neither a description nor a description hash was provided.
+	MissingDescription,
+	/// No timestamp was provided.
+	MissingTimestamp,
+	/// The timestamp is greater than MAX_TIMESTAMP.
+	TimestampOutOfBounds,
+	/// The amount is too large to be encoded.
+	AmountOutOfBounds,
+	/// The description is too long to fit in a single tagged field (639 bytes).
+	DescriptionTooLong,
+	/// A private route has too many hops to fit in a single tagged field (12 hops).
+	RouteTooLong,
+	/// A fallback has an invalid witness version or program length.
+	InvalidFallback,
+}
+
+/// Builds a new invoice, which is signed with our node secret by [`build_signed`].
+///
+/// A payment hash, a description (or description hash) and a timestamp are required, everything
+/// else is optional.
+///
+/// [`build_signed`]: #method.build_signed
+pub struct InvoiceBuilder {
+	currency: Currency,
+	amount_msat: Option<u64>,
+	timestamp: Option<u64>,
+	payment_hash: Option<PaymentHash>,
+	payment_secret: Option<PaymentSecret>,
+	description: Option<InvoiceDescription>,
+	expiry_time: Option<u64>,
+	min_final_cltv_expiry: Option<u64>,
+	fallbacks: Vec<Fallback>,
+	private_routes: Vec<Vec<RouteHint>>,
+	features: Option<InvoiceFeatures>,
+}
+
+impl InvoiceBuilder {
+	/// Starts building a new invoice payable in the given currency.
+	pub fn new(currency: Currency) -> Self {
+		InvoiceBuilder {
+			currency,
+			amount_msat: None,
+			timestamp: None,
+			payment_hash: None,
+			payment_secret: None,
+			description: None,
+			expiry_time: None,
+			min_final_cltv_expiry: None,
+			fallbacks: Vec::new(),
+			private_routes: Vec::new(),
+			features: None,
+		}
+	}
+
+	/// Sets the amount requested, in millisatoshis.
+	pub fn amount_msat(mut self, amount_msat: u64) -> Self {
+		self.amount_msat = Some(amount_msat);
+		self
+	}
+
+	/// Sets the creation time of the invoice, in seconds since the UNIX epoch.
+	pub fn timestamp(mut self, timestamp: u64) -> Self {
+		self.timestamp = Some(timestamp);
+		self
+	}
+
+	/// Sets the payment hash.
+	pub fn payment_hash(mut self, payment_hash: PaymentHash) -> Self {
+		self.payment_hash = Some(payment_hash);
+		self
+	}
+
+	/// Sets the payment secret.
+	pub fn payment_secret(mut self, payment_secret: PaymentSecret) -> Self {
+		self.payment_secret = Some(payment_secret);
+		self
+	}
+
+	/// Sets a short, human-readable description, replacing any description hash.
+	pub fn description(mut self, description: String) -> Self {
+		self.description = Some(InvoiceDescription::Direct(description));
+		self
+	}
+
+	/// Sets the hash of a longer description, replacing any direct description.
+	pub fn description_hash(mut self, description_hash: sha256::Hash) -> Self {
+		self.description = Some(InvoiceDescription::Hash(description_hash));
+		self
+	}
+
+	/// Sets the number of seconds after the timestamp at which the invoice expires.
+	pub fn expiry_time(mut self, expiry_time: u64) -> Self {
+		self.expiry_time = Some(expiry_time);
+		self
+	}
+
+	/// Sets the min_final_cltv_expiry the payer must use for the final hop.
+	pub fn min_final_cltv_expiry(mut self, min_final_cltv_expiry: u64) -> Self {
+		self.min_final_cltv_expiry = Some(min_final_cltv_expiry);
+		self
+	}
+
+	/// Adds an on-chain fallback address.
+	pub fn fallback(mut self, fallback: Fallback) -> Self {
+		self.fallbacks.push(fallback);
+		self
+	}
+
+	/// Adds a private route to us, the last hop of which must end at our node. Note that the
+	/// htlc_minimum_msat of each hop is not included in the invoice.
+	pub fn private_route(mut self, route: Vec<RouteHint>) -> Self {
+		self.private_routes.push(route);
+		self
+	}
+
+	/// Sets the features we support or require for this payment.
+	pub fn features(mut self, features: InvoiceFeatures) -> Self {
+		self.features = Some(features);
+		self
+	}
+
+	/// Builds the invoice and signs it with the node secret of the given KeysInterface.
+	pub fn build_signed<K: KeysInterface>(self, keys_manager: &K) -> Result<Invoice, CreationError> {
+		self.build_signed_with_secret(&keys_manager.get_node_secret())
+	}
+
+	/// Builds the invoice and signs it with the given node secret.
+	pub fn build_signed_with_secret(self, node_secret: &SecretKey) -> Result<Invoice, CreationError> {
+		let (hrp, data) = self.build_raw()?;
+		let secp_ctx = Secp256k1::signing_only();
+		let signature = secp_ctx.sign_recoverable(&signature_message(&hrp, &data), node_secret);
+		Ok(de::parse_signed(hrp, data, signature).expect("We always build valid invoices"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{Currency, CreationError, Fallback, Invoice, InvoiceBuilder, InvoiceDescription, ParseError};
+
+	use bitcoin::bech32;
+	use bitcoin::bech32::u5;
+	use bitcoin::hashes::Hash;
+	use bitcoin::hashes::sha256;
+	use bitcoin::network::constants::Network;
+	use bitcoin::secp256k1::Secp256k1;
+	use bitcoin::secp256k1::key::{PublicKey, SecretKey};
+
+	use lightning::chain::keysinterface::{KeysInterface, KeysManager};
+	use lightning::ln::channelmanager::{PaymentHash, PaymentSecret};
+	use lightning::ln::features::InvoiceFeatures;
+	use lightning::routing::network_graph::RoutingFees;
+	use lightning::routing::router::RouteHint;
+
+	fn route_hint(node_byte: u8, short_channel_id: u64) -> RouteHint {
+		let secp_ctx = Secp256k1::signing_only();
+		RouteHint {
+			src_node_id: PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[node_byte; 32]).unwrap()),
+			short_channel_id,
+			fees: RoutingFees { base_msat: 1000, proportional_millionths: 100 },
+			cltv_expiry_delta: 40,
+			htlc_minimum_msat: 0,
+		}
+	}
+
+	#[test]
+	fn builds_and_parses_signed_invoice() {
+		let keys_manager = KeysManager::new(&[42; 32], Network::Testnet, 42, 42);
+		let node_id = PublicKey::from_secret_key(&Secp256k1::signing_only(), &keys_manager.get_node_secret());
+
+		let routes = vec![vec![route_hint(1, 42)], vec![route_hint(2, 43), route_hint(3, 44)]];
+		let invoice = InvoiceBuilder::new(Currency::from(Network::Testnet))
+			.amount_msat(2_500_000)
+			.timestamp(1_496_314_658)
+			.payment_hash(PaymentHash([1; 32]))
+			.payment_secret(PaymentSecret([2; 32]))
+			.description("coffee beans".to_owned())
+			.expiry_time(60)
+			.min_final_cltv_expiry(144)
+			.fallback(Fallback::PubKeyHash([3; 20]))
+			.fallback(Fallback::SegWitProgram { version: u5::try_from_u8(0).unwrap(), program: vec![4; 32] })
+			.private_route(routes[0].clone())
+			.private_route(routes[1].clone())
+			.features(InvoiceFeatures::known())
+			.build_signed(&keys_manager).unwrap();
+
+		assert_eq!(invoice.currency(), Currency::BitcoinTestnet);
+		assert_eq!(invoice.amount_msat(), Some(2_500_000));
+		assert_eq!(invoice.timestamp(), 1_496_314_658);
+		assert_eq!(invoice.payment_hash(), &PaymentHash([1; 32]));
+		assert_eq!(invoice.payment_secret(), Some(&PaymentSecret([2; 32])));
+		assert_eq!(invoice.description(), &InvoiceDescription::Direct("coffee beans".to_owned()));
+		assert_eq!(invoice.payee_pub_key(), &node_id);
+		assert_eq!(invoice.expiry_time(), 60);
+		assert_eq!(invoice.min_final_cltv_expiry(), 144);
+		assert_eq!(invoice.fallbacks().len(), 2);
+		assert_eq!(invoice.private_routes(), &routes[..]);
+		assert_eq!(invoice.route_hints(), vec![route_hint(1, 42), route_hint(3, 44)]);
+		assert_eq!(invoice.features(), Some(&InvoiceFeatures::known()));
+
+		let serialized = invoice.to_string();
+		assert!(serialized.starts_with("lntb25u1"));
+		assert_eq!(serialized.parse::<Invoice>().unwrap(), invoice);
+		assert_eq!(serialized.to_uppercase().parse::<Invoice>().unwrap(), invoice);
+	}
+
+	#[test]
+	fn builds_minimal_invoice() {
+		let node_secret = SecretKey::from_slice(&[42; 32]).unwrap();
+		let description_hash = sha256::Hash::hash(b"a very long description");
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.timestamp(0)
+			.payment_hash(PaymentHash([1; 32]))
+			.description_hash(description_hash)
+			.build_signed_with_secret(&node_secret).unwrap();
+
+		assert_eq!(invoice.amount_msat(), None);
+		assert_eq!(invoice.payment_secret(), None);
+		assert_eq!(invoice.description(), &InvoiceDescription::Hash(description_hash));
+		assert_eq!(invoice.expiry_time(), 3600);
+		assert_eq!(invoice.min_final_cltv_expiry(), 9);
+		assert!(invoice.private_routes().is_empty());
+		assert_eq!(invoice.features(), None);
+		assert_eq!(invoice.to_string().parse::<Invoice>().unwrap(), invoice);
+	}
+
+	#[test]
+	fn encodes_amounts_with_largest_multiplier() {
+		let node_secret = SecretKey::from_slice(&[42; 32]).unwrap();
+		for &(amount_msat, hrp) in [(100_000_000_000, "lnbc1"), (250_000_000, "lnbc2500u"), (2_500_000_000, "lnbc25m"),
+				(1_000, "lnbc10n"), (1, "lnbc10p"), (1_234_567, "lnbc12345670p")].iter() {
+			let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+				.amount_msat(amount_msat)
+				.timestamp(0)
+				.payment_hash(PaymentHash([1; 32]))
+				.description(String::new())
+				.build_signed_with_secret(&node_secret).unwrap();
+			let serialized = invoice.to_string();
+			assert!(serialized.starts_with(&format!("{}1", hrp)), "{} should start with {}", serialized, hrp);
+			assert_eq!(serialized.parse::<Invoice>().unwrap().amount_msat(), Some(amount_msat));
+		}
+	}
+
+	#[test]
+	fn fails_to_build_invalid_invoices() {
+		let node_secret = SecretKey::from_slice(&[42; 32]).unwrap();
+		let builder = || InvoiceBuilder::new(Currency::Bitcoin)
+			.timestamp(0)
+			.payment_hash(PaymentHash([1; 32]))
+			.description(String::new());
+
+		assert_eq!(InvoiceBuilder::new(Currency::Bitcoin).timestamp(0).description(String::new())
+			.build_signed_with_secret(&node_secret), Err(CreationError::MissingPaymentHash));
+		assert_eq!(InvoiceBuilder::new(Currency::Bitcoin).timestamp(0).payment_hash(PaymentHash([1; 32]))
+			.build_signed_with_secret(&node_secret), Err(CreationError::MissingDescription));
+		assert_eq!(InvoiceBuilder::new(Currency::Bitcoin).payment_hash(PaymentHash([1; 32])).description(String::new())
+			.build_signed_with_secret(&node_secret), Err(CreationError::MissingTimestamp));
+		assert_eq!(builder().timestamp(1 << 35).build_signed_with_secret(&node_secret),
+			Err(CreationError::TimestampOutOfBounds));
+		assert_eq!(builder().amount_msat(u64::max_value()).build_signed_with_secret(&node_secret),
+			Err(CreationError::AmountOutOfBounds));
+		assert_eq!(builder().description(String::from_utf8(vec![b'a'; 640]).unwrap()).build_signed_with_secret(&node_secret),
+			Err(CreationError::DescriptionTooLong));
+		assert!(builder().description(String::from_utf8(vec![b'a'; 639]).unwrap()).build_signed_with_secret(&node_secret).is_ok());
+		assert_eq!(builder().private_route(vec![route_hint(1, 42); 13]).build_signed_with_secret(&node_secret),
+			Err(CreationError::RouteTooLong));
+		assert!(builder().private_route(vec![route_hint(1, 42); 12]).build_signed_with_secret(&node_secret).is_ok());
+		assert_eq!(builder().fallback(Fallback::SegWitProgram { version: u5::try_from_u8(17).unwrap(), program: vec![0; 20] })
+			.build_signed_with_secret(&node_secret), Err(CreationError::InvalidFallback));
+	}
+
+	#[test]
+	fn parses_bolt11_test_vector() {
+		// The "donation" example from BOLT #11
+		let invoice: Invoice = "lnbc1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq8rkx3yf5tcsyz3d73gafnh3cax9rn449d9p5uxz9ezhhypd0elx87sjle52x86fux2ypatgddc6k63n7erqz25le42c4u4ecky03ylcqca784w".parse().unwrap();
+		assert_eq!(invoice.currency(), Currency::Bitcoin);
+		assert_eq!(invoice.amount_msat(), None);
+		assert_eq!(invoice.timestamp(), 1496314658);
+		assert_eq!(&invoice.payment_hash().0[..], &hex::decode("0001020304050607080900010203040506070809000102030405060708090102").unwrap()[..]);
+		assert_eq!(invoice.description(), &InvoiceDescription::Direct("Please consider supporting this project".to_owned()));
+		assert_eq!(invoice.payee_pub_key(), &PublicKey::from_slice(&hex::decode("03e7156ae33b0a208d0744199163177e909e80176e55d97a2f221ede0f934dd9ad").unwrap()[..]).unwrap());
+	}
+
+	#[test]
+	fn fails_to_parse_invalid_invoices() {
+		let node_secret = SecretKey::from_slice(&[42; 32]).unwrap();
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.amount_msat(1000)
+			.timestamp(0)
+			.payment_hash(PaymentHash([1; 32]))
+			.description("test".to_owned())
+			.build_signed_with_secret(&node_secret).unwrap();
+		let (hrp, data) = bech32::decode(&invoice.to_string()).unwrap();
+
+		assert_eq!("lnbc10n1".parse::<Invoice>(), Err(ParseError::Bech32Error(bech32::Error::InvalidLength)));
+		let reencode = |hrp: &str, data: &[u5]| bech32::encode(hrp, data).unwrap().parse::<Invoice>();
+		assert_eq!(reencode("lnxx10n", &data), Err(ParseError::UnknownCurrency));
+		assert_eq!(reencode("bc10n", &data), Err(ParseError::UnknownCurrency));
+		assert_eq!(reencode("lnbc10x", &data), Err(ParseError::InvalidAmount));
+		assert_eq!(reencode("lnbc11p", &data), Err(ParseError::InvalidAmount));
+		assert_eq!(reencode("lnbc99999999999999999999", &data), Err(ParseError::InvalidAmount));
+		assert_eq!(reencode(&hrp, &data[..100]), Err(ParseError::TooShortDataPart));
+
+		// Changing the amount invalidates the signature, which we notice as the recovered payee
+		// no longer matches.
+		let tampered: Invoice = bech32::encode("lnbc20n", &data).unwrap().parse().unwrap();
+		assert_eq!(tampered.amount_msat(), Some(2000));
+		assert_ne!(tampered.payee_pub_key(), invoice.payee_pub_key());
+
+		// A signature with an invalid recovery id fails to parse.
+		let mut bad_sig_data = data.clone();
+		let last = bad_sig_data.len() - 1;
+		bad_sig_data[last] = u5::try_from_u8(31).unwrap();
+		assert_eq!(reencode(&hrp, &bad_sig_data), Err(ParseError::MalformedSignature));
+	}
+}
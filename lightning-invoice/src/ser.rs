@@ -0,0 +1,213 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Serialization of invoices into their bech32 form.
+
+use std::fmt;
+
+use bitcoin::bech32;
+use bitcoin::bech32::{u5, ToBase32};
+use bitcoin::hashes::Hash;
+
+use lightning::ln::features::InvoiceFeatures;
+use lightning::routing::router::RouteHint;
+
+use crate::{CreationError, Fallback, Invoice, InvoiceBuilder, InvoiceDescription, MAX_TAGGED_FIELD_BYTES, MAX_TIMESTAMP};
+
+/// The tags of the tagged fields we understand, as bech32 values.
+pub(crate) mod tags {
+	pub const PAYMENT_HASH: u8 = 1; // p
+	pub const PRIVATE_ROUTE: u8 = 3; // r
+	pub const FEATURES: u8 = 5; // 9
+	pub const EXPIRY_TIME: u8 = 6; // x
+	pub const FALLBACK: u8 = 9; // f
+	pub const DESCRIPTION: u8 = 13; // d
+	pub const PAYMENT_SECRET: u8 = 16; // s
+	pub const PAYEE_PUB_KEY: u8 = 19; // n
+	pub const DESCRIPTION_HASH: u8 = 23; // h
+	pub const MIN_FINAL_CLTV_EXPIRY: u8 = 24; // c
+}
+
+/// The multipliers which may follow the amount in the human-readable part, along with the number
+/// of millisatoshis each unit of the multiplied amount is worth. Pico-bitcoin is handled
+/// separately as each unit is worth a tenth of a millisatoshi.
+pub(crate) const AMOUNT_MULTIPLIERS: [(char, u64); 3] = [
+	('m', 100_000_000),
+	('u', 100_000),
+	('n', 100),
+];
+
+/// The number of millisatoshis in a bitcoin.
+pub(crate) const MSAT_PER_BTC: u64 = 100_000_000_000;
+
+/// Encodes an amount for the human-readable part, using the largest multiplier which can
+/// represent it exactly.
+fn encode_amount(amount_msat: u64) -> Result<String, CreationError> {
+	if amount_msat % MSAT_PER_BTC == 0 {
+		return Ok((amount_msat / MSAT_PER_BTC).to_string());
+	}
+	for &(multiplier, msat_per_unit) in AMOUNT_MULTIPLIERS.iter() {
+		if amount_msat % msat_per_unit == 0 {
+			return Ok(format!("{}{}", amount_msat / msat_per_unit, multiplier));
+		}
+	}
+	match amount_msat.checked_mul(10) {
+		Some(amount_pico_btc) => Ok(format!("{}p", amount_pico_btc)),
+		None => Err(CreationError::AmountOutOfBounds),
+	}
+}
+
+/// Encodes an integer as the minimal number of big-endian u5s.
+fn encode_int(mut value: u64) -> Vec<u5> {
+	let mut res = Vec::new();
+	while value != 0 {
+		res.push(u5::try_from_u8((value & 0x1f) as u8).unwrap());
+		value >>= 5;
+	}
+	res.reverse();
+	res
+}
+
+/// Encodes an integer as exactly `len` big-endian u5s.
+fn encode_int_padded(value: u64, len: usize) -> Vec<u5> {
+	let mut res = encode_int(value);
+	let mut padded = vec![u5::try_from_u8(0).unwrap(); len - res.len()];
+	padded.append(&mut res);
+	padded
+}
+
+/// Encodes features as big-endian u5s, in which the last u5 holds bits 0 through 4.
+fn encode_features(features: &InvoiceFeatures) -> Vec<u5> {
+	let flags = features.le_flags();
+	let bit_count = flags.iter().enumerate().rev()
+		.find(|&(_, byte)| *byte != 0)
+		.map(|(idx, byte)| idx * 8 + 8 - byte.leading_zeros() as usize)
+		.unwrap_or(0);
+	let mut res = Vec::new();
+	for u5_idx in (0..(bit_count + 4) / 5).rev() {
+		let mut value = 0;
+		for bit in 0..5 {
+			let flag_bit = u5_idx * 5 + bit;
+			if flag_bit < flags.len() * 8 && flags[flag_bit / 8] & (1 << (flag_bit % 8)) != 0 {
+				value |= 1 << bit;
+			}
+		}
+		res.push(u5::try_from_u8(value).unwrap());
+	}
+	res
+}
+
+fn encode_route(route: &[RouteHint]) -> Vec<u8> {
+	let mut res = Vec::with_capacity(route.len() * crate::ROUTE_HOP_BYTES);
+	for hop in route.iter() {
+		res.extend_from_slice(&hop.src_node_id.serialize());
+		res.extend_from_slice(&hop.short_channel_id.to_be_bytes());
+		res.extend_from_slice(&hop.fees.base_msat.to_be_bytes());
+		res.extend_from_slice(&hop.fees.proportional_millionths.to_be_bytes());
+		res.extend_from_slice(&hop.cltv_expiry_delta.to_be_bytes());
+	}
+	res
+}
+
+/// Appends a tagged field to the data part. The field must fit in 1023 u5s.
+fn write_tagged_field(data: &mut Vec<u5>, tag: u8, field: &[u5]) {
+	assert!(field.len() < 1024);
+	data.push(u5::try_from_u8(tag).unwrap());
+	data.extend_from_slice(&encode_int_padded(field.len() as u64, 2));
+	data.extend_from_slice(field);
+}
+
+impl InvoiceBuilder {
+	/// Serializes the human-readable part and the data part (excluding the signature) of the
+	/// invoice being built.
+	pub(crate) fn build_raw(&self) -> Result<(String, Vec<u5>), CreationError> {
+		let payment_hash = self.payment_hash.as_ref().ok_or(CreationError::MissingPaymentHash)?;
+		let description = self.description.as_ref().ok_or(CreationError::MissingDescription)?;
+		let timestamp = self.timestamp.ok_or(CreationError::MissingTimestamp)?;
+		if timestamp > MAX_TIMESTAMP {
+			return Err(CreationError::TimestampOutOfBounds);
+		}
+
+		let mut hrp = format!("ln{}", self.currency.hrp_prefix());
+		if let Some(amount_msat) = self.amount_msat {
+			hrp.push_str(&encode_amount(amount_msat)?);
+		}
+
+		let mut data = encode_int_padded(timestamp, 7);
+		write_tagged_field(&mut data, tags::PAYMENT_HASH, &payment_hash.0.to_base32());
+		match description {
+			InvoiceDescription::Direct(description) => {
+				if description.len() > MAX_TAGGED_FIELD_BYTES {
+					return Err(CreationError::DescriptionTooLong);
+				}
+				write_tagged_field(&mut data, tags::DESCRIPTION, &description.as_bytes().to_base32());
+			},
+			InvoiceDescription::Hash(hash) => {
+				write_tagged_field(&mut data, tags::DESCRIPTION_HASH, &hash.into_inner().to_base32());
+			},
+		}
+		if let Some(ref payment_secret) = self.payment_secret {
+			write_tagged_field(&mut data, tags::PAYMENT_SECRET, &payment_secret.0.to_base32());
+		}
+		if let Some(expiry_time) = self.expiry_time {
+			write_tagged_field(&mut data, tags::EXPIRY_TIME, &encode_int(expiry_time));
+		}
+		if let Some(min_final_cltv_expiry) = self.min_final_cltv_expiry {
+			write_tagged_field(&mut data, tags::MIN_FINAL_CLTV_EXPIRY, &encode_int(min_final_cltv_expiry));
+		}
+		for fallback in self.fallbacks.iter() {
+			let mut field = Vec::new();
+			match fallback {
+				Fallback::SegWitProgram { version, program } => {
+					if version.to_u8() > 16 || program.len() < 2 || program.len() > 40 {
+						return Err(CreationError::InvalidFallback);
+					}
+					field.push(*version);
+					field.extend_from_slice(&program.to_base32());
+				},
+				Fallback::PubKeyHash(hash) => {
+					field.push(u5::try_from_u8(17).unwrap());
+					field.extend_from_slice(&hash.to_base32());
+				},
+				Fallback::ScriptHash(hash) => {
+					field.push(u5::try_from_u8(18).unwrap());
+					field.extend_from_slice(&hash.to_base32());
+				},
+			}
+			write_tagged_field(&mut data, tags::FALLBACK, &field);
+		}
+		for route in self.private_routes.iter() {
+			let route_bytes = encode_route(route);
+			if route_bytes.len() > MAX_TAGGED_FIELD_BYTES {
+				return Err(CreationError::RouteTooLong);
+			}
+			write_tagged_field(&mut data, tags::PRIVATE_ROUTE, &route_bytes.to_base32());
+		}
+		if let Some(ref features) = self.features {
+			write_tagged_field(&mut data, tags::FEATURES, &encode_features(features));
+		}
+
+		Ok((hrp, data))
+	}
+}
+
+impl fmt::Display for Invoice {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		let (recovery_id, signature) = self.signature.serialize_compact();
+		let mut signature_bytes = signature.to_vec();
+		signature_bytes.push(recovery_id.to_i32() as u8);
+
+		let mut data = self.data.clone();
+		data.extend_from_slice(&signature_bytes.to_base32());
+		match bech32::encode_to_fmt(f, &self.hrp, data) {
+			Ok(res) => res,
+			Err(_) => Err(fmt::Error),
+		}
+	}
+}
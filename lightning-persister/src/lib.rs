@@ -0,0 +1,602 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Utilities for persisting Rust-Lightning channel data to, and reloading it from, the local
+//! filesystem.
+//!
+//! [`FilesystemPersister`] handles the on-disk layout and atomic writes of `ChannelMonitor`s,
+//! `ChannelMonitorUpdate`s and the `ChannelManager`, while [`FilesystemChannelMonitor`] wraps a
+//! `SimpleManyChannelMonitor` to provide a `ManyChannelMonitor` which durably persists every
+//! monitor and monitor update before returning to the `ChannelManager`.
+//!
+//! [`FilesystemPersister`]: struct.FilesystemPersister.html
+//! [`FilesystemChannelMonitor`]: struct.FilesystemChannelMonitor.html
+
+#![deny(missing_docs)]
+
+mod util;
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::hash_types::{BlockHash, Txid};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use lightning::chain::chaininterface::{BroadcasterInterface, ChainListener, ChainWatchInterface, FeeEstimator};
+use lightning::chain::keysinterface::{ChannelKeys, KeysInterface};
+use lightning::chain::transaction::OutPoint;
+use lightning::ln::channelmanager::ChannelManager;
+use lightning::ln::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateErr, ManyChannelMonitor, MonitorEvent, MonitorUpdateError, SimpleManyChannelMonitor};
+use lightning::util::events::{Event, EventsProvider};
+use lightning::util::logger::Logger;
+use lightning::util::ser::{Readable, Writeable};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// The number of `ChannelMonitorUpdate`s after which `FilesystemChannelMonitor` rewrites a
+/// channel's full `ChannelMonitor` and removes the now-redundant updates from disk.
+pub const UPDATES_PER_FULL_PERSIST: u64 = 64;
+
+const MONITORS_DIR: &str = "monitors";
+const UPDATES_DIR_SUFFIX: &str = ".updates";
+const MANAGER_FILE: &str = "manager";
+
+/// FilesystemPersister writes channel data to, and reads it back from, a directory on the local
+/// filesystem. Every write is atomic: data is written to a temporary file which is fsync'd and
+/// then renamed into place, so a crash never leaves a partially-written file behind.
+///
+/// The data directory is laid out as follows:
+///  * `manager` holds the latest serialized `ChannelManager`,
+///  * `monitors/<funding txid>_<funding output index>` holds the latest full `ChannelMonitor` for
+///    the channel with the given funding outpoint, as written by `ChannelMonitor::write_for_disk`,
+///  * `monitors/<funding txid>_<funding output index>.updates/<update_id>` holds each
+///    `ChannelMonitorUpdate` applied to that channel since its full `ChannelMonitor` was written.
+pub struct FilesystemPersister {
+	path_to_channel_data: PathBuf,
+}
+
+impl FilesystemPersister {
+	/// Initialize a new FilesystemPersister and set the path to the individual channels'
+	/// files. The directory is created on the first write if it does not yet exist.
+	pub fn new(path_to_channel_data: String) -> Self {
+		Self {
+			path_to_channel_data: PathBuf::from(path_to_channel_data),
+		}
+	}
+
+	/// Get the directory which was provided when this persister was initialized.
+	pub fn get_data_dir(&self) -> String {
+		self.path_to_channel_data.to_str().expect("path was created from a String").to_string()
+	}
+
+	fn monitors_dir(&self) -> PathBuf {
+		self.path_to_channel_data.join(MONITORS_DIR)
+	}
+
+	fn monitor_path(&self, funding_txo: &OutPoint) -> PathBuf {
+		self.monitors_dir().join(format!("{}_{}", funding_txo.txid.to_hex(), funding_txo.index))
+	}
+
+	fn updates_dir(&self, funding_txo: &OutPoint) -> PathBuf {
+		self.monitors_dir().join(format!("{}_{}{}", funding_txo.txid.to_hex(), funding_txo.index, UPDATES_DIR_SUFFIX))
+	}
+
+	/// Writes the provided `ChannelManager` to the `manager` file in the data directory.
+	///
+	/// The signature of this method matches the persister expected by
+	/// `lightning-background-processor`, so it may be passed there as
+	/// `move |manager| persister.persist_manager(manager)`.
+	pub fn persist_manager<ChanSigner, M, T, K, F, L>(&self, manager: &ChannelManager<ChanSigner, M, T, K, F, L>) -> Result<(), Error>
+		where ChanSigner: ChannelKeys + Writeable,
+		      M: Deref, M::Target: ManyChannelMonitor<Keys=ChanSigner>,
+		      T: Deref, T::Target: BroadcasterInterface,
+		      K: Deref, K::Target: KeysInterface<ChanKeySigner=ChanSigner>,
+		      F: Deref, F::Target: FeeEstimator,
+		      L: Deref, L::Target: Logger,
+	{
+		util::write_to_file(&self.path_to_channel_data.join(MANAGER_FILE), |writer| manager.write(writer))
+	}
+
+	/// Writes the full `ChannelMonitor` for the channel with the given funding outpoint, after
+	/// which any `ChannelMonitorUpdate`s it already includes are removed from disk.
+	pub fn persist_monitor<ChanSigner: ChannelKeys + Writeable>(&self, funding_txo: &OutPoint, monitor: &ChannelMonitor<ChanSigner>) -> Result<(), Error> {
+		util::write_to_file(&self.monitor_path(funding_txo), |writer| monitor.write_for_disk(writer))?;
+		self.prune_monitor_updates(funding_txo, monitor.get_latest_update_id())
+	}
+
+	/// Writes a single `ChannelMonitorUpdate` for the channel with the given funding outpoint. On
+	/// reload, it is replayed on top of the last full `ChannelMonitor` written with
+	/// `persist_monitor`.
+	pub fn persist_monitor_update(&self, funding_txo: &OutPoint, update: &ChannelMonitorUpdate) -> Result<(), Error> {
+		let path = self.updates_dir(funding_txo).join(update.update_id.to_string());
+		util::write_to_file(&path, |writer| update.write(writer))
+	}
+
+	/// Removes the stored updates for the given channel with an update_id at or below
+	/// `up_to_update_id`.
+	fn prune_monitor_updates(&self, funding_txo: &OutPoint, up_to_update_id: u64) -> Result<(), Error> {
+		let updates_dir = self.updates_dir(funding_txo);
+		if !updates_dir.exists() {
+			return Ok(());
+		}
+		for (update_id, path) in list_update_files(&updates_dir)? {
+			if update_id <= up_to_update_id {
+				fs::remove_file(path)?;
+			}
+		}
+		util::sync_dir(&updates_dir)
+	}
+
+	/// Reads all stored `ChannelMonitor`s, replaying any stored `ChannelMonitorUpdate`s on top of
+	/// each, and returns them keyed by funding outpoint along with the last block hash each has
+	/// seen.
+	///
+	/// The returned monitors can be passed by reference in
+	/// `ChannelManagerReadArgs::channel_monitors` and then handed to a `ManyChannelMonitor` (eg
+	/// via `FilesystemChannelMonitor::load_monitor`). As with any deserialized `ChannelMonitor`,
+	/// you MUST rescan any blocks since each monitor's returned block hash before using it.
+	///
+	/// `broadcaster` and `logger` are used while replaying updates, eg to rebroadcast our latest
+	/// commitment transaction if the channel was force-closed.
+	///
+	/// If replaying a given update fails, as it will have when it was first applied, no later
+	/// updates are replayed for that channel, leaving the monitor in the same state it was in
+	/// before shutdown.
+	pub fn read_channelmonitors<ChanSigner: ChannelKeys + Readable, B: Deref, L: Deref>(&self, broadcaster: &B, logger: &L)
+		-> Result<HashMap<OutPoint, (BlockHash, ChannelMonitor<ChanSigner>)>, Error>
+		where B::Target: BroadcasterInterface,
+		      L::Target: Logger,
+	{
+		let mut res = HashMap::new();
+		let monitors_dir = self.monitors_dir();
+		if !monitors_dir.exists() {
+			return Ok(res);
+		}
+		for entry in fs::read_dir(&monitors_dir)? {
+			let path = entry?.path();
+			if path.is_dir() {
+				continue;
+			}
+			let filename = match path.file_name().and_then(|name| name.to_str()) {
+				Some(name) => name,
+				None => return Err(Error::new(ErrorKind::InvalidData, "Invalid ChannelMonitor file name")),
+			};
+			if filename.ends_with(util::TMP_FILE_SUFFIX) {
+				// Left over from a write which was interrupted before it could be renamed into place.
+				continue;
+			}
+			let funding_txo = parse_funding_txo(filename)?;
+
+			let contents = fs::read(&path)?;
+			let (last_block_hash, mut monitor) = match <(BlockHash, ChannelMonitor<ChanSigner>)>::read(&mut Cursor::new(&contents)) {
+				Ok(res) => res,
+				Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Failed to deserialize ChannelMonitor")),
+			};
+			if monitor.get_funding_txo().0 != funding_txo {
+				return Err(Error::new(ErrorKind::InvalidData, "ChannelMonitor was stored under the wrong funding outpoint"));
+			}
+
+			let updates_dir = self.updates_dir(&funding_txo);
+			if updates_dir.exists() {
+				let mut updates = list_update_files(&updates_dir)?;
+				updates.sort_unstable_by_key(|(update_id, _)| *update_id);
+				for (update_id, update_path) in updates {
+					if update_id <= monitor.get_latest_update_id() {
+						// Already included in the full monitor, but not yet pruned.
+						continue;
+					}
+					if update_id != monitor.get_latest_update_id() + 1 {
+						return Err(Error::new(ErrorKind::InvalidData, "Missing ChannelMonitorUpdate"));
+					}
+					let update_contents = fs::read(&update_path)?;
+					let update: ChannelMonitorUpdate = match Readable::read(&mut Cursor::new(&update_contents)) {
+						Ok(update) => update,
+						Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Failed to deserialize ChannelMonitorUpdate")),
+					};
+					if update.update_id != update_id {
+						return Err(Error::new(ErrorKind::InvalidData, "ChannelMonitorUpdate was stored under the wrong update_id"));
+					}
+					if monitor.update_monitor(update, broadcaster, logger).is_err() {
+						break;
+					}
+				}
+			}
+			res.insert(funding_txo, (last_block_hash, monitor));
+		}
+		Ok(res)
+	}
+}
+
+/// Parses a `<txid>_<index>` monitor file name into the funding outpoint it refers to.
+fn parse_funding_txo(filename: &str) -> Result<OutPoint, Error> {
+	let mut parts = filename.splitn(2, '_');
+	let txid = parts.next().and_then(|txid_hex| Txid::from_hex(txid_hex).ok());
+	let index = parts.next().and_then(|index| index.parse::<u16>().ok());
+	match (txid, index) {
+		(Some(txid), Some(index)) => Ok(OutPoint { txid, index }),
+		_ => Err(Error::new(ErrorKind::InvalidData, "Invalid ChannelMonitor file name")),
+	}
+}
+
+/// Lists the `(update_id, path)` of each stored update in the given updates directory.
+fn list_update_files(updates_dir: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+	let mut res = Vec::new();
+	for entry in fs::read_dir(updates_dir)? {
+		let path = entry?.path();
+		let filename = match path.file_name().and_then(|name| name.to_str()) {
+			Some(name) => name,
+			None => return Err(Error::new(ErrorKind::InvalidData, "Invalid ChannelMonitorUpdate file name")),
+		};
+		if filename.ends_with(util::TMP_FILE_SUFFIX) {
+			continue;
+		}
+		match filename.parse::<u64>() {
+			Ok(update_id) => res.push((update_id, path)),
+			Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Invalid ChannelMonitorUpdate file name")),
+		}
+	}
+	Ok(res)
+}
+
+/// A `ManyChannelMonitor` which wraps a `SimpleManyChannelMonitor`, durably writing each new
+/// `ChannelMonitor` and each `ChannelMonitorUpdate` to disk via a `FilesystemPersister` before
+/// passing it on and returning to the `ChannelManager`.
+///
+/// Updates are written incrementally, and every `UPDATES_PER_FULL_PERSIST` updates the channel's
+/// full `ChannelMonitor` is rewritten and the updates it includes are removed.
+///
+/// Note that `ChannelMonitor`s also change as blocks are connected and disconnected (eg to track
+/// claims of our outputs), which is not captured by `ChannelMonitorUpdate`s. You should call
+/// `persist_all_monitors` periodically (eg after processing each new block) to capture those
+/// changes.
+///
+/// If writing to disk fails, `ChannelMonitorUpdateErr::PermanentFailure` is returned, causing the
+/// affected channel to be closed using the in-memory copy of its `ChannelMonitor`.
+pub struct FilesystemChannelMonitor<ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+	      C::Target: ChainWatchInterface,
+{
+	simple_monitor: SimpleManyChannelMonitor<OutPoint, ChanSigner, T, F, L, C>,
+	persister: FilesystemPersister,
+}
+
+impl<ChanSigner: ChannelKeys + Writeable, T: Deref, F: Deref, L: Deref, C: Deref> FilesystemChannelMonitor<ChanSigner, T, F, L, C>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+	      C::Target: ChainWatchInterface,
+{
+	/// Creates a new FilesystemChannelMonitor which monitors channels using the given
+	/// `SimpleManyChannelMonitor` and persists them using the given `FilesystemPersister`.
+	pub fn new(simple_monitor: SimpleManyChannelMonitor<OutPoint, ChanSigner, T, F, L, C>, persister: FilesystemPersister) -> Self {
+		Self { simple_monitor, persister }
+	}
+
+	/// Gets the wrapped `SimpleManyChannelMonitor`.
+	pub fn simple_monitor(&self) -> &SimpleManyChannelMonitor<OutPoint, ChanSigner, T, F, L, C> {
+		&self.simple_monitor
+	}
+
+	/// Gets the `FilesystemPersister` used to write channel data.
+	pub fn persister(&self) -> &FilesystemPersister {
+		&self.persister
+	}
+
+	/// Starts monitoring a channel whose `ChannelMonitor` was read from disk (eg via
+	/// `FilesystemPersister::read_channelmonitors`) without rewriting it.
+	pub fn load_monitor(&self, funding_txo: OutPoint, monitor: ChannelMonitor<ChanSigner>) -> Result<(), MonitorUpdateError> {
+		self.simple_monitor.add_monitor_by_key(funding_txo, monitor)
+	}
+
+	/// Rewrites the full `ChannelMonitor` of every monitored channel, removing any stored
+	/// `ChannelMonitorUpdate`s which are no longer needed.
+	pub fn persist_all_monitors(&self) -> Result<(), Error> {
+		let monitors = self.simple_monitor.monitors.lock().unwrap();
+		for (funding_txo, monitor) in monitors.iter() {
+			self.persister.persist_monitor(funding_txo, monitor)?;
+		}
+		Ok(())
+	}
+}
+
+impl<ChanSigner: ChannelKeys + Writeable, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, C: Deref + Sync + Send> ManyChannelMonitor for FilesystemChannelMonitor<ChanSigner, T, F, L, C>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+	      C::Target: ChainWatchInterface,
+{
+	type Keys = ChanSigner;
+
+	fn add_monitor(&self, funding_txo: OutPoint, monitor: ChannelMonitor<ChanSigner>) -> Result<(), ChannelMonitorUpdateErr> {
+		if self.persister.persist_monitor(&funding_txo, &monitor).is_err() {
+			return Err(ChannelMonitorUpdateErr::PermanentFailure);
+		}
+		self.simple_monitor.add_monitor(funding_txo, monitor)
+	}
+
+	fn update_monitor(&self, funding_txo: OutPoint, update: ChannelMonitorUpdate) -> Result<(), ChannelMonitorUpdateErr> {
+		// The update is written even if applying it fails below, as state may have been stored in
+		// the monitor before the failure.
+		if self.persister.persist_monitor_update(&funding_txo, &update).is_err() {
+			return Err(ChannelMonitorUpdateErr::PermanentFailure);
+		}
+		let update_id = update.update_id;
+		self.simple_monitor.update_monitor(funding_txo, update)?;
+		if update_id % UPDATES_PER_FULL_PERSIST == 0 {
+			if let Some(monitor) = self.simple_monitor.monitors.lock().unwrap().get(&funding_txo) {
+				// The update itself is already durable, so failing to compact is not fatal: we'll
+				// simply try again after the next UPDATES_PER_FULL_PERSIST updates.
+				let _ = self.persister.persist_monitor(&funding_txo, monitor);
+			}
+		}
+		Ok(())
+	}
+
+	fn get_and_clear_pending_monitor_events(&self) -> Vec<MonitorEvent> {
+		self.simple_monitor.get_and_clear_pending_monitor_events()
+	}
+}
+
+impl<ChanSigner: ChannelKeys, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, C: Deref + Sync + Send> ChainListener for FilesystemChannelMonitor<ChanSigner, T, F, L, C>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+	      C::Target: ChainWatchInterface,
+{
+	fn block_connected(&self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], indexes_of_txn_matched: &[usize]) {
+		self.simple_monitor.block_connected(header, height, txn_matched, indexes_of_txn_matched)
+	}
+
+	fn block_disconnected(&self, header: &BlockHeader, disconnected_height: u32) {
+		self.simple_monitor.block_disconnected(header, disconnected_height)
+	}
+}
+
+impl<ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref> EventsProvider for FilesystemChannelMonitor<ChanSigner, T, F, L, C>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+	      C::Target: ChainWatchInterface,
+{
+	fn get_and_clear_pending_events(&self) -> Vec<Event> {
+		self.simple_monitor.get_and_clear_pending_events()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::constants::genesis_block;
+	use bitcoin::blockdata::transaction::{Transaction, TxOut};
+	use bitcoin::network::constants::Network;
+	use lightning::chain::chaininterface::{BroadcasterInterface, ChainWatchInterfaceUtil, ConfirmationTarget, FeeEstimator};
+	use lightning::chain::keysinterface::{InMemoryChannelKeys, KeysManager};
+	use lightning::chain::transaction::OutPoint;
+	use lightning::ln::channelmanager::{ChainParameters, ChannelManager};
+	use lightning::ln::channelmonitor::{ChannelMonitor, SimpleManyChannelMonitor};
+	use lightning::ln::features::InitFeatures;
+	use lightning::ln::msgs::ChannelMessageHandler;
+	use lightning::util::config::UserConfig;
+	use lightning::util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
+	use lightning::util::logger::{Logger, Record};
+	use lightning::util::ser::Writeable;
+	use std::fs;
+	use std::io::ErrorKind;
+	use std::path::{Path, PathBuf};
+	use std::sync::{Arc, Mutex};
+	use super::{FilesystemChannelMonitor, FilesystemPersister};
+
+	struct TestFeeEstimator;
+	impl FeeEstimator for TestFeeEstimator {
+		fn get_est_sat_per_1000_weight(&self, _: ConfirmationTarget) -> u32 { 253 }
+	}
+
+	struct TestBroadcaster { txn_broadcasted: Mutex<Vec<Transaction>> }
+	impl BroadcasterInterface for TestBroadcaster {
+		fn broadcast_transaction(&self, tx: &Transaction) {
+			self.txn_broadcasted.lock().unwrap().push(tx.clone());
+		}
+	}
+
+	struct TestLogger;
+	impl Logger for TestLogger {
+		fn log(&self, _record: &Record) {}
+	}
+
+	type ChainMonitor = FilesystemChannelMonitor<InMemoryChannelKeys, Arc<TestBroadcaster>, Arc<TestFeeEstimator>, Arc<TestLogger>, Arc<ChainWatchInterfaceUtil>>;
+
+	type Manager = ChannelManager<InMemoryChannelKeys, Arc<ChainMonitor>, Arc<TestBroadcaster>, Arc<KeysManager>, Arc<TestFeeEstimator>, Arc<TestLogger>>;
+
+	struct Node {
+		node: Manager,
+		monitor: Arc<ChainMonitor>,
+		tx_broadcaster: Arc<TestBroadcaster>,
+		logger: Arc<TestLogger>,
+	}
+
+	// Gets a fresh, empty data directory for the given test.
+	fn get_data_dir(test_name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("lightning-persister-{}-{}", test_name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		dir
+	}
+
+	fn create_nodes(data_dir: &Path) -> Vec<Node> {
+		let mut nodes = Vec::new();
+		for i in 0..2 {
+			let fee_estimator = Arc::new(TestFeeEstimator);
+			let tx_broadcaster = Arc::new(TestBroadcaster { txn_broadcasted: Mutex::new(Vec::new()) });
+			let logger = Arc::new(TestLogger);
+			let chain_watch = Arc::new(ChainWatchInterfaceUtil::new(Network::Testnet));
+			let simple_monitor = SimpleManyChannelMonitor::new(chain_watch, tx_broadcaster.clone(), logger.clone(), fee_estimator.clone());
+			let persister = FilesystemPersister::new(data_dir.join(format!("node{}", i)).to_str().unwrap().to_string());
+			let monitor = Arc::new(FilesystemChannelMonitor::new(simple_monitor, persister));
+			let keys_manager = Arc::new(KeysManager::new(&[i as u8; 32], Network::Testnet, 42, 42));
+			let params = ChainParameters { network: Network::Testnet, latest_hash: genesis_block(Network::Testnet).header.block_hash(), latest_height: 0 };
+			let node = ChannelManager::new(fee_estimator, monitor.clone(), tx_broadcaster.clone(), logger.clone(), keys_manager, UserConfig::default(), params);
+			nodes.push(Node { node, monitor, tx_broadcaster, logger });
+		}
+		nodes
+	}
+
+	// Opens a channel from nodes[0] to nodes[1] up to the exchange of funding_signed, at which
+	// point both nodes have handed a ChannelMonitor to their ManyChannelMonitor.
+	fn open_channel(nodes: &[Node]) -> OutPoint {
+		nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+		let open_chan = match nodes[0].node.get_and_clear_pending_msg_events().pop() {
+			Some(MessageSendEvent::SendOpenChannel { msg, .. }) => msg,
+			_ => panic!("Expected open_channel"),
+		};
+		nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_chan);
+		let accept_chan = match nodes[1].node.get_and_clear_pending_msg_events().pop() {
+			Some(MessageSendEvent::SendAcceptChannel { msg, .. }) => msg,
+			_ => panic!("Expected accept_channel"),
+		};
+		nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_chan);
+
+		let funding_txo = match nodes[0].node.get_and_clear_pending_events().pop() {
+			Some(Event::FundingGenerationReady { temporary_channel_id, channel_value_satoshis, output_script, .. }) => {
+				let tx = Transaction { version: 2, lock_time: 0, input: Vec::new(), output: vec![TxOut {
+					value: channel_value_satoshis, script_pubkey: output_script,
+				}]};
+				let funding_txo = OutPoint { txid: tx.txid(), index: 0 };
+				nodes[0].node.funding_transaction_generated(&temporary_channel_id, funding_txo);
+				funding_txo
+			},
+			_ => panic!("Expected FundingGenerationReady"),
+		};
+		let funding_created = match nodes[0].node.get_and_clear_pending_msg_events().pop() {
+			Some(MessageSendEvent::SendFundingCreated { msg, .. }) => msg,
+			_ => panic!("Expected funding_created"),
+		};
+		nodes[1].node.handle_funding_created(&nodes[0].node.get_our_node_id(), &funding_created);
+		let funding_signed = match nodes[1].node.get_and_clear_pending_msg_events().pop() {
+			Some(MessageSendEvent::SendFundingSigned { msg, .. }) => msg,
+			_ => panic!("Expected funding_signed"),
+		};
+		nodes[0].node.handle_funding_signed(&nodes[1].node.get_our_node_id(), &funding_signed);
+		nodes[0].node.get_and_clear_pending_events();
+		funding_txo
+	}
+
+	fn monitor_bytes(monitor: &ChannelMonitor<InMemoryChannelKeys>) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		monitor.write_for_disk(&mut bytes).unwrap();
+		bytes
+	}
+
+	// Checks that reading the given node's monitors from disk results in exactly its in-memory
+	// monitors.
+	fn check_persisted_monitors(node: &Node) {
+		let read_monitors = node.monitor.persister().read_channelmonitors::<InMemoryChannelKeys, _, _>(&node.tx_broadcaster, &node.logger).unwrap();
+		let monitors = node.monitor.simple_monitor().monitors.lock().unwrap();
+		assert_eq!(read_monitors.len(), monitors.len());
+		for (funding_txo, monitor) in monitors.iter() {
+			let (_, read_monitor) = read_monitors.get(funding_txo).unwrap();
+			assert_eq!(monitor_bytes(read_monitor), monitor_bytes(monitor));
+		}
+	}
+
+	#[test]
+	fn test_persist_and_reload_monitors() {
+		let data_dir = get_data_dir("reload");
+		let nodes = create_nodes(&data_dir);
+		let funding_txo = open_channel(&nodes);
+		check_persisted_monitors(&nodes[0]);
+		check_persisted_monitors(&nodes[1]);
+
+		// Force-closing generates a ChannelMonitorUpdate, which is stored incrementally and
+		// replayed on reload, rebroadcasting our commitment transaction.
+		nodes[0].node.force_close_channel(&funding_txo.to_channel_id());
+		let updates_dir = data_dir.join("node0").join("monitors").join(format!("{}_{}.updates", funding_txo.txid, funding_txo.index));
+		assert_eq!(fs::read_dir(&updates_dir).unwrap().count(), 1);
+		let broadcast_count = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().len();
+		assert!(broadcast_count > 0);
+		check_persisted_monitors(&nodes[0]);
+		assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().len() > broadcast_count);
+
+		// Rewriting the full monitors removes the stored updates.
+		nodes[0].monitor.persist_all_monitors().unwrap();
+		assert_eq!(fs::read_dir(&updates_dir).unwrap().count(), 0);
+		check_persisted_monitors(&nodes[0]);
+
+		// Once reloaded, monitors can be handed to a fresh FilesystemChannelMonitor.
+		let new_nodes = create_nodes(&data_dir);
+		for (funding_txo, (_, monitor)) in nodes[0].monitor.persister().read_channelmonitors(&nodes[0].tx_broadcaster, &nodes[0].logger).unwrap() {
+			new_nodes[0].monitor.load_monitor(funding_txo, monitor).unwrap();
+		}
+		check_persisted_monitors(&new_nodes[0]);
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn test_missing_update() {
+		// If an intermediate update goes missing, reading should fail rather than silently
+		// returning an outdated monitor.
+		let data_dir = get_data_dir("missing_update");
+		let nodes = create_nodes(&data_dir);
+		let funding_txo = open_channel(&nodes);
+		nodes[0].node.force_close_channel(&funding_txo.to_channel_id());
+		let updates_dir = data_dir.join("node0").join("monitors").join(format!("{}_{}.updates", funding_txo.txid, funding_txo.index));
+		let update_path = fs::read_dir(&updates_dir).unwrap().next().unwrap().unwrap().path();
+		fs::rename(&update_path, updates_dir.join("100")).unwrap();
+		match nodes[0].monitor.persister().read_channelmonitors::<InMemoryChannelKeys, _, _>(&nodes[0].tx_broadcaster, &nodes[0].logger) {
+			Err(e) => assert_eq!(e.kind(), ErrorKind::InvalidData),
+			Ok(_) => panic!("Expected an error reading monitors with a missing update"),
+		}
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn test_invalid_monitor_files() {
+		let data_dir = get_data_dir("invalid_files");
+		let persister = FilesystemPersister::new(data_dir.to_str().unwrap().to_string());
+		let broadcaster = Arc::new(TestBroadcaster { txn_broadcasted: Mutex::new(Vec::new()) });
+		let logger = Arc::new(TestLogger);
+
+		// An empty (or missing) data directory simply contains no monitors.
+		assert!(persister.read_channelmonitors::<InMemoryChannelKeys, _, _>(&broadcaster, &logger).unwrap().is_empty());
+
+		// Temporary files left over from interrupted writes are ignored.
+		let monitors_dir = data_dir.join("monitors");
+		fs::create_dir_all(&monitors_dir).unwrap();
+		let monitor_name = format!("{}_0", OutPoint { txid: Default::default(), index: 0 }.txid);
+		fs::write(monitors_dir.join(format!("{}.tmp", monitor_name)), [42; 32]).unwrap();
+		assert!(persister.read_channelmonitors::<InMemoryChannelKeys, _, _>(&broadcaster, &logger).unwrap().is_empty());
+
+		// But corrupted monitors and unknown files are not.
+		fs::write(monitors_dir.join(&monitor_name), [42; 32]).unwrap();
+		match persister.read_channelmonitors::<InMemoryChannelKeys, _, _>(&broadcaster, &logger) {
+			Err(e) => assert_eq!(e.kind(), ErrorKind::InvalidData),
+			Ok(_) => panic!("Expected an error reading a corrupted monitor"),
+		}
+		fs::remove_file(monitors_dir.join(&monitor_name)).unwrap();
+		fs::write(monitors_dir.join("not_a_monitor"), [42; 32]).unwrap();
+		match persister.read_channelmonitors::<InMemoryChannelKeys, _, _>(&broadcaster, &logger) {
+			Err(e) => assert_eq!(e.kind(), ErrorKind::InvalidData),
+			Ok(_) => panic!("Expected an error reading an unknown file"),
+		}
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn test_persist_manager() {
+		let data_dir = get_data_dir("manager");
+		let nodes = create_nodes(&data_dir);
+		open_channel(&nodes);
+		let persister = FilesystemPersister::new(data_dir.to_str().unwrap().to_string());
+		persister.persist_manager(&nodes[0].node).unwrap();
+		assert_eq!(fs::read(data_dir.join("manager")).unwrap(), nodes[0].node.encode());
+		// No temporary file is left behind.
+		assert_eq!(fs::read_dir(&data_dir).unwrap().filter(|entry| entry.as_ref().unwrap().path().is_file()).count(), 1);
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
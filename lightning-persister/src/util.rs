@@ -0,0 +1,59 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Helpers for atomically writing files to disk.
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The suffix given to files while they are being written, before being renamed into place.
+pub(crate) const TMP_FILE_SUFFIX: &str = ".tmp";
+
+fn tmp_path(path: &Path) -> PathBuf {
+	let mut tmp_name = path.file_name().expect("paths we write to always have a file name").to_os_string();
+	tmp_name.push(TMP_FILE_SUFFIX);
+	path.with_file_name(tmp_name)
+}
+
+/// Fsyncs the given directory so that any renames of entries within it are durable.
+pub(crate) fn sync_dir(dir: &Path) -> std::io::Result<()> {
+	// Directories cannot be opened (let alone fsync'd) this way on Windows, where renames are
+	// durable once MoveFileEx returns.
+	#[cfg(not(target_os = "windows"))]
+	fs::File::open(dir)?.sync_all()?;
+	#[cfg(target_os = "windows")]
+	let _ = dir;
+	Ok(())
+}
+
+/// Writes the data produced by `write_data` to `path` such that, even across crashes, `path`
+/// always contains either its previous contents or the complete new contents.
+///
+/// The data is first written to a temporary file in the same directory, which is fsync'd and then
+/// renamed over `path`, after which the directory itself is fsync'd.
+pub(crate) fn write_to_file<F>(path: &Path, write_data: F) -> std::io::Result<()>
+	where F: FnOnce(&mut BufWriter<&mut fs::File>) -> std::io::Result<()>
+{
+	let dir = path.parent().expect("paths we write to always have a parent directory");
+	fs::create_dir_all(dir)?;
+
+	let tmp_path = tmp_path(path);
+	{
+		let mut f = fs::File::create(&tmp_path)?;
+		{
+			let mut writer = BufWriter::new(&mut f);
+			write_data(&mut writer)?;
+			writer.flush()?;
+		}
+		f.sync_all()?;
+	}
+	fs::rename(&tmp_path, path)?;
+	sync_dir(dir)
+}
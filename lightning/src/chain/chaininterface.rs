@@ -11,7 +11,8 @@
 //! blockchain.
 //!
 //! Includes traits for monitoring and receiving notifications of new blocks and block
-//! disconnections, transaction broadcasting, and feerate information requests.
+//! disconnections (or of individual transaction confirmations), transaction broadcasting, and
+//! feerate information requests.
 
 use bitcoin::blockdata::block::{Block, BlockHeader};
 use bitcoin::blockdata::transaction::Transaction;
@@ -101,6 +102,38 @@ pub trait ChainListener: Sync + Send {
 	fn block_disconnected(&self, header: &BlockHeader, disconnected_height: u32);
 }
 
+/// A trait indicating a desire to listen for transaction confirmations, for use with chain sources
+/// which are queried for the transactions of interest (eg Electrum servers) rather than providing
+/// full blocks.
+///
+/// Unlike ChainListener, which is notified of every block in order, a ConfirmationListener is told
+/// which transactions were confirmed in which blocks, which transactions are no longer confirmed,
+/// and what the best block is, each of which may skip over many blocks. For each update of the
+/// chain source, calls should be made in the following order:
+///  * transaction_unconfirmed for any txids returned by get_relevant_txids which are no longer
+///    confirmed (eg due to a reorg),
+///  * transactions_confirmed for any newly-confirmed transactions of interest, in block order,
+///  * best_block_updated with the new best block.
+pub trait ConfirmationListener: Sync + Send {
+	/// Notifies a listener that the given transactions were confirmed in the block with the given
+	/// header and height, each along with its index in the block.
+	///
+	/// Transactions need only include those which match watched transactions/outpoints, as with
+	/// ChainListener::block_connected. If a new transaction/outpoint is watched during this call,
+	/// any transactions in the same block matching it must be passed in a further call, with the
+	/// same header. Transactions may be passed again without harm.
+	fn transactions_confirmed(&self, header: &BlockHeader, height: u32, txdata: &[(usize, &Transaction)]);
+	/// Notifies a listener that the transaction with the given txid, previously passed to
+	/// transactions_confirmed, is no longer confirmed.
+	fn transaction_unconfirmed(&self, txid: &Txid);
+	/// Notifies a listener of a new best block, with its height. Blocks between the previous best
+	/// block and this one need not have been passed to the listener.
+	fn best_block_updated(&self, header: &BlockHeader, height: u32);
+	/// Gets the txids of confirmed transactions whose unconfirmation the listener must be told of,
+	/// via transaction_unconfirmed.
+	fn get_relevant_txids(&self) -> Vec<Txid>;
+}
+
 /// An enum that represents the speed at which we want a transaction to confirm used for feerate
 /// estimation.
 pub enum ConfirmationTarget {
@@ -11,12 +11,13 @@
 //! spendable on-chain outputs which the user owns and is responsible for using just as any other
 //! on-chain output which is theirs.
 
-use bitcoin::blockdata::transaction::{Transaction, TxOut, SigHashType};
+use bitcoin::blockdata::transaction::{Transaction, TxIn, TxOut, SigHashType};
 use bitcoin::blockdata::script::{Script, Builder};
 use bitcoin::blockdata::opcodes;
 use bitcoin::network::constants::Network;
-use bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey, ChildNumber};
+use bitcoin::util::bip32::{ExtendedPrivKey, ChildNumber};
 use bitcoin::util::bip143;
+use bitcoin::util::address::Address;
 
 use bitcoin::hashes::{Hash, HashEngine};
 use bitcoin::hashes::sha256::HashEngine as Sha256State;
@@ -37,6 +38,7 @@ use ln::chan_utils::{HTLCOutputInCommitment, make_funding_redeemscript, ChannelP
 use ln::msgs::UnsignedChannelAnnouncement;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::HashSet;
 use std::io::Error;
 use ln::msgs::DecodeError;
 
@@ -86,8 +88,9 @@ pub enum SpendableOutputDescriptor {
 	/// regenerated by passing the revocation_pubkey (derived as above), our delayed_payment pubkey
 	/// (derived as above), and the to_self_delay contained here to
 	/// chan_utils::get_revokeable_redeemscript.
-	//
-	// TODO: we need to expose utility methods in KeyManager to do all the relevant derivation.
+	///
+	/// If the output was generated by a KeysManager, KeysManager::spend_spendable_outputs will do
+	/// all of the above for you.
 	DynamicOutputP2WSH {
 		/// The outpoint which is spendable
 		outpoint: OutPoint,
@@ -109,6 +112,10 @@ pub enum SpendableOutputDescriptor {
 	/// The witness in the spending input, is, thus, simply:
 	/// <BIP 143 signature> <payment key>
 	///
+	/// For channels using anchor outputs, the output is instead a P2WSH to
+	/// chan_utils::get_to_countersignatory_with_anchors_redeemscript(payment key), and the
+	/// witness is <BIP 143 signature> <witnessScript> with an nSequence of 1 on the input.
+	///
 	/// These are generally the result of our counterparty having broadcast the current state,
 	/// allowing us to claim the non-HTLC-encumbered outputs immediately.
 	StaticOutputCounterpartyPayment {
@@ -122,6 +129,17 @@ pub enum SpendableOutputDescriptor {
 	}
 }
 
+impl SpendableOutputDescriptor {
+	/// The outpoint which is spendable, regardless of the descriptor type.
+	pub fn outpoint(&self) -> &OutPoint {
+		match self {
+			&SpendableOutputDescriptor::StaticOutput { ref outpoint, .. } => outpoint,
+			&SpendableOutputDescriptor::DynamicOutputP2WSH { ref outpoint, .. } => outpoint,
+			&SpendableOutputDescriptor::StaticOutputCounterpartyPayment { ref outpoint, .. } => outpoint,
+		}
+	}
+}
+
 impl Writeable for SpendableOutputDescriptor {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
 		match self {
@@ -319,13 +337,44 @@ pub trait ChannelKeys : Send+Clone {
 	/// protocol.
 	fn sign_channel_announcement<T: secp256k1::Signing>(&self, msg: &UnsignedChannelAnnouncement, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
 
+	/// Create a signature for the input spending our anchor output on a holder commitment
+	/// transaction, in order to bump the commitment transaction's fee via child-pays-for-parent.
+	///
+	/// The anchor output is spent with the witness script returned by
+	/// chan_utils::get_anchor_redeemscript for our funding pubkey, and the signature should commit
+	/// to the full transaction (SIGHASH_ALL), as the rest of the transaction is provided by the
+	/// user's on-chain wallet.
+	fn sign_holder_anchor_input<T: secp256k1::Signing>(&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+
+	/// Create a signature for the input spending the current funding output in a splice
+	/// transaction, ie a transaction which spends the channel's funding output into a new funding
+	/// output of a different value.
+	///
+	/// The signature should commit to the full transaction (SIGHASH_ALL) using the funding
+	/// redeemscript and the current channel value, as set at construction or by the last call to
+	/// on_splice.
+	fn sign_splice_shared_input<T: secp256k1::Signing>(&self, splice_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+
 	/// Set the counterparty channel basepoints and counterparty_selected/holder_selected_contest_delay.
 	/// This is done immediately on incoming channels and as soon as the channel is accepted on outgoing channels.
 	///
-	/// We bind holder_selected_contest_delay late here for API convenience.
+	/// We bind holder_selected_contest_delay late here for API convenience. opt_anchors indicates
+	/// whether the channel uses anchor outputs, which changes the format of the commitment and
+	/// HTLC transactions being signed.
 	///
 	/// Will be called before any signatures are applied.
-	fn on_accept(&mut self, channel_points: &ChannelPublicKeys, counterparty_selected_contest_delay: u16, holder_selected_contest_delay: u16);
+	fn on_accept(&mut self, channel_points: &ChannelPublicKeys, counterparty_selected_contest_delay: u16, holder_selected_contest_delay: u16, opt_anchors: bool);
+
+	/// Set the channel value once a splice transaction has been locked in by both parties. All
+	/// commitment and closing transactions signed afterwards spend the splice transaction's funding
+	/// output of the given value.
+	///
+	/// Note that a copy of a ChannelKeys may be updated this way before the splice is locked in in
+	/// order to sign commitment transactions spending the new funding output.
+	///
+	/// This is also called on dual-funded channels, before anything is signed, once both parties'
+	/// contributions (and thus the total channel value) are known.
+	fn on_splice(&mut self, channel_value_satoshis: u64);
 }
 
 /// A trait to describe an object which can get user secrets and key material.
@@ -365,6 +414,8 @@ struct AcceptedChannelData {
 	/// by our counterparty, ie the amount of time that they have to wait to recover their funds
 	/// if they broadcast a transaction.
 	holder_selected_contest_delay: u16,
+	/// Whether the channel uses anchor outputs.
+	opt_anchors: bool,
 }
 
 #[derive(Clone)]
@@ -454,6 +505,10 @@ impl InMemoryChannelKeys {
 	/// if they broadcast a transaction.
 	/// Will panic if on_accept wasn't called.
 	pub fn holder_selected_contest_delay(&self) -> u16 { self.accepted_channel_data.as_ref().unwrap().holder_selected_contest_delay }
+
+	/// Whether the channel uses anchor outputs.
+	/// Will panic if on_accept wasn't called.
+	pub fn opt_anchors(&self) -> bool { self.accepted_channel_data.as_ref().unwrap().opt_anchors }
 }
 
 impl ChannelKeys for InMemoryChannelKeys {
@@ -485,9 +540,10 @@ impl ChannelKeys for InMemoryChannelKeys {
 		let mut htlc_sigs = Vec::with_capacity(htlcs.len());
 		for ref htlc in htlcs {
 			if let Some(_) = htlc.transaction_output_index {
-				let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, feerate_per_kw, accepted_data.holder_selected_contest_delay, htlc, &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
-				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys);
-				let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, SigHashType::All)[..]);
+				let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, feerate_per_kw, accepted_data.holder_selected_contest_delay, htlc, accepted_data.opt_anchors, &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
+				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, accepted_data.opt_anchors, &keys);
+				let htlc_sighashtype = chan_utils::htlc_counterparty_sighash_type(accepted_data.opt_anchors);
+				let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, htlc_sighashtype)[..]);
 				let our_htlc_key = match chan_utils::derive_private_key(&secp_ctx, &keys.per_commitment_point, &self.htlc_base_key) {
 					Ok(s) => s,
 					Err(_) => return Err(()),
@@ -540,7 +596,7 @@ impl ChannelKeys for InMemoryChannelKeys {
 				Ok(holder_htlcpubkey) => holder_htlcpubkey,
 				Err(_) => return Err(())
 			};
-			chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &counterparty_htlcpubkey, &holder_htlcpubkey, &revocation_pubkey)
+			chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, self.opt_anchors(), &counterparty_htlcpubkey, &holder_htlcpubkey, &revocation_pubkey)
 		} else {
 			let counterparty_delayedpubkey = match chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.counterparty_pubkeys().delayed_payment_basepoint) {
 				Ok(counterparty_delayedpubkey) => counterparty_delayedpubkey,
@@ -558,7 +614,7 @@ impl ChannelKeys for InMemoryChannelKeys {
 			let witness_script = if let Ok(revocation_pubkey) = chan_utils::derive_public_revocation_key(&secp_ctx, &per_commitment_point, &self.pubkeys().revocation_basepoint) {
 				if let Ok(counterparty_htlcpubkey) = chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.counterparty_pubkeys().htlc_basepoint) {
 					if let Ok(htlcpubkey) = chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.pubkeys().htlc_basepoint) {
-						chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &counterparty_htlcpubkey, &htlcpubkey, &revocation_pubkey)
+						chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, self.opt_anchors(), &counterparty_htlcpubkey, &htlcpubkey, &revocation_pubkey)
 					} else { return Err(()) }
 				} else { return Err(()) }
 			} else { return Err(()) };
@@ -588,18 +644,72 @@ impl ChannelKeys for InMemoryChannelKeys {
 		Ok(secp_ctx.sign(&msghash, &self.funding_key))
 	}
 
-	fn on_accept(&mut self, channel_pubkeys: &ChannelPublicKeys, counterparty_selected_contest_delay: u16, holder_selected_contest_delay: u16) {
+	fn sign_holder_anchor_input<T: secp256k1::Signing>(&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		if input >= anchor_tx.input.len() { return Err(()); }
+		let anchor_redeemscript = chan_utils::get_anchor_redeemscript(&self.pubkeys().funding_pubkey);
+		let sighash = hash_to_message!(&bip143::SigHashCache::new(anchor_tx)
+			.signature_hash(input, &anchor_redeemscript, chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI, SigHashType::All)[..]);
+		Ok(secp_ctx.sign(&sighash, &self.funding_key))
+	}
+
+	fn sign_splice_shared_input<T: secp256k1::Signing>(&self, splice_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		if input >= splice_tx.input.len() { return Err(()); }
+		if splice_tx.input[input].witness.len() != 0 { return Err(()); }
+
+		let funding_pubkey = PublicKey::from_secret_key(secp_ctx, &self.funding_key);
+		let counterparty_channel_data = self.accepted_channel_data.as_ref().expect("must accept before signing");
+		let channel_funding_redeemscript = make_funding_redeemscript(&funding_pubkey, &counterparty_channel_data.counterparty_channel_pubkeys.funding_pubkey);
+
+		let sighash = hash_to_message!(&bip143::SigHashCache::new(splice_tx)
+			.signature_hash(input, &channel_funding_redeemscript, self.channel_value_satoshis, SigHashType::All)[..]);
+		Ok(secp_ctx.sign(&sighash, &self.funding_key))
+	}
+
+	fn on_accept(&mut self, channel_pubkeys: &ChannelPublicKeys, counterparty_selected_contest_delay: u16, holder_selected_contest_delay: u16, opt_anchors: bool) {
 		assert!(self.accepted_channel_data.is_none(), "Already accepted");
 		self.accepted_channel_data = Some(AcceptedChannelData {
 			counterparty_channel_pubkeys: channel_pubkeys.clone(),
 			counterparty_selected_contest_delay,
 			holder_selected_contest_delay,
+			opt_anchors,
 		});
 	}
+
+	fn on_splice(&mut self, channel_value_satoshis: u64) {
+		self.channel_value_satoshis = channel_value_satoshis;
+	}
 }
 
-impl_writeable!(AcceptedChannelData, 0,
- { counterparty_channel_pubkeys, counterparty_selected_contest_delay, holder_selected_contest_delay });
+impl Writeable for AcceptedChannelData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		self.counterparty_channel_pubkeys.write(writer)?;
+		self.counterparty_selected_contest_delay.write(writer)?;
+		self.holder_selected_contest_delay.write(writer)?;
+		self.opt_anchors.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for AcceptedChannelData {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		let counterparty_channel_pubkeys = Readable::read(reader)?;
+		let counterparty_selected_contest_delay = Readable::read(reader)?;
+		let holder_selected_contest_delay = Readable::read(reader)?;
+		// AcceptedChannelData is always written length-prefixed (as an Option), so data written
+		// before opt_anchors was added simply ends here.
+		let opt_anchors = match <bool as Readable>::read(reader) {
+			Ok(opt_anchors) => opt_anchors,
+			Err(DecodeError::ShortRead) => false,
+			Err(e) => return Err(e),
+		};
+		Ok(AcceptedChannelData {
+			counterparty_channel_pubkeys,
+			counterparty_selected_contest_delay,
+			holder_selected_contest_delay,
+			opt_anchors,
+		})
+	}
+}
 
 impl Writeable for InMemoryChannelKeys {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
@@ -661,7 +771,9 @@ impl Readable for InMemoryChannelKeys {
 pub struct KeysManager {
 	secp_ctx: Secp256k1<secp256k1::SignOnly>,
 	node_secret: SecretKey,
+	destination_key: SecretKey,
 	destination_script: Script,
+	shutdown_key: SecretKey,
 	shutdown_pubkey: PublicKey,
 	channel_master_key: ExtendedPrivKey,
 	channel_child_index: AtomicUsize,
@@ -698,26 +810,19 @@ impl KeysManager {
 		match ExtendedPrivKey::new_master(network.clone(), seed) {
 			Ok(master_key) => {
 				let node_secret = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(0).unwrap()).expect("Your RNG is busted").private_key.key;
-				let destination_script = match master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(1).unwrap()) {
-					Ok(destination_key) => {
-						let wpubkey_hash = WPubkeyHash::hash(&ExtendedPubKey::from_private(&secp_ctx, &destination_key).public_key.to_bytes());
-						Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0)
-						              .push_slice(&wpubkey_hash.into_inner())
-						              .into_script()
-					},
-					Err(_) => panic!("Your RNG is busted"),
-				};
-				let shutdown_pubkey = match master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(2).unwrap()) {
-					Ok(shutdown_key) => ExtendedPubKey::from_private(&secp_ctx, &shutdown_key).public_key.key,
-					Err(_) => panic!("Your RNG is busted"),
-				};
+				let destination_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(1).unwrap()).expect("Your RNG is busted").private_key.key;
+				let destination_script = Self::p2wpkh_script(&PublicKey::from_secret_key(&secp_ctx, &destination_key));
+				let shutdown_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(2).unwrap()).expect("Your RNG is busted").private_key.key;
+				let shutdown_pubkey = PublicKey::from_secret_key(&secp_ctx, &shutdown_key);
 				let channel_master_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(3).unwrap()).expect("Your RNG is busted");
 				let rand_bytes_master_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(4).unwrap()).expect("Your RNG is busted");
 
 				KeysManager {
 					secp_ctx,
 					node_secret,
+					destination_key,
 					destination_script,
+					shutdown_key,
 					shutdown_pubkey,
 					channel_master_key,
 					channel_child_index: AtomicUsize::new(0),
@@ -732,6 +837,12 @@ impl KeysManager {
 			Err(_) => panic!("Your rng is busted"),
 		}
 	}
+	fn p2wpkh_script(pubkey: &PublicKey) -> Script {
+		let wpubkey_hash = WPubkeyHash::hash(&pubkey.serialize());
+		Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0)
+		              .push_slice(&wpubkey_hash.into_inner())
+		              .into_script()
+	}
 	fn derive_unique_start(&self) -> Sha256State {
 		let mut unique_start = Sha256::engine();
 		unique_start.input(&byte_utils::be64_to_array(self.starting_time_secs));
@@ -792,6 +903,112 @@ impl KeysManager {
 			(params_1, params_2),
 		)
 	}
+
+	/// Creates a Transaction which spends the given descriptors to destination_script, paying a
+	/// fee of feerate_sat_per_1000_weight, and signs it with keys derived from this KeysManager.
+	///
+	/// All descriptors must have been generated by a ChannelMonitor whose keys came from this
+	/// KeysManager (ie via get_channel_keys, get_destination_script or get_shutdown_pubkey), and
+	/// any DynamicOutputP2WSH must be to_self_delay blocks deep before the returned transaction
+	/// can be broadcast.
+	///
+	/// The fee is computed on the worst-case weight of the signed transaction, so the actual
+	/// feerate may be marginally higher than requested.
+	///
+	/// Returns Err(()) if a descriptor does not belong to us or if the inputs do not cover the
+	/// fee plus a non-dust output.
+	pub fn spend_spendable_outputs(&self, descriptors: &[SpendableOutputDescriptor], destination_script: Script, feerate_sat_per_1000_weight: u32) -> Result<Transaction, ()> {
+		// For each input we store the signing key, the BIP 143 scriptCode, the value spent and the
+		// witness elements which follow the signature.
+		let mut signing_data = Vec::with_capacity(descriptors.len());
+		let mut input = Vec::with_capacity(descriptors.len());
+		let mut input_value: u64 = 0;
+		let mut seen_outpoints = HashSet::with_capacity(descriptors.len());
+		for outp in descriptors {
+			// The same output may be reported more than once, but may only be spent once.
+			if !seen_outpoints.insert(outp.outpoint()) { continue; }
+			let (outpoint, sequence) = match outp {
+				&SpendableOutputDescriptor::StaticOutput { ref outpoint, ref output } => {
+					let key = if output.script_pubkey == self.destination_script {
+						self.destination_key
+					} else if output.script_pubkey == Self::p2wpkh_script(&self.shutdown_pubkey) {
+						self.shutdown_key
+					} else { return Err(()); };
+					let pubkey = ::bitcoin::PublicKey { compressed: true, key: PublicKey::from_secret_key(&self.secp_ctx, &key) };
+					signing_data.push((key, Address::p2pkh(&pubkey, Network::Testnet).script_pubkey(), output.value, vec![pubkey.key.serialize().to_vec()]));
+					(outpoint, 0)
+				},
+				&SpendableOutputDescriptor::DynamicOutputP2WSH { ref outpoint, ref per_commitment_point, ref to_self_delay, ref output, ref key_derivation_params, ref revocation_pubkey } => {
+					let keys = self.derive_channel_keys(output.value, key_derivation_params.0, key_derivation_params.1);
+					let delayed_payment_key = chan_utils::derive_private_key(&self.secp_ctx, per_commitment_point, &keys.delayed_payment_base_key).map_err(|_| ())?;
+					let delayed_payment_pubkey = PublicKey::from_secret_key(&self.secp_ctx, &delayed_payment_key);
+					let witness_script = chan_utils::get_revokeable_redeemscript(revocation_pubkey, *to_self_delay, &delayed_payment_pubkey);
+					if output.script_pubkey != witness_script.to_v0_p2wsh() { return Err(()); }
+					signing_data.push((delayed_payment_key, witness_script.clone(), output.value, vec![vec!(), witness_script.into_bytes()]));
+					(outpoint, *to_self_delay as u32)
+				},
+				&SpendableOutputDescriptor::StaticOutputCounterpartyPayment { ref outpoint, ref output, ref key_derivation_params } => {
+					let keys = self.derive_channel_keys(output.value, key_derivation_params.0, key_derivation_params.1);
+					let payment_point = PublicKey::from_secret_key(&self.secp_ctx, &keys.payment_key);
+					let anchors_script = chan_utils::get_to_countersignatory_with_anchors_redeemscript(&payment_point);
+					if output.script_pubkey == Self::p2wpkh_script(&payment_point) {
+						let pubkey = ::bitcoin::PublicKey { compressed: true, key: payment_point };
+						signing_data.push((keys.payment_key, Address::p2pkh(&pubkey, Network::Testnet).script_pubkey(), output.value, vec![payment_point.serialize().to_vec()]));
+						(outpoint, 0)
+					} else if output.script_pubkey == anchors_script.to_v0_p2wsh() {
+						signing_data.push((keys.payment_key, anchors_script.clone(), output.value, vec![anchors_script.into_bytes()]));
+						(outpoint, 1)
+					} else { return Err(()); }
+				},
+			};
+			input_value = input_value.checked_add(signing_data.last().unwrap().2).ok_or(())?;
+			input.push(TxIn {
+				previous_output: outpoint.into_bitcoin_outpoint(),
+				script_sig: Script::new(),
+				sequence,
+				witness: Vec::new(),
+			});
+		}
+
+		let mut spend_tx = Transaction {
+			version: 2,
+			lock_time: 0,
+			input,
+			output: vec![TxOut {
+				script_pubkey: destination_script,
+				value: 0,
+			}],
+		};
+		// Without witnesses get_weight() only counts the non-witness data, so we add the segwit
+		// flags and, per input: number_of_witness_elements + sig_length + sig + remaining elements
+		let mut weight = spend_tx.get_weight() + 2;
+		for &(_, _, _, ref witness_tail) in signing_data.iter() {
+			weight += 1 + 1 + 73;
+			for elem in witness_tail {
+				weight += if elem.len() < 253 { 1 } else { 3 } + elem.len();
+			}
+		}
+		let fee = weight as u64 * feerate_sat_per_1000_weight as u64 / 1000;
+		if input_value < fee + 546 { return Err(()); }
+		spend_tx.output[0].value = input_value - fee;
+
+		let mut witnesses = Vec::with_capacity(signing_data.len());
+		{
+			let mut sighash_cache = bip143::SigHashCache::new(&spend_tx);
+			for (idx, (key, script_code, value, witness_tail)) in signing_data.drain(..).enumerate() {
+				let sighash = hash_to_message!(&sighash_cache.signature_hash(idx, &script_code, value, SigHashType::All)[..]);
+				let mut sig = self.secp_ctx.sign(&sighash, &key).serialize_der().to_vec();
+				sig.push(SigHashType::All as u8);
+				let mut witness = vec![sig];
+				witness.extend(witness_tail);
+				witnesses.push(witness);
+			}
+		}
+		for (txin, witness) in spend_tx.input.iter_mut().zip(witnesses.drain(..)) {
+			txin.witness = witness;
+		}
+		Ok(spend_tx)
+	}
 }
 
 impl KeysInterface for KeysManager {
@@ -826,3 +1043,17 @@ impl KeysInterface for KeysManager {
 		Sha256::from_engine(sha).into_inner()
 	}
 }
+
+/// A trait to describe an object which can build and sign transactions spending the outputs
+/// described by SpendableOutputDescriptors, eg for use by an OutputSweeper.
+pub trait OutputSpender: Send + Sync {
+	/// Creates a signed transaction spending all of the given descriptors to destination_script,
+	/// at the given feerate. See KeysManager::spend_spendable_outputs.
+	fn spend_spendable_outputs(&self, descriptors: &[SpendableOutputDescriptor], destination_script: Script, feerate_sat_per_1000_weight: u32) -> Result<Transaction, ()>;
+}
+
+impl OutputSpender for KeysManager {
+	fn spend_spendable_outputs(&self, descriptors: &[SpendableOutputDescriptor], destination_script: Script, feerate_sat_per_1000_weight: u32) -> Result<Transaction, ()> {
+		KeysManager::spend_spendable_outputs(self, descriptors, destination_script, feerate_sat_per_1000_weight)
+	}
+}
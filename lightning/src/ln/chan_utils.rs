@@ -21,7 +21,7 @@ use bitcoin::util::bip143;
 use bitcoin::hashes::{Hash, HashEngine};
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::ripemd160::Hash as Ripemd160;
-use bitcoin::hash_types::{Txid, PubkeyHash};
+use bitcoin::hash_types::{Txid, PubkeyHash, WPubkeyHash};
 
 use ln::channelmanager::{PaymentHash, PaymentPreimage};
 use ln::msgs::DecodeError;
@@ -39,6 +39,24 @@ const MAX_ALLOC_SIZE: usize = 64*1024;
 
 pub(super) const HTLC_SUCCESS_TX_WEIGHT: u64 = 703;
 pub(super) const HTLC_TIMEOUT_TX_WEIGHT: u64 = 663;
+// HTLC transactions of channels using anchor outputs spend the longer witness scripts which
+// require a 1-block CSV, and commit to a nSequence of 1.
+pub(super) const HTLC_SUCCESS_ANCHOR_TX_WEIGHT: u64 = 706;
+pub(super) const HTLC_TIMEOUT_ANCHOR_TX_WEIGHT: u64 = 666;
+
+/// The value, in satoshis, of each of the two anchor outputs on commitment transactions of
+/// channels using option_anchor_outputs.
+pub const ANCHOR_OUTPUT_VALUE_SATOSHI: u64 = 330;
+
+#[inline]
+pub(crate) fn htlc_success_tx_weight(opt_anchors: bool) -> u64 {
+	if opt_anchors { HTLC_SUCCESS_ANCHOR_TX_WEIGHT } else { HTLC_SUCCESS_TX_WEIGHT }
+}
+
+#[inline]
+pub(crate) fn htlc_timeout_tx_weight(opt_anchors: bool) -> u64 {
+	if opt_anchors { HTLC_TIMEOUT_ANCHOR_TX_WEIGHT } else { HTLC_TIMEOUT_TX_WEIGHT }
+}
 
 #[derive(PartialEq)]
 pub(crate) enum HTLCType {
@@ -47,11 +65,13 @@ pub(crate) enum HTLCType {
 }
 
 impl HTLCType {
-	/// Check if a given tx witnessScript len matchs one of a pre-signed HTLC
-	pub(crate) fn scriptlen_to_htlctype(witness_script_len: usize) ->  Option<HTLCType> {
-		if witness_script_len == 133 {
+	/// Check if a given tx witnessScript len matchs one of a pre-signed HTLC. Channels using anchor
+	/// outputs have 3 additional bytes in their HTLC witnessScripts (1 OP_CSV OP_DROP).
+	pub(crate) fn scriptlen_to_htlctype(witness_script_len: usize, opt_anchors: bool) ->  Option<HTLCType> {
+		let anchors_len = if opt_anchors { 3 } else { 0 };
+		if witness_script_len == 133 + anchors_len {
 			Some(HTLCType::OfferedHTLC)
-		} else if witness_script_len >= 136 && witness_script_len <= 139 {
+		} else if witness_script_len >= 136 + anchors_len && witness_script_len <= 139 + anchors_len {
 			Some(HTLCType::AcceptedHTLC)
 		} else {
 			None
@@ -430,9 +450,9 @@ impl_writeable!(HTLCOutputInCommitment, 1 + 8 + 4 + 32 + 5, {
 });
 
 #[inline]
-pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommitment, broadcaster_htlc_key: &PublicKey, countersignatory_htlc_key: &PublicKey, revocation_key: &PublicKey) -> Script {
+pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommitment, opt_anchors: bool, broadcaster_htlc_key: &PublicKey, countersignatory_htlc_key: &PublicKey, revocation_key: &PublicKey) -> Script {
 	let payment_hash160 = Ripemd160::hash(&htlc.payment_hash.0[..]).into_inner();
-	if htlc.offered {
+	let builder = if htlc.offered {
 		Builder::new().push_opcode(opcodes::all::OP_DUP)
 		              .push_opcode(opcodes::all::OP_HASH160)
 		              .push_slice(&PubkeyHash::hash(&revocation_key.serialize())[..])
@@ -458,8 +478,6 @@ pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommit
 		              .push_opcode(opcodes::all::OP_EQUALVERIFY)
 		              .push_opcode(opcodes::all::OP_CHECKSIG)
 		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .into_script()
 	} else {
 		Builder::new().push_opcode(opcodes::all::OP_DUP)
 		              .push_opcode(opcodes::all::OP_HASH160)
@@ -489,16 +507,68 @@ pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommit
 		              .push_opcode(opcodes::all::OP_DROP)
 		              .push_opcode(opcodes::all::OP_CHECKSIG)
 		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .into_script()
-	}
+	};
+	// With anchor outputs, the non-revocation paths are encumbered by a 1-block CSV so that the
+	// HTLC outputs can't be used to pin the commitment transaction via CPFP carve-out.
+	let builder = if opt_anchors {
+		builder.push_int(1)
+		       .push_opcode(opcodes::all::OP_CSV)
+		       .push_opcode(opcodes::all::OP_DROP)
+	} else { builder };
+	builder.push_opcode(opcodes::all::OP_ENDIF)
+	       .into_script()
 }
 
 /// Gets the witness redeemscript for an HTLC output in a commitment transaction. Note that htlc
 /// does not need to have its previous_output_index filled.
 #[inline]
-pub fn get_htlc_redeemscript(htlc: &HTLCOutputInCommitment, keys: &TxCreationKeys) -> Script {
-	get_htlc_redeemscript_with_explicit_keys(htlc, &keys.broadcaster_htlc_key, &keys.countersignatory_htlc_key, &keys.revocation_key)
+pub fn get_htlc_redeemscript(htlc: &HTLCOutputInCommitment, opt_anchors: bool, keys: &TxCreationKeys) -> Script {
+	get_htlc_redeemscript_with_explicit_keys(htlc, opt_anchors, &keys.broadcaster_htlc_key, &keys.countersignatory_htlc_key, &keys.revocation_key)
+}
+
+/// Gets the witness redeemscript for an anchor output of a commitment transaction, spendable by
+/// the owner of the given funding key, or by anyone once 16 blocks have passed since the
+/// commitment transaction confirmed.
+pub fn get_anchor_redeemscript(funding_pubkey: &PublicKey) -> Script {
+	Builder::new().push_slice(&funding_pubkey.serialize()[..])
+	              .push_opcode(opcodes::all::OP_CHECKSIG)
+	              .push_opcode(opcodes::all::OP_IFDUP)
+	              .push_opcode(opcodes::all::OP_NOTIF)
+	              .push_int(16)
+	              .push_opcode(opcodes::all::OP_CSV)
+	              .push_opcode(opcodes::all::OP_ENDIF)
+	              .into_script()
+}
+
+/// Gets the witness for spending an anchor output given the owner's funding pubkey and a
+/// signature from the corresponding funding key (see ChannelKeys::sign_holder_anchor_input).
+pub fn build_anchor_input_witness(funding_pubkey: &PublicKey, funding_sig: &Signature) -> Vec<Vec<u8>> {
+	let mut sig = funding_sig.serialize_der().to_vec();
+	sig.push(SigHashType::All as u8);
+	vec![sig, get_anchor_redeemscript(funding_pubkey).into_bytes()]
+}
+
+/// Gets the witness redeemscript for the to_remote output of a commitment transaction of a
+/// channel using anchor outputs, spendable by the given payment key after a 1-block CSV.
+pub fn get_to_countersignatory_with_anchors_redeemscript(payment_point: &PublicKey) -> Script {
+	Builder::new().push_slice(&payment_point.serialize()[..])
+	              .push_opcode(opcodes::all::OP_CHECKSIGVERIFY)
+	              .push_int(1)
+	              .push_opcode(opcodes::all::OP_CSV)
+	              .into_script()
+}
+
+/// Gets the scriptPubKey of the to_remote output of a commitment transaction, paying to the
+/// countersignatory's payment_point: a P2WPKH for legacy channels, or a P2WSH with a 1-block CSV
+/// for channels using anchor outputs.
+pub(crate) fn get_to_countersignatory_script_pubkey(payment_point: &PublicKey, opt_anchors: bool) -> Script {
+	if opt_anchors {
+		get_to_countersignatory_with_anchors_redeemscript(payment_point).to_v0_p2wsh()
+	} else {
+		Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0)
+		              .push_slice(&WPubkeyHash::hash(&payment_point.serialize())[..])
+		              .into_script()
+	}
 }
 
 /// Gets the redeemscript for a funding output from the two funding public keys.
@@ -517,8 +587,16 @@ pub fn make_funding_redeemscript(broadcaster: &PublicKey, countersignatory: &Pub
 	}.push_opcode(opcodes::all::OP_PUSHNUM_2).push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script()
 }
 
+/// Gets the sighash type the countersignatory uses for its signature on an HTLC transaction. With
+/// anchor outputs, SIGHASH_SINGLE|SIGHASH_ANYONECANPAY allows the broadcaster to attach additional
+/// inputs and outputs to bump the HTLC transaction's fee.
+#[inline]
+pub fn htlc_counterparty_sighash_type(opt_anchors: bool) -> SigHashType {
+	if opt_anchors { SigHashType::SinglePlusAnyoneCanPay } else { SigHashType::All }
+}
+
 /// panics if htlc.transaction_output_index.is_none()!
-pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, contest_delay: u16, htlc: &HTLCOutputInCommitment, broadcaster_delayed_payment_key: &PublicKey, revocation_key: &PublicKey) -> Transaction {
+pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, contest_delay: u16, htlc: &HTLCOutputInCommitment, opt_anchors: bool, broadcaster_delayed_payment_key: &PublicKey, revocation_key: &PublicKey) -> Transaction {
 	let mut txins: Vec<TxIn> = Vec::new();
 	txins.push(TxIn {
 		previous_output: OutPoint {
@@ -526,14 +604,14 @@ pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, contest_del
 			vout: htlc.transaction_output_index.expect("Can't build an HTLC transaction for a dust output"),
 		},
 		script_sig: Script::new(),
-		sequence: 0,
+		sequence: if opt_anchors { 1 } else { 0 },
 		witness: Vec::new(),
 	});
 
 	let total_fee = if htlc.offered {
-			feerate_per_kw as u64 * HTLC_TIMEOUT_TX_WEIGHT / 1000
+			feerate_per_kw as u64 * htlc_timeout_tx_weight(opt_anchors) / 1000
 		} else {
-			feerate_per_kw as u64 * HTLC_SUCCESS_TX_WEIGHT / 1000
+			feerate_per_kw as u64 * htlc_success_tx_weight(opt_anchors) / 1000
 		};
 
 	let mut txouts: Vec<TxOut> = Vec::new();
@@ -578,6 +656,12 @@ pub struct HolderCommitmentTransaction {
 	/// The counterparty HTLC signatures in the second element will always be set for non-dust HTLCs, ie
 	/// those for which transaction_output_index.is_some().
 	pub per_htlc: Vec<(HTLCOutputInCommitment, Option<Signature>)>,
+	/// Whether the channel uses anchor outputs, in which case the HTLC transactions spend the
+	/// anchor variant of the HTLC scripts and the counterparty HTLC signatures are
+	/// SIGHASH_SINGLE|SIGHASH_ANYONECANPAY.
+	///
+	/// Note that this is not serialized, and is always false after deserialization.
+	pub opt_anchors: bool,
 }
 impl HolderCommitmentTransaction {
 	#[cfg(test)]
@@ -610,7 +694,8 @@ impl HolderCommitmentTransaction {
 					broadcaster_delayed_payment_key: dummy_key.clone(),
 				},
 			feerate_per_kw: 0,
-			per_htlc: Vec::new()
+			per_htlc: Vec::new(),
+			opt_anchors: false,
 		}
 	}
 
@@ -619,7 +704,7 @@ impl HolderCommitmentTransaction {
 	///
 	/// The unsigned transaction outputs must be consistent with htlc_data.  This function
 	/// only checks that the shape and amounts are consistent, but does not check the scriptPubkey.
-	pub fn new_missing_holder_sig(unsigned_tx: Transaction, counterparty_sig: Signature, holder_funding_key: &PublicKey, counterparty_funding_key: &PublicKey, keys: TxCreationKeys, feerate_per_kw: u32, htlc_data: Vec<(HTLCOutputInCommitment, Option<Signature>)>) -> HolderCommitmentTransaction {
+	pub fn new_missing_holder_sig(unsigned_tx: Transaction, counterparty_sig: Signature, holder_funding_key: &PublicKey, counterparty_funding_key: &PublicKey, keys: TxCreationKeys, feerate_per_kw: u32, opt_anchors: bool, htlc_data: Vec<(HTLCOutputInCommitment, Option<Signature>)>) -> HolderCommitmentTransaction {
 		if unsigned_tx.input.len() != 1 { panic!("Tried to store a commitment transaction that had input count != 1!"); }
 		if unsigned_tx.input[0].witness.len() != 0 { panic!("Tried to store a signed commitment transaction?"); }
 
@@ -642,6 +727,7 @@ impl HolderCommitmentTransaction {
 			keys,
 			feerate_per_kw,
 			per_htlc: htlc_data,
+			opt_anchors,
 		}
 	}
 
@@ -703,9 +789,9 @@ impl HolderCommitmentTransaction {
 
 		for this_htlc in self.per_htlc.iter() {
 			if this_htlc.0.transaction_output_index.is_some() {
-				let htlc_tx = build_htlc_transaction(&txid, self.feerate_per_kw, counterparty_selected_contest_delay, &this_htlc.0, &self.keys.broadcaster_delayed_payment_key, &self.keys.revocation_key);
+				let htlc_tx = build_htlc_transaction(&txid, self.feerate_per_kw, counterparty_selected_contest_delay, &this_htlc.0, self.opt_anchors, &self.keys.broadcaster_delayed_payment_key, &self.keys.revocation_key);
 
-				let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc.0, &self.keys.broadcaster_htlc_key, &self.keys.countersignatory_htlc_key, &self.keys.revocation_key);
+				let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc.0, self.opt_anchors, &self.keys.broadcaster_htlc_key, &self.keys.countersignatory_htlc_key, &self.keys.revocation_key);
 
 				let sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, this_htlc.0.amount_msat / 1000, SigHashType::All)[..]);
 				ret.push(Some(secp_ctx.sign(&sighash, &holder_htlc_key)));
@@ -726,19 +812,19 @@ impl HolderCommitmentTransaction {
 		// Further, we should never be provided the preimage for an HTLC-Timeout transaction.
 		if  this_htlc.0.offered && preimage.is_some() { unreachable!(); }
 
-		let mut htlc_tx = build_htlc_transaction(&txid, self.feerate_per_kw, counterparty_selected_contest_delay, &this_htlc.0, &self.keys.broadcaster_delayed_payment_key, &self.keys.revocation_key);
+		let mut htlc_tx = build_htlc_transaction(&txid, self.feerate_per_kw, counterparty_selected_contest_delay, &this_htlc.0, self.opt_anchors, &self.keys.broadcaster_delayed_payment_key, &self.keys.revocation_key);
 		// Channel should have checked that we have a counterparty signature for this HTLC at
 		// creation, and we should have a sensible htlc transaction:
 		assert!(this_htlc.1.is_some());
 
-		let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc.0, &self.keys.broadcaster_htlc_key, &self.keys.countersignatory_htlc_key, &self.keys.revocation_key);
+		let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc.0, self.opt_anchors, &self.keys.broadcaster_htlc_key, &self.keys.countersignatory_htlc_key, &self.keys.revocation_key);
 
 		// First push the multisig dummy, note that due to BIP147 (NULLDUMMY) it must be a zero-length element.
 		htlc_tx.input[0].witness.push(Vec::new());
 
 		htlc_tx.input[0].witness.push(this_htlc.1.unwrap().serialize_der().to_vec());
 		htlc_tx.input[0].witness.push(signature.serialize_der().to_vec());
-		htlc_tx.input[0].witness[1].push(SigHashType::All as u8);
+		htlc_tx.input[0].witness[1].push(htlc_counterparty_sighash_type(self.opt_anchors) as u8);
 		htlc_tx.input[0].witness[2].push(SigHashType::All as u8);
 
 		if this_htlc.0.offered {
@@ -810,13 +896,21 @@ impl Readable for HolderCommitmentTransaction {
 			keys,
 			feerate_per_kw,
 			per_htlc,
+			// Not serialized, set by whoever holds the HolderCommitmentTransaction.
+			opt_anchors: false,
 		})
 	}
 }
 
 #[cfg(test)]
 mod tests {
-	use super::CounterpartyCommitmentSecrets;
+	use super::{CounterpartyCommitmentSecrets, HTLCOutputInCommitment, HTLCType};
+	use ln::chan_utils;
+	use ln::channelmanager::PaymentHash;
+	use bitcoin::hash_types::Txid;
+	use bitcoin::hashes::hex::FromHex;
+	use bitcoin::secp256k1::Secp256k1;
+	use bitcoin::secp256k1::key::{SecretKey, PublicKey};
 	use hex;
 
 	#[test]
@@ -1163,4 +1257,44 @@ mod tests {
 			assert!(monitor.provide_secret(281474976710648, secrets.last().unwrap().clone()).is_err());
 		}
 	}
+
+	#[test]
+	fn test_anchor_scripts_and_htlc_txn() {
+		let secp_ctx = Secp256k1::new();
+		let pubkey = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let mut htlc = HTLCOutputInCommitment {
+			offered: true,
+			amount_msat: 1_000_000,
+			cltv_expiry: 500_000,
+			payment_hash: PaymentHash([1; 32]),
+			transaction_output_index: Some(0),
+		};
+
+		// HTLC witnessScripts get 1 OP_CSV OP_DROP with anchors and are still told apart by length
+		for offered in [true, false].iter() {
+			htlc.offered = *offered;
+			let legacy_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, false, &pubkey, &pubkey, &pubkey);
+			let anchor_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, true, &pubkey, &pubkey, &pubkey);
+			assert_eq!(anchor_script.len(), legacy_script.len() + 3);
+			assert!(HTLCType::scriptlen_to_htlctype(legacy_script.len(), false) == Some(if *offered { HTLCType::OfferedHTLC } else { HTLCType::AcceptedHTLC }));
+			assert!(HTLCType::scriptlen_to_htlctype(anchor_script.len(), true) == Some(if *offered { HTLCType::OfferedHTLC } else { HTLCType::AcceptedHTLC }));
+		}
+
+		// Anchor and to_remote outputs are P2WSH with anchors, P2WPKH to_remote otherwise
+		assert!(chan_utils::get_anchor_redeemscript(&pubkey).to_v0_p2wsh().is_v0_p2wsh());
+		assert!(chan_utils::get_to_countersignatory_script_pubkey(&pubkey, false).is_v0_p2wpkh());
+		assert_eq!(chan_utils::get_to_countersignatory_script_pubkey(&pubkey, true), chan_utils::get_to_countersignatory_with_anchors_redeemscript(&pubkey).to_v0_p2wsh());
+		assert_eq!(chan_utils::build_anchor_input_witness(&pubkey, &secp_ctx.sign(&hash_to_message!(&[1; 32]), &SecretKey::from_slice(&[42; 32]).unwrap())).len(), 2);
+
+		// HTLC transactions of anchor channels are CSV-locked by one block and pay for their
+		// (larger) weight themselves
+		let prev_hash = Txid::from_hex("56944c5d3f98413ef45cf54545538103cc9f298e0575820ad3591376e2e0f65d").unwrap();
+		htlc.offered = true;
+		let legacy_tx = chan_utils::build_htlc_transaction(&prev_hash, 1000, 144, &htlc, false, &pubkey, &pubkey);
+		let anchor_tx = chan_utils::build_htlc_transaction(&prev_hash, 1000, 144, &htlc, true, &pubkey, &pubkey);
+		assert_eq!(legacy_tx.input[0].sequence, 0);
+		assert_eq!(anchor_tx.input[0].sequence, 1);
+		assert_eq!(legacy_tx.output[0].value, 1000 - chan_utils::htlc_timeout_tx_weight(false));
+		assert_eq!(anchor_tx.output[0].value, 1000 - chan_utils::htlc_timeout_tx_weight(true));
+	}
 }
@@ -26,14 +26,15 @@ use ln::features::{ChannelFeatures, InitFeatures};
 use ln::msgs;
 use ln::msgs::{DecodeError, OptionalField, DataLossProtect};
 use ln::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateStep, HTLC_FAIL_BACK_BUFFER};
-use ln::channelmanager::{PendingHTLCStatus, HTLCSource, HTLCFailReason, HTLCFailureMsg, PendingHTLCInfo, RAACommitmentOrder, PaymentPreimage, PaymentHash, BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT};
-use ln::chan_utils::{CounterpartyCommitmentSecrets, HolderCommitmentTransaction, TxCreationKeys, HTLCOutputInCommitment, HTLC_SUCCESS_TX_WEIGHT, HTLC_TIMEOUT_TX_WEIGHT, make_funding_redeemscript, ChannelPublicKeys, PreCalculatedTxCreationKeys};
+use ln::channelmanager::{PendingHTLCStatus, HTLCSource, HTLCFailReason, HTLCFailureMsg, PendingHTLCInfo, RAACommitmentOrder, PaymentPreimage, PaymentHash, SpliceContribution, FundingContribution, BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT};
+use ln::interactivetxs::{InteractiveTxConstructor, InteractiveTxInput, InteractiveTxMessageSend, ConstructedTransaction, TX_COMMON_FIELDS_WEIGHT, SHARED_INPUT_WEIGHT, P2WPKH_INPUT_WEIGHT};
+use ln::chan_utils::{CounterpartyCommitmentSecrets, HolderCommitmentTransaction, TxCreationKeys, HTLCOutputInCommitment, make_funding_redeemscript, ChannelPublicKeys, ANCHOR_OUTPUT_VALUE_SATOSHI, PreCalculatedTxCreationKeys};
 use ln::chan_utils;
 use chain::chaininterface::{FeeEstimator,ConfirmationTarget};
 use chain::transaction::OutPoint;
 use chain::keysinterface::{ChannelKeys, KeysInterface};
 use util::transaction_utils;
-use util::ser::{Readable, Writeable, Writer};
+use util::ser::{Readable, ReadableArgs, Writeable, Writer};
 use util::logger::Logger;
 use util::errors::APIError;
 use util::config::{UserConfig,ChannelConfig};
@@ -258,6 +259,138 @@ enum UpdateStatus {
 	DisabledStaged,
 }
 
+/// The stage an in-progress splice negotiation has reached.
+enum SpliceStage {
+	/// We've sent stfu and are waiting on our counterparty's.
+	AwaitingQuiescence,
+	/// We've responded to our counterparty's stfu and are waiting on their splice_init.
+	AwaitingSpliceInit,
+	/// We've sent splice_init and are waiting on our counterparty's splice_ack.
+	AwaitingSpliceAck,
+	/// The splice transaction is being interactively constructed.
+	Constructing(InteractiveTxConstructor),
+	/// The splice transaction has been constructed and we've sent our commitment_signed for the
+	/// new funding output, but have yet to receive our counterparty's.
+	AwaitingCommitmentSigned(SplicedFunding),
+	/// Both sides have signed commitment transactions spending the new funding output, and the
+	/// splice transaction's inputs are being signed.
+	AwaitingSignatures {
+		funding: SplicedFunding,
+		/// The splice transaction with witnesses for our own inputs, once the user has signed it.
+		holder_signed_tx: Option<Transaction>,
+		counterparty_signatures: Option<msgs::TxSignatures>,
+		/// The tx_signatures we sent, if we have.
+		holder_signatures: Option<msgs::TxSignatures>,
+	},
+}
+
+/// A constructed splice transaction and the channel state it results in.
+struct SplicedFunding {
+	constructed: ConstructedTransaction,
+	funding_txo: OutPoint,
+	channel_value_satoshis: u64,
+	value_to_self_msat: u64,
+}
+
+/// A splice which is being negotiated with our counterparty.
+struct PendingSplice {
+	stage: SpliceStage,
+	funding_feerate_per_kw: u32,
+	locktime: u32,
+	holder_contribution_satoshis: i64,
+	counterparty_contribution_satoshis: i64,
+	holder_inputs: Vec<InteractiveTxInput>,
+	holder_outputs: Vec<TxOut>,
+}
+
+/// A splice transaction which we've signed and are waiting to see confirm, after which both sides
+/// exchange splice_locked and the channel moves to the new funding output.
+struct LockingSplice {
+	funding_txo: OutPoint,
+	channel_value_satoshis: u64,
+	/// How much the splice changes our balance by. The channel keeps being updated while the
+	/// splice locks, so our balance on its funding output follows our current one.
+	holder_contribution_satoshis: i64,
+	/// The tx_signatures we sent, retransmitted on reconnection until our counterparty sends
+	/// splice_locked as we can't otherwise tell whether they were received.
+	holder_signatures: msgs::TxSignatures,
+	confirmed_in: Option<BlockHash>,
+	confirmation_height: u32,
+	short_channel_id: Option<u64>,
+	locked_sent: bool,
+	counterparty_locked: bool,
+}
+impl Writeable for LockingSplice {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.funding_txo.write(w)?;
+		self.channel_value_satoshis.write(w)?;
+		self.holder_contribution_satoshis.write(w)?;
+		// tx_signatures ends in TLV fields which run to the end of the message, so we have to
+		// length-prefix it.
+		self.holder_signatures.encode().write(w)?;
+		self.confirmed_in.write(w)?;
+		self.confirmation_height.write(w)?;
+		self.short_channel_id.write(w)?;
+		self.locked_sent.write(w)?;
+		self.counterparty_locked.write(w)?;
+		Ok(())
+	}
+}
+impl Readable for LockingSplice {
+	fn read<R: ::std::io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let funding_txo = Readable::read(r)?;
+		let channel_value_satoshis = Readable::read(r)?;
+		let holder_contribution_satoshis = Readable::read(r)?;
+		let encoded_holder_signatures: Vec<u8> = Readable::read(r)?;
+		let holder_signatures = Readable::read(&mut ::std::io::Cursor::new(&encoded_holder_signatures[..]))?;
+		Ok(Self {
+			funding_txo,
+			channel_value_satoshis,
+			holder_contribution_satoshis,
+			holder_signatures,
+			confirmed_in: Readable::read(r)?,
+			confirmation_height: Readable::read(r)?,
+			short_channel_id: Readable::read(r)?,
+			locked_sent: Readable::read(r)?,
+			counterparty_locked: Readable::read(r)?,
+		})
+	}
+}
+
+/// The stage an in-progress dual-funded channel open has reached.
+enum DualFundingStage {
+	/// We've received our counterparty's open_channel2 and are waiting on the user to accept it.
+	AwaitingAcceptance,
+	/// We've sent open_channel2 and are waiting on our counterparty's accept_channel2.
+	AwaitingAcceptChannel,
+	/// The funding transaction is being interactively constructed.
+	Constructing(InteractiveTxConstructor),
+	/// The funding transaction has been constructed and we've sent our commitment_signed for our
+	/// counterparty's initial commitment transaction, but have yet to receive theirs.
+	AwaitingCommitmentSigned(ConstructedTransaction),
+	/// Both sides have signed their initial commitment transactions, and the funding transaction's
+	/// inputs are being signed.
+	AwaitingSignatures {
+		constructed: ConstructedTransaction,
+		/// The funding transaction with witnesses for our own inputs, once the user has signed it.
+		holder_signed_tx: Option<Transaction>,
+		counterparty_signatures: Option<msgs::TxSignatures>,
+		/// The tx_signatures we sent, if we have.
+		holder_signatures: Option<msgs::TxSignatures>,
+	},
+}
+
+/// The negotiation of a dual-funded channel's funding transaction with our counterparty.
+struct PendingDualFunding {
+	stage: DualFundingStage,
+	funding_feerate_per_kw: u32,
+	locktime: u32,
+	holder_contribution_satoshis: u64,
+	counterparty_contribution_satoshis: u64,
+	holder_inputs: Vec<InteractiveTxInput>,
+	holder_outputs: Vec<TxOut>,
+}
+
 // TODO: We should refactor this to be an Inbound/OutboundChannel until initial setup handshaking
 // has been completed, and then turn into a Channel to get compiler-time enforcement of things like
 // calling channel_id() before we're set up or things like get_outbound_funding_signed on an
@@ -275,6 +408,9 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	channel_outbound: bool,
 	secp_ctx: Secp256k1<secp256k1::All>,
 	channel_value_satoshis: u64,
+	/// Whether the commitment transactions use the option_anchor_outputs format. Only known once
+	/// the counterparty's open_channel/accept_channel has been handled.
+	opt_anchors: bool,
 
 	latest_monitor_update_id: u64,
 
@@ -341,19 +477,39 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	counterparty_max_commitment_tx_output: ::std::sync::Mutex<(u64, u64)>,
 
 	last_sent_closing_fee: Option<(u32, u64, Signature)>, // (feerate, fee, holder_sig)
+	/// The fee range the user asked us to close the channel within, if any.
+	closing_fee_limits: Option<msgs::ClosingSignedFeeRange>,
 
 	funding_txo: Option<OutPoint>,
-
-	/// The hash of the block in which the funding transaction reached our CONF_TARGET. We use this
-	/// to detect unconfirmation after a serialize-unserialize roundtrip where we may not see a full
-	/// series of block_connected/block_disconnected calls. Obviously this is not a guarantee as we
-	/// could miss the funding_tx_confirmed_in block as well, but it serves as a useful fallback.
+	/// The funding output of the most recent splice to have locked, if any. Note that funding_txo
+	/// (and thus our channel_id and ChannelMonitor) always refers to the original funding output.
+	spliced_funding_txo: Option<OutPoint>,
+	/// A splice which is being negotiated, if any. Negotiation only survives a disconnection once
+	/// we're just waiting on tx_signatures, and never survives a restart, so this is not persisted.
+	pending_splice: Option<PendingSplice>,
+	/// A splice we've signed the transaction for, but which has yet to lock.
+	locking_splice: Option<LockingSplice>,
+	/// The negotiation of our funding transaction if this is a dual-funded channel which has yet
+	/// to be fully signed. This is never persisted, see dual_funding_commitment_signed.
+	pending_dual_funding: Option<PendingDualFunding>,
+
+	/// The hash of the block in which the funding transaction was confirmed, used to detect its
+	/// unconfirmation when that block is disconnected.
 	funding_tx_confirmed_in: Option<BlockHash>,
 	short_channel_id: Option<u64>,
+	/// Our short_channel_id before the most recent splice locked, if any. HTLCs added while the
+	/// splice was locking still refer to the channel by it, so we keep it around until the next
+	/// splice, which cannot start until they have all been resolved.
+	pre_splice_short_channel_id: Option<u64>,
 	/// Used to deduplicate block_connected callbacks, also used to verify consistency during
 	/// ChannelManager deserialization (hence pub(super))
 	pub(super) last_block_connected: BlockHash,
-	funding_tx_confirmations: u64,
+	/// The height of the block in which the funding transaction was confirmed, or 0 if it is not
+	/// confirmed. Confirmations are counted from this and the height of the best block.
+	funding_tx_confirmation_height: u32,
+	/// For inbound channels, the height of our best block when we received funding_created. Used
+	/// to give up on funding transactions which never confirm.
+	funding_created_height: Option<u32>,
 
 	counterparty_dust_limit_satoshis: u64,
 	#[cfg(test)]
@@ -387,6 +543,9 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	counterparty_node_id: PublicKey,
 
 	counterparty_shutdown_scriptpubkey: Option<Script>,
+	/// The script the user asked us to close the channel to, overriding the one derived from
+	/// shutdown_pubkey.
+	holder_shutdown_scriptpubkey: Option<Script>,
 
 	commitment_secrets: CounterpartyCommitmentSecrets,
 
@@ -394,10 +553,12 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 }
 
 pub const OUR_MAX_HTLCS: u16 = 50; //TODO
-/// Confirmation count threshold at which we close a channel. Ideally we'd keep the channel around
-/// on ice until the funding transaction gets more confirmations, but the LN protocol doesn't
-/// really allow for this, so instead we're stuck closing it out at that point.
-const UNCONF_THRESHOLD: u32 = 6;
+/// The weight of the parts of a splice transaction the initiator pays for: the common transaction
+/// fields, the shared input spending the current funding output and the new funding output.
+const SPLICE_INITIATOR_BASE_WEIGHT: u64 = TX_COMMON_FIELDS_WEIGHT + SHARED_INPUT_WEIGHT + 172;
+/// The weight of the parts of a dual-funded channel's funding transaction the initiator pays for:
+/// the common transaction fields and the funding output.
+const DUAL_FUND_INITIATOR_BASE_WEIGHT: u64 = TX_COMMON_FIELDS_WEIGHT + 172;
 const SPENDING_INPUT_FOR_A_OUTPUT_WEIGHT: u64 = 79; // prevout: 36, nSequence: 4, script len: 1, witness lengths: (3+1)/4, sig: 73/4, if-selector: 1, redeemScript: (6 ops + 2*33 pubkeys + 1*2 delay)/4
 const B_OUTPUT_PLUS_SPENDING_INPUT_WEIGHT: u64 = 104; // prevout: 40, nSequence: 4, script len: 1, witness lengths: 3/4, sig: 73/4, pubkey: 33/4, output: 31 (TODO: Wrong? Useless?)
 
@@ -406,10 +567,38 @@ const COMMITMENT_TX_BASE_WEIGHT: u64 = 724;
 #[cfg(test)]
 pub const COMMITMENT_TX_BASE_WEIGHT: u64 = 724;
 #[cfg(not(test))]
+const COMMITMENT_TX_BASE_ANCHOR_WEIGHT: u64 = 1124;
+#[cfg(test)]
+pub const COMMITMENT_TX_BASE_ANCHOR_WEIGHT: u64 = 1124;
+#[cfg(not(test))]
 const COMMITMENT_TX_WEIGHT_PER_HTLC: u64 = 172;
 #[cfg(test)]
 pub const COMMITMENT_TX_WEIGHT_PER_HTLC: u64 = 172;
 
+#[inline]
+fn commitment_tx_base_weight(opt_anchors: bool) -> u64 {
+	if opt_anchors { COMMITMENT_TX_BASE_ANCHOR_WEIGHT } else { COMMITMENT_TX_BASE_WEIGHT }
+}
+
+/// Returns true if the script is a segwit v1+ witness program, which BOLT 2 allows in shutdown
+/// messages only if option_shutdown_anysegwit was negotiated.
+pub(super) fn is_anysegwit_script(script: &Script) -> bool {
+	let bytes = script.as_bytes();
+	bytes.len() >= 4 && bytes.len() <= 42 &&
+		bytes[0] >= opcodes::all::OP_PUSHNUM_1.into_u8() && bytes[0] <= opcodes::all::OP_PUSHNUM_16.into_u8() &&
+		bytes[1] as usize == bytes.len() - 2
+}
+
+/// Returns true if the script is of one of the forms BOLT 2 allows in shutdown messages.
+pub(super) fn is_valid_shutdown_script(script: &Script) -> bool {
+	script.is_p2pkh() || script.is_p2sh() || script.is_v0_p2wpkh() || script.is_v0_p2wsh() || is_anysegwit_script(script)
+}
+
+/// The number of blocks after our receiving funding_created for an inbound channel after which, if
+/// the funding transaction still hasn't confirmed, we forget the channel. Only our counterparty
+/// has funds at stake, and they may well have double-spent the funding inputs.
+pub const FUNDING_CONF_DEADLINE_BLOCKS: u32 = 2016;
+
 /// Maximmum `funding_satoshis` value, according to the BOLT #2 specification
 /// it's 2^24.
 pub const MAX_FUNDING_SATOSHIS: u64 = 1 << 24;
@@ -495,6 +684,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			channel_outbound: true,
 			secp_ctx: Secp256k1::new(),
 			channel_value_satoshis: channel_value_satoshis,
+			opt_anchors: false,
 
 			latest_monitor_update_id: 0,
 
@@ -529,12 +719,19 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			counterparty_max_commitment_tx_output: ::std::sync::Mutex::new((channel_value_satoshis * 1000 - push_msat, push_msat)),
 
 			last_sent_closing_fee: None,
+			closing_fee_limits: None,
 
 			funding_txo: None,
+			spliced_funding_txo: None,
+			pending_splice: None,
+			locking_splice: None,
+			pending_dual_funding: None,
 			funding_tx_confirmed_in: None,
 			short_channel_id: None,
+			pre_splice_short_channel_id: None,
 			last_block_connected: Default::default(),
-			funding_tx_confirmations: 0,
+			funding_tx_confirmation_height: 0,
+			funding_created_height: None,
 
 			feerate_per_kw: feerate,
 			counterparty_dust_limit_satoshis: 0,
@@ -555,6 +752,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			counterparty_node_id,
 
 			counterparty_shutdown_scriptpubkey: None,
+			holder_shutdown_scriptpubkey: None,
 
 			commitment_secrets: CounterpartyCommitmentSecrets::new(),
 
@@ -590,7 +788,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			delayed_payment_basepoint: msg.delayed_payment_basepoint,
 			htlc_basepoint: msg.htlc_basepoint
 		};
-		chan_keys.on_accept(&counterparty_pubkeys, msg.to_self_delay, config.own_channel_config.our_to_self_delay);
+		let opt_anchors = config.own_channel_config.negotiate_anchor_outputs && their_features.supports_anchor_outputs();
+		chan_keys.on_accept(&counterparty_pubkeys, msg.to_self_delay, config.own_channel_config.our_to_self_delay, opt_anchors);
 		let mut local_config = (*config).channel_options.clone();
 
 		if config.own_channel_config.our_to_self_delay < BREAKDOWN_TIMEOUT {
@@ -682,13 +881,14 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		// check if the funder's amount for the initial commitment tx is sufficient
 		// for full fee payment
 		let funders_amount_msat = msg.funding_satoshis * 1000 - msg.push_msat;
-		let lower_limit = background_feerate as u64 * COMMITMENT_TX_BASE_WEIGHT;
+		let anchors_msat = if opt_anchors { ANCHOR_OUTPUT_VALUE_SATOSHI * 2 * 1000 } else { 0 };
+		let lower_limit = background_feerate as u64 * commitment_tx_base_weight(opt_anchors) + anchors_msat;
 		if funders_amount_msat < lower_limit {
 			return Err(ChannelError::Close(format!("Insufficient funding amount ({}) for initial commitment. Must be at least {}", funders_amount_msat, lower_limit)));
 		}
 
 		let to_local_msat = msg.push_msat;
-		let to_remote_msat = funders_amount_msat - background_feerate as u64 * COMMITMENT_TX_BASE_WEIGHT;
+		let to_remote_msat = funders_amount_msat - lower_limit;
 		if to_local_msat <= msg.channel_reserve_satoshis * 1000 && to_remote_msat <= holder_selected_channel_reserve_satoshis * 1000 {
 			return Err(ChannelError::Close("Insufficient funding amount for initial commitment".to_owned()));
 		}
@@ -697,7 +897,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			match &msg.shutdown_scriptpubkey {
 				&OptionalField::Present(ref script) => {
 					// Peer is signaling upfront_shutdown and has provided a non-accepted scriptpubkey format. We enforce it while receiving shutdown msg
-					if script.is_p2pkh() || script.is_p2sh() || script.is_v0_p2wsh() || script.is_v0_p2wpkh() {
+					if is_valid_shutdown_script(script) {
 						Some(script.clone())
 					// Peer is signaling upfront_shutdown and has opt-out with a 0-length script. We don't enforce anything
 					} else if script.len() == 0 {
@@ -722,6 +922,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			channel_state: (ChannelState::OurInitSent as u32) | (ChannelState::TheirInitSent as u32),
 			channel_outbound: false,
 			secp_ctx: Secp256k1::new(),
+			opt_anchors,
 
 			latest_monitor_update_id: 0,
 
@@ -756,12 +957,19 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			counterparty_max_commitment_tx_output: ::std::sync::Mutex::new((msg.push_msat, msg.funding_satoshis * 1000 - msg.push_msat)),
 
 			last_sent_closing_fee: None,
+			closing_fee_limits: None,
 
 			funding_txo: None,
+			spliced_funding_txo: None,
+			pending_splice: None,
+			locking_splice: None,
+			pending_dual_funding: None,
 			funding_tx_confirmed_in: None,
 			short_channel_id: None,
+			pre_splice_short_channel_id: None,
 			last_block_connected: Default::default(),
-			funding_tx_confirmations: 0,
+			funding_tx_confirmation_height: 0,
+			funding_created_height: None,
 
 			feerate_per_kw: msg.feerate_per_kw,
 			channel_value_satoshis: msg.funding_satoshis,
@@ -783,6 +991,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			counterparty_node_id,
 
 			counterparty_shutdown_scriptpubkey,
+			holder_shutdown_scriptpubkey: None,
 
 			commitment_secrets: CounterpartyCommitmentSecrets::new(),
 
@@ -792,6 +1001,70 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		Ok(chan)
 	}
 
+	/// Creates a new outbound dual-funded channel, to which we contribute the given funds and our
+	/// counterparty may add its own once it accepts it.
+	pub fn new_outbound_dual_funded<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, counterparty_node_id: PublicKey, contribution: &FundingContribution, funding_feerate_per_kw: u32, locktime: u32, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, APIError>
+	where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+	      F::Target: FeeEstimator,
+	{
+		let mut chan = Channel::new_outbound(fee_estimator, keys_provider, counterparty_node_id, contribution.value_satoshis, 0, user_id, config)?;
+		let (holder_inputs, holder_outputs) = chan.get_contribution_inputs_and_change(contribution.value_satoshis, &contribution.inputs, &contribution.change_script, funding_feerate_per_kw, DUAL_FUND_INITIATOR_BASE_WEIGHT)?;
+		chan.pending_dual_funding = Some(PendingDualFunding {
+			stage: DualFundingStage::AwaitingAcceptChannel,
+			funding_feerate_per_kw,
+			locktime,
+			holder_contribution_satoshis: contribution.value_satoshis,
+			counterparty_contribution_satoshis: 0,
+			holder_inputs,
+			holder_outputs,
+		});
+		Ok(chan)
+	}
+
+	/// Creates a new dual-funded channel from a remote side's open_channel2, which waits on the
+	/// user to call accept_dual_funded_channel before we respond.
+	/// Assumes chain_hash has already been checked and corresponds with what we expect!
+	pub fn new_from_req_dual_funded<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, counterparty_node_id: PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2, config: &UserConfig) -> Result<Channel<ChanSigner>, ChannelError>
+		where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+          F::Target: FeeEstimator
+	{
+		Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.funding_feerate_perkw)?;
+		// Until we know what we'll contribute, the channel is checked as if our counterparty were
+		// opening a single-funded channel with the reserve we'd require of it.
+		let open_channel = msgs::OpenChannel {
+			chain_hash: msg.chain_hash,
+			temporary_channel_id: msg.temporary_channel_id,
+			funding_satoshis: msg.funding_satoshis,
+			push_msat: 0,
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			channel_reserve_satoshis: Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(msg.funding_satoshis),
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			feerate_per_kw: msg.commitment_feerate_perkw,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			funding_pubkey: msg.funding_pubkey,
+			revocation_basepoint: msg.revocation_basepoint,
+			payment_point: msg.payment_point,
+			delayed_payment_basepoint: msg.delayed_payment_basepoint,
+			htlc_basepoint: msg.htlc_basepoint,
+			first_per_commitment_point: msg.first_per_commitment_point,
+			channel_flags: msg.channel_flags,
+			shutdown_scriptpubkey: msg.shutdown_scriptpubkey.clone(),
+		};
+		let mut chan = Channel::new_from_req(fee_estimator, keys_provider, counterparty_node_id, their_features, &open_channel, 0, config)?;
+		chan.pending_dual_funding = Some(PendingDualFunding {
+			stage: DualFundingStage::AwaitingAcceptance,
+			funding_feerate_per_kw: msg.funding_feerate_perkw,
+			locktime: msg.locktime,
+			holder_contribution_satoshis: 0,
+			counterparty_contribution_satoshis: msg.funding_satoshis,
+			holder_inputs: Vec::new(),
+			holder_outputs: Vec::new(),
+		});
+		Ok(chan)
+	}
+
 	// Utilities to build transactions:
 
 	fn get_commitment_transaction_number_obscure_factor(&self) -> u64 {
@@ -834,12 +1107,23 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// sources are provided only for outbound HTLCs in the third return value.
 	#[inline]
 	fn build_commitment_transaction<L: Deref>(&self, commitment_number: u64, keys: &TxCreationKeys, local: bool, generated_by_local: bool, feerate_per_kw: u32, logger: &L) -> (Transaction, usize, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>) where L::Target: Logger {
+		self.build_commitment_transaction_for_funding(None, commitment_number, keys, local, generated_by_local, feerate_per_kw, logger)
+	}
+
+	/// As build_commitment_transaction, but if a (funding outpoint, channel value, our balance)
+	/// tuple is given, builds the commitment transaction spending that splice funding output
+	/// instead of the current one.
+	fn build_commitment_transaction_for_funding<L: Deref>(&self, funding: Option<(OutPoint, u64, u64)>, commitment_number: u64, keys: &TxCreationKeys, local: bool, generated_by_local: bool, feerate_per_kw: u32, logger: &L) -> (Transaction, usize, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>) where L::Target: Logger {
+		let (funding_txo, channel_value_satoshis, holder_value_msat) = match funding {
+			Some(funding) => funding,
+			None => (self.get_current_funding_txo().unwrap(), self.channel_value_satoshis, self.value_to_self_msat),
+		};
 		let obscured_commitment_transaction_number = self.get_commitment_transaction_number_obscure_factor() ^ (INITIAL_COMMITMENT_NUMBER - commitment_number);
 
 		let txins = {
 			let mut ins: Vec<TxIn> = Vec::new();
 			ins.push(TxIn {
-				previous_output: self.funding_txo.unwrap().into_bitcoin_outpoint(),
+				previous_output: funding_txo.into_bitcoin_outpoint(),
 				script_sig: Script::new(),
 				sequence: ((0x80 as u32) << 8*3) | ((obscured_commitment_transaction_number >> 3*8) as u32),
 				witness: Vec::new(),
@@ -873,10 +1157,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			($htlc: expr, $outbound: expr, $source: expr, $state_name: expr) => {
 				if $outbound == local { // "offered HTLC output"
 					let htlc_in_tx = get_htlc_in_commitment!($htlc, true);
-					if $htlc.amount_msat / 1000 >= broadcaster_dust_limit_satoshis + (feerate_per_kw as u64 * HTLC_TIMEOUT_TX_WEIGHT / 1000) {
+					if $htlc.amount_msat / 1000 >= broadcaster_dust_limit_satoshis + (feerate_per_kw as u64 * chan_utils::htlc_timeout_tx_weight(self.opt_anchors) / 1000) {
 						log_trace!(logger, "   ...including {} {} HTLC {} (hash {}) with value {}", if $outbound { "outbound" } else { "inbound" }, $state_name, $htlc.htlc_id, log_bytes!($htlc.payment_hash.0), $htlc.amount_msat);
 						txouts.push((TxOut {
-							script_pubkey: chan_utils::get_htlc_redeemscript(&htlc_in_tx, &keys).to_v0_p2wsh(),
+							script_pubkey: chan_utils::get_htlc_redeemscript(&htlc_in_tx, self.opt_anchors, &keys).to_v0_p2wsh(),
 							value: $htlc.amount_msat / 1000
 						}, Some((htlc_in_tx, $source))));
 					} else {
@@ -885,10 +1169,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 					}
 				} else {
 					let htlc_in_tx = get_htlc_in_commitment!($htlc, false);
-					if $htlc.amount_msat / 1000 >= broadcaster_dust_limit_satoshis + (feerate_per_kw as u64 * HTLC_SUCCESS_TX_WEIGHT / 1000) {
+					if $htlc.amount_msat / 1000 >= broadcaster_dust_limit_satoshis + (feerate_per_kw as u64 * chan_utils::htlc_success_tx_weight(self.opt_anchors) / 1000) {
 						log_trace!(logger, "   ...including {} {} HTLC {} (hash {}) with value {}", if $outbound { "outbound" } else { "inbound" }, $state_name, $htlc.htlc_id, log_bytes!($htlc.payment_hash.0), $htlc.amount_msat);
 						txouts.push((TxOut { // "received HTLC output"
-							script_pubkey: chan_utils::get_htlc_redeemscript(&htlc_in_tx, &keys).to_v0_p2wsh(),
+							script_pubkey: chan_utils::get_htlc_redeemscript(&htlc_in_tx, self.opt_anchors, &keys).to_v0_p2wsh(),
 							value: $htlc.amount_msat / 1000
 						}, Some((htlc_in_tx, $source))));
 					} else {
@@ -954,17 +1238,17 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		}
 
-		let value_to_self_msat: i64 = (self.value_to_self_msat - local_htlc_total_msat) as i64 + value_to_self_msat_offset;
+		let value_to_self_msat: i64 = (holder_value_msat - local_htlc_total_msat) as i64 + value_to_self_msat_offset;
 		assert!(value_to_self_msat >= 0);
 		// Note that in case they have several just-awaiting-last-RAA fulfills in-progress (ie
 		// AwaitingRemoteRevokeToRemove or AwaitingRemovedRemoteRevoke) we may have allowed them to
 		// "violate" their reserve value by couting those against it. Thus, we have to convert
 		// everything to i64 before subtracting as otherwise we can overflow.
-		let value_to_remote_msat: i64 = (self.channel_value_satoshis * 1000) as i64 - (self.value_to_self_msat as i64) - (remote_htlc_total_msat as i64) - value_to_self_msat_offset;
+		let value_to_remote_msat: i64 = (channel_value_satoshis * 1000) as i64 - (holder_value_msat as i64) - (remote_htlc_total_msat as i64) - value_to_self_msat_offset;
 		assert!(value_to_remote_msat >= 0);
 
 		#[cfg(debug_assertions)]
-		{
+		if funding.is_none() {
 			// Make sure that the to_self/to_remote is always either past the appropriate
 			// channel_reserve *or* it is making progress towards it.
 			let mut broadcaster_max_commitment_tx_output = if generated_by_local {
@@ -978,11 +1262,14 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			broadcaster_max_commitment_tx_output.1 = cmp::max(broadcaster_max_commitment_tx_output.1, value_to_remote_msat as u64);
 		}
 
-		let total_fee = feerate_per_kw as u64 * (COMMITMENT_TX_BASE_WEIGHT + (txouts.len() as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
+		let num_nondust_htlcs = txouts.len();
+		let total_fee = feerate_per_kw as u64 * (commitment_tx_base_weight(self.opt_anchors) + (num_nondust_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
+		// With anchor outputs, the funder pays for both anchors on top of the commitment fee.
+		let funder_cost = total_fee as i64 + if self.opt_anchors { ANCHOR_OUTPUT_VALUE_SATOSHI as i64 * 2 } else { 0 };
 		let (value_to_self, value_to_remote) = if self.channel_outbound {
-			(value_to_self_msat / 1000 - total_fee as i64, value_to_remote_msat / 1000)
+			(value_to_self_msat / 1000 - funder_cost, value_to_remote_msat / 1000)
 		} else {
-			(value_to_self_msat / 1000, value_to_remote_msat / 1000 - total_fee as i64)
+			(value_to_self_msat / 1000, value_to_remote_msat / 1000 - funder_cost)
 		};
 
 		let value_to_a = if local { value_to_self } else { value_to_remote };
@@ -1004,15 +1291,35 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				self.counterparty_pubkeys.as_ref().unwrap().payment_point
 			} else {
 				self.holder_keys.pubkeys().payment_point
-			}.serialize();
+			};
 			txouts.push((TxOut {
-				script_pubkey: Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0)
-				                             .push_slice(&WPubkeyHash::hash(&static_payment_pk)[..])
-				                             .into_script(),
+				script_pubkey: chan_utils::get_to_countersignatory_script_pubkey(&static_payment_pk, self.opt_anchors),
 				value: value_to_b as u64
 			}, None));
 		}
 
+		if self.opt_anchors {
+			let (broadcaster_funding_key, countersignatory_funding_key) = if local {
+				(&self.holder_keys.pubkeys().funding_pubkey, self.counterparty_funding_pubkey())
+			} else {
+				(self.counterparty_funding_pubkey(), &self.holder_keys.pubkeys().funding_pubkey)
+			};
+			// Each side gets an anchor as long as it has something at stake in the commitment
+			// transaction, see BOLT 3.
+			if value_to_a >= (broadcaster_dust_limit_satoshis as i64) || num_nondust_htlcs != 0 {
+				txouts.push((TxOut {
+					script_pubkey: chan_utils::get_anchor_redeemscript(broadcaster_funding_key).to_v0_p2wsh(),
+					value: ANCHOR_OUTPUT_VALUE_SATOSHI,
+				}, None));
+			}
+			if value_to_b >= (broadcaster_dust_limit_satoshis as i64) || num_nondust_htlcs != 0 {
+				txouts.push((TxOut {
+					script_pubkey: chan_utils::get_anchor_redeemscript(countersignatory_funding_key).to_v0_p2wsh(),
+					value: ANCHOR_OUTPUT_VALUE_SATOSHI,
+				}, None));
+			}
+		}
+
 		transaction_utils::sort_outputs(&mut txouts, |a, b| {
 			if let &Some(ref a_htlc) = a {
 				if let &Some(ref b_htlc) = b {
@@ -1049,6 +1356,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	#[inline]
 	fn get_closing_scriptpubkey(&self) -> Script {
+		if let Some(ref script) = self.holder_shutdown_scriptpubkey {
+			return script.clone();
+		}
 		let channel_close_key_hash = WPubkeyHash::hash(&self.shutdown_pubkey.serialize());
 		Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&channel_close_key_hash[..]).into_script()
 	}
@@ -1063,7 +1373,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let txins = {
 			let mut ins: Vec<TxIn> = Vec::new();
 			ins.push(TxIn {
-				previous_output: self.funding_txo.unwrap().into_bitcoin_outpoint(),
+				previous_output: self.get_current_funding_txo().unwrap().into_bitcoin_outpoint(),
 				script_sig: Script::new(),
 				sequence: 0xffffffff,
 				witness: Vec::new(),
@@ -1134,6 +1444,35 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	#[inline]
 	/// Creates a set of keys for build_commitment_transaction to generate a transaction which we
 	/// will sign and send to our counterparty.
+	pub fn get_open_channel_v2(&self, chain_hash: BlockHash) -> msgs::OpenChannelV2 {
+		let (funding_feerate_perkw, locktime) = match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingAcceptChannel, funding_feerate_per_kw, locktime, .. }) => (funding_feerate_per_kw, locktime),
+			_ => panic!("Cannot generate an open_channel2 for a channel which isn't dual-funded or has moved forward"),
+		};
+		let msg = self.get_open_channel(chain_hash);
+		msgs::OpenChannelV2 {
+			chain_hash,
+			temporary_channel_id: msg.temporary_channel_id,
+			funding_feerate_perkw,
+			commitment_feerate_perkw: msg.feerate_per_kw,
+			funding_satoshis: msg.funding_satoshis,
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			locktime,
+			funding_pubkey: msg.funding_pubkey,
+			revocation_basepoint: msg.revocation_basepoint,
+			payment_point: msg.payment_point,
+			delayed_payment_basepoint: msg.delayed_payment_basepoint,
+			htlc_basepoint: msg.htlc_basepoint,
+			first_per_commitment_point: msg.first_per_commitment_point,
+			channel_flags: msg.channel_flags,
+			shutdown_scriptpubkey: msg.shutdown_scriptpubkey,
+		}
+	}
+
 	/// If an Err is returned, it is a ChannelError::Close (for get_outbound_funding_created)
 	fn build_remote_transaction_keys(&self) -> Result<TxCreationKeys, ChannelError> {
 		//TODO: Ensure that the payment_key derived here ends up in the library users' wallet as we
@@ -1156,7 +1495,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// @local is used only to convert relevant internal structures which refer to remote vs local
 	/// to decide value of outputs and direction of HTLCs.
 	fn build_htlc_transaction(&self, prev_hash: &Txid, htlc: &HTLCOutputInCommitment, local: bool, keys: &TxCreationKeys, feerate_per_kw: u32) -> Transaction {
-		chan_utils::build_htlc_transaction(prev_hash, feerate_per_kw, if local { self.counterparty_selected_contest_delay } else { self.holder_selected_contest_delay }, htlc, &keys.broadcaster_delayed_payment_key, &keys.revocation_key)
+		chan_utils::build_htlc_transaction(prev_hash, feerate_per_kw, if local { self.counterparty_selected_contest_delay } else { self.holder_selected_contest_delay }, htlc, self.opt_anchors, &keys.broadcaster_delayed_payment_key, &keys.revocation_key)
 	}
 
 	/// Per HTLC, only one get_update_fail_htlc or get_update_fulfill_htlc call may be made.
@@ -1287,6 +1626,11 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 	}
 
+	/// Gets the amount, in msat, of the inbound HTLC with the given id, if we still have it.
+	pub fn get_inbound_htlc_value_msat(&self, htlc_id: u64) -> Option<u64> {
+		self.pending_inbound_htlcs.iter().find(|htlc| htlc.htlc_id == htlc_id).map(|htlc| htlc.amount_msat)
+	}
+
 	/// Per HTLC, only one get_update_fail_htlc or get_update_fulfill_htlc call may be made.
 	/// In such cases we debug_assert!(false) and return a ChannelError::Ignore. Thus, will always
 	/// return Ok(_) if debug assertions are turned on or preconditions are met.
@@ -1365,6 +1709,13 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	// Message handlers:
 
 	pub fn accept_channel(&mut self, msg: &msgs::AcceptChannel, config: &UserConfig, their_features: InitFeatures) -> Result<(), ChannelError> {
+		if self.pending_dual_funding.is_some() {
+			return Err(ChannelError::Close("Got an accept_channel message for a dual-funded channel".to_owned()));
+		}
+		self.check_accept_channel(msg, config, their_features)
+	}
+
+	fn check_accept_channel(&mut self, msg: &msgs::AcceptChannel, config: &UserConfig, their_features: InitFeatures) -> Result<(), ChannelError> {
 		// Check sanity of message fields:
 		if !self.channel_outbound {
 			return Err(ChannelError::Close("Got an accept_channel message from an inbound peer".to_owned()));
@@ -1430,7 +1781,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			match &msg.shutdown_scriptpubkey {
 				&OptionalField::Present(ref script) => {
 					// Peer is signaling upfront_shutdown and has provided a non-accepted scriptpubkey format. We enforce it while receiving shutdown msg
-					if script.is_p2pkh() || script.is_p2sh() || script.is_v0_p2wsh() || script.is_v0_p2wpkh() {
+					if is_valid_shutdown_script(script) {
 						Some(script.clone())
 					// Peer is signaling upfront_shutdown and has opt-out with a 0-length script. We don't enforce anything
 					} else if script.len() == 0 {
@@ -1463,7 +1814,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			htlc_basepoint: msg.htlc_basepoint
 		};
 
-		self.holder_keys.on_accept(&counterparty_pubkeys, msg.to_self_delay, self.holder_selected_contest_delay);
+		self.opt_anchors = config.own_channel_config.negotiate_anchor_outputs && their_features.supports_anchor_outputs();
+		self.holder_keys.on_accept(&counterparty_pubkeys, msg.to_self_delay, self.holder_selected_contest_delay, self.opt_anchors);
 		self.counterparty_pubkeys = Some(counterparty_pubkeys);
 
 		self.counterparty_cur_commitment_point = Some(msg.first_per_commitment_point);
@@ -1485,7 +1837,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		log_trace!(logger, "Checking funding_created tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(sig.serialize_compact()[..]), log_bytes!(self.counterparty_funding_pubkey().serialize()), encode::serialize_hex(&initial_commitment_tx), log_bytes!(sighash[..]), encode::serialize_hex(&funding_script));
 		secp_check!(self.secp_ctx.verify(&sighash, &sig, self.counterparty_funding_pubkey()), "Invalid funding_created signature from peer".to_owned());
 
-		let tx = HolderCommitmentTransaction::new_missing_holder_sig(initial_commitment_tx, sig.clone(), &self.holder_keys.pubkeys().funding_pubkey, self.counterparty_funding_pubkey(), keys, self.feerate_per_kw, Vec::new());
+		let tx = HolderCommitmentTransaction::new_missing_holder_sig(initial_commitment_tx, sig.clone(), &self.holder_keys.pubkeys().funding_pubkey, self.counterparty_funding_pubkey(), keys, self.feerate_per_kw, self.opt_anchors, Vec::new());
 
 		let counterparty_keys = self.build_remote_transaction_keys()?;
 		let counterparty_initial_commitment_tx = self.build_commitment_transaction(self.cur_counterparty_commitment_transaction_number, &counterparty_keys, false, false, self.feerate_per_kw, logger).0;
@@ -1501,10 +1853,13 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		&self.counterparty_pubkeys.as_ref().expect("funding_pubkey() only allowed after accept_channel").funding_pubkey
 	}
 
-	pub fn funding_created<L: Deref>(&mut self, msg: &msgs::FundingCreated, logger: &L) -> Result<(msgs::FundingSigned, ChannelMonitor<ChanSigner>), ChannelError> where L::Target: Logger {
+	pub fn funding_created<L: Deref>(&mut self, msg: &msgs::FundingCreated, best_block: (BlockHash, u32), logger: &L) -> Result<(msgs::FundingSigned, ChannelMonitor<ChanSigner>), ChannelError> where L::Target: Logger {
 		if self.channel_outbound {
 			return Err(ChannelError::Close("Received funding_created for an outbound channel?".to_owned()));
 		}
+		if self.pending_dual_funding.is_some() {
+			return Err(ChannelError::Close("Received funding_created for a dual-funded channel".to_owned()));
+		}
 		if self.channel_state != (ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32) {
 			// BOLT 2 says that if we disconnect before we send funding_signed we SHOULD NOT
 			// remember the channel, so it's safe to just send an error_message here and drop the
@@ -1537,11 +1892,11 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			() => { {
 				let mut channel_monitor = ChannelMonitor::new(self.holder_keys.clone(),
 				                                              &self.shutdown_pubkey, self.holder_selected_contest_delay,
-				                                              &self.destination_script, (funding_txo, funding_txo_script.clone()),
+				                                              &self.destination_script, (funding_txo, funding_txo_script.clone()), funding_txo.to_channel_id(),
 				                                              &counterparty_pubkeys.htlc_basepoint, &counterparty_pubkeys.delayed_payment_basepoint,
 				                                              self.counterparty_selected_contest_delay, funding_redeemscript.clone(), self.channel_value_satoshis,
 				                                              self.get_commitment_transaction_number_obscure_factor(),
-				                                              initial_commitment_tx.clone());
+				                                              initial_commitment_tx.clone(), best_block);
 
 				channel_monitor.provide_latest_counterparty_commitment_tx_info(&counterparty_initial_commitment_tx, Vec::new(), self.cur_counterparty_commitment_transaction_number, self.counterparty_cur_commitment_point.unwrap(), logger);
 				channel_monitor
@@ -1554,6 +1909,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.channel_id = funding_txo.to_channel_id();
 		self.cur_counterparty_commitment_transaction_number -= 1;
 		self.cur_holder_commitment_transaction_number -= 1;
+		self.funding_created_height = Some(best_block.1);
 
 		Ok((msgs::FundingSigned {
 			channel_id: self.channel_id,
@@ -1563,7 +1919,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	/// Handles a funding_signed message from the remote end.
 	/// If this call is successful, broadcast the funding transaction (and not before!)
-	pub fn funding_signed<L: Deref>(&mut self, msg: &msgs::FundingSigned, logger: &L) -> Result<ChannelMonitor<ChanSigner>, ChannelError> where L::Target: Logger {
+	pub fn funding_signed<L: Deref>(&mut self, msg: &msgs::FundingSigned, best_block: (BlockHash, u32), logger: &L) -> Result<ChannelMonitor<ChanSigner>, ChannelError> where L::Target: Logger {
 		if !self.channel_outbound {
 			return Err(ChannelError::Close("Received funding_signed for an inbound channel?".to_owned()));
 		}
@@ -1598,14 +1954,14 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let funding_txo_script = funding_redeemscript.to_v0_p2wsh();
 		macro_rules! create_monitor {
 			() => { {
-				let commitment_tx = HolderCommitmentTransaction::new_missing_holder_sig(initial_commitment_tx.clone(), msg.signature.clone(), &self.holder_keys.pubkeys().funding_pubkey, counterparty_funding_pubkey, holder_keys.clone(), self.feerate_per_kw, Vec::new());
+				let commitment_tx = HolderCommitmentTransaction::new_missing_holder_sig(initial_commitment_tx.clone(), msg.signature.clone(), &self.holder_keys.pubkeys().funding_pubkey, counterparty_funding_pubkey, holder_keys.clone(), self.feerate_per_kw, self.opt_anchors, Vec::new());
 				let mut channel_monitor = ChannelMonitor::new(self.holder_keys.clone(),
 				                                              &self.shutdown_pubkey, self.holder_selected_contest_delay,
-				                                              &self.destination_script, (funding_txo.clone(), funding_txo_script.clone()),
+				                                              &self.destination_script, (funding_txo.clone(), funding_txo_script.clone()), self.channel_id,
 				                                              &counterparty_pubkeys.htlc_basepoint, &counterparty_pubkeys.delayed_payment_basepoint,
 				                                              self.counterparty_selected_contest_delay, funding_redeemscript.clone(), self.channel_value_satoshis,
 				                                              self.get_commitment_transaction_number_obscure_factor(),
-				                                              commitment_tx);
+				                                              commitment_tx, best_block);
 
 				channel_monitor.provide_latest_counterparty_commitment_tx_info(&counterparty_initial_commitment_tx, Vec::new(), self.cur_counterparty_commitment_transaction_number, self.counterparty_cur_commitment_point.unwrap(), logger);
 
@@ -1696,10 +2052,12 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	// Get the fee cost of a commitment tx with a given number of HTLC outputs.
 	// Note that num_htlcs should not include dust HTLCs.
+	// With anchor outputs, this includes the value of the two anchors, which the funder pays for.
 	fn commit_tx_fee_msat(&self, num_htlcs: usize) -> u64 {
 		// Note that we need to divide before multiplying to round properly,
 		// since the lowest denomination of bitcoin on-chain is the satoshi.
-		(COMMITMENT_TX_BASE_WEIGHT + num_htlcs as u64 * COMMITMENT_TX_WEIGHT_PER_HTLC) * self.feerate_per_kw as u64 / 1000 * 1000
+		let anchors_msat = if self.opt_anchors { ANCHOR_OUTPUT_VALUE_SATOSHI * 2 * 1000 } else { 0 };
+		(commitment_tx_base_weight(self.opt_anchors) + num_htlcs as u64 * COMMITMENT_TX_WEIGHT_PER_HTLC) * self.feerate_per_kw as u64 / 1000 * 1000 + anchors_msat
 	}
 
 	// Get the commitment tx fee for the local (i.e our) next commitment transaction
@@ -1763,6 +2121,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	pub fn update_add_htlc<F, L: Deref>(&mut self, msg: &msgs::UpdateAddHTLC, mut pending_forward_status: PendingHTLCStatus, create_pending_htlc_status: F, logger: &L) -> Result<(), ChannelError>
 	where F: for<'a> Fn(&'a Self, PendingHTLCStatus, u16) -> PendingHTLCStatus, L::Target: Logger {
+		if self.is_quiescent() {
+			return Err(ChannelError::Close("Peer sent update_add_htlc while the channel was quiescent".to_owned()));
+		}
 		// We can't accept HTLCs sent after we've sent a shutdown.
 		let local_sent_shutdown = (self.channel_state & (ChannelState::ChannelFunded as u32 | ChannelState::LocalShutdownSent as u32)) != (ChannelState::ChannelFunded as u32);
 		if local_sent_shutdown {
@@ -1817,8 +2178,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 		let pending_value_to_self_msat =
 			self.value_to_self_msat + htlc_inbound_value_msat - removed_outbound_total_msat;
+		// The HTLC also has to fit on top of their balance on the funding output of any splice
+		// which is locking.
 		let pending_remote_value_msat =
-			self.channel_value_satoshis * 1000 - pending_value_to_self_msat;
+			(self.channel_value_satoshis * 1000 - pending_value_to_self_msat).saturating_sub(self.get_locking_splice_balance_reductions_msat().1);
 		if pending_remote_value_msat < msg.amount_msat {
 			return Err(ChannelError::Close("Remote HTLC add would overdraw remaining funds".to_owned()));
 		}
@@ -1890,7 +2253,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	/// Marks an outbound HTLC which we have received update_fail/fulfill/malformed
 	#[inline]
-	fn mark_outbound_htlc_removed(&mut self, htlc_id: u64, check_preimage: Option<PaymentHash>, fail_reason: Option<HTLCFailReason>) -> Result<&HTLCSource, ChannelError> {
+	fn mark_outbound_htlc_removed(&mut self, htlc_id: u64, check_preimage: Option<PaymentHash>, fail_reason: Option<HTLCFailReason>) -> Result<&OutboundHTLCOutput, ChannelError> {
 		for htlc in self.pending_outbound_htlcs.iter_mut() {
 			if htlc.htlc_id == htlc_id {
 				match check_preimage {
@@ -1909,22 +2272,27 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 					OutboundHTLCState::AwaitingRemoteRevokeToRemove(_) | OutboundHTLCState::AwaitingRemovedRemoteRevoke(_) | OutboundHTLCState::RemoteRemoved(_) =>
 						return Err(ChannelError::Close(format!("Remote tried to fulfill/fail HTLC ({}) that they'd already fulfilled/failed", htlc_id))),
 				}
-				return Ok(&htlc.source);
+				return Ok(htlc);
 			}
 		}
 		Err(ChannelError::Close("Remote tried to fulfill/fail an HTLC we couldn't find".to_owned()))
 	}
 
-	pub fn update_fulfill_htlc(&mut self, msg: &msgs::UpdateFulfillHTLC) -> Result<HTLCSource, ChannelError> {
+	/// Handles an update_fulfill_htlc from our counterparty, returning the source of the HTLC and
+	/// its amount in msat.
+	pub fn update_fulfill_htlc(&mut self, msg: &msgs::UpdateFulfillHTLC) -> Result<(HTLCSource, u64), ChannelError> {
 		if (self.channel_state & (ChannelState::ChannelFunded as u32)) != (ChannelState::ChannelFunded as u32) {
 			return Err(ChannelError::Close("Got fulfill HTLC message when channel was not in an operational state".to_owned()));
 		}
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fulfill_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		if self.is_quiescent() {
+			return Err(ChannelError::Close("Peer sent update_fulfill_htlc while the channel was quiescent".to_owned()));
+		}
 
 		let payment_hash = PaymentHash(Sha256::hash(&msg.payment_preimage.0[..]).into_inner());
-		self.mark_outbound_htlc_removed(msg.htlc_id, Some(payment_hash), None).map(|source| source.clone())
+		self.mark_outbound_htlc_removed(msg.htlc_id, Some(payment_hash), None).map(|htlc| (htlc.source.clone(), htlc.amount_msat))
 	}
 
 	pub fn update_fail_htlc(&mut self, msg: &msgs::UpdateFailHTLC, fail_reason: HTLCFailReason) -> Result<(), ChannelError> {
@@ -1934,6 +2302,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fail_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		if self.is_quiescent() {
+			return Err(ChannelError::Close("Peer sent update_fail_htlc while the channel was quiescent".to_owned()));
+		}
 
 		self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?;
 		Ok(())
@@ -1946,6 +2317,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fail_malformed_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		if self.is_quiescent() {
+			return Err(ChannelError::Close("Peer sent update_fail_malformed_htlc while the channel was quiescent".to_owned()));
+		}
 
 		self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?;
 		Ok(())
@@ -1961,10 +2335,30 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err((None, ChannelError::Close("Peer sent commitment_signed when we needed a channel_reestablish".to_owned())));
 		}
+		if self.is_quiescent() {
+			return Err((None, ChannelError::Close("Peer sent commitment_signed while the channel was quiescent".to_owned())));
+		}
 		if self.channel_state & BOTH_SIDES_SHUTDOWN_MASK == BOTH_SIDES_SHUTDOWN_MASK && self.last_sent_closing_fee.is_some() {
 			return Err((None, ChannelError::Close("Peer sent commitment_signed after we'd started exchanging closing_signeds".to_owned())));
 		}
 
+		// If a splice locked just before our counterparty saw our splice_locked, it signed our
+		// commitment transaction spending the new funding output as the splice's, and the other
+		// one spends an output which is no longer ours.
+		let spliced_msg;
+		let msg = match msg.splice_signatures {
+			Some(ref splice_signatures) if self.locking_splice.is_none() && self.spliced_funding_txo.is_some() => {
+				spliced_msg = msgs::CommitmentSigned {
+					channel_id: msg.channel_id,
+					signature: splice_signatures.signature,
+					htlc_signatures: splice_signatures.htlc_signatures.clone(),
+					splice_signatures: None,
+				};
+				&spliced_msg
+			},
+			_ => msg,
+		};
+
 		let funding_script = self.get_funding_redeemscript();
 
 		let keys = self.build_holder_transaction_keys(self.cur_holder_commitment_transaction_number).map_err(|e| (None, e))?;
@@ -1992,10 +2386,12 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		//If channel fee was updated by funder confirm funder can afford the new fee rate when applied to the current local commitment transaction
 		if update_fee {
 			let num_htlcs = commitment_tx.1;
-			let total_fee = feerate_per_kw as u64 * (COMMITMENT_TX_BASE_WEIGHT + (num_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
+			let total_fee = feerate_per_kw as u64 * (commitment_tx_base_weight(self.opt_anchors) + (num_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
+			// The funder also pays for both anchor outputs.
+			let funder_cost = total_fee + if self.opt_anchors { ANCHOR_OUTPUT_VALUE_SATOSHI * 2 } else { 0 };
 
 			let counterparty_reserve_we_require = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(self.channel_value_satoshis);
-			if self.channel_value_satoshis - self.value_to_self_msat / 1000 < total_fee + counterparty_reserve_we_require {
+			if self.channel_value_satoshis - self.value_to_self_msat / 1000 < funder_cost + counterparty_reserve_we_require {
 				return Err((None, ChannelError::Close("Funding remote cannot afford proposed new fee".to_owned())));
 			}
 		}
@@ -2011,8 +2407,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		for (idx, (htlc, source)) in commitment_tx.2.drain(..).enumerate() {
 			if let Some(_) = htlc.transaction_output_index {
 				let htlc_tx = self.build_htlc_transaction(&commitment_txid, &htlc, true, &keys, feerate_per_kw);
-				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys);
-				let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, SigHashType::All)[..]);
+				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, self.opt_anchors, &keys);
+				let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, chan_utils::htlc_counterparty_sighash_type(self.opt_anchors))[..]);
 				log_trace!(logger, "Checking HTLC tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(msg.htlc_signatures[idx].serialize_compact()[..]), log_bytes!(keys.countersignatory_htlc_key.serialize()), encode::serialize_hex(&htlc_tx), log_bytes!(htlc_sighash[..]), encode::serialize_hex(&htlc_redeemscript));
 				if let Err(_) = self.secp_ctx.verify(&htlc_sighash, &msg.htlc_signatures[idx], &keys.countersignatory_htlc_key) {
 					return Err((None, ChannelError::Close("Invalid HTLC tx signature from peer".to_owned())));
@@ -2025,6 +2421,14 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		}
 
+		let splice_update = match self.get_locking_splice_funding() {
+			Some(funding) => match msg.splice_signatures {
+				Some(ref splice_signatures) => Some(self.check_splice_commitment_signatures(funding, splice_signatures, &keys, feerate_per_kw, logger).map_err(|e| (None, e))?),
+				None => return Err((None, ChannelError::Close("Peer sent commitment_signed without signing our commitment transaction for the locking splice".to_owned()))),
+			},
+			None => None,
+		};
+
 		let next_per_commitment_point = self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number - 1, &self.secp_ctx);
 		let per_commitment_secret = self.holder_keys.release_commitment_secret(self.cur_holder_commitment_transaction_number + 1);
 
@@ -2049,10 +2453,13 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let mut monitor_update = ChannelMonitorUpdate {
 			update_id: self.latest_monitor_update_id,
 			updates: vec![ChannelMonitorUpdateStep::LatestHolderCommitmentTXInfo {
-				commitment_tx: HolderCommitmentTransaction::new_missing_holder_sig(commitment_tx.0, msg.signature.clone(), &self.holder_keys.pubkeys().funding_pubkey, &counterparty_funding_pubkey, keys, self.feerate_per_kw, htlcs_without_source),
+				commitment_tx: HolderCommitmentTransaction::new_missing_holder_sig(commitment_tx.0, msg.signature.clone(), &self.holder_keys.pubkeys().funding_pubkey, &counterparty_funding_pubkey, keys, self.feerate_per_kw, self.opt_anchors, htlcs_without_source),
 				htlc_outputs: htlcs_and_sigs
 			}]
 		};
+		if let Some(splice_update) = splice_update {
+			monitor_update.updates.push(splice_update);
+		}
 
 		for htlc in self.pending_inbound_htlcs.iter_mut() {
 			let new_forward = if let &InboundHTLCState::RemoteAnnounced(ref forward_info) = &htlc.state {
@@ -2118,6 +2525,42 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}, commitment_signed, closing_signed, monitor_update))
 	}
 
+	/// Checks our counterparty's signatures on our commitment transaction spending the given
+	/// funding output of the splice we're waiting on to lock, returning the ChannelMonitor update
+	/// step tracking it.
+	fn check_splice_commitment_signatures<L: Deref>(&self, funding: (OutPoint, u64, u64), signatures: &msgs::SpliceCommitmentSignatures, keys: &TxCreationKeys, feerate_per_kw: u32, logger: &L) -> Result<ChannelMonitorUpdateStep, ChannelError> where L::Target: Logger {
+		let (commitment_tx, num_htlcs, htlcs) = self.build_commitment_transaction_for_funding(Some(funding), self.cur_holder_commitment_transaction_number, keys, true, false, feerate_per_kw, logger);
+		let commitment_txid = commitment_tx.txid();
+		let sighash = hash_to_message!(&bip143::SigHashCache::new(&commitment_tx).signature_hash(0, &self.get_funding_redeemscript(), funding.1, SigHashType::All)[..]);
+		secp_check!(self.secp_ctx.verify(&sighash, &signatures.signature, self.counterparty_funding_pubkey()), "Invalid splice commitment tx signature from peer".to_owned());
+		if signatures.htlc_signatures.len() != num_htlcs {
+			return Err(ChannelError::Close(format!("Got wrong number of HTLC signatures ({}) for a splice commitment transaction from remote. It must be {}", signatures.htlc_signatures.len(), num_htlcs)));
+		}
+
+		let mut htlcs_without_source = Vec::with_capacity(htlcs.len());
+		let mut htlcs_and_sigs = Vec::with_capacity(htlcs.len());
+		for (idx, (htlc, source)) in htlcs.into_iter().enumerate() {
+			if let Some(_) = htlc.transaction_output_index {
+				let htlc_tx = self.build_htlc_transaction(&commitment_txid, &htlc, true, keys, feerate_per_kw);
+				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, self.opt_anchors, keys);
+				let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, chan_utils::htlc_counterparty_sighash_type(self.opt_anchors))[..]);
+				secp_check!(self.secp_ctx.verify(&htlc_sighash, &signatures.htlc_signatures[idx], &keys.countersignatory_htlc_key), "Invalid splice HTLC tx signature from peer".to_owned());
+				htlcs_without_source.push((htlc.clone(), Some(signatures.htlc_signatures[idx])));
+				htlcs_and_sigs.push((htlc, Some(signatures.htlc_signatures[idx]), source.cloned()));
+			} else {
+				htlcs_without_source.push((htlc.clone(), None));
+				htlcs_and_sigs.push((htlc, None, source.cloned()));
+			}
+		}
+
+		Ok(ChannelMonitorUpdateStep::SpliceFundingSigned {
+			funding_outpoint: funding.0,
+			channel_value_satoshis: funding.1,
+			commitment_tx: HolderCommitmentTransaction::new_missing_holder_sig(commitment_tx, signatures.signature, &self.holder_keys.pubkeys().funding_pubkey, self.counterparty_funding_pubkey(), keys.clone(), feerate_per_kw, self.opt_anchors, htlcs_without_source),
+			htlc_outputs: htlcs_and_sigs,
+		})
+	}
+
 	/// Used to fulfill holding_cell_htlcs when we get a remote ack (or implicitly get it by them
 	/// fulfilling or failing the last pending HTLC)
 	fn free_holding_cell_htlcs<L: Deref>(&mut self, logger: &L) -> Result<(Option<(msgs::CommitmentUpdate, ChannelMonitorUpdate)>, Vec<(HTLCSource, PaymentHash)>), ChannelError> where L::Target: Logger {
@@ -2506,6 +2949,12 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		// Upon reconnect we have to start the closing_signed dance over, but shutdown messages
 		// will be retransmitted.
 		self.last_sent_closing_fee = None;
+		// Splice negotiation has to start over too, unless we've already exchanged commitment_signed
+		// for the splice transaction, in which case only the tx_signatures remain to be exchanged.
+		match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { .. }, .. }) => {},
+			_ => self.pending_splice = None,
+		}
 
 		let mut inbound_drop_count = 0;
 		self.pending_inbound_htlcs.retain(|htlc| {
@@ -2643,6 +3092,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fee when we needed a channel_reestablish".to_owned()));
 		}
+		if self.is_quiescent() {
+			return Err(ChannelError::Close("Peer sent update_fee while the channel was quiescent".to_owned()));
+		}
 		Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.feerate_per_kw)?;
 		self.pending_update_fee = Some(msg.feerate_per_kw);
 		self.update_time_counter += 1;
@@ -2881,7 +3333,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			proposed_feerate = self.feerate_per_kw;
 		}
 		let tx_weight = Self::get_closing_transaction_weight(&self.get_closing_scriptpubkey(), self.counterparty_shutdown_scriptpubkey.as_ref().unwrap());
-		let proposed_total_fee_satoshis = proposed_feerate as u64 * tx_weight / 1000;
+		let (min_fee_satoshis, max_fee_satoshis) = self.get_closing_fee_range(fee_estimator);
+		let proposed_total_fee_satoshis = cmp::min(cmp::max(proposed_feerate as u64 * tx_weight / 1000, min_fee_satoshis), max_fee_satoshis);
 
 		let (closing_tx, total_fee_satoshis) = self.build_closing_transaction(proposed_total_fee_satoshis, false);
 		let sig = self.holder_keys
@@ -2889,20 +3342,55 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			.ok();
 		if sig.is_none() { return None; }
 
-		self.last_sent_closing_fee = Some((proposed_feerate, total_fee_satoshis, sig.clone().unwrap()));
+		self.last_sent_closing_fee = Some(((total_fee_satoshis * 1000 / tx_weight) as u32, total_fee_satoshis, sig.clone().unwrap()));
 		Some(msgs::ClosingSigned {
 			channel_id: self.channel_id,
 			fee_satoshis: total_fee_satoshis,
 			signature: sig.unwrap(),
+			fee_range: Some(Self::closing_fee_range_including(min_fee_satoshis, max_fee_satoshis, total_fee_satoshis)),
 		})
 	}
 
+	/// Gets the minimum and maximum fees we're willing to place on the closing transaction: the
+	/// limits given to get_shutdown, if any, or otherwise a range based on our fee estimates (with
+	/// no real upper bound if we aren't the funder, as we aren't paying the fee).
+	fn get_closing_fee_range<F: Deref>(&self, fee_estimator: &F) -> (u64, u64)
+		where F::Target: FeeEstimator
+	{
+		if let Some(ref limits) = self.closing_fee_limits {
+			return (limits.min_fee_satoshis, limits.max_fee_satoshis);
+		}
+		let tx_weight = Self::get_closing_transaction_weight(&self.get_closing_scriptpubkey(), self.counterparty_shutdown_scriptpubkey.as_ref().unwrap());
+		let min_fee_satoshis = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Background) as u64 * tx_weight / 1000;
+		let max_fee_satoshis = if self.channel_outbound {
+			cmp::max(fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal), self.feerate_per_kw) as u64 * tx_weight / 1000
+		} else {
+			self.channel_value_satoshis
+		};
+		(min_fee_satoshis, cmp::max(min_fee_satoshis, max_fee_satoshis))
+	}
+
+	/// Gets the fee_range to send along with a closing_signed, which must include the fee we're
+	/// proposing even if building the closing transaction moved it out of our range.
+	fn closing_fee_range_including(min_fee_satoshis: u64, max_fee_satoshis: u64, fee_satoshis: u64) -> msgs::ClosingSignedFeeRange {
+		msgs::ClosingSignedFeeRange {
+			min_fee_satoshis: cmp::min(min_fee_satoshis, fee_satoshis),
+			max_fee_satoshis: cmp::max(max_fee_satoshis, fee_satoshis),
+		}
+	}
+
 	pub fn shutdown<F: Deref>(&mut self, fee_estimator: &F, msg: &msgs::Shutdown) -> Result<(Option<msgs::Shutdown>, Option<msgs::ClosingSigned>, Vec<(HTLCSource, PaymentHash)>), ChannelError>
 		where F::Target: FeeEstimator
 	{
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent shutdown when we needed a channel_reestablish".to_owned()));
 		}
+		if self.is_quiescent() {
+			return Err(ChannelError::Close("Peer sent shutdown while the channel was quiescent".to_owned()));
+		}
+		if self.locking_splice.is_some() {
+			return Err(ChannelError::Close("Peer sent shutdown while a splice was locking".to_owned()));
+		}
 		if self.channel_state < ChannelState::FundingSent as u32 {
 			// Spec says we should fail the connection, not the channel, but that's nonsense, there
 			// are plenty of reasons you may want to fail a channel pre-funding, and spec says you
@@ -2917,13 +3405,13 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		assert_eq!(self.channel_state & ChannelState::ShutdownComplete as u32, 0);
 
 		// BOLT 2 says we must only send a scriptpubkey of certain standard forms, which are up to
-		// 34 bytes in length, so don't let the remote peer feed us some super fee-heavy script.
-		if self.channel_outbound && msg.scriptpubkey.len() > 34 {
+		// 42 bytes in length, so don't let the remote peer feed us some super fee-heavy script.
+		if self.channel_outbound && msg.scriptpubkey.len() > 42 {
 			return Err(ChannelError::Close(format!("Got counterparty shutdown_scriptpubkey ({}) of absurd length from remote peer", msg.scriptpubkey.to_bytes().to_hex())));
 		}
 
 		//Check counterparty_shutdown_scriptpubkey form as BOLT says we must
-		if !msg.scriptpubkey.is_p2pkh() && !msg.scriptpubkey.is_p2sh() && !msg.scriptpubkey.is_v0_p2wpkh() && !msg.scriptpubkey.is_v0_p2wsh() {
+		if !is_valid_shutdown_script(&msg.scriptpubkey) {
 			return Err(ChannelError::Close(format!("Got a nonstandard scriptpubkey ({}) from remote peer", msg.scriptpubkey.to_bytes().to_hex())));
 		}
 
@@ -3040,42 +3528,63 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		}
 
-		macro_rules! propose_new_feerate {
-			($new_feerate: expr) => {
+		let (our_min_fee, our_max_fee) = self.get_closing_fee_range(fee_estimator);
+
+		macro_rules! propose_new_fee {
+			($new_fee: expr) => {
 				let closing_tx_max_weight = Self::get_closing_transaction_weight(&self.get_closing_scriptpubkey(), self.counterparty_shutdown_scriptpubkey.as_ref().unwrap());
-				let (closing_tx, used_total_fee) = self.build_closing_transaction($new_feerate as u64 * closing_tx_max_weight / 1000, false);
+				let (closing_tx, used_total_fee) = self.build_closing_transaction($new_fee, false);
 				let sig = self.holder_keys
 					.sign_closing_transaction(&closing_tx, &self.secp_ctx)
 					.map_err(|_| ChannelError::Close("External signer refused to sign closing transaction".to_owned()))?;
-				self.last_sent_closing_fee = Some(($new_feerate, used_total_fee, sig.clone()));
+				self.last_sent_closing_fee = Some(((used_total_fee * 1000 / closing_tx_max_weight) as u32, used_total_fee, sig.clone()));
 				return Ok((Some(msgs::ClosingSigned {
 					channel_id: self.channel_id,
 					fee_satoshis: used_total_fee,
 					signature: sig,
+					fee_range: Some(Self::closing_fee_range_including(our_min_fee, our_max_fee, used_total_fee)),
 				}), None))
 			}
 		}
 
-		let proposed_sat_per_kw = msg.fee_satoshis  * 1000 / closing_tx.get_weight() as u64;
-		if self.channel_outbound {
-			let max_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
-			if (proposed_sat_per_kw as u32) > max_feerate {
-				if let Some((last_feerate, _, _)) = self.last_sent_closing_fee {
-					if max_feerate <= last_feerate {
-						return Err(ChannelError::Close(format!("Unable to come to consensus about closing feerate, remote wanted something higher ({}) than our Normal feerate ({})", last_feerate, max_feerate)));
+		if let Some(ref fee_range) = msg.fee_range {
+			// The peer supports the fee_range negotiation: the fee must be in the overlap of both
+			// ranges, with the non-funder picking a fee in the overlap if the funder's wasn't in it
+			// and the funder then having to accept it.
+			if msg.fee_satoshis < fee_range.min_fee_satoshis || msg.fee_satoshis > fee_range.max_fee_satoshis {
+				return Err(ChannelError::Close(format!("Remote sent us a closing_signed with a fee ({}) outside of its own fee_range ({}-{})", msg.fee_satoshis, fee_range.min_fee_satoshis, fee_range.max_fee_satoshis)));
+			}
+			let overlap_min_fee = cmp::max(fee_range.min_fee_satoshis, our_min_fee);
+			let overlap_max_fee = cmp::min(fee_range.max_fee_satoshis, our_max_fee);
+			if overlap_min_fee > overlap_max_fee {
+				return Err(ChannelError::Close(format!("Unable to come to consensus about closing fee, remote's fee_range ({}-{}) doesn't overlap with ours ({}-{})", fee_range.min_fee_satoshis, fee_range.max_fee_satoshis, our_min_fee, our_max_fee)));
+			}
+			if !self.channel_outbound && self.last_sent_closing_fee.is_some() {
+				return Err(ChannelError::Close(format!("Remote sent us a closing_signed with a fee ({}) other than the one we proposed", msg.fee_satoshis)));
+			}
+			if msg.fee_satoshis < overlap_min_fee || msg.fee_satoshis > overlap_max_fee {
+				if self.channel_outbound {
+					return Err(ChannelError::Close(format!("Remote sent us a closing_signed with a fee ({}) outside of our fee range ({}-{})", msg.fee_satoshis, our_min_fee, our_max_fee)));
+				}
+				propose_new_fee!(cmp::min(cmp::max(msg.fee_satoshis, overlap_min_fee), overlap_max_fee));
+			}
+		} else if self.channel_outbound {
+			if msg.fee_satoshis > our_max_fee {
+				if let Some((_, last_fee, _)) = self.last_sent_closing_fee {
+					if our_max_fee <= last_fee {
+						return Err(ChannelError::Close(format!("Unable to come to consensus about closing fee, remote wanted something higher ({}) than our max fee ({})", msg.fee_satoshis, our_max_fee)));
 					}
 				}
-				propose_new_feerate!(max_feerate);
+				propose_new_fee!(our_max_fee);
 			}
 		} else {
-			let min_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Background);
-			if (proposed_sat_per_kw as u32) < min_feerate {
-				if let Some((last_feerate, _, _)) = self.last_sent_closing_fee {
-					if min_feerate >= last_feerate {
-						return Err(ChannelError::Close(format!("Unable to come to consensus about closing feerate, remote wanted something lower ({}) than our Background feerate ({}).", last_feerate, min_feerate)));
+			if msg.fee_satoshis < our_min_fee {
+				if let Some((_, last_fee, _)) = self.last_sent_closing_fee {
+					if our_min_fee >= last_fee {
+						return Err(ChannelError::Close(format!("Unable to come to consensus about closing fee, remote wanted something lower ({}) than our min fee ({})", msg.fee_satoshis, our_min_fee)));
 					}
 				}
-				propose_new_feerate!(min_feerate);
+				propose_new_fee!(our_min_fee);
 			}
 		}
 
@@ -3091,113 +3600,1281 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			channel_id: self.channel_id,
 			fee_satoshis: msg.fee_satoshis,
 			signature: sig,
+			fee_range: Some(Self::closing_fee_range_including(our_min_fee, our_max_fee, msg.fee_satoshis)),
 		}), Some(closing_tx)))
 	}
 
-	// Public utilities:
+	// Dual-funded channel establishment:
 
-	pub fn channel_id(&self) -> [u8; 32] {
-		self.channel_id
+	/// Returns true if this is a dual-funded channel whose funding transaction has yet to be fully
+	/// negotiated and signed.
+	pub fn is_pending_dual_funding(&self) -> bool {
+		self.pending_dual_funding.is_some()
 	}
 
-	/// Gets the "user_id" value passed into the construction of this channel. It has no special
-	/// meaning and exists only to allow users to have a persistent identifier of a channel.
-	pub fn get_user_id(&self) -> u64 {
-		self.user_id
+	/// Gets the channel_id of a dual-funded channel, which is derived from both sides' revocation
+	/// basepoints rather than the funding outpoint.
+	fn get_dual_funded_channel_id(&self) -> [u8; 32] {
+		let holder_revocation_basepoint = self.holder_keys.pubkeys().revocation_basepoint.serialize();
+		let counterparty_revocation_basepoint = self.counterparty_pubkeys.as_ref().unwrap().revocation_basepoint.serialize();
+		let mut sha = Sha256::engine();
+		if holder_revocation_basepoint[..] < counterparty_revocation_basepoint[..] {
+			sha.input(&holder_revocation_basepoint);
+			sha.input(&counterparty_revocation_basepoint);
+		} else {
+			sha.input(&counterparty_revocation_basepoint);
+			sha.input(&holder_revocation_basepoint);
+		}
+		Sha256::from_engine(sha).into_inner()
 	}
 
-	/// Guaranteed to be Some after both FundingLocked messages have been exchanged (and, thus,
-	/// is_usable() returns true).
-	/// Allowed in any state (including after shutdown)
-	pub fn get_short_channel_id(&self) -> Option<u64> {
-		self.short_channel_id
+	/// Sets the channel value once both sides' contributions to a dual-funded channel are known.
+	fn set_dual_funded_channel_value(&mut self, channel_value_satoshis: u64) {
+		self.channel_value_satoshis = channel_value_satoshis;
+		self.holder_keys.on_splice(channel_value_satoshis);
 	}
 
-	/// Returns the funding_txo we either got from our peer, or were given by
-	/// get_outbound_funding_created.
-	pub fn get_funding_txo(&self) -> Option<OutPoint> {
-		self.funding_txo
+	/// Accepts a dual-funded channel our counterparty proposed, optionally contributing funds of
+	/// our own, and returns the accept_channel2 to send.
+	/// Note that channel_id changes during this call!
+	pub fn accept_dual_funded_channel(&mut self, contribution: Option<&FundingContribution>, user_id: u64) -> Result<msgs::AcceptChannelV2, APIError> {
+		let (funding_feerate_per_kw, locktime, counterparty_contribution_satoshis) = match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingAcceptance, funding_feerate_per_kw, locktime, counterparty_contribution_satoshis, .. }) =>
+				(funding_feerate_per_kw, locktime, counterparty_contribution_satoshis),
+			_ => return Err(APIError::APIMisuseError { err: "Channel is not a dual-funded channel awaiting acceptance".to_owned() }),
+		};
+		let (holder_contribution_satoshis, holder_inputs, holder_outputs) = match contribution {
+			Some(contribution) => {
+				if contribution.value_satoshis == 0 {
+					return Err(APIError::APIMisuseError { err: "Cannot contribute a zero value to a channel".to_owned() });
+				}
+				let (inputs, outputs) = self.get_contribution_inputs_and_change(contribution.value_satoshis, &contribution.inputs, &contribution.change_script, funding_feerate_per_kw, 0)?;
+				(contribution.value_satoshis, inputs, outputs)
+			},
+			None => (0, Vec::new(), Vec::new()),
+		};
+		let channel_value_satoshis = counterparty_contribution_satoshis.saturating_add(holder_contribution_satoshis);
+		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS {
+			return Err(APIError::APIMisuseError { err: format!("Contributing {} sat would make the channel value of {} sat too large", holder_contribution_satoshis, channel_value_satoshis) });
+		}
+
+		self.set_dual_funded_channel_value(channel_value_satoshis);
+		self.value_to_self_msat = holder_contribution_satoshis * 1000;
+		self.counterparty_selected_channel_reserve_satoshis = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(channel_value_satoshis);
+		self.user_id = user_id;
+		let msg = self.get_accept_channel();
+
+		self.channel_id = self.get_dual_funded_channel_id();
+		let funding_script = self.get_funding_redeemscript().to_v0_p2wsh();
+		let (constructor, _) = InteractiveTxConstructor::new(self.channel_id, false, locktime, funding_feerate_per_kw,
+			holder_contribution_satoshis as i64, counterparty_contribution_satoshis as i64, None, Some(funding_script), holder_inputs, holder_outputs);
+		let dual_funding = self.pending_dual_funding.as_mut().unwrap();
+		dual_funding.holder_contribution_satoshis = holder_contribution_satoshis;
+		dual_funding.stage = DualFundingStage::Constructing(constructor);
+
+		Ok(msgs::AcceptChannelV2 {
+			temporary_channel_id: msg.temporary_channel_id,
+			funding_satoshis: holder_contribution_satoshis,
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			minimum_depth: msg.minimum_depth,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			funding_pubkey: msg.funding_pubkey,
+			revocation_basepoint: msg.revocation_basepoint,
+			payment_point: msg.payment_point,
+			delayed_payment_basepoint: msg.delayed_payment_basepoint,
+			htlc_basepoint: msg.htlc_basepoint,
+			first_per_commitment_point: msg.first_per_commitment_point,
+			shutdown_scriptpubkey: msg.shutdown_scriptpubkey,
+		})
 	}
 
-	/// Allowed in any state (including after shutdown)
-	pub fn get_counterparty_node_id(&self) -> PublicKey {
-		self.counterparty_node_id
+	/// Handles an accept_channel2 from our counterparty, returning the first message of the
+	/// interactive construction of the funding transaction.
+	/// Note that channel_id changes during this call!
+	pub fn accept_channel_v2(&mut self, msg: &msgs::AcceptChannelV2, config: &UserConfig, their_features: InitFeatures) -> Result<InteractiveTxMessageSend, ChannelError> {
+		let holder_contribution_satoshis = match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingAcceptChannel, holder_contribution_satoshis, .. }) => holder_contribution_satoshis,
+			_ => return Err(ChannelError::Close("Got an accept_channel2 message for a channel we didn't open as dual-funded".to_owned())),
+		};
+		let channel_value_satoshis = holder_contribution_satoshis.saturating_add(msg.funding_satoshis);
+		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS {
+			return Err(ChannelError::Close(format!("Peer's contribution of {} sat made the channel value too large", msg.funding_satoshis)));
+		}
+		self.set_dual_funded_channel_value(channel_value_satoshis);
+		// Both sides require the same reserve of the other, computed from the total channel value.
+		self.check_accept_channel(&msgs::AcceptChannel {
+			temporary_channel_id: msg.temporary_channel_id,
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			channel_reserve_satoshis: Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(channel_value_satoshis),
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			minimum_depth: msg.minimum_depth,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			funding_pubkey: msg.funding_pubkey,
+			revocation_basepoint: msg.revocation_basepoint,
+			payment_point: msg.payment_point,
+			delayed_payment_basepoint: msg.delayed_payment_basepoint,
+			htlc_basepoint: msg.htlc_basepoint,
+			first_per_commitment_point: msg.first_per_commitment_point,
+			shutdown_scriptpubkey: msg.shutdown_scriptpubkey.clone(),
+		}, config, their_features)?;
+
+		self.channel_id = self.get_dual_funded_channel_id();
+		let funding_script = self.get_funding_redeemscript().to_v0_p2wsh();
+		let funding_output = TxOut { value: channel_value_satoshis, script_pubkey: funding_script.clone() };
+		let channel_id = self.channel_id;
+		let dual_funding = self.pending_dual_funding.as_mut().unwrap();
+		let inputs = mem::replace(&mut dual_funding.holder_inputs, Vec::new());
+		let mut outputs = mem::replace(&mut dual_funding.holder_outputs, Vec::new());
+		outputs.insert(0, funding_output);
+		let (constructor, first_msg) = InteractiveTxConstructor::new(channel_id, true, dual_funding.locktime, dual_funding.funding_feerate_per_kw,
+			holder_contribution_satoshis as i64, msg.funding_satoshis as i64, None, Some(funding_script), inputs, outputs);
+		dual_funding.counterparty_contribution_satoshis = msg.funding_satoshis;
+		dual_funding.stage = DualFundingStage::Constructing(constructor);
+		Ok(first_msg.unwrap())
 	}
 
-	/// Allowed in any state (including after shutdown)
-	#[cfg(test)]
-	pub fn get_holder_htlc_minimum_msat(&self) -> u64 {
-		self.holder_htlc_minimum_msat
-	}
+	/// Checks the fully-constructed funding transaction matches what was negotiated and signs our
+	/// counterparty's initial commitment transaction spending its funding output.
+	fn dual_funding_transaction_constructed<L: Deref>(&mut self, constructed: ConstructedTransaction, logger: &L) -> Result<msgs::CommitmentSigned, String> where L::Target: Logger {
+		let funding_output_index = constructed.shared_output_index.unwrap();
+		if constructed.tx.output[funding_output_index].value != self.channel_value_satoshis {
+			return Err(format!("Funding transaction's funding output of {} sat did not match the negotiated channel value of {} sat", constructed.tx.output[funding_output_index].value, self.channel_value_satoshis));
+		}
+		if funding_output_index > ::std::u16::MAX as usize {
+			return Err("Funding transaction's funding output index was too large".to_owned());
+		}
+		if self.commitment_secrets.get_min_seen_secret() != (1 << 48) ||
+				self.cur_counterparty_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER ||
+				self.cur_holder_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER {
+			panic!("Should not have advanced channel commitment tx numbers prior to constructing the funding transaction");
+		}
 
-	/// Allowed in any state (including after shutdown)
-	pub fn get_announced_htlc_max_msat(&self) -> u64 {
-		return cmp::min(
-			// Upper bound by capacity. We make it a bit less than full capacity to prevent attempts
-			// to use full capacity. This is an effort to reduce routing failures, because in many cases
-			// channel might have been used to route very small values (either by honest users or as DoS).
-			self.channel_value_satoshis * 9 / 10,
+		self.funding_txo = Some(OutPoint { txid: constructed.tx.txid(), index: funding_output_index as u16 });
+		#[cfg(debug_assertions)]
+		{
+			// Our balances only became known now, so they've made no progress to track yet.
+			*self.holder_max_commitment_tx_output.lock().unwrap() = (0, 0);
+			*self.counterparty_max_commitment_tx_output.lock().unwrap() = (0, 0);
+		}
+		let signature = match self.get_outbound_funding_created_signature(logger) {
+			Ok(signature) => signature,
+			Err(e) => {
+				self.funding_txo = None;
+				return Err(format!("{:?}", e));
+			}
+		};
 
-			Channel::<ChanSigner>::get_holder_max_htlc_value_in_flight_msat(self.channel_value_satoshis)
-		);
+		log_trace!(logger, "Constructed funding transaction {} for dual-funded channel {}, sending commitment_signed", constructed.tx.txid(), log_bytes!(self.channel_id()));
+		self.pending_dual_funding.as_mut().unwrap().stage = DualFundingStage::AwaitingCommitmentSigned(constructed);
+		Ok(msgs::CommitmentSigned {
+			channel_id: self.channel_id,
+			signature,
+			htlc_signatures: Vec::new(),
+			splice_signatures: None,
+		})
 	}
 
-	/// Allowed in any state (including after shutdown)
-	pub fn get_counterparty_htlc_minimum_msat(&self) -> u64 {
-		self.counterparty_htlc_minimum_msat
+	/// Returns true if we're waiting on our counterparty's commitment_signed for our initial
+	/// commitment transaction in a dual-funded channel, in which case any commitment_signed should
+	/// be passed to dual_funding_commitment_signed.
+	pub fn is_awaiting_dual_funding_commitment_signed(&self) -> bool {
+		match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingCommitmentSigned(_), .. }) => true,
+			_ => false,
+		}
 	}
 
-	pub fn get_value_satoshis(&self) -> u64 {
-		self.channel_value_satoshis
-	}
+	/// Handles our counterparty's commitment_signed for our initial commitment transaction in a
+	/// dual-funded channel, much as funding_signed does for single-funded channels. Returns the
+	/// ChannelMonitor to add, the tx_signatures to send if it is our turn to sign the funding
+	/// transaction, and the funding transaction if the user needs to sign our inputs to it.
+	///
+	/// Note that we don't persist the funding transaction's signing, so if we restart before it
+	/// completes we can only wait for our counterparty to broadcast it (which it can only do if we
+	/// contributed no inputs).
+	pub fn dual_funding_commitment_signed<L: Deref>(&mut self, msg: &msgs::CommitmentSigned, best_block: (BlockHash, u32), logger: &L) -> Result<(ChannelMonitor<ChanSigner>, Option<msgs::TxSignatures>, Option<Transaction>), ChannelError> where L::Target: Logger {
+		let constructed = match self.pending_dual_funding.as_mut().map(|dual_funding| mem::replace(&mut dual_funding.stage, DualFundingStage::AwaitingAcceptance)) {
+			Some(DualFundingStage::AwaitingCommitmentSigned(constructed)) => constructed,
+			_ => panic!("dual_funding_commitment_signed called without checking is_awaiting_dual_funding_commitment_signed"),
+		};
+		if !msg.htlc_signatures.is_empty() {
+			return Err(ChannelError::Close("Peer sent HTLC signatures for an initial commitment transaction".to_owned()));
+		}
 
-	pub fn get_fee_proportional_millionths(&self) -> u32 {
-		self.config.fee_proportional_millionths
-	}
+		let funding_script = self.get_funding_redeemscript();
 
-	#[cfg(test)]
-	pub fn get_feerate(&self) -> u32 {
-		self.feerate_per_kw
-	}
+		let counterparty_keys = self.build_remote_transaction_keys()?;
+		let counterparty_initial_commitment_tx = self.build_commitment_transaction(self.cur_counterparty_commitment_transaction_number, &counterparty_keys, false, false, self.feerate_per_kw, logger).0;
 
-	pub fn get_cur_holder_commitment_transaction_number(&self) -> u64 {
-		self.cur_holder_commitment_transaction_number + 1
-	}
+		let holder_keys = self.build_holder_transaction_keys(self.cur_holder_commitment_transaction_number)?;
+		let initial_commitment_tx = self.build_commitment_transaction(self.cur_holder_commitment_transaction_number, &holder_keys, true, false, self.feerate_per_kw, logger).0;
+		let sighash = hash_to_message!(&bip143::SigHashCache::new(&initial_commitment_tx).signature_hash(0, &funding_script, self.channel_value_satoshis, SigHashType::All)[..]);
 
-	pub fn get_cur_counterparty_commitment_transaction_number(&self) -> u64 {
-		self.cur_counterparty_commitment_transaction_number + 1 - if self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32) != 0 { 1 } else { 0 }
-	}
+		// They sign our commitment transaction, allowing us to broadcast the tx if we wish.
+		log_trace!(logger, "Checking initial commitment_signed tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(msg.signature.serialize_compact()[..]), log_bytes!(self.counterparty_funding_pubkey().serialize()), encode::serialize_hex(&initial_commitment_tx), log_bytes!(sighash[..]), encode::serialize_hex(&funding_script));
+		secp_check!(self.secp_ctx.verify(&sighash, &msg.signature, self.counterparty_funding_pubkey()), "Invalid commitment_signed signature from peer for our initial commitment transaction".to_owned());
 
-	pub fn get_revoked_counterparty_commitment_transaction_number(&self) -> u64 {
-		self.cur_counterparty_commitment_transaction_number + 2
-	}
+		let counterparty_pubkeys = self.counterparty_pubkeys.as_ref().unwrap();
+		let funding_txo = self.funding_txo.unwrap();
+		let commitment_tx = HolderCommitmentTransaction::new_missing_holder_sig(initial_commitment_tx, msg.signature.clone(), &self.holder_keys.pubkeys().funding_pubkey, &counterparty_pubkeys.funding_pubkey, holder_keys, self.feerate_per_kw, self.opt_anchors, Vec::new());
+		let mut channel_monitor = ChannelMonitor::new(self.holder_keys.clone(),
+		                                              &self.shutdown_pubkey, self.holder_selected_contest_delay,
+		                                              &self.destination_script, (funding_txo, funding_script.to_v0_p2wsh()), self.channel_id,
+		                                              &counterparty_pubkeys.htlc_basepoint, &counterparty_pubkeys.delayed_payment_basepoint,
+		                                              self.counterparty_selected_contest_delay, funding_script.clone(), self.channel_value_satoshis,
+		                                              self.get_commitment_transaction_number_obscure_factor(),
+		                                              commitment_tx, best_block);
+		channel_monitor.provide_latest_counterparty_commitment_tx_info(&counterparty_initial_commitment_tx, Vec::new(), self.cur_counterparty_commitment_transaction_number, self.counterparty_cur_commitment_point.unwrap(), logger);
 
-	#[cfg(test)]
-	pub fn get_keys(&self) -> &ChanSigner {
-		&self.holder_keys
+		self.channel_state = ChannelState::FundingSent as u32;
+		self.cur_holder_commitment_transaction_number -= 1;
+		self.cur_counterparty_commitment_transaction_number -= 1;
+
+		let needs_signing = !constructed.holder_input_indexes.is_empty();
+		let unsigned_tx = if needs_signing { Some(constructed.tx.clone()) } else { None };
+		let holder_signed_tx = if needs_signing { None } else { Some(constructed.tx.clone()) };
+		self.pending_dual_funding.as_mut().unwrap().stage = DualFundingStage::AwaitingSignatures {
+			constructed,
+			holder_signed_tx,
+			counterparty_signatures: None,
+			holder_signatures: None,
+		};
+		let (tx_signatures, _) = self.maybe_send_dual_funding_tx_signatures();
+		Ok((channel_monitor, tx_signatures, unsigned_tx))
 	}
 
-	#[cfg(test)]
-	pub fn get_value_stat(&self) -> ChannelValueStat {
-		ChannelValueStat {
-			value_to_self_msat: self.value_to_self_msat,
-			channel_value_msat: self.channel_value_satoshis * 1000,
-			channel_reserve_msat: self.counterparty_selected_channel_reserve_satoshis * 1000,
-			pending_outbound_htlcs_amount_msat: self.pending_outbound_htlcs.iter().map(|ref h| h.amount_msat).sum::<u64>(),
-			pending_inbound_htlcs_amount_msat: self.pending_inbound_htlcs.iter().map(|ref h| h.amount_msat).sum::<u64>(),
-			holding_cell_outbound_amount_msat: {
-				let mut res = 0;
-				for h in self.holding_cell_htlc_updates.iter() {
-					match h {
-						&HTLCUpdateAwaitingACK::AddHTLC{amount_msat, .. } => {
-							res += amount_msat;
-						}
-						_ => {}
+	/// Provides the funding transaction with witnesses for all of our inputs, as previously given
+	/// to the user for signing. Returns the tx_signatures to send if it is our turn to sign, and
+	/// the fully-signed funding transaction if it is ready for broadcast.
+	pub fn funding_transaction_signed(&mut self, signed_tx: Transaction) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), APIError> {
+		match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { ref constructed, ref mut holder_signed_tx, .. }, .. }) if holder_signed_tx.is_none() => {
+				if signed_tx.txid() != constructed.tx.txid() {
+					return Err(APIError::APIMisuseError { err: "Signed transaction did not match the funding transaction".to_owned() });
+				}
+				for idx in constructed.holder_input_indexes.iter() {
+					if signed_tx.input[*idx].witness.is_empty() {
+						return Err(APIError::APIMisuseError { err: format!("Input {} of the funding transaction was not signed", idx) });
 					}
 				}
-				res
+				*holder_signed_tx = Some(signed_tx);
 			},
-			counterparty_max_htlc_value_in_flight_msat: self.counterparty_max_htlc_value_in_flight_msat,
+			_ => return Err(APIError::APIMisuseError { err: "No funding transaction is awaiting our signatures".to_owned() }),
+		}
+		Ok(self.maybe_send_dual_funding_tx_signatures())
+	}
+
+	/// Sends our tx_signatures for the funding transaction if we've signed our inputs and it's our
+	/// turn, returning the fully-signed funding transaction if we already have our counterparty's
+	/// signatures.
+	fn maybe_send_dual_funding_tx_signatures(&mut self) -> (Option<msgs::TxSignatures>, Option<Transaction>) {
+		let tx_signatures = match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { ref constructed, holder_signed_tx: Some(ref holder_signed_tx), ref counterparty_signatures, holder_signatures: None }, .. }) => {
+				if counterparty_signatures.is_none() && !self.holder_sends_tx_signatures_first(constructed) {
+					return (None, None);
+				}
+				msgs::TxSignatures {
+					channel_id: self.channel_id,
+					txid: constructed.tx.txid(),
+					witnesses: constructed.holder_input_indexes.iter().map(|idx| holder_signed_tx.input[*idx].witness.clone()).collect(),
+					shared_input_signature: None,
+				}
+			},
+			_ => return (None, None),
+		};
+		if let Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { ref mut holder_signatures, .. }, .. }) = self.pending_dual_funding {
+			*holder_signatures = Some(tx_signatures.clone());
+		}
+		(Some(tx_signatures), self.maybe_finalize_dual_funding_transaction())
+	}
+
+	/// Builds the fully-signed funding transaction if both sides have sent tx_signatures, after
+	/// which the channel only has to wait for it to confirm like any other.
+	fn maybe_finalize_dual_funding_transaction(&mut self) -> Option<Transaction> {
+		let tx = match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { ref constructed, holder_signed_tx: Some(ref holder_signed_tx), counterparty_signatures: Some(ref counterparty_signatures), holder_signatures: Some(_) }, .. }) => {
+				let mut tx = holder_signed_tx.clone();
+				let mut counterparty_witnesses = counterparty_signatures.witnesses.iter();
+				for (idx, input) in tx.input.iter_mut().enumerate() {
+					if !constructed.holder_input_indexes.contains(&idx) {
+						input.witness = counterparty_witnesses.next().unwrap().clone();
+					}
+				}
+				tx
+			},
+			_ => return None,
+		};
+		self.pending_dual_funding = None;
+		Some(tx)
+	}
+
+	/// Handles our counterparty's tx_signatures for a dual-funded channel's funding transaction,
+	/// see tx_signatures.
+	fn dual_funding_tx_signatures(&mut self, msg: &msgs::TxSignatures) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), ChannelError> {
+		match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { ref constructed, ref counterparty_signatures, ref holder_signatures, .. }, .. }) if counterparty_signatures.is_none() => {
+				if msg.txid != constructed.tx.txid() {
+					return Err(ChannelError::Close("Peer sent tx_signatures for a different transaction".to_owned()));
+				}
+				if holder_signatures.is_none() && self.holder_sends_tx_signatures_first(constructed) {
+					return Err(ChannelError::Close("Peer sent tx_signatures before we sent ours".to_owned()));
+				}
+				let counterparty_inputs_count = constructed.tx.input.len() - constructed.holder_input_indexes.len();
+				if msg.witnesses.len() != counterparty_inputs_count || msg.witnesses.iter().any(|witness| witness.is_empty()) {
+					return Err(ChannelError::Close("Peer sent tx_signatures without a witness for each of its inputs".to_owned()));
+				}
+				if msg.shared_input_signature.is_some() {
+					return Err(ChannelError::Close("Peer sent a shared input signature for a funding transaction without a shared input".to_owned()));
+				}
+			},
+			_ => return Err(ChannelError::Close("Peer sent unexpected tx_signatures".to_owned())),
+		}
+		if let Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { ref mut counterparty_signatures, .. }, .. }) = self.pending_dual_funding {
+			*counterparty_signatures = Some(msg.clone());
+		}
+
+		match self.maybe_finalize_dual_funding_transaction() {
+			Some(tx) => Ok((None, Some(tx))),
+			None => Ok(self.maybe_send_dual_funding_tx_signatures()),
+		}
+	}
+
+	/// Gets the tx_signatures to retransmit on reconnection, if we've sent ours for the funding
+	/// transaction but have yet to receive our counterparty's.
+	pub fn get_dual_funding_tx_signatures(&self) -> Option<msgs::TxSignatures> {
+		match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { counterparty_signatures: None, holder_signatures: Some(ref holder_signatures), .. }, .. }) => Some(holder_signatures.clone()),
+			_ => None,
+		}
+	}
+
+	// Splicing:
+
+	/// Returns true if the channel is quiescent (or waiting to become so) for a splice, in which
+	/// case neither side may propose any updates to the commitment transactions until both sides
+	/// have sent tx_signatures for the splice transaction or the splice is aborted.
+	pub fn is_quiescent(&self) -> bool {
+		self.pending_splice.is_some()
+	}
+
+	/// Returns true if there are no updates pending in either direction, allowing the channel to
+	/// become quiescent.
+	fn can_quiesce(&self) -> bool {
+		self.channel_state == ChannelState::ChannelFunded as u32 &&
+			self.pending_inbound_htlcs.is_empty() && self.pending_outbound_htlcs.is_empty() &&
+			self.holding_cell_htlc_updates.is_empty() &&
+			self.pending_update_fee.is_none() && self.holding_cell_update_fee.is_none()
+	}
+
+	/// Checks that a splice in which each side contributes the given amounts leaves both sides
+	/// above their channel reserve, returning the new channel value and our new balance.
+	fn check_splice_contributions(&self, holder_contribution_satoshis: i64, counterparty_contribution_satoshis: i64) -> Result<(u64, u64), String> {
+		let channel_value_satoshis = self.channel_value_satoshis as i64 + holder_contribution_satoshis + counterparty_contribution_satoshis;
+		if channel_value_satoshis <= 0 || channel_value_satoshis as u64 >= MAX_FUNDING_SATOSHIS {
+			return Err(format!("Splice would result in an invalid channel value of {} sat", channel_value_satoshis));
+		}
+		let value_to_self_msat = self.value_to_self_msat as i64 + holder_contribution_satoshis * 1000;
+		let value_to_remote_msat = (self.channel_value_satoshis * 1000 - self.value_to_self_msat) as i64 + counterparty_contribution_satoshis * 1000;
+		let commit_tx_fee_msat = self.commit_tx_fee_msat(0) as i64;
+		let (holder_fee_msat, counterparty_fee_msat) = if self.channel_outbound { (commit_tx_fee_msat, 0) } else { (0, commit_tx_fee_msat) };
+		if holder_contribution_satoshis < 0 && value_to_self_msat < self.counterparty_selected_channel_reserve_satoshis as i64 * 1000 + holder_fee_msat {
+			return Err(format!("Splicing out {} sat would leave our balance of {} msat below the channel reserve", -holder_contribution_satoshis, value_to_self_msat));
+		}
+		if counterparty_contribution_satoshis < 0 && value_to_remote_msat < Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(channel_value_satoshis as u64) as i64 * 1000 + counterparty_fee_msat {
+			return Err(format!("Splicing out {} sat would leave our counterparty's balance of {} msat below the channel reserve", -counterparty_contribution_satoshis, value_to_remote_msat));
+		}
+		Ok((channel_value_satoshis as u64, value_to_self_msat as u64))
+	}
+
+	/// Builds the inputs and change output with which we contribute value_satoshis to an
+	/// interactively-constructed transaction, checking that the given outputs (each given as the
+	/// transaction containing it and its index therein) can pay for it, as well as for their own
+	/// weight plus base_weight at the given feerate.
+	fn get_contribution_inputs_and_change(&self, value_satoshis: u64, inputs: &[(Transaction, u32)], change_script: &Script, feerate_per_kw: u32, base_weight: u64) -> Result<(Vec<InteractiveTxInput>, Vec<TxOut>), APIError> {
+		let mut contribution_inputs = Vec::with_capacity(inputs.len());
+		let mut inputs_value: u64 = 0;
+		for &(ref prevtx, prevtx_out) in inputs.iter() {
+			let output = match prevtx.output.get(prevtx_out as usize) {
+				Some(output) => output,
+				None => return Err(APIError::APIMisuseError { err: format!("Input {}:{} does not exist", prevtx.txid(), prevtx_out) }),
+			};
+			if !output.script_pubkey.is_witness_program() {
+				return Err(APIError::APIMisuseError { err: format!("Input {}:{} is not a segwit output", prevtx.txid(), prevtx_out) });
+			}
+			inputs_value = inputs_value.saturating_add(output.value);
+			contribution_inputs.push(InteractiveTxInput { prevtx: Some(prevtx.clone()), prevtx_out, sequence: 0xfffffffd });
+		}
+		let weight = base_weight + P2WPKH_INPUT_WEIGHT * inputs.len() as u64;
+		let fee = feerate_per_kw as u64 * weight / 1000;
+		if inputs_value < value_satoshis.saturating_add(fee) {
+			return Err(APIError::APIMisuseError { err: format!("Inputs totalling {} sat cannot cover the contributed value of {} sat plus a fee of {} sat", inputs_value, value_satoshis, fee) });
+		}
+		let mut outputs = Vec::new();
+		// Compute the change output's fee as the increase in the total fee so that we never round
+		// our share of the fee below what our counterparty expects.
+		let change_fee = feerate_per_kw as u64 * (weight + (8 + 1 + change_script.len() as u64) * 4) / 1000 - fee;
+		let change_value = inputs_value - value_satoshis - fee;
+		if change_value >= change_fee + self.holder_dust_limit_satoshis {
+			outputs.push(TxOut { value: change_value - change_fee, script_pubkey: change_script.clone() });
+		}
+		Ok((contribution_inputs, outputs))
+	}
+
+	/// Begins splicing funds into or out of the channel, returning the stfu message which starts
+	/// quiescing the channel. The splice is negotiated once our counterparty responds with its own
+	/// stfu.
+	pub fn splice_channel(&mut self, contribution: &SpliceContribution, funding_feerate_per_kw: u32, locktime: u32) -> Result<msgs::Stfu, APIError> {
+		if self.is_quiescent() || self.locking_splice.is_some() {
+			return Err(APIError::APIMisuseError { err: "A splice is already in progress on this channel".to_owned() });
+		}
+		if !self.can_quiesce() {
+			return Err(APIError::ChannelUnavailable { err: "Channel must be established, connected and have no pending updates before it can be spliced".to_owned() });
+		}
+
+		let funding_txo = self.get_current_funding_txo().unwrap();
+		let shared_input = InteractiveTxInput { prevtx: None, prevtx_out: funding_txo.index as u32, sequence: 0xfffffffd };
+		let (holder_contribution_satoshis, holder_inputs, holder_outputs) = match contribution {
+			&SpliceContribution::SpliceIn { value_satoshis, ref inputs, ref change_script } => {
+				if value_satoshis == 0 {
+					return Err(APIError::APIMisuseError { err: "Cannot splice in a zero value".to_owned() });
+				}
+				let (mut splice_inputs, outputs) = self.get_contribution_inputs_and_change(value_satoshis, inputs, change_script, funding_feerate_per_kw, SPLICE_INITIATOR_BASE_WEIGHT)?;
+				splice_inputs.insert(0, shared_input);
+				(value_satoshis as i64, splice_inputs, outputs)
+			},
+			&SpliceContribution::SpliceOut { ref outputs } => {
+				if outputs.is_empty() {
+					return Err(APIError::APIMisuseError { err: "Cannot splice out to no outputs".to_owned() });
+				}
+				let mut outputs_value: u64 = 0;
+				let mut weight = SPLICE_INITIATOR_BASE_WEIGHT;
+				for output in outputs.iter() {
+					if output.value < self.holder_dust_limit_satoshis {
+						return Err(APIError::APIMisuseError { err: format!("Splice out output value of {} sat is below the dust limit", output.value) });
+					}
+					outputs_value = outputs_value.saturating_add(output.value);
+					weight += (8 + 1 + output.script_pubkey.len() as u64) * 4;
+				}
+				let spliced_out_value = outputs_value.saturating_add(funding_feerate_per_kw as u64 * weight / 1000);
+				if spliced_out_value >= self.channel_value_satoshis {
+					return Err(APIError::APIMisuseError { err: format!("Cannot splice out {} sat from a channel of {} sat", spliced_out_value, self.channel_value_satoshis) });
+				}
+				(-(spliced_out_value as i64), vec![shared_input], outputs.clone())
+			},
+		};
+		self.check_splice_contributions(holder_contribution_satoshis, 0).map_err(|err| APIError::APIMisuseError { err })?;
+
+		self.pending_splice = Some(PendingSplice {
+			stage: SpliceStage::AwaitingQuiescence,
+			funding_feerate_per_kw,
+			locktime,
+			holder_contribution_satoshis,
+			counterparty_contribution_satoshis: 0,
+			holder_inputs,
+			holder_outputs,
+		});
+		Ok(msgs::Stfu {
+			channel_id: self.channel_id,
+			initiator: 1,
+		})
+	}
+
+	/// Handles an stfu message from our counterparty, returning the stfu to respond with if they
+	/// initiated quiescence, or the splice_init to send if we did.
+	pub fn stfu(&mut self, msg: &msgs::Stfu) -> Result<(Option<msgs::Stfu>, Option<msgs::SpliceInit>), ChannelError> {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent stfu when we needed a channel_reestablish".to_owned()));
+		}
+		let we_initiated = match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingQuiescence, .. }) => true,
+			Some(_) => return Err(ChannelError::Close("Peer sent stfu while a splice was already being negotiated".to_owned())),
+			None => false,
+		};
+		if self.locking_splice.is_some() {
+			return Err(ChannelError::Close("Peer sent stfu while a previous splice was still locking".to_owned()));
+		}
+		if msg.initiator == 0 && !we_initiated {
+			return Err(ChannelError::Close("Peer responded to an stfu we never sent".to_owned()));
+		}
+
+		// If both sides sent stfu to initiate quiescence, the channel funder gets to go ahead.
+		if we_initiated && (msg.initiator == 0 || self.channel_outbound) {
+			let channel_id = self.channel_id;
+			let funding_pubkey = self.holder_keys.pubkeys().funding_pubkey;
+			let splice = self.pending_splice.as_mut().unwrap();
+			splice.stage = SpliceStage::AwaitingSpliceAck;
+			return Ok((None, Some(msgs::SpliceInit {
+				channel_id,
+				funding_contribution_satoshis: splice.holder_contribution_satoshis,
+				funding_feerate_perkw: splice.funding_feerate_per_kw,
+				locktime: splice.locktime,
+				funding_pubkey,
+			})));
+		}
+
+		if !we_initiated && !self.can_quiesce() {
+			return Err(ChannelError::Close("Peer sent stfu while there were updates pending".to_owned()));
+		}
+		self.pending_splice = Some(PendingSplice {
+			stage: SpliceStage::AwaitingSpliceInit,
+			funding_feerate_per_kw: 0,
+			locktime: 0,
+			holder_contribution_satoshis: 0,
+			counterparty_contribution_satoshis: 0,
+			holder_inputs: Vec::new(),
+			holder_outputs: Vec::new(),
+		});
+		if we_initiated {
+			// We already sent our (now superseded) stfu, which serves as our response.
+			Ok((None, None))
+		} else {
+			Ok((Some(msgs::Stfu { channel_id: self.channel_id, initiator: 0 }), None))
+		}
+	}
+
+	/// Gets the shared input and output which the interactively-constructed splice transaction
+	/// must spend and create.
+	fn get_splice_shared_input_output(&self) -> (Option<(::bitcoin::blockdata::transaction::OutPoint, u64)>, Option<Script>) {
+		let funding_txo = self.get_current_funding_txo().unwrap();
+		(Some((funding_txo.into_bitcoin_outpoint(), self.channel_value_satoshis)), Some(self.get_funding_redeemscript().to_v0_p2wsh()))
+	}
+
+	/// Handles a splice_init from our counterparty, returning the splice_ack to respond with. We
+	/// never contribute funds to a splice our counterparty initiated.
+	pub fn splice_init(&mut self, msg: &msgs::SpliceInit) -> Result<msgs::SpliceAck, ChannelError> {
+		match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSpliceInit, .. }) => {},
+			_ => return Err(ChannelError::Close("Peer sent splice_init without first quiescing the channel".to_owned())),
+		}
+		if msg.funding_pubkey != *self.counterparty_funding_pubkey() {
+			return Err(ChannelError::Close("Peer tried to change its funding pubkey in a splice".to_owned()));
+		}
+		self.check_splice_contributions(0, msg.funding_contribution_satoshis).map_err(|e| ChannelError::Close(e))?;
+
+		let (shared_input, shared_output_script) = self.get_splice_shared_input_output();
+		let (constructor, _) = InteractiveTxConstructor::new(self.channel_id, false, msg.locktime, msg.funding_feerate_perkw,
+			0, msg.funding_contribution_satoshis, shared_input, shared_output_script, Vec::new(), Vec::new());
+		let splice = self.pending_splice.as_mut().unwrap();
+		splice.funding_feerate_per_kw = msg.funding_feerate_perkw;
+		splice.locktime = msg.locktime;
+		splice.counterparty_contribution_satoshis = msg.funding_contribution_satoshis;
+		splice.stage = SpliceStage::Constructing(constructor);
+		Ok(msgs::SpliceAck {
+			channel_id: self.channel_id,
+			funding_contribution_satoshis: 0,
+			funding_pubkey: self.holder_keys.pubkeys().funding_pubkey,
+		})
+	}
+
+	/// Handles a splice_ack from our counterparty, returning the first message of the
+	/// interactive construction of the splice transaction.
+	pub fn splice_ack(&mut self, msg: &msgs::SpliceAck) -> Result<InteractiveTxMessageSend, ChannelError> {
+		let holder_contribution_satoshis = match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSpliceAck, holder_contribution_satoshis, .. }) => holder_contribution_satoshis,
+			_ => return Err(ChannelError::Close("Peer sent splice_ack for a splice we didn't initiate".to_owned())),
+		};
+		if msg.funding_pubkey != *self.counterparty_funding_pubkey() {
+			return Err(ChannelError::Close("Peer tried to change its funding pubkey in a splice".to_owned()));
+		}
+		let (channel_value_satoshis, _) = self.check_splice_contributions(holder_contribution_satoshis, msg.funding_contribution_satoshis)
+			.map_err(|e| ChannelError::Close(e))?;
+
+		let (shared_input, shared_output_script) = self.get_splice_shared_input_output();
+		let funding_output = TxOut { value: channel_value_satoshis, script_pubkey: shared_output_script.clone().unwrap() };
+		let channel_id = self.channel_id;
+		let splice = self.pending_splice.as_mut().unwrap();
+		let inputs = mem::replace(&mut splice.holder_inputs, Vec::new());
+		let mut outputs = mem::replace(&mut splice.holder_outputs, Vec::new());
+		outputs.insert(0, funding_output);
+		let (constructor, first_msg) = InteractiveTxConstructor::new(channel_id, true, splice.locktime, splice.funding_feerate_per_kw,
+			holder_contribution_satoshis, msg.funding_contribution_satoshis, shared_input, shared_output_script, inputs, outputs);
+		splice.counterparty_contribution_satoshis = msg.funding_contribution_satoshis;
+		splice.stage = SpliceStage::Constructing(constructor);
+		Ok(first_msg.unwrap())
+	}
+
+	/// Abandons any splice or dual-funded channel open being negotiated, returning the tx_abort to
+	/// send our counterparty. A dual-funded channel which has yet to exchange commitment_signed is
+	/// closed outright (see is_shutdown).
+	fn abort_interactive_tx(&mut self, reason: &str) -> msgs::TxAbort {
+		self.pending_splice = None;
+		if self.pending_dual_funding.take().is_some() && self.channel_state < ChannelState::FundingSent as u32 {
+			self.channel_state = ChannelState::ShutdownComplete as u32;
+			self.update_time_counter += 1;
+		}
+		msgs::TxAbort {
+			channel_id: self.channel_id,
+			data: reason.as_bytes().to_vec(),
+		}
+	}
+
+	fn get_interactive_tx_constructor(&mut self) -> Option<&mut InteractiveTxConstructor> {
+		if let Some(PendingDualFunding { stage: DualFundingStage::Constructing(ref mut constructor), .. }) = self.pending_dual_funding {
+			return Some(constructor);
+		}
+		match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::Constructing(ref mut constructor), .. }) => Some(constructor),
+			_ => None,
+		}
+	}
+
+	/// Handles a tx_add_input from our counterparty, returning the message to respond with, or
+	/// the tx_abort to send if the splice or dual-funded channel open must be abandoned.
+	pub fn tx_add_input(&mut self, msg: &msgs::TxAddInput) -> Result<InteractiveTxMessageSend, msgs::TxAbort> {
+		let res = match self.get_interactive_tx_constructor() {
+			Some(constructor) => constructor.handle_tx_add_input(msg),
+			None => Err("Received tx_add_input without a transaction being constructed"),
+		};
+		res.map_err(|e| self.abort_interactive_tx(e))
+	}
+
+	/// Handles a tx_add_output from our counterparty, see tx_add_input.
+	pub fn tx_add_output(&mut self, msg: &msgs::TxAddOutput) -> Result<InteractiveTxMessageSend, msgs::TxAbort> {
+		let res = match self.get_interactive_tx_constructor() {
+			Some(constructor) => constructor.handle_tx_add_output(msg),
+			None => Err("Received tx_add_output without a transaction being constructed"),
+		};
+		res.map_err(|e| self.abort_interactive_tx(e))
+	}
+
+	/// Handles a tx_remove_input from our counterparty, see tx_add_input.
+	pub fn tx_remove_input(&mut self, msg: &msgs::TxRemoveInput) -> Result<InteractiveTxMessageSend, msgs::TxAbort> {
+		let res = match self.get_interactive_tx_constructor() {
+			Some(constructor) => constructor.handle_tx_remove_input(msg),
+			None => Err("Received tx_remove_input without a transaction being constructed"),
+		};
+		res.map_err(|e| self.abort_interactive_tx(e))
+	}
+
+	/// Handles a tx_remove_output from our counterparty, see tx_add_input.
+	pub fn tx_remove_output(&mut self, msg: &msgs::TxRemoveOutput) -> Result<InteractiveTxMessageSend, msgs::TxAbort> {
+		let res = match self.get_interactive_tx_constructor() {
+			Some(constructor) => constructor.handle_tx_remove_output(msg),
+			None => Err("Received tx_remove_output without a transaction being constructed"),
+		};
+		res.map_err(|e| self.abort_interactive_tx(e))
+	}
+
+	/// Handles a tx_complete from our counterparty, returning the message to respond with, if any.
+	/// Once the splice or funding transaction has been fully constructed, also returns the
+	/// commitment_signed for our counterparty's commitment transaction spending its funding output.
+	pub fn tx_complete<L: Deref>(&mut self, msg: &msgs::TxComplete, logger: &L) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), msgs::TxAbort> where L::Target: Logger {
+		let res = match self.get_interactive_tx_constructor() {
+			Some(constructor) => constructor.handle_tx_complete(msg),
+			None => Err("Received tx_complete without a transaction being constructed"),
+		};
+		let (response, constructed) = res.map_err(|e| self.abort_interactive_tx(e))?;
+		match constructed {
+			Some(constructed) if self.pending_dual_funding.is_some() => match self.dual_funding_transaction_constructed(constructed, logger) {
+				Ok(commitment_signed) => Ok((response, Some(commitment_signed))),
+				Err(e) => Err(self.abort_interactive_tx(&e)),
+			},
+			Some(constructed) => match self.splice_transaction_constructed(constructed, logger) {
+				Ok(commitment_signed) => Ok((response, Some(commitment_signed))),
+				Err(e) => Err(self.abort_interactive_tx(&e)),
+			},
+			None => Ok((response, None)),
+		}
+	}
+
+	/// Checks the fully-constructed splice transaction matches what was negotiated and signs our
+	/// counterparty's commitment transaction spending its funding output.
+	fn splice_transaction_constructed<L: Deref>(&mut self, constructed: ConstructedTransaction, logger: &L) -> Result<msgs::CommitmentSigned, String> where L::Target: Logger {
+		let (holder_contribution_satoshis, counterparty_contribution_satoshis) = {
+			let splice = self.pending_splice.as_ref().unwrap();
+			(splice.holder_contribution_satoshis, splice.counterparty_contribution_satoshis)
+		};
+		let (channel_value_satoshis, value_to_self_msat) = self.check_splice_contributions(holder_contribution_satoshis, counterparty_contribution_satoshis)?;
+		let funding_output_index = constructed.shared_output_index.unwrap();
+		if constructed.tx.output[funding_output_index].value != channel_value_satoshis {
+			return Err(format!("Splice transaction's funding output of {} sat did not match the negotiated channel value of {} sat", constructed.tx.output[funding_output_index].value, channel_value_satoshis));
+		}
+		if funding_output_index > ::std::u16::MAX as usize {
+			return Err("Splice transaction's funding output index was too large".to_owned());
+		}
+
+		let funding = SplicedFunding {
+			funding_txo: OutPoint { txid: constructed.tx.txid(), index: funding_output_index as u16 },
+			channel_value_satoshis,
+			value_to_self_msat,
+			constructed,
+		};
+		let (counterparty_commitment_tx, counterparty_keys, counterparty_htlcs) = self.build_splice_commitment_transaction(&funding, false, logger).map_err(|e| format!("{:?}", e))?;
+		let mut splice_keys = self.holder_keys.clone();
+		splice_keys.on_splice(channel_value_satoshis);
+		let (signature, htlc_signatures) = {
+			let htlcs: Vec<&HTLCOutputInCommitment> = counterparty_htlcs.iter().map(|&(ref htlc, _)| htlc).collect();
+			splice_keys.sign_counterparty_commitment(self.feerate_per_kw, &counterparty_commitment_tx, &PreCalculatedTxCreationKeys::new(counterparty_keys), &htlcs, &self.secp_ctx)
+				.map_err(|_| "Failed to sign the splice commitment transaction".to_owned())?
+		};
+
+		log_trace!(logger, "Constructed splice transaction {} for channel {}, sending commitment_signed for the new funding output", funding.funding_txo.txid, log_bytes!(self.channel_id()));
+		self.pending_splice.as_mut().unwrap().stage = SpliceStage::AwaitingCommitmentSigned(funding);
+		Ok(msgs::CommitmentSigned {
+			channel_id: self.channel_id,
+			signature,
+			htlc_signatures,
+			splice_signatures: None,
+		})
+	}
+
+	/// Builds our or our counterparty's latest commitment transaction as it would be if it spent
+	/// the given splice funding output instead of the current one.
+	fn build_splice_commitment_transaction<L: Deref>(&self, funding: &SplicedFunding, local: bool, logger: &L) -> Result<(Transaction, TxCreationKeys, Vec<(HTLCOutputInCommitment, Option<HTLCSource>)>), ChannelError> where L::Target: Logger {
+		// The channel is quiescent, so the latest commitment transactions (which we're replacing)
+		// have no updates pending on top of them.
+		let (commitment_number, keys) = if local {
+			(self.cur_holder_commitment_transaction_number + 1, self.build_holder_transaction_keys(self.cur_holder_commitment_transaction_number + 1)?)
+		} else {
+			let counterparty_pubkeys = self.counterparty_pubkeys.as_ref().unwrap();
+			(self.cur_counterparty_commitment_transaction_number + 1,
+				secp_check!(TxCreationKeys::derive_new(&self.secp_ctx, &self.counterparty_prev_commitment_point.unwrap(), &counterparty_pubkeys.delayed_payment_basepoint, &counterparty_pubkeys.htlc_basepoint, &self.holder_keys.pubkeys().revocation_basepoint, &self.holder_keys.pubkeys().htlc_basepoint), "Remote tx keys generation got bogus keys".to_owned()))
+		};
+
+		let (tx, htlcs) = {
+			let commitment_tx = self.build_commitment_transaction_for_funding(Some((funding.funding_txo, funding.channel_value_satoshis, funding.value_to_self_msat)), commitment_number, &keys, local, false, self.feerate_per_kw, logger);
+			(commitment_tx.0, commitment_tx.2.into_iter().map(|(htlc, source)| (htlc, source.cloned())).collect())
+		};
+		Ok((tx, keys, htlcs))
+	}
+
+	/// Gets the (funding outpoint, channel value, our balance) tuple our commitment transactions
+	/// spending the funding output of the splice we're waiting on to lock would have, if any.
+	fn get_locking_splice_funding(&self) -> Option<(OutPoint, u64, u64)> {
+		self.locking_splice.as_ref().map(|splice|
+			(splice.funding_txo, splice.channel_value_satoshis, (self.value_to_self_msat as i64 + splice.holder_contribution_satoshis * 1000) as u64))
+	}
+
+	/// Gets how much lower our and our counterparty's balances are on the funding output of the
+	/// splice we're waiting on to lock than on the current one. New HTLCs have to be affordable
+	/// on both, as every commitment transaction is signed for both until the splice locks.
+	fn get_locking_splice_balance_reductions_msat(&self) -> (u64, u64) {
+		match self.locking_splice {
+			Some(ref splice) => {
+				let counterparty_contribution_satoshis = splice.channel_value_satoshis as i64 - self.channel_value_satoshis as i64 - splice.holder_contribution_satoshis;
+				(cmp::max(-splice.holder_contribution_satoshis, 0) as u64 * 1000, cmp::max(-counterparty_contribution_satoshis, 0) as u64 * 1000)
+			},
+			None => (0, 0),
+		}
+	}
+
+	/// Returns true if we're waiting on our counterparty's commitment_signed for the new funding
+	/// output of a splice, in which case any commitment_signed should be passed to
+	/// splice_commitment_signed.
+	pub fn is_awaiting_splice_commitment_signed(&self) -> bool {
+		match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingCommitmentSigned(_), .. }) => true,
+			_ => false,
+		}
+	}
+
+	/// Handles our counterparty's commitment_signed for our commitment transaction spending the
+	/// new funding output of a splice. Returns the ChannelMonitorUpdate which must be applied
+	/// before anything else is sent, the tx_signatures to send if it is our turn to sign the
+	/// splice transaction, and the splice transaction if the user needs to sign our inputs to it.
+	pub fn splice_commitment_signed<L: Deref>(&mut self, msg: &msgs::CommitmentSigned, logger: &L) -> Result<(ChannelMonitorUpdate, Option<msgs::TxSignatures>, Option<Transaction>), ChannelError> where L::Target: Logger {
+		let funding = match self.pending_splice {
+			Some(ref mut splice) => match mem::replace(&mut splice.stage, SpliceStage::AwaitingQuiescence) {
+				SpliceStage::AwaitingCommitmentSigned(funding) => funding,
+				stage => {
+					splice.stage = stage;
+					return Err(ChannelError::Close("Peer sent commitment_signed for a splice before the splice transaction was constructed".to_owned()));
+				},
+			},
+			None => return Err(ChannelError::Close("Peer sent commitment_signed for a splice while no splice was being negotiated".to_owned())),
+		};
+
+		let (commitment_tx, keys, htlcs) = self.build_splice_commitment_transaction(&funding, true, logger)?;
+		let commitment_txid = commitment_tx.txid();
+		let funding_script = self.get_funding_redeemscript();
+		let sighash = hash_to_message!(&bip143::SigHashCache::new(&commitment_tx).signature_hash(0, &funding_script, funding.channel_value_satoshis, SigHashType::All)[..]);
+		log_trace!(logger, "Checking splice commitment_signed tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(msg.signature.serialize_compact()[..]), log_bytes!(self.counterparty_funding_pubkey().serialize()), encode::serialize_hex(&commitment_tx), log_bytes!(sighash[..]), encode::serialize_hex(&funding_script));
+		secp_check!(self.secp_ctx.verify(&sighash, &msg.signature, self.counterparty_funding_pubkey()), "Invalid splice commitment_signed signature from peer".to_owned());
+
+		let num_htlcs = htlcs.iter().filter(|&&(ref htlc, _)| htlc.transaction_output_index.is_some()).count();
+		if msg.htlc_signatures.len() != num_htlcs {
+			return Err(ChannelError::Close(format!("Got wrong number of HTLC signatures ({}) for a splice commitment transaction from remote. It must be {}", msg.htlc_signatures.len(), num_htlcs)));
+		}
+		let mut htlcs_without_source = Vec::with_capacity(htlcs.len());
+		let mut htlcs_and_sigs = Vec::with_capacity(htlcs.len());
+		for (idx, (htlc, source)) in htlcs.into_iter().enumerate() {
+			if let Some(_) = htlc.transaction_output_index {
+				let htlc_tx = self.build_htlc_transaction(&commitment_txid, &htlc, true, &keys, self.feerate_per_kw);
+				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, self.opt_anchors, &keys);
+				let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, chan_utils::htlc_counterparty_sighash_type(self.opt_anchors))[..]);
+				secp_check!(self.secp_ctx.verify(&htlc_sighash, &msg.htlc_signatures[idx], &keys.countersignatory_htlc_key), "Invalid splice HTLC tx signature from peer".to_owned());
+				htlcs_without_source.push((htlc.clone(), Some(msg.htlc_signatures[idx])));
+				htlcs_and_sigs.push((htlc, Some(msg.htlc_signatures[idx]), source));
+			} else {
+				htlcs_without_source.push((htlc.clone(), None));
+				htlcs_and_sigs.push((htlc, None, source));
+			}
+		}
+
+		// Rebuild the counterparty's commitment transaction we signed when the splice transaction
+		// was constructed, which is unchanged as the channel is quiescent.
+		let (counterparty_commitment_tx, _, counterparty_htlcs) = self.build_splice_commitment_transaction(&funding, false, logger)?;
+
+		let commitment_tx = HolderCommitmentTransaction::new_missing_holder_sig(commitment_tx, msg.signature.clone(), &self.holder_keys.pubkeys().funding_pubkey, self.counterparty_funding_pubkey(), keys, self.feerate_per_kw, self.opt_anchors, htlcs_without_source);
+		self.latest_monitor_update_id += 1;
+		let monitor_update = ChannelMonitorUpdate {
+			update_id: self.latest_monitor_update_id,
+			updates: vec![ChannelMonitorUpdateStep::SpliceFundingSigned {
+				funding_outpoint: funding.funding_txo,
+				channel_value_satoshis: funding.channel_value_satoshis,
+				commitment_tx,
+				htlc_outputs: htlcs_and_sigs,
+			}, ChannelMonitorUpdateStep::SpliceCounterpartyCommitmentTXInfo {
+				unsigned_commitment_tx: counterparty_commitment_tx,
+				htlc_outputs: counterparty_htlcs.into_iter().map(|(htlc, source)| (htlc, source.map(|source| Box::new(source)))).collect(),
+			}],
+		};
+
+		let needs_signing = !funding.constructed.holder_input_indexes.is_empty();
+		let unsigned_tx = if needs_signing { Some(funding.constructed.tx.clone()) } else { None };
+		let holder_signed_tx = if needs_signing { None } else { Some(funding.constructed.tx.clone()) };
+		self.pending_splice.as_mut().unwrap().stage = SpliceStage::AwaitingSignatures {
+			funding,
+			holder_signed_tx,
+			counterparty_signatures: None,
+			holder_signatures: None,
+		};
+		let (tx_signatures, _) = self.maybe_send_tx_signatures()?;
+		Ok((monitor_update, tx_signatures, unsigned_tx))
+	}
+
+	/// Provides the splice transaction with witnesses for all of our inputs, as previously given
+	/// to the user for signing. Returns the tx_signatures to send if it is our turn to sign, and
+	/// the fully-signed splice transaction if it is ready for broadcast.
+	pub fn splice_transaction_signed(&mut self, signed_tx: Transaction) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), APIError> {
+		match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { ref funding, ref mut holder_signed_tx, .. }, .. }) if holder_signed_tx.is_none() => {
+				if signed_tx.txid() != funding.funding_txo.txid {
+					return Err(APIError::APIMisuseError { err: "Signed transaction did not match the splice transaction".to_owned() });
+				}
+				for idx in funding.constructed.holder_input_indexes.iter() {
+					if signed_tx.input[*idx].witness.is_empty() {
+						return Err(APIError::APIMisuseError { err: format!("Input {} of the splice transaction was not signed", idx) });
+					}
+				}
+				*holder_signed_tx = Some(signed_tx);
+			},
+			_ => return Err(APIError::APIMisuseError { err: "No splice transaction is awaiting our signatures".to_owned() }),
+		}
+		self.maybe_send_tx_signatures().map_err(|e| APIError::APIMisuseError { err: format!("{:?}", e) })
+	}
+
+	/// Returns true if we should send our tx_signatures before our counterparty. The side
+	/// contributing the smaller total input value (ignoring the shared input) goes first, with the
+	/// side with the lesser funding pubkey going first if both contributed the same amount.
+	fn holder_sends_tx_signatures_first(&self, constructed: &ConstructedTransaction) -> bool {
+		if constructed.holder_inputs_value != constructed.counterparty_inputs_value {
+			return constructed.holder_inputs_value < constructed.counterparty_inputs_value;
+		}
+		self.holder_keys.pubkeys().funding_pubkey.serialize()[..] < self.counterparty_funding_pubkey().serialize()[..]
+	}
+
+	/// Sends our tx_signatures if we've signed our inputs and it's our turn, returning the
+	/// fully-signed splice transaction if we already have our counterparty's signatures.
+	fn maybe_send_tx_signatures(&mut self) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), ChannelError> {
+		let (tx_signatures, locking_splice) = match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { ref funding, holder_signed_tx: Some(ref holder_signed_tx), ref counterparty_signatures, holder_signatures: None }, holder_contribution_satoshis, .. }) => {
+				if counterparty_signatures.is_none() && !self.holder_sends_tx_signatures_first(&funding.constructed) {
+					return Ok((None, None));
+				}
+				let shared_input_signature = match funding.constructed.shared_input_index {
+					Some(idx) => Some(self.holder_keys.sign_splice_shared_input(&funding.constructed.tx, idx, &self.secp_ctx)
+						.map_err(|_| ChannelError::Close("Failed to sign the shared input of the splice transaction".to_owned()))?),
+					None => None,
+				};
+				let tx_signatures = msgs::TxSignatures {
+					channel_id: self.channel_id,
+					txid: funding.funding_txo.txid,
+					witnesses: funding.constructed.holder_input_indexes.iter().map(|idx| holder_signed_tx.input[*idx].witness.clone()).collect(),
+					shared_input_signature,
+				};
+				(tx_signatures.clone(), LockingSplice {
+					funding_txo: funding.funding_txo,
+					channel_value_satoshis: funding.channel_value_satoshis,
+					holder_contribution_satoshis,
+					holder_signatures: tx_signatures,
+					confirmed_in: None,
+					confirmation_height: 0,
+					short_channel_id: None,
+					locked_sent: false,
+					counterparty_locked: false,
+				})
+			},
+			_ => return Ok((None, None)),
+		};
+		// Once we've sent our signatures our counterparty may broadcast the splice transaction, so
+		// we have to watch for it to confirm from here on.
+		self.locking_splice = Some(locking_splice);
+		if let Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { ref mut holder_signatures, .. }, .. }) = self.pending_splice {
+			*holder_signatures = Some(tx_signatures.clone());
+		}
+		Ok((Some(tx_signatures), self.maybe_finalize_splice_transaction()))
+	}
+
+	/// Builds the fully-signed splice transaction if both sides have sent tx_signatures, after
+	/// which the splice is no longer being negotiated and only has to lock.
+	fn maybe_finalize_splice_transaction(&mut self) -> Option<Transaction> {
+		let tx = match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { ref funding, holder_signed_tx: Some(ref holder_signed_tx), counterparty_signatures: Some(ref counterparty_signatures), holder_signatures: Some(ref holder_signatures) }, .. }) => {
+				let mut tx = holder_signed_tx.clone();
+				let mut counterparty_witnesses = counterparty_signatures.witnesses.iter();
+				for (idx, input) in tx.input.iter_mut().enumerate() {
+					if Some(idx) == funding.constructed.shared_input_index {
+						let holder_sig = holder_signatures.shared_input_signature.unwrap();
+						let counterparty_sig = counterparty_signatures.shared_input_signature.unwrap();
+						input.witness.push(Vec::new()); // First is the multisig dummy
+						let funding_key = self.holder_keys.pubkeys().funding_pubkey.serialize();
+						let counterparty_funding_key = self.counterparty_funding_pubkey().serialize();
+						if funding_key[..] < counterparty_funding_key[..] {
+							input.witness.push(holder_sig.serialize_der().to_vec());
+							input.witness.push(counterparty_sig.serialize_der().to_vec());
+						} else {
+							input.witness.push(counterparty_sig.serialize_der().to_vec());
+							input.witness.push(holder_sig.serialize_der().to_vec());
+						}
+						input.witness[1].push(SigHashType::All as u8);
+						input.witness[2].push(SigHashType::All as u8);
+						input.witness.push(self.get_funding_redeemscript().into_bytes());
+					} else if !funding.constructed.holder_input_indexes.contains(&idx) {
+						input.witness = counterparty_witnesses.next().unwrap().clone();
+					}
+				}
+				tx
+			},
+			_ => return None,
+		};
+		self.pending_splice = None;
+		Some(tx)
+	}
+
+	/// Handles our counterparty's tx_signatures, returning our own tx_signatures to send if we
+	/// haven't already, and the fully-signed splice or funding transaction if it is ready for
+	/// broadcast.
+	pub fn tx_signatures(&mut self, msg: &msgs::TxSignatures) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), ChannelError> {
+		if self.pending_dual_funding.is_some() {
+			return self.dual_funding_tx_signatures(msg);
+		}
+		// We may have already received these before a reconnection, after which our counterparty
+		// retransmits them.
+		let duplicate = match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { counterparty_signatures: Some(ref counterparty_signatures), .. }, .. }) => *counterparty_signatures == *msg,
+			Some(_) => false,
+			None => self.locking_splice.as_ref().map(|splice| splice.funding_txo.txid == msg.txid).unwrap_or(false) ||
+				self.spliced_funding_txo.map(|txo| txo.txid) == Some(msg.txid),
+		};
+		if duplicate {
+			return Ok((None, None));
+		}
+		match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { ref funding, ref counterparty_signatures, ref holder_signatures, .. }, .. }) if counterparty_signatures.is_none() => {
+				if msg.txid != funding.funding_txo.txid {
+					return Err(ChannelError::Close("Peer sent tx_signatures for a different transaction".to_owned()));
+				}
+				if holder_signatures.is_none() && self.holder_sends_tx_signatures_first(&funding.constructed) {
+					return Err(ChannelError::Close("Peer sent tx_signatures before we sent ours".to_owned()));
+				}
+				let counterparty_inputs_count = funding.constructed.tx.input.len() - funding.constructed.holder_input_indexes.len() -
+					if funding.constructed.shared_input_index.is_some() { 1 } else { 0 };
+				if msg.witnesses.len() != counterparty_inputs_count || msg.witnesses.iter().any(|witness| witness.is_empty()) {
+					return Err(ChannelError::Close("Peer sent tx_signatures without a witness for each of its inputs".to_owned()));
+				}
+				if let Some(idx) = funding.constructed.shared_input_index {
+					let sig = match msg.shared_input_signature {
+						Some(sig) => sig,
+						None => return Err(ChannelError::Close("Peer sent tx_signatures without signing the shared input".to_owned())),
+					};
+					let sighash = hash_to_message!(&bip143::SigHashCache::new(&funding.constructed.tx).signature_hash(idx, &self.get_funding_redeemscript(), self.channel_value_satoshis, SigHashType::All)[..]);
+					secp_check!(self.secp_ctx.verify(&sighash, &sig, self.counterparty_funding_pubkey()), "Invalid shared input signature in tx_signatures from peer".to_owned());
+				}
+			},
+			_ => return Err(ChannelError::Close("Peer sent unexpected tx_signatures".to_owned())),
+		}
+		if let Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { ref mut counterparty_signatures, .. }, .. }) = self.pending_splice {
+			*counterparty_signatures = Some(msg.clone());
+		}
+
+		match self.maybe_finalize_splice_transaction() {
+			Some(tx) => Ok((None, Some(tx))),
+			None => self.maybe_send_tx_signatures(),
+		}
+	}
+
+	/// Handles a tx_abort from our counterparty, returning the tx_abort to echo back if we were
+	/// negotiating a splice or dual-funded channel open.
+	pub fn tx_abort(&mut self, msg: &msgs::TxAbort) -> Result<Option<msgs::TxAbort>, ChannelError> {
+		match self.pending_dual_funding {
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { holder_signatures: Some(_), .. }, .. }) =>
+				return Err(ChannelError::Close("Peer aborted the funding transaction after we'd sent our tx_signatures".to_owned())),
+			Some(_) => return Ok(Some(self.abort_interactive_tx(&String::from_utf8_lossy(&msg.data)))),
+			None => {},
+		}
+		match self.pending_splice {
+			Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { holder_signatures: Some(_), .. }, .. }) =>
+				Err(ChannelError::Close("Peer aborted a splice after we'd sent our tx_signatures".to_owned())),
+			Some(_) => Ok(Some(self.abort_interactive_tx(&String::from_utf8_lossy(&msg.data)))),
+			None => Ok(None),
+		}
+	}
+
+	/// Returns the splice_locked message to send if, with the given best block height, the
+	/// splice transaction has reached our minimum depth and we haven't yet sent one. Also returns
+	/// whether the channel moved to the new funding output as a result.
+	pub fn check_get_splice_locked(&mut self, height: u32) -> Option<(msgs::SpliceLocked, bool)> {
+		let splice_txid = match self.locking_splice {
+			Some(ref mut splice) if !splice.locked_sent && splice.confirmation_height != 0 && height >= splice.confirmation_height &&
+					height - splice.confirmation_height + 1 >= self.minimum_depth => {
+				splice.locked_sent = true;
+				splice.funding_txo.txid
+			},
+			_ => return None,
+		};
+		// If we never received our counterparty's tx_signatures they're no longer needed now that the
+		// splice transaction has confirmed.
+		if let Some(PendingSplice { stage: SpliceStage::AwaitingSignatures { ref funding, .. }, .. }) = self.pending_splice {
+			if funding.funding_txo.txid == splice_txid {
+				self.pending_splice = None;
+			}
+		}
+		let promoted = self.maybe_promote_locking_splice();
+		Some((msgs::SpliceLocked {
+			channel_id: self.channel_id,
+			splice_txid,
+		}, promoted))
+	}
+
+	/// Gets the tx_signatures to retransmit on reconnection, if we've sent ours for a splice
+	/// transaction and our counterparty has yet to send splice_locked for it.
+	pub fn get_splice_tx_signatures(&self) -> Option<msgs::TxSignatures> {
+		match self.locking_splice {
+			Some(ref splice) if !splice.counterparty_locked => Some(splice.holder_signatures.clone()),
+			_ => None,
+		}
+	}
+
+	/// Gets the splice_locked message to retransmit on reconnection, if we've sent one for a
+	/// splice which has yet to lock.
+	pub fn get_splice_locked(&self) -> Option<msgs::SpliceLocked> {
+		match self.locking_splice {
+			Some(ref splice) if splice.locked_sent => Some(msgs::SpliceLocked {
+				channel_id: self.channel_id,
+				splice_txid: splice.funding_txo.txid,
+			}),
+			_ => None,
+		}
+	}
+
+	/// Handles a splice_locked from our counterparty, returning true if the channel moved to the
+	/// new funding output as a result.
+	pub fn splice_locked(&mut self, msg: &msgs::SpliceLocked) -> Result<bool, ChannelError> {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent splice_locked when we needed a channel_reestablish".to_owned()));
+		}
+		match self.locking_splice {
+			Some(ref mut splice) if splice.funding_txo.txid == msg.splice_txid => {
+				splice.counterparty_locked = true;
+			},
+			_ => {
+				if self.spliced_funding_txo.map(|txo| txo.txid) == Some(msg.splice_txid) {
+					// They're retransmitting splice_locked after a reconnection.
+					return Ok(false);
+				}
+				return Err(ChannelError::Close("Peer sent splice_locked for an unknown splice".to_owned()));
+			},
+		}
+		Ok(self.maybe_promote_locking_splice())
+	}
+
+	/// Moves the channel to the funding output of the locking splice once both sides have sent
+	/// splice_locked, returning true if it did so.
+	fn maybe_promote_locking_splice(&mut self) -> bool {
+		match self.locking_splice {
+			Some(ref splice) if splice.locked_sent && splice.counterparty_locked => {},
+			_ => return false,
+		}
+		let (_, _, value_to_self_msat) = self.get_locking_splice_funding().unwrap();
+		let splice = self.locking_splice.take().unwrap();
+		self.spliced_funding_txo = Some(splice.funding_txo);
+		self.channel_value_satoshis = splice.channel_value_satoshis;
+		self.value_to_self_msat = value_to_self_msat;
+		self.holder_keys.on_splice(splice.channel_value_satoshis);
+		self.funding_tx_confirmed_in = splice.confirmed_in;
+		self.funding_tx_confirmation_height = splice.confirmation_height;
+		self.pre_splice_short_channel_id = self.short_channel_id;
+		self.short_channel_id = splice.short_channel_id;
+		#[cfg(debug_assertions)]
+		{
+			// Balances on the new funding output start over from the splice's contributions.
+			*self.holder_max_commitment_tx_output.lock().unwrap() = (0, 0);
+			*self.counterparty_max_commitment_tx_output.lock().unwrap() = (0, 0);
+		}
+		self.update_time_counter += 1;
+		true
+	}
+
+	/// Returns true if the given transaction is the splice transaction we're waiting on to lock,
+	/// which spends our current funding output without closing the channel.
+	pub fn is_locking_splice_transaction(&self, txid: &Txid) -> bool {
+		self.locking_splice.as_ref().map(|splice| splice.funding_txo.txid == *txid).unwrap_or(false)
+	}
+
+	/// Gets the txid of the splice transaction we're waiting on to lock, if it has confirmed.
+	pub fn get_confirmed_locking_splice_txid(&self) -> Option<Txid> {
+		match self.locking_splice {
+			Some(ref splice) if splice.confirmation_height != 0 => Some(splice.funding_txo.txid),
+			_ => None,
+		}
+	}
+
+	/// Handles the splice transaction we're waiting on to lock being reorganized out of the
+	/// chain. If we've yet to send splice_locked we simply wait for it to confirm again,
+	/// otherwise the channel must be closed.
+	fn splice_tx_unconfirmed(&mut self) -> bool {
+		if let Some(ref mut splice) = self.locking_splice {
+			if splice.locked_sent {
+				return true;
+			}
+			splice.confirmed_in = None;
+			splice.confirmation_height = 0;
+			splice.short_channel_id = None;
+		}
+		false
+	}
+
+	// Public utilities:
+
+	pub fn channel_id(&self) -> [u8; 32] {
+		self.channel_id
+	}
+
+	/// Gets the "user_id" value passed into the construction of this channel. It has no special
+	/// meaning and exists only to allow users to have a persistent identifier of a channel.
+	pub fn get_user_id(&self) -> u64 {
+		self.user_id
+	}
+
+	/// Guaranteed to be Some after both FundingLocked messages have been exchanged (and, thus,
+	/// is_usable() returns true).
+	/// Allowed in any state (including after shutdown)
+	pub fn get_short_channel_id(&self) -> Option<u64> {
+		self.short_channel_id
+	}
+
+	/// Gets our short_channel_id from before the most recent splice locked, if any.
+	pub fn get_pre_splice_short_channel_id(&self) -> Option<u64> {
+		self.pre_splice_short_channel_id
+	}
+
+	/// Gets all the short_channel_ids HTLCs may refer to this channel by.
+	pub fn get_short_channel_ids(&self) -> impl Iterator<Item = u64> {
+		self.short_channel_id.into_iter().chain(self.pre_splice_short_channel_id.into_iter())
+	}
+
+	/// Returns the funding_txo we either got from our peer, or were given by
+	/// get_outbound_funding_created.
+	pub fn get_funding_txo(&self) -> Option<OutPoint> {
+		self.funding_txo
+	}
+
+	/// Returns the funding output the channel's commitment transactions currently spend, which
+	/// differs from get_funding_txo once a splice has locked.
+	pub fn get_current_funding_txo(&self) -> Option<OutPoint> {
+		self.spliced_funding_txo.or(self.funding_txo)
+	}
+
+	/// Allowed in any state (including after shutdown)
+	pub fn get_counterparty_node_id(&self) -> PublicKey {
+		self.counterparty_node_id
+	}
+
+	/// Allowed in any state (including after shutdown)
+	#[cfg(test)]
+	pub fn get_holder_htlc_minimum_msat(&self) -> u64 {
+		self.holder_htlc_minimum_msat
+	}
+
+	/// Allowed in any state (including after shutdown)
+	pub fn get_announced_htlc_max_msat(&self) -> u64 {
+		return cmp::min(
+			// Upper bound by capacity. We make it a bit less than full capacity to prevent attempts
+			// to use full capacity. This is an effort to reduce routing failures, because in many cases
+			// channel might have been used to route very small values (either by honest users or as DoS).
+			self.channel_value_satoshis * 1000 * 9 / 10,
+
+			Channel::<ChanSigner>::get_holder_max_htlc_value_in_flight_msat(self.channel_value_satoshis)
+		);
+	}
+
+	/// Allowed in any state (including after shutdown)
+	pub fn get_counterparty_htlc_minimum_msat(&self) -> u64 {
+		self.counterparty_htlc_minimum_msat
+	}
+
+	pub fn get_value_satoshis(&self) -> u64 {
+		self.channel_value_satoshis
+	}
+
+	/// Gets whether this channel uses anchor outputs.
+	pub fn opt_anchors(&self) -> bool {
+		self.opt_anchors
+	}
+
+	pub fn get_fee_proportional_millionths(&self) -> u32 {
+		self.config.fee_proportional_millionths
+	}
+
+	#[cfg(test)]
+	pub fn get_feerate(&self) -> u32 {
+		self.feerate_per_kw
+	}
+
+	pub fn get_cur_holder_commitment_transaction_number(&self) -> u64 {
+		self.cur_holder_commitment_transaction_number + 1
+	}
+
+	pub fn get_cur_counterparty_commitment_transaction_number(&self) -> u64 {
+		self.cur_counterparty_commitment_transaction_number + 1 - if self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32) != 0 { 1 } else { 0 }
+	}
+
+	pub fn get_revoked_counterparty_commitment_transaction_number(&self) -> u64 {
+		self.cur_counterparty_commitment_transaction_number + 2
+	}
+
+	#[cfg(test)]
+	pub fn get_keys(&self) -> &ChanSigner {
+		&self.holder_keys
+	}
+
+	#[cfg(test)]
+	pub fn get_value_stat(&self) -> ChannelValueStat {
+		ChannelValueStat {
+			value_to_self_msat: self.value_to_self_msat,
+			channel_value_msat: self.channel_value_satoshis * 1000,
+			channel_reserve_msat: self.counterparty_selected_channel_reserve_satoshis * 1000,
+			pending_outbound_htlcs_amount_msat: self.pending_outbound_htlcs.iter().map(|ref h| h.amount_msat).sum::<u64>(),
+			pending_inbound_htlcs_amount_msat: self.pending_inbound_htlcs.iter().map(|ref h| h.amount_msat).sum::<u64>(),
+			holding_cell_outbound_amount_msat: {
+				let mut res = 0;
+				for h in self.holding_cell_htlc_updates.iter() {
+					match h {
+						&HTLCUpdateAwaitingACK::AddHTLC{amount_msat, .. } => {
+							res += amount_msat;
+						}
+						_ => {}
+					}
+				}
+				res
+			},
+			counterparty_max_htlc_value_in_flight_msat: self.counterparty_max_htlc_value_in_flight_msat,
 			counterparty_dust_limit_msat: self.counterparty_dust_limit_satoshis * 1000,
 		}
 	}
@@ -3228,7 +4905,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		// output value back into a transaction with the regular channel output:
 
 		// the fee cost of the HTLC-Success/HTLC-Timeout transaction:
-		let mut res = self.feerate_per_kw as u64 * cmp::max(HTLC_TIMEOUT_TX_WEIGHT, HTLC_SUCCESS_TX_WEIGHT) / 1000;
+		let mut res = self.feerate_per_kw as u64 * cmp::max(chan_utils::htlc_timeout_tx_weight(self.opt_anchors), chan_utils::htlc_success_tx_weight(self.opt_anchors)) / 1000;
 
 		if self.channel_outbound {
 			// + the marginal fee increase cost to us in the commitment transaction:
@@ -3271,6 +4948,24 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.channel_state >= ChannelState::FundingSent as u32
 	}
 
+	/// Returns the funding outpoint if we've generated the funding transaction (or constructed it,
+	/// for dual-funded channels) but it can't have been broadcast yet, ie if it should be discarded
+	/// should the channel be closed now.
+	pub fn get_unbroadcast_funding_txo(&self) -> Option<OutPoint> {
+		let funding_txo = match self.funding_txo {
+			Some(funding_txo) => funding_txo,
+			None => return None,
+		};
+		match self.pending_dual_funding {
+			// Once we've sent our tx_signatures our counterparty may broadcast the funding
+			// transaction at any time.
+			Some(PendingDualFunding { stage: DualFundingStage::AwaitingSignatures { holder_signatures: Some(_), .. }, .. }) => None,
+			Some(_) => Some(funding_txo),
+			None if self.channel_outbound && self.channel_state < ChannelState::FundingSent as u32 => Some(funding_txo),
+			None => None,
+		}
+	}
+
 	/// Returns true if this channel is fully shut down. True here implies that no further actions
 	/// may/will be taken on this channel, and thus this object should be freed. Any future changes
 	/// will be handled appropriately by the chain monitor.
@@ -3301,41 +4996,19 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.network_sync == UpdateStatus::DisabledMarked
 	}
 
-	/// When we receive a new block, we (a) check whether the block contains the funding
-	/// transaction (which would start us counting blocks until we send the funding_signed), and
-	/// (b) check the height of the block against outbound holding cell HTLCs in case we need to
-	/// give up on them prematurely and time them out. Everything else (e.g. commitment
-	/// transaction broadcasts, channel closure detection, HTLC transaction broadcasting, etc) is
-	/// handled by the ChannelMonitor.
+	/// Checks whether any of the given transactions, confirmed in the block with the given hash
+	/// and height, is our funding transaction, in which case we start counting its confirmations
+	/// from that block. May be called more than once for the same block.
+	///
+	/// Confirmations are only acted upon (ie funding_locked sent) in best_block_updated.
 	///
 	/// If we return Err, the channel may have been closed, at which point the standard
 	/// requirements apply - no calls may be made except those explicitly stated to be allowed
 	/// post-shutdown.
-	/// Only returns an ErrorAction of DisconnectPeer, if Err.
-	///
-	/// May return some HTLCs (and their payment_hash) which have timed out and should be failed
-	/// back.
-	pub fn block_connected(&mut self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], indexes_of_txn_matched: &[usize]) -> Result<(Option<msgs::FundingLocked>, Vec<(HTLCSource, PaymentHash)>), msgs::ErrorMessage> {
-		let mut timed_out_htlcs = Vec::new();
-		self.holding_cell_htlc_updates.retain(|htlc_update| {
-			match htlc_update {
-				&HTLCUpdateAwaitingACK::AddHTLC { ref payment_hash, ref source, ref cltv_expiry, .. } => {
-					if *cltv_expiry <= height + HTLC_FAIL_BACK_BUFFER {
-						timed_out_htlcs.push((source.clone(), payment_hash.clone()));
-						false
-					} else { true }
-				},
-				_ => true
-			}
-		});
+	pub fn transactions_confirmed(&mut self, block_hash: &BlockHash, height: u32, txdata: &[(usize, &Transaction)]) -> Result<(), msgs::ErrorMessage> {
 		let non_shutdown_state = self.channel_state & (!MULTI_STATE_FLAGS);
-		if header.block_hash() != self.last_block_connected {
-			if self.funding_tx_confirmations > 0 {
-				self.funding_tx_confirmations += 1;
-			}
-		}
 		if non_shutdown_state & !(ChannelState::TheirFundingLocked as u32) == ChannelState::FundingSent as u32 {
-			for (ref tx, index_in_block) in txn_matched.iter().zip(indexes_of_txn_matched) {
+			for &(index_in_block, tx) in txdata.iter() {
 				if tx.txid() == self.funding_txo.unwrap().txid {
 					let txo_idx = self.funding_txo.unwrap().index as usize;
 					if txo_idx >= tx.output.len() || tx.output[txo_idx].script_pubkey != self.get_funding_redeemscript().to_v0_p2wsh() ||
@@ -3366,82 +5039,167 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 								}
 							}
 						}
-						if height > 0xff_ff_ff || (*index_in_block) > 0xff_ff_ff {
+						if height > 0xff_ff_ff || index_in_block > 0xff_ff_ff {
 							panic!("Block was bogus - either height 16 million or had > 16 million transactions");
 						}
 						assert!(txo_idx <= 0xffff); // txo_idx is a (u16 as usize), so this is just listed here for completeness
-						self.funding_tx_confirmations = 1;
+						self.funding_tx_confirmation_height = height;
+						self.funding_tx_confirmed_in = Some(*block_hash);
 						self.short_channel_id = Some(((height as u64)          << (5*8)) |
-						                             ((*index_in_block as u64) << (2*8)) |
+						                             ((index_in_block as u64) << (2*8)) |
 						                             ((txo_idx as u64)         << (0*8)));
 					}
 				}
 			}
 		}
+		if let Some(ref mut splice) = self.locking_splice {
+			for &(index_in_block, tx) in txdata.iter() {
+				if tx.txid() == splice.funding_txo.txid {
+					if height > 0xff_ff_ff || index_in_block > 0xff_ff_ff {
+						panic!("Block was bogus - either height 16 million or had > 16 million transactions");
+					}
+					splice.confirmation_height = height;
+					splice.confirmed_in = Some(*block_hash);
+					splice.short_channel_id = Some(((height as u64)                    << (5*8)) |
+					                               ((index_in_block as u64)           << (2*8)) |
+					                               ((splice.funding_txo.index as u64) << (0*8)));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Called when a new best block is seen, with its height. Times out any outbound holding cell
+	/// HTLCs which are too close to expiry and, if the funding transaction has reached our
+	/// minimum depth as of this block, returns the funding_locked message to send.
+	///
+	/// May return some HTLCs (and their payment_hash) which have timed out and should be failed
+	/// back.
+	pub fn best_block_updated(&mut self, header: &BlockHeader, height: u32) -> (Option<msgs::FundingLocked>, Vec<(HTLCSource, PaymentHash)>) {
+		let mut timed_out_htlcs = Vec::new();
+		self.holding_cell_htlc_updates.retain(|htlc_update| {
+			match htlc_update {
+				&HTLCUpdateAwaitingACK::AddHTLC { ref payment_hash, ref source, ref cltv_expiry, .. } => {
+					if *cltv_expiry <= height + HTLC_FAIL_BACK_BUFFER {
+						timed_out_htlcs.push((source.clone(), payment_hash.clone()));
+						false
+					} else { true }
+				},
+				_ => true
+			}
+		});
 		if header.block_hash() != self.last_block_connected {
 			self.last_block_connected = header.block_hash();
 			self.update_time_counter = cmp::max(self.update_time_counter, header.time);
-			if self.funding_tx_confirmations > 0 {
-				if self.funding_tx_confirmations == self.minimum_depth as u64 {
-					let need_commitment_update = if non_shutdown_state == ChannelState::FundingSent as u32 {
-						self.channel_state |= ChannelState::OurFundingLocked as u32;
-						true
-					} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::TheirFundingLocked as u32) {
-						self.channel_state = ChannelState::ChannelFunded as u32 | (self.channel_state & MULTI_STATE_FLAGS);
-						self.update_time_counter += 1;
-						true
-					} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::OurFundingLocked as u32) {
-						// We got a reorg but not enough to trigger a force close, just update
-						// funding_tx_confirmed_in and return.
-						false
-					} else if self.channel_state < ChannelState::ChannelFunded as u32 {
-						panic!("Started confirming a channel in a state pre-FundingSent?: {}", self.channel_state);
-					} else {
-						// We got a reorg but not enough to trigger a force close, just update
-						// funding_tx_confirmed_in and return.
-						false
-					};
-					self.funding_tx_confirmed_in = Some(self.last_block_connected);
-
-					//TODO: Note that this must be a duplicate of the previous commitment point they sent us,
-					//as otherwise we will have a commitment transaction that they can't revoke (well, kinda,
-					//they can by sending two revoke_and_acks back-to-back, but not really). This appears to be
-					//a protocol oversight, but I assume I'm just missing something.
-					if need_commitment_update {
-						if self.channel_state & (ChannelState::MonitorUpdateFailed as u32) == 0 {
-							let next_per_commitment_point = self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number, &self.secp_ctx);
-							return Ok((Some(msgs::FundingLocked {
-								channel_id: self.channel_id,
-								next_per_commitment_point: next_per_commitment_point,
-							}), timed_out_htlcs));
-						} else {
-							self.monitor_pending_funding_locked = true;
-							return Ok((None, timed_out_htlcs));
-						}
-					}
-				}
+		}
+		(self.check_get_funding_locked(height), timed_out_htlcs)
+	}
+
+	/// Returns true if this is an inbound channel whose funding transaction has failed to confirm
+	/// within FUNDING_CONF_DEADLINE_BLOCKS of our receiving funding_created, as of the given best
+	/// block height, in which case the channel should be closed.
+	pub fn funding_timed_out(&self, height: u32) -> bool {
+		if self.channel_outbound || self.funding_tx_confirmation_height != 0 {
+			return false;
+		}
+		if self.channel_state & !(MULTI_STATE_FLAGS | ChannelState::TheirFundingLocked as u32) != ChannelState::FundingSent as u32 {
+			return false;
+		}
+		match self.funding_created_height {
+			Some(funding_created_height) => height >= funding_created_height + FUNDING_CONF_DEADLINE_BLOCKS,
+			None => false,
+		}
+	}
+
+	/// Returns the funding_locked message to send if, with the given best block height, the
+	/// funding transaction has reached our minimum depth and we haven't yet sent one.
+	fn check_get_funding_locked(&mut self, height: u32) -> Option<msgs::FundingLocked> {
+		if self.funding_tx_confirmation_height == 0 || height < self.funding_tx_confirmation_height {
+			return None;
+		}
+		if height - self.funding_tx_confirmation_height + 1 < self.minimum_depth {
+			return None;
+		}
+		let non_shutdown_state = self.channel_state & (!MULTI_STATE_FLAGS);
+		let need_commitment_update = if non_shutdown_state == ChannelState::FundingSent as u32 {
+			self.channel_state |= ChannelState::OurFundingLocked as u32;
+			true
+		} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::TheirFundingLocked as u32) {
+			self.channel_state = ChannelState::ChannelFunded as u32 | (self.channel_state & MULTI_STATE_FLAGS);
+			self.update_time_counter += 1;
+			true
+		} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::OurFundingLocked as u32) {
+			// We already sent funding_locked and are waiting on theirs.
+			false
+		} else if self.channel_state < ChannelState::ChannelFunded as u32 {
+			panic!("Started confirming a channel in a state pre-FundingSent?: {}", self.channel_state);
+		} else {
+			// We're already funded (or closing), nothing to do.
+			false
+		};
+
+		//TODO: Note that this must be a duplicate of the previous commitment point they sent us,
+		//as otherwise we will have a commitment transaction that they can't revoke (well, kinda,
+		//they can by sending two revoke_and_acks back-to-back, but not really). This appears to be
+		//a protocol oversight, but I assume I'm just missing something.
+		if need_commitment_update {
+			if self.channel_state & (ChannelState::MonitorUpdateFailed as u32) == 0 {
+				let next_per_commitment_point = self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number, &self.secp_ctx);
+				return Some(msgs::FundingLocked {
+					channel_id: self.channel_id,
+					next_per_commitment_point: next_per_commitment_point,
+				});
+			} else {
+				self.monitor_pending_funding_locked = true;
 			}
 		}
-		Ok((None, timed_out_htlcs))
+		None
 	}
 
 	/// Called by channelmanager based on chain blocks being disconnected.
 	/// Returns true if we need to close the channel now due to funding transaction
 	/// unconfirmation/reorg.
-	pub fn block_disconnected(&mut self, header: &BlockHeader) -> bool {
-		if self.funding_tx_confirmations > 0 {
-			self.funding_tx_confirmations -= 1;
-			if self.funding_tx_confirmations == UNCONF_THRESHOLD as u64 {
-				return true;
-			}
+	pub fn block_disconnected(&mut self, header: &BlockHeader, height: u32) -> bool {
+		// Our best block is now the disconnected block's parent.
+		self.last_block_connected = header.prev_blockhash;
+		if height == self.funding_tx_confirmation_height && Some(header.block_hash()) == self.funding_tx_confirmed_in {
+			return self.funding_tx_unconfirmed();
 		}
-		self.last_block_connected = header.block_hash();
-		if Some(self.last_block_connected) == self.funding_tx_confirmed_in {
-			self.funding_tx_confirmations = self.minimum_depth as u64 - 1;
+		match self.locking_splice {
+			Some(LockingSplice { confirmation_height, confirmed_in, .. }) if height == confirmation_height && Some(header.block_hash()) == confirmed_in =>
+				self.splice_tx_unconfirmed(),
+			_ => false,
 		}
+	}
+
+	/// Called when the transaction with the given txid is no longer confirmed, eg due to a reorg.
+	/// Returns true if we need to close the channel now as it was our funding transaction.
+	pub fn transaction_unconfirmed(&mut self, txid: &Txid) -> bool {
+		match self.get_current_funding_txo() {
+			Some(funding_txo) if funding_txo.txid == *txid && self.funding_tx_confirmation_height != 0 => self.funding_tx_unconfirmed(),
+			_ => if self.get_confirmed_locking_splice_txid() == Some(*txid) { self.splice_tx_unconfirmed() } else { false },
+		}
+	}
+
+	/// Handles our funding transaction being reorganized out of the chain. If we've yet to send
+	/// funding_locked we simply wait for it to confirm again, otherwise the channel must be
+	/// closed.
+	fn funding_tx_unconfirmed(&mut self) -> bool {
+		if self.channel_state & (ChannelState::OurFundingLocked as u32) != 0 || self.channel_state & !MULTI_STATE_FLAGS >= ChannelState::ChannelFunded as u32 {
+			return true;
+		}
+		self.funding_tx_confirmation_height = 0;
+		self.funding_tx_confirmed_in = None;
+		self.short_channel_id = None;
 		false
 	}
 
+	/// Gets the height of the block in which the funding transaction was confirmed, or 0 if it
+	/// is not confirmed.
+	pub fn get_funding_tx_confirmation_height(&self) -> u32 {
+		self.funding_tx_confirmation_height
+	}
+
 	// Methods to get unprompted messages to send to the remote end (or where we already returned
 	// something in the handler for the message that prompted this message):
 
@@ -3539,6 +5297,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state != (ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32) {
 			panic!("Tried to get a funding_created messsage at a time other than immediately after initial handshake completion (or tried to get funding_created twice)");
 		}
+		if self.pending_dual_funding.is_some() {
+			panic!("Tried to get a funding_created message for a dual-funded channel");
+		}
 		if self.commitment_secrets.get_min_seen_secret() != (1 << 48) ||
 				self.cur_counterparty_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER ||
 				self.cur_holder_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER {
@@ -3673,6 +5434,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if (self.channel_state & (ChannelState::ChannelFunded as u32 | BOTH_SIDES_SHUTDOWN_MASK)) != (ChannelState::ChannelFunded as u32) {
 			return Err(ChannelError::Ignore("Cannot send HTLC until channel is fully established and we haven't started shutting down".to_owned()));
 		}
+		if self.is_quiescent() {
+			return Err(ChannelError::Ignore("Cannot send HTLC while the channel is quiescent for a splice".to_owned()));
+		}
 		let channel_total_msat = self.channel_value_satoshis * 1000;
 		if amount_msat > channel_total_msat {
 			return Err(ChannelError::Ignore(format!("Cannot send amount {}, because it is more than the total value of the channel {}", amount_msat, channel_total_msat)));
@@ -3717,7 +5481,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		}
 
-		let pending_value_to_self_msat = self.value_to_self_msat - htlc_outbound_value_msat;
+		let pending_value_to_self_msat = (self.value_to_self_msat - htlc_outbound_value_msat).saturating_sub(self.get_locking_splice_balance_reductions_msat().0);
 		if pending_value_to_self_msat < amount_msat {
 			return Err(ChannelError::Ignore(format!("Cannot send value that would overdraw remaining funds. Amount: {}, pending value to self {}", amount_msat, pending_value_to_self_msat)));
 		}
@@ -3829,18 +5593,20 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 		self.resend_order = RAACommitmentOrder::RevokeAndACKFirst;
 
-		let (res, counterparty_commitment_tx, htlcs) = match self.send_commitment_no_state_update(logger) {
-			Ok((res, (counterparty_commitment_tx, mut htlcs))) => {
+		let (res, counterparty_commitment_tx, htlcs, splice_commitment_tx) = match self.send_commitment_no_state_update(logger) {
+			Ok((res, (counterparty_commitment_tx, mut htlcs), splice_commitment_tx)) => {
 				// Update state now that we've passed all the can-fail calls...
 				let htlcs_no_ref: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)> =
 					htlcs.drain(..).map(|(htlc, htlc_source)| (htlc, htlc_source.map(|source_ref| Box::new(source_ref.clone())))).collect();
-				(res, counterparty_commitment_tx, htlcs_no_ref)
+				let splice_commitment_tx = splice_commitment_tx.map(|(tx, htlcs)|
+					(tx, htlcs.into_iter().map(|(htlc, htlc_source)| (htlc, htlc_source.map(|source_ref| Box::new(source_ref.clone())))).collect()));
+				(res, counterparty_commitment_tx, htlcs_no_ref, splice_commitment_tx)
 			},
 			Err(e) => return Err(e),
 		};
 
 		self.latest_monitor_update_id += 1;
-		let monitor_update = ChannelMonitorUpdate {
+		let mut monitor_update = ChannelMonitorUpdate {
 			update_id: self.latest_monitor_update_id,
 			updates: vec![ChannelMonitorUpdateStep::LatestCounterpartyCommitmentTXInfo {
 				unsigned_commitment_tx: counterparty_commitment_tx.clone(),
@@ -3849,13 +5615,18 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				their_revocation_point: self.counterparty_cur_commitment_point.unwrap()
 			}]
 		};
+		if let Some((unsigned_commitment_tx, htlc_outputs)) = splice_commitment_tx {
+			monitor_update.updates.push(ChannelMonitorUpdateStep::SpliceCounterpartyCommitmentTXInfo { unsigned_commitment_tx, htlc_outputs });
+		}
 		self.channel_state |= ChannelState::AwaitingRemoteRevoke as u32;
 		Ok((res, monitor_update))
 	}
 
 	/// Only fails in case of bad keys. Used for channel_reestablish commitment_signed generation
 	/// when we shouldn't change HTLC/channel state.
-	fn send_commitment_no_state_update<L: Deref>(&self, logger: &L) -> Result<(msgs::CommitmentSigned, (Transaction, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>)), ChannelError> where L::Target: Logger {
+	/// While a splice is locking, also returns the counterparty commitment transaction we signed
+	/// spending its funding output.
+	fn send_commitment_no_state_update<L: Deref>(&self, logger: &L) -> Result<(msgs::CommitmentSigned, (Transaction, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>), Option<(Transaction, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>)>), ChannelError> where L::Target: Logger {
 		let mut feerate_per_kw = self.feerate_per_kw;
 		if let Some(feerate) = self.pending_update_fee {
 			if self.channel_outbound {
@@ -3887,18 +5658,34 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 			for (ref htlc_sig, ref htlc) in htlc_signatures.iter().zip(htlcs) {
 				log_trace!(logger, "Signed remote HTLC tx {} with redeemscript {} with pubkey {} -> {}",
-					encode::serialize_hex(&chan_utils::build_htlc_transaction(&counterparty_commitment_tx.0.txid(), feerate_per_kw, self.holder_selected_contest_delay, htlc, &counterparty_keys.broadcaster_delayed_payment_key, &counterparty_keys.revocation_key)),
-					encode::serialize_hex(&chan_utils::get_htlc_redeemscript(&htlc, counterparty_keys)),
+					encode::serialize_hex(&chan_utils::build_htlc_transaction(&counterparty_commitment_tx.0.txid(), feerate_per_kw, self.holder_selected_contest_delay, htlc, self.opt_anchors, &counterparty_keys.broadcaster_delayed_payment_key, &counterparty_keys.revocation_key)),
+					encode::serialize_hex(&chan_utils::get_htlc_redeemscript(&htlc, self.opt_anchors, counterparty_keys)),
 					log_bytes!(counterparty_keys.broadcaster_htlc_key.serialize()),
 					log_bytes!(htlc_sig.serialize_compact()[..]));
 			}
 		}
 
+		let (splice_signatures, splice_commitment_tx) = match self.get_locking_splice_funding() {
+			Some(funding) => {
+				let counterparty_keys = self.build_remote_transaction_keys()?;
+				let splice_commitment_tx = self.build_commitment_transaction_for_funding(Some(funding), self.cur_counterparty_commitment_transaction_number, &counterparty_keys, false, true, feerate_per_kw, logger);
+				let htlcs: Vec<&HTLCOutputInCommitment> = splice_commitment_tx.2.iter().map(|&(ref htlc, _)| htlc).collect();
+				let mut splice_keys = self.holder_keys.clone();
+				splice_keys.on_splice(funding.1);
+				let (signature, htlc_signatures) = splice_keys.sign_counterparty_commitment(feerate_per_kw, &splice_commitment_tx.0, &PreCalculatedTxCreationKeys::new(counterparty_keys), &htlcs, &self.secp_ctx)
+					.map_err(|_| ChannelError::Close("Failed to get signatures for new splice commitment_signed".to_owned()))?;
+				log_trace!(logger, "Signed remote commitment tx {} spending splice funding output {}:{}", splice_commitment_tx.0.txid(), funding.0.txid, funding.0.index);
+				(Some(msgs::SpliceCommitmentSignatures { signature, htlc_signatures }), Some((splice_commitment_tx.0, splice_commitment_tx.2)))
+			},
+			None => (None, None),
+		};
+
 		Ok((msgs::CommitmentSigned {
 			channel_id: self.channel_id,
 			signature,
 			htlc_signatures,
-		}, (counterparty_commitment_tx.0, counterparty_commitment_tx.2)))
+			splice_signatures,
+		}, (counterparty_commitment_tx.0, counterparty_commitment_tx.2), splice_commitment_tx))
 	}
 
 	/// Adds a pending outbound HTLC to this channel, and creates a signed commitment transaction
@@ -3917,7 +5704,14 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	/// Begins the shutdown process, getting a message for the remote peer and returning all
 	/// holding cell HTLCs for payment failure.
-	pub fn get_shutdown(&mut self) -> Result<(msgs::Shutdown, Vec<(HTLCSource, PaymentHash)>), APIError> {
+	///
+	/// If provided, shutdown_script overrides the script we close to (which must be one which the
+	/// counterparty accepts, see is_valid_shutdown_script) and fee_range limits the fee we'll
+	/// negotiate for the closing transaction.
+	pub fn get_shutdown(&mut self, shutdown_script: Option<Script>, fee_range: Option<msgs::ClosingSignedFeeRange>) -> Result<(msgs::Shutdown, Vec<(HTLCSource, PaymentHash)>), APIError> {
+		if self.is_quiescent() || self.locking_splice.is_some() {
+			return Err(APIError::ChannelUnavailable{err: "Cannot begin shutdown while a splice is in progress".to_owned()});
+		}
 		for htlc in self.pending_outbound_htlcs.iter() {
 			if let OutboundHTLCState::LocalAnnounced(_) = htlc.state {
 				return Err(APIError::APIMisuseError{err: "Cannot begin shutdown with pending HTLCs. Process pending events first".to_owned()});
@@ -3936,9 +5730,31 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			return Err(APIError::ChannelUnavailable{err: "Cannot begin shutdown while peer is disconnected or we're waiting on a monitor update, maybe force-close instead?".to_owned()});
 		}
 
-		let closing_script = self.get_closing_scriptpubkey();
+		if let Some(ref script) = shutdown_script {
+			if !is_valid_shutdown_script(script) {
+				return Err(APIError::IncompatibleShutdownScript { script: script.clone() });
+			}
+			// If we committed to an upfront shutdown script, BOLT 2 requires we close to it.
+			if self.config.commit_upfront_shutdown_pubkey && *script != self.get_closing_scriptpubkey() {
+				return Err(APIError::APIMisuseError{err: "Cannot close to a different script than the upfront shutdown script committed to at channel open".to_owned()});
+			}
+		}
+		if let Some(ref range) = fee_range {
+			if range.min_fee_satoshis > range.max_fee_satoshis {
+				return Err(APIError::APIMisuseError{err: format!("Closing fee range minimum ({}) is greater than its maximum ({})", range.min_fee_satoshis, range.max_fee_satoshis)});
+			}
+			if range.max_fee_satoshis > self.channel_value_satoshis {
+				return Err(APIError::APIMisuseError{err: format!("Closing fee range maximum ({}) is greater than the channel value ({})", range.max_fee_satoshis, self.channel_value_satoshis)});
+			}
+		}
 
 		// From here on out, we may not fail!
+		if shutdown_script.is_some() {
+			self.holder_shutdown_scriptpubkey = shutdown_script;
+		}
+		self.closing_fee_limits = fee_range;
+		let closing_script = self.get_closing_scriptpubkey();
+
 		if self.channel_state < ChannelState::FundingSent as u32 {
 			self.channel_state = ChannelState::ShutdownComplete as u32;
 		} else {
@@ -3970,9 +5786,12 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// shutdown of this channel - no more calls into this Channel may be made afterwards except
 	/// those explicitly stated to be allowed after shutdown completes, eg some simple getters).
 	/// Also returns the list of payment_hashes for channels which we can safely fail backwards
-	/// immediately (others we will have to allow to time out).
-	pub fn force_shutdown(&mut self, should_broadcast: bool) -> (Option<OutPoint>, ChannelMonitorUpdate, Vec<(HTLCSource, PaymentHash)>) {
+	/// immediately (others we will have to allow to time out), and the channel_id and user_id of
+	/// the channel along with the funding outpoint of a funding transaction which will now never
+	/// be broadcast, if any.
+	pub fn force_shutdown(&mut self, should_broadcast: bool) -> (Option<OutPoint>, ChannelMonitorUpdate, Vec<(HTLCSource, PaymentHash)>, ([u8; 32], u64, Option<OutPoint>)) {
 		assert!(self.channel_state != ChannelState::ShutdownComplete as u32);
+		let closed_channel = (self.channel_id, self.user_id, self.get_unbroadcast_funding_txo());
 
 		// We go ahead and "free" any holding cell HTLCs or HTLCs we haven't yet committed to and
 		// return them to fail the payment.
@@ -3992,18 +5811,25 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			//which correspond)
 		}
 
+		// We only hand the counterparty our funding_created once we've generated the funding
+		// transaction, and only create a ChannelMonitor on receipt of their funding_signed, so
+		// there's no monitor to update before then.
+		let monitor_funding_txo = if self.pending_dual_funding.is_none() && self.channel_state < ChannelState::FundingSent as u32 {
+			None
+		} else { self.funding_txo.clone() };
+
 		self.channel_state = ChannelState::ShutdownComplete as u32;
 		self.update_time_counter += 1;
 		self.latest_monitor_update_id += 1;
-		(self.funding_txo.clone(), ChannelMonitorUpdate {
+		(monitor_funding_txo, ChannelMonitorUpdate {
 			update_id: self.latest_monitor_update_id,
 			updates: vec![ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast }],
-		}, dropped_outbound_htlcs)
+		}, dropped_outbound_htlcs, closed_channel)
 	}
 }
 
-const SERIALIZATION_VERSION: u8 = 1;
-const MIN_SERIALIZATION_VERSION: u8 = 1;
+const SERIALIZATION_VERSION: u8 = 2;
+const MIN_SERIALIZATION_VERSION: u8 = 2;
 
 impl Writeable for InboundHTLCRemovalReason {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
@@ -4052,6 +5878,7 @@ impl<ChanSigner: ChannelKeys + Writeable> Writeable for Channel<ChanSigner> {
 		(self.channel_state | ChannelState::PeerDisconnected as u32).write(writer)?;
 		self.channel_outbound.write(writer)?;
 		self.channel_value_satoshis.write(writer)?;
+		self.opt_anchors.write(writer)?;
 
 		self.latest_monitor_update_id.write(writer)?;
 
@@ -4197,7 +6024,8 @@ impl<ChanSigner: ChannelKeys + Writeable> Writeable for Channel<ChanSigner> {
 		self.short_channel_id.write(writer)?;
 
 		self.last_block_connected.write(writer)?;
-		self.funding_tx_confirmations.write(writer)?;
+		self.funding_tx_confirmation_height.write(writer)?;
+		self.funding_created_height.write(writer)?;
 
 		self.counterparty_dust_limit_satoshis.write(writer)?;
 		self.holder_dust_limit_satoshis.write(writer)?;
@@ -4217,15 +6045,23 @@ impl<ChanSigner: ChannelKeys + Writeable> Writeable for Channel<ChanSigner> {
 		self.counterparty_node_id.write(writer)?;
 
 		self.counterparty_shutdown_scriptpubkey.write(writer)?;
+		self.holder_shutdown_scriptpubkey.write(writer)?;
+		self.closing_fee_limits.write(writer)?;
 
 		self.commitment_secrets.write(writer)?;
+
+		self.spliced_funding_txo.write(writer)?;
+		self.locking_splice.write(writer)?;
+		self.pre_splice_short_channel_id.write(writer)?;
 		Ok(())
 	}
 }
 
-impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
-	fn read<R : ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
-		let _ver: u8 = Readable::read(reader)?;
+/// Channels are read with the height of the ChannelManager's best block, which is also the block
+/// they were last connected to, to convert fields of older versions which were relative to it.
+impl<ChanSigner: ChannelKeys + Readable> ReadableArgs<u32> for Channel<ChanSigner> {
+	fn read<R : ::std::io::Read>(reader: &mut R, best_block_height: u32) -> Result<Self, DecodeError> {
+		let ver: u8 = Readable::read(reader)?;
 		let min_ver: u8 = Readable::read(reader)?;
 		if min_ver > SERIALIZATION_VERSION {
 			return Err(DecodeError::UnknownVersion);
@@ -4236,8 +6072,9 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 
 		let channel_id = Readable::read(reader)?;
 		let channel_state = Readable::read(reader)?;
-		let channel_outbound = Readable::read(reader)?;
+		let channel_outbound: bool = Readable::read(reader)?;
 		let channel_value_satoshis = Readable::read(reader)?;
+		let opt_anchors = if ver >= 2 { Readable::read(reader)? } else { false };
 
 		let latest_monitor_update_id = Readable::read(reader)?;
 
@@ -4351,7 +6188,21 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 		let short_channel_id = Readable::read(reader)?;
 
 		let last_block_connected = Readable::read(reader)?;
-		let funding_tx_confirmations = Readable::read(reader)?;
+		let funding_tx_confirmation_height = if ver >= 2 {
+			Readable::read(reader)?
+		} else {
+			// Versions before 2 stored the number of confirmations instead.
+			let funding_tx_confirmations: u64 = Readable::read(reader)?;
+			if funding_tx_confirmations == 0 { 0 } else {
+				(best_block_height as u64 + 1).checked_sub(funding_tx_confirmations).ok_or(DecodeError::InvalidValue)? as u32
+			}
+		};
+		let funding_created_height = if ver >= 2 {
+			Readable::read(reader)?
+		} else if !channel_outbound && funding_tx_confirmation_height == 0 {
+			// We didn't track when we received funding_created, so start the deadline over from now.
+			Some(best_block_height)
+		} else { None };
 
 		let counterparty_dust_limit_satoshis = Readable::read(reader)?;
 		let holder_dust_limit_satoshis = Readable::read(reader)?;
@@ -4371,8 +6222,15 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 		let counterparty_node_id = Readable::read(reader)?;
 
 		let counterparty_shutdown_scriptpubkey = Readable::read(reader)?;
+		let (holder_shutdown_scriptpubkey, closing_fee_limits) = if ver >= 2 {
+			(Readable::read(reader)?, Readable::read(reader)?)
+		} else { (None, None) };
 		let commitment_secrets = Readable::read(reader)?;
 
+		let (spliced_funding_txo, locking_splice, pre_splice_short_channel_id) = if ver >= 2 {
+			(Readable::read(reader)?, Readable::read(reader)?, Readable::read(reader)?)
+		} else { (None, None, None) };
+
 		Ok(Channel {
 			user_id,
 
@@ -4382,6 +6240,7 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 			channel_outbound,
 			secp_ctx: Secp256k1::new(),
 			channel_value_satoshis,
+			opt_anchors,
 
 			latest_monitor_update_id,
 
@@ -4418,12 +6277,19 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 			counterparty_max_commitment_tx_output: ::std::sync::Mutex::new((0, 0)),
 
 			last_sent_closing_fee,
+			closing_fee_limits,
 
 			funding_txo,
+			spliced_funding_txo,
+			pending_splice: None,
+			locking_splice,
+			pending_dual_funding: None,
 			funding_tx_confirmed_in,
 			short_channel_id,
+			pre_splice_short_channel_id,
 			last_block_connected,
-			funding_tx_confirmations,
+			funding_tx_confirmation_height,
+			funding_created_height,
 
 			counterparty_dust_limit_satoshis,
 			holder_dust_limit_satoshis,
@@ -4443,6 +6309,7 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for Channel<ChanSigner> {
 			counterparty_node_id,
 
 			counterparty_shutdown_scriptpubkey,
+			holder_shutdown_scriptpubkey,
 
 			commitment_secrets,
 
@@ -4582,10 +6449,10 @@ mod tests {
 		}]};
 		let funding_outpoint = OutPoint{ txid: tx.txid(), index: 0 };
 		let funding_created_msg = node_a_chan.get_outbound_funding_created(funding_outpoint, &&logger).unwrap();
-		let (funding_signed_msg, _) = node_b_chan.funding_created(&funding_created_msg, &&logger).unwrap();
+		let (funding_signed_msg, _) = node_b_chan.funding_created(&funding_created_msg, (Default::default(), 0), &&logger).unwrap();
 
 		// Node B --> Node A: funding signed
-		let _ = node_a_chan.funding_signed(&funding_signed_msg, &&logger);
+		let _ = node_a_chan.funding_signed(&funding_signed_msg, (Default::default(), 0), &&logger);
 
 		// Now disconnect the two nodes and check that the commitment point in
 		// Node B's channel_reestablish message is sane.
@@ -4656,7 +6523,7 @@ mod tests {
 			delayed_payment_basepoint: public_from_secret_hex(&secp_ctx, "1552dfba4f6cf29a62a0af13c8d6981d36d0ef8d61ba10fb0fe90da7634d7e13"),
 			htlc_basepoint: public_from_secret_hex(&secp_ctx, "4444444444444444444444444444444444444444444444444444444444444444")
 		};
-		chan_keys.on_accept(&counterparty_pubkeys, chan.counterparty_selected_contest_delay, chan.holder_selected_contest_delay);
+		chan_keys.on_accept(&counterparty_pubkeys, chan.counterparty_selected_contest_delay, chan.holder_selected_contest_delay, false);
 
 		assert_eq!(counterparty_pubkeys.payment_point.serialize()[..],
 		           hex::decode("032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991").unwrap()[..]);
@@ -4705,7 +6572,7 @@ mod tests {
 				})*
 				assert_eq!(unsigned_tx.1.len(), per_htlc.len());
 
-				holdertx = HolderCommitmentTransaction::new_missing_holder_sig(unsigned_tx.0.clone(), counterparty_signature.clone(), &chan_keys.pubkeys().funding_pubkey, chan.counterparty_funding_pubkey(), keys.clone(), chan.feerate_per_kw, per_htlc);
+				holdertx = HolderCommitmentTransaction::new_missing_holder_sig(unsigned_tx.0.clone(), counterparty_signature.clone(), &chan_keys.pubkeys().funding_pubkey, chan.counterparty_funding_pubkey(), keys.clone(), chan.feerate_per_kw, false, per_htlc);
 				let holder_sig = chan_keys.sign_holder_commitment(&holdertx, &chan.secp_ctx).unwrap();
 				assert_eq!(Signature::from_der(&hex::decode($sig_hex).unwrap()[..]).unwrap(), holder_sig);
 
@@ -4720,7 +6587,7 @@ mod tests {
 
 					let ref htlc = unsigned_tx.1[$htlc_idx];
 					let htlc_tx = chan.build_htlc_transaction(&unsigned_tx.0.txid(), &htlc, true, &keys, chan.feerate_per_kw);
-					let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys);
+					let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, false, &keys);
 					let htlc_sighash = Message::from_slice(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, SigHashType::All)[..]).unwrap();
 					secp_ctx.verify(&htlc_sighash, &remote_signature, &keys.countersignatory_htlc_key).unwrap();
 
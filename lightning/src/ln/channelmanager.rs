@@ -18,7 +18,8 @@
 //! imply it needs to fail HTLCs/payments/channels it manages).
 
 use bitcoin::blockdata::block::BlockHeader;
-use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::blockdata::transaction::{Transaction, TxOut};
+use bitcoin::blockdata::script::Script;
 use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::network::constants::Network;
 
@@ -27,16 +28,18 @@ use bitcoin::hashes::hmac::{Hmac, HmacEngine};
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 use bitcoin::hashes::cmp::fixed_time_eq;
-use bitcoin::hash_types::BlockHash;
+use bitcoin::hash_types::{BlockHash, Txid};
 
 use bitcoin::secp256k1::key::{SecretKey,PublicKey};
 use bitcoin::secp256k1::Secp256k1;
 use bitcoin::secp256k1::ecdh::SharedSecret;
 use bitcoin::secp256k1;
 
-use chain::chaininterface::{BroadcasterInterface,ChainListener,FeeEstimator};
+use chain::chaininterface::{BroadcasterInterface,ChainListener,ConfirmationListener,FeeEstimator};
 use chain::transaction::OutPoint;
+use ln::channel;
 use ln::channel::{Channel, ChannelError};
+use ln::interactivetxs::InteractiveTxMessageSend;
 use ln::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateErr, ManyChannelMonitor, HTLC_FAIL_BACK_BUFFER, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ANTI_REORG_DELAY, MonitorEvent};
 use ln::features::{InitFeatures, NodeFeatures};
 use routing::router::{Route, RouteHop};
@@ -46,7 +49,7 @@ use ln::onion_utils;
 use ln::msgs::{ChannelMessageHandler, DecodeError, LightningError, OptionalField};
 use chain::keysinterface::{ChannelKeys, KeysInterface, KeysManager, InMemoryChannelKeys};
 use util::config::UserConfig;
-use util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
+use util::events::{ClosureReason, Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
 use util::{byte_utils, events};
 use util::ser::{Readable, ReadableArgs, MaybeReadable, Writeable, Writer};
 use util::chacha20::{ChaCha20, ChaChaReader};
@@ -56,9 +59,9 @@ use util::errors::APIError;
 use std::{cmp, mem};
 use std::collections::{HashMap, hash_map, HashSet};
 use std::io::{Cursor, Read};
-use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::marker::{Sync, Send};
 use std::ops::Deref;
 use bitcoin::hashes::hex::ToHex;
@@ -88,6 +91,7 @@ enum PendingHTLCRouting {
 	},
 	Receive {
 		payment_data: Option<msgs::FinalOnionHopData>,
+		keysend_preimage: Option<PaymentPreimage>, // Set for spontaneous payments, already checked against the payment hash
 		incoming_cltv_expiry: u32, // Used to track when we should expire pending HTLCs that go unclaimed
 	},
 }
@@ -192,7 +196,7 @@ pub struct PaymentPreimage(pub [u8;32]);
 #[derive(Hash, Copy, Clone, PartialEq, Eq, Debug)]
 pub struct PaymentSecret(pub [u8;32]);
 
-type ShutdownResult = (Option<OutPoint>, ChannelMonitorUpdate, Vec<(HTLCSource, PaymentHash)>);
+type ShutdownResult = (Option<OutPoint>, ChannelMonitorUpdate, Vec<(HTLCSource, PaymentHash)>, ([u8; 32], u64, Option<OutPoint>));
 
 /// Error type returned across the channel_state mutex boundary. When an Err is generated for a
 /// Channel, we generally end up with a ChannelError::Close for which we have to close the channel
@@ -347,6 +351,99 @@ pub type SimpleArcChannelManager<M, T, F, L> = Arc<ChannelManager<InMemoryChanne
 /// concrete type of the KeysManager.
 pub type SimpleRefChannelManager<'a, 'b, 'c, 'd, 'e, M, T, F, L> = ChannelManager<InMemoryChannelKeys, &'a M, &'b T, &'c KeysManager, &'d F, &'e L>;
 
+/// Mechanism for signalling to the user that the ChannelManager has been updated and should be
+/// re-persisted. Waiters block on a condition variable until `notify` is called.
+struct PersistenceNotifier {
+	/// Users won't access the persistence_lock directly, but rather wait on its bool using
+	/// `wait_timeout` and `wait`.
+	persistence_lock: (Mutex<bool>, Condvar),
+}
+
+impl PersistenceNotifier {
+	fn new() -> Self {
+		Self {
+			persistence_lock: (Mutex::new(false), Condvar::new()),
+		}
+	}
+
+	fn wait(&self) {
+		let &(ref mtx, ref cvar) = &self.persistence_lock;
+		let mut guard = mtx.lock().unwrap();
+		while !*guard {
+			guard = cvar.wait(guard).unwrap();
+		}
+		*guard = false;
+	}
+
+	fn wait_timeout(&self, max_wait: Duration) -> bool {
+		let deadline = Instant::now() + max_wait;
+		let &(ref mtx, ref cvar) = &self.persistence_lock;
+		let mut guard = mtx.lock().unwrap();
+		while !*guard {
+			let now = Instant::now();
+			if now >= deadline {
+				return false;
+			}
+			guard = cvar.wait_timeout(guard, deadline - now).unwrap().0;
+		}
+		*guard = false;
+		true
+	}
+
+	/// Wake waiters, tracking that persistence needs to occur.
+	fn notify(&self) {
+		let &(ref persist_mtx, ref cnd) = &self.persistence_lock;
+		let mut persistence_lock = persist_mtx.lock().unwrap();
+		*persistence_lock = true;
+		mem::drop(persistence_lock);
+		cnd.notify_all();
+	}
+}
+
+/// Takes a read lock on the total_consistency_lock for the duration of a ChannelManager operation
+/// and, once the operation completes (and the lock is released), notifies any waiters that the
+/// ChannelManager should be re-persisted.
+struct PersistenceNotifierGuard<'a> {
+	persistence_notifier: &'a PersistenceNotifier,
+	_read_guard: RwLockReadGuard<'a, ()>,
+}
+
+impl<'a> PersistenceNotifierGuard<'a> {
+	fn new(lock: &'a RwLock<()>, notifier: &'a PersistenceNotifier) -> Self {
+		let read_guard = lock.read().unwrap();
+
+		Self {
+			persistence_notifier: notifier,
+			_read_guard: read_guard,
+		}
+	}
+}
+
+impl<'a> Drop for PersistenceNotifierGuard<'a> {
+	fn drop(&mut self) {
+		self.persistence_notifier.notify();
+	}
+}
+
+/// Chain-related parameters used to construct a new `ChannelManager`.
+///
+/// Typically, the block-specific parameters are derived from the best block hash for the network,
+/// as a newly constructed `ChannelManager` will not have created any channels yet. These parameters
+/// are not needed when deserializing a previously constructed `ChannelManager`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ChainParameters {
+	/// The network for determining the `chain_hash` in Lightning messages.
+	pub network: Network,
+
+	/// The hash of the latest block successfully connected.
+	pub latest_hash: BlockHash,
+
+	/// The height of the latest block successfully connected.
+	///
+	/// Used to track on-chain channel funding outputs and send payments with reliable timelocks.
+	pub latest_height: usize,
+}
+
 /// Manager which keeps track of a number of channels and sends messages to the appropriate
 /// channel, also tracking HTLC preimages and forwarding onion packets appropriately.
 ///
@@ -367,10 +464,10 @@ pub type SimpleRefChannelManager<'a, 'b, 'c, 'd, 'e, M, T, F, L> = ChannelManage
 /// ChannelMonitor state and no funds will be lost (mod on-chain transaction fees).
 ///
 /// Note that the deserializer is only implemented for (Sha256dHash, ChannelManager), which
-/// tells you the last block hash which was block_connect()ed. You MUST rescan any blocks along
-/// the "reorg path" (ie call block_disconnected() until you get to a common block and then call
-/// block_connected() to step towards your best block) upon deserialization before using the
-/// object!
+/// tells you the last block hash which was block_connect()ed (also available afterwards via
+/// `current_best_block`). You MUST rescan any blocks along the "reorg path" (ie call
+/// block_disconnected() until you get to a common block and then call block_connected() to step
+/// towards your best block) upon deserialization before using the object!
 ///
 /// Note that ChannelManager is responsible for tracking liveness of its channels and generating
 /// ChannelUpdate messages informing peers that the channel is temporarily disabled. To avoid
@@ -427,6 +524,8 @@ pub struct ChannelManager<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref,
 	/// Taken first everywhere where we are making changes before any other locks.
 	total_consistency_lock: RwLock<()>,
 
+	persistence_notifier: PersistenceNotifier,
+
 	keys_manager: K,
 
 	logger: L,
@@ -498,6 +597,45 @@ pub struct ChannelDetails {
 	pub is_live: bool,
 }
 
+/// The funds we wish to add to or remove from a channel in a call to
+/// ChannelManager::splice_channel.
+#[derive(Clone)]
+pub enum SpliceContribution {
+	/// Adds value_satoshis to our balance in the channel, funded by the given outputs (each given
+	/// as the transaction containing it and its index therein), which must be segwit outputs we
+	/// are able to sign for. Any value left over once the splice transaction's fee has been paid
+	/// is sent to change_script.
+	SpliceIn {
+		/// The amount to add to our channel balance.
+		value_satoshis: u64,
+		/// The outputs to spend to fund the splice.
+		inputs: Vec<(Transaction, u32)>,
+		/// The script to send any change to.
+		change_script: Script,
+	},
+	/// Removes funds from our balance in the channel, paying them to the given outputs. The
+	/// splice transaction's fee is deducted from our channel balance in addition to the value of
+	/// the outputs.
+	SpliceOut {
+		/// The outputs to pay to.
+		outputs: Vec<TxOut>,
+	},
+}
+
+/// The funds we wish to contribute to a dual-funded channel in a call to
+/// ChannelManager::create_dual_funded_channel or ChannelManager::accept_dual_funded_channel.
+#[derive(Clone)]
+pub struct FundingContribution {
+	/// The amount to add to our channel balance.
+	pub value_satoshis: u64,
+	/// The outputs to spend to fund our contribution, each given as the transaction containing it
+	/// and its index therein. These must be segwit outputs we are able to sign for.
+	pub inputs: Vec<(Transaction, u32)>,
+	/// The script to send any value left over to, once our share of the funding transaction's
+	/// fee has been paid.
+	pub change_script: Script,
+}
+
 /// If a payment fails to send, it can be in one of several states. This enum is returned as the
 /// Err() type describing which state the payment is in, see the description of individual enum
 /// states for more.
@@ -507,10 +645,10 @@ pub enum PaymentSendFailure {
 	/// send the payment at all. No channel state has been changed or messages sent to peers, and
 	/// once you've changed the parameter at error, you can freely retry the payment in full.
 	ParameterError(APIError),
-	/// A parameter in a single path which was passed to send_payment was invalid, preventing us
-	/// from attempting to send the payment at all. No channel state has been changed or messages
-	/// sent to peers, and once you've changed the parameter at error, you can freely retry the
-	/// payment in full.
+	/// A parameter in a single path which was passed to send_payment was invalid, or the path's
+	/// first-hop channel could not currently be used, preventing us from attempting to send the
+	/// payment at all. No channel state has been changed or messages sent to peers, and once
+	/// you've changed the path at error, you can freely retry the payment in full.
 	///
 	/// The results here are ordered the same as the paths in the route object which was passed to
 	/// send_payment.
@@ -534,6 +672,28 @@ pub enum PaymentSendFailure {
 	PartialFailure(Vec<Result<(), APIError>>),
 }
 
+/// Wraps a message from an interactive transaction construction in the event to send it with.
+fn interactive_tx_msg_event(node_id: &PublicKey, msg: InteractiveTxMessageSend) -> events::MessageSendEvent {
+	match msg {
+		InteractiveTxMessageSend::TxAddInput(msg) => events::MessageSendEvent::SendTxAddInput { node_id: node_id.clone(), msg },
+		InteractiveTxMessageSend::TxAddOutput(msg) => events::MessageSendEvent::SendTxAddOutput { node_id: node_id.clone(), msg },
+		InteractiveTxMessageSend::TxComplete(msg) => events::MessageSendEvent::SendTxComplete { node_id: node_id.clone(), msg },
+	}
+}
+
+/// Gets the id of the channel with the given funding outpoint. This is derived from the outpoint
+/// for all but dual-funded channels, which we have to search for.
+fn funding_txo_channel_id<ChanSigner: ChannelKeys>(by_id: &HashMap<[u8; 32], Channel<ChanSigner>>, funding_txo: &OutPoint) -> [u8; 32] {
+	let channel_id = funding_txo.to_channel_id();
+	if by_id.contains_key(&channel_id) {
+		return channel_id;
+	}
+	match by_id.iter().find(|(_, chan)| chan.get_funding_txo() == Some(*funding_txo)) {
+		Some((channel_id, _)) => *channel_id,
+		None => channel_id,
+	}
+}
+
 macro_rules! handle_error {
 	($self: ident, $internal: expr, $counterparty_node_id: expr) => {
 		match $internal {
@@ -549,7 +709,7 @@ macro_rules! handle_error {
 				let mut msg_events = Vec::with_capacity(2);
 
 				if let Some((shutdown_res, update_option)) = shutdown_finish {
-					$self.finish_force_close_channel(shutdown_res);
+					$self.finish_force_close_channel(shutdown_res, ClosureReason::ProcessingError { err: err.err.clone() });
 					if let Some(update) = update_option {
 						msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
 							msg: update
@@ -587,7 +747,7 @@ macro_rules! break_chan_entry {
 			Err(ChannelError::Close(msg)) => {
 				log_trace!($self.logger, "Closing channel {} due to Close-required error: {}", log_bytes!($entry.key()[..]), msg);
 				let (channel_id, mut chan) = $entry.remove_entry();
-				if let Some(short_id) = chan.get_short_channel_id() {
+				for short_id in chan.get_short_channel_ids() {
 					$channel_state.short_to_id.remove(&short_id);
 				}
 				break Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, chan.force_shutdown(true), $self.get_channel_update(&chan).ok()))
@@ -607,7 +767,7 @@ macro_rules! try_chan_entry {
 			Err(ChannelError::Close(msg)) => {
 				log_trace!($self.logger, "Closing channel {} due to Close-required error: {}", log_bytes!($entry.key()[..]), msg);
 				let (channel_id, mut chan) = $entry.remove_entry();
-				if let Some(short_id) = chan.get_short_channel_id() {
+				for short_id in chan.get_short_channel_ids() {
 					$channel_state.short_to_id.remove(&short_id);
 				}
 				return Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, chan.force_shutdown(true), $self.get_channel_update(&chan).ok()))
@@ -615,7 +775,7 @@ macro_rules! try_chan_entry {
 			Err(ChannelError::CloseDelayBroadcast(msg)) => {
 				log_error!($self.logger, "Channel {} need to be shutdown but closing transactions not broadcast due to {}", log_bytes!($entry.key()[..]), msg);
 				let (channel_id, mut chan) = $entry.remove_entry();
-				if let Some(short_id) = chan.get_short_channel_id() {
+				for short_id in chan.get_short_channel_ids() {
 					$channel_state.short_to_id.remove(&short_id);
 				}
 				let shutdown_res = chan.force_shutdown(false);
@@ -634,7 +794,7 @@ macro_rules! handle_monitor_err {
 			ChannelMonitorUpdateErr::PermanentFailure => {
 				log_error!($self.logger, "Closing channel {} due to monitor update PermanentFailure", log_bytes!($entry.key()[..]));
 				let (channel_id, mut chan) = $entry.remove_entry();
-				if let Some(short_id) = chan.get_short_channel_id() {
+				for short_id in chan.get_short_channel_ids() {
 					$channel_state.short_to_id.remove(&short_id);
 				}
 				// TODO: $failed_fails is dropped here, which will cause other channels to hit the
@@ -712,8 +872,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	///
 	/// panics if channel_value_satoshis is >= `MAX_FUNDING_SATOSHIS`!
 	///
-	/// Users must provide the current blockchain height from which to track onchain channel
-	/// funding outpoints and send payments with reliable timelocks.
+	/// Users must provide the hash and height of the current best block, from which to track
+	/// onchain channel funding outpoints and send payments with reliable timelocks. See
+	/// `ChainParameters` for more details.
 	///
 	/// Users need to notify the new ChannelManager when a new block is connected or
 	/// disconnected using its `block_connected` and `block_disconnected` methods.
@@ -721,18 +882,18 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// the ChannelManager as a listener to the BlockNotifier and call the BlockNotifier's
 	/// `block_(dis)connected` methods, which will notify all registered listeners in one
 	/// go.
-	pub fn new(network: Network, fee_est: F, monitor: M, tx_broadcaster: T, logger: L, keys_manager: K, config: UserConfig, current_blockchain_height: usize) -> Self {
+	pub fn new(fee_est: F, monitor: M, tx_broadcaster: T, logger: L, keys_manager: K, config: UserConfig, params: ChainParameters) -> Self {
 		let secp_ctx = Secp256k1::new();
 
 		ChannelManager {
 			default_configuration: config.clone(),
-			genesis_hash: genesis_block(network).header.block_hash(),
+			genesis_hash: genesis_block(params.network).header.block_hash(),
 			fee_estimator: fee_est,
 			monitor,
 			tx_broadcaster,
 
-			latest_block_height: AtomicUsize::new(current_blockchain_height),
-			last_block_hash: Mutex::new(Default::default()),
+			latest_block_height: AtomicUsize::new(params.latest_height),
+			last_block_hash: Mutex::new(params.latest_hash),
 			secp_ctx,
 
 			channel_state: Mutex::new(ChannelHolder{
@@ -750,6 +911,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 			pending_events: Mutex::new(Vec::new()),
 			total_consistency_lock: RwLock::new(()),
+			persistence_notifier: PersistenceNotifier::new(),
 
 			keys_manager,
 
@@ -778,7 +940,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		let channel = Channel::new_outbound(&self.fee_estimator, &self.keys_manager, their_network_key, channel_value_satoshis, push_msat, user_id, config)?;
 		let res = channel.get_open_channel(self.genesis_hash.clone());
 
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let mut channel_state = self.channel_state.lock().unwrap();
 		match channel_state.by_id.entry(channel.channel_id()) {
 			hash_map::Entry::Occupied(_) => {
@@ -797,6 +959,152 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		Ok(())
 	}
 
+	/// Creates a new outbound dual-funded channel to the given remote node, to which we contribute
+	/// the given funds, which requires our counterparty to support option_dual_fund. Our
+	/// counterparty may contribute its own funds when it accepts the channel.
+	///
+	/// The funding transaction is negotiated with our counterparty, paying the given
+	/// funding_feerate_per_kw, and once constructed an Event::FundingTransactionReadyForSigning is
+	/// generated, after which you must call funding_transaction_signed. The funding transaction is
+	/// broadcast once both sides have signed it.
+	///
+	/// user_id will be provided back as user_channel_id in FundingTransactionReadyForSigning
+	/// events.
+	///
+	/// If successful, will generate a SendOpenChannelV2 message event, so you should probably poll
+	/// PeerManager::process_events afterwards.
+	pub fn create_dual_funded_channel(&self, their_network_key: PublicKey, contribution: FundingContribution, funding_feerate_per_kw: u32, user_id: u64, override_config: Option<UserConfig>) -> Result<(), APIError> {
+		if contribution.value_satoshis < 1000 {
+			return Err(APIError::APIMisuseError { err: format!("Channel value must be at least 1000 satoshis. It was {}", contribution.value_satoshis) });
+		}
+		let supports_dual_fund = match self.per_peer_state.read().unwrap().get(&their_network_key) {
+			Some(peer_state) => peer_state.lock().unwrap().latest_features.supports_dual_fund(),
+			None => false,
+		};
+		if !supports_dual_fund {
+			return Err(APIError::APIMisuseError { err: "Our counterparty is not connected or does not support dual-funded channels".to_owned() });
+		}
+
+		let config = if override_config.is_some() { override_config.as_ref().unwrap() } else { &self.default_configuration };
+		let locktime = self.latest_block_height.load(Ordering::Acquire) as u32;
+		let channel = Channel::new_outbound_dual_funded(&self.fee_estimator, &self.keys_manager, their_network_key, &contribution, funding_feerate_per_kw, locktime, user_id, config)?;
+		let res = channel.get_open_channel_v2(self.genesis_hash.clone());
+
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let mut channel_state = self.channel_state.lock().unwrap();
+		match channel_state.by_id.entry(channel.channel_id()) {
+			hash_map::Entry::Occupied(_) => {
+				if cfg!(feature = "fuzztarget") {
+					return Err(APIError::APIMisuseError { err: "Fuzzy bad RNG".to_owned() });
+				} else {
+					panic!("RNG is bad???");
+				}
+			},
+			hash_map::Entry::Vacant(entry) => { entry.insert(channel); }
+		}
+		channel_state.pending_msg_events.push(events::MessageSendEvent::SendOpenChannelV2 {
+			node_id: their_network_key,
+			msg: res,
+		});
+		Ok(())
+	}
+
+	/// Accepts a dual-funded channel our counterparty proposed, as given in an
+	/// Event::OpenChannelRequest, contributing the given funds to it, if any.
+	///
+	/// If we contribute any funds, an Event::FundingTransactionReadyForSigning is generated once
+	/// the funding transaction has been constructed, as for create_dual_funded_channel. user_id
+	/// will be provided back as user_channel_id in that event.
+	///
+	/// If successful, will generate a SendAcceptChannelV2 message event, so you should probably
+	/// poll PeerManager::process_events afterwards.
+	pub fn accept_dual_funded_channel(&self, temporary_channel_id: &[u8; 32], contribution: Option<FundingContribution>, user_id: u64) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		let mut chan = match channel_state.by_id.entry(*temporary_channel_id) {
+			hash_map::Entry::Occupied(chan) => chan,
+			hash_map::Entry::Vacant(_) => return Err(APIError::ChannelUnavailable { err: "No such channel".to_owned() }),
+		};
+		let msg = chan.get_mut().accept_dual_funded_channel(contribution.as_ref(), user_id)?;
+		let (_, chan) = chan.remove_entry();
+		match channel_state.by_id.entry(chan.channel_id()) {
+			hash_map::Entry::Occupied(_) => {
+				// The channel_id is derived from both sides' revocation basepoints, so this can only
+				// happen if our counterparty reused its basepoint across channels.
+				return Err(APIError::ChannelUnavailable { err: "Already had a channel with the same channel_id".to_owned() });
+			},
+			hash_map::Entry::Vacant(entry) => {
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannelV2 {
+					node_id: chan.get_counterparty_node_id(),
+					msg,
+				});
+				entry.insert(chan);
+			},
+		}
+		Ok(())
+	}
+
+	/// Rejects a dual-funded channel our counterparty proposed, as given in an
+	/// Event::OpenChannelRequest.
+	///
+	/// If successful, will generate a HandleError message event telling our counterparty we've
+	/// rejected the channel, so you should probably poll PeerManager::process_events afterwards.
+	pub fn reject_dual_funded_channel(&self, temporary_channel_id: &[u8; 32]) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(*temporary_channel_id) {
+			hash_map::Entry::Occupied(chan) => {
+				if chan.get().is_outbound() || chan.get().is_funding_initiated() || !chan.get().is_pending_dual_funding() {
+					return Err(APIError::APIMisuseError { err: "Channel is not a dual-funded channel awaiting acceptance".to_owned() });
+				}
+				let chan = chan.remove();
+				channel_state.pending_msg_events.push(events::MessageSendEvent::HandleError {
+					node_id: chan.get_counterparty_node_id(),
+					action: msgs::ErrorAction::SendErrorMessage {
+						msg: msgs::ErrorMessage {
+							channel_id: *temporary_channel_id,
+							data: "Channel rejected".to_owned(),
+						},
+					},
+				});
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(APIError::ChannelUnavailable { err: "No such channel".to_owned() }),
+		}
+	}
+
+	/// Provides the funding transaction given in an Event::FundingTransactionReadyForSigning, with
+	/// witnesses for all of the inputs we contributed to it.
+	///
+	/// May generate a SendTxSignatures message event on success, which should be relayed.
+	pub fn funding_transaction_signed(&self, channel_id: &[u8; 32], signed_transaction: Transaction) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.get_mut(channel_id) {
+			Some(chan) => {
+				let (tx_signatures, funding_tx) = chan.funding_transaction_signed(signed_transaction)?;
+				if let Some(msg) = tx_signatures {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+						node_id: chan.get_counterparty_node_id(),
+						msg,
+					});
+				}
+				if let Some(tx) = funding_tx {
+					log_info!(self.logger, "Broadcasting funding transaction {} for channel {}", tx.txid(), log_bytes!(chan.channel_id()));
+					self.tx_broadcaster.broadcast_transaction(&tx);
+				}
+				Ok(())
+			},
+			None => Err(APIError::ChannelUnavailable { err: "No such channel".to_owned() }),
+		}
+	}
+
 	fn list_channels_with_filter<Fn: FnMut(&(&[u8; 32], &Channel<ChanSigner>)) -> bool>(&self, f: Fn) -> Vec<ChannelDetails> {
 		let mut res = Vec::new();
 		{
@@ -850,22 +1158,63 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	///
 	/// May generate a SendShutdown message event on success, which should be relayed.
 	pub fn close_channel(&self, channel_id: &[u8; 32]) -> Result<(), APIError> {
-		let _ = self.total_consistency_lock.read().unwrap();
+		self.close_channel_internal(channel_id, None, None)
+	}
+
+	/// Begins the process of closing a channel, as in close_channel, but sending the channel's
+	/// funds to shutdown_script (if Some) instead of a script derived from
+	/// KeysInterface::get_shutdown_pubkey and negotiating a closing transaction fee within
+	/// fee_range (if Some) instead of one based on our FeeEstimator.
+	///
+	/// The shutdown_script must be a P2PKH, P2SH, segwit v0 P2WPKH/P2WSH script or, if the
+	/// counterparty supports option_shutdown_anysegwit, any segwit v1+ witness program, otherwise
+	/// APIError::IncompatibleShutdownScript is returned. Note that BOLT 2 requires we close to the
+	/// script we committed to at channel open if ChannelConfig::commit_upfront_shutdown_pubkey was
+	/// set, so this will fail with an APIMisuseError if a different script is given for such a
+	/// channel.
+	///
+	/// If the counterparty's fee_range doesn't overlap with ours, the channel will be closed
+	/// with an error instead of being closed cooperatively, at which point only a
+	/// force-close remains.
+	pub fn close_channel_with_params(&self, channel_id: &[u8; 32], shutdown_script: Option<Script>, fee_range: Option<msgs::ClosingSignedFeeRange>) -> Result<(), APIError> {
+		self.close_channel_internal(channel_id, shutdown_script, fee_range)
+	}
+
+	fn close_channel_internal(&self, channel_id: &[u8; 32], shutdown_script: Option<Script>, fee_range: Option<msgs::ClosingSignedFeeRange>) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+
+		if let Some(ref script) = shutdown_script {
+			if channel::is_anysegwit_script(script) {
+				let counterparty_node_id = match self.channel_state.lock().unwrap().by_id.get(channel_id) {
+					Some(chan) => chan.get_counterparty_node_id(),
+					None => return Err(APIError::ChannelUnavailable{err: "No such channel".to_owned()}),
+				};
+				let supports_anysegwit = match self.per_peer_state.read().unwrap().get(&counterparty_node_id) {
+					Some(peer_state) => peer_state.lock().unwrap().latest_features.supports_shutdown_anysegwit(),
+					None => false,
+				};
+				if !supports_anysegwit {
+					return Err(APIError::IncompatibleShutdownScript { script: script.clone() });
+				}
+			}
+		}
 
 		let (mut failed_htlcs, chan_option) = {
 			let mut channel_state_lock = self.channel_state.lock().unwrap();
 			let channel_state = &mut *channel_state_lock;
 			match channel_state.by_id.entry(channel_id.clone()) {
 				hash_map::Entry::Occupied(mut chan_entry) => {
-					let (shutdown_msg, failed_htlcs) = chan_entry.get_mut().get_shutdown()?;
+					let unbroadcast_funding_txo = chan_entry.get().get_unbroadcast_funding_txo();
+					let (shutdown_msg, failed_htlcs) = chan_entry.get_mut().get_shutdown(shutdown_script, fee_range)?;
 					channel_state.pending_msg_events.push(events::MessageSendEvent::SendShutdown {
 						node_id: chan_entry.get().get_counterparty_node_id(),
 						msg: shutdown_msg
 					});
 					if chan_entry.get().is_shutdown() {
-						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
+						for short_id in chan_entry.get().get_short_channel_ids() {
 							channel_state.short_to_id.remove(&short_id);
 						}
+						self.issue_channel_close_events(chan_entry.get(), unbroadcast_funding_txo, ClosureReason::CooperativeClosure);
 						(failed_htlcs, Some(chan_entry.remove_entry().1))
 					} else { (failed_htlcs, None) }
 				},
@@ -892,9 +1241,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	}
 
 	#[inline]
-	fn finish_force_close_channel(&self, shutdown_res: ShutdownResult) {
-		let (funding_txo_option, monitor_update, mut failed_htlcs) = shutdown_res;
+	fn finish_force_close_channel(&self, shutdown_res: ShutdownResult, reason: ClosureReason) {
+		let (funding_txo_option, monitor_update, mut failed_htlcs, (channel_id, user_channel_id, unbroadcast_funding_txo)) = shutdown_res;
 		log_trace!(self.logger, "Finishing force-closure of channel {} HTLCs to fail", failed_htlcs.len());
+		self.push_channel_closed_events(channel_id, user_channel_id, unbroadcast_funding_txo, reason);
 		for htlc_source in failed_htlcs.drain(..) {
 			self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), htlc_source.0, &htlc_source.1, HTLCFailReason::Reason { failure_code: 0x4000 | 8, data: Vec::new() });
 		}
@@ -907,16 +1257,39 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Generates the ChannelClosed event, and a DiscardFunding event if we'll never broadcast its
+	/// funding transaction, for a closed channel.
+	fn push_channel_closed_events(&self, channel_id: [u8; 32], user_channel_id: u64, unbroadcast_funding_txo: Option<OutPoint>, reason: ClosureReason) {
+		let mut pending_events = self.pending_events.lock().unwrap();
+		pending_events.push(events::Event::ChannelClosed { channel_id, user_channel_id, reason });
+		if let Some(funding_txo) = unbroadcast_funding_txo {
+			pending_events.push(events::Event::DiscardFunding { channel_id, user_channel_id, funding_txo });
+		}
+	}
+
+	/// Generates events for a channel which was closed other than via Channel::force_shutdown,
+	/// given its get_unbroadcast_funding_txo from before it was closed.
+	fn issue_channel_close_events(&self, chan: &Channel<ChanSigner>, unbroadcast_funding_txo: Option<OutPoint>, reason: ClosureReason) {
+		self.push_channel_closed_events(chan.channel_id(), chan.get_user_id(), unbroadcast_funding_txo, reason);
+	}
+
 	/// Force closes a channel, immediately broadcasting the latest local commitment transaction to
 	/// the chain and rejecting new HTLCs on the given channel.
 	pub fn force_close_channel(&self, channel_id: &[u8; 32]) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		self.force_close_channel_with_reason(channel_id, ClosureReason::HolderForceClosed);
+	}
 
+	/// Force-closes the given channel, reporting the given reason in the ChannelClosed event.
+	///
+	/// The caller must hold a PersistenceNotifierGuard, as the total_consistency_lock read lock
+	/// is not re-entrant.
+	fn force_close_channel_with_reason(&self, channel_id: &[u8; 32], reason: ClosureReason) {
 		let mut chan = {
 			let mut channel_state_lock = self.channel_state.lock().unwrap();
 			let channel_state = &mut *channel_state_lock;
 			if let Some(chan) = channel_state.by_id.remove(channel_id) {
-				if let Some(short_id) = chan.get_short_channel_id() {
+				for short_id in chan.get_short_channel_ids() {
 					channel_state.short_to_id.remove(&short_id);
 				}
 				chan
@@ -925,7 +1298,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			}
 		};
 		log_trace!(self.logger, "Force-closing channel {}", log_bytes!(channel_id[..]));
-		self.finish_force_close_channel(chan.force_shutdown(true));
+		self.finish_force_close_channel(chan.force_shutdown(true), reason);
 		if let Ok(update) = self.get_channel_update(&chan) {
 			let mut channel_state = self.channel_state.lock().unwrap();
 			channel_state.pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
@@ -1061,12 +1434,20 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					return_err!("Upstream node set CLTV to the wrong value", 18, &byte_utils::be32_to_array(msg.cltv_expiry));
 				}
 
-				let payment_data = match next_hop_data.format {
-					msgs::OnionHopDataFormat::Legacy { .. } => None,
+				let (payment_data, keysend_preimage) = match next_hop_data.format {
+					msgs::OnionHopDataFormat::Legacy { .. } => (None, None),
 					msgs::OnionHopDataFormat::NonFinalNode { .. } => return_err!("Got non final data with an HMAC of 0", 0x4000 | 22, &[0;0]),
-					msgs::OnionHopDataFormat::FinalNode { payment_data } => payment_data,
+					msgs::OnionHopDataFormat::FinalNode { payment_data, keysend_preimage } => (payment_data, keysend_preimage),
 				};
 
+				// If the sender included a preimage (ie this is a spontaneous payment), make sure it
+				// actually matches the payment hash before we accept the HTLC.
+				if let Some(ref preimage) = keysend_preimage {
+					if Sha256::hash(&preimage.0).into_inner() != msg.payment_hash.0 {
+						return_err!("Payment preimage didn't match payment hash", 0x4000 | 22, &[0;0]);
+					}
+				}
+
 				// Note that we could obviously respond immediately with an update_fulfill_htlc
 				// message, however that would leak that we are the recipient of this payment, so
 				// instead we stay symmetric with the forwarding case, only responding (after a
@@ -1075,6 +1456,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				PendingHTLCStatus::Forward(PendingHTLCInfo {
 					routing: PendingHTLCRouting::Receive {
 						payment_data,
+						keysend_preimage,
 						incoming_cltv_expiry: msg.cltv_expiry,
 					},
 					payment_hash: msg.payment_hash.clone(),
@@ -1244,38 +1626,56 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		})
 	}
 
-	// Only public for testing, this should otherwise never be called direcly
-	pub(crate) fn send_payment_along_path(&self, path: &Vec<RouteHop>, payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>, total_value: u64, cur_height: u32) -> Result<(), APIError> {
-		log_trace!(self.logger, "Attempting to send payment for path with next hop {}", path.first().unwrap().short_channel_id);
+	/// Builds the onion for the given payment path, returning the session key used to build it,
+	/// along with the onion and the amount and CLTV expiry of the first-hop HTLC.
+	fn build_payment_onion(&self, path: &Vec<RouteHop>, payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>, keysend_preimage: &Option<PaymentPreimage>, total_value: u64, cur_height: u32) -> Result<(SecretKey, msgs::OnionPacket, u64, u32), APIError> {
 		let prng_seed = self.keys_manager.get_secure_random_bytes();
 		let session_priv = SecretKey::from_slice(&self.keys_manager.get_secure_random_bytes()[..]).expect("RNG is busted");
 
 		let onion_keys = onion_utils::construct_onion_keys(&self.secp_ctx, &path, &session_priv)
 			.map_err(|_| APIError::RouteError{err: "Pubkey along hop was maliciously selected"})?;
-		let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(path, total_value, payment_secret, cur_height)?;
+		let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(path, total_value, payment_secret, keysend_preimage, cur_height)?;
 		if onion_utils::route_size_insane(&onion_payloads) {
 			return Err(APIError::RouteError{err: "Route size too large considering onion data"});
 		}
 		let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, prng_seed, payment_hash);
+		Ok((session_priv, onion_packet, htlc_msat, htlc_cltv))
+	}
 
-		let _ = self.total_consistency_lock.read().unwrap();
+	/// Checks that the first hop of the given path is a channel of ours with the right peer which
+	/// can currently send HTLCs.
+	fn check_first_hop(channel_state: &ChannelHolder<ChanSigner>, path: &Vec<RouteHop>) -> Result<(), APIError> {
+		let chan = match channel_state.short_to_id.get(&path.first().unwrap().short_channel_id) {
+			None => return Err(APIError::ChannelUnavailable{err: "No channel available with first hop!".to_owned()}),
+			Some(id) => channel_state.by_id.get(id).unwrap(),
+		};
+		if chan.get_counterparty_node_id() != path.first().unwrap().pubkey {
+			return Err(APIError::RouteError{err: "Node ID mismatch on first hop!"});
+		}
+		if !chan.is_live() {
+			return Err(APIError::ChannelUnavailable{err: "Peer for first hop currently disconnected/pending monitor update!".to_owned()});
+		}
+		Ok(())
+	}
+
+	// Only public for testing, this should otherwise never be called direcly
+	pub(crate) fn send_payment_along_path(&self, path: &Vec<RouteHop>, payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>, keysend_preimage: &Option<PaymentPreimage>, total_value: u64, cur_height: u32) -> Result<(), APIError> {
+		let (session_priv, onion_packet, htlc_msat, htlc_cltv) = self.build_payment_onion(path, payment_hash, payment_secret, keysend_preimage, total_value, cur_height)?;
+		self.send_payment_onion_along_path(path, payment_hash, session_priv, onion_packet, htlc_msat, htlc_cltv)
+	}
+
+	fn send_payment_onion_along_path(&self, path: &Vec<RouteHop>, payment_hash: &PaymentHash, session_priv: SecretKey, onion_packet: msgs::OnionPacket, htlc_msat: u64, htlc_cltv: u32) -> Result<(), APIError> {
+		log_trace!(self.logger, "Attempting to send payment for path with next hop {}", path.first().unwrap().short_channel_id);
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
 		let err: Result<(), _> = loop {
 			let mut channel_lock = self.channel_state.lock().unwrap();
-			let id = match channel_lock.short_to_id.get(&path.first().unwrap().short_channel_id) {
-				None => return Err(APIError::ChannelUnavailable{err: "No channel available with first hop!".to_owned()}),
-				Some(id) => id.clone(),
-			};
+			Self::check_first_hop(&channel_lock, path)?;
+			let id = channel_lock.short_to_id.get(&path.first().unwrap().short_channel_id).unwrap().clone();
 
 			let channel_state = &mut *channel_lock;
 			if let hash_map::Entry::Occupied(mut chan) = channel_state.by_id.entry(id) {
 				match {
-					if chan.get().get_counterparty_node_id() != path.first().unwrap().pubkey {
-						return Err(APIError::RouteError{err: "Node ID mismatch on first hop!"});
-					}
-					if !chan.get().is_live() {
-						return Err(APIError::ChannelUnavailable{err: "Peer for first hop currently disconnected/pending monitor update!".to_owned()});
-					}
 					break_chan_entry!(self, chan.get_mut().send_htlc_and_commit(htlc_msat, payment_hash.clone(), htlc_cltv, HTLCSource::OutboundRoute {
 						path: path.clone(),
 						session_priv: session_priv.clone(),
@@ -1359,6 +1759,34 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// bit set (either as required or as available). If multiple paths are present in the Route,
 	/// we assume the invoice had the basic_mpp feature set.
 	pub fn send_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>) -> Result<(), PaymentSendFailure> {
+		self.send_payment_internal(route, payment_hash, payment_secret, None)
+	}
+
+	/// Sends a spontaneous ("keysend") payment along a given route, i.e. one for which the
+	/// recipient never gave us an invoice. The preimage is included in the onion so that the
+	/// recipient can claim the payment. Returns the payment hash on success.
+	///
+	/// If payment_preimage is None, a random preimage is generated for you. If you provide one,
+	/// note that any intermediary node which has seen it before (eg because you reused it) can
+	/// claim the payment instead of forwarding it, and that it must not be guessable.
+	///
+	/// The recipient must support variable-length onions. As spontaneous payments carry no
+	/// payment_secret, the route must have exactly one path.
+	///
+	/// See send_payment for the errors which may be returned and how they should be handled.
+	pub fn send_spontaneous_payment(&self, route: &Route, payment_preimage: Option<PaymentPreimage>) -> Result<PaymentHash, PaymentSendFailure> {
+		let preimage = match payment_preimage {
+			Some(p) => p,
+			None => PaymentPreimage(self.keys_manager.get_secure_random_bytes()),
+		};
+		let payment_hash = PaymentHash(Sha256::hash(&preimage.0).into_inner());
+		match self.send_payment_internal(route, payment_hash, &None, Some(preimage)) {
+			Ok(()) => Ok(payment_hash),
+			Err(e) => Err(e)
+		}
+	}
+
+	fn send_payment_internal(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>, keysend_preimage: Option<PaymentPreimage>) -> Result<(), PaymentSendFailure> {
 		if route.paths.len() < 1 {
 			return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "There must be at least one path to send over"}));
 		}
@@ -1368,6 +1796,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			// for now more than 10 paths likely carries too much one-path failure.
 			return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Sending over more than 10 paths is not currently supported"}));
 		}
+		if route.paths.len() > 1 && payment_secret.is_none() {
+			return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Multi-path payments require a payment_secret"}));
+		}
 		let mut total_value = 0;
 		let our_node_id = self.get_our_node_id();
 		let mut path_errs = Vec::with_capacity(route.paths.len());
@@ -1389,10 +1820,28 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			return Err(PaymentSendFailure::PathParameterError(path_errs));
 		}
 
+		// Build the onions and check that every first hop is usable before sending anything, so
+		// that an unusable path doesn't leave the paths before it in flight.
 		let cur_height = self.latest_block_height.load(Ordering::Acquire) as u32 + 1;
+		let mut onions = Vec::with_capacity(route.paths.len());
+		let mut path_errs = Vec::with_capacity(route.paths.len());
+		{
+			let channel_state = self.channel_state.lock().unwrap();
+			for path in route.paths.iter() {
+				path_errs.push(Self::check_first_hop(&channel_state, path)
+					.and_then(|_| self.build_payment_onion(path, &payment_hash, payment_secret, &keysend_preimage, total_value, cur_height))
+					.map(|onion| onions.push(onion)));
+			}
+		}
+		if path_errs.iter().all(|e| e.is_err()) {
+			return Err(PaymentSendFailure::AllFailedRetrySafe(path_errs.drain(..).map(|r| r.unwrap_err()).collect()));
+		} else if path_errs.iter().any(|e| e.is_err()) {
+			return Err(PaymentSendFailure::PathParameterError(path_errs));
+		}
+
 		let mut results = Vec::new();
-		for path in route.paths.iter() {
-			results.push(self.send_payment_along_path(&path, &payment_hash, payment_secret, total_value, cur_height));
+		for (path, (session_priv, onion_packet, htlc_msat, htlc_cltv)) in route.paths.iter().zip(onions.drain(..)) {
+			results.push(self.send_payment_onion_along_path(&path, &payment_hash, session_priv, onion_packet, htlc_msat, htlc_cltv));
 		}
 		let mut has_ok = false;
 		let mut has_err = false;
@@ -1426,7 +1875,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// May panic if the funding_txo is duplicative with some other channel (note that this should
 	/// be trivially prevented by using unique funding transaction keys per-channel).
 	pub fn funding_transaction_generated(&self, temporary_channel_id: &[u8; 32], funding_txo: OutPoint) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
 		let (chan, msg) = {
 			let (res, chan) = match self.channel_state.lock().unwrap().by_id.remove(temporary_channel_id) {
@@ -1509,14 +1958,18 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	///
 	/// Panics if addresses is absurdly large (more than 500).
 	pub fn broadcast_node_announcement(&self, rgb: [u8; 3], alias: [u8; 32], addresses: Vec<NetAddress>) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
 		if addresses.len() > 500 {
 			panic!("More than half the message size was taken up by public addresses!");
 		}
 
+		let mut features = NodeFeatures::known();
+		if !self.default_configuration.own_channel_config.negotiate_anchor_outputs {
+			features = features.clear_anchor_outputs();
+		}
 		let announcement = msgs::UnsignedNodeAnnouncement {
-			features: NodeFeatures::known(),
+			features,
 			timestamp: self.last_node_announcement_serial.fetch_add(1, Ordering::AcqRel) as u32,
 			node_id: self.get_our_node_id(),
 			rgb, alias, addresses,
@@ -1539,7 +1992,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// Should only really ever be called in response to a PendingHTLCsForwardable event.
 	/// Will likely generate further events.
 	pub fn process_pending_htlc_forwards(&self) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
 		let mut new_events = Vec::new();
 		let mut failed_forwards = Vec::new();
@@ -1668,7 +2121,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 										ChannelError::Close(msg) => {
 											log_trace!(self.logger, "Closing channel {} due to Close-required error: {}", log_bytes!(chan.key()[..]), msg);
 											let (channel_id, mut channel) = chan.remove_entry();
-											if let Some(short_id) = channel.get_short_channel_id() {
+											for short_id in channel.get_short_channel_ids() {
 												channel_state.short_to_id.remove(&short_id);
 											}
 											Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, channel.force_shutdown(true), self.get_channel_update(&channel).ok()))
@@ -1702,7 +2155,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					for forward_info in pending_forwards.drain(..) {
 						match forward_info {
 							HTLCForwardInfo::AddHTLC { prev_short_channel_id, prev_htlc_id, forward_info: PendingHTLCInfo {
-									routing: PendingHTLCRouting::Receive { payment_data, incoming_cltv_expiry },
+									routing: PendingHTLCRouting::Receive { payment_data, keysend_preimage, incoming_cltv_expiry },
 									incoming_shared_secret, payment_hash, amt_to_forward, .. }, } => {
 								let prev_hop = HTLCPreviousHopData {
 									short_channel_id: prev_short_channel_id,
@@ -1750,6 +2203,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 										new_events.push(events::Event::PaymentReceived {
 											payment_hash: payment_hash,
 											payment_secret: Some(data.payment_secret),
+											payment_preimage: keysend_preimage,
 											amt: total_value,
 										});
 									}
@@ -1757,6 +2211,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 									new_events.push(events::Event::PaymentReceived {
 										payment_hash: payment_hash,
 										payment_secret: None,
+										payment_preimage: keysend_preimage,
 										amt: amt_to_forward,
 									});
 								}
@@ -1792,7 +2247,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	///
 	/// This method handles all the details, and must be called roughly once per minute.
 	pub fn timer_chan_freshness_every_min(&self) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let mut channel_state_lock = self.channel_state.lock().unwrap();
 		let channel_state = &mut *channel_state_lock;
 		for (_, chan) in channel_state.by_id.iter_mut() {
@@ -1817,7 +2272,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// Returns false if no payment was found to fail backwards, true if the process of failing the
 	/// HTLC backwards has been started.
 	pub fn fail_htlc_backwards(&self, payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>) -> bool {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
 		let mut channel_state = Some(self.channel_state.lock().unwrap());
 		let removed_source = channel_state.as_mut().unwrap().claimable_htlcs.remove(&(*payment_hash, *payment_secret));
@@ -1858,11 +2313,12 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					self.fail_htlc_backwards_internal(channel_state,
 						htlc_src, &payment_hash, HTLCFailReason::Reason { failure_code, data: onion_failure_data});
 				},
-				HTLCSource::OutboundRoute { .. } => {
+				HTLCSource::OutboundRoute { ref path, .. } => {
 					self.pending_events.lock().unwrap().push(
 						events::Event::PaymentFailed {
 							payment_hash,
 							rejected_by_dest: false,
+							short_channel_id: Some(path.first().unwrap().short_channel_id),
 #[cfg(test)]
 							error_code: None,
 #[cfg(test)]
@@ -1892,9 +2348,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				match &onion_error {
 					&HTLCFailReason::LightningError { ref err } => {
 #[cfg(test)]
-						let (channel_update, payment_retryable, onion_error_code, onion_error_data) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
+						let (channel_update, payment_retryable, failing_short_channel_id, onion_error_code, onion_error_data) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
 #[cfg(not(test))]
-						let (channel_update, payment_retryable, _, _) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
+						let (channel_update, payment_retryable, failing_short_channel_id, _, _) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
 						// TODO: If we decided to blame ourselves (or one of our channels) in
 						// process_onion_failure we should close that channel as it implies our
 						// next-hop is needlessly blaming us!
@@ -1909,6 +2365,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 							events::Event::PaymentFailed {
 								payment_hash: payment_hash.clone(),
 								rejected_by_dest: !payment_retryable,
+								short_channel_id: failing_short_channel_id,
 #[cfg(test)]
 								error_code: onion_error_code,
 #[cfg(test)]
@@ -1933,6 +2390,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 							events::Event::PaymentFailed {
 								payment_hash: payment_hash.clone(),
 								rejected_by_dest: path.len() == 1,
+								short_channel_id: Some(path.first().unwrap().short_channel_id),
 #[cfg(test)]
 								error_code: Some(*failure_code),
 #[cfg(test)]
@@ -1996,7 +2454,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	pub fn claim_funds(&self, payment_preimage: PaymentPreimage, payment_secret: &Option<PaymentSecret>, expected_amount: u64) -> bool {
 		let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
 
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
 		let mut channel_state = Some(self.channel_state.lock().unwrap());
 		let removed_source = channel_state.as_mut().unwrap().claimable_htlcs.remove(&(payment_hash, *payment_secret));
@@ -2127,7 +2585,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		} else { unreachable!(); }
 	}
 
-	fn claim_funds_internal(&self, mut channel_state_lock: MutexGuard<ChannelHolder<ChanSigner>>, source: HTLCSource, payment_preimage: PaymentPreimage) {
+	/// Claims the source of an HTLC we learned the preimage for from the next hop, given the
+	/// amount of the outbound HTLC, if known, and the channel it was claimed on.
+	fn claim_funds_internal(&self, mut channel_state_lock: MutexGuard<ChannelHolder<ChanSigner>>, source: HTLCSource, payment_preimage: PaymentPreimage, forwarded_htlc_value_msat: Option<u64>, from_onchain: bool, next_channel_id: [u8; 32]) {
 		match source {
 			HTLCSource::OutboundRoute { .. } => {
 				mem::drop(channel_state_lock);
@@ -2137,20 +2597,34 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				});
 			},
 			HTLCSource::PreviousHopData(hop_data) => {
-				if let Err((counterparty_node_id, err)) = match self.claim_funds_from_hop(&mut channel_state_lock, hop_data, payment_preimage) {
-					Ok(()) => Ok(()),
+				let prev_channel_id = channel_state_lock.short_to_id.get(&hop_data.short_channel_id).cloned();
+				let htlc_value_msat = prev_channel_id.as_ref().and_then(|channel_id| channel_state_lock.by_id.get(channel_id))
+					.and_then(|chan| chan.get_inbound_htlc_value_msat(hop_data.htlc_id));
+				let fee_earned_msat = match (htlc_value_msat, forwarded_htlc_value_msat) {
+					(Some(htlc_value_msat), Some(forwarded_htlc_value_msat)) => htlc_value_msat.checked_sub(forwarded_htlc_value_msat),
+					_ => None,
+				};
+				let res = self.claim_funds_from_hop(&mut channel_state_lock, hop_data, payment_preimage);
+				mem::drop(channel_state_lock);
+				match res {
+					Ok(()) => {},
 					Err(None) => {
 						// TODO: There is probably a channel monitor somewhere that needs to
 						// learn the preimage as the channel already hit the chain and that's
 						// why it's missing.
-						Ok(())
 					},
-					Err(Some(res)) => Err(res),
-				} {
-					mem::drop(channel_state_lock);
-					let res: Result<(), _> = Err(err);
-					let _ = handle_error!(self, res, counterparty_node_id);
+					Err(Some((counterparty_node_id, err))) => {
+						let res: Result<(), _> = Err(err);
+						let _ = handle_error!(self, res, counterparty_node_id);
+						return;
+					},
 				}
+				self.pending_events.lock().unwrap().push(events::Event::PaymentForwarded {
+					prev_channel_id,
+					next_channel_id,
+					fee_earned_msat,
+					claim_from_onchain_tx: from_onchain,
+				});
 			},
 		}
 	}
@@ -2181,9 +2655,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	///  4) once all remote copies are updated, you call this function with the update_id that
 	///     completed, and once it is the latest the Channel will be re-enabled.
 	pub fn channel_monitor_updated(&self, funding_txo: &OutPoint, highest_applied_update_id: u64) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
-		let mut close_results = Vec::new();
 		let mut htlc_forwards = Vec::new();
 		let mut htlc_failures = Vec::new();
 		let mut pending_events = Vec::new();
@@ -2193,7 +2666,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			let channel_state = &mut *channel_lock;
 			let short_to_id = &mut channel_state.short_to_id;
 			let pending_msg_events = &mut channel_state.pending_msg_events;
-			let channel = match channel_state.by_id.get_mut(&funding_txo.to_channel_id()) {
+			let channel_id = funding_txo_channel_id(&channel_state.by_id, funding_txo);
+			let channel = match channel_state.by_id.get_mut(&channel_id) {
 				Some(chan) => chan,
 				None => return,
 			};
@@ -2260,10 +2734,6 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), failure.0, &failure.1, failure.2);
 		}
 		self.forward_htlcs(&mut htlc_forwards[..]);
-
-		for res in close_results.drain(..) {
-			self.finish_force_close_channel(res);
-		}
 	}
 
 	fn internal_open_channel(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannel) -> Result<(), MsgHandleErrInternal> {
@@ -2313,6 +2783,55 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		Ok(())
 	}
 
+	fn internal_open_channel_v2(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2) -> Result<(), MsgHandleErrInternal> {
+		if msg.chain_hash != self.genesis_hash {
+			return Err(MsgHandleErrInternal::send_err_msg_no_close("Unknown genesis block hash".to_owned(), msg.temporary_channel_id.clone()));
+		}
+		if !their_features.supports_dual_fund() {
+			return Err(MsgHandleErrInternal::send_err_msg_no_close("Peer sent open_channel2 without signaling option_dual_fund".to_owned(), msg.temporary_channel_id.clone()));
+		}
+
+		let channel = Channel::new_from_req_dual_funded(&self.fee_estimator, &self.keys_manager, counterparty_node_id.clone(), their_features, msg, &self.default_configuration)
+			.map_err(|e| MsgHandleErrInternal::from_chan_no_close(e, msg.temporary_channel_id))?;
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(channel.channel_id()) {
+			hash_map::Entry::Occupied(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("temporary_channel_id collision!".to_owned(), msg.temporary_channel_id.clone())),
+			hash_map::Entry::Vacant(entry) => { entry.insert(channel); }
+		}
+		self.pending_events.lock().unwrap().push(events::Event::OpenChannelRequest {
+			temporary_channel_id: msg.temporary_channel_id,
+			counterparty_node_id: counterparty_node_id.clone(),
+			counterparty_funding_satoshis: msg.funding_satoshis,
+			funding_feerate_per_kw: msg.funding_feerate_perkw,
+		});
+		Ok(())
+	}
+
+	fn internal_accept_channel_v2(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::AcceptChannelV2) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		let (tx_msg, chan) = match channel_state.by_id.entry(msg.temporary_channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.temporary_channel_id));
+				}
+				(try_chan_entry!(self, chan.get_mut().accept_channel_v2(&msg, &self.default_configuration, their_features), channel_state, chan), chan.remove())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.temporary_channel_id))
+		};
+		match channel_state.by_id.entry(chan.channel_id()) {
+			hash_map::Entry::Occupied(_) => {
+				return Err(MsgHandleErrInternal::send_err_msg_no_close("Already had channel with the new channel_id".to_owned(), chan.channel_id()))
+			},
+			hash_map::Entry::Vacant(e) => {
+				channel_state.pending_msg_events.push(interactive_tx_msg_event(counterparty_node_id, tx_msg));
+				e.insert(chan);
+			}
+		}
+		Ok(())
+	}
+
 	fn internal_funding_created(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingCreated) -> Result<(), MsgHandleErrInternal> {
 		let ((funding_msg, monitor_update), mut chan) = {
 			let mut channel_lock = self.channel_state.lock().unwrap();
@@ -2322,7 +2841,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					if chan.get().get_counterparty_node_id() != *counterparty_node_id {
 						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.temporary_channel_id));
 					}
-					(try_chan_entry!(self, chan.get_mut().funding_created(msg, &self.logger), channel_state, chan), chan.remove())
+					(try_chan_entry!(self, chan.get_mut().funding_created(msg, self.best_block(), &self.logger), channel_state, chan), chan.remove())
 				},
 				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.temporary_channel_id))
 			}
@@ -2373,7 +2892,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					if chan.get().get_counterparty_node_id() != *counterparty_node_id {
 						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
 					}
-					let monitor = match chan.get_mut().funding_signed(&msg, &self.logger) {
+					let monitor = match chan.get_mut().funding_signed(&msg, self.best_block(), &self.logger) {
 						Ok(update) => update,
 						Err(e) => try_chan_entry!(self, Err(e), channel_state, chan),
 					};
@@ -2434,6 +2953,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					if chan_entry.get().get_counterparty_node_id() != *counterparty_node_id {
 						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
 					}
+					let unbroadcast_funding_txo = chan_entry.get().get_unbroadcast_funding_txo();
 					let (shutdown, closing_signed, dropped_htlcs) = try_chan_entry!(self, chan_entry.get_mut().shutdown(&self.fee_estimator, &msg), channel_state, chan_entry);
 					if let Some(msg) = shutdown {
 						channel_state.pending_msg_events.push(events::MessageSendEvent::SendShutdown {
@@ -2448,9 +2968,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						});
 					}
 					if chan_entry.get().is_shutdown() {
-						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
+						for short_id in chan_entry.get().get_short_channel_ids() {
 							channel_state.short_to_id.remove(&short_id);
 						}
+						self.issue_channel_close_events(chan_entry.get(), unbroadcast_funding_txo, ClosureReason::CooperativeClosure);
 						(dropped_htlcs, Some(chan_entry.remove_entry().1))
 					} else { (dropped_htlcs, None) }
 				},
@@ -2493,9 +3014,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						// also implies there are no pending HTLCs left on the channel, so we can
 						// fully delete it from tracking (the channel monitor is still around to
 						// watch for old state broadcasts)!
-						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
+						for short_id in chan_entry.get().get_short_channel_ids() {
 							channel_state.short_to_id.remove(&short_id);
 						}
+						self.issue_channel_close_events(chan_entry.get(), None, ClosureReason::CooperativeClosure);
 						(tx, Some(chan_entry.remove_entry().1))
 					} else { (tx, None) }
 				},
@@ -2503,7 +3025,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			}
 		};
 		if let Some(broadcast_tx) = tx {
-			log_trace!(self.logger, "Broadcast onchain {}", log_tx!(broadcast_tx));
+			let opt_anchors = chan_option.as_ref().map(|chan| chan.opt_anchors()).unwrap_or(false);
+			log_trace!(self.logger, "Broadcast onchain {}", log_tx!(broadcast_tx, opt_anchors));
 			self.tx_broadcaster.broadcast_transaction(&broadcast_tx);
 		}
 		if let Some(chan) = chan_option {
@@ -2584,7 +3107,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 	fn internal_update_fulfill_htlc(&self, counterparty_node_id: &PublicKey, msg: &msgs::UpdateFulfillHTLC) -> Result<(), MsgHandleErrInternal> {
 		let mut channel_lock = self.channel_state.lock().unwrap();
-		let htlc_source = {
+		let (htlc_source, forwarded_htlc_value_msat) = {
 			let channel_state = &mut *channel_lock;
 			match channel_state.by_id.entry(msg.channel_id) {
 				hash_map::Entry::Occupied(mut chan) => {
@@ -2596,7 +3119,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
 			}
 		};
-		self.claim_funds_internal(channel_lock, htlc_source, msg.payment_preimage.clone());
+		self.claim_funds_internal(channel_lock, htlc_source, msg.payment_preimage.clone(), Some(forwarded_htlc_value_msat), false, msg.channel_id);
 		Ok(())
 	}
 
@@ -2642,6 +3165,48 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
 					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
 				}
+				if chan.get().is_awaiting_dual_funding_commitment_signed() {
+					let (monitor, tx_signatures, unsigned_tx) = try_chan_entry!(self, chan.get_mut().dual_funding_commitment_signed(&msg, self.best_block(), &self.logger), channel_state, chan);
+					// The funding transaction can't have been broadcast yet, so there's no harm in
+					// closing the channel if we can't persist its monitor.
+					if let Err(_) = self.monitor.add_monitor(chan.get().get_funding_txo().unwrap(), monitor) {
+						try_chan_entry!(self, Err(ChannelError::Close("Failed to add the ChannelMonitor for our dual-funded channel".to_owned())), channel_state, chan);
+					}
+					if let Some(msg) = tx_signatures {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: counterparty_node_id.clone(),
+							msg,
+						});
+					}
+					if let Some(unsigned_transaction) = unsigned_tx {
+						self.pending_events.lock().unwrap().push(events::Event::FundingTransactionReadyForSigning {
+							channel_id: msg.channel_id,
+							user_channel_id: chan.get().get_user_id(),
+							unsigned_transaction,
+						});
+					}
+					return Ok(());
+				}
+				if chan.get().is_awaiting_splice_commitment_signed() {
+					let (monitor_update, tx_signatures, unsigned_tx) = try_chan_entry!(self, chan.get_mut().splice_commitment_signed(&msg, &self.logger), channel_state, chan);
+					if let Err(_) = self.monitor.update_monitor(chan.get().get_funding_txo().unwrap(), monitor_update) {
+						try_chan_entry!(self, Err(ChannelError::Close("Failed to update the ChannelMonitor with our splice commitment transaction".to_owned())), channel_state, chan);
+					}
+					if let Some(msg) = tx_signatures {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: counterparty_node_id.clone(),
+							msg,
+						});
+					}
+					if let Some(unsigned_transaction) = unsigned_tx {
+						self.pending_events.lock().unwrap().push(events::Event::SpliceTransactionReadyForSigning {
+							channel_id: msg.channel_id,
+							user_channel_id: chan.get().get_user_id(),
+							unsigned_transaction,
+						});
+					}
+					return Ok(());
+				}
 				let (revoke_and_ack, commitment_signed, closing_signed, monitor_update) =
 					match chan.get_mut().commitment_signed(&msg, &self.fee_estimator, &self.logger) {
 						Err((None, e)) => try_chan_entry!(self, Err(e), channel_state, chan),
@@ -2912,26 +3477,237 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						msg,
 					});
 				}
+				if let Some(msg) = chan.get().get_splice_locked() {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendSpliceLocked {
+						node_id: counterparty_node_id.clone(),
+						msg,
+					});
+				}
+				if let Some(msg) = chan.get().get_dual_funding_tx_signatures().or(chan.get().get_splice_tx_signatures()) {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+						node_id: counterparty_node_id.clone(),
+						msg,
+					});
+				}
 				Ok(())
 			},
 			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
 		}
 	}
 
-	/// Begin Update fee process. Allowed only on an outbound channel.
-	/// If successful, will generate a UpdateHTLCs event, so you should probably poll
-	/// PeerManager::process_events afterwards.
-	/// Note: This API is likely to change!
-	/// (C-not exported) Cause its doc(hidden) anyway
-	#[doc(hidden)]
-	pub fn update_fee(&self, channel_id: [u8;32], feerate_per_kw: u32) -> Result<(), APIError> {
-		let _ = self.total_consistency_lock.read().unwrap();
-		let counterparty_node_id;
-		let err: Result<(), _> = loop {
-			let mut channel_state_lock = self.channel_state.lock().unwrap();
-			let channel_state = &mut *channel_state_lock;
-
-			match channel_state.by_id.entry(channel_id) {
+	fn internal_stfu(&self, counterparty_node_id: &PublicKey, msg: &msgs::Stfu) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				let (stfu, splice_init) = try_chan_entry!(self, chan.get_mut().stfu(&msg), channel_state, chan);
+				if let Some(msg) = stfu {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendStfu {
+						node_id: counterparty_node_id.clone(),
+						msg,
+					});
+				}
+				if let Some(msg) = splice_init {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendSpliceInit {
+						node_id: counterparty_node_id.clone(),
+						msg,
+					});
+				}
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	fn internal_splice_init(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceInit) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				let splice_ack = try_chan_entry!(self, chan.get_mut().splice_init(&msg), channel_state, chan);
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendSpliceAck {
+					node_id: counterparty_node_id.clone(),
+					msg: splice_ack,
+				});
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	fn internal_splice_ack(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceAck) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				let tx_msg = try_chan_entry!(self, chan.get_mut().splice_ack(&msg), channel_state, chan);
+				channel_state.pending_msg_events.push(interactive_tx_msg_event(counterparty_node_id, tx_msg));
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	fn internal_interactive_tx_msg<H>(&self, counterparty_node_id: &PublicKey, channel_id: [u8; 32], handle_msg: H) -> Result<(), MsgHandleErrInternal>
+	where H: FnOnce(&mut Channel<ChanSigner>) -> Result<(Option<InteractiveTxMessageSend>, Option<msgs::CommitmentSigned>), msgs::TxAbort> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), channel_id));
+				}
+				let unbroadcast_funding_txo = chan.get().get_unbroadcast_funding_txo();
+				match handle_msg(chan.get_mut()) {
+					Ok((tx_msg, commitment_signed)) => {
+						if let Some(tx_msg) = tx_msg {
+							channel_state.pending_msg_events.push(interactive_tx_msg_event(counterparty_node_id, tx_msg));
+						}
+						if let Some(commitment_signed) = commitment_signed {
+							channel_state.pending_msg_events.push(events::MessageSendEvent::UpdateHTLCs {
+								node_id: counterparty_node_id.clone(),
+								updates: msgs::CommitmentUpdate {
+									update_add_htlcs: Vec::new(),
+									update_fulfill_htlcs: Vec::new(),
+									update_fail_htlcs: Vec::new(),
+									update_fail_malformed_htlcs: Vec::new(),
+									update_fee: None,
+									commitment_signed,
+								},
+							});
+						}
+					},
+					Err(tx_abort) => {
+						let err = String::from_utf8_lossy(&tx_abort.data).into_owned();
+						log_debug!(self.logger, "Aborting interactive transaction construction for channel {}: {}", log_bytes!(channel_id), err);
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxAbort {
+							node_id: counterparty_node_id.clone(),
+							msg: tx_abort,
+						});
+						if chan.get().is_shutdown() {
+							// A dual-funded channel we gave up on before it was funded.
+							self.issue_channel_close_events(chan.get(), unbroadcast_funding_txo, ClosureReason::ProcessingError { err });
+							chan.remove_entry();
+						}
+					},
+				}
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), channel_id))
+		}
+	}
+
+	fn internal_tx_signatures(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxSignatures) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				let is_dual_funding = chan.get().is_pending_dual_funding();
+				let (tx_signatures, signed_tx) = try_chan_entry!(self, chan.get_mut().tx_signatures(&msg), channel_state, chan);
+				if let Some(msg) = tx_signatures {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+						node_id: counterparty_node_id.clone(),
+						msg,
+					});
+				}
+				if let Some(tx) = signed_tx {
+					log_info!(self.logger, "Broadcasting {} transaction {} for channel {}", if is_dual_funding { "funding" } else { "splice" }, tx.txid(), log_bytes!(msg.channel_id));
+					self.tx_broadcaster.broadcast_transaction(&tx);
+				}
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	fn internal_tx_abort(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAbort) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				let unbroadcast_funding_txo = chan.get().get_unbroadcast_funding_txo();
+				if let Some(msg) = try_chan_entry!(self, chan.get_mut().tx_abort(&msg), channel_state, chan) {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxAbort {
+						node_id: counterparty_node_id.clone(),
+						msg,
+					});
+				}
+				if chan.get().is_shutdown() {
+					// A dual-funded channel our counterparty gave up on before it was funded.
+					let peer_msg = String::from_utf8_lossy(&msg.data).into_owned();
+					self.issue_channel_close_events(chan.get(), unbroadcast_funding_txo, ClosureReason::CounterpartyForceClosed { peer_msg });
+					chan.remove_entry();
+				}
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	fn internal_splice_locked(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceLocked) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				let stale_short_channel_id = chan.get().get_pre_splice_short_channel_id();
+				if try_chan_entry!(self, chan.get_mut().splice_locked(&msg), channel_state, chan) {
+					self.splice_promoted(chan.get(), stale_short_channel_id, &mut channel_state.short_to_id, &mut channel_state.pending_msg_events);
+				}
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	/// Handles a channel moving to the funding output of a splice, which gives it a new short
+	/// channel id we have to announce. Its previous short channel id stays mapped for HTLCs which
+	/// were added while the splice was locking, replacing the one from before any earlier splice.
+	fn splice_promoted(&self, chan: &Channel<ChanSigner>, stale_short_channel_id: Option<u64>, short_to_id: &mut HashMap<u64, [u8; 32]>, pending_msg_events: &mut Vec<events::MessageSendEvent>) {
+		log_info!(self.logger, "Channel {} is now spliced, with a value of {} sat", log_bytes!(chan.channel_id()), chan.get_value_satoshis());
+		if let Some(short_id) = stale_short_channel_id {
+			short_to_id.remove(&short_id);
+		}
+		short_to_id.insert(chan.get_short_channel_id().unwrap(), chan.channel_id());
+		if let Some(announcement_sigs) = self.get_announcement_sigs(chan) {
+			pending_msg_events.push(events::MessageSendEvent::SendAnnouncementSignatures {
+				node_id: chan.get_counterparty_node_id(),
+				msg: announcement_sigs,
+			});
+		}
+	}
+
+	/// Begin Update fee process. Allowed only on an outbound channel.
+	/// If successful, will generate a UpdateHTLCs event, so you should probably poll
+	/// PeerManager::process_events afterwards.
+	/// Note: This API is likely to change!
+	/// (C-not exported) Cause its doc(hidden) anyway
+	#[doc(hidden)]
+	pub fn update_fee(&self, channel_id: [u8;32], feerate_per_kw: u32) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let counterparty_node_id;
+		let err: Result<(), _> = loop {
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+
+			match channel_state.by_id.entry(channel_id) {
 				hash_map::Entry::Vacant(_) => return Err(APIError::APIMisuseError{err: format!("Failed to find corresponding channel for id {}", channel_id.to_hex())}),
 				hash_map::Entry::Occupied(mut chan) => {
 					if !chan.get().is_outbound() {
@@ -2943,6 +3719,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					if !chan.get().is_live() {
 						return Err(APIError::ChannelUnavailable{err: "Channel is either not yet fully established or peer is currently disconnected".to_owned()});
 					}
+					if chan.get().is_quiescent() {
+						return Err(APIError::ChannelUnavailable{err: "Cannot update the fee while a splice is in progress".to_owned()});
+					}
 					counterparty_node_id = chan.get().get_counterparty_node_id();
 					if let Some((update_fee, commitment_signed, monitor_update)) =
 							break_chan_entry!(self, chan.get_mut().send_update_fee_and_commit(feerate_per_kw, &self.logger), channel_state, chan)
@@ -2973,6 +3752,73 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Begins splicing funds into or out of a channel without closing it, which requires our
+	/// counterparty to support option_splice.
+	///
+	/// The channel first becomes quiescent, after which no payments can be sent or received over
+	/// it until the splice transaction has confirmed and both sides have sent splice_locked. The
+	/// splice transaction is then negotiated with our counterparty, paying the given
+	/// funding_feerate_per_kw, and once constructed, if we contributed any inputs, an
+	/// Event::SpliceTransactionReadyForSigning is generated, after which you must call
+	/// splice_transaction_signed. The splice transaction is broadcast once both sides have signed
+	/// it.
+	///
+	/// If successful, will generate a SendStfu message event, so you should probably poll
+	/// PeerManager::process_events afterwards.
+	pub fn splice_channel(&self, channel_id: &[u8; 32], contribution: SpliceContribution, funding_feerate_per_kw: u32) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.get_mut(channel_id) {
+			Some(chan) => {
+				let supports_splicing = match self.per_peer_state.read().unwrap().get(&chan.get_counterparty_node_id()) {
+					Some(peer_state) => peer_state.lock().unwrap().latest_features.supports_splicing(),
+					None => false,
+				};
+				if !supports_splicing {
+					return Err(APIError::ChannelUnavailable { err: "Our counterparty does not support splicing".to_owned() });
+				}
+				let locktime = self.latest_block_height.load(Ordering::Acquire) as u32;
+				let msg = chan.splice_channel(&contribution, funding_feerate_per_kw, locktime)?;
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendStfu {
+					node_id: chan.get_counterparty_node_id(),
+					msg,
+				});
+				Ok(())
+			},
+			None => Err(APIError::ChannelUnavailable { err: "No such channel".to_owned() }),
+		}
+	}
+
+	/// Provides the splice transaction given in an Event::SpliceTransactionReadyForSigning, with
+	/// witnesses for all of the inputs we contributed to it.
+	///
+	/// May generate a SendTxSignatures message event on success, which should be relayed.
+	pub fn splice_transaction_signed(&self, channel_id: &[u8; 32], signed_transaction: Transaction) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.get_mut(channel_id) {
+			Some(chan) => {
+				let (tx_signatures, splice_tx) = chan.splice_transaction_signed(signed_transaction)?;
+				if let Some(msg) = tx_signatures {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+						node_id: chan.get_counterparty_node_id(),
+						msg,
+					});
+				}
+				if let Some(tx) = splice_tx {
+					log_info!(self.logger, "Broadcasting splice transaction {} for channel {}", tx.txid(), log_bytes!(chan.channel_id()));
+					self.tx_broadcaster.broadcast_transaction(&tx);
+				}
+				Ok(())
+			},
+			None => Err(APIError::ChannelUnavailable { err: "No such channel".to_owned() }),
+		}
+	}
+
 	/// Process pending events from the ManyChannelMonitor.
 	fn process_pending_monitor_events(&self) {
 		let mut failed_channels = Vec::new();
@@ -2982,7 +3828,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					MonitorEvent::HTLCEvent(htlc_update) => {
 						if let Some(preimage) = htlc_update.payment_preimage {
 							log_trace!(self.logger, "Claiming HTLC with preimage {} from our monitor", log_bytes!(preimage.0));
-							self.claim_funds_internal(self.channel_state.lock().unwrap(), htlc_update.source, preimage);
+							self.claim_funds_internal(self.channel_state.lock().unwrap(), htlc_update.source, preimage, htlc_update.htlc_value_msat, true, htlc_update.channel_id);
 						} else {
 							log_trace!(self.logger, "Failing HTLC with hash {} from our monitor", log_bytes!(htlc_update.payment_hash.0));
 							self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), htlc_update.source, &htlc_update.payment_hash, HTLCFailReason::Reason { failure_code: 0x4000 | 8, data: Vec::new() });
@@ -2994,8 +3840,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						let by_id = &mut channel_state.by_id;
 						let short_to_id = &mut channel_state.short_to_id;
 						let pending_msg_events = &mut channel_state.pending_msg_events;
-						if let Some(mut chan) = by_id.remove(&funding_outpoint.to_channel_id()) {
-							if let Some(short_id) = chan.get_short_channel_id() {
+						let channel_id = funding_txo_channel_id(by_id, &funding_outpoint);
+						if let Some(mut chan) = by_id.remove(&channel_id) {
+							for short_id in chan.get_short_channel_ids() {
 								short_to_id.remove(&short_id);
 							}
 							failed_channels.push(chan.force_shutdown(false));
@@ -3011,9 +3858,40 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 
 		for failure in failed_channels.drain(..) {
-			self.finish_force_close_channel(failure);
+			let err = "ChannelMonitor broadcast our latest commitment transaction to resolve an HTLC on-chain".to_owned();
+			self.finish_force_close_channel(failure, ClosureReason::ProcessingError { err });
 		}
 	}
+
+	/// Blocks until ChannelManager needs to be persisted or a timeout is reached. It returns a bool
+	/// indicating whether persistence is necessary. Only one listener on
+	/// `await_persistable_update` or `await_persistable_update_timeout` is guaranteed to be woken
+	/// up.
+	pub fn await_persistable_update_timeout(&self, max_wait: Duration) -> bool {
+		self.persistence_notifier.wait_timeout(max_wait)
+	}
+
+	/// Blocks until ChannelManager needs to be persisted. Only one listener on
+	/// `await_persistable_update` or `await_persistable_update_timeout` is guaranteed to be woken
+	/// up.
+	pub fn await_persistable_update(&self) {
+		self.persistence_notifier.wait()
+	}
+
+	/// Gets the hash and height of the latest block connected to this ChannelManager, ie the
+	/// block from which chain sync should resume, eg after deserialization.
+	///
+	/// After a block is disconnected, this is its parent.
+	pub fn current_best_block(&self) -> (BlockHash, u32) {
+		self.best_block()
+	}
+
+	/// Gets the latest block hash and height, consistent with each other as both are updated
+	/// while holding the last_block_hash lock.
+	fn best_block(&self) -> (BlockHash, u32) {
+		let last_block_hash = self.last_block_hash.lock().unwrap();
+		(*last_block_hash, self.latest_block_height.load(Ordering::Acquire) as u32)
+	}
 }
 
 impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref> MessageSendEventsProvider for ChannelManager<ChanSigner, M, T, K, F, L>
@@ -3065,53 +3943,83 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 	fn block_connected(&self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], indexes_of_txn_matched: &[usize]) {
 		let header_hash = header.block_hash();
 		log_trace!(self.logger, "Block {} at height {} connected with {} txn matched", header_hash, height, txn_matched.len());
-		let _ = self.total_consistency_lock.read().unwrap();
+		let txdata: Vec<(usize, &Transaction)> = indexes_of_txn_matched.iter().cloned().zip(txn_matched.iter().cloned()).collect();
+		self.transactions_confirmed(header, height, &txdata);
+		self.best_block_updated(header, height);
+	}
+
+	/// We force-close the channel without letting our counterparty participate in the shutdown
+	fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let mut failed_channels = Vec::new();
-		let mut timed_out_htlcs = Vec::new();
 		{
 			let mut channel_lock = self.channel_state.lock().unwrap();
 			let channel_state = &mut *channel_lock;
 			let short_to_id = &mut channel_state.short_to_id;
 			let pending_msg_events = &mut channel_state.pending_msg_events;
-			channel_state.by_id.retain(|_, channel| {
-				let res = channel.block_connected(header, height, txn_matched, indexes_of_txn_matched);
-				if let Ok((chan_res, mut timed_out_pending_htlcs)) = res {
-					for (source, payment_hash) in timed_out_pending_htlcs.drain(..) {
-						let chan_update = self.get_channel_update(&channel).map(|u| u.encode_with_len()).unwrap(); // Cannot add/recv HTLCs before we have a short_id so unwrap is safe
-						timed_out_htlcs.push((source, payment_hash,  HTLCFailReason::Reason {
-							failure_code: 0x1000 | 14, // expiry_too_soon, or at least it is now
-							data: chan_update,
-						}));
+			channel_state.by_id.retain(|_,  v| {
+				if v.block_disconnected(header, height) {
+					for short_id in v.get_short_channel_ids() {
+						short_to_id.remove(&short_id);
 					}
-					if let Some(funding_locked) = chan_res {
-						pending_msg_events.push(events::MessageSendEvent::SendFundingLocked {
-							node_id: channel.get_counterparty_node_id(),
-							msg: funding_locked,
+					failed_channels.push(v.force_shutdown(true));
+					if let Ok(update) = self.get_channel_update(&v) {
+						pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
+							msg: update
 						});
-						if let Some(announcement_sigs) = self.get_announcement_sigs(channel) {
-							log_trace!(self.logger, "Sending funding_locked and announcement_signatures for {}", log_bytes!(channel.channel_id()));
-							pending_msg_events.push(events::MessageSendEvent::SendAnnouncementSignatures {
-								node_id: channel.get_counterparty_node_id(),
-								msg: announcement_sigs,
-							});
-						} else {
-							log_trace!(self.logger, "Sending funding_locked WITHOUT announcement_signatures for {}", log_bytes!(channel.channel_id()));
-						}
-						short_to_id.insert(channel.get_short_channel_id().unwrap(), channel.channel_id());
 					}
-				} else if let Err(e) = res {
+					false
+				} else {
+					true
+				}
+			});
+		}
+		for failure in failed_channels.drain(..) {
+			let err = "Funding transaction was reorganized out of the chain".to_owned();
+			self.finish_force_close_channel(failure, ClosureReason::ProcessingError { err });
+		}
+		let mut last_block_hash = self.last_block_hash.try_lock().expect("block_(dis)connected must not be called in parallel");
+		self.latest_block_height.fetch_sub(1, Ordering::AcqRel);
+		*last_block_hash = header.prev_blockhash;
+	}
+}
+
+impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send>
+	ConfirmationListener for ChannelManager<ChanSigner, M, T, K, F, L>
+	where M::Target: ManyChannelMonitor<Keys=ChanSigner>,
+        T::Target: BroadcasterInterface,
+        K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+        F::Target: FeeEstimator,
+				L::Target: Logger,
+{
+	fn transactions_confirmed(&self, header: &BlockHeader, height: u32, txdata: &[(usize, &Transaction)]) {
+		let block_hash = header.block_hash();
+		log_trace!(self.logger, "{} transactions confirmed in block {} at height {}", txdata.len(), block_hash, height);
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let mut failed_channels = Vec::new();
+		{
+			let mut channel_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_lock;
+			let short_to_id = &mut channel_state.short_to_id;
+			let pending_msg_events = &mut channel_state.pending_msg_events;
+			channel_state.by_id.retain(|_, channel| {
+				if let Err(e) = channel.transactions_confirmed(&block_hash, height, txdata) {
+					self.issue_channel_close_events(channel, None, ClosureReason::ProcessingError { err: e.data.clone() });
 					pending_msg_events.push(events::MessageSendEvent::HandleError {
 						node_id: channel.get_counterparty_node_id(),
 						action: msgs::ErrorAction::SendErrorMessage { msg: e },
 					});
 					return false;
 				}
-				if let Some(funding_txo) = channel.get_funding_txo() {
-					for tx in txn_matched {
+				if let Some(funding_txo) = channel.get_current_funding_txo() {
+					for &(_, tx) in txdata.iter() {
+						if channel.is_locking_splice_transaction(&tx.txid()) {
+							continue;
+						}
 						for inp in tx.input.iter() {
 							if inp.previous_output == funding_txo.into_bitcoin_outpoint() {
 								log_trace!(self.logger, "Detected channel-closing tx {} spending {}:{}, closing channel {}", tx.txid(), inp.previous_output.txid, inp.previous_output.vout, log_bytes!(channel.channel_id()));
-								if let Some(short_id) = channel.get_short_channel_id() {
+								for short_id in channel.get_short_channel_ids() {
 									short_to_id.remove(&short_id);
 								}
 								// It looks like our counterparty went on-chain. We go ahead and
@@ -3130,6 +4038,109 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 				}
 				true
 			});
+		}
+		for failure in failed_channels.drain(..) {
+			self.finish_force_close_channel(failure, ClosureReason::CommitmentTxConfirmed);
+		}
+	}
+
+	/// If our funding transaction is unconfirmed after we've sent funding_locked, we force-close
+	/// the channel without letting our counterparty participate in the shutdown
+	fn transaction_unconfirmed(&self, txid: &Txid) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let mut failed_channels = Vec::new();
+		{
+			let mut channel_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_lock;
+			let short_to_id = &mut channel_state.short_to_id;
+			let pending_msg_events = &mut channel_state.pending_msg_events;
+			channel_state.by_id.retain(|_,  v| {
+				if v.transaction_unconfirmed(txid) {
+					for short_id in v.get_short_channel_ids() {
+						short_to_id.remove(&short_id);
+					}
+					failed_channels.push(v.force_shutdown(true));
+					if let Ok(update) = self.get_channel_update(&v) {
+						pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
+							msg: update
+						});
+					}
+					false
+				} else {
+					true
+				}
+			});
+		}
+		for failure in failed_channels.drain(..) {
+			let err = "Funding transaction was reorganized out of the chain".to_owned();
+			self.finish_force_close_channel(failure, ClosureReason::ProcessingError { err });
+		}
+	}
+
+	fn best_block_updated(&self, header: &BlockHeader, height: u32) {
+		let header_hash = header.block_hash();
+		log_trace!(self.logger, "New best block {} at height {}", header_hash, height);
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let mut timed_out_htlcs = Vec::new();
+		let mut failed_channels = Vec::new();
+		{
+			let mut channel_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_lock;
+			let short_to_id = &mut channel_state.short_to_id;
+			let pending_msg_events = &mut channel_state.pending_msg_events;
+			channel_state.by_id.retain(|_, channel| {
+				if channel.funding_timed_out(height) {
+					log_info!(self.logger, "Closing inbound channel {} as its funding transaction failed to confirm in time", log_bytes!(channel.channel_id()));
+					// The channel isn't confirmed so has no short_channel_id or channel_update.
+					failed_channels.push(channel.force_shutdown(false));
+					pending_msg_events.push(events::MessageSendEvent::HandleError {
+						node_id: channel.get_counterparty_node_id(),
+						action: msgs::ErrorAction::SendErrorMessage {
+							msg: msgs::ErrorMessage {
+								channel_id: channel.channel_id(),
+								data: "Funding transaction failed to confirm in time".to_owned(),
+							},
+						},
+					});
+					false
+				} else { true }
+			});
+			for (_, channel) in channel_state.by_id.iter_mut() {
+				let (funding_locked, mut timed_out_pending_htlcs) = channel.best_block_updated(header, height);
+				for (source, payment_hash) in timed_out_pending_htlcs.drain(..) {
+					let chan_update = self.get_channel_update(&channel).map(|u| u.encode_with_len()).unwrap(); // Cannot add/recv HTLCs before we have a short_id so unwrap is safe
+					timed_out_htlcs.push((source, payment_hash,  HTLCFailReason::Reason {
+						failure_code: 0x1000 | 14, // expiry_too_soon, or at least it is now
+						data: chan_update,
+					}));
+				}
+				if let Some(funding_locked) = funding_locked {
+					pending_msg_events.push(events::MessageSendEvent::SendFundingLocked {
+						node_id: channel.get_counterparty_node_id(),
+						msg: funding_locked,
+					});
+					if let Some(announcement_sigs) = self.get_announcement_sigs(channel) {
+						log_trace!(self.logger, "Sending funding_locked and announcement_signatures for {}", log_bytes!(channel.channel_id()));
+						pending_msg_events.push(events::MessageSendEvent::SendAnnouncementSignatures {
+							node_id: channel.get_counterparty_node_id(),
+							msg: announcement_sigs,
+						});
+					} else {
+						log_trace!(self.logger, "Sending funding_locked WITHOUT announcement_signatures for {}", log_bytes!(channel.channel_id()));
+					}
+					short_to_id.insert(channel.get_short_channel_id().unwrap(), channel.channel_id());
+				}
+				let stale_short_channel_id = channel.get_pre_splice_short_channel_id();
+				if let Some((splice_locked, promoted)) = channel.check_get_splice_locked(height) {
+					pending_msg_events.push(events::MessageSendEvent::SendSpliceLocked {
+						node_id: channel.get_counterparty_node_id(),
+						msg: splice_locked,
+					});
+					if promoted {
+						self.splice_promoted(channel, stale_short_channel_id, short_to_id, pending_msg_events);
+					}
+				}
+			}
 
 			channel_state.claimable_htlcs.retain(|&(ref payment_hash, _), htlcs| {
 				htlcs.retain(|htlc| {
@@ -3150,15 +4161,18 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 				!htlcs.is_empty() // Only retain this entry if htlcs has at least one entry.
 			});
 		}
-		for failure in failed_channels.drain(..) {
-			self.finish_force_close_channel(failure);
-		}
 
 		for (source, payment_hash, reason) in timed_out_htlcs.drain(..) {
 			self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), source, &payment_hash, reason);
 		}
-		self.latest_block_height.store(height as usize, Ordering::Release);
-		*self.last_block_hash.try_lock().expect("block_(dis)connected must not be called in parallel") = header_hash;
+		for failure in failed_channels.drain(..) {
+			self.finish_force_close_channel(failure, ClosureReason::FundingTimedOut);
+		}
+		{
+			let mut last_block_hash = self.last_block_hash.try_lock().expect("block_(dis)connected must not be called in parallel");
+			self.latest_block_height.store(height as usize, Ordering::Release);
+			*last_block_hash = header_hash;
+		}
 		loop {
 			// Update last_node_announcement_serial to be the max of its current value and the
 			// block timestamp. This should keep us close to the current time without relying on
@@ -3173,37 +4187,20 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		}
 	}
 
-	/// We force-close the channel without letting our counterparty participate in the shutdown
-	fn block_disconnected(&self, header: &BlockHeader, _: u32) {
-		let _ = self.total_consistency_lock.read().unwrap();
-		let mut failed_channels = Vec::new();
-		{
-			let mut channel_lock = self.channel_state.lock().unwrap();
-			let channel_state = &mut *channel_lock;
-			let short_to_id = &mut channel_state.short_to_id;
-			let pending_msg_events = &mut channel_state.pending_msg_events;
-			channel_state.by_id.retain(|_,  v| {
-				if v.block_disconnected(header) {
-					if let Some(short_id) = v.get_short_channel_id() {
-						short_to_id.remove(&short_id);
-					}
-					failed_channels.push(v.force_shutdown(true));
-					if let Ok(update) = self.get_channel_update(&v) {
-						pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
-							msg: update
-						});
-					}
-					false
-				} else {
-					true
+	fn get_relevant_txids(&self) -> Vec<Txid> {
+		let channel_state = self.channel_state.lock().unwrap();
+		let mut res = Vec::with_capacity(channel_state.short_to_id.len());
+		for chan in channel_state.by_id.values() {
+			if let Some(funding_txo) = chan.get_current_funding_txo() {
+				if chan.get_funding_tx_confirmation_height() != 0 {
+					res.push(funding_txo.txid);
 				}
-			});
-		}
-		for failure in failed_channels.drain(..) {
-			self.finish_force_close_channel(failure);
+			}
+			if let Some(splice_txid) = chan.get_confirmed_locking_splice_txid() {
+				res.push(splice_txid);
+			}
 		}
-		self.latest_block_height.fetch_sub(1, Ordering::AcqRel);
-		*self.last_block_hash.try_lock().expect("block_(dis)connected must not be called in parallel") = header.block_hash();
+		res
 	}
 }
 
@@ -3216,87 +4213,152 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
         L::Target: Logger,
 {
 	fn handle_open_channel(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannel) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_open_channel(counterparty_node_id, their_features, msg), *counterparty_node_id);
 	}
 
 	fn handle_accept_channel(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::AcceptChannel) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_accept_channel(counterparty_node_id, their_features, msg), *counterparty_node_id);
 	}
 
 	fn handle_funding_created(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingCreated) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_funding_created(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_funding_signed(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingSigned) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_funding_signed(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_funding_locked(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingLocked) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_funding_locked(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
+	fn handle_open_channel_v2(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_open_channel_v2(counterparty_node_id, their_features, msg), *counterparty_node_id);
+	}
+
+	fn handle_accept_channel_v2(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::AcceptChannelV2) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_accept_channel_v2(counterparty_node_id, their_features, msg), *counterparty_node_id);
+	}
+
 	fn handle_shutdown(&self, counterparty_node_id: &PublicKey, msg: &msgs::Shutdown) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_shutdown(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_closing_signed(&self, counterparty_node_id: &PublicKey, msg: &msgs::ClosingSigned) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_closing_signed(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_update_add_htlc(&self, counterparty_node_id: &PublicKey, msg: &msgs::UpdateAddHTLC) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_update_add_htlc(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_update_fulfill_htlc(&self, counterparty_node_id: &PublicKey, msg: &msgs::UpdateFulfillHTLC) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_update_fulfill_htlc(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_update_fail_htlc(&self, counterparty_node_id: &PublicKey, msg: &msgs::UpdateFailHTLC) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_update_fail_htlc(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_update_fail_malformed_htlc(&self, counterparty_node_id: &PublicKey, msg: &msgs::UpdateFailMalformedHTLC) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_update_fail_malformed_htlc(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_commitment_signed(&self, counterparty_node_id: &PublicKey, msg: &msgs::CommitmentSigned) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_commitment_signed(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_revoke_and_ack(&self, counterparty_node_id: &PublicKey, msg: &msgs::RevokeAndACK) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_revoke_and_ack(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_update_fee(&self, counterparty_node_id: &PublicKey, msg: &msgs::UpdateFee) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_update_fee(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_announcement_signatures(&self, counterparty_node_id: &PublicKey, msg: &msgs::AnnouncementSignatures) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_announcement_signatures(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
 	fn handle_channel_reestablish(&self, counterparty_node_id: &PublicKey, msg: &msgs::ChannelReestablish) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let _ = handle_error!(self, self.internal_channel_reestablish(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
+	fn handle_stfu(&self, counterparty_node_id: &PublicKey, msg: &msgs::Stfu) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_stfu(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_splice_init(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceInit) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_splice_init(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_splice_ack(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceAck) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_splice_ack(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_splice_locked(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceLocked) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_splice_locked(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_tx_add_input(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAddInput) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_interactive_tx_msg(counterparty_node_id, msg.channel_id, |chan| chan.tx_add_input(msg).map(|tx_msg| (Some(tx_msg), None))), *counterparty_node_id);
+	}
+
+	fn handle_tx_add_output(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAddOutput) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_interactive_tx_msg(counterparty_node_id, msg.channel_id, |chan| chan.tx_add_output(msg).map(|tx_msg| (Some(tx_msg), None))), *counterparty_node_id);
+	}
+
+	fn handle_tx_remove_input(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxRemoveInput) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_interactive_tx_msg(counterparty_node_id, msg.channel_id, |chan| chan.tx_remove_input(msg).map(|tx_msg| (Some(tx_msg), None))), *counterparty_node_id);
+	}
+
+	fn handle_tx_remove_output(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxRemoveOutput) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_interactive_tx_msg(counterparty_node_id, msg.channel_id, |chan| chan.tx_remove_output(msg).map(|tx_msg| (Some(tx_msg), None))), *counterparty_node_id);
+	}
+
+	fn handle_tx_complete(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxComplete) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_interactive_tx_msg(counterparty_node_id, msg.channel_id, |chan| chan.tx_complete(msg, &self.logger)), *counterparty_node_id);
+	}
+
+	fn handle_tx_signatures(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxSignatures) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_tx_signatures(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_tx_abort(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAbort) {
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
+		let _ = handle_error!(self, self.internal_tx_abort(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
 	fn peer_disconnected(&self, counterparty_node_id: &PublicKey, no_connection_possible: bool) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 		let mut failed_channels = Vec::new();
 		let mut failed_payments = Vec::new();
 		let mut no_channels_remain = true;
@@ -3309,7 +4371,7 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 				log_debug!(self.logger, "Failing all channels with {} due to no_connection_possible", log_pubkey!(counterparty_node_id));
 				channel_state.by_id.retain(|_, chan| {
 					if chan.get_counterparty_node_id() == *counterparty_node_id {
-						if let Some(short_id) = chan.get_short_channel_id() {
+						for short_id in chan.get_short_channel_ids() {
 							short_to_id.remove(&short_id);
 						}
 						failed_channels.push(chan.force_shutdown(true));
@@ -3331,6 +4393,7 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 						// holding cell add-HTLCs, so if in the future we stop removing uncommitted HTLCs
 						// on peer disconnect here, there will need to be corresponding changes in
 						// reestablish logic.
+						let unbroadcast_funding_txo = chan.get_unbroadcast_funding_txo();
 						let failed_adds = chan.remove_uncommitted_htlcs_and_mark_paused(&self.logger);
 						chan.to_disabled_marked();
 						if !failed_adds.is_empty() {
@@ -3338,9 +4401,10 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 							failed_payments.push((chan_update, failed_adds));
 						}
 						if chan.is_shutdown() {
-							if let Some(short_id) = chan.get_short_channel_id() {
+							for short_id in chan.get_short_channel_ids() {
 								short_to_id.remove(&short_id);
 							}
+							self.issue_channel_close_events(&chan, unbroadcast_funding_txo, ClosureReason::DisconnectedPeer);
 							return false;
 						} else {
 							no_channels_remain = false;
@@ -3353,6 +4417,8 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 				match msg {
 					&events::MessageSendEvent::SendAcceptChannel { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendOpenChannel { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendAcceptChannelV2 { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendOpenChannelV2 { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendFundingCreated { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendFundingSigned { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendFundingLocked { ref node_id, .. } => node_id != counterparty_node_id,
@@ -3361,10 +4427,29 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 					&events::MessageSendEvent::SendRevokeAndACK { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendClosingSigned { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendShutdown { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendStfu { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendSpliceInit { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendSpliceAck { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendSpliceLocked { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxAddInput { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxAddOutput { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxRemoveInput { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxRemoveOutput { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxComplete { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxSignatures { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxAbort { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendChannelReestablish { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::BroadcastChannelAnnouncement { .. } => true,
 					&events::MessageSendEvent::BroadcastNodeAnnouncement { .. } => true,
 					&events::MessageSendEvent::BroadcastChannelUpdate { .. } => true,
+					&events::MessageSendEvent::SendChannelAnnouncement { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendNodeAnnouncement { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendChannelUpdate { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendReplyChannelRange { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendReplyShortChannelIdsEnd { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendChannelRangeQuery { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendShortIdsQuery { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendGossipTimestampFilter { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::HandleError { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::PaymentFailureNetworkUpdate { .. } => true,
 				}
@@ -3375,7 +4460,7 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		}
 
 		for failure in failed_channels.drain(..) {
-			self.finish_force_close_channel(failure);
+			self.finish_force_close_channel(failure, ClosureReason::DisconnectedPeer);
 		}
 		for (chan_update, mut htlc_sources) in failed_payments {
 			for (htlc_source, payment_hash) in htlc_sources.drain(..) {
@@ -3387,7 +4472,7 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 	fn peer_connected(&self, counterparty_node_id: &PublicKey, init_msg: &msgs::Init) {
 		log_debug!(self.logger, "Generating channel_reestablish events for {}", log_pubkey!(counterparty_node_id));
 
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
 		{
 			let mut peer_state_lock = self.per_peer_state.write().unwrap();
@@ -3413,6 +4498,7 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 					// peer we probably failed to send the open_channel message, which is now
 					// lost. We can't have had anything pending related to this channel, so we just
 					// drop it.
+					self.issue_channel_close_events(chan, None, ClosureReason::DisconnectedPeer);
 					false
 				} else {
 					pending_msg_events.push(events::MessageSendEvent::SendChannelReestablish {
@@ -3426,17 +4512,21 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		//TODO: Also re-broadcast announcement_signatures
 	}
 
+	fn supports_anchor_outputs(&self) -> bool {
+		self.default_configuration.own_channel_config.negotiate_anchor_outputs
+	}
+
 	fn handle_error(&self, counterparty_node_id: &PublicKey, msg: &msgs::ErrorMessage) {
-		let _ = self.total_consistency_lock.read().unwrap();
+		let _persistence_guard = PersistenceNotifierGuard::new(&self.total_consistency_lock, &self.persistence_notifier);
 
 		if msg.channel_id == [0; 32] {
 			for chan in self.list_channels() {
 				if chan.remote_network_id == *counterparty_node_id {
-					self.force_close_channel(&chan.channel_id);
+					self.force_close_channel_with_reason(&chan.channel_id, ClosureReason::CounterpartyForceClosed { peer_msg: msg.data.clone() });
 				}
 			}
 		} else {
-			self.force_close_channel(&msg.channel_id);
+			self.force_close_channel_with_reason(&msg.channel_id, ClosureReason::CounterpartyForceClosed { peer_msg: msg.data.clone() });
 		}
 	}
 }
@@ -3452,11 +4542,17 @@ impl Writeable for PendingHTLCInfo {
 				onion_packet.write(writer)?;
 				short_channel_id.write(writer)?;
 			},
-			&PendingHTLCRouting::Receive { ref payment_data, ref incoming_cltv_expiry } => {
+			&PendingHTLCRouting::Receive { ref payment_data, keysend_preimage: None, ref incoming_cltv_expiry } => {
 				1u8.write(writer)?;
 				payment_data.write(writer)?;
 				incoming_cltv_expiry.write(writer)?;
 			},
+			&PendingHTLCRouting::Receive { ref payment_data, keysend_preimage: Some(ref keysend_preimage), ref incoming_cltv_expiry } => {
+				2u8.write(writer)?;
+				payment_data.write(writer)?;
+				keysend_preimage.write(writer)?;
+				incoming_cltv_expiry.write(writer)?;
+			},
 		}
 		self.incoming_shared_secret.write(writer)?;
 		self.payment_hash.write(writer)?;
@@ -3476,6 +4572,12 @@ impl Readable for PendingHTLCInfo {
 				},
 				1u8 => PendingHTLCRouting::Receive {
 					payment_data: Readable::read(reader)?,
+					keysend_preimage: None,
+					incoming_cltv_expiry: Readable::read(reader)?,
+				},
+				2u8 => PendingHTLCRouting::Receive {
+					payment_data: Readable::read(reader)?,
+					keysend_preimage: Some(Readable::read(reader)?),
 					incoming_cltv_expiry: Readable::read(reader)?,
 				},
 				_ => return Err(DecodeError::InvalidValue),
@@ -3844,8 +4946,9 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 		let mut funding_txo_set = HashSet::with_capacity(cmp::min(channel_count as usize, 128));
 		let mut by_id = HashMap::with_capacity(cmp::min(channel_count as usize, 128));
 		let mut short_to_id = HashMap::with_capacity(cmp::min(channel_count as usize, 128));
+		let mut closed_channel_events = Vec::new();
 		for _ in 0..channel_count {
-			let mut channel: Channel<ChanSigner> = Readable::read(reader)?;
+			let mut channel: Channel<ChanSigner> = ReadableArgs::read(reader, latest_block_height)?;
 			if channel.last_block_connected != Default::default() && channel.last_block_connected != last_block_hash {
 				return Err(DecodeError::InvalidValue);
 			}
@@ -3864,11 +4967,16 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 						channel.get_cur_counterparty_commitment_transaction_number() > monitor.get_cur_counterparty_commitment_number() ||
 						channel.get_latest_monitor_update_id() < monitor.get_latest_update_id() {
 					// But if the channel is behind of the monitor, close the channel:
-					let (_, _, mut new_failed_htlcs) = channel.force_shutdown(true);
+					let (_, _, mut new_failed_htlcs, _) = channel.force_shutdown(true);
 					failed_htlcs.append(&mut new_failed_htlcs);
+					closed_channel_events.push(events::Event::ChannelClosed {
+						channel_id: channel.channel_id(),
+						user_channel_id: channel.get_user_id(),
+						reason: ClosureReason::ProcessingError { err: "ChannelManager was stale relative to the channel's ChannelMonitor".to_owned() },
+					});
 					monitor.broadcast_latest_holder_commitment_txn(&args.tx_broadcaster, &args.logger);
 				} else {
-					if let Some(short_channel_id) = channel.get_short_channel_id() {
+					for short_channel_id in channel.get_short_channel_ids() {
 						short_to_id.insert(short_channel_id, channel.channel_id());
 					}
 					by_id.insert(channel.channel_id(), channel);
@@ -3927,6 +5035,7 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 				None => continue,
 			}
 		}
+		pending_events_read.append(&mut closed_channel_events);
 
 		let last_node_announcement_serial: u32 = Readable::read(reader)?;
 
@@ -3955,6 +5064,7 @@ impl<'a, ChanSigner: ChannelKeys + Readable, M: Deref, T: Deref, K: Deref, F: De
 
 			pending_events: Mutex::new(pending_events_read),
 			total_consistency_lock: RwLock::new(()),
+			persistence_notifier: PersistenceNotifier::new(),
 			keys_manager: args.keys_manager,
 			logger: args.logger,
 			default_configuration: args.default_config,
@@ -21,7 +21,7 @@
 //! ChannelMonitors to get out of the HSM and onto monitoring devices.
 
 use bitcoin::blockdata::block::BlockHeader;
-use bitcoin::blockdata::transaction::{TxOut,Transaction};
+use bitcoin::blockdata::transaction::{TxIn,TxOut,Transaction,SigHashType};
 use bitcoin::blockdata::transaction::OutPoint as BitcoinOutPoint;
 use bitcoin::blockdata::script::{Script, Builder};
 use bitcoin::blockdata::opcodes;
@@ -37,14 +37,14 @@ use bitcoin::secp256k1;
 
 use ln::msgs::DecodeError;
 use ln::chan_utils;
-use ln::chan_utils::{CounterpartyCommitmentSecrets, HTLCOutputInCommitment, HolderCommitmentTransaction, HTLCType};
+use ln::chan_utils::{CounterpartyCommitmentSecrets, HTLCOutputInCommitment, HolderCommitmentTransaction, HTLCType, TxCreationKeys};
 use ln::channelmanager::{HTLCSource, PaymentPreimage, PaymentHash};
-use ln::onchaintx::{OnchainTxHandler, InputDescriptors};
-use chain::chaininterface::{ChainListener, ChainWatchInterface, BroadcasterInterface, FeeEstimator};
+use ln::onchaintx::{OnchainTxHandler, InputDescriptors, ClaimEvent};
+use chain::chaininterface::{ChainListener, ChainWatchInterface, BroadcasterInterface, ConfirmationListener, FeeEstimator};
 use chain::transaction::OutPoint;
 use chain::keysinterface::{SpendableOutputDescriptor, ChannelKeys};
 use util::logger::Logger;
-use util::ser::{Readable, MaybeReadable, Writer, Writeable, U48};
+use util::ser::{Readable, ReadableArgs, MaybeReadable, Writer, Writeable, U48};
 use util::{byte_utils, events};
 use util::events::Event;
 
@@ -173,9 +173,13 @@ pub enum MonitorEvent {
 pub struct HTLCUpdate {
 	pub(super) payment_hash: PaymentHash,
 	pub(super) payment_preimage: Option<PaymentPreimage>,
-	pub(super) source: HTLCSource
+	pub(super) source: HTLCSource,
+	/// The amount of the HTLC, set if we learned its preimage on-chain.
+	pub(super) htlc_value_msat: Option<u64>,
+	/// The id of the channel the HTLC was resolved on.
+	pub(super) channel_id: [u8; 32],
 }
-impl_writeable!(HTLCUpdate, 0, { payment_hash, payment_preimage, source });
+impl_writeable!(HTLCUpdate, 0, { payment_hash, payment_preimage, source, htlc_value_msat, channel_id });
 
 /// A simple implementation of a ManyChannelMonitor and ChainListener. Can be used to create a
 /// watchtower or watch our own channels.
@@ -204,6 +208,22 @@ pub struct SimpleManyChannelMonitor<Key, ChanSigner: ChannelKeys, T: Deref, F: D
 	fee_estimator: F
 }
 
+impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref> SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+        C::Target: ChainWatchInterface,
+{
+	/// Registers the outputs returned by a monitor with the chain watch interface.
+	fn install_watch_outputs(&self, txn_outputs: Vec<(Txid, Vec<TxOut>)>) {
+		for (ref txid, ref outputs) in txn_outputs {
+			for (idx, output) in outputs.iter().enumerate() {
+				self.chain_monitor.install_watch_outpoint((txid.clone(), idx as u32), &output.script_pubkey);
+			}
+		}
+	}
+}
+
 impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, C: Deref + Sync + Send>
 	ChainListener for SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C>
 	where T::Target: BroadcasterInterface,
@@ -212,28 +232,60 @@ impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref + Sync
         C::Target: ChainWatchInterface,
 {
 	fn block_connected(&self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], _indexes_of_txn_matched: &[usize]) {
-		let block_hash = header.block_hash();
 		{
 			let mut monitors = self.monitors.lock().unwrap();
 			for monitor in monitors.values_mut() {
-				let txn_outputs = monitor.block_connected(txn_matched, height, &block_hash, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
-
-				for (ref txid, ref outputs) in txn_outputs {
-					for (idx, output) in outputs.iter().enumerate() {
-						self.chain_monitor.install_watch_outpoint((txid.clone(), idx as u32), &output.script_pubkey);
-					}
-				}
+				let txn_outputs = monitor.block_connected(header, txn_matched, height, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+				self.install_watch_outputs(txn_outputs);
 			}
 		}
 	}
 
 	fn block_disconnected(&self, header: &BlockHeader, disconnected_height: u32) {
-		let block_hash = header.block_hash();
 		let mut monitors = self.monitors.lock().unwrap();
 		for monitor in monitors.values_mut() {
-			monitor.block_disconnected(disconnected_height, &block_hash, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+			monitor.block_disconnected(header, disconnected_height, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+		}
+	}
+}
+
+impl<Key : Send + cmp::Eq + hash::Hash, ChanSigner: ChannelKeys, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, C: Deref + Sync + Send>
+	ConfirmationListener for SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C>
+	where T::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+        C::Target: ChainWatchInterface,
+{
+	fn transactions_confirmed(&self, header: &BlockHeader, height: u32, txdata: &[(usize, &Transaction)]) {
+		let mut monitors = self.monitors.lock().unwrap();
+		for monitor in monitors.values_mut() {
+			let txn_outputs = monitor.transactions_confirmed(header, txdata, height, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+			self.install_watch_outputs(txn_outputs);
+		}
+	}
+
+	fn transaction_unconfirmed(&self, txid: &Txid) {
+		let mut monitors = self.monitors.lock().unwrap();
+		for monitor in monitors.values_mut() {
+			monitor.transaction_unconfirmed(txid, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+		}
+	}
+
+	fn best_block_updated(&self, header: &BlockHeader, height: u32) {
+		let mut monitors = self.monitors.lock().unwrap();
+		for monitor in monitors.values_mut() {
+			let txn_outputs = monitor.best_block_updated(header, height, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+			self.install_watch_outputs(txn_outputs);
 		}
 	}
+
+	fn get_relevant_txids(&self) -> Vec<Txid> {
+		let monitors = self.monitors.lock().unwrap();
+		let mut txids: Vec<Txid> = monitors.values().flat_map(|monitor| monitor.get_relevant_txids()).collect();
+		txids.sort();
+		txids.dedup();
+		txids
+	}
 }
 
 impl<Key : Send + cmp::Eq + hash::Hash + 'static, ChanSigner: ChannelKeys, T: Deref, F: Deref, L: Deref, C: Deref> SimpleManyChannelMonitor<Key, ChanSigner, T, F, L, C>
@@ -265,9 +317,13 @@ impl<Key : Send + cmp::Eq + hash::Hash + 'static, ChanSigner: ChannelKeys, T: De
 		};
 		{
 			let funding_txo = monitor.get_funding_txo();
-			log_trace!(self.logger, "Got new Channel Monitor for channel {}", log_bytes!(funding_txo.0.to_channel_id()[..]));
+			log_trace!(self.logger, "Got new Channel Monitor for channel {}", log_bytes!(monitor.channel_id[..]));
 			self.chain_monitor.install_watch_tx(&funding_txo.0.txid, &funding_txo.1);
 			self.chain_monitor.install_watch_outpoint((funding_txo.0.txid, funding_txo.0.index as u32), &funding_txo.1);
+			for splice_txo in monitor.get_splice_funding_txos() {
+				self.chain_monitor.install_watch_tx(&splice_txo.txid, &funding_txo.1);
+				self.chain_monitor.install_watch_outpoint((splice_txo.txid, splice_txo.index as u32), &funding_txo.1);
+			}
 			for (txid, outputs) in monitor.get_outputs_to_watch().iter() {
 				for (idx, script) in outputs.iter().enumerate() {
 					self.chain_monitor.install_watch_outpoint((*txid, idx as u32), script);
@@ -284,7 +340,13 @@ impl<Key : Send + cmp::Eq + hash::Hash + 'static, ChanSigner: ChannelKeys, T: De
 		match monitors.get_mut(&key) {
 			Some(orig_monitor) => {
 				log_trace!(self.logger, "Updating Channel Monitor for channel {}", log_funding_info!(orig_monitor));
-				orig_monitor.update_monitor(update, &self.broadcaster, &self.logger)
+				orig_monitor.update_monitor(update, &self.broadcaster, &self.logger)?;
+				let funding_script = &orig_monitor.get_funding_txo().1;
+				for splice_txo in orig_monitor.get_splice_funding_txos() {
+					self.chain_monitor.install_watch_tx(&splice_txo.txid, funding_script);
+					self.chain_monitor.install_watch_outpoint((splice_txo.txid, splice_txo.index as u32), funding_script);
+				}
+				Ok(())
 			},
 			None => Err(MonitorUpdateError("No such monitor registered"))
 		}
@@ -401,6 +463,44 @@ struct HolderSignedTx {
 	htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>,
 }
 
+/// A splice of the funding output which has been signed but not yet seen on chain with
+/// ANTI_REORG_DELAY confirmations.
+#[derive(Clone, PartialEq)]
+struct PendingSplice {
+	funding_outpoint: OutPoint,
+	channel_value_satoshis: u64,
+	commitment_tx: HolderCommitmentTransaction,
+	htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>,
+}
+
+impl Writeable for PendingSplice {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.funding_outpoint.write(w)?;
+		self.channel_value_satoshis.write(w)?;
+		self.commitment_tx.write(w)?;
+		(self.htlc_outputs.len() as u64).write(w)?;
+		for &(ref output, ref signature, ref source) in self.htlc_outputs.iter() {
+			output.write(w)?;
+			signature.write(w)?;
+			source.write(w)?;
+		}
+		Ok(())
+	}
+}
+impl Readable for PendingSplice {
+	fn read<R: ::std::io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let funding_outpoint = Readable::read(r)?;
+		let channel_value_satoshis = Readable::read(r)?;
+		let commitment_tx = Readable::read(r)?;
+		let htlcs_len: u64 = Readable::read(r)?;
+		let mut htlc_outputs = Vec::with_capacity(cmp::min(htlcs_len as usize, MAX_ALLOC_SIZE / 128));
+		for _ in 0..htlcs_len {
+			htlc_outputs.push((Readable::read(r)?, Readable::read(r)?, Readable::read(r)?));
+		}
+		Ok(Self { funding_outpoint, channel_value_satoshis, commitment_tx, htlc_outputs })
+	}
+}
+
 /// We use this to track counterparty commitment transactions and htlcs outputs and
 /// use it to generate any justice or 2nd-stage preimage/timeout transactions.
 #[derive(PartialEq)]
@@ -616,10 +716,15 @@ enum OnchainEvent {
 	MaturingOutput {
 		descriptor: SpendableOutputDescriptor,
 	},
+	/// The transaction creating the pending splice's funding output confirmed, at maturation we
+	/// move over to the new funding output.
+	SpliceConfirmed {
+		funding_outpoint: OutPoint,
+	},
 }
 
-const SERIALIZATION_VERSION: u8 = 1;
-const MIN_SERIALIZATION_VERSION: u8 = 1;
+const SERIALIZATION_VERSION: u8 = 2;
+const MIN_SERIALIZATION_VERSION: u8 = 2;
 
 #[cfg_attr(test, derive(PartialEq))]
 #[derive(Clone)]
@@ -648,6 +753,23 @@ pub(super) enum ChannelMonitorUpdateStep {
 		/// think we've fallen behind!
 		should_broadcast: bool,
 	},
+	/// Used to indicate that a splice of the funding output has been negotiated and that the given
+	/// holder commitment transaction, spending the splice transaction's funding output, has been
+	/// signed by our counterparty. Provided again alongside each new holder commitment transaction
+	/// until the splice locks.
+	SpliceFundingSigned {
+		funding_outpoint: OutPoint,
+		channel_value_satoshis: u64,
+		commitment_tx: HolderCommitmentTransaction,
+		htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>,
+	},
+	/// Used to indicate that we've signed a counterparty commitment transaction spending the
+	/// funding output of a splice. It has the same commitment number as the latest counterparty
+	/// commitment transaction, which it may be broadcast in place of.
+	SpliceCounterpartyCommitmentTXInfo {
+		unsigned_commitment_tx: Transaction, // TODO: We should actually only need the txid here
+		htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>,
+	},
 }
 
 impl Writeable for ChannelMonitorUpdateStep {
@@ -687,6 +809,27 @@ impl Writeable for ChannelMonitorUpdateStep {
 				4u8.write(w)?;
 				should_broadcast.write(w)?;
 			},
+			&ChannelMonitorUpdateStep::SpliceFundingSigned { ref funding_outpoint, ref channel_value_satoshis, ref commitment_tx, ref htlc_outputs } => {
+				5u8.write(w)?;
+				funding_outpoint.write(w)?;
+				channel_value_satoshis.write(w)?;
+				commitment_tx.write(w)?;
+				(htlc_outputs.len() as u64).write(w)?;
+				for &(ref output, ref signature, ref source) in htlc_outputs.iter() {
+					output.write(w)?;
+					signature.write(w)?;
+					source.write(w)?;
+				}
+			},
+			&ChannelMonitorUpdateStep::SpliceCounterpartyCommitmentTXInfo { ref unsigned_commitment_tx, ref htlc_outputs } => {
+				6u8.write(w)?;
+				unsigned_commitment_tx.write(w)?;
+				(htlc_outputs.len() as u64).write(w)?;
+				for &(ref output, ref source) in htlc_outputs.iter() {
+					output.write(w)?;
+					source.as_ref().map(|b| b.as_ref()).write(w)?;
+				}
+			},
 		}
 		Ok(())
 	}
@@ -738,6 +881,34 @@ impl Readable for ChannelMonitorUpdateStep {
 					should_broadcast: Readable::read(r)?
 				})
 			},
+			5u8 => {
+				Ok(ChannelMonitorUpdateStep::SpliceFundingSigned {
+					funding_outpoint: Readable::read(r)?,
+					channel_value_satoshis: Readable::read(r)?,
+					commitment_tx: Readable::read(r)?,
+					htlc_outputs: {
+						let len: u64 = Readable::read(r)?;
+						let mut res = Vec::new();
+						for _ in 0..len {
+							res.push((Readable::read(r)?, Readable::read(r)?, Readable::read(r)?));
+						}
+						res
+					},
+				})
+			},
+			6u8 => {
+				Ok(ChannelMonitorUpdateStep::SpliceCounterpartyCommitmentTXInfo {
+					unsigned_commitment_tx: Readable::read(r)?,
+					htlc_outputs: {
+						let len: u64 = Readable::read(r)?;
+						let mut res = Vec::new();
+						for _ in 0..len {
+							res.push((Readable::read(r)?, <Option<HTLCSource> as Readable>::read(r)?.map(|o| Box::new(o))));
+						}
+						res
+					},
+				})
+			},
 			_ => Err(DecodeError::InvalidValue),
 		}
 	}
@@ -764,6 +935,8 @@ pub struct ChannelMonitor<ChanSigner: ChannelKeys> {
 
 	keys: ChanSigner,
 	funding_info: (OutPoint, Script),
+	// The channel's id, which for dual-funded channels is not derived from funding_info.
+	channel_id: [u8; 32],
 	current_counterparty_commitment_txid: Option<Txid>,
 	prev_counterparty_commitment_txid: Option<Txid>,
 
@@ -838,12 +1011,31 @@ pub struct ChannelMonitor<ChanSigner: ChannelKeys> {
 	// remote monitor out-of-order with regards to the block view.
 	holder_tx_signed: bool,
 
+	// Set when our holder commitment transaction of an anchor channel was broadcast outside of
+	// block_connected (ie on a manual force-close), so that the next block hands it over to the
+	// OnchainTxHandler which can then track it and request CPFP bumps of it.
+	holder_tx_bump_pending: bool,
+
+	// Transactions passed to transactions_confirmed, with the height at which they were confirmed,
+	// until they reach ANTI_REORG_DELAY confirmations. These are the transactions we must be told
+	// of the unconfirmation of via transaction_unconfirmed, so that we can undo their effects.
+	onchain_txn_awaiting_threshold_conf: Vec<(u32, Transaction)>,
+
+	// Set when a splice of the funding output has been negotiated, until the splice transaction is
+	// seen on chain, at which point its funding output replaces the current one.
+	pending_splice: Option<PendingSplice>,
+	// The funding outpoint spent by our commitment transactions once the channel has been spliced.
+	// funding_info keeps the original funding outpoint, which continues to identify the channel.
+	spliced_funding_outpoint: Option<OutPoint>,
+
 	// We simply modify last_block_hash in Channel's block_connected so that serialization is
 	// consistent but hopefully the users' copy handles block_connected in a consistent way.
 	// (we do *not*, however, update them in update_monitor to ensure any local user copies keep
 	// their last_block_hash from its state and not based on updated copies that didn't run through
 	// the full block_connected).
+	// After a block is disconnected, these refer to its parent.
 	last_block_hash: BlockHash,
+	last_block_height: u32,
 	secp_ctx: Secp256k1<secp256k1::All>, //TODO: dedup this a bit...
 }
 
@@ -928,6 +1120,7 @@ impl<ChanSigner: ChannelKeys> PartialEq for ChannelMonitor<ChanSigner> {
 			self.counterparty_payment_script != other.counterparty_payment_script ||
 			self.keys.pubkeys() != other.keys.pubkeys() ||
 			self.funding_info != other.funding_info ||
+			self.channel_id != other.channel_id ||
 			self.current_counterparty_commitment_txid != other.current_counterparty_commitment_txid ||
 			self.prev_counterparty_commitment_txid != other.prev_counterparty_commitment_txid ||
 			self.counterparty_tx_cache != other.counterparty_tx_cache ||
@@ -949,7 +1142,11 @@ impl<ChanSigner: ChannelKeys> PartialEq for ChannelMonitor<ChanSigner> {
 			self.onchain_events_waiting_threshold_conf != other.onchain_events_waiting_threshold_conf ||
 			self.outputs_to_watch != other.outputs_to_watch ||
 			self.lockdown_from_offchain != other.lockdown_from_offchain ||
-			self.holder_tx_signed != other.holder_tx_signed
+			self.holder_tx_signed != other.holder_tx_signed ||
+			self.holder_tx_bump_pending != other.holder_tx_bump_pending ||
+			self.onchain_txn_awaiting_threshold_conf != other.onchain_txn_awaiting_threshold_conf ||
+			self.pending_splice != other.pending_splice ||
+			self.spliced_funding_outpoint != other.spliced_funding_outpoint
 		{
 			false
 		} else {
@@ -994,6 +1191,7 @@ impl<ChanSigner: ChannelKeys + Writeable> ChannelMonitor<ChanSigner> {
 		writer.write_all(&self.funding_info.0.txid[..])?;
 		writer.write_all(&byte_utils::be16_to_array(self.funding_info.0.index))?;
 		self.funding_info.1.write(writer)?;
+		self.channel_id.write(writer)?;
 		self.current_counterparty_commitment_txid.write(writer)?;
 		self.prev_counterparty_commitment_txid.write(writer)?;
 
@@ -1117,6 +1315,7 @@ impl<ChanSigner: ChannelKeys + Writeable> ChannelMonitor<ChanSigner> {
 		}
 
 		self.last_block_hash.write(writer)?;
+		writer.write_all(&byte_utils::be32_to_array(self.last_block_height))?;
 
 		writer.write_all(&byte_utils::be64_to_array(self.onchain_events_waiting_threshold_conf.len() as u64))?;
 		for (ref target, ref events) in self.onchain_events_waiting_threshold_conf.iter() {
@@ -1133,6 +1332,10 @@ impl<ChanSigner: ChannelKeys + Writeable> ChannelMonitor<ChanSigner> {
 						1u8.write(writer)?;
 						descriptor.write(writer)?;
 					},
+					OnchainEvent::SpliceConfirmed { ref funding_outpoint } => {
+						2u8.write(writer)?;
+						funding_outpoint.write(writer)?;
+					},
 				}
 			}
 		}
@@ -1149,6 +1352,15 @@ impl<ChanSigner: ChannelKeys + Writeable> ChannelMonitor<ChanSigner> {
 
 		self.lockdown_from_offchain.write(writer)?;
 		self.holder_tx_signed.write(writer)?;
+		self.holder_tx_bump_pending.write(writer)?;
+
+		(self.onchain_txn_awaiting_threshold_conf.len() as u64).write(writer)?;
+		for &(height, ref tx) in self.onchain_txn_awaiting_threshold_conf.iter() {
+			height.write(writer)?;
+			tx.write(writer)?;
+		}
+		self.pending_splice.write(writer)?;
+		self.spliced_funding_outpoint.write(writer)?;
 
 		Ok(())
 	}
@@ -1156,21 +1368,21 @@ impl<ChanSigner: ChannelKeys + Writeable> ChannelMonitor<ChanSigner> {
 
 impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 	pub(super) fn new(keys: ChanSigner, shutdown_pubkey: &PublicKey,
-			on_counterparty_tx_csv: u16, destination_script: &Script, funding_info: (OutPoint, Script),
+			on_counterparty_tx_csv: u16, destination_script: &Script, funding_info: (OutPoint, Script), channel_id: [u8; 32],
 			counterparty_htlc_base_key: &PublicKey, counterparty_delayed_payment_base_key: &PublicKey,
 			on_holder_tx_csv: u16, funding_redeemscript: Script, channel_value_satoshis: u64,
 			commitment_transaction_number_obscure_factor: u64,
-			initial_holder_commitment_tx: HolderCommitmentTransaction) -> ChannelMonitor<ChanSigner> {
+			initial_holder_commitment_tx: HolderCommitmentTransaction,
+			best_block: (BlockHash, u32)) -> ChannelMonitor<ChanSigner> {
 
 		assert!(commitment_transaction_number_obscure_factor <= (1 << 48));
 		let our_channel_close_key_hash = WPubkeyHash::hash(&shutdown_pubkey.serialize());
 		let shutdown_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&our_channel_close_key_hash[..]).into_script();
-		let payment_key_hash = WPubkeyHash::hash(&keys.pubkeys().payment_point.serialize());
-		let counterparty_payment_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&payment_key_hash[..]).into_script();
+		let counterparty_payment_script = chan_utils::get_to_countersignatory_script_pubkey(&keys.pubkeys().payment_point, initial_holder_commitment_tx.opt_anchors);
 
 		let counterparty_tx_cache = CounterpartyCommitmentTransaction { counterparty_delayed_payment_base_key: *counterparty_delayed_payment_base_key, counterparty_htlc_base_key: *counterparty_htlc_base_key, on_counterparty_tx_csv, per_htlc: HashMap::new() };
 
-		let mut onchain_tx_handler = OnchainTxHandler::new(destination_script.clone(), keys.clone(), on_holder_tx_csv);
+		let mut onchain_tx_handler = OnchainTxHandler::new(destination_script.clone(), keys.clone(), on_holder_tx_csv, initial_holder_commitment_tx.opt_anchors);
 
 		let holder_tx_sequence = initial_holder_commitment_tx.unsigned_tx.input[0].sequence as u64;
 		let holder_tx_locktime = initial_holder_commitment_tx.unsigned_tx.lock_time as u64;
@@ -1197,6 +1409,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 
 			keys,
 			funding_info,
+			channel_id,
 			current_counterparty_commitment_txid: None,
 			prev_counterparty_commitment_txid: None,
 
@@ -1228,8 +1441,13 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 
 			lockdown_from_offchain: false,
 			holder_tx_signed: false,
+			holder_tx_bump_pending: false,
+			onchain_txn_awaiting_threshold_conf: Vec::new(),
+			pending_splice: None,
+			spliced_funding_outpoint: None,
 
-			last_block_hash: Default::default(),
+			last_block_hash: best_block.0,
+			last_block_height: best_block.1,
 			secp_ctx: Secp256k1::new(),
 		}
 	}
@@ -1333,12 +1551,46 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		self.counterparty_tx_cache.per_htlc.insert(new_txid, htlcs);
 	}
 
+	/// Informs this monitor of a counterparty commitment transaction spending the funding output of
+	/// a pending splice. It shares its commitment number with the latest counterparty commitment
+	/// transaction, so we only track its HTLC outputs in case it is broadcast instead.
+	fn provide_splice_counterparty_commitment_tx_info<L: Deref>(&mut self, unsigned_commitment_tx: &Transaction, htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>, logger: &L) where L::Target: Logger {
+		for &(ref htlc, _) in &htlc_outputs {
+			self.counterparty_hash_commitment_number.insert(htlc.payment_hash, self.current_counterparty_commitment_number);
+		}
+
+		let new_txid = unsigned_commitment_tx.txid();
+		log_trace!(logger, "Tracking new counterparty splice commitment transaction with txid {} at commitment number {} with {} HTLC outputs", new_txid, self.current_counterparty_commitment_number, htlc_outputs.len());
+		let mut htlcs = Vec::with_capacity(htlc_outputs.len());
+		for htlc in htlc_outputs.iter() {
+			if htlc.0.transaction_output_index.is_some() {
+				htlcs.push(htlc.0.clone());
+			}
+		}
+		self.counterparty_claimable_outpoints.insert(new_txid, htlc_outputs);
+		self.counterparty_tx_cache.per_htlc.insert(new_txid, htlcs);
+	}
+
 	/// Informs this monitor of the latest holder (ie broadcastable) commitment transaction. The
 	/// monitor watches for timeouts and may broadcast it if we approach such a timeout. Thus, it
 	/// is important that any clones of this channel monitor (including remote clones) by kept
 	/// up-to-date as our holder commitment transaction is updated.
 	/// Panics if set_on_holder_tx_csv has never been called.
-	pub(super) fn provide_latest_holder_commitment_tx_info(&mut self, commitment_tx: HolderCommitmentTransaction, htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>) -> Result<(), MonitorUpdateError> {
+	pub(super) fn provide_latest_holder_commitment_tx_info<L: Deref>(&mut self, commitment_tx: HolderCommitmentTransaction, htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>, logger: &L) -> Result<(), MonitorUpdateError> where L::Target: Logger {
+		// Once the splice has locked our counterparty may sign commitment transactions spending its
+		// funding output before we've seen it reach ANTI_REORG_DELAY confirmations ourselves.
+		let spends_pending_splice = match self.pending_splice {
+			Some(ref splice) => commitment_tx.unsigned_tx.input[0].previous_output == splice.funding_outpoint.into_bitcoin_outpoint(),
+			None => false,
+		};
+		if spends_pending_splice {
+			self.promote_pending_splice(logger);
+		} else if self.spliced_funding_outpoint.is_some() && commitment_tx.unsigned_tx.input[0].previous_output != self.get_current_funding_outpoint().into_bitcoin_outpoint() {
+			// Until the Channel sees the splice lock too it keeps signing commitment transactions
+			// spending the funding output the splice spent, which are of no use to us.
+			log_trace!(logger, "Ignoring holder commitment transaction {} spending our previous funding output", commitment_tx.txid());
+			return Ok(());
+		}
 		let txid = commitment_tx.txid();
 		let sequence = commitment_tx.unsigned_tx.input[0].sequence as u64;
 		let locktime = commitment_tx.unsigned_tx.lock_time as u64;
@@ -1375,6 +1627,18 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		for tx in self.get_latest_holder_commitment_txn(logger).iter() {
 			broadcaster.broadcast_transaction(tx);
 		}
+		// If a splice is pending, either the splice transaction or our current funding spend may end
+		// up confirming, so broadcast a commitment transaction spending each.
+		if let Some(ref splice) = self.pending_splice {
+			let mut keys = self.keys.clone();
+			keys.on_splice(splice.channel_value_satoshis);
+			if let Ok(sig) = keys.sign_holder_commitment(&splice.commitment_tx, &self.secp_ctx) {
+				broadcaster.broadcast_transaction(&splice.commitment_tx.add_holder_sig(&self.funding_redeemscript, sig));
+			}
+		}
+		if self.onchain_tx_handler.opt_anchors() {
+			self.holder_tx_bump_pending = true;
+		}
 		self.pending_monitor_events.push(MonitorEvent::CommitmentTxBroadcasted(self.funding_info.0));
 	}
 
@@ -1393,7 +1657,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 			match update {
 				ChannelMonitorUpdateStep::LatestHolderCommitmentTXInfo { commitment_tx, htlc_outputs } => {
 					if self.lockdown_from_offchain { panic!(); }
-					self.provide_latest_holder_commitment_tx_info(commitment_tx, htlc_outputs)?
+					self.provide_latest_holder_commitment_tx_info(commitment_tx, htlc_outputs, logger)?
 				},
 				ChannelMonitorUpdateStep::LatestCounterpartyCommitmentTXInfo { unsigned_commitment_tx, htlc_outputs, commitment_number, their_revocation_point } =>
 					self.provide_latest_counterparty_commitment_tx_info(&unsigned_commitment_tx, htlc_outputs, commitment_number, their_revocation_point, logger),
@@ -1408,7 +1672,20 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 					} else {
 						log_error!(logger, "You have a toxic holder commitment transaction avaible in channel monitor, read comment in ChannelMonitor::get_latest_holder_commitment_txn to be informed of manual action to take");
 					}
-				}
+				},
+				ChannelMonitorUpdateStep::SpliceFundingSigned { funding_outpoint, channel_value_satoshis, commitment_tx, htlc_outputs } => {
+					if self.lockdown_from_offchain { panic!(); }
+					if self.spliced_funding_outpoint == Some(funding_outpoint) {
+						// We've already moved over to the splice's funding output, so this is simply our
+						// latest holder commitment transaction.
+						self.provide_latest_holder_commitment_tx_info(commitment_tx, htlc_outputs, logger)?
+					} else {
+						log_trace!(logger, "Tracking splice of channel {} into funding outpoint {}:{}", log_bytes!(self.channel_id), funding_outpoint.txid, funding_outpoint.index);
+						self.pending_splice = Some(PendingSplice { funding_outpoint, channel_value_satoshis, commitment_tx, htlc_outputs });
+					}
+				},
+				ChannelMonitorUpdateStep::SpliceCounterpartyCommitmentTXInfo { unsigned_commitment_tx, htlc_outputs } =>
+					self.provide_splice_counterparty_commitment_tx_info(&unsigned_commitment_tx, htlc_outputs, logger),
 			}
 		}
 		self.latest_update_id = updates.update_id;
@@ -1421,11 +1698,71 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		self.latest_update_id
 	}
 
+	/// Gets the hash and height of the latest block connected to this ChannelMonitor, ie the
+	/// block from which chain sync should resume after deserialization. After a block is
+	/// disconnected, this is its parent.
+	///
+	/// For monitors created at channel funding, this starts out as the ChannelManager's best
+	/// block at that time. For monitors read from a version which did not track the height, the
+	/// height is 0 until the next block is connected.
+	pub fn get_last_block(&self) -> (BlockHash, u32) {
+		(self.last_block_hash, self.last_block_height)
+	}
+
 	/// Gets the funding transaction outpoint of the channel this ChannelMonitor is monitoring for.
 	pub fn get_funding_txo(&self) -> &(OutPoint, Script) {
 		&self.funding_info
 	}
 
+	/// Gets the funding outpoints of any splices of the channel, whether pending or already seen
+	/// on chain. Spends of these must be monitored for in addition to the get_funding_txo
+	/// outpoint, using the same output script.
+	pub fn get_splice_funding_txos(&self) -> Vec<OutPoint> {
+		let mut res = Vec::new();
+		if let Some(outpoint) = self.spliced_funding_outpoint {
+			res.push(outpoint);
+		}
+		if let Some(ref splice) = self.pending_splice {
+			res.push(splice.funding_outpoint);
+		}
+		res
+	}
+
+	/// The funding outpoint spent by our commitment transactions, which differs from the
+	/// get_funding_txo outpoint once the channel has been spliced.
+	fn get_current_funding_outpoint(&self) -> OutPoint {
+		self.spliced_funding_outpoint.unwrap_or(self.funding_info.0)
+	}
+
+	/// Replaces our funding output and holder commitment transaction with those of the pending
+	/// splice. This is done once the splice transaction has ANTI_REORG_DELAY confirmations, or
+	/// earlier if we see a transaction spending its funding output, as until then it may be
+	/// reorganized out of the chain and our current funding output spent instead.
+	///
+	/// The splice commitment transaction replaces our current holder commitment transaction, as
+	/// every update to the latter is signed for the splice too.
+	fn promote_pending_splice<L: Deref>(&mut self, logger: &L) where L::Target: Logger {
+		if let Some(splice) = self.pending_splice.take() {
+			log_info!(logger, "Splice transaction {} for channel {} locked in, now watching its funding output", splice.funding_outpoint.txid, log_bytes!(self.channel_id));
+			let commitment_tx = splice.commitment_tx;
+			self.current_holder_commitment_tx = HolderSignedTx {
+				txid: commitment_tx.txid(),
+				revocation_key: commitment_tx.keys.revocation_key,
+				a_htlc_key: commitment_tx.keys.broadcaster_htlc_key,
+				b_htlc_key: commitment_tx.keys.countersignatory_htlc_key,
+				delayed_payment_key: commitment_tx.keys.broadcaster_delayed_payment_key,
+				per_commitment_point: commitment_tx.keys.per_commitment_point,
+				feerate_per_kw: commitment_tx.feerate_per_kw,
+				htlc_outputs: splice.htlc_outputs,
+			};
+			self.prev_holder_signed_commitment_tx = None;
+			self.keys.on_splice(splice.channel_value_satoshis);
+			self.channel_value_satoshis = splice.channel_value_satoshis;
+			self.onchain_tx_handler.provide_splice_holder_tx(commitment_tx, splice.channel_value_satoshis);
+			self.spliced_funding_outpoint = Some(splice.funding_outpoint);
+		}
+	}
+
 	/// Gets a list of txids, with their output scripts (in the order they appear in the
 	/// transaction), which we must learn about spends of via block_connected().
 	///
@@ -1487,6 +1824,96 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		self.current_holder_commitment_number
 	}
 
+	/// Builds and signs a transaction claiming every output of a revoked counterparty commitment
+	/// transaction which we can spend with the given per-commitment secret (ie their to_self
+	/// output and the given HTLC outputs), paying to our destination script at the given feerate.
+	/// This lets a justice transaction be handed to a watchtower before the revoked commitment
+	/// transaction is ever broadcast.
+	///
+	/// Returns None if there is nothing to claim, if the claimed value can't pay for the
+	/// transaction's fee, or if the HTLCs don't match the commitment transaction's outputs.
+	pub(super) fn sign_justice_tx(&self, commitment_tx: &Transaction, htlcs: &[HTLCOutputInCommitment], per_commitment_secret: &[u8; 32], feerate_per_kw: u64) -> Option<Transaction> {
+		let per_commitment_key = match SecretKey::from_slice(per_commitment_secret) {
+			Ok(key) => key,
+			Err(_) => return None,
+		};
+		let per_commitment_point = PublicKey::from_secret_key(&self.secp_ctx, &per_commitment_key);
+		let chan_keys = match TxCreationKeys::derive_new(&self.secp_ctx, &per_commitment_point, &self.counterparty_tx_cache.counterparty_delayed_payment_base_key, &self.counterparty_tx_cache.counterparty_htlc_base_key, &self.keys.pubkeys().revocation_basepoint, &self.keys.pubkeys().htlc_basepoint) {
+			Ok(keys) => keys,
+			Err(_) => return None,
+		};
+		let opt_anchors = self.onchain_tx_handler.opt_anchors();
+
+		// The outputs to claim, each with its index, value, HTLC (if any) and witness script.
+		let mut claims = Vec::new();
+		let revokeable_redeemscript = chan_utils::get_revokeable_redeemscript(&chan_keys.revocation_key, self.counterparty_tx_cache.on_counterparty_tx_csv, &chan_keys.broadcaster_delayed_payment_key);
+		let revokeable_p2wsh = revokeable_redeemscript.to_v0_p2wsh();
+		for (idx, outp) in commitment_tx.output.iter().enumerate() {
+			if outp.script_pubkey == revokeable_p2wsh {
+				claims.push((idx as u32, outp.value, None, revokeable_redeemscript.clone()));
+			}
+		}
+		for htlc in htlcs.iter() {
+			if let Some(transaction_output_index) = htlc.transaction_output_index {
+				let witness_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(htlc, opt_anchors, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key);
+				match commitment_tx.output.get(transaction_output_index as usize) {
+					Some(outp) if outp.script_pubkey == witness_script.to_v0_p2wsh() => {
+						claims.push((transaction_output_index, outp.value, Some(htlc.clone()), witness_script));
+					},
+					_ => return None,
+				}
+			}
+		}
+		if claims.is_empty() {
+			return None;
+		}
+
+		let commitment_txid = commitment_tx.txid();
+		let mut justice_tx = Transaction {
+			version: 2,
+			lock_time: 0,
+			input: claims.iter().map(|&(vout, _, _, _)| TxIn {
+				previous_output: BitcoinOutPoint { txid: commitment_txid, vout },
+				script_sig: Script::new(),
+				sequence: 0xfffffffd,
+				witness: Vec::new(),
+			}).collect(),
+			output: vec![TxOut { script_pubkey: self.destination_script.clone(), value: 0 }],
+		};
+		let input_descriptors: Vec<InputDescriptors> = claims.iter().map(|&(_, _, ref htlc, _)| match htlc {
+			&None => InputDescriptors::RevokedOutput,
+			&Some(ref htlc) if htlc.offered => InputDescriptors::RevokedOfferedHTLC,
+			&Some(_) => InputDescriptors::RevokedReceivedHTLC,
+		}).collect();
+		let predicted_weight = (justice_tx.get_weight() + OnchainTxHandler::<ChanSigner>::get_witnesses_weight(&input_descriptors, opt_anchors)) as u64;
+		let fee = feerate_per_kw * predicted_weight / 1000;
+		let value: u64 = claims.iter().map(|&(_, value, _, _)| value).sum();
+		if value <= fee {
+			return None;
+		}
+		justice_tx.output[0].value = value - fee;
+
+		let mut sigs = Vec::with_capacity(claims.len());
+		for (i, &(_, value, ref htlc, _)) in claims.iter().enumerate() {
+			match self.keys.sign_justice_transaction(&justice_tx, i, value, &per_commitment_key, htlc, &self.secp_ctx) {
+				Ok(sig) => sigs.push(sig),
+				Err(_) => return None,
+			}
+		}
+		for (i, (sig, &(_, _, ref htlc, ref witness_script))) in sigs.iter().zip(claims.iter()).enumerate() {
+			let witness = &mut justice_tx.input[i].witness;
+			witness.push(sig.serialize_der().to_vec());
+			witness[0].push(SigHashType::All as u8);
+			if htlc.is_some() {
+				witness.push(chan_keys.revocation_key.serialize().to_vec());
+			} else {
+				witness.push(vec!(1));
+			}
+			witness.push(witness_script.clone().into_bytes());
+		}
+		Some(justice_tx)
+	}
+
 	/// Attempts to claim a counterparty commitment transaction's outputs using the revocation key and
 	/// data in counterparty_claimable_outpoints. Will directly claim any HTLC outputs which expire at a
 	/// height > height + CLTV_SHARED_CLAIM_BUFFER. In any case, will install monitoring for
@@ -1656,12 +2083,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 						if revocation_points.0 == commitment_number + 1 { Some(point) } else { None }
 					} else { None };
 				if let Some(revocation_point) = revocation_point_option {
-					self.counterparty_payment_script = {
-						// Note that the Network here is ignored as we immediately drop the address for the
-						// script_pubkey version
-						let payment_hash160 = WPubkeyHash::hash(&self.keys.pubkeys().payment_point.serialize());
-						Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&payment_hash160[..]).into_script()
-					};
+					self.counterparty_payment_script = chan_utils::get_to_countersignatory_script_pubkey(&self.keys.pubkeys().payment_point, self.onchain_tx_handler.opt_anchors());
 
 					// Then, try to find htlc outputs
 					for (_, &(ref htlc, _)) in per_commitment_data.iter().enumerate() {
@@ -1881,15 +2303,141 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 	}
 
 	/// Called by SimpleManyChannelMonitor::block_connected, which implements
-	/// ChainListener::block_connected.
-	/// Eventually this should be pub and, roughly, implement ChainListener, however this requires
-	/// &mut self, as well as returns new spendable outputs and outpoints to watch for spending of
-	/// on-chain.
-	fn block_connected<B: Deref, F: Deref, L: Deref>(&mut self, txn_matched: &[&Transaction], height: u32, block_hash: &BlockHash, broadcaster: B, fee_estimator: F, logger: L)-> Vec<(Txid, Vec<TxOut>)>
+	/// ChainListener::block_connected, or directly to bring a deserialized ChannelMonitor up to
+	/// the chain tip before handing it to a ManyChannelMonitor.
+	///
+	/// Unlike ChainListener::block_connected, this requires &mut self, and returns new outputs to
+	/// watch for spending of on-chain. These are also returned by get_outputs_to_watch, so need not
+	/// be handled if the monitor is later added to a SimpleManyChannelMonitor.
+	pub fn block_connected<B: Deref, F: Deref, L: Deref>(&mut self, header: &BlockHeader, txn_matched: &[&Transaction], height: u32, broadcaster: B, fee_estimator: F, logger: L)-> Vec<(Txid, Vec<TxOut>)>
 		where B::Target: BroadcasterInterface,
 		      F::Target: FeeEstimator,
 					L::Target: Logger,
 	{
+		let block_hash = header.block_hash();
+		log_trace!(logger, "Block {} at height {} connected with {} txn matched", block_hash, height, txn_matched.len());
+		let (mut claimable_outpoints, mut watch_outputs) = self.check_transactions(txn_matched, height, &logger);
+		self.check_height(height, &mut claimable_outpoints, &mut watch_outputs, &logger);
+		self.process_matured_events(height, &logger);
+
+		self.onchain_tx_handler.block_connected(txn_matched, claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*logger);
+		self.process_claim_events();
+
+		self.last_block_hash = block_hash;
+		self.last_block_height = height;
+		self.register_outputs_to_watch(&watch_outputs);
+
+		watch_outputs
+	}
+
+	/// Called by SimpleManyChannelMonitor::transactions_confirmed, which implements
+	/// ConfirmationListener::transactions_confirmed, with transactions confirmed in the block with
+	/// the given header at the given height, each along with its index in the block.
+	///
+	/// As with block_connected, returns new outputs to watch for spending of on-chain. The
+	/// transactions are kept until they reach ANTI_REORG_DELAY confirmations, being returned by
+	/// get_relevant_txids in the meantime.
+	pub fn transactions_confirmed<B: Deref, F: Deref, L: Deref>(&mut self, header: &BlockHeader, txdata: &[(usize, &Transaction)], height: u32, broadcaster: B, fee_estimator: F, logger: L) -> Vec<(Txid, Vec<TxOut>)>
+		where B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		log_trace!(logger, "{} transactions confirmed in block {} at height {}", txdata.len(), header.block_hash(), height);
+		let txn_matched: Vec<&Transaction> = txdata.iter().map(|&(_, tx)| tx).collect();
+		let (claimable_outpoints, watch_outputs) = self.check_transactions(&txn_matched, height, &logger);
+		self.onchain_tx_handler.transactions_confirmed(&txn_matched, claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*logger);
+
+		if height + ANTI_REORG_DELAY - 1 > self.last_block_height {
+			for tx in txn_matched {
+				self.onchain_txn_awaiting_threshold_conf.retain(|&(_, ref confirmed_tx)| confirmed_tx.txid() != tx.txid());
+				self.onchain_txn_awaiting_threshold_conf.push((height, tx.clone()));
+			}
+		} else {
+			// We learned of the transactions late, after they already had enough confirmations
+			// for any events they resulted in to be acted upon.
+			self.process_matured_events(self.last_block_height, &logger);
+			self.onchain_tx_handler.process_matured_events(self.last_block_height);
+		}
+		self.process_claim_events();
+		self.register_outputs_to_watch(&watch_outputs);
+
+		watch_outputs
+	}
+
+	/// Called by SimpleManyChannelMonitor::transaction_unconfirmed, which implements
+	/// ConfirmationListener::transaction_unconfirmed, when a transaction previously passed to
+	/// transactions_confirmed is no longer confirmed.
+	///
+	/// Any pending events resulting from transactions confirmed at the same height are undone as
+	/// well, as when the block at that height is disconnected, but the other transactions are then
+	/// immediately re-applied as they remain confirmed.
+	pub fn transaction_unconfirmed<B: Deref, F: Deref, L: Deref>(&mut self, txid: &Txid, broadcaster: B, fee_estimator: F, logger: L)
+		where B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		let height = match self.onchain_txn_awaiting_threshold_conf.iter().find(|&&(_, ref tx)| tx.txid() == *txid) {
+			Some(&(height, _)) => height,
+			None => return,
+		};
+		log_trace!(logger, "Transaction {} at height {} unconfirmed", txid, height);
+		self.onchain_txn_awaiting_threshold_conf.retain(|&(_, ref tx)| tx.txid() != *txid);
+		self.onchain_events_waiting_threshold_conf.remove(&(height + ANTI_REORG_DELAY - 1));
+		self.onchain_tx_handler.block_disconnected(height, &*broadcaster, &*fee_estimator, &*logger);
+
+		let remaining_txn: Vec<Transaction> = self.onchain_txn_awaiting_threshold_conf.iter()
+			.filter(|&&(confirmed_height, _)| confirmed_height == height)
+			.map(|&(_, ref tx)| tx.clone()).collect();
+		let txn_matched: Vec<&Transaction> = remaining_txn.iter().collect();
+		let (claimable_outpoints, watch_outputs) = self.check_transactions(&txn_matched, height, &logger);
+		self.onchain_tx_handler.transactions_confirmed(&txn_matched, claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*logger);
+		self.process_claim_events();
+		self.register_outputs_to_watch(&watch_outputs);
+	}
+
+	/// Called by SimpleManyChannelMonitor::best_block_updated, which implements
+	/// ConfirmationListener::best_block_updated, with the new best block and its height.
+	///
+	/// Unlike block_disconnected, this does not undo the effects of any transactions if the new
+	/// best block is at a lower height, which must instead be done with transaction_unconfirmed.
+	/// As with block_connected, returns new outputs to watch for spending of on-chain.
+	pub fn best_block_updated<B: Deref, F: Deref, L: Deref>(&mut self, header: &BlockHeader, height: u32, broadcaster: B, fee_estimator: F, logger: L) -> Vec<(Txid, Vec<TxOut>)>
+		where B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		let block_hash = header.block_hash();
+		if block_hash == self.last_block_hash {
+			return Vec::new();
+		}
+		log_trace!(logger, "New best block {} at height {}", block_hash, height);
+		let mut claimable_outpoints = Vec::new();
+		let mut watch_outputs = Vec::new();
+		self.check_height(height, &mut claimable_outpoints, &mut watch_outputs, &logger);
+		self.process_matured_events(height, &logger);
+
+		self.onchain_tx_handler.transactions_confirmed(&[], claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*logger);
+		self.onchain_tx_handler.best_block_updated(height, &*broadcaster, &*fee_estimator, &*logger);
+		self.process_claim_events();
+
+		self.last_block_hash = block_hash;
+		self.last_block_height = height;
+		self.onchain_txn_awaiting_threshold_conf.retain(|&(confirmed_height, _)| confirmed_height + ANTI_REORG_DELAY - 1 > height);
+		self.register_outputs_to_watch(&watch_outputs);
+
+		watch_outputs
+	}
+
+	/// Gets the txids of transactions passed to transactions_confirmed which have yet to reach
+	/// ANTI_REORG_DELAY confirmations, and thus must be passed to transaction_unconfirmed if they
+	/// are reorganized out of the chain.
+	pub fn get_relevant_txids(&self) -> Vec<Txid> {
+		self.onchain_txn_awaiting_threshold_conf.iter().map(|&(_, ref tx)| tx.txid()).collect()
+	}
+
+	/// Checks the given transactions, confirmed at the given height, for any spends of the funding
+	/// output or outputs we're watching, returning any outputs to claim and new outputs to watch.
+	fn check_transactions<L: Deref>(&mut self, txn_matched: &[&Transaction], height: u32, logger: &L) -> (Vec<ClaimRequest>, Vec<(Txid, Vec<TxOut>)>) where L::Target: Logger {
 		for tx in txn_matched {
 			let mut output_val = 0;
 			for out in tx.output.iter() {
@@ -1899,24 +2447,47 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 			}
 		}
 
-		log_trace!(logger, "Block {} at height {} connected with {} txn matched", block_hash, height, txn_matched.len());
 		let mut watch_outputs = Vec::new();
 		let mut claimable_outpoints = Vec::new();
 		for tx in txn_matched {
+			let pending_splice_outpoint = self.pending_splice.as_ref().map(|splice| splice.funding_outpoint);
+			if let Some(splice_outpoint) = pending_splice_outpoint {
+				if splice_outpoint.txid == tx.txid() {
+					log_trace!(logger, "Splice transaction {} confirmed, waiting for confirmation (at height {})", splice_outpoint.txid, height + ANTI_REORG_DELAY - 1);
+					let event = OnchainEvent::SpliceConfirmed { funding_outpoint: splice_outpoint };
+					match self.onchain_events_waiting_threshold_conf.entry(height + ANTI_REORG_DELAY - 1) {
+						hash_map::Entry::Occupied(mut entry) => {
+							let e = entry.get_mut();
+							if !e.contains(&event) {
+								e.push(event);
+							}
+						}
+						hash_map::Entry::Vacant(entry) => {
+							entry.insert(vec![event]);
+						}
+					}
+				}
+			}
 			if tx.input.len() == 1 {
 				// Assuming our keys were not leaked (in which case we're screwed no matter what),
 				// commitment transactions and HTLC transactions will all only ever have one input,
 				// which is an easy way to filter out any potential non-matching txn for lazy
 				// filters.
 				let prevout = &tx.input[0].previous_output;
-				if prevout.txid == self.funding_info.0.txid && prevout.vout == self.funding_info.0.index as u32 {
+				// A spend of the pending splice's funding output means the splice transaction
+				// confirmed, so we have to move over to it before checking the spend.
+				if pending_splice_outpoint.map(|outpoint| outpoint.into_bitcoin_outpoint()) == Some(*prevout) {
+					self.promote_pending_splice(logger);
+				}
+				let funding_outpoint = self.get_current_funding_outpoint();
+				if prevout.txid == funding_outpoint.txid && prevout.vout == funding_outpoint.index as u32 {
 					if (tx.input[0].sequence >> 8*3) as u8 == 0x80 && (tx.lock_time >> 8*3) as u8 == 0x20 {
-						let (mut new_outpoints, new_outputs) = self.check_spend_counterparty_transaction(&tx, height, &logger);
+						let (mut new_outpoints, new_outputs) = self.check_spend_counterparty_transaction(&tx, height, logger);
 						if !new_outputs.1.is_empty() {
 							watch_outputs.push(new_outputs);
 						}
 						if new_outpoints.is_empty() {
-							let (mut new_outpoints, new_outputs) = self.check_spend_holder_transaction(&tx, height, &logger);
+							let (mut new_outpoints, new_outputs) = self.check_spend_holder_transaction(&tx, height, logger);
 							if !new_outputs.1.is_empty() {
 								watch_outputs.push(new_outputs);
 							}
@@ -1926,7 +2497,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 					}
 				} else {
 					if let Some(&(commitment_number, _)) = self.counterparty_commitment_txn_on_chain.get(&prevout.txid) {
-						let (mut new_outpoints, new_outputs_option) = self.check_spend_counterparty_htlc(&tx, commitment_number, height, &logger);
+						let (mut new_outpoints, new_outputs_option) = self.check_spend_counterparty_htlc(&tx, commitment_number, height, logger);
 						claimable_outpoints.append(&mut new_outpoints);
 						if let Some(new_outputs) = new_outputs_option {
 							watch_outputs.push(new_outputs);
@@ -1937,13 +2508,21 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 			// While all commitment/HTLC-Success/HTLC-Timeout transactions have one input, HTLCs
 			// can also be resolved in a few other ways which can have more than one output. Thus,
 			// we call is_resolving_htlc_output here outside of the tx.input.len() == 1 check.
-			self.is_resolving_htlc_output(&tx, height, &logger);
+			self.is_resolving_htlc_output(&tx, height, logger);
 
-			self.is_paying_spendable_output(&tx, height, &logger);
+			self.is_paying_spendable_output(&tx, height, logger);
 		}
-		let should_broadcast = self.would_broadcast_at_height(height, &logger);
-		if should_broadcast {
-			claimable_outpoints.push(ClaimRequest { absolute_timelock: height, aggregable: false, outpoint: BitcoinOutPoint { txid: self.funding_info.0.txid.clone(), vout: self.funding_info.0.index as u32 }, witness_data: InputMaterial::Funding { funding_redeemscript: self.funding_redeemscript.clone() }});
+		(claimable_outpoints, watch_outputs)
+	}
+
+	/// Checks whether we must broadcast our holder commitment transaction as of the given height,
+	/// adding any resulting outputs to claim and new outputs to watch.
+	fn check_height<L: Deref>(&mut self, height: u32, claimable_outpoints: &mut Vec<ClaimRequest>, watch_outputs: &mut Vec<(Txid, Vec<TxOut>)>, logger: &L) where L::Target: Logger {
+		let should_broadcast = self.would_broadcast_at_height(height, logger);
+		if should_broadcast || self.holder_tx_bump_pending {
+			self.holder_tx_bump_pending = false;
+			let funding_outpoint = self.get_current_funding_outpoint();
+			claimable_outpoints.push(ClaimRequest { absolute_timelock: height, aggregable: false, outpoint: funding_outpoint.into_bitcoin_outpoint(), witness_data: InputMaterial::Funding { funding_redeemscript: self.funding_redeemscript.clone() }});
 		}
 		if should_broadcast {
 			self.pending_monitor_events.push(MonitorEvent::CommitmentTxBroadcasted(self.funding_info.0));
@@ -1956,8 +2535,14 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 				claimable_outpoints.append(&mut new_outpoints);
 			}
 		}
-		if let Some(events) = self.onchain_events_waiting_threshold_conf.remove(&height) {
-			for ev in events {
+	}
+
+	/// Passes upstream any events which have reached ANTI_REORG_DELAY confirmations as of the given
+	/// height, including those from earlier heights which were confirmed late.
+	fn process_matured_events<L: Deref>(&mut self, height: u32, logger: &L) where L::Target: Logger {
+		let matured_heights: Vec<u32> = self.onchain_events_waiting_threshold_conf.keys().filter(|h| **h <= height).cloned().collect();
+		for matured_height in matured_heights {
+			for ev in self.onchain_events_waiting_threshold_conf.remove(&matured_height).unwrap() {
 				match ev {
 					OnchainEvent::HTLCUpdate { htlc_update } => {
 						log_trace!(logger, "HTLC {} failure update has got enough confirmations to be passed upstream", log_bytes!((htlc_update.1).0));
@@ -1965,6 +2550,8 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 							payment_hash: htlc_update.1,
 							payment_preimage: None,
 							source: htlc_update.0,
+							htlc_value_msat: None,
+							channel_id: self.channel_id,
 						}));
 					},
 					OnchainEvent::MaturingOutput { descriptor } => {
@@ -1972,36 +2559,62 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 						self.pending_events.push(Event::SpendableOutputs {
 							outputs: vec![descriptor]
 						});
-					}
+					},
+					OnchainEvent::SpliceConfirmed { funding_outpoint } => {
+						if self.pending_splice.as_ref().map(|splice| splice.funding_outpoint) == Some(funding_outpoint) {
+							self.promote_pending_splice(logger);
+						}
+					},
 				}
 			}
 		}
+	}
 
-		self.onchain_tx_handler.block_connected(txn_matched, claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*logger);
-
-		self.last_block_hash = block_hash.clone();
+	fn register_outputs_to_watch(&mut self, watch_outputs: &[(Txid, Vec<TxOut>)]) {
 		for &(ref txid, ref output_scripts) in watch_outputs.iter() {
 			self.outputs_to_watch.insert(txid.clone(), output_scripts.iter().map(|o| o.script_pubkey.clone()).collect());
 		}
-
-		watch_outputs
 	}
 
-	fn block_disconnected<B: Deref, F: Deref, L: Deref>(&mut self, height: u32, block_hash: &BlockHash, broadcaster: B, fee_estimator: F, logger: L)
+	/// Called by SimpleManyChannelMonitor::block_disconnected, which implements
+	/// ChainListener::block_disconnected, or directly to rewind a deserialized ChannelMonitor back
+	/// to a common ancestor with the best chain.
+	pub fn block_disconnected<B: Deref, F: Deref, L: Deref>(&mut self, header: &BlockHeader, height: u32, broadcaster: B, fee_estimator: F, logger: L)
 		where B::Target: BroadcasterInterface,
 		      F::Target: FeeEstimator,
 		      L::Target: Logger,
 	{
-		log_trace!(logger, "Block {} at height {} disconnected", block_hash, height);
+		log_trace!(logger, "Block {} at height {} disconnected", header.block_hash(), height);
 		if let Some(_) = self.onchain_events_waiting_threshold_conf.remove(&(height + ANTI_REORG_DELAY - 1)) {
 			//We may discard:
 			//- htlc update there as failure-trigger tx (revoked commitment tx, non-revoked commitment tx, HTLC-timeout tx) has been disconnected
 			//- maturing spendable output has transaction paying us has been disconnected
+			//- pending splice as the splice transaction has been disconnected
 		}
 
 		self.onchain_tx_handler.block_disconnected(height, broadcaster, fee_estimator, logger);
+		self.process_claim_events();
 
-		self.last_block_hash = block_hash.clone();
+		self.last_block_hash = header.prev_blockhash;
+		self.last_block_height = height - 1;
+	}
+
+	fn process_claim_events(&mut self) {
+		for claim_event in self.onchain_tx_handler.get_and_clear_pending_claim_events() {
+			match claim_event {
+				ClaimEvent::BumpCommitment { target_feerate_sat_per_1000_weight, commitment_tx, anchor_output_idx } => {
+					let commitment_tx_fee_satoshis = self.channel_value_satoshis - commitment_tx.output.iter().map(|output| output.value).sum::<u64>();
+					self.pending_events.push(Event::BumpCommitmentTransaction {
+						anchor_outpoint: OutPoint { txid: commitment_tx.txid(), index: anchor_output_idx as u16 },
+						anchor_witness_script: chan_utils::get_anchor_redeemscript(&self.keys.pubkeys().funding_pubkey),
+						commitment_tx,
+						commitment_tx_fee_satoshis,
+						target_feerate_sat_per_1000_weight,
+						key_derivation_params: self.keys.key_derivation_params(),
+					});
+				},
+			}
+		}
 	}
 
 	fn would_broadcast_at_height<L: Deref>(&self, height: u32, logger: &L) -> bool where L::Target: Logger {
@@ -2070,12 +2683,13 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 	/// Check if any transaction broadcasted is resolving HTLC output by a success or timeout on a holder
 	/// or counterparty commitment tx, if so send back the source, preimage if found and payment_hash of resolved HTLC
 	fn is_resolving_htlc_output<L: Deref>(&mut self, tx: &Transaction, height: u32, logger: &L) where L::Target: Logger {
+		let opt_anchors = self.onchain_tx_handler.opt_anchors();
 		'outer_loop: for input in &tx.input {
 			let mut payment_data = None;
-			let revocation_sig_claim = (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::OfferedHTLC) && input.witness[1].len() == 33)
-				|| (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::AcceptedHTLC) && input.witness[1].len() == 33);
-			let accepted_preimage_claim = input.witness.len() == 5 && HTLCType::scriptlen_to_htlctype(input.witness[4].len()) == Some(HTLCType::AcceptedHTLC);
-			let offered_preimage_claim = input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::OfferedHTLC);
+			let revocation_sig_claim = (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), opt_anchors) == Some(HTLCType::OfferedHTLC) && input.witness[1].len() == 33)
+				|| (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), opt_anchors) == Some(HTLCType::AcceptedHTLC) && input.witness[1].len() == 33);
+			let accepted_preimage_claim = input.witness.len() == 5 && HTLCType::scriptlen_to_htlctype(input.witness[4].len(), opt_anchors) == Some(HTLCType::AcceptedHTLC);
+			let offered_preimage_claim = input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), opt_anchors) == Some(HTLCType::OfferedHTLC);
 
 			macro_rules! log_claim {
 				($tx_info: expr, $holder_tx: expr, $htlc: expr, $source_avail: expr) => {
@@ -2106,7 +2720,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 							if pending_htlc.payment_hash == $htlc_output.payment_hash && pending_htlc.amount_msat == $htlc_output.amount_msat {
 								if let &Some(ref source) = pending_source {
 									log_claim!("revoked counterparty commitment tx", false, pending_htlc, true);
-									payment_data = Some(((**source).clone(), $htlc_output.payment_hash));
+									payment_data = Some(((**source).clone(), $htlc_output.payment_hash, $htlc_output.amount_msat));
 									break;
 								}
 							}
@@ -2126,7 +2740,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 								// transaction. This implies we either learned a preimage, the HTLC
 								// has timed out, or we screwed up. In any case, we should now
 								// resolve the source HTLC with the original sender.
-								payment_data = Some(((*source).clone(), htlc_output.payment_hash));
+								payment_data = Some(((*source).clone(), htlc_output.payment_hash, htlc_output.amount_msat));
 							} else if !$holder_tx {
 									check_htlc_valid_counterparty!(self.current_counterparty_commitment_txid, htlc_output);
 								if payment_data.is_none() {
@@ -2159,7 +2773,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 
 			// Check that scan_commitment, above, decided there is some source worth relaying an
 			// HTLC resolution backwards to and figure out whether we learned a preimage from it.
-			if let Some((source, payment_hash)) = payment_data {
+			if let Some((source, payment_hash, amount_msat)) = payment_data {
 				let mut payment_preimage = PaymentPreimage([0; 32]);
 				if accepted_preimage_claim {
 					if !self.pending_monitor_events.iter().any(
@@ -2168,7 +2782,9 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 						self.pending_monitor_events.push(MonitorEvent::HTLCEvent(HTLCUpdate {
 							source,
 							payment_preimage: Some(payment_preimage),
-							payment_hash
+							payment_hash,
+							htlc_value_msat: Some(amount_msat),
+							channel_id: self.channel_id,
 						}));
 					}
 				} else if offered_preimage_claim {
@@ -2180,7 +2796,9 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 						self.pending_monitor_events.push(MonitorEvent::HTLCEvent(HTLCUpdate {
 							source,
 							payment_preimage: Some(payment_preimage),
-							payment_hash
+							payment_hash,
+							htlc_value_msat: Some(amount_msat),
+							channel_id: self.channel_id,
 						}));
 					}
 				} else {
@@ -2285,7 +2903,7 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 			}
 		}
 
-		let _ver: u8 = Readable::read(reader)?;
+		let ver: u8 = Readable::read(reader)?;
 		let min_ver: u8 = Readable::read(reader)?;
 		if min_ver > SERIALIZATION_VERSION {
 			return Err(DecodeError::UnknownVersion);
@@ -2316,6 +2934,7 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 			index: Readable::read(reader)?,
 		};
 		let funding_info = (outpoint, Readable::read(reader)?);
+		let channel_id = if ver >= 2 { Readable::read(reader)? } else { funding_info.0.to_channel_id() };
 		let current_counterparty_commitment_txid = Readable::read(reader)?;
 		let prev_counterparty_commitment_txid = Readable::read(reader)?;
 
@@ -2455,7 +3074,14 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 		let mut pending_monitor_events = Vec::with_capacity(cmp::min(pending_monitor_events_len as usize, MAX_ALLOC_SIZE / (32 + 8*3)));
 		for _ in 0..pending_monitor_events_len {
 			let ev = match <u8 as Readable>::read(reader)? {
-				0 => MonitorEvent::HTLCEvent(Readable::read(reader)?),
+				0 if ver >= 2 => MonitorEvent::HTLCEvent(Readable::read(reader)?),
+				0 => MonitorEvent::HTLCEvent(HTLCUpdate {
+					payment_hash: Readable::read(reader)?,
+					payment_preimage: Readable::read(reader)?,
+					source: Readable::read(reader)?,
+					htlc_value_msat: None,
+					channel_id,
+				}),
 				1 => MonitorEvent::CommitmentTxBroadcasted(funding_info.0),
 				_ => return Err(DecodeError::InvalidValue)
 			};
@@ -2471,6 +3097,9 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 		}
 
 		let last_block_hash: BlockHash = Readable::read(reader)?;
+		// Monitors written before we tracked the height of the last block start out at height 0
+		// until the next block is connected.
+		let last_block_height: u32 = if ver >= 2 { Readable::read(reader)? } else { 0 };
 
 		let waiting_threshold_conf_len: u64 = Readable::read(reader)?;
 		let mut onchain_events_waiting_threshold_conf = HashMap::with_capacity(cmp::min(waiting_threshold_conf_len as usize, MAX_ALLOC_SIZE / 128));
@@ -2493,6 +3122,12 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 							descriptor
 						}
 					},
+					2 => {
+						let funding_outpoint = Readable::read(reader)?;
+						OnchainEvent::SpliceConfirmed {
+							funding_outpoint
+						}
+					},
 					_ => return Err(DecodeError::InvalidValue),
 				};
 				events.push(ev);
@@ -2513,10 +3148,25 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 				return Err(DecodeError::InvalidValue);
 			}
 		}
-		let onchain_tx_handler = Readable::read(reader)?;
+		let onchain_tx_handler = ReadableArgs::read(reader, ver)?;
 
 		let lockdown_from_offchain = Readable::read(reader)?;
 		let holder_tx_signed = Readable::read(reader)?;
+		let holder_tx_bump_pending = if ver >= 2 { Readable::read(reader)? } else { false };
+
+		let mut onchain_txn_awaiting_threshold_conf = Vec::new();
+		if ver >= 2 {
+			let onchain_txn_len: u64 = Readable::read(reader)?;
+			onchain_txn_awaiting_threshold_conf.reserve(cmp::min(onchain_txn_len as usize, MAX_ALLOC_SIZE / 128));
+			for _ in 0..onchain_txn_len {
+				let height: u32 = Readable::read(reader)?;
+				let tx: Transaction = Readable::read(reader)?;
+				onchain_txn_awaiting_threshold_conf.push((height, tx));
+			}
+		}
+		let (pending_splice, spliced_funding_outpoint) = if ver >= 2 {
+			(Readable::read(reader)?, Readable::read(reader)?)
+		} else { (None, None) };
 
 		Ok((last_block_hash.clone(), ChannelMonitor {
 			latest_update_id,
@@ -2529,6 +3179,7 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 
 			keys,
 			funding_info,
+			channel_id,
 			current_counterparty_commitment_txid,
 			prev_counterparty_commitment_txid,
 
@@ -2560,8 +3211,13 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for (BlockHash, ChannelMonitor
 
 			lockdown_from_offchain,
 			holder_tx_signed,
+			holder_tx_bump_pending,
+			onchain_txn_awaiting_threshold_conf,
+			pending_splice,
+			spliced_funding_outpoint,
 
 			last_block_hash,
+			last_block_height,
 			secp_ctx: Secp256k1::new(),
 		}))
 	}
@@ -2659,12 +3315,12 @@ mod tests {
 		// old state.
 		let mut monitor = ChannelMonitor::new(keys,
 			&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap()), 0, &Script::new(),
-			(OutPoint { txid: Txid::from_slice(&[43; 32]).unwrap(), index: 0 }, Script::new()),
+			(OutPoint { txid: Txid::from_slice(&[43; 32]).unwrap(), index: 0 }, Script::new()), [43; 32],
 			&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[44; 32]).unwrap()),
 			&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[45; 32]).unwrap()),
-			10, Script::new(), 46, 0, HolderCommitmentTransaction::dummy());
+			10, Script::new(), 46, 0, HolderCommitmentTransaction::dummy(), (Default::default(), 0));
 
-		monitor.provide_latest_holder_commitment_tx_info(HolderCommitmentTransaction::dummy(), preimages_to_holder_htlcs!(preimages[0..10])).unwrap();
+		monitor.provide_latest_holder_commitment_tx_info(HolderCommitmentTransaction::dummy(), preimages_to_holder_htlcs!(preimages[0..10]), &logger).unwrap();
 		monitor.provide_latest_counterparty_commitment_tx_info(&dummy_tx, preimages_slice_to_htlc_outputs!(preimages[5..15]), 281474976710655, dummy_key, &logger);
 		monitor.provide_latest_counterparty_commitment_tx_info(&dummy_tx, preimages_slice_to_htlc_outputs!(preimages[15..20]), 281474976710654, dummy_key, &logger);
 		monitor.provide_latest_counterparty_commitment_tx_info(&dummy_tx, preimages_slice_to_htlc_outputs!(preimages[17..20]), 281474976710653, dummy_key, &logger);
@@ -2690,7 +3346,7 @@ mod tests {
 
 		// Now update holder commitment tx info, pruning only element 18 as we still care about the
 		// previous commitment tx's preimages too
-		monitor.provide_latest_holder_commitment_tx_info(HolderCommitmentTransaction::dummy(), preimages_to_holder_htlcs!(preimages[0..5])).unwrap();
+		monitor.provide_latest_holder_commitment_tx_info(HolderCommitmentTransaction::dummy(), preimages_to_holder_htlcs!(preimages[0..5]), &logger).unwrap();
 		secret[0..32].clone_from_slice(&hex::decode("2273e227a5b7449b6e70f1fb4652864038b1cbf9cd7c043a7d6456b7fc275ad8").unwrap());
 		monitor.provide_secret(281474976710653, secret.clone()).unwrap();
 		assert_eq!(monitor.payment_preimages.len(), 12);
@@ -2698,7 +3354,7 @@ mod tests {
 		test_preimages_exist!(&preimages[18..20], monitor);
 
 		// But if we do it again, we'll prune 5-10
-		monitor.provide_latest_holder_commitment_tx_info(HolderCommitmentTransaction::dummy(), preimages_to_holder_htlcs!(preimages[0..3])).unwrap();
+		monitor.provide_latest_holder_commitment_tx_info(HolderCommitmentTransaction::dummy(), preimages_to_holder_htlcs!(preimages[0..3]), &logger).unwrap();
 		secret[0..32].clone_from_slice(&hex::decode("27cddaa5624534cb6cb9d7da077cf2b22ab21e9b506fd4998a51d54502e99116").unwrap());
 		monitor.provide_secret(281474976710652, secret.clone()).unwrap();
 		assert_eq!(monitor.payment_preimages.len(), 5);
@@ -2716,7 +3372,7 @@ mod tests {
 		let mut sum_actual_sigs = 0;
 
 		macro_rules! sign_input {
-			($sighash_parts: expr, $idx: expr, $amount: expr, $input_type: expr, $sum_actual_sigs: expr) => {
+			($sighash_parts: expr, $idx: expr, $amount: expr, $input_type: expr, $sum_actual_sigs: expr, $opt_anchors: expr) => {
 				let htlc = HTLCOutputInCommitment {
 					offered: if *$input_type == InputDescriptors::RevokedOfferedHTLC || *$input_type == InputDescriptors::OfferedHTLC { true } else { false },
 					amount_msat: 0,
@@ -2724,7 +3380,7 @@ mod tests {
 					payment_hash: PaymentHash([1; 32]),
 					transaction_output_index: Some($idx as u32),
 				};
-				let redeem_script = if *$input_type == InputDescriptors::RevokedOutput { chan_utils::get_revokeable_redeemscript(&pubkey, 256, &pubkey) } else { chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &pubkey, &pubkey, &pubkey) };
+				let redeem_script = if *$input_type == InputDescriptors::RevokedOutput { chan_utils::get_revokeable_redeemscript(&pubkey, 256, &pubkey) } else { chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, $opt_anchors, &pubkey, &pubkey, &pubkey) };
 				let sighash = hash_to_message!(&$sighash_parts.signature_hash($idx, &redeem_script, $amount, SigHashType::All)[..]);
 				let sig = secp_ctx.sign(&sighash, &privkey);
 				$sighash_parts.access_witness($idx).push(sig.serialize_der().to_vec());
@@ -2771,10 +3427,10 @@ mod tests {
 		{
 			let mut sighash_parts = bip143::SigHashCache::new(&mut claim_tx);
 			for (idx, inp) in inputs_des.iter().enumerate() {
-				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs);
+				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs, false);
 			}
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
 
 		// Claim tx with 1 offered HTLCs, 3 received HTLCs
 		claim_tx.input.clear();
@@ -2795,10 +3451,10 @@ mod tests {
 		{
 			let mut sighash_parts = bip143::SigHashCache::new(&mut claim_tx);
 			for (idx, inp) in inputs_des.iter().enumerate() {
-				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs);
+				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs, false);
 			}
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
 
 		// Justice tx with 1 revoked HTLC-Success tx output
 		claim_tx.input.clear();
@@ -2817,10 +3473,34 @@ mod tests {
 		{
 			let mut sighash_parts = bip143::SigHashCache::new(&mut claim_tx);
 			for (idx, inp) in inputs_des.iter().enumerate() {
-				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs);
+				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs, false);
+			}
+		}
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false), claim_tx.get_weight() + /* max_length_isg */ (73 * inputs_des.len() - sum_actual_sigs));
+
+		// Claim tx with 1 offered HTLC, 1 received HTLC and 1 revoked to_holder output of an anchor channel
+		claim_tx.input.clear();
+		sum_actual_sigs = 0;
+		for i in 0..3 {
+			claim_tx.input.push(TxIn {
+				previous_output: BitcoinOutPoint {
+					txid,
+					vout: i,
+				},
+				script_sig: Script::new(),
+				sequence: 1,
+				witness: Vec::new(),
+			});
+		}
+		let base_weight = claim_tx.get_weight();
+		let inputs_des = vec![InputDescriptors::OfferedHTLC, InputDescriptors::ReceivedHTLC, InputDescriptors::RevokedOutput];
+		{
+			let mut sighash_parts = bip143::SigHashCache::new(&mut claim_tx);
+			for (idx, inp) in inputs_des.iter().enumerate() {
+				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs, true);
 			}
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]), claim_tx.get_weight() + /* max_length_isg */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], true), claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
 	}
 
 	// Further testing is done in the ChannelManager integration tests.
@@ -101,14 +101,34 @@ mod sealed {
 			StaticRemoteKey,
 			// Byte 2
 			,
+			// Byte 3
+			,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			,
 		],
 		optional_features: [
 			// Byte 0
-			DataLossProtect | InitialRoutingSync | UpfrontShutdownScript,
+			DataLossProtect | InitialRoutingSync | UpfrontShutdownScript | GossipQueries,
 			// Byte 1
 			VariableLengthOnion | PaymentSecret,
 			// Byte 2
-			BasicMPP,
+			BasicMPP | AnchorOutputs,
+			// Byte 3
+			ShutdownAnySegwit | DualFund,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			Splicing,
 		],
 	});
 	define_context!(NodeContext {
@@ -119,20 +139,51 @@ mod sealed {
 			StaticRemoteKey,
 			// Byte 2
 			,
+			// Byte 3
+			,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			,
 		],
 		optional_features: [
 			// Byte 0
-			DataLossProtect | UpfrontShutdownScript,
+			DataLossProtect | UpfrontShutdownScript | GossipQueries,
 			// Byte 1
 			VariableLengthOnion | PaymentSecret,
 			// Byte 2
-			BasicMPP,
+			BasicMPP | AnchorOutputs,
+			// Byte 3
+			ShutdownAnySegwit | DualFund,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			Splicing,
 		],
 	});
 	define_context!(ChannelContext {
 		required_features: [],
 		optional_features: [],
 	});
+	define_context!(InvoiceContext {
+		required_features: [,,,],
+		optional_features: [
+			// Byte 0
+			,
+			// Byte 1
+			VariableLengthOnion | PaymentSecret,
+			// Byte 2
+			BasicMPP,
+		],
+	});
 
 	/// Defines a feature with the given bits for the specified [`Context`]s. The generated trait is
 	/// useful for manipulating feature flags.
@@ -243,14 +294,24 @@ mod sealed {
 		"Feature flags for `initial_routing_sync`.");
 	define_feature!(5, UpfrontShutdownScript, [InitContext, NodeContext],
 		"Feature flags for `option_upfront_shutdown_script`.");
-	define_feature!(9, VariableLengthOnion, [InitContext, NodeContext],
+	define_feature!(7, GossipQueries, [InitContext, NodeContext],
+		"Feature flags for `gossip_queries`.");
+	define_feature!(9, VariableLengthOnion, [InitContext, NodeContext, InvoiceContext],
 		"Feature flags for `var_onion_optin`.");
 	define_feature!(13, StaticRemoteKey, [InitContext, NodeContext],
 		"Feature flags for `option_static_remotekey`.");
-	define_feature!(15, PaymentSecret, [InitContext, NodeContext],
+	define_feature!(15, PaymentSecret, [InitContext, NodeContext, InvoiceContext],
 		"Feature flags for `payment_secret`.");
-	define_feature!(17, BasicMPP, [InitContext, NodeContext],
+	define_feature!(17, BasicMPP, [InitContext, NodeContext, InvoiceContext],
 		"Feature flags for `basic_mpp`.");
+	define_feature!(21, AnchorOutputs, [InitContext, NodeContext],
+		"Feature flags for `option_anchor_outputs`.");
+	define_feature!(27, ShutdownAnySegwit, [InitContext, NodeContext],
+		"Feature flags for `option_shutdown_anysegwit`.");
+	define_feature!(29, DualFund, [InitContext, NodeContext],
+		"Feature flags for `option_dual_fund`.");
+	define_feature!(63, Splicing, [InitContext, NodeContext],
+		"Feature flags for `option_splice`.");
 
 	#[cfg(test)]
 	define_context!(TestingContext {
@@ -312,6 +373,8 @@ pub type InitFeatures = Features<sealed::InitContext>;
 pub type NodeFeatures = Features<sealed::NodeContext>;
 /// Features used within a `channel_announcement` message.
 pub type ChannelFeatures = Features<sealed::ChannelContext>;
+/// Features used within an invoice.
+pub type InvoiceFeatures = Features<sealed::InvoiceContext>;
 
 impl InitFeatures {
 	/// Writes all features present up to, and including, 13.
@@ -348,6 +411,14 @@ impl InitFeatures {
 	}
 }
 
+impl InvoiceFeatures {
+	/// Converts `InvoiceFeatures` to `Features<C>`. Only known `InvoiceFeatures` relevant to
+	/// context `C` are included in the result.
+	pub(crate) fn to_context<C: sealed::Context>(&self) -> Features<C> {
+		self.to_context_internal()
+	}
+}
+
 impl<T: sealed::Context> Features<T> {
 	/// Create a blank Features with no features set
 	pub fn empty() -> Features<T> {
@@ -382,7 +453,6 @@ impl<T: sealed::Context> Features<T> {
 		Features::<C> { flags, mark: PhantomData, }
 	}
 
-	#[cfg(test)]
 	/// Create a Features given a set of flags, in LE.
 	pub fn from_le_bytes(flags: Vec<u8>) -> Features<T> {
 		Features {
@@ -391,7 +461,6 @@ impl<T: sealed::Context> Features<T> {
 		}
 	}
 
-	#[cfg(test)]
 	/// Gets the underlying flags set, in LE.
 	pub fn le_flags(&self) -> &Vec<u8> {
 		&self.flags
@@ -473,6 +542,16 @@ impl<T: sealed::UpfrontShutdownScript> Features<T> {
 	}
 }
 
+impl<T: sealed::GossipQueries> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_gossip_queries(&self) -> bool {
+		<T as sealed::GossipQueries>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_gossip_queries(&self) -> bool {
+		<T as sealed::GossipQueries>::supports_feature(&self.flags)
+	}
+}
+
 impl<T: sealed::VariableLengthOnion> Features<T> {
 	#[cfg(test)]
 	pub(crate) fn requires_variable_length_onion(&self) -> bool {
@@ -521,13 +600,70 @@ impl<T: sealed::BasicMPP> Features<T> {
 	pub(crate) fn requires_basic_mpp(&self) -> bool {
 		<T as sealed::BasicMPP>::requires_feature(&self.flags)
 	}
-	// We currently never test for this since we don't actually *generate* multipath routes.
-	#[allow(dead_code)]
 	pub(crate) fn supports_basic_mpp(&self) -> bool {
 		<T as sealed::BasicMPP>::supports_feature(&self.flags)
 	}
 }
 
+impl<T: sealed::AnchorOutputs> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_anchor_outputs(&self) -> bool {
+		<T as sealed::AnchorOutputs>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_anchor_outputs(&self) -> bool {
+		<T as sealed::AnchorOutputs>::supports_feature(&self.flags)
+	}
+	pub(crate) fn clear_anchor_outputs(mut self) -> Self {
+		<T as sealed::AnchorOutputs>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
+impl<T: sealed::ShutdownAnySegwit> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_shutdown_anysegwit(&self) -> bool {
+		<T as sealed::ShutdownAnySegwit>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_shutdown_anysegwit(&self) -> bool {
+		<T as sealed::ShutdownAnySegwit>::supports_feature(&self.flags)
+	}
+	#[cfg(test)]
+	pub(crate) fn clear_shutdown_anysegwit(mut self) -> Self {
+		<T as sealed::ShutdownAnySegwit>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
+impl<T: sealed::DualFund> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_dual_fund(&self) -> bool {
+		<T as sealed::DualFund>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_dual_fund(&self) -> bool {
+		<T as sealed::DualFund>::supports_feature(&self.flags)
+	}
+	#[cfg(test)]
+	pub(crate) fn clear_dual_fund(mut self) -> Self {
+		<T as sealed::DualFund>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
+impl<T: sealed::Splicing> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_splicing(&self) -> bool {
+		<T as sealed::Splicing>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_splicing(&self) -> bool {
+		<T as sealed::Splicing>::supports_feature(&self.flags)
+	}
+	#[cfg(test)]
+	pub(crate) fn clear_splicing(mut self) -> Self {
+		<T as sealed::Splicing>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
 impl<T: sealed::Context> Writeable for Features<T> {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		w.size_hint(self.flags.len() + 2);
@@ -552,7 +688,7 @@ impl<T: sealed::Context> Readable for Features<T> {
 
 #[cfg(test)]
 mod tests {
-	use super::{ChannelFeatures, InitFeatures, NodeFeatures};
+	use super::{ChannelFeatures, InitFeatures, InvoiceFeatures, NodeFeatures};
 
 	#[test]
 	fn sanity_test_known_features() {
@@ -562,6 +698,8 @@ mod tests {
 		assert!(!InitFeatures::known().supports_unknown_bits());
 		assert!(!NodeFeatures::known().requires_unknown_bits());
 		assert!(!NodeFeatures::known().supports_unknown_bits());
+		assert!(!InvoiceFeatures::known().requires_unknown_bits());
+		assert!(!InvoiceFeatures::known().supports_unknown_bits());
 
 		assert!(InitFeatures::known().supports_upfront_shutdown_script());
 		assert!(NodeFeatures::known().supports_upfront_shutdown_script());
@@ -573,10 +711,17 @@ mod tests {
 		assert!(!InitFeatures::known().requires_data_loss_protect());
 		assert!(!NodeFeatures::known().requires_data_loss_protect());
 
+		assert!(InitFeatures::known().supports_gossip_queries());
+		assert!(NodeFeatures::known().supports_gossip_queries());
+		assert!(!InitFeatures::known().requires_gossip_queries());
+		assert!(!NodeFeatures::known().requires_gossip_queries());
+
 		assert!(InitFeatures::known().supports_variable_length_onion());
 		assert!(NodeFeatures::known().supports_variable_length_onion());
 		assert!(!InitFeatures::known().requires_variable_length_onion());
 		assert!(!NodeFeatures::known().requires_variable_length_onion());
+		assert!(InvoiceFeatures::known().supports_variable_length_onion());
+		assert!(!InvoiceFeatures::known().requires_variable_length_onion());
 
 		assert!(InitFeatures::known().supports_static_remote_key());
 		assert!(NodeFeatures::known().supports_static_remote_key());
@@ -587,11 +732,39 @@ mod tests {
 		assert!(NodeFeatures::known().supports_payment_secret());
 		assert!(!InitFeatures::known().requires_payment_secret());
 		assert!(!NodeFeatures::known().requires_payment_secret());
+		assert!(InvoiceFeatures::known().supports_payment_secret());
+		assert!(!InvoiceFeatures::known().requires_payment_secret());
 
 		assert!(InitFeatures::known().supports_basic_mpp());
 		assert!(NodeFeatures::known().supports_basic_mpp());
 		assert!(!InitFeatures::known().requires_basic_mpp());
 		assert!(!NodeFeatures::known().requires_basic_mpp());
+		assert!(InvoiceFeatures::known().supports_basic_mpp());
+		assert!(!InvoiceFeatures::known().requires_basic_mpp());
+
+		assert!(InitFeatures::known().supports_anchor_outputs());
+		assert!(NodeFeatures::known().supports_anchor_outputs());
+		assert!(!InitFeatures::known().requires_anchor_outputs());
+		assert!(!NodeFeatures::known().requires_anchor_outputs());
+		assert!(!InitFeatures::known().clear_anchor_outputs().supports_anchor_outputs());
+
+		assert!(InitFeatures::known().supports_shutdown_anysegwit());
+		assert!(NodeFeatures::known().supports_shutdown_anysegwit());
+		assert!(!InitFeatures::known().requires_shutdown_anysegwit());
+		assert!(!NodeFeatures::known().requires_shutdown_anysegwit());
+		assert!(!InitFeatures::known().clear_shutdown_anysegwit().supports_shutdown_anysegwit());
+
+		assert!(InitFeatures::known().supports_dual_fund());
+		assert!(NodeFeatures::known().supports_dual_fund());
+		assert!(!InitFeatures::known().requires_dual_fund());
+		assert!(!NodeFeatures::known().requires_dual_fund());
+		assert!(!InitFeatures::known().clear_dual_fund().supports_dual_fund());
+
+		assert!(InitFeatures::known().supports_splicing());
+		assert!(NodeFeatures::known().supports_splicing());
+		assert!(!InitFeatures::known().requires_splicing());
+		assert!(!NodeFeatures::known().requires_splicing());
+		assert!(!InitFeatures::known().clear_splicing().supports_splicing());
 
 		let mut init_features = InitFeatures::known();
 		assert!(init_features.initial_routing_sync());
@@ -627,13 +800,20 @@ mod tests {
 		let node_features: NodeFeatures = init_features.to_context();
 		{
 			// Check that the flags are as expected:
-			// - option_data_loss_protect
+			// - option_data_loss_protect | gossip_queries
 			// - var_onion_optin | static_remote_key (req) | payment_secret
-			// - basic_mpp
-			assert_eq!(node_features.flags.len(), 3);
-			assert_eq!(node_features.flags[0], 0b00000010);
+			// - basic_mpp | option_anchor_outputs
+			// - option_shutdown_anysegwit | option_dual_fund
+			// - option_splice
+			assert_eq!(node_features.flags.len(), 8);
+			assert_eq!(node_features.flags[0], 0b10000010);
 			assert_eq!(node_features.flags[1], 0b10010010);
-			assert_eq!(node_features.flags[2], 0b00000010);
+			assert_eq!(node_features.flags[2], 0b00100010);
+			assert_eq!(node_features.flags[3], 0b00101000);
+			assert_eq!(node_features.flags[4], 0b00000000);
+			assert_eq!(node_features.flags[5], 0b00000000);
+			assert_eq!(node_features.flags[6], 0b00000000);
+			assert_eq!(node_features.flags[7], 0b10000000);
 		}
 
 		// Check that cleared flags are kept blank when converting back:
@@ -12,24 +12,29 @@
 //! claim outputs on-chain.
 
 use chain::transaction::OutPoint;
-use chain::keysinterface::{ChannelKeys, KeysInterface, SpendableOutputDescriptor};
+use chain::keysinterface::{ChannelKeys, KeysInterface, OutputSpender, SpendableOutputDescriptor};
 use chain::chaininterface;
-use chain::chaininterface::{ChainListener, ChainWatchInterfaceUtil, BlockNotifier};
-use ln::channel::{COMMITMENT_TX_BASE_WEIGHT, COMMITMENT_TX_WEIGHT_PER_HTLC};
-use ln::channelmanager::{ChannelManager, ChannelManagerReadArgs, RAACommitmentOrder, PaymentPreimage, PaymentHash, PaymentSecret, PaymentSendFailure, BREAKDOWN_TIMEOUT};
+use chain::chaininterface::{ChainListener, ChainWatchInterfaceUtil, BlockNotifier, ConfirmationListener};
+use ln::channel::{COMMITMENT_TX_BASE_WEIGHT, COMMITMENT_TX_BASE_ANCHOR_WEIGHT, COMMITMENT_TX_WEIGHT_PER_HTLC, FUNDING_CONF_DEADLINE_BLOCKS};
+use ln::channelmanager::{ChannelManager, ChannelManagerReadArgs, RAACommitmentOrder, PaymentPreimage, PaymentHash, PaymentSecret, PaymentSendFailure, SpliceContribution, FundingContribution, BREAKDOWN_TIMEOUT};
 use ln::channelmonitor::{ChannelMonitor, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ManyChannelMonitor, ANTI_REORG_DELAY};
 use ln::channelmonitor;
+use ln::payment_retry::{PaymentError, PaymentRetrier, RetryConfig};
+use ln::watchtower::{WatchtowerClient, WatchtowerServer};
 use ln::channel::{Channel, ChannelError};
 use ln::{chan_utils, onion_utils};
 use routing::router::{Route, RouteHop, get_route};
-use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
+use routing::scorer::{Score, Scorer, ScoringParameters};
+use ln::features::{ChannelFeatures, InitFeatures, InvoiceFeatures, NodeFeatures};
 use ln::msgs;
 use ln::msgs::{ChannelMessageHandler,RoutingMessageHandler,HTLCFailChannelUpdate, ErrorAction};
 use util::enforcing_trait_impls::EnforcingChannelKeys;
 use util::{byte_utils, test_utils};
-use util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
+use util::events::{ClosureReason, Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
 use util::errors::APIError;
 use util::ser::{Writeable, ReadableArgs, Readable};
+use util::sweep::OutputSweeper;
+use util::forwarding_stats::{ChannelForwardingStats, ForwardingStats};
 use util::config::UserConfig;
 
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
@@ -53,10 +58,12 @@ use bitcoin::secp256k1::key::{PublicKey,SecretKey};
 
 use regex;
 
+use std::cell::RefCell;
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::default::Default;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 use std::mem;
 
 use ln::functional_test_utils::*;
@@ -170,7 +177,7 @@ fn test_async_inbound_update_fee() {
 	// ...but before it's delivered, nodes[1] starts to send a payment back to nodes[0]...
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger).unwrap(), our_payment_hash, &None).unwrap();
+	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap(), our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[1], 1);
 
 	let payment_event = {
@@ -268,7 +275,7 @@ fn test_update_fee_unordered_raa() {
 	// ...but before it's delivered, nodes[1] starts to send a payment back to nodes[0]...
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger).unwrap(), our_payment_hash, &None).unwrap();
+	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap(), our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[1], 1);
 
 	let payment_event = {
@@ -617,7 +624,58 @@ fn test_update_fee_that_funder_cannot_afford() {
 	nodes[1].node.handle_commitment_signed(&nodes[0].node.get_our_node_id(), &update2_msg.commitment_signed);
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Funding remote cannot afford proposed new fee".to_string(), 1);
 	check_added_monitors!(nodes[1], 1);
-	check_closed_broadcast!(nodes[1], true);
+	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data });
+}
+
+#[test]
+fn test_update_fee_that_funder_cannot_afford_anchors() {
+	// As test_update_fee_that_funder_cannot_afford, but the funder also has to pay for both anchor
+	// outputs out of its balance.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut anchors_config = UserConfig::default();
+	anchors_config.channel_options.announced_channel = true;
+	anchors_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	anchors_config.own_channel_config.negotiate_anchor_outputs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(anchors_config.clone()), Some(anchors_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	// 700 sat pushed to the fundee, 1000 sat channel reserve, 660 sat of anchors and a fee of
+	// 292 sat at a feerate of 260.
+	let channel_value = 2652;
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, channel_value, 700000, InitFeatures::known(), InitFeatures::known());
+	let channel_id = chan.2;
+
+	let feerate = 260;
+	nodes[0].node.update_fee(channel_id, feerate).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let update_msg = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+
+	nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), &update_msg.update_fee.unwrap());
+
+	commitment_signed_dance!(nodes[1], nodes[0], update_msg.commitment_signed, false);
+
+	{
+		let commitment_tx = get_local_commitment_txn!(nodes[1], channel_id)[0].clone();
+		assert_eq!(commitment_tx.output.iter().filter(|output| output.value == chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI).count(), 2);
+		let actual_fee = channel_value - commitment_tx.output.iter().fold(0, |acc, output| acc + output.value);
+		assert_eq!(actual_fee, feerate as u64 * COMMITMENT_TX_BASE_ANCHOR_WEIGHT / 1000);
+	}
+
+	nodes[0].node.update_fee(channel_id, feerate+2).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	let update2_msg = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+
+	nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), &update2_msg.update_fee.unwrap());
+
+	// The fee alone would still fit in the funder's balance above its reserve, but the anchors
+	// don't.
+	nodes[1].node.handle_commitment_signed(&nodes[0].node.get_our_node_id(), &update2_msg.commitment_signed);
+	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Funding remote cannot afford proposed new fee".to_string(), 1);
+	check_added_monitors!(nodes[1], 1);
+	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data });
 }
 
 #[test]
@@ -652,7 +710,7 @@ fn test_update_fee_with_fundee_update_add_htlc() {
 
 	let (our_payment_preimage, our_payment_hash) = get_payment_preimage_hash!(nodes[1]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &Vec::new(), 800000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 
 	// nothing happens since node[1] is in AwaitingRemoteRevoke
 	nodes[1].node.send_payment(&route, our_payment_hash, &None).unwrap();
@@ -845,6 +903,8 @@ fn pre_funding_lock_shutdown_test() {
 	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	assert!(nodes[1].node.list_channels().is_empty());
@@ -876,8 +936,8 @@ fn updates_shutdown_wait() {
 
 	let net_graph_msg_handler0 = &nodes[0].net_graph_msg_handler;
 	let net_graph_msg_handler1 = &nodes[1].net_graph_msg_handler;
-	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler0.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	let route_2 = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler1.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler0.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	let route_2 = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler1.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	unwrap_send_err!(nodes[0].node.send_payment(&route_1, payment_hash, &None), true, APIError::ChannelUnavailable {..}, {});
 	unwrap_send_err!(nodes[1].node.send_payment(&route_2, payment_hash, &None), true, APIError::ChannelUnavailable {..}, {});
 
@@ -890,6 +950,7 @@ fn updates_shutdown_wait() {
 	assert!(updates.update_fee.is_none());
 	assert_eq!(updates.update_fulfill_htlcs.len(), 1);
 	nodes[1].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	expect_payment_forwarded!(nodes[1], Some(239), false);
 	check_added_monitors!(nodes[1], 1);
 	let updates_2 = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
 	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
@@ -917,6 +978,8 @@ fn updates_shutdown_wait() {
 	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 
@@ -940,7 +1003,7 @@ fn htlc_fail_async_shutdown() {
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -995,6 +1058,8 @@ fn htlc_fail_async_shutdown() {
 	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 
@@ -1063,6 +1128,7 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 	assert!(updates.update_fee.is_none());
 	assert_eq!(updates.update_fulfill_htlcs.len(), 1);
 	nodes[1].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	expect_payment_forwarded!(nodes[1], Some(239), false);
 	check_added_monitors!(nodes[1], 1);
 	let updates_2 = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
 	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
@@ -1121,6 +1187,8 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 		nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 		let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 		assert!(node_0_none.is_none());
+		check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+		check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 	} else {
 		// If one node, however, received + responded with an identical closing_signed we end
 		// up erroring and node[0] will try to broadcast its own latest commitment transaction.
@@ -1139,6 +1207,9 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 				&ErrorAction::SendErrorMessage { ref msg } => {
 					nodes[0].node.handle_error(&nodes[1].node.get_our_node_id(), &msg);
 					assert_eq!(msg.channel_id, chan_1.2);
+					check_closed_event!(nodes[0], 1, ClosureReason::CounterpartyForceClosed { peer_msg: msg.data.clone() });
+					// nodes[1] had already broadcast the closing transaction, so forgot the channel
+					check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 				},
 				_ => panic!("Unexpected event!"),
 			}
@@ -1315,7 +1386,7 @@ fn holding_cell_htlc_counting() {
 	for _ in 0..::ln::channel::OUR_MAX_HTLCS {
 		let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[1].node.send_payment(&route, payment_hash, &None).unwrap();
 		payments.push((payment_preimage, payment_hash));
 	}
@@ -1332,7 +1403,7 @@ fn holding_cell_htlc_counting() {
 	let (_, payment_hash_1) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		unwrap_send_err!(nodes[1].node.send_payment(&route, payment_hash_1, &None), true, APIError::ChannelUnavailable { ref err },
 			assert!(regex::Regex::new(r"Cannot push more than their max accepted HTLCs \(\d+\)").unwrap().is_match(err)));
 		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
@@ -1343,7 +1414,7 @@ fn holding_cell_htlc_counting() {
 	let (_, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, payment_hash_2, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 	}
@@ -1476,7 +1547,7 @@ fn test_duplicate_htlc_different_direction_onchain() {
 	let (payment_preimage, payment_hash) = route_payment(&nodes[0], &vec!(&nodes[1])[..], 900_000);
 
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800_000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &Vec::new(), 800_000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &vec!(&nodes[0])[..], 800_000, payment_hash);
 
 	// Provide preimage to node 0 by claiming payment
@@ -1500,6 +1571,7 @@ fn test_duplicate_htlc_different_direction_onchain() {
 	let header = BlockHeader { version: 0x2000_0000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![remote_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	// Check we only broadcast 1 timeout tx
 	let claim_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
@@ -1548,7 +1620,7 @@ fn test_basic_channel_reserve() {
 	let commit_tx_fee = 2 * commit_tx_fee_msat(get_feerate!(nodes[0], chan.2), 1 + 1);
 	let max_can_send = 5000000 - channel_reserve - commit_tx_fee;
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), max_can_send + 1, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, None, &Vec::new(), max_can_send + 1, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	let err = nodes[0].node.send_payment(&route, our_payment_hash, &None).err().unwrap();
 	match err {
 		PaymentSendFailure::AllFailedRetrySafe(ref fails) => {
@@ -1579,7 +1651,7 @@ fn test_fee_spike_violation_fails_htlc() {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler.network_graph.read().unwrap();
-			let route = get_route(&nodes[0].node.get_our_node_id(), net_graph_msg_handler, &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), net_graph_msg_handler, &nodes.last().unwrap().node.get_our_node_id(), None, None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1592,7 +1664,7 @@ fn test_fee_spike_violation_fails_htlc() {
 	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3460001, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3460001, &None, &None, cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1665,7 +1737,7 @@ fn test_fee_spike_violation_fails_htlc() {
 	};
 
 	let htlc_output = TxOut {
-		script_pubkey: chan_utils::get_htlc_redeemscript(&accepted_htlc_info, &commit_tx_keys).to_v0_p2wsh(),
+		script_pubkey: chan_utils::get_htlc_redeemscript(&accepted_htlc_info, false, &commit_tx_keys).to_v0_p2wsh(),
 		value: 3460001 / 1000
 	};
 
@@ -1710,7 +1782,8 @@ fn test_fee_spike_violation_fails_htlc() {
 	let commit_signed_msg = msgs::CommitmentSigned {
 		channel_id: chan.2,
 		signature: res.0,
-		htlc_signatures: res.1
+		htlc_signatures: res.1,
+		splice_signatures: None,
 	};
 
 	// Send the commitment_signed message to the nodes[1].
@@ -1757,7 +1830,7 @@ fn test_chan_reserve_violation_outbound_htlc_inbound_chan() {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1789,7 +1862,7 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1800,7 +1873,7 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 1000, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 1000, &None, &None, cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1816,6 +1889,7 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 	nodes[0].logger.assert_log("lightning::ln::channelmanager".to_string(), "Cannot accept HTLC that would put our balance under counterparty-announced channel reserve value".to_string(), 1);
 	assert_eq!(nodes[0].node.list_channels().len(), 0);
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert_eq!(err_msg.data, "Cannot accept HTLC that would put our balance under counterparty-announced channel reserve value");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -1834,7 +1908,7 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1872,7 +1946,7 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 	let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route_2.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route_2.paths[0], recv_value_2, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route_2.paths[0], recv_value_2, &None, &None, cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &our_payment_hash_1);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1888,6 +1962,7 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Remote HTLC add would put them under remote reserve value".to_string(), 1);
 	assert_eq!(nodes[1].node.list_channels().len(), 1);
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert_eq!(err_msg.data, "Remote HTLC add would put them under remote reserve value");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -1935,7 +2010,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1958,7 +2033,9 @@ fn test_channel_reserve_holding_cell_htlcs() {
 
 	// attempt to send amt_msat > their_max_htlc_value_in_flight_msat
 	{
-		let (route, our_payment_hash, _) = get_route_and_payment_hash!(recv_value_0 + 1);
+		// The router won't exceed the channel's htlc_maximum_msat, so add the extra msat ourselves
+		let (mut route, our_payment_hash, _) = get_route_and_payment_hash!(recv_value_0);
+		route.paths[0].last_mut().unwrap().fee_msat += 1;
 		assert!(route.paths[0].iter().rev().skip(1).all(|h| h.fee_msat == feemsat));
 		unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
 			assert!(regex::Regex::new(r"Cannot send value that would put us over the max HTLC value in flight our peer will accept \(\d+\)").unwrap().is_match(err)));
@@ -2109,7 +2186,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	let events = nodes[2].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 2);
 	match events[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, payment_preimage: None, amt } => {
 			assert_eq!(our_payment_hash_21, *payment_hash);
 			assert_eq!(*payment_secret, None);
 			assert_eq!(recv_value_21, amt);
@@ -2117,7 +2194,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 		_ => panic!("Unexpected event"),
 	}
 	match events[1] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, payment_preimage: None, amt } => {
 			assert_eq!(our_payment_hash_22, *payment_hash);
 			assert_eq!(None, *payment_secret);
 			assert_eq!(recv_value_22, amt);
@@ -2200,7 +2277,7 @@ fn channel_reserve_in_flight_removes() {
 	let (payment_preimage_3, payment_hash_3) = get_payment_preimage_hash!(nodes[0]);
 	let send_1 = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, payment_hash_3, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -2273,7 +2350,7 @@ fn channel_reserve_in_flight_removes() {
 	let (payment_preimage_4, payment_hash_4) = get_payment_preimage_hash!(nodes[1]);
 	let send_2 = {
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 10000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &[], 10000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[1].node.send_payment(&route, payment_hash_4, &None).unwrap();
 		check_added_monitors!(nodes[1], 1);
 		let mut events = nodes[1].node.get_and_clear_pending_msg_events();
@@ -2347,6 +2424,8 @@ fn channel_monitor_network_test() {
 	get_announce_close_broadcast_events(&nodes, 0, 1);
 	assert_eq!(nodes[0].node.list_channels().len(), 0);
 	assert_eq!(nodes[1].node.list_channels().len(), 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
+	check_closed_event!(nodes[1], 1, ClosureReason::DisconnectedPeer);
 
 	// One pending HTLC is discarded by the force-close:
 	let payment_preimage_1 = route_payment(&nodes[1], &vec!(&nodes[2], &nodes[3])[..], 3000000).0;
@@ -2364,6 +2443,8 @@ fn channel_monitor_network_test() {
 	get_announce_close_broadcast_events(&nodes, 1, 2);
 	assert_eq!(nodes[1].node.list_channels().len(), 0);
 	assert_eq!(nodes[2].node.list_channels().len(), 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::DisconnectedPeer);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 
 	macro_rules! claim_funds {
 		($node: expr, $prev_node: expr, $preimage: expr, $amount: expr) => {
@@ -2406,6 +2487,8 @@ fn channel_monitor_network_test() {
 	get_announce_close_broadcast_events(&nodes, 2, 3);
 	assert_eq!(nodes[2].node.list_channels().len(), 0);
 	assert_eq!(nodes[3].node.list_channels().len(), 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::DisconnectedPeer);
+	check_closed_event!(nodes[3], 1, ClosureReason::CommitmentTxConfirmed);
 
 	{ // Cheat and reset nodes[4]'s height to 1
 		let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
@@ -2479,6 +2562,9 @@ fn channel_monitor_network_test() {
 	nodes[4].net_graph_msg_handler.handle_channel_update(&close_chan_update_1).unwrap();
 	assert_eq!(nodes[3].node.list_channels().len(), 0);
 	assert_eq!(nodes[4].node.list_channels().len(), 0);
+	let err = "ChannelMonitor broadcast our latest commitment transaction to resolve an HTLC on-chain".to_owned();
+	check_closed_event!(nodes[3], 1, ClosureReason::ProcessingError { err: err.clone() });
+	check_closed_event!(nodes[4], 1, ClosureReason::ProcessingError { err });
 }
 
 #[test]
@@ -2527,6 +2613,7 @@ fn test_justice_tx() {
 			node_txn.truncate(1);
 		}
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		test_txn_broadcast(&nodes[1], &chan_5, None, HTLCType::NONE);
 
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
@@ -2534,6 +2621,7 @@ fn test_justice_tx() {
 		let node_txn = test_txn_broadcast(&nodes[0], &chan_5, Some(revoked_local_txn[0].clone()), HTLCType::TIMEOUT);
 		header = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		// Broadcast revoked HTLC-timeout on node 1
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![node_txn[1].clone()] }, 1);
 		test_revoked_htlc_claim_txn_broadcast(&nodes[1], node_txn[1].clone(), revoked_local_txn[0].clone());
@@ -2573,12 +2661,14 @@ fn test_justice_tx() {
 			node_txn.swap_remove(0);
 		}
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		test_txn_broadcast(&nodes[0], &chan_6, None, HTLCType::NONE);
 
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		let node_txn = test_txn_broadcast(&nodes[1], &chan_6, Some(revoked_local_txn[0].clone()), HTLCType::SUCCESS);
 		header = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![node_txn[1].clone()] }, 1);
 		test_revoked_htlc_claim_txn_broadcast(&nodes[0], node_txn[1].clone(), revoked_local_txn[0].clone());
 	}
@@ -2608,6 +2698,7 @@ fn revoked_output_claim() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 2); // ChannelMonitor: justice tx against revoked to_local output, ChannelManager: local commitment tx
 
@@ -2617,7 +2708,8 @@ fn revoked_output_claim() {
 	// Inform nodes[0] that a watchtower cheated on its behalf, so it will force-close the chan
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	get_announce_close_broadcast_events(&nodes, 0, 1);
-	check_added_monitors!(nodes[0], 1)
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 }
 
 #[test]
@@ -2654,8 +2746,10 @@ fn claim_htlc_outputs_shared_tx() {
 		let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
 		expect_payment_failed!(nodes[1], payment_hash_2, true);
 
@@ -2719,7 +2813,17 @@ fn claim_htlc_outputs_single_tx() {
 		check_added_monitors!(nodes[0], 1);
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 200);
 		check_added_monitors!(nodes[1], 1);
-		expect_pending_htlcs_forwardable_ignore!(nodes[0]);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 2);
+		match events[0] {
+			Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+			_ => panic!("Unexpected event"),
+		}
+		match events[1] {
+			Event::PendingHTLCsForwardable { .. } => {},
+			_ => panic!("Unexpected event"),
+		}
 
 		connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 200, true, header.block_hash());
 		expect_payment_failed!(nodes[1], payment_hash_2, true);
@@ -2812,6 +2916,7 @@ fn test_htlc_on_chain_success() {
 	nodes[2].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
 	check_added_monitors!(nodes[2], 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 3 (commitment tx, 2*htlc-success tx), ChannelMonitor : 2 (2 * HTLC-Success tx)
 	assert_eq!(node_txn.len(), 5);
 	assert_eq!(node_txn[0], node_txn[3]);
@@ -2857,6 +2962,23 @@ fn test_htlc_on_chain_success() {
 		},
 		_ => panic!("Unexpected event"),
 	};
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 3);
+	match events[0] {
+		Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	for event in events[1..].iter() {
+		match *event {
+			Event::PaymentForwarded { ref prev_channel_id, ref next_channel_id, fee_earned_msat, claim_from_onchain_tx } => {
+				assert_eq!(*prev_channel_id, Some(chan_1.2));
+				assert_eq!(*next_channel_id, chan_2.2);
+				assert_eq!(fee_earned_msat, Some(239));
+				assert!(claim_from_onchain_tx);
+			},
+			_ => panic!("Unexpected event"),
+		}
+	}
 	macro_rules! check_tx_local_broadcast {
 		($node: expr, $htlc_offered: expr, $commitment_tx: expr, $chan_tx: expr) => { {
 			let mut node_txn = $node.tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -2903,6 +3025,7 @@ fn test_htlc_on_chain_success() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 3 (commitment tx + HTLC-Sucess * 2), ChannelMonitor : 1 (HTLC-Success)
 	assert_eq!(node_txn.len(), 4);
 	check_spends!(node_txn[0], commitment_tx[0]);
@@ -2923,10 +3046,11 @@ fn test_htlc_on_chain_success() {
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
 	let events = nodes[0].node.get_and_clear_pending_events();
-	assert_eq!(events.len(), 2);
+	assert_eq!(events.len(), 3);
 	let mut first_claimed = false;
 	for event in events {
 		match event {
+			Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
 			Event::PaymentSent { payment_preimage } => {
 				if payment_preimage == our_payment_preimage {
 					assert!(!first_claimed);
@@ -2990,6 +3114,7 @@ fn test_htlc_on_chain_timeout() {
 	nodes[2].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
 	check_added_monitors!(nodes[2], 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 1 (commitment tx)
 	assert_eq!(node_txn.len(), 1);
 	check_spends!(node_txn[0], chan_2.3);
@@ -3016,6 +3141,7 @@ fn test_htlc_on_chain_timeout() {
 		timeout_tx = node_txn[0].clone();
 		node_txn.clear();
 	}
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![timeout_tx]}, 1);
 	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
@@ -3046,6 +3172,7 @@ fn test_htlc_on_chain_timeout() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 200);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 2 (commitment tx, HTLC-Timeout tx), ChannelMonitor : 1 timeout tx
 	assert_eq!(node_txn.len(), 3);
 	check_spends!(node_txn[0], commitment_tx[0]);
@@ -3080,6 +3207,7 @@ fn test_simple_commitment_revoked_fail_backward() {
 
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42};
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
 	check_added_monitors!(nodes[1], 1);
 	check_closed_broadcast!(nodes[1], false);
@@ -3207,7 +3335,7 @@ fn do_test_commitment_revoked_fail_backward_exhaustive(deliver_bs_raa: bool, use
 	let (_, fourth_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[1].node.send_payment(&route, fourth_payment_hash, &None).unwrap();
 	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
 	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
@@ -3237,15 +3365,19 @@ fn do_test_commitment_revoked_fail_backward_exhaustive(deliver_bs_raa: bool, use
 	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
 
 	let events = nodes[1].node.get_and_clear_pending_events();
-	assert_eq!(events.len(), if deliver_bs_raa { 1 } else { 2 });
+	assert_eq!(events.len(), if deliver_bs_raa { 2 } else { 3 });
 	match events[0] {
+		Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => { },
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
 		Event::PaymentFailed { ref payment_hash, .. } => {
 			assert_eq!(*payment_hash, fourth_payment_hash);
 		},
 		_ => panic!("Unexpected event"),
 	}
 	if !deliver_bs_raa {
-		match events[1] {
+		match events[2] {
 			Event::PendingHTLCsForwardable { .. } => { },
 			_ => panic!("Unexpected event"),
 		};
@@ -3353,7 +3485,7 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 	{
 		let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
@@ -3370,7 +3502,7 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 	let (_, failed_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, failed_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 0);
 
@@ -3385,8 +3517,8 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 		let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 		let current_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
-		let (onion_payloads, _amount_msat, cltv_expiry) = onion_utils::build_onion_payloads(&route.paths[0], 50_000, &None, current_height).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+		let (onion_payloads, _amount_msat, cltv_expiry) = onion_utils::build_onion_payloads(&route.paths[0], 50_000, &None, &None, current_height).unwrap();
 		let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
 		let onion_routing_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 
@@ -3403,8 +3535,20 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 	}
 
 	// Check that Alice fails backward the pending HTLC from the second payment.
-	expect_payment_failed!(nodes[0], failed_payment_hash, true);
-	check_closed_broadcast!(nodes[0], true);
+	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { ref reason, .. } => assert_eq!(*reason, ClosureReason::ProcessingError { err: err_msg.data }),
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PaymentFailed { ref payment_hash, rejected_by_dest, .. } => {
+			assert_eq!(*payment_hash, failed_payment_hash);
+			assert!(rejected_by_dest);
+		},
+		_ => panic!("Unexpected event"),
+	}
 	check_added_monitors!(nodes[0], 1);
 }
 
@@ -3422,6 +3566,7 @@ fn test_htlc_ignore_latest_remote_commitment() {
 	nodes[0].node.force_close_channel(&nodes[0].node.list_channels()[0].channel_id);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 2);
@@ -3430,6 +3575,7 @@ fn test_htlc_ignore_latest_remote_commitment() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![node_txn[0].clone(), node_txn[1].clone()]}, 1);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	// Duplicate the block_connected call since this may happen due to other listeners
 	// registering new transactions
@@ -3451,7 +3597,7 @@ fn test_force_close_fail_back() {
 
 	let mut payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, 42, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 1000000, 42, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
@@ -3483,6 +3629,7 @@ fn test_force_close_fail_back() {
 	nodes[2].node.force_close_channel(&payment_event.commitment_msg.channel_id);
 	check_closed_broadcast!(nodes[2], false);
 	check_added_monitors!(nodes[2], 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::HolderForceClosed);
 	let tx = {
 		let mut node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap();
 		// Note that we don't bother broadcasting the HTLC-Success transaction here as we don't
@@ -3498,6 +3645,7 @@ fn test_force_close_fail_back() {
 	// Note no UpdateHTLCs event here from nodes[1] to nodes[0]!
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	// Now check that if we add the preimage to ChannelMonitor it broadcasts our HTLC-Success..
 	{
@@ -3544,6 +3692,7 @@ fn test_unconf_chan() {
 	}
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Funding transaction was reorganized out of the chain".to_owned() });
 	let channel_state = nodes[0].node.channel_state.lock().unwrap();
 	assert_eq!(channel_state.by_id.len(), 0);
 	assert_eq!(channel_state.short_to_id.len(), 0);
@@ -3625,8 +3774,8 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 	let payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(),
-			&nodes[1].node.get_our_node_id(), Some(&nodes[0].node.list_usable_channels().iter().collect::<Vec<_>>()),
-			&Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+			&nodes[1].node.get_our_node_id(), None, Some(&nodes[0].node.list_usable_channels().iter().collect::<Vec<_>>()),
+			&Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, payment_hash_1, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
@@ -3702,7 +3851,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 	let events_2 = nodes[1].node.get_and_clear_pending_events();
 	assert_eq!(events_2.len(), 1);
 	match events_2[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, payment_preimage: None, amt } => {
 			assert_eq!(payment_hash_1, *payment_hash);
 			assert_eq!(*payment_secret, None);
 			assert_eq!(amt, 1000000);
@@ -3802,8 +3951,8 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 	// Channel should still work fine...
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(),
-		&nodes[1].node.get_our_node_id(), Some(&nodes[0].node.list_usable_channels().iter().collect::<Vec<_>>()),
-		&Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+		&nodes[1].node.get_our_node_id(), None, Some(&nodes[0].node.list_usable_channels().iter().collect::<Vec<_>>()),
+		&Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	let payment_preimage_2 = send_along_route(&nodes[0], route, &[&nodes[1]], 1000000).0;
 	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage_2, 1_000_000);
 }
@@ -3823,6 +3972,40 @@ fn test_drop_messages_peer_disconnect_b() {
 	do_test_drop_messages_peer_disconnect(6);
 }
 
+#[test]
+fn test_inbound_funding_timeout() {
+	// Test that we forget an inbound channel whose funding transaction hasn't confirmed
+	// FUNDING_CONF_DEADLINE_BLOCKS after we received funding_created, but no sooner.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_chan_between_nodes_with_value_init(&nodes[0], &nodes[1], 100000, 10001, InitFeatures::known(), InitFeatures::known());
+	let chan_id = nodes[1].node.list_channels()[0].channel_id;
+
+	let (block_hash, funding_created_height) = nodes[1].node.current_best_block();
+	let block_hash = connect_blocks(&nodes[1].block_notifier, FUNDING_CONF_DEADLINE_BLOCKS - 1, funding_created_height, true, block_hash);
+	assert_eq!(nodes[1].node.list_channels().len(), 1);
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+	connect_blocks(&nodes[1].block_notifier, 1, funding_created_height + FUNDING_CONF_DEADLINE_BLOCKS - 1, true, block_hash);
+	assert!(nodes[1].node.list_channels().is_empty());
+	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::FundingTimedOut);
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	match msg_events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, node_id } => {
+			assert_eq!(node_id, nodes[0].node.get_our_node_id());
+			assert_eq!(msg.channel_id, chan_id);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	// As the funder, our counterparty keeps waiting on its own funding transaction.
+	assert_eq!(nodes[0].node.list_channels().len(), 1);
+}
+
 #[test]
 fn test_funding_peer_disconnect() {
 	// Test that we can lock in our funding tx while disconnected
@@ -3904,7 +4087,7 @@ fn test_funding_peer_disconnect() {
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	let (payment_preimage, _) = send_along_route(&nodes[0], route, &[&nodes[1]], 1000000);
 	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 1_000_000);
 }
@@ -3925,7 +4108,7 @@ fn test_drop_messages_peer_disconnect_dual_htlc() {
 	// Now try to send a second payment which will fail to send
 	let (payment_preimage_2, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, payment_hash_2, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
@@ -4039,7 +4222,7 @@ fn test_drop_messages_peer_disconnect_dual_htlc() {
 	let events_5 = nodes[1].node.get_and_clear_pending_events();
 	assert_eq!(events_5.len(), 1);
 	match events_5[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt: _ } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, payment_preimage: None, amt: _ } => {
 			assert_eq!(payment_hash_2, *payment_hash);
 			assert_eq!(*payment_secret, None);
 		},
@@ -4066,12 +4249,12 @@ fn do_test_htlc_timeout(send_partial_mpp: bool) {
 
 	let our_payment_hash = if send_partial_mpp {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		let (_, our_payment_hash) = get_payment_preimage_hash!(&nodes[0]);
 		let payment_secret = PaymentSecret([0xdb; 32]);
 		// Use the utility function send_payment_along_path to send the payment with MPP data which
 		// indicates there are more HTLCs coming.
-		nodes[0].node.send_payment_along_path(&route.paths[0], &our_payment_hash, &Some(payment_secret), 200000, CHAN_CONFIRM_DEPTH).unwrap();
+		nodes[0].node.send_payment_along_path(&route.paths[0], &our_payment_hash, &Some(payment_secret), &None, 200000, CHAN_CONFIRM_DEPTH).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
 		assert_eq!(events.len(), 1);
@@ -4129,7 +4312,7 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 	let (_, first_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[1].node.send_payment(&route, first_payment_hash, &None).unwrap();
 	}
 	assert_eq!(nodes[1].node.get_and_clear_pending_msg_events().len(), 1);
@@ -4139,7 +4322,7 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 	let (_, second_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	if forwarded_htlc {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, second_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let payment_event = SendEvent::from_event(nodes[0].node.get_and_clear_pending_msg_events().remove(0));
@@ -4149,7 +4332,7 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 		check_added_monitors!(nodes[1], 0);
 	} else {
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[1].node.send_payment(&route, second_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[1], 0);
 	}
@@ -4636,6 +4819,7 @@ fn test_manager_serialize_deserialize_inconsistent_monitor() {
 		check_added_monitors!(nodes[0], 1);
 	}
 	nodes[0].node = &nodes_0_deserialized;
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "ChannelManager was stale relative to the channel's ChannelMonitor".to_owned() });
 
 	// nodes[1] and nodes[2] have no lost state with nodes[0]...
 	reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
@@ -4790,6 +4974,7 @@ fn test_claim_sizeable_push_msat() {
 	nodes[1].node.force_close_channel(&chan.2);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 1);
 	check_spends!(node_txn[0], chan.3);
@@ -4817,6 +5002,7 @@ fn test_claim_on_remote_sizeable_push_msat() {
 	nodes[0].node.force_close_channel(&chan.2);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 1);
@@ -4827,6 +5013,7 @@ fn test_claim_on_remote_sizeable_push_msat() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![node_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
 
 	let spend_txn = check_spendable_outputs!(nodes[1], 1, node_cfgs[1].keys_manager, 100000);
@@ -4856,6 +5043,7 @@ fn test_claim_on_remote_revoked_sizeable_push_msat() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	let header_1 = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
@@ -4891,6 +5079,7 @@ fn test_static_spendable_outputs_preimage_tx() {
 	check_added_monitors!(nodes[1], 1);
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()] }, 1);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -4941,6 +5130,7 @@ fn test_static_spendable_outputs_timeout_tx() {
 	let header = BlockHeader { version: 0x2000_0000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42};
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()] }, 0);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::BroadcastChannelUpdate { .. } => {},
@@ -4986,6 +5176,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_commitment_tx() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 2);
@@ -5023,6 +5214,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_timeout_tx() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let revoked_htlc_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(revoked_htlc_txn.len(), 2);
@@ -5035,6 +5227,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_timeout_tx() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone(), revoked_htlc_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 3); // ChannelMonitor: bogus justice tx, justice tx on revoked outputs, ChannelManager: local commitment tx
@@ -5092,6 +5285,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_success_tx() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let revoked_htlc_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 
 	assert_eq!(revoked_htlc_txn.len(), 2);
@@ -5107,6 +5301,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_success_tx() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone(), revoked_htlc_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 3); // ChannelMonitor: justice tx on revoked commitment, justice tx on revoked HTLC-success, ChannelManager: local commitment tx
@@ -5183,6 +5378,7 @@ fn test_onchain_to_onchain_claim() {
 	nodes[2].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
 	check_added_monitors!(nodes[2], 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let c_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 2 (commitment tx, HTLC-Success tx), ChannelMonitor : 1 (HTLC-Success tx)
 	assert_eq!(c_txn.len(), 3);
@@ -5215,6 +5411,19 @@ fn test_onchain_to_onchain_claim() {
 	check_added_monitors!(nodes[1], 1);
 	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
 	check_added_monitors!(nodes[1], 1);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PaymentForwarded { fee_earned_msat, claim_from_onchain_tx, .. } => {
+			assert_eq!(fee_earned_msat, Some(239));
+			assert!(claim_from_onchain_tx);
+		},
+		_ => panic!("Unexpected event"),
+	}
 	match msg_events[0] {
 		MessageSendEvent::BroadcastChannelUpdate {  .. } => {},
 		_ => panic!("Unexpected event"),
@@ -5244,6 +5453,7 @@ fn test_onchain_to_onchain_claim() {
 
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 }
 
 #[test]
@@ -5270,6 +5480,7 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let htlc_timeout_tx;
 	{ // Extract one of the two HTLC-Timeout transaction
@@ -5292,6 +5503,7 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 	nodes[2].node.claim_funds(our_payment_preimage, &None, 900_000);
 	nodes[2].block_notifier.block_connected(&Block { header, txdata: vec![commitment_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[2], 3);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[2].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -5350,6 +5562,7 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 	assert_eq!(updates.update_fulfill_htlcs[0].htlc_id, 0);
 	assert!(updates.update_fail_malformed_htlcs.is_empty());
 	check_added_monitors!(nodes[1], 1);
+	expect_payment_forwarded!(nodes[1], Some(239), true);
 
 	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
 	commitment_signed_dance!(nodes[0], nodes[1], &updates.commitment_signed, false);
@@ -5384,6 +5597,7 @@ fn test_dynamic_spendable_outputs_local_htlc_success_tx() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![local_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -5449,7 +5663,7 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	let (_, payment_hash_2) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], ds_dust_limit*1000); // not added < dust limit + HTLC tx fee
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
 	let our_node_id = &nodes[1].node.get_our_node_id();
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	// 2nd HTLC:
 	send_along_route_with_hash(&nodes[1], route.clone(), &[&nodes[2], &nodes[3], &nodes[5]], ds_dust_limit*1000, payment_hash_1); // not added < dust limit + HTLC tx fee
 	// 3rd HTLC:
@@ -5458,7 +5672,7 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	let (_, payment_hash_3) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
 	// 5th HTLC:
 	let (_, payment_hash_4) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	// 6th HTLC:
 	send_along_route_with_hash(&nodes[1], route.clone(), &[&nodes[2], &nodes[3], &nodes[5]], 1000000, payment_hash_3);
 	// 7th HTLC:
@@ -5467,13 +5681,13 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	// 8th HTLC:
 	let (_, payment_hash_5) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
 	// 9th HTLC:
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &[&nodes[2], &nodes[3], &nodes[5]], ds_dust_limit*1000, payment_hash_5); // not added < dust limit + HTLC tx fee
 
 	// 10th HTLC:
 	let (_, payment_hash_6) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], ds_dust_limit*1000); // not added < dust limit + HTLC tx fee
 	// 11th HTLC:
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &[&nodes[2], &nodes[3], &nodes[5]], 1000000, payment_hash_6);
 
 	// Double-check that six of the new HTLC were added
@@ -5549,7 +5763,18 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	}
 	connect_blocks(&nodes[2].block_notifier, ANTI_REORG_DELAY - 1, 1, true,  header.block_hash());
 	check_closed_broadcast!(nodes[2], false);
-	expect_pending_htlcs_forwardable!(nodes[2]);
+	// Depending on which commitment transaction was broadcast, the HTLCs may become forwardable
+	// either before or after the channel is closed.
+	let events = nodes[2].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	for event in events {
+		match event {
+			Event::PendingHTLCsForwardable { .. } => {},
+			Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+			_ => panic!("Unexpected event"),
+		}
+	}
+	nodes[2].node.process_pending_htlc_forwards();
 	check_added_monitors!(nodes[2], 3);
 
 	let cs_msgs = nodes[2].node.get_and_clear_pending_msg_events();
@@ -5688,6 +5913,7 @@ fn test_dynamic_spendable_outputs_local_htlc_timeout_tx() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![local_txn[0].clone()] }, 200);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let htlc_timeout = {
 		let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5758,6 +5984,7 @@ fn test_key_derivation_params() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![local_txn_1[0].clone()] }, 200);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let htlc_timeout = {
 		let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5809,6 +6036,194 @@ fn test_static_output_closing_tx() {
 	check_spends!(spend_txn[0], closing_tx);
 }
 
+#[test]
+fn test_keys_manager_spend_spendable_outputs() {
+	// Sweep a DynamicOutputP2WSH (our own commitment), a StaticOutputCounterpartyPayment (their
+	// commitment) and a StaticOutput (a cooperative close) in a single transaction built by
+	// KeysManager::spend_spendable_outputs.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let chan_a = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 99000000, InitFeatures::known(), InitFeatures::known());
+	let chan_b = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 99000000, InitFeatures::known(), InitFeatures::known());
+	let chan_c = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 99000000, InitFeatures::known(), InitFeatures::known());
+
+	nodes[1].node.force_close_channel(&chan_a.2);
+	check_closed_broadcast!(nodes[1], false);
+	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
+	let local_commitment_tx = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(local_commitment_tx.len(), 1);
+	check_spends!(local_commitment_tx[0], chan_a.3);
+
+	nodes[0].node.force_close_channel(&chan_b.2);
+	check_closed_broadcast!(nodes[0], false);
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+	let remote_commitment_tx = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(remote_commitment_tx.len(), 1);
+	check_spends!(remote_commitment_tx[0], chan_b.3);
+
+	let closing_tx = close_channel(&nodes[0], &nodes[1], &chan_c.2, chan_c.3, true).2;
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![local_commitment_tx[0].clone(), remote_commitment_tx[0].clone(), closing_tx.clone()] }, 1);
+	check_closed_broadcast!(nodes[1], false);
+	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
+	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
+
+	let mut descriptors = Vec::new();
+	for event in nodes[1].chan_monitor.simple_monitor.get_and_clear_pending_events() {
+		if let Event::SpendableOutputs { mut outputs } = event {
+			descriptors.append(&mut outputs);
+		} else { panic!("Unexpected event"); }
+	}
+	let mut input_value = 0;
+	let mut seen = HashSet::new();
+	for descriptor in descriptors.iter() {
+		match descriptor {
+			&SpendableOutputDescriptor::StaticOutput { ref outpoint, ref output } => {
+				assert_eq!(outpoint.txid, closing_tx.txid());
+				if seen.insert(*outpoint) { input_value += output.value; }
+			},
+			&SpendableOutputDescriptor::DynamicOutputP2WSH { ref outpoint, ref output, .. } => {
+				assert_eq!(outpoint.txid, local_commitment_tx[0].txid());
+				if seen.insert(*outpoint) { input_value += output.value; }
+			},
+			&SpendableOutputDescriptor::StaticOutputCounterpartyPayment { ref outpoint, ref output, .. } => {
+				assert_eq!(outpoint.txid, remote_commitment_tx[0].txid());
+				if seen.insert(*outpoint) { input_value += output.value; }
+			},
+		}
+	}
+	assert_eq!(seen.len(), 3);
+
+	let destination_script = Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script();
+	let spend_tx = node_cfgs[1].keys_manager.spend_spendable_outputs(&descriptors, destination_script.clone(), 2530).unwrap();
+	assert_eq!(spend_tx.input.len(), 3);
+	assert_eq!(spend_tx.output.len(), 1);
+	assert_eq!(spend_tx.output[0].script_pubkey, destination_script);
+	check_spends!(spend_tx, local_commitment_tx[0], remote_commitment_tx[0], closing_tx);
+	for input in spend_tx.input.iter() {
+		if input.previous_output.txid == local_commitment_tx[0].txid() {
+			assert_eq!(input.sequence, BREAKDOWN_TIMEOUT as u32);
+		} else {
+			assert_eq!(input.sequence, 0);
+		}
+	}
+	// The fee is computed on the worst-case weight, so may only slightly overshoot the feerate.
+	let fee = input_value - spend_tx.output[0].value;
+	assert!(fee >= spend_tx.get_weight() as u64 * 2530 / 1000);
+	assert!(fee <= (spend_tx.get_weight() as u64 + 3 * 2) * 2530 / 1000);
+
+	// Descriptors from another node's keys, or too small to pay for themselves, are refused.
+	assert!(node_cfgs[0].keys_manager.spend_spendable_outputs(&descriptors, destination_script.clone(), 2530).is_err());
+	assert!(node_cfgs[1].keys_manager.spend_spendable_outputs(&descriptors, destination_script, (input_value * 1000 / spend_tx.get_weight() as u64) as u32).is_err());
+}
+
+#[test]
+fn test_output_sweeper() {
+	// Check that an OutputSweeper waits for the CSV delay on our to_self output before sweeping
+	// it, rebroadcasts and fee-bumps the sweep, handles reorgs and forgets the output once the
+	// sweep is ANTI_REORG_DELAY deep.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 99000000, InitFeatures::known(), InitFeatures::known());
+	nodes[1].node.force_close_channel(&chan.2);
+	check_closed_broadcast!(nodes[1], false);
+	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
+	let commitment_tx = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(commitment_tx.len(), 1);
+
+	let broadcaster = test_utils::TestBroadcaster { txn_broadcasted: Mutex::new(Vec::new()) };
+	let fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 253 };
+	let logger = test_utils::TestLogger::new();
+	let chain_watch = ChainWatchInterfaceUtil::new(Network::Testnet);
+	let destination_script = Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script();
+	let sweeper = OutputSweeper::new(destination_script.clone(), 0, &broadcaster, &fee_estimator, &node_cfgs[1].keys_manager, &chain_watch, &logger);
+	let sweeper_notifier: chaininterface::BlockNotifierRef<&ChainWatchInterfaceUtil> = BlockNotifier::new(&chain_watch);
+	sweeper_notifier.register_listener(&sweeper);
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()] }, 1);
+	sweeper_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()] }, 1);
+	connect_blocks(&nodes[1].block_notifier, ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
+	let mut tip = connect_blocks(&sweeper_notifier, ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
+
+	let mut descriptors = Vec::new();
+	for event in nodes[1].chan_monitor.simple_monitor.get_and_clear_pending_events() {
+		if let Event::SpendableOutputs { mut outputs } = event {
+			descriptors.append(&mut outputs);
+		} else { panic!("Unexpected event"); }
+	}
+	assert_eq!(descriptors.len(), 1);
+	if let SpendableOutputDescriptor::DynamicOutputP2WSH { .. } = descriptors[0] {} else { panic!(); }
+	sweeper.track_spendable_outputs(descriptors.clone());
+	// Duplicates are ignored.
+	sweeper.track_spendable_outputs(descriptors.clone());
+	assert_eq!(sweeper.tracked_outputs(), descriptors);
+
+	// The commitment transaction confirmed at height 1, so the sweep may be included in the block
+	// at height BREAKDOWN_TIMEOUT + 1 and is broadcast once the block before it is connected.
+	tip = connect_blocks(&sweeper_notifier, BREAKDOWN_TIMEOUT as u32 - 1 - ANTI_REORG_DELAY, ANTI_REORG_DELAY, true, tip);
+	assert!(broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	tip = connect_blocks(&sweeper_notifier, 1, BREAKDOWN_TIMEOUT as u32 - 1, true, tip);
+	let first_sweep = {
+		let mut txn = broadcaster.txn_broadcasted.lock().unwrap();
+		assert_eq!(txn.len(), 1);
+		txn.pop().unwrap()
+	};
+	check_spends!(first_sweep, commitment_tx[0]);
+	assert_eq!(first_sweep.output[0].script_pubkey, destination_script);
+
+	// The sweep is rebroadcast until it has been stuck for long enough, at which point its feerate
+	// is bumped.
+	tip = connect_blocks(&sweeper_notifier, 2, BREAKDOWN_TIMEOUT as u32, true, tip);
+	assert_eq!(*broadcaster.txn_broadcasted.lock().unwrap(), vec![first_sweep.clone(), first_sweep.clone()]);
+	broadcaster.txn_broadcasted.lock().unwrap().clear();
+	tip = connect_blocks(&sweeper_notifier, 1, BREAKDOWN_TIMEOUT as u32 + 2, true, tip);
+	let bumped_sweep = {
+		let mut txn = broadcaster.txn_broadcasted.lock().unwrap();
+		assert_eq!(txn.len(), 1);
+		txn.pop().unwrap()
+	};
+	check_spends!(bumped_sweep, commitment_tx[0]);
+	assert!(bumped_sweep.output[0].value < first_sweep.output[0].value);
+
+	// Once the sweep confirms it is no longer rebroadcast, unless the block is disconnected.
+	let sweep_height = BREAKDOWN_TIMEOUT as u32 + 4;
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: tip, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	sweeper_notifier.block_connected(&Block { header, txdata: vec![bumped_sweep.clone()] }, sweep_height);
+	assert!(broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	sweeper_notifier.block_disconnected(&header, sweep_height);
+	tip = connect_blocks(&sweeper_notifier, 1, sweep_height - 1, true, tip);
+	assert_eq!(*broadcaster.txn_broadcasted.lock().unwrap(), vec![bumped_sweep.clone()]);
+	broadcaster.txn_broadcasted.lock().unwrap().clear();
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: tip, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	sweeper_notifier.block_connected(&Block { header, txdata: vec![bumped_sweep.clone()] }, sweep_height + 1);
+	tip = connect_blocks(&sweeper_notifier, ANTI_REORG_DELAY - 2, sweep_height + 1, true, header.block_hash());
+	assert!(broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	assert_eq!(sweeper.tracked_outputs(), descriptors);
+
+	// The sweeper's state survives a round trip through serialization.
+	let encoded = sweeper.encode();
+	let read_sweeper: OutputSweeper<_, _, _, _, _> = ReadableArgs::read(&mut &encoded[..], (&broadcaster, &fee_estimator, &node_cfgs[1].keys_manager, &chain_watch, &logger)).unwrap();
+	assert_eq!(read_sweeper.encode(), encoded);
+	assert_eq!(read_sweeper.tracked_outputs(), descriptors);
+
+	connect_blocks(&sweeper_notifier, 1, sweep_height + ANTI_REORG_DELAY - 1, true, tip);
+	assert!(sweeper.tracked_outputs().is_empty());
+	assert!(broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+}
+
 fn do_htlc_claim_local_commitment_only(use_dust: bool) {
 	let chanmon_cfgs = create_chanmon_cfgs(2);
 	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
@@ -5847,6 +6262,7 @@ fn do_htlc_claim_local_commitment_only(use_dust: bool) {
 	}
 	test_txn_broadcast(&nodes[1], &chan, None, if use_dust { HTLCType::NONE } else { HTLCType::SUCCESS });
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "ChannelMonitor broadcast our latest commitment transaction to resolve an HTLC on-chain".to_owned() });
 	check_added_monitors!(nodes[1], 1);
 }
 
@@ -5860,7 +6276,7 @@ fn do_htlc_claim_current_remote_commitment_only(use_dust: bool) {
 
 	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), if use_dust { 50000 } else { 3000000 }, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &Vec::new(), if use_dust { 50000 } else { 3000000 }, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
@@ -5878,6 +6294,7 @@ fn do_htlc_claim_current_remote_commitment_only(use_dust: bool) {
 	}
 	test_txn_broadcast(&nodes[0], &chan, None, HTLCType::NONE);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "ChannelMonitor broadcast our latest commitment transaction to resolve an HTLC on-chain".to_owned() });
 	check_added_monitors!(nodes[0], 1);
 }
 
@@ -5922,6 +6339,7 @@ fn do_htlc_claim_previous_remote_commitment_only(use_dust: bool, check_revoke_no
 	if !check_revoke_no_close {
 		test_txn_broadcast(&nodes[0], &chan, None, HTLCType::NONE);
 		check_closed_broadcast!(nodes[0], false);
+		check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "ChannelMonitor broadcast our latest commitment transaction to resolve an HTLC on-chain".to_owned() });
 		check_added_monitors!(nodes[0], 1);
 	} else {
 		expect_payment_failed!(nodes[0], our_payment_hash, true);
@@ -6061,7 +6479,7 @@ fn test_fail_holding_cell_htlc_upon_free() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let max_can_send = 5000000 - channel_reserve - 2*commit_tx_fee_msat(feerate, 1 + 1);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], max_can_send, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 
 	// Send a payment which passes reserve checks but gets stuck in the holding cell.
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
@@ -6088,7 +6506,7 @@ fn test_fail_holding_cell_htlc_upon_free() {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match &events[0] {
-		&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, ref error_code, ref error_data } => {
+		&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, short_channel_id: _, ref error_code, ref error_data } => {
 			assert_eq!(our_payment_hash.clone(), *payment_hash);
 			assert_eq!(*rejected_by_dest, false);
 			assert_eq!(*error_code, None);
@@ -6136,8 +6554,8 @@ fn test_free_and_fail_holding_cell_htlcs() {
 	let (_, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
 	let amt_2 = 5000000 - channel_reserve - 2*commit_tx_fee_msat(feerate, 2 + 1) - amt_1;
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], amt_1, TEST_FINAL_CLTV, &logger).unwrap();
-	let route_2 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], amt_2, TEST_FINAL_CLTV, &logger).unwrap();
+	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], amt_1, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	let route_2 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], amt_2, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 
 	// Send 2 payments which pass reserve checks but get stuck in the holding cell.
 	nodes[0].node.send_payment(&route_1, payment_hash_1, &None).unwrap();
@@ -6168,7 +6586,7 @@ fn test_free_and_fail_holding_cell_htlcs() {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match &events[0] {
-		&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, ref error_code, ref error_data } => {
+		&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, short_channel_id: _, ref error_code, ref error_data } => {
 			assert_eq!(payment_hash_2.clone(), *payment_hash);
 			assert_eq!(*rejected_by_dest, false);
 			assert_eq!(*error_code, None);
@@ -6260,7 +6678,7 @@ fn test_fail_holding_cell_htlc_upon_free_multihop() {
 	let max_can_send = 5000000 - channel_reserve - 2*commit_tx_fee_msat(feerate, 1 + 1) - total_routing_fee_msat;
 	let payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &[], max_can_send, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
@@ -6376,7 +6794,7 @@ fn test_update_add_htlc_bolt2_sender_value_below_minimum_msat() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	route.paths[0][0].fee_msat = 100;
 
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
@@ -6397,7 +6815,7 @@ fn test_update_add_htlc_bolt2_sender_zero_value_msat() {
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	route.paths[0][0].fee_msat = 0;
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
 		assert_eq!(err, "Cannot send 0-msat HTLC"));
@@ -6418,7 +6836,7 @@ fn test_update_add_htlc_bolt2_receiver_zero_value_msat() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6426,7 +6844,8 @@ fn test_update_add_htlc_bolt2_receiver_zero_value_msat() {
 
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Remote side tried to send a 0-msat HTLC".to_string(), 1);
-	check_closed_broadcast!(nodes[1], true).unwrap();
+	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data });
 	check_added_monitors!(nodes[1], 1);
 }
 
@@ -6444,7 +6863,7 @@ fn test_update_add_htlc_bolt2_sender_cltv_expiry_too_high() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000000, 500000001, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 1000000, 500000001, &logger, &test_utils::TestScorer::new()).unwrap();
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::RouteError { ref err },
 		assert_eq!(err, &"Channel CLTV overflowed?"));
 }
@@ -6466,7 +6885,7 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_num_and_htlc_id_increment()
 		let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let payment_event = {
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 			nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 			check_added_monitors!(nodes[0], 1);
 
@@ -6488,7 +6907,7 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_num_and_htlc_id_increment()
 	}
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
 		assert!(regex::Regex::new(r"Cannot push more than their max accepted HTLCs \(\d+\)").unwrap().is_match(err)));
 
@@ -6512,7 +6931,9 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_value_in_flight() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_in_flight+1, TEST_FINAL_CLTV, &logger).unwrap();
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], max_in_flight, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	// The router won't exceed the channel's htlc_maximum_msat, so add the extra msat ourselves
+	route.paths[0].last_mut().unwrap().fee_msat += 1;
 	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
 		assert!(regex::Regex::new(r"Cannot send value that would put us over the max HTLC value in flight our peer will accept \(\d+\)").unwrap().is_match(err)));
 
@@ -6541,7 +6962,7 @@ fn test_update_add_htlc_bolt2_receiver_check_amount_received_more_than_min() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], htlc_minimum_msat, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], htlc_minimum_msat, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6549,6 +6970,7 @@ fn test_update_add_htlc_bolt2_receiver_check_amount_received_more_than_min() {
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert!(regex::Regex::new(r"Remote side tried to send less than our minimum HTLC value\. Lower limit: \(\d+\)\. Actual: \(\d+\)").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6572,7 +6994,7 @@ fn test_update_add_htlc_bolt2_receiver_sender_can_afford_amount_sent() {
 	let max_can_send = 5000000 - channel_reserve - commit_tx_fee_outbound;
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], max_can_send, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6585,6 +7007,7 @@ fn test_update_add_htlc_bolt2_receiver_sender_can_afford_amount_sent() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert_eq!(err_msg.data, "Remote HTLC add would put them under remote reserve value");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6604,11 +7027,11 @@ fn test_update_add_htlc_bolt2_receiver_check_max_htlc_limit() {
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 3999999, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 3999999, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 
 	let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&Secp256k1::signing_only(), &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, _htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3999999, &None, cur_height).unwrap();
+	let (onion_payloads, _htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3999999, &None, &None, cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &our_payment_hash);
 
 	let mut msg = msgs::UpdateAddHTLC {
@@ -6629,6 +7052,7 @@ fn test_update_add_htlc_bolt2_receiver_check_max_htlc_limit() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert!(regex::Regex::new(r"Remote tried to push more than our max accepted HTLCs \(\d+\)").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6645,7 +7069,7 @@ fn test_update_add_htlc_bolt2_receiver_check_max_in_flight_msat() {
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6654,6 +7078,7 @@ fn test_update_add_htlc_bolt2_receiver_check_max_in_flight_msat() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert!(regex::Regex::new("Remote HTLC add would put them over our max HTLC value").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6670,7 +7095,7 @@ fn test_update_add_htlc_bolt2_receiver_check_cltv_expiry() {
 	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6679,6 +7104,7 @@ fn test_update_add_htlc_bolt2_receiver_check_cltv_expiry() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert_eq!(err_msg.data,"Remote provided CLTV expiry in seconds instead of block height");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6697,7 +7123,7 @@ fn test_update_add_htlc_bolt2_receiver_check_repeated_id_ignore() {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6728,6 +7154,7 @@ fn test_update_add_htlc_bolt2_receiver_check_repeated_id_ignore() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert!(regex::Regex::new(r"Remote skipped HTLC ID \(skipped ID: \d+\)").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6744,7 +7171,7 @@ fn test_update_fulfill_htlc_bolt2_update_fulfill_htlc_before_commitment() {
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let (our_payment_preimage, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 
 	check_added_monitors!(nodes[0], 1);
@@ -6761,6 +7188,7 @@ fn test_update_fulfill_htlc_bolt2_update_fulfill_htlc_before_commitment() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert!(regex::Regex::new(r"Remote tried to fulfill/fail HTLC \(\d+\) before it had been committed").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6778,7 +7206,7 @@ fn test_update_fulfill_htlc_bolt2_update_fail_htlc_before_commitment() {
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6794,6 +7222,7 @@ fn test_update_fulfill_htlc_bolt2_update_fail_htlc_before_commitment() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert!(regex::Regex::new(r"Remote tried to fulfill/fail HTLC \(\d+\) before it had been committed").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6811,7 +7240,7 @@ fn test_update_fulfill_htlc_bolt2_update_fail_malformed_htlc_before_commitment()
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6828,6 +7257,7 @@ fn test_update_fulfill_htlc_bolt2_update_fail_malformed_htlc_before_commitment()
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert!(regex::Regex::new(r"Remote tried to fulfill/fail HTLC \(\d+\) before it had been committed").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6869,6 +7299,7 @@ fn test_update_fulfill_htlc_bolt2_incorrect_htlc_id() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert_eq!(err_msg.data, "Remote tried to fulfill/fail an HTLC we couldn't find");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6910,6 +7341,7 @@ fn test_update_fulfill_htlc_bolt2_wrong_preimage() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert!(regex::Regex::new(r"Remote tried to fulfill HTLC \(\d+\) with an incorrect preimage").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6927,7 +7359,7 @@ fn test_update_fulfill_htlc_bolt2_missing_badonion_bit_for_malformed_htlc_messag
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 1000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
@@ -6958,6 +7390,7 @@ fn test_update_fulfill_htlc_bolt2_missing_badonion_bit_for_malformed_htlc_messag
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: err_msg.data.clone() });
 	assert_eq!(err_msg.data, "Got update_fail_malformed_htlc with BADONION not set");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6980,7 +7413,7 @@ fn test_update_fulfill_htlc_bolt2_after_malformed_htlc_message_must_forward_upda
 	//First hop
 	let mut payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -7104,7 +7537,7 @@ fn do_test_failure_delay_dust_htlc_local_commitment(announce_latest: bool) {
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
 
-	assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	connect_blocks(&nodes[0].block_notifier, ANTI_REORG_DELAY - 1, 1, true,  header.block_hash());
 	let events = nodes[0].node.get_and_clear_pending_events();
 	// Only 2 PaymentFailed events should show up, over-dust HTLC has to be failed by timeout tx
@@ -7220,7 +7653,7 @@ fn do_test_sweep_outbound_htlc_failure_update(revoked: bool, local: bool) {
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![as_commitment_tx[0].clone()]}, 1);
 		check_closed_broadcast!(nodes[0], false);
 		check_added_monitors!(nodes[0], 1);
-		assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		timeout_tx.push(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap()[0].clone());
 		let parent_hash  = connect_blocks(&nodes[0].block_notifier, ANTI_REORG_DELAY - 1, 2, true, header.block_hash());
 		expect_payment_failed!(nodes[0], dust_hash, true);
@@ -7237,7 +7670,7 @@ fn do_test_sweep_outbound_htlc_failure_update(revoked: bool, local: bool) {
 		nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![bs_commitment_tx[0].clone()]}, 1);
 		check_closed_broadcast!(nodes[0], false);
 		check_added_monitors!(nodes[0], 1);
-		assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		timeout_tx.push(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap()[0].clone());
 		let parent_hash  = connect_blocks(&nodes[0].block_notifier, ANTI_REORG_DELAY - 1, 2, true, header.block_hash());
 		let header_2 = BlockHeader { version: 0x20000000, prev_blockhash: parent_hash, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
@@ -7304,7 +7737,9 @@ fn test_upfront_shutdown_script() {
 	node_0_shutdown.scriptpubkey = Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script().to_p2sh();
 	// Test we enforce upfront_scriptpbukey if by providing a diffrent one at closing that  we disconnect peer
 	nodes[2].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
-    assert!(regex::Regex::new(r"Got shutdown request with a scriptpubkey \([A-Fa-f0-9]+\) which did not match their previous scriptpubkey.").unwrap().is_match(check_closed_broadcast!(nodes[2], true).unwrap().data.as_str()));
+	let err_msg = check_closed_broadcast!(nodes[2], true).unwrap();
+	assert!(regex::Regex::new(r"Got shutdown request with a scriptpubkey \([A-Fa-f0-9]+\) which did not match their previous scriptpubkey.").unwrap().is_match(err_msg.data.as_str()));
+	check_closed_event!(nodes[2], 1, ClosureReason::ProcessingError { err: err_msg.data });
 	check_added_monitors!(nodes[2], 1);
 
 	// We test that in case of peer committing upfront to a script, if it doesn't change at closing, we sign
@@ -7368,87 +7803,960 @@ fn test_upfront_shutdown_script() {
 }
 
 #[test]
-fn test_user_configurable_csv_delay() {
-	// We test our channel constructors yield errors when we pass them absurd csv delay
-
-	let mut low_our_to_self_config = UserConfig::default();
-	low_our_to_self_config.own_channel_config.our_to_self_delay = 6;
-	let mut high_their_to_self_config = UserConfig::default();
-	high_their_to_self_config.peer_channel_config_limits.their_to_self_delay = 100;
-	let user_cfgs = [Some(high_their_to_self_config.clone()), None];
+fn test_close_channel_with_params() {
+	// Test that a cooperative close can be directed to a user-provided script and that the
+	// closing fee is negotiated within the user-provided fee range, which goes in the BOLT 2
+	// closing_signed fee_range TLV.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	config.channel_options.commit_upfront_shutdown_pubkey = false;
 	let chanmon_cfgs = create_chanmon_cfgs(2);
 	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
-	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &user_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
 	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 0, InitFeatures::known(), InitFeatures::known());
+
+	let shutdown_script = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_1).into_script().to_v0_p2wsh();
+	let fee_range = msgs::ClosingSignedFeeRange { min_fee_satoshis: 2000, max_fee_satoshis: 2000 };
+
+	// Reject nonsensical parameters up-front, leaving the channel open
+	if let Err(APIError::IncompatibleShutdownScript { script }) = nodes[0].node.close_channel_with_params(&chan.2, Some(Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script()), None) {
+		assert_eq!(script, Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script());
+	} else { panic!(); }
+	if let Err(APIError::APIMisuseError { err }) = nodes[0].node.close_channel_with_params(&chan.2, None, Some(msgs::ClosingSignedFeeRange { min_fee_satoshis: 2001, max_fee_satoshis: 2000 })) {
+		assert_eq!(err, "Closing fee range minimum (2001) is greater than its maximum (2000)");
+	} else { panic!(); }
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
 
-	// We test config.our_to_self > BREAKDOWN_TIMEOUT is enforced in Channel::new_outbound()
-	let keys_manager: Arc<KeysInterface<ChanKeySigner = EnforcingChannelKeys>> = Arc::new(test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet));
-	if let Err(error) = Channel::new_outbound(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), 1000000, 1000000, 0, &low_our_to_self_config) {
-		match error {
-			APIError::APIMisuseError { err } => { assert!(regex::Regex::new(r"Configured with an unreasonable our_to_self_delay \(\d+\) putting user funds at risks").unwrap().is_match(err.as_str())); },
-			_ => panic!("Unexpected event"),
-		}
-	} else { assert!(false) }
-
-	// We test config.our_to_self > BREAKDOWN_TIMEOUT is enforced in Channel::new_from_req()
-	nodes[1].node.create_channel(nodes[0].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
-	let mut open_channel = get_event_msg!(nodes[1], MessageSendEvent::SendOpenChannel, nodes[0].node.get_our_node_id());
-	open_channel.to_self_delay = 200;
-	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &low_our_to_self_config) {
-		match error {
-			ChannelError::Close(err) => { assert!(regex::Regex::new(r"Configured with an unreasonable our_to_self_delay \(\d+\) putting user funds at risks").unwrap().is_match(err.as_str()));  },
-			_ => panic!("Unexpected event"),
-		}
-	} else { assert!(false); }
+	nodes[0].node.close_channel_with_params(&chan.2, Some(shutdown_script.clone()), Some(fee_range.clone())).unwrap();
+	let node_0_shutdown = get_event_msg!(nodes[0], MessageSendEvent::SendShutdown, nodes[1].node.get_our_node_id());
+	assert_eq!(node_0_shutdown.scriptpubkey, shutdown_script);
+	nodes[1].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
+	let node_1_shutdown = get_event_msg!(nodes[1], MessageSendEvent::SendShutdown, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_shutdown(&nodes[1].node.get_our_node_id(), &node_1_shutdown);
 
-	// We test msg.to_self_delay <= config.their_to_self_delay is enforced in Chanel::accept_channel()
-	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
-	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id()));
-	let mut accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
-	accept_channel.to_self_delay = 200;
-	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_channel);
-	if let MessageSendEvent::HandleError { ref action, .. } = nodes[0].node.get_and_clear_pending_msg_events()[0] {
-		match action {
-			&ErrorAction::SendErrorMessage { ref msg } => {
-				assert!(regex::Regex::new(r"They wanted our payments to be delayed by a needlessly long period\. Upper limit: \d+\. Actual: \d+").unwrap().is_match(msg.data.as_str()));
-			},
-			_ => { assert!(false); }
-		}
-	} else { assert!(false); }
+	let node_0_closing_signed = get_event_msg!(nodes[0], MessageSendEvent::SendClosingSigned, nodes[1].node.get_our_node_id());
+	assert_eq!(node_0_closing_signed.fee_satoshis, 2000);
+	assert_eq!(node_0_closing_signed.fee_range, Some(fee_range));
+	nodes[1].node.handle_closing_signed(&nodes[0].node.get_our_node_id(), &node_0_closing_signed);
+	let (_, node_1_closing_signed) = get_closing_signed_broadcast!(nodes[1].node, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
+	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
+	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
-	// We test msg.to_self_delay <= config.their_to_self_delay is enforced in Channel::new_from_req()
-	nodes[1].node.create_channel(nodes[0].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
-	let mut open_channel = get_event_msg!(nodes[1], MessageSendEvent::SendOpenChannel, nodes[0].node.get_our_node_id());
-	open_channel.to_self_delay = 200;
-	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &high_their_to_self_config) {
-		match error {
-			ChannelError::Close(err) => { assert!(regex::Regex::new(r"They wanted our payments to be delayed by a needlessly long period\. Upper limit: \d+\. Actual: \d+").unwrap().is_match(err.as_str())); },
-			_ => panic!("Unexpected event"),
-		}
-	} else { assert!(false); }
+	let closing_tx = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(closing_tx.len(), 1);
+	assert_eq!(closing_tx, nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0));
+	check_spends!(closing_tx[0], chan.3);
+	assert_eq!(closing_tx[0].output.len(), 1);
+	assert_eq!(closing_tx[0].output[0].script_pubkey, shutdown_script);
+	assert_eq!(closing_tx[0].output[0].value, 100000 - 2000);
 }
 
 #[test]
-fn test_data_loss_protect() {
-	// We want to be sure that :
-	// * we don't broadcast our Local Commitment Tx in case of fallen behind
-	// * we close channel in case of detecting other being fallen behind
-	// * we are able to claim our own outputs thanks to to_remote being static
-	let keys_manager;
-	let logger;
-	let fee_estimator;
-	let tx_broadcaster;
-	let chain_monitor;
-	let monitor;
-	let node_state_0;
+fn test_close_channel_fee_range_mismatch() {
+	// If the fundee asks for a closing fee range the funder's range doesn't overlap with, the
+	// fundee gives up on the cooperative close as soon as it sees the funder's fee_range.
 	let chanmon_cfgs = create_chanmon_cfgs(2);
 	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
-	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 
-	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
+	nodes[1].node.close_channel_with_params(&chan.2, None, Some(msgs::ClosingSignedFeeRange { min_fee_satoshis: 5000, max_fee_satoshis: 6000 })).unwrap();
+	let node_1_shutdown = get_event_msg!(nodes[1], MessageSendEvent::SendShutdown, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_shutdown(&nodes[1].node.get_our_node_id(), &node_1_shutdown);
+	let events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	let node_0_shutdown = match events[0] {
+		MessageSendEvent::SendShutdown { ref msg, .. } => msg.clone(),
+		_ => panic!("Unexpected event"),
+	};
+	let node_0_closing_signed = match events[1] {
+		MessageSendEvent::SendClosingSigned { ref msg, .. } => msg.clone(),
+		_ => panic!("Unexpected event"),
+	};
+	assert!(node_0_closing_signed.fee_range.as_ref().unwrap().max_fee_satoshis < 5000);
 
-	// Cache node A state before any channel update
+	nodes[1].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
+	nodes[1].node.handle_closing_signed(&nodes[0].node.get_our_node_id(), &node_0_closing_signed);
+	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	assert!(regex::Regex::new(r"Unable to come to consensus about closing fee, remote's fee_range \(\d+-\d+\) doesn't overlap with ours \(5000-6000\)").unwrap().is_match(err_msg.data.as_str()));
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: err_msg.data });
+	check_added_monitors!(nodes[1], 1);
+	assert!(nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().iter().all(|tx| tx.input[0].previous_output.txid == chan.3.txid() && tx.input[0].sequence != 0xffffffff));
+}
+
+#[test]
+fn test_close_channel_anysegwit_script() {
+	// Segwit v1+ shutdown scripts may only be sent to peers which signal option_shutdown_anysegwit.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	config.channel_options.commit_upfront_shutdown_pubkey = false;
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[Some(config), None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[0].node.peer_connected(&nodes[2].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known().clear_shutdown_anysegwit() });
+	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let chan_2 = create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known().clear_shutdown_anysegwit());
+
+	let v1_script = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_1).push_slice(&[42; 32]).into_script();
+	if let Err(APIError::IncompatibleShutdownScript { script }) = nodes[0].node.close_channel_with_params(&chan_2.2, Some(v1_script.clone()), None) {
+		assert_eq!(script, v1_script);
+	} else { panic!(); }
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[0].node.close_channel_with_params(&chan_1.2, Some(v1_script.clone()), None).unwrap();
+	let node_0_shutdown = get_event_msg!(nodes[0], MessageSendEvent::SendShutdown, nodes[1].node.get_our_node_id());
+	assert_eq!(node_0_shutdown.scriptpubkey, v1_script);
+	nodes[1].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
+	let node_1_shutdown = get_event_msg!(nodes[1], MessageSendEvent::SendShutdown, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_shutdown(&nodes[1].node.get_our_node_id(), &node_1_shutdown);
+	let node_0_closing_signed = get_event_msg!(nodes[0], MessageSendEvent::SendClosingSigned, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_closing_signed(&nodes[0].node.get_our_node_id(), &node_0_closing_signed);
+	let (_, node_1_closing_signed) = get_closing_signed_broadcast!(nodes[1].node, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
+	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
+	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
+
+	let closing_tx = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(closing_tx.len(), 1);
+	check_spends!(closing_tx[0], chan_1.3);
+	assert!(closing_tx[0].output.iter().any(|outp| outp.script_pubkey == v1_script));
+}
+
+/// Delivers channel establishment, splice and interactive transaction construction messages between
+/// the first two nodes until neither has any left to send.
+fn deliver_interactive_tx_messages<'a, 'b, 'c>(nodes: &Vec<Node<'a, 'b, 'c>>) {
+	loop {
+		let mut delivered = false;
+		for (src, dst) in [(0, 1), (1, 0)].iter() {
+			let src_id = nodes[*src].node.get_our_node_id();
+			for event in nodes[*src].node.get_and_clear_pending_msg_events() {
+				delivered = true;
+				let node = &nodes[*dst].node;
+				match event {
+					MessageSendEvent::SendOpenChannelV2 { ref msg, .. } => node.handle_open_channel_v2(&src_id, InitFeatures::known(), msg),
+					MessageSendEvent::SendAcceptChannelV2 { ref msg, .. } => node.handle_accept_channel_v2(&src_id, InitFeatures::known(), msg),
+					MessageSendEvent::SendStfu { ref msg, .. } => node.handle_stfu(&src_id, msg),
+					MessageSendEvent::SendSpliceInit { ref msg, .. } => node.handle_splice_init(&src_id, msg),
+					MessageSendEvent::SendSpliceAck { ref msg, .. } => node.handle_splice_ack(&src_id, msg),
+					MessageSendEvent::SendTxAddInput { ref msg, .. } => node.handle_tx_add_input(&src_id, msg),
+					MessageSendEvent::SendTxAddOutput { ref msg, .. } => node.handle_tx_add_output(&src_id, msg),
+					MessageSendEvent::SendTxComplete { ref msg, .. } => node.handle_tx_complete(&src_id, msg),
+					MessageSendEvent::SendTxSignatures { ref msg, .. } => node.handle_tx_signatures(&src_id, msg),
+					MessageSendEvent::SendSpliceLocked { ref msg, .. } => node.handle_splice_locked(&src_id, msg),
+					MessageSendEvent::UpdateHTLCs { ref updates, .. } => {
+						assert!(updates.update_add_htlcs.is_empty() && updates.update_fulfill_htlcs.is_empty());
+						assert!(updates.update_fail_htlcs.is_empty() && updates.update_fee.is_none());
+						node.handle_commitment_signed(&src_id, &updates.commitment_signed);
+						check_added_monitors!(nodes[*dst], 1);
+					},
+					MessageSendEvent::SendFundingLocked { ref msg, .. } => node.handle_funding_locked(&src_id, msg),
+					MessageSendEvent::SendAnnouncementSignatures { ref msg, .. } => node.handle_announcement_signatures(&src_id, msg),
+					MessageSendEvent::BroadcastChannelAnnouncement { .. } => {},
+					MessageSendEvent::BroadcastChannelUpdate { .. } => {},
+					_ => panic!("Unexpected event"),
+				}
+			}
+		}
+		if !delivered { break; }
+	}
+}
+
+/// Confirms the given splice transaction on the first two nodes at the given height, followed by enough
+/// blocks to reach the default minimum_depth.
+fn confirm_splice_transaction<'a, 'b, 'c>(nodes: &Vec<Node<'a, 'b, 'c>>, splice_tx: &Transaction, height: u32) {
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	for node in nodes[..2].iter() {
+		assert!(node.chain_monitor.does_match_tx(splice_tx));
+		node.block_notifier.block_connected_checked(&header, height, &[splice_tx], &[1]);
+		connect_blocks(&node.block_notifier, 5, height, true, header.block_hash());
+	}
+}
+
+#[test]
+fn test_splice_in() {
+	// Splice 20000 sat from a P2WPKH wallet output into a channel, checking that the channel
+	// moves to the new funding output once the splice transaction reaches minimum_depth.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 0, InitFeatures::known(), InitFeatures::known());
+	let prev_short_channel_id = nodes[0].node.list_channels()[0].short_channel_id.unwrap();
+
+	let secp_ctx = Secp256k1::new();
+	let wallet_key = SecretKey::from_slice(&[42; 32]).unwrap();
+	let wallet_pubkey = PublicKey::from_secret_key(&secp_ctx, &wallet_key);
+	let wallet_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&WPubkeyHash::hash(&wallet_pubkey.serialize())[..]).into_script();
+	let prev_tx = Transaction { version: 2, lock_time: 0, input: Vec::new(), output: vec![TxOut { value: 50000, script_pubkey: wallet_script.clone() }] };
+
+	// The wallet inputs must cover the spliced in value and the fee.
+	if let Err(APIError::APIMisuseError { err }) = nodes[0].node.splice_channel(&chan.2, SpliceContribution::SpliceIn { value_satoshis: 60000, inputs: vec![(prev_tx.clone(), 0)], change_script: wallet_script.clone() }, 253) {
+		assert!(err.starts_with("Inputs totalling"), "{}", err);
+	} else { panic!(); }
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[0].node.splice_channel(&chan.2, SpliceContribution::SpliceIn { value_satoshis: 20000, inputs: vec![(prev_tx.clone(), 0)], change_script: wallet_script.clone() }, 253).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let mut splice_tx = match events[0] {
+		Event::SpliceTransactionReadyForSigning { ref channel_id, ref unsigned_transaction, .. } => {
+			assert_eq!(*channel_id, chan.2);
+			unsigned_transaction.clone()
+		},
+		_ => panic!("Unexpected event"),
+	};
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+
+	let wallet_input = splice_tx.input.iter().position(|input| input.previous_output.txid == prev_tx.txid()).unwrap();
+	let script_code = Address::p2pkh(&::bitcoin::PublicKey { compressed: true, key: wallet_pubkey }, Network::Testnet).script_pubkey();
+	let sighash = Message::from_slice(&bip143::SigHashCache::new(&splice_tx).signature_hash(wallet_input, &script_code, 50000, SigHashType::All)[..]).unwrap();
+	let mut sig = secp_ctx.sign(&sighash, &wallet_key).serialize_der().to_vec();
+	sig.push(SigHashType::All as u8);
+	splice_tx.input[wallet_input].witness = vec![sig, wallet_pubkey.serialize().to_vec()];
+	nodes[0].node.splice_transaction_signed(&chan.2, splice_tx.clone()).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+
+	let splice_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(splice_txn.len(), 1);
+	assert_eq!(splice_txn, nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0));
+	check_spends!(splice_txn[0], chan.3, prev_tx);
+	assert!(splice_txn[0].output.iter().any(|outp| outp.value == 120000));
+
+	confirm_splice_transaction(&nodes, &splice_txn[0], CHAN_CONFIRM_DEPTH);
+	deliver_interactive_tx_messages(&nodes);
+
+	for node in nodes.iter() {
+		let channels = node.node.list_channels();
+		assert_eq!(channels.len(), 1);
+		assert_eq!(channels[0].channel_value_satoshis, 120000);
+		assert_ne!(channels[0].short_channel_id, Some(prev_short_channel_id));
+	}
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	assert!(nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_splice_out() {
+	// Splice 30000 sat out of a channel to an external output, paying the splice transaction's
+	// fee from the channel balance.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[Some(config), None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[0].node.peer_connected(&nodes[2].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known().clear_splicing() });
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 0, InitFeatures::known(), InitFeatures::known());
+	let chan_2 = create_announced_chan_between_nodes_with_value(&nodes, 0, 2, 100000, 0, InitFeatures::known(), InitFeatures::known().clear_splicing());
+
+	// Splicing requires the counterparty to signal support for it.
+	let splice_out = TxOut { value: 30000, script_pubkey: Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[42; 20]).into_script() };
+	if let Err(APIError::ChannelUnavailable { err }) = nodes[0].node.splice_channel(&chan_2.2, SpliceContribution::SpliceOut { outputs: vec![splice_out.clone()] }, 253) {
+		assert_eq!(err, "Our counterparty does not support splicing");
+	} else { panic!(); }
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[0].node.splice_channel(&chan.2, SpliceContribution::SpliceOut { outputs: vec![splice_out.clone()] }, 253).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+	// With only the shared input to sign there's nothing for the user to do.
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+
+	let splice_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(splice_txn.len(), 1);
+	assert_eq!(splice_txn, nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0));
+	check_spends!(splice_txn[0], chan.3);
+	assert_eq!(splice_txn[0].output.len(), 2);
+	assert!(splice_txn[0].output.contains(&splice_out));
+	let new_value = splice_txn[0].output.iter().find(|outp| **outp != splice_out).unwrap().value;
+	assert!(new_value < 70000 && new_value > 69000);
+
+	confirm_splice_transaction(&nodes, &splice_txn[0], CHAN_CONFIRM_DEPTH);
+	deliver_interactive_tx_messages(&nodes);
+	for node in nodes[..2].iter() {
+		let channels = node.node.list_channels();
+		assert_eq!(channels.iter().find(|channel| channel.channel_id == chan.2).unwrap().channel_value_satoshis, new_value);
+	}
+
+	// The channel is usable again, and its monitors follow the new funding output.
+	let logger = test_utils::TestLogger::new();
+	let first_hops = nodes[0].node.list_usable_channels();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(),
+		&nodes[1].node.get_our_node_id(), None, Some(&first_hops.iter().filter(|channel| channel.channel_id == chan.2).collect::<Vec<_>>()),
+		&Vec::new(), 5000000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	let payment_preimage = send_along_route(&nodes[0], route, &[&nodes[1]], 5000000).0;
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 5_000_000);
+	let local_txn = get_local_commitment_txn!(nodes[0], chan.2);
+	check_spends!(local_txn[0], splice_txn[0]);
+}
+
+/// Splices 30000 sat out of a fresh channel between the first two nodes, returning the channel and
+/// the broadcast splice transaction.
+fn splice_out_channel<'a, 'b, 'c>(nodes: &Vec<Node<'a, 'b, 'c>>) -> ((msgs::ChannelUpdate, msgs::ChannelUpdate, [u8; 32], Transaction), Transaction) {
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 0, InitFeatures::known(), InitFeatures::known());
+
+	let splice_out = TxOut { value: 30000, script_pubkey: Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[42; 20]).into_script() };
+	nodes[0].node.splice_channel(&chan.2, SpliceContribution::SpliceOut { outputs: vec![splice_out] }, 253).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+
+	let splice_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(splice_txn.len(), 1);
+	nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+	(chan, splice_txn[0].clone())
+}
+
+#[test]
+fn test_splice_monitor_reorg() {
+	// A ChannelMonitor must not move over to the splice's funding output until the splice
+	// transaction has ANTI_REORG_DELAY confirmations, as until then it may be reorganized out and
+	// our current funding output spent instead.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let (chan, splice_tx) = splice_out_channel(&nodes);
+
+	let mut headers = Vec::new();
+	let mut header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[0].block_notifier.block_connected_checked(&header, CHAN_CONFIRM_DEPTH, &[&splice_tx], &[1]);
+	headers.push(header);
+	for i in 1..ANTI_REORG_DELAY - 1 {
+		header = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+		nodes[0].block_notifier.block_connected_checked(&header, CHAN_CONFIRM_DEPTH + i, &[], &[]);
+		headers.push(header);
+	}
+	check_spends!(get_local_commitment_txn!(nodes[0], chan.2)[0], chan.3);
+
+	// Reorg the splice transaction out and extend the new chain past ANTI_REORG_DELAY.
+	for i in (0..ANTI_REORG_DELAY - 1).rev() {
+		nodes[0].block_notifier.block_disconnected(&headers.pop().unwrap(), CHAN_CONFIRM_DEPTH + i);
+	}
+	connect_blocks(&nodes[0].block_notifier, ANTI_REORG_DELAY, CHAN_CONFIRM_DEPTH - 1, false, Default::default());
+	check_spends!(get_local_commitment_txn!(nodes[0], chan.2)[0], chan.3);
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	// Once the splice transaction does reach ANTI_REORG_DELAY confirmations we move over to it.
+	header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 43, bits: 42, nonce: 42 };
+	let height = CHAN_CONFIRM_DEPTH + ANTI_REORG_DELAY;
+	nodes[0].block_notifier.block_connected_checked(&header, height, &[&splice_tx], &[1]);
+	connect_blocks(&nodes[0].block_notifier, ANTI_REORG_DELAY - 1, height, true, header.block_hash());
+	check_spends!(get_local_commitment_txn!(nodes[0], chan.2)[0], splice_tx);
+	let events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::SendSpliceLocked { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_splice_counterparty_commitment_tx() {
+	// If our counterparty broadcasts its commitment transaction spending the splice's funding
+	// output, our ChannelMonitor must recognize it as a counterparty commitment transaction.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let (chan, splice_tx) = splice_out_channel(&nodes);
+
+	confirm_splice_transaction(&nodes, &splice_tx, CHAN_CONFIRM_DEPTH);
+	deliver_interactive_tx_messages(&nodes);
+
+	let counterparty_txn = get_local_commitment_txn!(nodes[1], chan.2);
+	check_spends!(counterparty_txn[0], splice_tx);
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 43, bits: 42, nonce: 42 };
+	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![counterparty_txn[0].clone()] }, CHAN_CONFIRM_DEPTH + ANTI_REORG_DELAY);
+	check_closed_broadcast!(nodes[0], false);
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
+	{
+		let monitors = nodes[0].chan_monitor.simple_monitor.monitors.lock().unwrap();
+		let monitor = monitors.values().find(|monitor| monitor.get_funding_txo().0.to_channel_id() == chan.2).unwrap();
+		assert!(monitor.get_outputs_to_watch().contains_key(&counterparty_txn[0].txid()));
+	}
+	nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+}
+
+#[test]
+fn test_splice_payments_while_locking() {
+	// Once both sides have sent tx_signatures the channel is no longer quiescent, with every
+	// commitment transaction signed for the splice's funding output too until it locks.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let (chan, splice_tx) = splice_out_channel(&nodes);
+
+	send_payment(&nodes[0], &[&nodes[1]], 5_000_000, 5_000_000);
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1]], 3_000_000).0;
+
+	// Our counterparty can't splice out more than it has, so the HTLC has to be affordable on
+	// both funding outputs.
+	let splice_value = splice_tx.output.iter().map(|outp| outp.value).max().unwrap();
+	for channel in nodes[0].node.list_channels() {
+		assert!(channel.outbound_capacity_msat / 1000 > splice_value);
+	}
+
+	confirm_splice_transaction(&nodes, &splice_tx, CHAN_CONFIRM_DEPTH);
+	deliver_interactive_tx_messages(&nodes);
+	for node in nodes.iter() {
+		assert_eq!(node.node.list_channels()[0].channel_value_satoshis, splice_value);
+	}
+
+	// The HTLC which was pending when the splice locked is carried over to the new funding output,
+	// in both the Channel and the ChannelMonitor.
+	let local_txn = get_local_commitment_txn!(nodes[0], chan.2);
+	check_spends!(local_txn[0], splice_tx);
+	assert_eq!(local_txn[0].output.len(), 3);
+	assert!(local_txn[0].output.iter().any(|outp| outp.value == 5_000));
+	assert!(local_txn[0].output.iter().any(|outp| outp.value == 3_000));
+	nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 3_000_000);
+	send_payment(&nodes[0], &[&nodes[1]], 2_000_000, 2_000_000);
+	let local_txn = get_local_commitment_txn!(nodes[1], chan.2);
+	check_spends!(local_txn[0], splice_tx);
+	assert!(local_txn[0].output.iter().any(|outp| outp.value == 10_000));
+}
+
+#[test]
+fn test_splice_tx_signatures_retransmission() {
+	// If our tx_signatures for a splice are lost in a disconnection we retransmit them on
+	// reconnection, as our counterparty still needs them to complete the splice transaction.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 0, InitFeatures::known(), InitFeatures::known());
+
+	let splice_out = TxOut { value: 30000, script_pubkey: Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[42; 20]).into_script() };
+	nodes[0].node.splice_channel(&chan.2, SpliceContribution::SpliceOut { outputs: vec![splice_out] }, 253).unwrap();
+
+	// Deliver everything up to the first tx_signatures, which is lost.
+	let mut lost_tx_signatures = false;
+	while !lost_tx_signatures {
+		let mut delivered = false;
+		for (src, dst) in [(0, 1), (1, 0)].iter() {
+			let src_id = nodes[*src].node.get_our_node_id();
+			for event in nodes[*src].node.get_and_clear_pending_msg_events() {
+				delivered = true;
+				let node = &nodes[*dst].node;
+				match event {
+					MessageSendEvent::SendStfu { ref msg, .. } => node.handle_stfu(&src_id, msg),
+					MessageSendEvent::SendSpliceInit { ref msg, .. } => node.handle_splice_init(&src_id, msg),
+					MessageSendEvent::SendSpliceAck { ref msg, .. } => node.handle_splice_ack(&src_id, msg),
+					MessageSendEvent::SendTxAddInput { ref msg, .. } => node.handle_tx_add_input(&src_id, msg),
+					MessageSendEvent::SendTxAddOutput { ref msg, .. } => node.handle_tx_add_output(&src_id, msg),
+					MessageSendEvent::SendTxComplete { ref msg, .. } => node.handle_tx_complete(&src_id, msg),
+					MessageSendEvent::SendTxSignatures { .. } => lost_tx_signatures = true,
+					MessageSendEvent::UpdateHTLCs { ref updates, .. } => {
+						node.handle_commitment_signed(&src_id, &updates.commitment_signed);
+						check_added_monitors!(nodes[*dst], 1);
+					},
+					_ => panic!("Unexpected event"),
+				}
+			}
+		}
+		assert!(delivered);
+	}
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	// The channel stays quiescent until the splice transaction has been signed by both sides.
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(),
+		&nodes[1].node.get_our_node_id(), None, None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &test_utils::TestLogger::new(), &test_utils::TestScorer::new()).unwrap();
+	let (_, payment_hash) = get_payment_preimage_hash!(nodes[1]);
+	unwrap_send_err!(nodes[0].node.send_payment(&route, payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+		assert!(err.contains("quiescent")));
+
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+	nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id(), false);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let reestablish_1 = get_chan_reestablish_msgs!(nodes[0], nodes[1]);
+	let reestablish_2 = get_chan_reestablish_msgs!(nodes[1], nodes[0]);
+	nodes[1].node.handle_channel_reestablish(&nodes[0].node.get_our_node_id(), &reestablish_1[0]);
+	nodes[0].node.handle_channel_reestablish(&nodes[1].node.get_our_node_id(), &reestablish_2[0]);
+	deliver_interactive_tx_messages(&nodes);
+
+	let splice_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(splice_txn.len(), 1);
+	assert_eq!(splice_txn, nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0));
+	check_spends!(splice_txn[0], chan.3);
+
+	// With the splice transaction signed the channel is usable again before it locks.
+	send_payment(&nodes[0], &[&nodes[1]], 1_000_000, 1_000_000);
+}
+
+/// Signs the P2WPKH input spending prev_tx's first output in the given transaction with the given
+/// wallet key.
+fn sign_wallet_input(tx: &mut Transaction, prev_tx: &Transaction, wallet_key: &SecretKey) {
+	let secp_ctx = Secp256k1::new();
+	let wallet_pubkey = PublicKey::from_secret_key(&secp_ctx, wallet_key);
+	let wallet_input = tx.input.iter().position(|input| input.previous_output.txid == prev_tx.txid()).unwrap();
+	let script_code = Address::p2pkh(&::bitcoin::PublicKey { compressed: true, key: wallet_pubkey }, Network::Testnet).script_pubkey();
+	let sighash = Message::from_slice(&bip143::SigHashCache::new(&*tx).signature_hash(wallet_input, &script_code, prev_tx.output[0].value, SigHashType::All)[..]).unwrap();
+	let mut sig = secp_ctx.sign(&sighash, wallet_key).serialize_der().to_vec();
+	sig.push(SigHashType::All as u8);
+	tx.input[wallet_input].witness = vec![sig, wallet_pubkey.serialize().to_vec()];
+}
+
+fn wallet_output(wallet_key: &SecretKey, value: u64) -> (Script, Transaction) {
+	let wallet_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), wallet_key);
+	let wallet_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&WPubkeyHash::hash(&wallet_pubkey.serialize())[..]).into_script();
+	let prev_tx = Transaction { version: 2, lock_time: 0, input: Vec::new(), output: vec![TxOut { value, script_pubkey: wallet_script.clone() }] };
+	(wallet_script, prev_tx)
+}
+
+#[test]
+fn test_dual_funded_channel() {
+	// Open a channel to which both sides contribute funds from their own P2WPKH wallet outputs,
+	// then check it can be announced and used like any other channel.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[Some(config), None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[0].node.peer_connected(&nodes[2].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known().clear_dual_fund() });
+
+	let node_0_key = SecretKey::from_slice(&[42; 32]).unwrap();
+	let (node_0_script, node_0_prev_tx) = wallet_output(&node_0_key, 150000);
+	let node_1_key = SecretKey::from_slice(&[43; 32]).unwrap();
+	let (node_1_script, node_1_prev_tx) = wallet_output(&node_1_key, 80000);
+	let node_0_contribution = FundingContribution { value_satoshis: 100000, inputs: vec![(node_0_prev_tx.clone(), 0)], change_script: node_0_script.clone() };
+
+	// Dual-funded channels require the counterparty to signal support for them.
+	if let Err(APIError::APIMisuseError { err }) = nodes[0].node.create_dual_funded_channel(nodes[2].node.get_our_node_id(), node_0_contribution.clone(), 253, 42, None) {
+		assert_eq!(err, "Our counterparty is not connected or does not support dual-funded channels");
+	} else { panic!(); }
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), node_0_contribution, 253, 42, None).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let temporary_channel_id = match events[0] {
+		Event::OpenChannelRequest { ref temporary_channel_id, ref counterparty_node_id, counterparty_funding_satoshis, funding_feerate_per_kw } => {
+			assert_eq!(*counterparty_node_id, nodes[0].node.get_our_node_id());
+			assert_eq!(counterparty_funding_satoshis, 100000);
+			assert_eq!(funding_feerate_per_kw, 253);
+			*temporary_channel_id
+		},
+		_ => panic!("Unexpected event"),
+	};
+	nodes[1].node.accept_dual_funded_channel(&temporary_channel_id, Some(FundingContribution { value_satoshis: 50000, inputs: vec![(node_1_prev_tx.clone(), 0)], change_script: node_1_script.clone() }), 43).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+
+	let mut signed_txn = Vec::new();
+	for (node, (prev_tx, key), user_channel_id) in [(&nodes[0], (&node_0_prev_tx, &node_0_key), 42), (&nodes[1], (&node_1_prev_tx, &node_1_key), 43)].iter() {
+		let events = node.node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::FundingTransactionReadyForSigning { ref channel_id, user_channel_id: ref event_user_channel_id, ref unsigned_transaction } => {
+				assert_eq!(*event_user_channel_id, *user_channel_id);
+				let mut tx = unsigned_transaction.clone();
+				sign_wallet_input(&mut tx, prev_tx, key);
+				signed_txn.push((*channel_id, tx));
+			},
+			_ => panic!("Unexpected event"),
+		}
+		assert!(node.tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	}
+	assert_eq!(signed_txn[0].0, signed_txn[1].0);
+	let channel_id = signed_txn[0].0;
+	nodes[0].node.funding_transaction_signed(&channel_id, signed_txn[0].1.clone()).unwrap();
+	nodes[1].node.funding_transaction_signed(&channel_id, signed_txn[1].1.clone()).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+
+	let funding_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(funding_txn.len(), 1);
+	assert_eq!(funding_txn, nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0));
+	check_spends!(funding_txn[0], node_0_prev_tx, node_1_prev_tx);
+	assert!(funding_txn[0].output.iter().any(|outp| outp.value == 150000));
+
+	let funding_msgs = create_chan_between_nodes_with_value_confirm(&nodes[0], &nodes[1], &funding_txn[0]);
+	assert_eq!(funding_msgs.1, channel_id);
+	let (announcement, as_update, bs_update) = create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &funding_msgs.0);
+	for node in nodes.iter() {
+		assert!(node.net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+		node.net_graph_msg_handler.handle_channel_update(&as_update).unwrap();
+		node.net_graph_msg_handler.handle_channel_update(&bs_update).unwrap();
+	}
+
+	for node in nodes[..2].iter() {
+		let channels = node.node.list_usable_channels();
+		assert_eq!(channels.len(), 1);
+		assert_eq!(channels[0].channel_id, channel_id);
+		assert_eq!(channels[0].channel_value_satoshis, 150000);
+	}
+	// Both sides have funds to send over the channel.
+	send_payment(&nodes[0], &[&nodes[1]], 5000000, 5_000_000);
+	send_payment(&nodes[1], &[&nodes[0]], 3000000, 3_000_000);
+	// Unlike the channel_id, monitors are still keyed by the funding outpoint.
+	let funding_output = funding_txn[0].output.iter().position(|outp| outp.value == 150000).unwrap();
+	let local_txn = get_local_commitment_txn!(nodes[0], OutPoint { txid: funding_txn[0].txid(), index: funding_output as u16 }.to_channel_id());
+	check_spends!(local_txn[0], funding_txn[0]);
+}
+
+#[test]
+fn test_dual_funded_channel_reject() {
+	// Reject a dual-funded channel our counterparty proposed, checking it's forgotten on both sides.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let (wallet_script, prev_tx) = wallet_output(&SecretKey::from_slice(&[42; 32]).unwrap(), 150000);
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), FundingContribution { value_satoshis: 100000, inputs: vec![(prev_tx, 0)], change_script: wallet_script }, 253, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel_v2(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let temporary_channel_id = match events[0] {
+		Event::OpenChannelRequest { ref temporary_channel_id, .. } => *temporary_channel_id,
+		_ => panic!("Unexpected event"),
+	};
+	assert_eq!(nodes[1].node.list_channels().len(), 1);
+
+	nodes[1].node.reject_dual_funded_channel(&temporary_channel_id).unwrap();
+	assert!(nodes[1].node.list_channels().is_empty());
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::HandleError { action: msgs::ErrorAction::SendErrorMessage { ref msg }, .. } => {
+			assert_eq!(msg.channel_id, temporary_channel_id);
+			nodes[0].node.handle_error(&nodes[1].node.get_our_node_id(), msg);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	assert!(nodes[0].node.list_channels().is_empty());
+	check_closed_event!(nodes[0], 1, ClosureReason::CounterpartyForceClosed { peer_msg: "Channel rejected".to_owned() });
+	if let Err(APIError::ChannelUnavailable { err }) = nodes[1].node.accept_dual_funded_channel(&temporary_channel_id, None, 43) {
+		assert_eq!(err, "No such channel");
+	} else { panic!(); }
+}
+
+#[test]
+fn test_discard_funding() {
+	// Check that a channel which is closed after funding_transaction_generated but before
+	// funding_signed, whether by us or because our peer disconnected, generates a DiscardFunding
+	// event, whereas one closed after its funding transaction may have been broadcast doesn't.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	for disconnect in [false, true].iter() {
+		nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 0, 42, None).unwrap();
+		nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id()));
+		nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id()));
+		let (temporary_channel_id, _, funding_output) = create_funding_transaction(&nodes[0], 100000, 42);
+		nodes[0].node.funding_transaction_generated(&temporary_channel_id, funding_output);
+		get_event_msg!(nodes[0], MessageSendEvent::SendFundingCreated, nodes[1].node.get_our_node_id());
+
+		if *disconnect {
+			nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+		} else {
+			nodes[0].node.force_close_channel(&funding_output.to_channel_id());
+		}
+		assert!(nodes[0].node.list_channels().is_empty());
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 2);
+		match events[0] {
+			Event::ChannelClosed { ref channel_id, user_channel_id, ref reason } => {
+				assert_eq!(*channel_id, funding_output.to_channel_id());
+				assert_eq!(user_channel_id, 42);
+				assert_eq!(*reason, if *disconnect { ClosureReason::DisconnectedPeer } else { ClosureReason::HolderForceClosed });
+			},
+			_ => panic!("Unexpected event"),
+		}
+		match events[1] {
+			Event::DiscardFunding { ref channel_id, user_channel_id, ref funding_txo } => {
+				assert_eq!(*channel_id, funding_output.to_channel_id());
+				assert_eq!(user_channel_id, 42);
+				assert_eq!(*funding_txo, funding_output);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		nodes[0].node.get_and_clear_pending_msg_events();
+
+		// Our counterparty never saw the funding transaction, so has nothing to discard.
+		nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id(), false);
+		assert!(nodes[1].node.list_channels().is_empty());
+		check_closed_event!(nodes[1], 1, ClosureReason::DisconnectedPeer);
+	}
+
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	nodes[0].node.force_close_channel(&chan.2);
+	check_added_monitors!(nodes[0], 1);
+	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+}
+
+#[test]
+fn test_dual_funded_channel_onchain_forward_claim() {
+	// Check that when a forwarded HTLC over a dual-funded channel is claimed on-chain, the
+	// PaymentForwarded event carries the dual-funded channel's id, which isn't derived from its
+	// funding outpoint.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[Some(config), None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let wallet_key = SecretKey::from_slice(&[42; 32]).unwrap();
+	let (wallet_script, prev_tx) = wallet_output(&wallet_key, 150000);
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), FundingContribution { value_satoshis: 100000, inputs: vec![(prev_tx.clone(), 0)], change_script: wallet_script }, 253, 42, None).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+	let temporary_channel_id = match nodes[1].node.get_and_clear_pending_events()[0] {
+		Event::OpenChannelRequest { ref temporary_channel_id, .. } => *temporary_channel_id,
+		_ => panic!("Unexpected event"),
+	};
+	nodes[1].node.accept_dual_funded_channel(&temporary_channel_id, None, 43).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+	let (channel_id, mut funding_tx) = match nodes[0].node.get_and_clear_pending_events()[0] {
+		Event::FundingTransactionReadyForSigning { ref channel_id, ref unsigned_transaction, .. } => (*channel_id, unsigned_transaction.clone()),
+		_ => panic!("Unexpected event"),
+	};
+	sign_wallet_input(&mut funding_tx, &prev_tx, &wallet_key);
+	nodes[0].node.funding_transaction_signed(&channel_id, funding_tx.clone()).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+	nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+
+	let funding_msgs = create_chan_between_nodes_with_value_confirm(&nodes[0], &nodes[1], &funding_tx);
+	assert_eq!(funding_msgs.1, channel_id);
+	let (announcement, as_update, bs_update) = create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &funding_msgs.0);
+	for node in nodes.iter() {
+		assert!(node.net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+		node.net_graph_msg_handler.handle_channel_update(&as_update).unwrap();
+		node.net_graph_msg_handler.handle_channel_update(&bs_update).unwrap();
+	}
+	let funding_outpoint = OutPoint { txid: funding_tx.txid(), index: funding_tx.output.iter().position(|outp| outp.value == 100000).unwrap() as u16 };
+	assert_ne!(funding_outpoint.to_channel_id(), channel_id);
+	let chan = create_announced_chan_between_nodes(&nodes, 2, 0, InitFeatures::known(), InitFeatures::known());
+
+	// Forward a payment from nodes[2] to nodes[1], which claims it on-chain with an HTLC-Success
+	// transaction spending its commitment transaction.
+	let (payment_preimage, _) = route_payment(&nodes[2], &[&nodes[0], &nodes[1]], 3000000);
+	let commitment_tx = get_local_commitment_txn!(nodes[1], funding_outpoint.to_channel_id());
+	assert_eq!(commitment_tx.len(), 1);
+	check_spends!(commitment_tx[0], funding_tx);
+	nodes[1].node.claim_funds(payment_preimage, &None, 3_000_000);
+	check_added_monitors!(nodes[1], 1);
+	get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone()] }, 1);
+	check_closed_broadcast!(nodes[1], false);
+	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
+	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
+	let htlc_success_tx = node_txn.iter().find(|tx| tx.input[0].previous_output.txid == commitment_tx[0].txid()).unwrap().clone();
+
+	// nodes[0] learns the preimage from the HTLC-Success transaction and claims backwards.
+	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![commitment_tx[0].clone(), htlc_success_tx] }, 1);
+	check_added_monitors!(nodes[0], 1);
+	let msg_events = nodes[0].node.get_and_clear_pending_msg_events();
+	check_added_monitors!(nodes[0], 1);
+	assert!(msg_events.iter().any(|event| match event {
+		MessageSendEvent::UpdateHTLCs { ref node_id, ref updates } => *node_id == nodes[2].node.get_our_node_id() && updates.update_fulfill_htlcs.len() == 1,
+		_ => false,
+	}));
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { channel_id: ref closed_channel_id, reason: ClosureReason::CommitmentTxConfirmed, .. } => assert_eq!(*closed_channel_id, channel_id),
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PaymentForwarded { ref prev_channel_id, ref next_channel_id, fee_earned_msat, claim_from_onchain_tx } => {
+			assert_eq!(*prev_channel_id, Some(chan.2));
+			assert_eq!(*next_channel_id, channel_id);
+			assert!(fee_earned_msat.is_some());
+			assert!(claim_from_onchain_tx);
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_dual_funded_channel_discard_funding() {
+	// Check that force-closing a dual-funded channel before we've signed its funding transaction
+	// generates a DiscardFunding event.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let (wallet_script, prev_tx) = wallet_output(&SecretKey::from_slice(&[42; 32]).unwrap(), 150000);
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), FundingContribution { value_satoshis: 100000, inputs: vec![(prev_tx, 0)], change_script: wallet_script }, 253, 42, None).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+	let temporary_channel_id = match nodes[1].node.get_and_clear_pending_events()[0] {
+		Event::OpenChannelRequest { ref temporary_channel_id, .. } => *temporary_channel_id,
+		_ => panic!("Unexpected event"),
+	};
+	nodes[1].node.accept_dual_funded_channel(&temporary_channel_id, None, 43).unwrap();
+	deliver_interactive_tx_messages(&nodes);
+
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let (channel_id, funding_tx) = match events[0] {
+		Event::FundingTransactionReadyForSigning { ref channel_id, ref unsigned_transaction, .. } => (*channel_id, unsigned_transaction.clone()),
+		_ => panic!("Unexpected event"),
+	};
+	nodes[0].node.force_close_channel(&channel_id);
+	check_added_monitors!(nodes[0], 1);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { reason: ClosureReason::HolderForceClosed, .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::DiscardFunding { channel_id: ref discarded_channel_id, user_channel_id, ref funding_txo } => {
+			assert_eq!(*discarded_channel_id, channel_id);
+			assert_eq!(user_channel_id, 42);
+			assert_eq!(funding_txo.txid, funding_tx.txid());
+		},
+		_ => panic!("Unexpected event"),
+	}
+	// Our counterparty contributed no inputs so sent its tx_signatures right away, and can't
+	// know whether we'll broadcast the funding transaction.
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+}
+
+#[test]
+fn test_user_configurable_csv_delay() {
+	// We test our channel constructors yield errors when we pass them absurd csv delay
+
+	let mut low_our_to_self_config = UserConfig::default();
+	low_our_to_self_config.own_channel_config.our_to_self_delay = 6;
+	let mut high_their_to_self_config = UserConfig::default();
+	high_their_to_self_config.peer_channel_config_limits.their_to_self_delay = 100;
+	let user_cfgs = [Some(high_their_to_self_config.clone()), None];
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &user_cfgs);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// We test config.our_to_self > BREAKDOWN_TIMEOUT is enforced in Channel::new_outbound()
+	let keys_manager: Arc<KeysInterface<ChanKeySigner = EnforcingChannelKeys>> = Arc::new(test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet));
+	if let Err(error) = Channel::new_outbound(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), 1000000, 1000000, 0, &low_our_to_self_config) {
+		match error {
+			APIError::APIMisuseError { err } => { assert!(regex::Regex::new(r"Configured with an unreasonable our_to_self_delay \(\d+\) putting user funds at risks").unwrap().is_match(err.as_str())); },
+			_ => panic!("Unexpected event"),
+		}
+	} else { assert!(false) }
+
+	// We test config.our_to_self > BREAKDOWN_TIMEOUT is enforced in Channel::new_from_req()
+	nodes[1].node.create_channel(nodes[0].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
+	let mut open_channel = get_event_msg!(nodes[1], MessageSendEvent::SendOpenChannel, nodes[0].node.get_our_node_id());
+	open_channel.to_self_delay = 200;
+	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &low_our_to_self_config) {
+		match error {
+			ChannelError::Close(err) => { assert!(regex::Regex::new(r"Configured with an unreasonable our_to_self_delay \(\d+\) putting user funds at risks").unwrap().is_match(err.as_str()));  },
+			_ => panic!("Unexpected event"),
+		}
+	} else { assert!(false); }
+
+	// We test msg.to_self_delay <= config.their_to_self_delay is enforced in Chanel::accept_channel()
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id()));
+	let mut accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+	accept_channel.to_self_delay = 200;
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_channel);
+	if let MessageSendEvent::HandleError { ref action, .. } = nodes[0].node.get_and_clear_pending_msg_events()[0] {
+		match action {
+			&ErrorAction::SendErrorMessage { ref msg } => {
+				assert!(regex::Regex::new(r"They wanted our payments to be delayed by a needlessly long period\. Upper limit: \d+\. Actual: \d+").unwrap().is_match(msg.data.as_str()));
+			},
+			_ => { assert!(false); }
+		}
+	} else { assert!(false); }
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "They wanted our payments to be delayed by a needlessly long period. Upper limit: 100. Actual: 200".to_owned() });
+
+	// We test msg.to_self_delay <= config.their_to_self_delay is enforced in Channel::new_from_req()
+	nodes[1].node.create_channel(nodes[0].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
+	let mut open_channel = get_event_msg!(nodes[1], MessageSendEvent::SendOpenChannel, nodes[0].node.get_our_node_id());
+	open_channel.to_self_delay = 200;
+	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &high_their_to_self_config) {
+		match error {
+			ChannelError::Close(err) => { assert!(regex::Regex::new(r"They wanted our payments to be delayed by a needlessly long period\. Upper limit: \d+\. Actual: \d+").unwrap().is_match(err.as_str())); },
+			_ => panic!("Unexpected event"),
+		}
+	} else { assert!(false); }
+}
+
+#[test]
+fn test_data_loss_protect() {
+	// We want to be sure that :
+	// * we don't broadcast our Local Commitment Tx in case of fallen behind
+	// * we close channel in case of detecting other being fallen behind
+	// * we are able to claim our own outputs thanks to to_remote being static
+	let keys_manager;
+	let logger;
+	let fee_estimator;
+	let tx_broadcaster;
+	let chain_monitor;
+	let monitor;
+	let node_state_0;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
+
+	// Cache node A state before any channel update
 	let previous_node_state = nodes[0].node.encode();
 	let mut previous_chan_monitor_state = test_utils::TestVecWriter(Vec::new());
 	nodes[0].chan_monitor.simple_monitor.monitors.lock().unwrap().iter().next().unwrap().1.write_for_disk(&mut previous_chan_monitor_state).unwrap();
@@ -7522,10 +8830,12 @@ fn test_data_loss_protect() {
 			panic!("Unexpected event")
 		}
 	}
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "We have fallen behind - we have received proof that if we broadcast remote is going to claim our funds - we can't do any automated broadcasting".to_owned() });
 
 	// Check we close channel detecting A is fallen-behind
 	nodes[1].node.handle_channel_reestablish(&nodes[0].node.get_our_node_id(), &reestablish_1[0]);
 	assert_eq!(check_closed_broadcast!(nodes[1], true).unwrap().data, "Peer attempted to reestablish channel with a very old local commitment transaction");
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Peer attempted to reestablish channel with a very old local commitment transaction".to_owned() });
 	check_added_monitors!(nodes[1], 1);
 
 
@@ -7666,7 +8976,7 @@ fn test_bump_penalty_txn_on_revoked_commitment() {
 
 	let payment_preimage = route_payment(&nodes[0], &vec!(&nodes[1])[..], 3000000).0;
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 3000000, 30, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &Vec::new(), 3000000, 30, &logger, &test_utils::TestScorer::new()).unwrap();
 	send_along_route(&nodes[1], route, &vec!(&nodes[0])[..], 3000000);
 
 	let revoked_txn = get_local_commitment_txn!(nodes[0], chan.2);
@@ -7784,6 +9094,7 @@ fn test_bump_penalty_txn_on_revoked_htlcs() {
 	nodes[1].block_notifier.block_connected(&Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 
 	let revoked_htlc_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(revoked_htlc_txn.len(), 4);
@@ -7806,7 +9117,16 @@ fn test_bump_penalty_txn_on_revoked_htlcs() {
 	// Broadcast set of revoked txn on A
 	let header_128 = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[0].block_notifier.block_connected(&Block { header: header_128, txdata: vec![revoked_local_txn[0].clone()] }, 128);
-	expect_pending_htlcs_forwardable_ignore!(nodes[0]);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PendingHTLCsForwardable { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
 	let header_129 = BlockHeader { version: 0x20000000, prev_blockhash: header_128.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	nodes[0].block_notifier.block_connected(&Block { header: header_129, txdata: vec![revoked_htlc_txn[0].clone(), revoked_htlc_txn[1].clone()] }, 129);
 	let first;
@@ -8066,6 +9386,7 @@ fn test_set_outpoints_partial_claiming() {
 	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![remote_txn[0].clone()] }, 101);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	// Verify node A broadcast tx claiming both HTLCs
 	{
 		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -8083,6 +9404,7 @@ fn test_set_outpoints_partial_claiming() {
 	connect_blocks(&nodes[1].block_notifier, 135, 0, false, Default::default());
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "ChannelMonitor broadcast our latest commitment transaction to resolve an HTLC on-chain".to_owned() });
 	// Verify node B broadcast 2 HTLC-timeout txn
 	let partial_claim_tx = {
 		let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -8152,6 +9474,7 @@ fn test_counterparty_raa_skip_no_crash() {
 	nodes[1].node.handle_revoke_and_ack(&nodes[0].node.get_our_node_id(),
 		&msgs::RevokeAndACK { channel_id, per_commitment_secret, next_per_commitment_point });
 	assert_eq!(check_closed_broadcast!(nodes[1], true).unwrap().data, "Received an unexpected revoke_and_ack");
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Received an unexpected revoke_and_ack".to_owned() });
 	check_added_monitors!(nodes[1], 1);
 }
 
@@ -8185,6 +9508,7 @@ fn test_bump_txn_sanitize_tracking_maps() {
 	nodes[0].block_notifier.block_connected(&Block { header: header_129, txdata: vec![revoked_local_txn[0].clone()] }, 129);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	let penalty_txn = {
 		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
 		assert_eq!(node_txn.len(), 4); //ChannelMonitor: justice txn * 3, ChannelManager: local commitment tx
@@ -8208,46 +9532,355 @@ fn test_bump_txn_sanitize_tracking_maps() {
 }
 
 #[test]
-fn test_override_channel_config() {
-	let chanmon_cfgs = create_chanmon_cfgs(2);
-	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
-	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
-	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+fn test_override_channel_config() {
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// Node0 initiates a channel to node1 using the override config.
+	let mut override_config = UserConfig::default();
+	override_config.own_channel_config.our_to_self_delay = 200;
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 16_000_000, 12_000_000, 42, Some(override_config)).unwrap();
+
+	// Assert the channel created by node0 is using the override config.
+	let res = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	assert_eq!(res.channel_flags, 0);
+	assert_eq!(res.to_self_delay, 200);
+}
+
+#[test]
+fn test_override_0msat_htlc_minimum() {
+	let mut zero_config = UserConfig::default();
+	zero_config.own_channel_config.our_htlc_minimum_msat = 0;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(zero_config.clone())]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 16_000_000, 12_000_000, 42, Some(zero_config)).unwrap();
+	let res = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	assert_eq!(res.htlc_minimum_msat, 1);
+
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &res);
+	let res = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+	assert_eq!(res.htlc_minimum_msat, 1);
+}
+
+#[test]
+fn test_simple_payment_secret() {
+	// Simple test of sending a payment with a payment_secret present. This does not use any AMP
+	// features, however.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let payment_secret = PaymentSecret([0xdb; 32]);
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[2]]], 100000, payment_hash, Some(payment_secret.clone()));
+	// Claiming with all the correct values but the wrong secret should result in nothing...
+	assert_eq!(nodes[2].node.claim_funds(payment_preimage, &None, 100_000), false);
+	assert_eq!(nodes[2].node.claim_funds(payment_preimage, &Some(PaymentSecret([42; 32])), 100_000), false);
+	// ...but with the right secret we should be able to claim all the way back
+	claim_payment_along_route_with_secret(&nodes[0], &[&[&nodes[1], &nodes[2]]], false, payment_preimage, Some(payment_secret.clone()), 100_000);
+}
+
+#[test]
+fn test_simple_mpp() {
+	// Simple test of sending a multi-path payment.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	let chan_1_id = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let chan_2_id = create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let chan_3_id = create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let chan_4_id = create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let logger = test_utils::TestLogger::new();
+
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let payment_secret = PaymentSecret([0xdb; 32]);
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[3].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	let path = route.paths[0].clone();
+	route.paths.push(path);
+	route.paths[0][0].pubkey = nodes[1].node.get_our_node_id();
+	route.paths[0][0].short_channel_id = chan_1_id;
+	route.paths[0][1].short_channel_id = chan_3_id;
+	route.paths[1][0].pubkey = nodes[2].node.get_our_node_id();
+	route.paths[1][0].short_channel_id = chan_2_id;
+	route.paths[1][1].short_channel_id = chan_4_id;
+	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[3]], &[&nodes[2], &nodes[3]]], 200_000, payment_hash, Some(payment_secret.clone()));
+	// Claiming with all the correct values but the wrong secret should result in nothing...
+	assert_eq!(nodes[3].node.claim_funds(payment_preimage, &None, 200_000), false);
+	assert_eq!(nodes[3].node.claim_funds(payment_preimage, &Some(PaymentSecret([42; 32])), 200_000), false);
+	// ...but with the right secret we should be able to claim all the way back
+	claim_payment_along_route_with_secret(&nodes[0], &[&[&nodes[1], &nodes[3]], &[&nodes[2], &nodes[3]]], false, payment_preimage, Some(payment_secret), 200_000);
+}
+
+#[test]
+fn test_mpp_unusable_later_path() {
+	// Test that if the first hop of a later path of a multi-path payment can't be used, no path is
+	// sent, rather than leaving the earlier paths in flight.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	let chan_1_id = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let chan_2_id = create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let chan_3_id = create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let chan_4_id = create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let logger = test_utils::TestLogger::new();
+
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let payment_secret = PaymentSecret([0xdb; 32]);
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[3].node.get_our_node_id(), None, None, &[], 100000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	let path = route.paths[0].clone();
+	route.paths.push(path);
+	route.paths[0][0].pubkey = nodes[1].node.get_our_node_id();
+	route.paths[0][0].short_channel_id = chan_1_id;
+	route.paths[0][1].short_channel_id = chan_3_id;
+	route.paths[1][0].pubkey = nodes[2].node.get_our_node_id();
+	route.paths[1][0].short_channel_id = chan_2_id;
+	route.paths[1][1].short_channel_id = chan_4_id;
+
+	nodes[0].node.peer_disconnected(&nodes[2].node.get_our_node_id(), false);
+	nodes[2].node.peer_disconnected(&nodes[0].node.get_our_node_id(), false);
+
+	match nodes[0].node.send_payment(&route, payment_hash, &Some(payment_secret)) {
+		Err(PaymentSendFailure::PathParameterError(results)) => {
+			assert_eq!(results.len(), 2);
+			assert!(results[0].is_ok());
+			match results[1] {
+				Err(APIError::ChannelUnavailable { ref err }) => assert!(err.contains("Peer for first hop currently disconnected")),
+				_ => panic!("Unexpected result"),
+			}
+		},
+		_ => panic!("Unexpected result"),
+	}
+	check_added_monitors!(nodes[0], 0);
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+}
+
+#[test]
+fn test_mpp_route_splitting() {
+	// Test that we split a payment larger than any single channel's htlc_maximum_msat over
+	// multiple paths and that it can be sent and claimed.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+
+	// Each channel is announced with an htlc_maximum_msat of 10% of its value (10,000,000 msat)
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	let payment_secret = PaymentSecret([0xdb; 32]);
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[3].node.get_our_node_id(), Some(&InvoiceFeatures::known()), None, &[], 15_000_000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	assert_eq!(route.paths.len(), 2);
+	assert_eq!(route.paths.iter().map(|path| path.last().unwrap().fee_msat).sum::<u64>(), 15_000_000);
+	assert_ne!(route.paths[0][0].pubkey, route.paths[1][0].pubkey);
+
+	// The paths are sent in the order they appear in the route
+	let path_via_1: &[&Node] = &[&nodes[1], &nodes[3]];
+	let path_via_2: &[&Node] = &[&nodes[2], &nodes[3]];
+	let expected_paths: Vec<&[&Node]> = route.paths.iter().map(|path| {
+		if path[0].pubkey == nodes[1].node.get_our_node_id() { path_via_1 } else { path_via_2 }
+	}).collect();
+
+	// Multi-path payments require a payment_secret
+	match nodes[0].node.send_payment(&route, payment_hash, &None) {
+		Err(PaymentSendFailure::ParameterError(APIError::RouteError { err })) => assert_eq!(err, "Multi-path payments require a payment_secret"),
+		_ => panic!(),
+	}
+
+	send_along_route_with_secret(&nodes[0], route, &expected_paths[..], 15_000_000, payment_hash, Some(payment_secret.clone()));
+	claim_payment_along_route_with_secret(&nodes[0], &expected_paths[..], false, payment_preimage, Some(payment_secret), 15_000_000);
+}
+
+#[test]
+fn test_spontaneous_payment() {
+	// Test that we can send a payment without an invoice, including the preimage in the onion,
+	// and that the recipient learns the preimage and can claim the payment with it.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100_000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	let payment_hash = nodes[0].node.send_spontaneous_payment(&route, None).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let payment_preimage = match events[0] {
+		Event::PaymentReceived { payment_hash: ref received_hash, payment_secret: None, payment_preimage: Some(preimage), amt } => {
+			assert_eq!(*received_hash, payment_hash);
+			assert_eq!(PaymentHash(Sha256::hash(&preimage.0).into_inner()), payment_hash);
+			assert_eq!(amt, 100_000);
+			preimage
+		},
+		_ => panic!("Unexpected event"),
+	};
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 100_000);
+
+	// A caller-provided preimage is used as-is
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, None, &[], 100_000, TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
+	let payment_preimage = PaymentPreimage([42; 32]);
+	let payment_hash = nodes[0].node.send_spontaneous_payment(&route, Some(payment_preimage)).unwrap();
+	assert_eq!(payment_hash, PaymentHash(Sha256::hash(&payment_preimage.0).into_inner()));
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentReceived { payment_preimage: Some(preimage), .. } => assert_eq!(preimage, payment_preimage),
+		_ => panic!("Unexpected event"),
+	}
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 100_000);
+}
+
+#[test]
+fn test_payment_retry_excludes_failed_channel() {
+	// Test that a PaymentRetrier re-routes a payment which failed at an intermediary hop around
+	// the failing channel, and that the failure is not surfaced to the user when it does so.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
 
-	// Node0 initiates a channel to node1 using the override config.
-	let mut override_config = UserConfig::default();
-	override_config.own_channel_config.our_to_self_delay = 200;
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known());
+	let chan_1_3_scid = create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
+	let chan_2_3_scid = create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
 
-	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 16_000_000, 12_000_000, 42, Some(override_config)).unwrap();
+	let scorer = Scorer::default();
+	let events_handled = RefCell::new(Vec::new());
+	let retrier = PaymentRetrier::new(nodes[0].node, &nodes[0].net_graph_msg_handler, nodes[0].logger, &scorer, |event| events_handled.borrow_mut().push(event), RetryConfig::default());
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	retrier.pay(&nodes[3].node.get_our_node_id(), payment_hash, None, None, Vec::new(), 1_000_000, TEST_FINAL_CLTV).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	let (failing_hop, other_hop, failing_scid) = if payment_event.node_id == nodes[1].node.get_our_node_id() { (1, 2, chan_1_3_scid) } else { (2, 1, chan_2_3_scid) };
+
+	// Disconnect the first hop from the payee so that it cannot forward the HTLC and fails it back
+	nodes[failing_hop].node.peer_disconnected(&nodes[3].node.get_our_node_id(), false);
+	nodes[3].node.peer_disconnected(&nodes[failing_hop].node.get_our_node_id(), false);
+	nodes[failing_hop].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[failing_hop], nodes[0], payment_event.commitment_msg, false, true);
+	let updates = get_htlc_update_msgs!(nodes[failing_hop], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[failing_hop].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[failing_hop], updates.commitment_signed, false, true);
 
-	// Assert the channel created by node0 is using the override config.
-	let res = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
-	assert_eq!(res.channel_flags, 0);
-	assert_eq!(res.to_self_delay, 200);
+	// The failure is swallowed by the retrier, which sends the payment again via the other hop
+	for event in nodes[0].node.get_and_clear_pending_events() {
+		retrier.handle_event(event);
+	}
+	assert!(events_handled.borrow().is_empty());
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	match events.remove(0) {
+		MessageSendEvent::PaymentFailureNetworkUpdate { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+
+	// The scorer has learned that the failing channel should be avoided in future
+	let base_penalty_msat = ScoringParameters::default().base_penalty_msat;
+	assert!(scorer.channel_penalty_msat(failing_scid, 1_000_000, &nodes[failing_hop].node.get_our_node_id(), &nodes[3].node.get_our_node_id()) > base_penalty_msat);
+
+	pass_along_path(&nodes[0], &[&nodes[other_hop], &nodes[3]], 1_000_000, payment_hash, None, events.remove(0), true);
+	claim_payment(&nodes[0], &[&nodes[other_hop], &nodes[3]], payment_preimage, 1_000_000);
 }
 
 #[test]
-fn test_override_0msat_htlc_minimum() {
-	let mut zero_config = UserConfig::default();
-	zero_config.own_channel_config.our_htlc_minimum_msat = 0;
-	let chanmon_cfgs = create_chanmon_cfgs(2);
-	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
-	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(zero_config.clone())]);
-	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+fn test_payment_retry_gives_up() {
+	// Test that once a PaymentRetrier runs out of retries it surfaces a single PaymentFailed event.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
 
-	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 16_000_000, 12_000_000, 42, Some(zero_config)).unwrap();
-	let res = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
-	assert_eq!(res.htlc_minimum_msat, 1);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 
-	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &res);
-	let res = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
-	assert_eq!(res.htlc_minimum_msat, 1);
+	let scorer = test_utils::TestScorer::new();
+	let events_handled = RefCell::new(Vec::new());
+	let retrier = PaymentRetrier::new(nodes[0].node, &nodes[0].net_graph_msg_handler, nodes[0].logger, &scorer, |event| events_handled.borrow_mut().push(event), RetryConfig { max_retries: 0, ..Default::default() });
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	retrier.pay(&nodes[2].node.get_our_node_id(), payment_hash, None, None, Vec::new(), 1_000_000, TEST_FINAL_CLTV).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	match retrier.pay(&nodes[2].node.get_our_node_id(), payment_hash, None, None, Vec::new(), 1_000_000, TEST_FINAL_CLTV) {
+		Err(PaymentError::Sending(PaymentSendFailure::ParameterError(APIError::APIMisuseError { .. }))) => {},
+		_ => panic!("Expected an error for a duplicate payment_hash"),
+	}
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+
+	nodes[1].node.peer_disconnected(&nodes[2].node.get_our_node_id(), false);
+	nodes[2].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	nodes[0].node.get_and_clear_pending_msg_events();
+
+	for event in nodes[0].node.get_and_clear_pending_events() {
+		retrier.handle_event(event);
+	}
+	let events = events_handled.borrow();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_hash, rejected_by_dest, short_channel_id, .. } => {
+			assert_eq!(*failed_hash, payment_hash);
+			assert!(!rejected_by_dest);
+			assert_eq!(short_channel_id, Some(nodes[1].node.list_channels().iter().find(|chan| chan.remote_network_id == nodes[2].node.get_our_node_id()).unwrap().short_channel_id.unwrap()));
+		},
+		_ => panic!("Unexpected event"),
+	}
 }
 
 #[test]
-fn test_simple_payment_secret() {
-	// Simple test of sending a payment with a payment_secret present. This does not use any AMP
-	// features, however.
+fn test_payment_retry_on_timer_tick() {
+	// Test that if a failed payment can't be re-routed right away, the PaymentRetrier retries it
+	// on timer ticks until it runs out of retries, only then surfacing the failure.
 	let chanmon_cfgs = create_chanmon_cfgs(3);
 	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
 	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
@@ -8255,52 +9888,92 @@ fn test_simple_payment_secret() {
 
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
-	let logger = test_utils::TestLogger::new();
 
-	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
-	let payment_secret = PaymentSecret([0xdb; 32]);
-	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[2]]], 100000, payment_hash, Some(payment_secret.clone()));
-	// Claiming with all the correct values but the wrong secret should result in nothing...
-	assert_eq!(nodes[2].node.claim_funds(payment_preimage, &None, 100_000), false);
-	assert_eq!(nodes[2].node.claim_funds(payment_preimage, &Some(PaymentSecret([42; 32])), 100_000), false);
-	// ...but with the right secret we should be able to claim all the way back
-	claim_payment_along_route_with_secret(&nodes[0], &[&[&nodes[1], &nodes[2]]], false, payment_preimage, Some(payment_secret.clone()), 100_000);
+	let scorer = test_utils::TestScorer::new();
+	let events_handled = RefCell::new(Vec::new());
+	let retrier = PaymentRetrier::new(nodes[0].node, &nodes[0].net_graph_msg_handler, nodes[0].logger, &scorer, |event| events_handled.borrow_mut().push(event), RetryConfig { max_retries: 2, ..Default::default() });
+	let (_, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
+	retrier.pay(&nodes[2].node.get_our_node_id(), payment_hash, None, None, Vec::new(), 1_000_000, TEST_FINAL_CLTV).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+
+	nodes[1].node.peer_disconnected(&nodes[2].node.get_our_node_id(), false);
+	nodes[2].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	nodes[0].node.get_and_clear_pending_msg_events();
+
+	// With the failing channel excluded there is no route to retry over, so the payment waits...
+	for event in nodes[0].node.get_and_clear_pending_events() {
+		retrier.handle_event(event);
+	}
+	assert!(events_handled.borrow().is_empty());
+	retrier.timer_tick_occurred();
+	assert!(events_handled.borrow().is_empty());
+
+	// ...until it runs out of retries on a later tick.
+	retrier.timer_tick_occurred();
+	let events = events_handled.borrow();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_hash, rejected_by_dest, .. } => {
+			assert_eq!(*failed_hash, payment_hash);
+			assert!(!rejected_by_dest);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
 }
 
 #[test]
-fn test_simple_mpp() {
-	// Simple test of sending a multi-path payment.
+fn test_payment_retry_mpp_path_failure_after_success() {
+	// Test that once a path of a multi-path payment succeeded, the PaymentRetrier neither retries
+	// nor surfaces failures of its other paths, and forgets the payment once they all resolved.
 	let chanmon_cfgs = create_chanmon_cfgs(4);
 	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
 	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
 	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
 
-	let chan_1_id = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
-	let chan_2_id = create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
-	let chan_3_id = create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
-	let chan_4_id = create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
-	let logger = test_utils::TestLogger::new();
+	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1_000_000, 0, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes_with_value(&nodes, 0, 2, 1_000_000, 0, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known());
+	let chan_2_3_scid = create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
 
+	let scorer = test_utils::TestScorer::new();
+	let events_handled = RefCell::new(Vec::new());
+	let retrier = PaymentRetrier::new(nodes[0].node, &nodes[0].net_graph_msg_handler, nodes[0].logger, &scorer, |event| events_handled.borrow_mut().push(event), RetryConfig::default());
 	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
 	let payment_secret = PaymentSecret([0xdb; 32]);
-	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[3].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	let path = route.paths[0].clone();
-	route.paths.push(path);
-	route.paths[0][0].pubkey = nodes[1].node.get_our_node_id();
-	route.paths[0][0].short_channel_id = chan_1_id;
-	route.paths[0][1].short_channel_id = chan_3_id;
-	route.paths[1][0].pubkey = nodes[2].node.get_our_node_id();
-	route.paths[1][0].short_channel_id = chan_2_id;
-	route.paths[1][1].short_channel_id = chan_4_id;
-	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[3]], &[&nodes[2], &nodes[3]]], 200_000, payment_hash, Some(payment_secret.clone()));
-	// Claiming with all the correct values but the wrong secret should result in nothing...
-	assert_eq!(nodes[3].node.claim_funds(payment_preimage, &None, 200_000), false);
-	assert_eq!(nodes[3].node.claim_funds(payment_preimage, &Some(PaymentSecret([42; 32])), 200_000), false);
-	// ...but with the right secret we should be able to claim all the way back
-	claim_payment_along_route_with_secret(&nodes[0], &[&[&nodes[1], &nodes[3]], &[&nodes[2], &nodes[3]]], false, payment_preimage, Some(payment_secret), 200_000);
+	// The channels to nodes[3] are announced with an htlc_maximum_msat of 10,000,000 msat, so
+	// this is split over both paths.
+	retrier.pay(&nodes[3].node.get_our_node_id(), payment_hash, Some(payment_secret), Some(InvoiceFeatures::known()), Vec::new(), 15_000_000, TEST_FINAL_CLTV).unwrap();
+	check_added_monitors!(nodes[0], 2);
+	assert_eq!(nodes[0].node.get_and_clear_pending_msg_events().len(), 2);
+
+	let path_failed = |short_channel_id| Event::PaymentFailed {
+		payment_hash, rejected_by_dest: false, short_channel_id: Some(short_channel_id), error_code: None, error_data: None,
+	};
+	retrier.handle_event(Event::PaymentSent { payment_preimage });
+	retrier.handle_event(path_failed(chan_2_3_scid));
+	check_added_monitors!(nodes[0], 0);
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	{
+		let events = events_handled.borrow();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::PaymentSent { payment_preimage: ref preimage } => assert_eq!(*preimage, payment_preimage),
+			_ => panic!("Unexpected event"),
+		}
+	}
+
+	// With both paths resolved the payment is forgotten, so further events are passed on as-is.
+	retrier.handle_event(path_failed(chan_2_3_scid));
+	assert_eq!(events_handled.borrow().len(), 2);
 }
 
 #[test]
@@ -8433,7 +10106,7 @@ fn test_concurrent_monitor_claim() {
 	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 3000000 , TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, None, &Vec::new(), 3000000 , TEST_FINAL_CLTV, &logger, &test_utils::TestScorer::new()).unwrap();
 		nodes[1].node.send_payment(&route, payment_hash, &None).unwrap();
 	}
 	check_added_monitors!(nodes[1], 1);
@@ -8475,3 +10148,317 @@ fn test_concurrent_monitor_claim() {
 		check_spends!(htlc_txn[1], bob_state_y);
 	}
 }
+
+#[test]
+fn test_anchor_outputs_force_close_bump() {
+	// Open a channel using anchor outputs, force-close it with an HTLC pending and check that the
+	// commitment transaction carries both anchors, that HTLC transactions are valid, and that we
+	// are asked to CPFP the commitment transaction through our anchor once our fee estimator
+	// reports a higher feerate than the one it pays.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let high_fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 2000 };
+	let mut node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	node_cfgs[0].chan_monitor = test_utils::TestChannelMonitor::new(&chanmon_cfgs[0].chain_monitor, &chanmon_cfgs[0].tx_broadcaster, &chanmon_cfgs[0].logger, &high_fee_estimator);
+	let mut anchors_config = UserConfig::default();
+	anchors_config.channel_options.announced_channel = true;
+	anchors_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	anchors_config.own_channel_config.negotiate_anchor_outputs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(anchors_config.clone()), Some(anchors_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	route_payment(&nodes[0], &[&nodes[1]], 3_000_000);
+
+	let local_txn = get_local_commitment_txn!(nodes[0], chan.2);
+	assert_eq!(local_txn.len(), 2);
+	check_spends!(local_txn[0], chan.3);
+	// to_local, the HTLC and both anchors, as the pending HTLC keeps the to_remote anchor around
+	// even though our counterparty has no balance yet
+	assert_eq!(local_txn[0].output.len(), 4);
+	assert_eq!(local_txn[0].output.iter().filter(|output| output.value == chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI).count(), 2);
+	// The HTLC-timeout transaction is CSV-locked and carries a SIGHASH_SINGLE|ANYONECANPAY
+	// signature from our counterparty
+	check_spends!(local_txn[1], local_txn[0]);
+	assert_eq!(local_txn[1].input[0].sequence, 1);
+	assert_eq!(*local_txn[1].input[0].witness[1].last().unwrap(), SigHashType::SinglePlusAnyoneCanPay as u8);
+
+	nodes[0].node.force_close_channel(&chan.2);
+	check_closed_broadcast!(nodes[0], false);
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+	{
+		// The commitment transaction and our HTLC-timeout transaction
+		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+		assert_eq!(node_txn.len(), 2);
+		assert_eq!(node_txn[0].txid(), local_txn[0].txid());
+		check_spends!(node_txn[1], local_txn[0]);
+		node_txn.clear();
+	}
+
+	// The next block hands the broadcast commitment transaction over to the OnchainTxHandler,
+	// which notices it pays less than the HighPriority estimate.
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![] }, 1);
+	let events = nodes[0].chan_monitor.simple_monitor.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::BumpCommitmentTransaction { ref commitment_tx, commitment_tx_fee_satoshis, target_feerate_sat_per_1000_weight, ref anchor_outpoint, ref anchor_witness_script, ref key_derivation_params } => {
+			assert_eq!(commitment_tx.txid(), local_txn[0].txid());
+			assert_eq!(target_feerate_sat_per_1000_weight, 2000);
+			assert_eq!(commitment_tx_fee_satoshis, 100000 - commitment_tx.output.iter().map(|output| output.value).sum::<u64>());
+			assert_eq!(commitment_tx.output[anchor_outpoint.index as usize].script_pubkey, anchor_witness_script.to_v0_p2wsh());
+
+			// Build a child spending our anchor with the keys we're pointed to
+			let keys = node_cfgs[0].keys_manager.derive_channel_keys(100000, key_derivation_params.0, key_derivation_params.1);
+			let mut child_tx = Transaction {
+				version: 2,
+				lock_time: 0,
+				input: vec![TxIn {
+					previous_output: anchor_outpoint.into_bitcoin_outpoint(),
+					script_sig: Script::new(),
+					sequence: 0xfffffffd,
+					witness: Vec::new(),
+				}],
+				output: vec![TxOut {
+					script_pubkey: Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script(),
+					value: 0,
+				}],
+			};
+			let sig = keys.sign_holder_anchor_input(&child_tx, 0, &Secp256k1::new()).unwrap();
+			child_tx.input[0].witness = chan_utils::build_anchor_input_witness(&keys.pubkeys().funding_pubkey, &sig);
+			check_spends!(child_tx, commitment_tx);
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	// The commitment transaction is rebroadcast on the next block, but without a new bump request
+	// as the estimated feerate didn't move.
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[0].block_notifier.block_connected(&Block { header, txdata: vec![] }, 2);
+	assert!(nodes[0].chan_monitor.simple_monitor.get_and_clear_pending_events().is_empty());
+	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+	assert!(node_txn.iter().any(|tx| tx.txid() == local_txn[0].txid()));
+}
+
+#[test]
+fn test_persistence_notifier_on_channel_update() {
+	// Check that operations on the ChannelManager wake up anyone waiting on
+	// await_persistable_update, and that the notification is consumed by the waiter.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// Clear out any notification generated while setting up the network.
+	nodes[0].node.await_persistable_update_timeout(Duration::from_millis(1));
+	assert!(!nodes[0].node.await_persistable_update_timeout(Duration::from_millis(1)));
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+	nodes[0].node.await_persistable_update();
+	assert!(!nodes[0].node.await_persistable_update_timeout(Duration::from_millis(1)));
+	get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+
+	nodes[0].node.timer_chan_freshness_every_min();
+	assert!(nodes[0].node.await_persistable_update_timeout(Duration::from_millis(1)));
+}
+
+#[test]
+fn test_best_block_tracked_across_disconnect_and_reload() {
+	// Check that the ChannelManager and ChannelMonitor both track the hash and height of the
+	// latest block connected, that this rewinds to the parent block on disconnection, and that the
+	// ChannelMonitor's copy survives serialization.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+
+	macro_rules! monitor_last_block {
+		() => { nodes[0].chan_monitor.simple_monitor.monitors.lock().unwrap().iter().next().unwrap().1.get_last_block() }
+	}
+	let (funded_hash, funded_height) = nodes[0].node.current_best_block();
+	assert_eq!(funded_height, CHAN_CONFIRM_DEPTH - 1);
+	assert_eq!(monitor_last_block!(), (funded_hash, funded_height));
+
+	let header_1 = BlockHeader { version: 0x20000000, prev_blockhash: funded_hash, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let header_2 = BlockHeader { version: 0x20000000, prev_blockhash: header_1.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	nodes[0].block_notifier.block_connected_checked(&header_1, funded_height + 1, &[], &[]);
+	nodes[0].block_notifier.block_connected_checked(&header_2, funded_height + 2, &[], &[]);
+	assert_eq!(nodes[0].node.current_best_block(), (header_2.block_hash(), funded_height + 2));
+	assert_eq!(monitor_last_block!(), (header_2.block_hash(), funded_height + 2));
+
+	nodes[0].block_notifier.block_disconnected(&header_2, funded_height + 2);
+	assert_eq!(nodes[0].node.current_best_block(), (header_1.block_hash(), funded_height + 1));
+	assert_eq!(monitor_last_block!(), (header_1.block_hash(), funded_height + 1));
+
+	let mut monitor_serialized = test_utils::TestVecWriter(Vec::new());
+	nodes[0].chan_monitor.simple_monitor.monitors.lock().unwrap().iter().next().unwrap().1.write_for_disk(&mut monitor_serialized).unwrap();
+	let (last_block_hash, monitor) = <(BlockHash, ChannelMonitor<EnforcingChannelKeys>)>::read(&mut &monitor_serialized.0[..]).unwrap();
+	assert_eq!(last_block_hash, header_1.block_hash());
+	assert_eq!(monitor.get_last_block(), (header_1.block_hash(), funded_height + 1));
+}
+
+#[test]
+fn test_transaction_based_funding_confirmation() {
+	// Check that a channel can be confirmed via ConfirmationListener without being handed every
+	// block, that unconfirming the funding transaction before funding_locked simply resets its
+	// confirmation count, and that unconfirming it after funding_locked force-closes the channel.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let tx = create_chan_between_nodes_with_value_init(&nodes[0], &nodes[1], 100000, 10001, InitFeatures::known(), InitFeatures::known());
+	let funding_txid = tx.txid();
+
+	// Vary the time so that every height gets a distinct block hash.
+	let header_at = |height: u32| BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42 + height, bits: 42, nonce: 42 };
+	macro_rules! confirm_at {
+		($node: expr, $conf_height: expr, $best_height: expr) => {{
+			$node.node.transactions_confirmed(&header_at($conf_height), $conf_height, &[(1, &tx)]);
+			$node.chan_monitor.simple_monitor.transactions_confirmed(&header_at($conf_height), $conf_height, &[(1, &tx)]);
+			$node.node.best_block_updated(&header_at($best_height), $best_height);
+			$node.chan_monitor.simple_monitor.best_block_updated(&header_at($best_height), $best_height);
+		}}
+	}
+
+	// Five confirmations are one short of the default minimum_depth.
+	confirm_at!(nodes[0], 1, 5);
+	confirm_at!(nodes[1], 1, 5);
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	assert_eq!(nodes[1].node.get_relevant_txids(), vec![funding_txid]);
+	assert_eq!(nodes[1].chan_monitor.simple_monitor.get_relevant_txids(), vec![funding_txid]);
+
+	nodes[1].node.transaction_unconfirmed(&funding_txid);
+	nodes[1].chan_monitor.simple_monitor.transaction_unconfirmed(&funding_txid);
+	assert!(nodes[1].node.get_relevant_txids().is_empty());
+	assert!(nodes[1].chan_monitor.simple_monitor.get_relevant_txids().is_empty());
+	assert_eq!(nodes[1].node.list_channels().len(), 1);
+
+	// Without the funding transaction, moving the tip forward doesn't lock the channel...
+	nodes[1].node.best_block_updated(&header_at(6), 6);
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	// ...but once it is confirmed again, it does.
+	confirm_at!(nodes[1], 1, 7);
+	nodes[0].node.handle_funding_locked(&nodes[1].node.get_our_node_id(), &get_event_msg!(nodes[1], MessageSendEvent::SendFundingLocked, nodes[0].node.get_our_node_id()));
+	nodes[0].node.best_block_updated(&header_at(7), 7);
+	let (funding_locked, _) = create_chan_between_nodes_with_value_confirm_second(&nodes[1], &nodes[0]);
+	create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &funding_locked);
+	assert_eq!(nodes[0].node.list_usable_channels().len(), 1);
+	assert_eq!(nodes[0].node.get_relevant_txids(), vec![funding_txid]);
+
+	nodes[0].node.transaction_unconfirmed(&funding_txid);
+	check_closed_broadcast!(nodes[0], false);
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Funding transaction was reorganized out of the chain".to_owned() });
+	assert!(nodes[0].node.list_channels().is_empty());
+}
+
+#[test]
+fn test_watchtower_punishes_revoked_commitment_tx() {
+	// Check that a WatchtowerClient hands out a justice transaction for each counterparty
+	// commitment transaction revoked, and that a WatchtowerServer broadcasts it once the revoked
+	// commitment transaction appears on chain.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 0, InitFeatures::known(), InitFeatures::known());
+
+	// Record the updates to node 1's monitor from here on.
+	nodes[1].chan_monitor.monitor_updates.lock().unwrap().clear();
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1]], 3000000).0;
+	// Node 0's commitment transaction, with its to_self and HTLC outputs, which will be revoked.
+	let revoked_local_txn = get_local_commitment_txn!(nodes[0], chan.2);
+	assert_eq!(revoked_local_txn[0].output.len(), 2);
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 3_000_000);
+
+	let fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 253 };
+	let client = WatchtowerClient::new(&fee_estimator);
+	let mut blobs = Vec::new();
+	{
+		let monitors = nodes[1].chan_monitor.simple_monitor.monitors.lock().unwrap();
+		let monitor = monitors.values().next().unwrap();
+		for (_, update) in nodes[1].chan_monitor.monitor_updates.lock().unwrap().iter() {
+			blobs.append(&mut client.process_update(monitor, update));
+		}
+	}
+	assert!(!blobs.is_empty());
+
+	let tower_broadcaster = test_utils::TestBroadcaster { txn_broadcasted: Mutex::new(Vec::new()) };
+	let tower_logger = test_utils::TestLogger::new();
+	let server = WatchtowerServer::new(&tower_broadcaster, &tower_logger);
+	for blob in blobs.drain(..) {
+		server.add_blob(blob);
+	}
+
+	// Blobs survive a restart of the tower.
+	let server = WatchtowerServer::read(&mut &server.encode()[..], (&tower_broadcaster, &tower_logger)).unwrap();
+	assert!(server.blob_count() > 0);
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let unrelated_tx = Transaction { version: 2, lock_time: 0, input: Vec::new(), output: vec![TxOut { value: 3, script_pubkey: Script::new() }] };
+	server.block_connected(&header, 1, &[&unrelated_tx], &[0]);
+	assert!(tower_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+
+	server.block_connected(&header, 1, &[&unrelated_tx, &revoked_local_txn[0]], &[0, 1]);
+	let justice_txn = tower_broadcaster.txn_broadcasted.lock().unwrap().clone();
+	assert_eq!(justice_txn.len(), 1);
+	// The justice transaction claims both outputs of the revoked commitment transaction.
+	assert_eq!(justice_txn[0].input.len(), 2);
+	check_spends!(justice_txn[0], revoked_local_txn[0]);
+}
+
+#[test]
+fn test_payment_forwarded_stats() {
+	// Tests that we generate a PaymentForwarded event when a forwarded HTLC is claimed, and that
+	// ForwardingStats accumulates them per-channel and round-trips through serialization.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+
+	let mut stats = ForwardingStats::new();
+	for _ in 0..2 {
+		let (payment_preimage, _) = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 100_000);
+		assert!(nodes[2].node.claim_funds(payment_preimage, &None, 100_000));
+		check_added_monitors!(nodes[2], 1);
+		let updates = get_htlc_update_msgs!(nodes[2], nodes[1].node.get_our_node_id());
+		nodes[1].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+		check_added_monitors!(nodes[1], 1);
+
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::PaymentForwarded { ref prev_channel_id, ref next_channel_id, fee_earned_msat, claim_from_onchain_tx } => {
+				assert_eq!(*prev_channel_id, Some(chan_1.2));
+				assert_eq!(*next_channel_id, chan_2.2);
+				assert_eq!(fee_earned_msat, Some(239));
+				assert!(!claim_from_onchain_tx);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		stats.process_event(&events[0]);
+
+		let updates_2 = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+		commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
+		nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates_2.update_fulfill_htlcs[0]);
+		commitment_signed_dance!(nodes[0], nodes[1], updates_2.commitment_signed, false);
+		expect_payment_sent!(nodes[0], payment_preimage);
+	}
+
+	assert_eq!(*stats.channel_stats(&chan_1.2).unwrap(), ChannelForwardingStats {
+		inbound_forwards: 2, outbound_forwards: 0, outbound_onchain_claims: 0, fee_earned_msat: 0,
+	});
+	assert_eq!(*stats.channel_stats(&chan_2.2).unwrap(), ChannelForwardingStats {
+		inbound_forwards: 0, outbound_forwards: 2, outbound_onchain_claims: 0, fee_earned_msat: 478,
+	});
+	assert_eq!(stats.total_fee_earned_msat(), 478);
+
+	let read_stats: ForwardingStats = Readable::read(&mut ::std::io::Cursor::new(stats.encode())).unwrap();
+	assert_eq!(read_stats, stats);
+
+	assert!(stats.remove_channel(&chan_2.2).is_some());
+	assert_eq!(stats.total_fee_earned_msat(), 0);
+}
@@ -0,0 +1,582 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Interactive construction of a transaction collaboratively funded by both channel peers, as
+//! used by splicing. Each side takes turns adding inputs and outputs via tx_add_input and
+//! tx_add_output, with negotiation completing once both sides have consecutively sent
+//! tx_complete.
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{OutPoint as BitcoinOutPoint, Transaction, TxIn, TxOut};
+
+use ln::msgs;
+use ln::msgs::MAX_VALUE_MSAT;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// The maximum number of inputs or outputs either side may add during a single construction.
+const MAX_INPUTS_OUTPUTS_COUNT: usize = 252;
+
+/// The version used for interactively-constructed transactions.
+const INTERACTIVE_TX_VERSION: i32 = 2;
+
+/// The weight of a transaction's common fields (version, locktime, input and output counts and the
+/// segwit marker and flag), which the initiator pays for.
+pub(super) const TX_COMMON_FIELDS_WEIGHT: u64 = 42;
+/// The weight of the shared input spending the previous 2-of-2 funding output, which the initiator
+/// pays for.
+pub(super) const SHARED_INPUT_WEIGHT: u64 = 386;
+/// The weight of a P2WPKH input. We assume all non-shared inputs are at least this heavy when
+/// checking the fee each side pays.
+pub(super) const P2WPKH_INPUT_WEIGHT: u64 = 272;
+
+/// An input which we wish to contribute to the transaction.
+#[derive(Clone)]
+pub(super) struct InteractiveTxInput {
+	/// The transaction containing the output being spent, or None if this spends the shared
+	/// channel funding output.
+	pub prevtx: Option<Transaction>,
+	/// The index of the output being spent in prevtx (or the shared funding transaction).
+	pub prevtx_out: u32,
+	/// The sequence number to use for this input.
+	pub sequence: u32,
+}
+
+/// A message which should be sent to the counterparty in response to the last message received.
+pub(super) enum InteractiveTxMessageSend {
+	TxAddInput(msgs::TxAddInput),
+	TxAddOutput(msgs::TxAddOutput),
+	TxComplete(msgs::TxComplete),
+}
+
+/// A fully-negotiated transaction, along with the values each side contributed to it.
+pub(super) struct ConstructedTransaction {
+	/// The transaction itself, without any witnesses.
+	pub tx: Transaction,
+	/// The indexes of the inputs we contributed (not including the shared input), in order.
+	pub holder_input_indexes: Vec<usize>,
+	/// The index of the shared input spending the previous funding output, if any.
+	pub shared_input_index: Option<usize>,
+	/// The index of the output paying to the shared funding script, if any.
+	pub shared_output_index: Option<usize>,
+	/// The total value of the inputs we contributed, not including the shared input.
+	pub holder_inputs_value: u64,
+	/// The total value of the outputs we contributed, not including the shared output.
+	pub holder_outputs_value: u64,
+	/// The total value of the inputs our counterparty contributed, not including the shared input.
+	pub counterparty_inputs_value: u64,
+	/// The total value of the outputs our counterparty contributed, not including the shared
+	/// output.
+	pub counterparty_outputs_value: u64,
+}
+
+struct NegotiatedInput {
+	prevout: BitcoinOutPoint,
+	value: u64,
+	sequence: u32,
+	is_holder: bool,
+	is_shared: bool,
+}
+
+struct NegotiatedOutput {
+	txout: TxOut,
+	is_holder: bool,
+}
+
+/// Tracks the state of an interactive transaction construction with a single counterparty.
+pub(super) struct InteractiveTxConstructor {
+	channel_id: [u8; 32],
+	is_initiator: bool,
+	locktime: u32,
+	funding_feerate_per_kw: u32,
+	/// The amount by which each side's inputs, less its outputs and its share of the fee, change the
+	/// value of the shared funding output.
+	holder_contribution_satoshis: i64,
+	counterparty_contribution_satoshis: i64,
+	/// The previous funding output and its value, which either side may add as an input.
+	shared_input: Option<(BitcoinOutPoint, u64)>,
+	/// The script of the new shared funding output.
+	shared_output_script: Option<Script>,
+
+	inputs_to_send: VecDeque<InteractiveTxInput>,
+	outputs_to_send: VecDeque<TxOut>,
+	next_serial_id: u64,
+
+	inputs: HashMap<u64, NegotiatedInput>,
+	outputs: HashMap<u64, NegotiatedOutput>,
+	prevouts: HashSet<BitcoinOutPoint>,
+	counterparty_inputs_count: usize,
+	counterparty_outputs_count: usize,
+
+	last_sent_complete: bool,
+	complete: bool,
+}
+
+impl InteractiveTxConstructor {
+	/// Begins constructing a transaction. If we are the initiator, the first message to send to
+	/// our counterparty is returned.
+	///
+	/// The completed transaction must pay at least funding_feerate_per_kw, with each side's inputs
+	/// covering its own outputs, its contribution and its share of the fee.
+	pub fn new(channel_id: [u8; 32], is_initiator: bool, locktime: u32, funding_feerate_per_kw: u32, holder_contribution_satoshis: i64, counterparty_contribution_satoshis: i64, shared_input: Option<(BitcoinOutPoint, u64)>, shared_output_script: Option<Script>, inputs: Vec<InteractiveTxInput>, outputs: Vec<TxOut>) -> (Self, Option<InteractiveTxMessageSend>) {
+		let mut constructor = Self {
+			channel_id,
+			is_initiator,
+			locktime,
+			funding_feerate_per_kw,
+			holder_contribution_satoshis,
+			counterparty_contribution_satoshis,
+			shared_input,
+			shared_output_script,
+			inputs_to_send: inputs.into_iter().collect(),
+			outputs_to_send: outputs.into_iter().collect(),
+			next_serial_id: if is_initiator { 0 } else { 1 },
+			inputs: HashMap::new(),
+			outputs: HashMap::new(),
+			prevouts: HashSet::new(),
+			counterparty_inputs_count: 0,
+			counterparty_outputs_count: 0,
+			last_sent_complete: false,
+			complete: false,
+		};
+		let msg = if is_initiator { Some(constructor.next_message()) } else { None };
+		(constructor, msg)
+	}
+
+	/// Returns true once both sides have consecutively sent tx_complete.
+	#[cfg(test)]
+	pub fn is_complete(&self) -> bool {
+		self.complete
+	}
+
+	fn is_counterparty_serial_id(&self, serial_id: u64) -> bool {
+		// The initiator's serial_ids are even, the non-initiator's are odd.
+		(serial_id % 2 == 0) != self.is_initiator
+	}
+
+	fn next_message(&mut self) -> InteractiveTxMessageSend {
+		let serial_id = self.next_serial_id;
+		if let Some(input) = self.inputs_to_send.pop_front() {
+			self.next_serial_id += 2;
+			self.last_sent_complete = false;
+			let (prevout, value, shared_input_txid) = match input.prevtx {
+				Some(ref prevtx) => (BitcoinOutPoint { txid: prevtx.txid(), vout: input.prevtx_out }, prevtx.output[input.prevtx_out as usize].value, None),
+				None => {
+					let (shared_outpoint, shared_value) = self.shared_input.expect("Cannot spend the shared input if there is none");
+					(shared_outpoint, shared_value, Some(shared_outpoint.txid))
+				},
+			};
+			self.prevouts.insert(prevout);
+			self.inputs.insert(serial_id, NegotiatedInput { prevout, value, sequence: input.sequence, is_holder: true, is_shared: shared_input_txid.is_some() });
+			InteractiveTxMessageSend::TxAddInput(msgs::TxAddInput {
+				channel_id: self.channel_id,
+				serial_id,
+				prevtx: input.prevtx,
+				prevtx_out: input.prevtx_out,
+				sequence: input.sequence,
+				shared_input_txid,
+			})
+		} else if let Some(output) = self.outputs_to_send.pop_front() {
+			self.next_serial_id += 2;
+			self.last_sent_complete = false;
+			let msg = msgs::TxAddOutput {
+				channel_id: self.channel_id,
+				serial_id,
+				sats: output.value,
+				script: output.script_pubkey.clone(),
+			};
+			self.outputs.insert(serial_id, NegotiatedOutput { txout: output, is_holder: true });
+			InteractiveTxMessageSend::TxAddOutput(msg)
+		} else {
+			self.last_sent_complete = true;
+			InteractiveTxMessageSend::TxComplete(msgs::TxComplete { channel_id: self.channel_id })
+		}
+	}
+
+	fn check_can_receive(&self) -> Result<(), &'static str> {
+		if self.complete {
+			return Err("Received an interactive transaction message after construction completed");
+		}
+		Ok(())
+	}
+
+	/// Handles a tx_add_input from our counterparty, returning the message to respond with.
+	pub fn handle_tx_add_input(&mut self, msg: &msgs::TxAddInput) -> Result<InteractiveTxMessageSend, &'static str> {
+		self.check_can_receive()?;
+		if !self.is_counterparty_serial_id(msg.serial_id) {
+			return Err("Received tx_add_input with a serial_id of the wrong parity");
+		}
+		if self.inputs.contains_key(&msg.serial_id) {
+			return Err("Received tx_add_input with a duplicate serial_id");
+		}
+		if self.counterparty_inputs_count >= MAX_INPUTS_OUTPUTS_COUNT {
+			return Err("Received too many inputs");
+		}
+		let (prevout, value, is_shared) = match (&msg.prevtx, &msg.shared_input_txid) {
+			(&None, &Some(ref shared_input_txid)) => {
+				match self.shared_input {
+					Some((shared_outpoint, shared_value)) if shared_outpoint.txid == *shared_input_txid && shared_outpoint.vout == msg.prevtx_out =>
+						(shared_outpoint, shared_value, true),
+					_ => return Err("Received tx_add_input spending an unknown shared input"),
+				}
+			},
+			(&Some(ref prevtx), &None) => {
+				let prevtx_output = match prevtx.output.get(msg.prevtx_out as usize) {
+					Some(output) => output,
+					None => return Err("Received tx_add_input with a prevtx_out beyond the prevtx's outputs"),
+				};
+				if !prevtx_output.script_pubkey.is_witness_program() {
+					return Err("Received tx_add_input spending a non-segwit output");
+				}
+				if prevtx_output.value > MAX_VALUE_MSAT / 1000 {
+					return Err("Received tx_add_input spending an output with an invalid value");
+				}
+				(BitcoinOutPoint { txid: prevtx.txid(), vout: msg.prevtx_out }, prevtx_output.value, false)
+			},
+			_ => return Err("Received tx_add_input with an invalid combination of prevtx and shared_input_txid"),
+		};
+		if !self.prevouts.insert(prevout) {
+			return Err("Received tx_add_input spending an output which is already spent by the transaction");
+		}
+		self.counterparty_inputs_count += 1;
+		self.inputs.insert(msg.serial_id, NegotiatedInput { prevout, value, sequence: msg.sequence, is_holder: false, is_shared });
+		Ok(self.next_message())
+	}
+
+	/// Handles a tx_add_output from our counterparty, returning the message to respond with.
+	pub fn handle_tx_add_output(&mut self, msg: &msgs::TxAddOutput) -> Result<InteractiveTxMessageSend, &'static str> {
+		self.check_can_receive()?;
+		if !self.is_counterparty_serial_id(msg.serial_id) {
+			return Err("Received tx_add_output with a serial_id of the wrong parity");
+		}
+		if self.outputs.contains_key(&msg.serial_id) {
+			return Err("Received tx_add_output with a duplicate serial_id");
+		}
+		if self.counterparty_outputs_count >= MAX_INPUTS_OUTPUTS_COUNT {
+			return Err("Received too many outputs");
+		}
+		if msg.sats == 0 || msg.sats > MAX_VALUE_MSAT / 1000 {
+			return Err("Received tx_add_output with an invalid value");
+		}
+		self.counterparty_outputs_count += 1;
+		self.outputs.insert(msg.serial_id, NegotiatedOutput { txout: TxOut { value: msg.sats, script_pubkey: msg.script.clone() }, is_holder: false });
+		Ok(self.next_message())
+	}
+
+	/// Handles a tx_remove_input from our counterparty, returning the message to respond with.
+	pub fn handle_tx_remove_input(&mut self, msg: &msgs::TxRemoveInput) -> Result<InteractiveTxMessageSend, &'static str> {
+		self.check_can_receive()?;
+		if !self.is_counterparty_serial_id(msg.serial_id) {
+			return Err("Received tx_remove_input for an input we added");
+		}
+		match self.inputs.remove(&msg.serial_id) {
+			Some(input) => {
+				self.prevouts.remove(&input.prevout);
+				self.counterparty_inputs_count -= 1;
+			},
+			None => return Err("Received tx_remove_input for an unknown serial_id"),
+		}
+		Ok(self.next_message())
+	}
+
+	/// Handles a tx_remove_output from our counterparty, returning the message to respond with.
+	pub fn handle_tx_remove_output(&mut self, msg: &msgs::TxRemoveOutput) -> Result<InteractiveTxMessageSend, &'static str> {
+		self.check_can_receive()?;
+		if !self.is_counterparty_serial_id(msg.serial_id) {
+			return Err("Received tx_remove_output for an output we added");
+		}
+		if self.outputs.remove(&msg.serial_id).is_none() {
+			return Err("Received tx_remove_output for an unknown serial_id");
+		}
+		self.counterparty_outputs_count -= 1;
+		Ok(self.next_message())
+	}
+
+	/// Handles a tx_complete from our counterparty. Returns the message to respond with, if any,
+	/// as well as the final transaction if construction has completed.
+	pub fn handle_tx_complete(&mut self, _msg: &msgs::TxComplete) -> Result<(Option<InteractiveTxMessageSend>, Option<ConstructedTransaction>), &'static str> {
+		self.check_can_receive()?;
+		let response = if self.last_sent_complete {
+			None
+		} else {
+			let msg = self.next_message();
+			if !self.last_sent_complete {
+				return Ok((Some(msg), None));
+			}
+			Some(msg)
+		};
+		self.complete = true;
+		Ok((response, Some(self.build_transaction()?)))
+	}
+
+	fn build_transaction(&self) -> Result<ConstructedTransaction, &'static str> {
+		let mut input_serial_ids: Vec<&u64> = self.inputs.keys().collect();
+		input_serial_ids.sort_unstable();
+		let mut output_serial_ids: Vec<&u64> = self.outputs.keys().collect();
+		output_serial_ids.sort_unstable();
+
+		let mut constructed = ConstructedTransaction {
+			tx: Transaction { version: INTERACTIVE_TX_VERSION, lock_time: self.locktime, input: Vec::with_capacity(input_serial_ids.len()), output: Vec::with_capacity(output_serial_ids.len()) },
+			holder_input_indexes: Vec::new(),
+			shared_input_index: None,
+			shared_output_index: None,
+			holder_inputs_value: 0,
+			holder_outputs_value: 0,
+			counterparty_inputs_value: 0,
+			counterparty_outputs_value: 0,
+		};
+
+		// The weight each side is responsible for paying the fee on. The initiator pays for the
+		// common fields and the shared input and output, with each side paying for the inputs and
+		// outputs it added.
+		let mut holder_weight = 0;
+		let mut counterparty_weight = 0;
+		{
+			let initiator_weight = if self.is_initiator { &mut holder_weight } else { &mut counterparty_weight };
+			*initiator_weight += TX_COMMON_FIELDS_WEIGHT;
+			if self.shared_input.is_some() { *initiator_weight += SHARED_INPUT_WEIGHT; }
+		}
+
+		let mut total_inputs_value: u64 = 0;
+		for serial_id in input_serial_ids {
+			let input = self.inputs.get(serial_id).unwrap();
+			let idx = constructed.tx.input.len();
+			constructed.tx.input.push(TxIn { previous_output: input.prevout, script_sig: Script::new(), sequence: input.sequence, witness: Vec::new() });
+			total_inputs_value = total_inputs_value.checked_add(input.value).ok_or("Inputs overflowed")?;
+			if input.is_shared {
+				constructed.shared_input_index = Some(idx);
+			} else if input.is_holder {
+				constructed.holder_input_indexes.push(idx);
+				constructed.holder_inputs_value += input.value;
+				holder_weight += P2WPKH_INPUT_WEIGHT;
+			} else {
+				constructed.counterparty_inputs_value += input.value;
+				counterparty_weight += P2WPKH_INPUT_WEIGHT;
+			}
+		}
+		if self.shared_input.is_some() && constructed.shared_input_index.is_none() {
+			return Err("Transaction did not spend the shared input");
+		}
+
+		let mut total_outputs_value: u64 = 0;
+		for serial_id in output_serial_ids {
+			let output = self.outputs.get(serial_id).unwrap();
+			let idx = constructed.tx.output.len();
+			constructed.tx.output.push(output.txout.clone());
+			total_outputs_value = total_outputs_value.checked_add(output.txout.value).ok_or("Outputs overflowed")?;
+			let output_weight = (8 + 1 + output.txout.script_pubkey.len() as u64) * 4;
+			if Some(&output.txout.script_pubkey) == self.shared_output_script.as_ref() {
+				if constructed.shared_output_index.is_some() {
+					return Err("Transaction contained more than one shared output");
+				}
+				constructed.shared_output_index = Some(idx);
+				if self.is_initiator { holder_weight += output_weight; } else { counterparty_weight += output_weight; }
+			} else if output.is_holder {
+				constructed.holder_outputs_value += output.txout.value;
+				holder_weight += output_weight;
+			} else {
+				constructed.counterparty_outputs_value += output.txout.value;
+				counterparty_weight += output_weight;
+			}
+		}
+		if self.shared_output_script.is_some() && constructed.shared_output_index.is_none() {
+			return Err("Transaction did not contain the shared output");
+		}
+		if total_inputs_value < total_outputs_value {
+			return Err("Transaction outputs exceed its inputs");
+		}
+		let feerate = self.funding_feerate_per_kw as u64;
+		let required_fee = feerate * (holder_weight + counterparty_weight) / 1000;
+		if total_inputs_value - total_outputs_value < required_fee {
+			return Err("Transaction fee is below the negotiated feerate");
+		}
+
+		// Compute the non-initiator's share of the fee directly, leaving the initiator to pay the
+		// rest (including any rounding), so that the two shares always sum to required_fee.
+		let (holder_fee, counterparty_fee) = if self.is_initiator {
+			let counterparty_fee = feerate * counterparty_weight / 1000;
+			(required_fee - counterparty_fee, counterparty_fee)
+		} else {
+			let holder_fee = feerate * holder_weight / 1000;
+			(holder_fee, required_fee - holder_fee)
+		};
+		// Input and output values are bounded by MAX_VALUE_MSAT, so these can't overflow an i64.
+		if (constructed.holder_inputs_value as i64) - (constructed.holder_outputs_value as i64) - self.holder_contribution_satoshis < holder_fee as i64 {
+			return Err("Our inputs did not cover our outputs, contribution and share of the fee");
+		}
+		if (constructed.counterparty_inputs_value as i64) - (constructed.counterparty_outputs_value as i64) - self.counterparty_contribution_satoshis < counterparty_fee as i64 {
+			return Err("Counterparty's inputs did not cover its outputs, contribution and share of the fee");
+		}
+		Ok(constructed)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::opcodes;
+	use bitcoin::blockdata::script::{Builder, Script};
+	use bitcoin::blockdata::transaction::{OutPoint as BitcoinOutPoint, Transaction, TxIn, TxOut};
+	use bitcoin::hash_types::Txid;
+	use bitcoin::hashes::Hash;
+
+	use ln::interactivetxs::{InteractiveTxConstructor, InteractiveTxInput, InteractiveTxMessageSend, ConstructedTransaction};
+	use ln::msgs;
+
+	fn p2wsh_script(byte: u8) -> Script {
+		Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[byte; 32]).into_script()
+	}
+
+	fn prevtx(value: u64, byte: u8) -> Transaction {
+		Transaction { version: 2, lock_time: 0, input: vec![TxIn { previous_output: BitcoinOutPoint { txid: Txid::from_slice(&[byte; 32]).unwrap(), vout: 0 }, script_sig: Script::new(), sequence: 0, witness: Vec::new() }], output: vec![TxOut { value, script_pubkey: p2wsh_script(byte) }] }
+	}
+
+	fn deliver(to: &mut InteractiveTxConstructor, msg: InteractiveTxMessageSend) -> Result<(Option<InteractiveTxMessageSend>, Option<ConstructedTransaction>), &'static str> {
+		match msg {
+			InteractiveTxMessageSend::TxAddInput(msg) => to.handle_tx_add_input(&msg).map(|m| (Some(m), None)),
+			InteractiveTxMessageSend::TxAddOutput(msg) => to.handle_tx_add_output(&msg).map(|m| (Some(m), None)),
+			InteractiveTxMessageSend::TxComplete(msg) => to.handle_tx_complete(&msg),
+		}
+	}
+
+	fn run_construction(mut initiator: InteractiveTxConstructor, mut acceptor: InteractiveTxConstructor, first_msg: InteractiveTxMessageSend) -> Result<(ConstructedTransaction, ConstructedTransaction), &'static str> {
+		let mut msg = first_msg;
+		let mut to_acceptor = true;
+		let mut initiator_tx = None;
+		let mut acceptor_tx = None;
+		loop {
+			let (response, tx) = if to_acceptor { deliver(&mut acceptor, msg)? } else { deliver(&mut initiator, msg)? };
+			if let Some(tx) = tx {
+				if to_acceptor { acceptor_tx = Some(tx); } else { initiator_tx = Some(tx); }
+			}
+			match response {
+				Some(response) => { msg = response; to_acceptor = !to_acceptor; },
+				None => break,
+			}
+		}
+		assert!(initiator.is_complete() && acceptor.is_complete());
+		Ok((initiator_tx.unwrap(), acceptor_tx.unwrap()))
+	}
+
+	#[test]
+	fn test_interactive_tx_construction() {
+		let shared_outpoint = BitcoinOutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), vout: 1 };
+		let shared_script = p2wsh_script(42);
+		let initiator_prevtx = prevtx(50_000, 1);
+		let acceptor_prevtx = prevtx(30_000, 2);
+
+		let (initiator, first_msg) = InteractiveTxConstructor::new([0; 32], true, 100, 253, 49_000, 0, Some((shared_outpoint, 100_000)), Some(shared_script.clone()),
+			vec![InteractiveTxInput { prevtx: None, prevtx_out: 1, sequence: 0xfffffffd }, InteractiveTxInput { prevtx: Some(initiator_prevtx.clone()), prevtx_out: 0, sequence: 0xfffffffd }],
+			vec![TxOut { value: 149_000, script_pubkey: shared_script.clone() }]);
+		let (acceptor, no_msg) = InteractiveTxConstructor::new([0; 32], false, 100, 253, 0, 49_000, Some((shared_outpoint, 100_000)), Some(shared_script.clone()),
+			vec![InteractiveTxInput { prevtx: Some(acceptor_prevtx.clone()), prevtx_out: 0, sequence: 0xfffffffd }],
+			vec![TxOut { value: 29_000, script_pubkey: p2wsh_script(3) }]);
+		assert!(no_msg.is_none());
+
+		let (initiator_tx, acceptor_tx) = run_construction(initiator, acceptor, first_msg.unwrap()).unwrap();
+		assert_eq!(initiator_tx.tx, acceptor_tx.tx);
+		let tx = initiator_tx.tx;
+		assert_eq!(tx.version, 2);
+		assert_eq!(tx.lock_time, 100);
+		// Inputs and outputs are ordered by serial_id, alternating between the initiator's even
+		// serial_ids and the acceptor's odd ones.
+		assert_eq!(tx.input.len(), 3);
+		assert_eq!(tx.input[0].previous_output, shared_outpoint);
+		assert_eq!(tx.input[1].previous_output.txid, acceptor_prevtx.txid());
+		assert_eq!(tx.input[2].previous_output.txid, initiator_prevtx.txid());
+		assert_eq!(tx.output.len(), 2);
+
+		assert_eq!(initiator_tx.shared_input_index, Some(0));
+		assert_eq!(initiator_tx.holder_input_indexes, vec![2]);
+		assert_eq!(initiator_tx.holder_inputs_value, 50_000);
+		assert_eq!(initiator_tx.counterparty_inputs_value, 30_000);
+		assert_eq!(initiator_tx.holder_outputs_value, 0);
+		assert_eq!(initiator_tx.counterparty_outputs_value, 29_000);
+		assert_eq!(tx.output[initiator_tx.shared_output_index.unwrap()].value, 149_000);
+
+		assert_eq!(acceptor_tx.holder_input_indexes, vec![1]);
+		assert_eq!(acceptor_tx.holder_inputs_value, 30_000);
+		assert_eq!(acceptor_tx.counterparty_inputs_value, 50_000);
+		assert_eq!(acceptor_tx.shared_output_index, initiator_tx.shared_output_index);
+	}
+
+	#[test]
+	fn test_interactive_tx_acceptor_contributes_nothing() {
+		let shared_outpoint = BitcoinOutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), vout: 0 };
+		let shared_script = p2wsh_script(42);
+		let (initiator, first_msg) = InteractiveTxConstructor::new([0; 32], true, 0, 253, -40_000, 0, Some((shared_outpoint, 100_000)), Some(shared_script.clone()),
+			vec![InteractiveTxInput { prevtx: None, prevtx_out: 0, sequence: 0xfffffffd }],
+			vec![TxOut { value: 60_000, script_pubkey: shared_script.clone() }, TxOut { value: 39_000, script_pubkey: p2wsh_script(5) }]);
+		let (acceptor, _) = InteractiveTxConstructor::new([0; 32], false, 0, 253, 0, -40_000, Some((shared_outpoint, 100_000)), Some(shared_script.clone()), Vec::new(), Vec::new());
+		let (initiator_tx, acceptor_tx) = run_construction(initiator, acceptor, first_msg.unwrap()).unwrap();
+		assert_eq!(initiator_tx.tx, acceptor_tx.tx);
+		assert_eq!(initiator_tx.holder_outputs_value, 39_000);
+		assert_eq!(acceptor_tx.counterparty_outputs_value, 39_000);
+		assert!(acceptor_tx.holder_input_indexes.is_empty());
+	}
+
+	#[test]
+	fn test_interactive_tx_fee_below_feerate() {
+		// The initiator splices out 39_000 sat, paying a 1_000 sat fee, which is below the
+		// negotiated 2_000 sat/kW for the transaction's 772 weight.
+		let shared_outpoint = BitcoinOutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), vout: 0 };
+		let shared_script = p2wsh_script(42);
+		let (initiator, first_msg) = InteractiveTxConstructor::new([0; 32], true, 0, 2_000, -40_000, 0, Some((shared_outpoint, 100_000)), Some(shared_script.clone()),
+			vec![InteractiveTxInput { prevtx: None, prevtx_out: 0, sequence: 0xfffffffd }],
+			vec![TxOut { value: 60_000, script_pubkey: shared_script.clone() }, TxOut { value: 39_000, script_pubkey: p2wsh_script(5) }]);
+		let (acceptor, _) = InteractiveTxConstructor::new([0; 32], false, 0, 2_000, 0, -40_000, Some((shared_outpoint, 100_000)), Some(shared_script.clone()), Vec::new(), Vec::new());
+		assert_eq!(run_construction(initiator, acceptor, first_msg.unwrap()).err(), Some("Transaction fee is below the negotiated feerate"));
+	}
+
+	#[test]
+	fn test_interactive_tx_contribution_not_covered() {
+		// The transaction as a whole pays a 1_550 sat fee, well above the negotiated feerate, but
+		// the acceptor's input only covers its output and 50 sat of fee, less than its 112 sat share.
+		let shared_outpoint = BitcoinOutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), vout: 1 };
+		let shared_script = p2wsh_script(42);
+		let (initiator, first_msg) = InteractiveTxConstructor::new([0; 32], true, 0, 253, 49_000, 0, Some((shared_outpoint, 100_000)), Some(shared_script.clone()),
+			vec![InteractiveTxInput { prevtx: None, prevtx_out: 1, sequence: 0xfffffffd }, InteractiveTxInput { prevtx: Some(prevtx(50_500, 1)), prevtx_out: 0, sequence: 0xfffffffd }],
+			vec![TxOut { value: 149_000, script_pubkey: shared_script.clone() }]);
+		let (acceptor, _) = InteractiveTxConstructor::new([0; 32], false, 0, 253, 0, 49_000, Some((shared_outpoint, 100_000)), Some(shared_script.clone()),
+			vec![InteractiveTxInput { prevtx: Some(prevtx(30_000, 2)), prevtx_out: 0, sequence: 0xfffffffd }],
+			vec![TxOut { value: 29_950, script_pubkey: p2wsh_script(3) }]);
+		assert_eq!(run_construction(initiator, acceptor, first_msg.unwrap()).err(), Some("Counterparty's inputs did not cover its outputs, contribution and share of the fee"));
+	}
+
+	#[test]
+	fn test_interactive_tx_invalid_messages() {
+		let shared_outpoint = BitcoinOutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), vout: 0 };
+		let (mut acceptor, _) = InteractiveTxConstructor::new([0; 32], false, 0, 253, 0, 0, Some((shared_outpoint, 100_000)), Some(p2wsh_script(42)), Vec::new(), Vec::new());
+		let tx = prevtx(10_000, 7);
+
+		// The initiator must use even serial_ids
+		assert!(acceptor.handle_tx_add_input(&msgs::TxAddInput { channel_id: [0; 32], serial_id: 1, prevtx: Some(tx.clone()), prevtx_out: 0, sequence: 0, shared_input_txid: None }).is_err());
+		// prevtx_out must exist
+		assert!(acceptor.handle_tx_add_input(&msgs::TxAddInput { channel_id: [0; 32], serial_id: 0, prevtx: Some(tx.clone()), prevtx_out: 1, sequence: 0, shared_input_txid: None }).is_err());
+		// Only the known shared input may be spent without a prevtx
+		assert!(acceptor.handle_tx_add_input(&msgs::TxAddInput { channel_id: [0; 32], serial_id: 0, prevtx: None, prevtx_out: 1, sequence: 0, shared_input_txid: Some(shared_outpoint.txid) }).is_err());
+
+		let mut non_segwit_tx = tx.clone();
+		non_segwit_tx.output[0].script_pubkey = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_1).into_script();
+		assert!(acceptor.handle_tx_add_input(&msgs::TxAddInput { channel_id: [0; 32], serial_id: 0, prevtx: Some(non_segwit_tx), prevtx_out: 0, sequence: 0, shared_input_txid: None }).is_err());
+
+		assert!(acceptor.handle_tx_add_input(&msgs::TxAddInput { channel_id: [0; 32], serial_id: 0, prevtx: Some(tx.clone()), prevtx_out: 0, sequence: 0, shared_input_txid: None }).is_ok());
+		// Duplicate serial_ids and prevouts are rejected
+		assert!(acceptor.handle_tx_add_input(&msgs::TxAddInput { channel_id: [0; 32], serial_id: 0, prevtx: Some(prevtx(1, 8)), prevtx_out: 0, sequence: 0, shared_input_txid: None }).is_err());
+		assert!(acceptor.handle_tx_add_input(&msgs::TxAddInput { channel_id: [0; 32], serial_id: 2, prevtx: Some(tx.clone()), prevtx_out: 0, sequence: 0, shared_input_txid: None }).is_err());
+		// Removing it allows it to be re-added
+		assert!(acceptor.handle_tx_remove_input(&msgs::TxRemoveInput { channel_id: [0; 32], serial_id: 0 }).is_ok());
+		assert!(acceptor.handle_tx_add_input(&msgs::TxAddInput { channel_id: [0; 32], serial_id: 2, prevtx: Some(tx.clone()), prevtx_out: 0, sequence: 0, shared_input_txid: None }).is_ok());
+
+		assert!(acceptor.handle_tx_add_output(&msgs::TxAddOutput { channel_id: [0; 32], serial_id: 4, sats: 0, script: p2wsh_script(1) }).is_err());
+		assert!(acceptor.handle_tx_add_output(&msgs::TxAddOutput { channel_id: [0; 32], serial_id: 4, sats: 21_000_000_0000_0001, script: p2wsh_script(1) }).is_err());
+
+		// Completing without the shared input or output fails
+		assert!(acceptor.handle_tx_complete(&msgs::TxComplete { channel_id: [0; 32] }).is_err());
+	}
+}
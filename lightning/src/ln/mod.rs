@@ -24,6 +24,8 @@ pub mod msgs;
 pub mod peer_handler;
 pub mod chan_utils;
 pub mod features;
+pub mod payment_retry;
+pub mod watchtower;
 pub(crate) mod onchaintx;
 
 #[cfg(feature = "fuzztarget")]
@@ -32,6 +34,7 @@ pub mod peer_channel_encryptor;
 pub(crate) mod peer_channel_encryptor;
 
 mod channel;
+mod interactivetxs;
 mod onion_utils;
 mod wire;
 
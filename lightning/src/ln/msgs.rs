@@ -28,7 +28,9 @@ use bitcoin::secp256k1::key::PublicKey;
 use bitcoin::secp256k1::Signature;
 use bitcoin::secp256k1;
 use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::hash_types::{Txid, BlockHash};
+use bitcoin::consensus::encode::{Encodable, Decodable};
 
 use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
 
@@ -177,6 +179,87 @@ pub struct AcceptChannel {
 	pub shutdown_scriptpubkey: OptionalField<Script>,
 }
 
+/// An open_channel2 message to be sent or received from a peer, opening a channel to which both
+/// sides may contribute funds
+#[derive(Clone)]
+pub struct OpenChannelV2 {
+	/// The genesis hash of the blockchain where the channel is to be opened
+	pub chain_hash: BlockHash,
+	/// A temporary channel ID, until the acceptor's revocation basepoint is known
+	pub temporary_channel_id: [u8; 32],
+	/// The feerate for the funding transaction
+	pub funding_feerate_perkw: u32,
+	/// The feerate per 1000-weight of sender generated commitment transactions, until updated by
+	/// update_fee
+	pub commitment_feerate_perkw: u32,
+	/// The amount the sender is contributing to the channel
+	pub funding_satoshis: u64,
+	/// The threshold below which outputs on transactions broadcast by sender will be omitted
+	pub dust_limit_satoshis: u64,
+	/// The maximum inbound HTLC value in flight towards sender, in milli-satoshi
+	pub max_htlc_value_in_flight_msat: u64,
+	/// The minimum HTLC size incoming to sender, in milli-satoshi
+	pub htlc_minimum_msat: u64,
+	/// The number of blocks which the counterparty will have to wait to claim on-chain funds if they broadcast a commitment transaction
+	pub to_self_delay: u16,
+	/// The maximum number of inbound HTLCs towards sender
+	pub max_accepted_htlcs: u16,
+	/// The locktime for the funding transaction
+	pub locktime: u32,
+	/// The sender's key controlling the funding transaction
+	pub funding_pubkey: PublicKey,
+	/// Used to derive a revocation key for transactions broadcast by counterparty
+	pub revocation_basepoint: PublicKey,
+	/// A payment key to sender for transactions broadcast by counterparty
+	pub payment_point: PublicKey,
+	/// Used to derive a payment key to sender for transactions broadcast by sender
+	pub delayed_payment_basepoint: PublicKey,
+	/// Used to derive an HTLC payment key to sender
+	pub htlc_basepoint: PublicKey,
+	/// The first to-be-broadcast-by-sender transaction's per commitment point
+	pub first_per_commitment_point: PublicKey,
+	/// Channel flags
+	pub channel_flags: u8,
+	/// Optionally, a request to pre-set the to-sender output's scriptPubkey for when we collaboratively close
+	pub shutdown_scriptpubkey: OptionalField<Script>,
+}
+
+/// An accept_channel2 message to be sent or received from a peer in response to an
+/// open_channel2
+#[derive(Clone)]
+pub struct AcceptChannelV2 {
+	/// The same temporary channel ID as in the open_channel2
+	pub temporary_channel_id: [u8; 32],
+	/// The amount the sender is contributing to the channel
+	pub funding_satoshis: u64,
+	/// The threshold below which outputs on transactions broadcast by sender will be omitted
+	pub dust_limit_satoshis: u64,
+	/// The maximum inbound HTLC value in flight towards sender, in milli-satoshi
+	pub max_htlc_value_in_flight_msat: u64,
+	/// The minimum HTLC size incoming to sender, in milli-satoshi
+	pub htlc_minimum_msat: u64,
+	/// Minimum depth of the funding transaction before the channel is considered open
+	pub minimum_depth: u32,
+	/// The number of blocks which the counterparty will have to wait to claim on-chain funds if they broadcast a commitment transaction
+	pub to_self_delay: u16,
+	/// The maximum number of inbound HTLCs towards sender
+	pub max_accepted_htlcs: u16,
+	/// The sender's key controlling the funding transaction
+	pub funding_pubkey: PublicKey,
+	/// Used to derive a revocation key for transactions broadcast by counterparty
+	pub revocation_basepoint: PublicKey,
+	/// A payment key to sender for transactions broadcast by counterparty
+	pub payment_point: PublicKey,
+	/// Used to derive a payment key to sender for transactions broadcast by sender
+	pub delayed_payment_basepoint: PublicKey,
+	/// Used to derive an HTLC payment key to sender for transactions broadcast by counterparty
+	pub htlc_basepoint: PublicKey,
+	/// The first to-be-broadcast-by-sender transaction's per commitment point
+	pub first_per_commitment_point: PublicKey,
+	/// Optionally, a request to pre-set the to-sender output's scriptPubkey for when we collaboratively close
+	pub shutdown_scriptpubkey: OptionalField<Script>,
+}
+
 /// A funding_created message to be sent or received from a peer
 #[derive(Clone)]
 pub struct FundingCreated {
@@ -218,6 +301,19 @@ pub struct Shutdown {
 	pub scriptpubkey: Script,
 }
 
+/// The minimum and maximum fees which the sender is willing to place on the closing transaction.
+/// This is provided in ClosingSigned by both sides to indicate the fee range they are willing to
+/// use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClosingSignedFeeRange {
+	/// The minimum absolute fee, in satoshis, which the sender is willing to place on the closing
+	/// transaction.
+	pub min_fee_satoshis: u64,
+	/// The maximum absolute fee, in satoshis, which the sender is willing to place on the closing
+	/// transaction.
+	pub max_fee_satoshis: u64,
+}
+
 /// A closing_signed message to be sent or received from a peer
 #[derive(Clone, PartialEq)]
 pub struct ClosingSigned {
@@ -227,6 +323,9 @@ pub struct ClosingSigned {
 	pub fee_satoshis: u64,
 	/// A signature on the closing transaction
 	pub signature: Signature,
+	/// The minimum and maximum fees which the sender is willing to accept, provided only by new
+	/// nodes.
+	pub fee_range: Option<ClosingSignedFeeRange>,
 }
 
 /// An update_add_htlc message to be sent or received from a peer
@@ -278,6 +377,16 @@ pub struct UpdateFailMalformedHTLC {
 	pub failure_code: u16,
 }
 
+/// The signatures on a commitment transaction spending the funding output of a splice which has
+/// yet to lock, sent alongside those on the commitment transaction spending the current one.
+#[derive(Clone, PartialEq)]
+pub struct SpliceCommitmentSignatures {
+	/// A signature on the commitment transaction
+	pub signature: Signature,
+	/// Signatures on the HTLC transactions
+	pub htlc_signatures: Vec<Signature>,
+}
+
 /// A commitment_signed message to be sent or received from a peer
 #[derive(Clone, PartialEq)]
 pub struct CommitmentSigned {
@@ -287,6 +396,9 @@ pub struct CommitmentSigned {
 	pub signature: Signature,
 	/// Signatures on the HTLC transactions
 	pub htlc_signatures: Vec<Signature>,
+	/// The signatures on the same commitment transaction spending the funding output of the splice
+	/// which is waiting to lock, if any.
+	pub splice_signatures: Option<SpliceCommitmentSignatures>,
 }
 
 /// A revoke_and_ack message to be sent or received from a peer
@@ -348,6 +460,142 @@ pub struct AnnouncementSignatures {
 	pub bitcoin_signature: Signature,
 }
 
+/// A stfu message to be sent or received from a peer, indicating the sender will not send any
+/// further channel updates until the channel has been quiesced and the quiescent operation
+/// (currently only splicing) has completed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stfu {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// 1 if the sender is the one which requested quiescence, 0 otherwise
+	pub initiator: u8,
+}
+
+/// A splice_init message to be sent or received from a peer, proposing a change to the channel's
+/// funding output once the channel is quiescent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpliceInit {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The amount, in satoshis, the sender is adding to (if positive) or removing from (if
+	/// negative) the channel
+	pub funding_contribution_satoshis: i64,
+	/// The feerate for the splice transaction
+	pub funding_feerate_perkw: u32,
+	/// The locktime for the splice transaction
+	pub locktime: u32,
+	/// The sender's key controlling the new funding output
+	pub funding_pubkey: PublicKey,
+}
+
+/// A splice_ack message to be sent or received from a peer in response to a splice_init
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpliceAck {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The amount, in satoshis, the sender is adding to (if positive) or removing from (if
+	/// negative) the channel
+	pub funding_contribution_satoshis: i64,
+	/// The sender's key controlling the new funding output
+	pub funding_pubkey: PublicKey,
+}
+
+/// A splice_locked message to be sent or received from a peer, indicating the splice transaction
+/// has reached the sender's required confirmation depth.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpliceLocked {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The ID of the splice transaction
+	pub splice_txid: Txid,
+}
+
+/// A tx_add_input message to be sent or received from a peer while interactively constructing a
+/// transaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxAddInput {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// A unique identifier for this input, which must be even if the sender initiated the
+	/// construction and odd otherwise
+	pub serial_id: u64,
+	/// The transaction containing the output being spent, or None for the shared channel input
+	pub prevtx: Option<Transaction>,
+	/// The index of the output being spent
+	pub prevtx_out: u32,
+	/// The sequence number of this input
+	pub sequence: u32,
+	/// The txid of the channel's current funding transaction, if this input spends it
+	pub shared_input_txid: Option<Txid>,
+}
+
+/// A tx_add_output message to be sent or received from a peer while interactively constructing a
+/// transaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxAddOutput {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// A unique identifier for this output, which must be even if the sender initiated the
+	/// construction and odd otherwise
+	pub serial_id: u64,
+	/// The satoshi value of the output
+	pub sats: u64,
+	/// The scriptPubKey for the output
+	pub script: Script,
+}
+
+/// A tx_remove_input message to be sent or received from a peer, removing an input it
+/// previously added
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxRemoveInput {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The serial ID of the input to remove
+	pub serial_id: u64,
+}
+
+/// A tx_remove_output message to be sent or received from a peer, removing an output it
+/// previously added
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxRemoveOutput {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The serial ID of the output to remove
+	pub serial_id: u64,
+}
+
+/// A tx_complete message to be sent or received from a peer, indicating the sender has no
+/// further inputs or outputs to add
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxComplete {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+}
+
+/// A tx_signatures message to be sent or received from a peer once an interactively-constructed
+/// transaction is complete
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxSignatures {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The txid of the transaction being signed
+	pub txid: Txid,
+	/// The witness stacks for each of the sender's inputs, in serial ID order
+	pub witnesses: Vec<Vec<Vec<u8>>>,
+	/// The sender's signature spending the shared channel input, if any
+	pub shared_input_signature: Option<Signature>,
+}
+
+/// A tx_abort message to be sent or received from a peer, cancelling an in-progress interactive
+/// transaction construction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxAbort {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// A possibly human-readable description of why the construction was aborted
+	pub data: Vec<u8>,
+}
+
 /// An address which can be used to connect to a remote peer
 #[derive(Clone, PartialEq, Debug)]
 pub enum NetAddress {
@@ -584,6 +832,17 @@ pub struct QueryChannelRange {
 	pub number_of_blocks: u32,
 }
 
+impl QueryChannelRange {
+	/// Calculates the overflow safe ending block height for the query.
+	/// Overflow returns `0xffffffff`, otherwise returns `first_blocknum + number_of_blocks`
+	pub fn end_blocknum(&self) -> u32 {
+		match self.first_blocknum.checked_add(self.number_of_blocks) {
+			Some(block) => block,
+			None => u32::max_value(),
+		}
+	}
+}
+
 /// A reply_channel_range message is a reply to a query_channel_range
 /// message. Multiple reply_channel_range messages can be sent in reply
 /// to a single query_channel_range message. The query recipient makes a
@@ -755,6 +1014,10 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 	fn handle_funding_signed(&self, their_node_id: &PublicKey, msg: &FundingSigned);
 	/// Handle an incoming funding_locked message from the given peer.
 	fn handle_funding_locked(&self, their_node_id: &PublicKey, msg: &FundingLocked);
+	/// Handle an incoming open_channel2 message from the given peer.
+	fn handle_open_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &OpenChannelV2);
+	/// Handle an incoming accept_channel2 message from the given peer.
+	fn handle_accept_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &AcceptChannelV2);
 
 	// Channl close:
 	/// Handle an incoming shutdown message from the given peer.
@@ -762,6 +1025,30 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 	/// Handle an incoming closing_signed message from the given peer.
 	fn handle_closing_signed(&self, their_node_id: &PublicKey, msg: &ClosingSigned);
 
+	// Quiescence and splicing:
+	/// Handle an incoming stfu message from the given peer.
+	fn handle_stfu(&self, their_node_id: &PublicKey, msg: &Stfu);
+	/// Handle an incoming splice_init message from the given peer.
+	fn handle_splice_init(&self, their_node_id: &PublicKey, msg: &SpliceInit);
+	/// Handle an incoming splice_ack message from the given peer.
+	fn handle_splice_ack(&self, their_node_id: &PublicKey, msg: &SpliceAck);
+	/// Handle an incoming splice_locked message from the given peer.
+	fn handle_splice_locked(&self, their_node_id: &PublicKey, msg: &SpliceLocked);
+	/// Handle an incoming tx_add_input message from the given peer.
+	fn handle_tx_add_input(&self, their_node_id: &PublicKey, msg: &TxAddInput);
+	/// Handle an incoming tx_add_output message from the given peer.
+	fn handle_tx_add_output(&self, their_node_id: &PublicKey, msg: &TxAddOutput);
+	/// Handle an incoming tx_remove_input message from the given peer.
+	fn handle_tx_remove_input(&self, their_node_id: &PublicKey, msg: &TxRemoveInput);
+	/// Handle an incoming tx_remove_output message from the given peer.
+	fn handle_tx_remove_output(&self, their_node_id: &PublicKey, msg: &TxRemoveOutput);
+	/// Handle an incoming tx_complete message from the given peer.
+	fn handle_tx_complete(&self, their_node_id: &PublicKey, msg: &TxComplete);
+	/// Handle an incoming tx_signatures message from the given peer.
+	fn handle_tx_signatures(&self, their_node_id: &PublicKey, msg: &TxSignatures);
+	/// Handle an incoming tx_abort message from the given peer.
+	fn handle_tx_abort(&self, their_node_id: &PublicKey, msg: &TxAbort);
+
 	// HTLC handling:
 	/// Handle an incoming update_add_htlc message from the given peer.
 	fn handle_update_add_htlc(&self, their_node_id: &PublicKey, msg: &UpdateAddHTLC);
@@ -792,6 +1079,11 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 
 	/// Handle a peer reconnecting, possibly generating channel_reestablish message(s).
 	fn peer_connected(&self, their_node_id: &PublicKey, msg: &Init);
+	/// Indicates whether option_anchor_outputs should be advertised in the init messages we send
+	/// to peers, ie whether new channels should use anchor outputs where the peer supports them.
+	///
+	/// Defaults to false.
+	fn supports_anchor_outputs(&self) -> bool { false }
 	/// Handle an incoming channel_reestablish message from the given peer.
 	fn handle_channel_reestablish(&self, their_node_id: &PublicKey, msg: &ChannelReestablish);
 
@@ -801,7 +1093,10 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 }
 
 /// A trait to describe an object which can receive routing messages.
-pub trait RoutingMessageHandler : Send + Sync {
+///
+/// Replies to gossip queries are not returned directly but are instead provided via
+/// MessageSendEvents, which must be fetched (eg by the PeerManager) and sent to the relevant peer.
+pub trait RoutingMessageHandler : events::MessageSendEventsProvider + Send + Sync {
 	/// Handle an incoming node_announcement message, returning true if it should be forwarded on,
 	/// false or returning an Err otherwise.
 	fn handle_node_announcement(&self, msg: &NodeAnnouncement) -> Result<bool, LightningError>;
@@ -824,10 +1119,30 @@ pub trait RoutingMessageHandler : Send + Sync {
 	fn get_next_node_announcements(&self, starting_point: Option<&PublicKey>, batch_amount: u8) -> Vec<NodeAnnouncement>;
 	/// Returns whether a full sync should be requested from a peer.
 	fn should_request_full_sync(&self, node_id: &PublicKey) -> bool;
+	/// Handles an incoming query_channel_range message from the given peer, generating one or more
+	/// reply_channel_range messages which together cover the full queried block range.
+	fn handle_query_channel_range(&self, their_node_id: &PublicKey, msg: &QueryChannelRange) -> Result<(), LightningError>;
+	/// Handles an incoming query_short_channel_ids message from the given peer, generating the
+	/// known announcements and updates for each requested short_channel_id followed by a
+	/// reply_short_channel_ids_end message.
+	fn handle_query_short_channel_ids(&self, their_node_id: &PublicKey, msg: &QueryShortChannelIds) -> Result<(), LightningError>;
+	/// Called when a connection is established with a peer, after their init message has been
+	/// received. If the peer supports gossip_queries, this should begin syncing our routing table
+	/// with theirs and eventually set a gossip_timestamp_filter so that the peer sends us gossip.
+	fn sync_routing_table(&self, their_node_id: &PublicKey, init: &Init);
+	/// Indicates a connection to the given peer was closed, abandoning any routing table sync
+	/// in progress with them.
+	fn peer_disconnected(&self, their_node_id: &PublicKey);
+	/// Handles an incoming reply_channel_range message from the given peer, in response to a
+	/// query_channel_range we sent during sync_routing_table.
+	fn handle_reply_channel_range(&self, their_node_id: &PublicKey, msg: &ReplyChannelRange) -> Result<(), LightningError>;
+	/// Handles an incoming reply_short_channel_ids_end message from the given peer, indicating
+	/// that it has finished replying to a query_short_channel_ids we sent.
+	fn handle_reply_short_channel_ids_end(&self, their_node_id: &PublicKey, msg: &ReplyShortChannelIdsEnd) -> Result<(), LightningError>;
 }
 
 mod fuzzy_internal_msgs {
-	use ln::channelmanager::PaymentSecret;
+	use ln::channelmanager::{PaymentPreimage, PaymentSecret};
 
 	// These types aren't intended to be pub, but are exposed for direct fuzzing (as we deserialize
 	// them from untrusted input):
@@ -848,6 +1163,8 @@ mod fuzzy_internal_msgs {
 		},
 		FinalNode {
 			payment_data: Option<FinalOnionHopData>,
+			/// The preimage of a spontaneous (keysend) payment, chosen by the sender.
+			keysend_preimage: Option<PaymentPreimage>,
 		},
 	}
 
@@ -997,6 +1314,27 @@ impl_writeable_len_match!(AcceptChannel, {
 	shutdown_scriptpubkey
 });
 
+impl_writeable_len_match!(AcceptChannelV2, {
+		{ AcceptChannelV2 { shutdown_scriptpubkey: OptionalField::Present(ref script), .. }, 270 + 2 + script.len() },
+		{ _, 270 }
+	}, {
+	temporary_channel_id,
+	funding_satoshis,
+	dust_limit_satoshis,
+	max_htlc_value_in_flight_msat,
+	htlc_minimum_msat,
+	minimum_depth,
+	to_self_delay,
+	max_accepted_htlcs,
+	funding_pubkey,
+	revocation_basepoint,
+	payment_point,
+	delayed_payment_basepoint,
+	htlc_basepoint,
+	first_per_commitment_point,
+	shutdown_scriptpubkey
+});
+
 impl_writeable!(AnnouncementSignatures, 32+8+64*2, {
 	channel_id,
 	short_channel_id,
@@ -1042,20 +1380,75 @@ impl Readable for ChannelReestablish{
 	}
 }
 
-impl_writeable!(ClosingSigned, 32+8+64, {
-	channel_id,
-	fee_satoshis,
-	signature
+impl_writeable!(ClosingSignedFeeRange, 8+8, {
+	min_fee_satoshis,
+	max_fee_satoshis
 });
 
-impl_writeable_len_match!(CommitmentSigned, {
-		{ CommitmentSigned { ref htlc_signatures, .. }, 32+64+2+htlc_signatures.len()*64 }
+impl Writeable for ClosingSigned {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 8 + 64 + if self.fee_range.is_some() { 1+1+8+8 } else { 0 });
+		self.channel_id.write(w)?;
+		self.fee_satoshis.write(w)?;
+		self.signature.write(w)?;
+		if let Some(ref fee_range) = self.fee_range {
+			encode_tlv!(w, {
+				(1, fee_range)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for ClosingSigned {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let fee_satoshis = Readable::read(r)?;
+		let signature = Readable::read(r)?;
+		let mut fee_range: Option<ClosingSignedFeeRange> = None;
+		decode_tlv!(&mut *r, {}, {
+			(1, fee_range)
+		});
+		Ok(Self { channel_id, fee_satoshis, signature, fee_range })
+	}
+}
+
+impl_writeable_len_match!(SpliceCommitmentSignatures, {
+		{ SpliceCommitmentSignatures { ref htlc_signatures, .. }, 64+2+htlc_signatures.len()*64 }
 	}, {
-	channel_id,
 	signature,
 	htlc_signatures
 });
 
+impl Writeable for CommitmentSigned {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 64 + 2 + self.htlc_signatures.len() * 64 +
+			if let Some(ref splice_signatures) = self.splice_signatures { 1 + 3 + 64 + 2 + splice_signatures.htlc_signatures.len() * 64 } else { 0 });
+		self.channel_id.write(w)?;
+		self.signature.write(w)?;
+		self.htlc_signatures.write(w)?;
+		if let Some(ref splice_signatures) = self.splice_signatures {
+			encode_tlv!(w, {
+				(1, splice_signatures)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for CommitmentSigned {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let signature = Readable::read(r)?;
+		let htlc_signatures = Readable::read(r)?;
+		let mut splice_signatures: Option<SpliceCommitmentSignatures> = None;
+		decode_tlv!(&mut *r, {}, {
+			(1, splice_signatures)
+		});
+		Ok(Self { channel_id, signature, htlc_signatures, splice_signatures })
+	}
+}
+
 impl_writeable_len_match!(DecodedOnionErrorPacket, {
 		{ DecodedOnionErrorPacket { ref failuremsg, ref pad, .. }, 32 + 4 + failuremsg.len() + pad.len() }
 	}, {
@@ -1125,6 +1518,31 @@ impl_writeable_len_match!(OpenChannel, {
 	shutdown_scriptpubkey
 });
 
+impl_writeable_len_match!(OpenChannelV2, {
+		{ OpenChannelV2 { shutdown_scriptpubkey: OptionalField::Present(ref script), .. }, 311 + 2 + script.len() },
+		{ _, 311 }
+	}, {
+	chain_hash,
+	temporary_channel_id,
+	funding_feerate_perkw,
+	commitment_feerate_perkw,
+	funding_satoshis,
+	dust_limit_satoshis,
+	max_htlc_value_in_flight_msat,
+	htlc_minimum_msat,
+	to_self_delay,
+	max_accepted_htlcs,
+	locktime,
+	funding_pubkey,
+	revocation_basepoint,
+	payment_point,
+	delayed_payment_basepoint,
+	htlc_basepoint,
+	first_per_commitment_point,
+	channel_flags,
+	shutdown_scriptpubkey
+});
+
 impl_writeable!(RevokeAndACK, 32+32+33, {
 	channel_id,
 	per_commitment_secret,
@@ -1138,6 +1556,164 @@ impl_writeable_len_match!(Shutdown, {
 	scriptpubkey
 });
 
+impl_writeable!(Stfu, 32+1, {
+	channel_id,
+	initiator
+});
+
+impl_writeable!(SpliceInit, 32+8+4+4+33, {
+	channel_id,
+	funding_contribution_satoshis,
+	funding_feerate_perkw,
+	locktime,
+	funding_pubkey
+});
+
+impl_writeable!(SpliceAck, 32+8+33, {
+	channel_id,
+	funding_contribution_satoshis,
+	funding_pubkey
+});
+
+impl_writeable!(SpliceLocked, 32+32, {
+	channel_id,
+	splice_txid
+});
+
+impl Writeable for TxAddInput {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.channel_id.write(w)?;
+		self.serial_id.write(w)?;
+		// prevtx is a u16-length-prefixed consensus-encoded transaction, which is left empty when
+		// the shared channel input is being spent.
+		match self.prevtx {
+			Some(ref tx) => {
+				let encoded = ::bitcoin::consensus::encode::serialize(tx);
+				encoded.write(w)?;
+			},
+			None => 0u16.write(w)?,
+		}
+		self.prevtx_out.write(w)?;
+		self.sequence.write(w)?;
+		if let Some(ref shared_input_txid) = self.shared_input_txid {
+			encode_tlv!(w, {
+				(0, shared_input_txid)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TxAddInput {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let serial_id = Readable::read(r)?;
+		let prevtx_bytes: Vec<u8> = Readable::read(r)?;
+		let prevtx = if prevtx_bytes.is_empty() { None } else {
+			match ::bitcoin::consensus::encode::deserialize(&prevtx_bytes) {
+				Ok(tx) => Some(tx),
+				Err(_) => return Err(DecodeError::InvalidValue),
+			}
+		};
+		let prevtx_out = Readable::read(r)?;
+		let sequence = Readable::read(r)?;
+		let mut shared_input_txid: Option<Txid> = None;
+		decode_tlv!(&mut *r, {}, {
+			(0, shared_input_txid)
+		});
+		if prevtx.is_none() == shared_input_txid.is_none() {
+			return Err(DecodeError::InvalidValue);
+		}
+		Ok(Self { channel_id, serial_id, prevtx, prevtx_out, sequence, shared_input_txid })
+	}
+}
+
+impl_writeable_len_match!(TxAddOutput, {
+		{ TxAddOutput { ref script, .. }, 32+8+8+2+script.len() }
+	}, {
+	channel_id,
+	serial_id,
+	sats,
+	script
+});
+
+impl_writeable!(TxRemoveInput, 32+8, {
+	channel_id,
+	serial_id
+});
+
+impl_writeable!(TxRemoveOutput, 32+8, {
+	channel_id,
+	serial_id
+});
+
+impl_writeable!(TxComplete, 32, {
+	channel_id
+});
+
+impl Writeable for TxSignatures {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.channel_id.write(w)?;
+		self.txid.write(w)?;
+		(self.witnesses.len() as u16).write(w)?;
+		for witness in self.witnesses.iter() {
+			// Each witness is a u16-length-prefixed consensus-encoded witness stack
+			let mut encoded = Vec::new();
+			::bitcoin::consensus::encode::VarInt(witness.len() as u64).consensus_encode(&mut encoded).unwrap();
+			for element in witness.iter() {
+				element.consensus_encode(&mut encoded).unwrap();
+			}
+			encoded.write(w)?;
+		}
+		if let Some(ref shared_input_signature) = self.shared_input_signature {
+			encode_tlv!(w, {
+				(0, shared_input_signature)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TxSignatures {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let txid = Readable::read(r)?;
+		let witness_count: u16 = Readable::read(r)?;
+		let mut witnesses = Vec::with_capacity(cmp::min(witness_count as usize, 64));
+		for _ in 0..witness_count {
+			let encoded: Vec<u8> = Readable::read(r)?;
+			let mut cursor = ::std::io::Cursor::new(&encoded[..]);
+			let element_count: ::bitcoin::consensus::encode::VarInt = match Decodable::consensus_decode(&mut cursor) {
+				Ok(count) => count,
+				Err(_) => return Err(DecodeError::InvalidValue),
+			};
+			let mut witness = Vec::with_capacity(cmp::min(element_count.0 as usize, 64));
+			for _ in 0..element_count.0 {
+				match Decodable::consensus_decode(&mut cursor) {
+					Ok(element) => witness.push(element),
+					Err(_) => return Err(DecodeError::InvalidValue),
+				}
+			}
+			if cursor.position() as usize != encoded.len() {
+				return Err(DecodeError::InvalidValue);
+			}
+			witnesses.push(witness);
+		}
+		let mut shared_input_signature: Option<Signature> = None;
+		decode_tlv!(&mut *r, {}, {
+			(0, shared_input_signature)
+		});
+		Ok(Self { channel_id, txid, witnesses, shared_input_signature })
+	}
+}
+
+impl_writeable_len_match!(TxAbort, {
+		{ TxAbort { ref data, .. }, 32+2+data.len() }
+	}, {
+	channel_id,
+	data
+});
+
 impl_writeable_len_match!(UpdateFailHTLC, {
 		{ UpdateFailHTLC { ref reason, .. }, 32 + 10 + reason.data.len() }
 	}, {
@@ -1246,7 +1822,7 @@ impl Writeable for OnionHopData {
 					(6, short_channel_id)
 				});
 			},
-			OnionHopDataFormat::FinalNode { payment_data: Some(ref final_data) } => {
+			OnionHopDataFormat::FinalNode { payment_data: Some(ref final_data), keysend_preimage: None } => {
 				if final_data.total_msat > MAX_VALUE_MSAT { panic!("We should never be sending infinite/overflow onion payments"); }
 				encode_varint_length_prefixed_tlv!(w, {
 					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
@@ -1254,12 +1830,28 @@ impl Writeable for OnionHopData {
 					(8, final_data)
 				});
 			},
-			OnionHopDataFormat::FinalNode { payment_data: None } => {
+			OnionHopDataFormat::FinalNode { payment_data: Some(ref final_data), keysend_preimage: Some(ref keysend_preimage) } => {
+				if final_data.total_msat > MAX_VALUE_MSAT { panic!("We should never be sending infinite/overflow onion payments"); }
+				encode_varint_length_prefixed_tlv!(w, {
+					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
+					(4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value)),
+					(8, final_data),
+					(5482373484, keysend_preimage)
+				});
+			},
+			OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: None } => {
 				encode_varint_length_prefixed_tlv!(w, {
 					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
 					(4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value))
 				});
 			},
+			OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: Some(ref keysend_preimage) } => {
+				encode_varint_length_prefixed_tlv!(w, {
+					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
+					(4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value)),
+					(5482373484, keysend_preimage)
+				});
+			},
 		}
 		Ok(())
 	}
@@ -1280,16 +1872,20 @@ impl Readable for OnionHopData {
 			let mut cltv_value = HighZeroBytesDroppedVarInt(0u32);
 			let mut short_id: Option<u64> = None;
 			let mut payment_data: Option<FinalOnionHopData> = None;
+			// The keysend preimage uses type 5482373484, as chosen by the first implementations of
+			// spontaneous payments.
+			let mut keysend_preimage: Option<PaymentPreimage> = None;
 			decode_tlv!(&mut rd, {
 				(2, amt),
 				(4, cltv_value)
 			}, {
 				(6, short_id),
-				(8, payment_data)
+				(8, payment_data),
+				(5482373484, keysend_preimage)
 			});
 			rd.eat_remaining().map_err(|_| DecodeError::ShortRead)?;
 			let format = if let Some(short_channel_id) = short_id {
-				if payment_data.is_some() { return Err(DecodeError::InvalidValue); }
+				if payment_data.is_some() || keysend_preimage.is_some() { return Err(DecodeError::InvalidValue); }
 				OnionHopDataFormat::NonFinalNode {
 					short_channel_id,
 				}
@@ -1300,7 +1896,8 @@ impl Readable for OnionHopData {
 					}
 				}
 				OnionHopDataFormat::FinalNode {
-					payment_data
+					payment_data,
+					keysend_preimage,
 				}
 			};
 			(format, amt.0, cltv_value.0)
@@ -2263,10 +2860,26 @@ mod tests {
 			channel_id: [2; 32],
 			fee_satoshis: 2316138423780173,
 			signature: sig_1,
+			fee_range: None,
 		};
 		let encoded_value = closing_signed.encode();
 		let target_value = hex::decode("020202020202020202020202020202020202020202020202020202020202020200083a840000034dd977cb9b53d93a6ff64bb5f1e158b4094b66e798fb12911168a3ccdf80a83096340a6a95da0ae8d9f776528eecdbb747eb6b545495a4319ed5378e35b21e073a").unwrap();
 		assert_eq!(encoded_value, target_value);
+		assert!(msgs::ClosingSigned::read(&mut Cursor::new(&target_value)).unwrap() == closing_signed);
+
+		let closing_signed_with_range = msgs::ClosingSigned {
+			channel_id: [2; 32],
+			fee_satoshis: 2316138423780173,
+			signature: sig_1,
+			fee_range: Some(msgs::ClosingSignedFeeRange {
+				min_fee_satoshis: 0xdeadbeef,
+				max_fee_satoshis: 0x1badcafe01234567,
+			}),
+		};
+		let encoded_value_with_range = closing_signed_with_range.encode();
+		let target_value_with_range = hex::decode("020202020202020202020202020202020202020202020202020202020202020200083a840000034dd977cb9b53d93a6ff64bb5f1e158b4094b66e798fb12911168a3ccdf80a83096340a6a95da0ae8d9f776528eecdbb747eb6b545495a4319ed5378e35b21e073a011000000000deadbeef1badcafe01234567").unwrap();
+		assert_eq!(encoded_value_with_range, target_value_with_range);
+		assert!(msgs::ClosingSigned::read(&mut Cursor::new(&target_value_with_range)).unwrap() == closing_signed_with_range);
 	}
 
 	#[test]
@@ -2332,7 +2945,7 @@ mod tests {
 		assert_eq!(encoded_value, target_value);
 	}
 
-	fn do_encoding_commitment_signed(htlcs: bool) {
+	fn do_encoding_commitment_signed(htlcs: bool, splice: bool) {
 		let secp_ctx = Secp256k1::new();
 		let (privkey_1, _) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
 		let (privkey_2, _) = get_keys_from!("0202020202020202020202020202020202020202020202020202020202020202", secp_ctx);
@@ -2346,6 +2959,7 @@ mod tests {
 			channel_id: [2; 32],
 			signature: sig_1,
 			htlc_signatures: if htlcs { vec![sig_2, sig_3, sig_4] } else { Vec::new() },
+			splice_signatures: if splice { Some(msgs::SpliceCommitmentSignatures { signature: sig_2, htlc_signatures: vec![sig_3] }) } else { None },
 		};
 		let encoded_value = commitment_signed.encode();
 		let mut target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202d977cb9b53d93a6ff64bb5f1e158b4094b66e798fb12911168a3ccdf80a83096340a6a95da0ae8d9f776528eecdbb747eb6b545495a4319ed5378e35b21e073a").unwrap();
@@ -2354,13 +2968,18 @@ mod tests {
 		} else {
 			target_value.append(&mut hex::decode("0000").unwrap());
 		}
+		if splice {
+			target_value.append(&mut hex::decode("01821735b6a427e80d5fe7cd90a2f4ee08dc9c27cda7c35a4172e5d85b12c49d4232537e98f9b1f3c5e6989a8b9644e90e8918127680dbd0d4043510840fc0f1e11a0001216c280b5395a2546e7e4b2663e04f811622f15a4f91e83aa2e92ba2a573c139142c54ae63072a1ec1ee7dc0c04bde5c847806172aa05c92c22ae8e308d1d269").unwrap());
+		}
 		assert_eq!(encoded_value, target_value);
 	}
 
 	#[test]
 	fn encoding_commitment_signed() {
-		do_encoding_commitment_signed(true);
-		do_encoding_commitment_signed(false);
+		do_encoding_commitment_signed(true, false);
+		do_encoding_commitment_signed(false, false);
+		do_encoding_commitment_signed(true, true);
+		do_encoding_commitment_signed(false, true);
 	}
 
 	#[test]
@@ -2472,6 +3091,7 @@ mod tests {
 		let mut msg = msgs::OnionHopData {
 			format: OnionHopDataFormat::FinalNode {
 				payment_data: None,
+				keysend_preimage: None,
 			},
 			amt_to_forward: 0x0badf00d01020304,
 			outgoing_cltv_value: 0xffffffff,
@@ -2480,7 +3100,7 @@ mod tests {
 		let target_value = hex::decode("1002080badf00d010203040404ffffffff").unwrap();
 		assert_eq!(encoded_value, target_value);
 		msg = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
-		if let OnionHopDataFormat::FinalNode { payment_data: None } = msg.format { } else { panic!(); }
+		if let OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: None } = msg.format { } else { panic!(); }
 		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
 		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
 	}
@@ -2494,6 +3114,7 @@ mod tests {
 					payment_secret: expected_payment_secret,
 					total_msat: 0x1badca1f
 				}),
+				keysend_preimage: None,
 			},
 			amt_to_forward: 0x0badf00d01020304,
 			outgoing_cltv_value: 0xffffffff,
@@ -2506,7 +3127,8 @@ mod tests {
 			payment_data: Some(FinalOnionHopData {
 				payment_secret,
 				total_msat: 0x1badca1f
-			})
+			}),
+			keysend_preimage: None,
 		} = msg.format {
 			assert_eq!(payment_secret, expected_payment_secret);
 		} else { panic!(); }
@@ -2514,6 +3136,28 @@ mod tests {
 		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
 	}
 
+	#[test]
+	fn encoding_final_onion_hop_data_with_keysend_preimage() {
+		let expected_preimage = PaymentPreimage([0x11u8; 32]);
+		let mut msg = msgs::OnionHopData {
+			format: OnionHopDataFormat::FinalNode {
+				payment_data: None,
+				keysend_preimage: Some(expected_preimage),
+			},
+			amt_to_forward: 0x0badf00d01020304,
+			outgoing_cltv_value: 0xffffffff,
+		};
+		let encoded_value = msg.encode();
+		let target_value = hex::decode("3a02080badf00d010203040404ffffffffff0000000146c6616c201111111111111111111111111111111111111111111111111111111111111111").unwrap();
+		assert_eq!(encoded_value, target_value);
+		msg = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		if let OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: Some(preimage) } = msg.format {
+			assert_eq!(preimage, expected_preimage);
+		} else { panic!(); }
+		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
+		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
+	}
+
 	#[test]
 	fn encoding_query_channel_range() {
 		let mut query_channel_range = msgs::QueryChannelRange {
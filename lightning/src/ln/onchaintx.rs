@@ -29,7 +29,7 @@ use ln::chan_utils::{TxCreationKeys, HolderCommitmentTransaction};
 use chain::chaininterface::{FeeEstimator, BroadcasterInterface, ConfirmationTarget, MIN_RELAY_FEE_SAT_PER_1000_WEIGHT};
 use chain::keysinterface::ChannelKeys;
 use util::logger::Logger;
-use util::ser::{Readable, Writer, Writeable};
+use util::ser::{Readable, ReadableArgs, Writer, Writeable};
 use util::byte_utils;
 
 use std::collections::{HashMap, hash_map};
@@ -56,6 +56,18 @@ enum OnchainEvent {
 	}
 }
 
+/// Actions the user must take to get a claim confirmed which we can't take on our own, surfaced
+/// through ChannelMonitor as Events.
+pub(crate) enum ClaimEvent {
+	/// Our holder commitment transaction, which can't be RBF-bumped, needs a child transaction
+	/// spending our anchor output to get to the given feerate.
+	BumpCommitment {
+		target_feerate_sat_per_1000_weight: u32,
+		commitment_tx: Transaction,
+		anchor_output_idx: u32,
+	},
+}
+
 /// Higher-level cache structure needed to re-generate bumped claim txn if needed
 #[derive(Clone, PartialEq)]
 pub struct ClaimTxBumpMaterial {
@@ -250,6 +262,7 @@ pub struct OnchainTxHandler<ChanSigner: ChannelKeys> {
 	prev_holder_commitment: Option<HolderCommitmentTransaction>,
 	prev_holder_htlc_sigs: Option<Vec<Option<(usize, Signature)>>>,
 	on_holder_tx_csv: u16,
+	opt_anchors: bool,
 
 	key_storage: ChanSigner,
 
@@ -282,6 +295,10 @@ pub struct OnchainTxHandler<ChanSigner: ChannelKeys> {
 
 	onchain_events_waiting_threshold_conf: HashMap<u32, Vec<OnchainEvent>>,
 
+	// Bump requests generated while handling claims, drained by ChannelMonitor after each block
+	// and turned into user-facing events.
+	pending_claim_events: Vec<ClaimEvent>,
+
 	secp_ctx: Secp256k1<secp256k1::All>,
 }
 
@@ -294,6 +311,7 @@ impl<ChanSigner: ChannelKeys + Writeable> OnchainTxHandler<ChanSigner> {
 		self.prev_holder_htlc_sigs.write(writer)?;
 
 		self.on_holder_tx_csv.write(writer)?;
+		self.opt_anchors.write(writer)?;
 
 		self.key_storage.write(writer)?;
 
@@ -332,16 +350,22 @@ impl<ChanSigner: ChannelKeys + Writeable> OnchainTxHandler<ChanSigner> {
 	}
 }
 
-impl<ChanSigner: ChannelKeys + Readable> Readable for OnchainTxHandler<ChanSigner> {
-	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+/// The OnchainTxHandler is serialized as part of a ChannelMonitor, whose serialization version
+/// it is read with.
+impl<ChanSigner: ChannelKeys + Readable> ReadableArgs<u8> for OnchainTxHandler<ChanSigner> {
+	fn read<R: ::std::io::Read>(reader: &mut R, ver: u8) -> Result<Self, DecodeError> {
 		let destination_script = Readable::read(reader)?;
 
-		let holder_commitment = Readable::read(reader)?;
+		let mut holder_commitment: Option<HolderCommitmentTransaction> = Readable::read(reader)?;
 		let holder_htlc_sigs = Readable::read(reader)?;
-		let prev_holder_commitment = Readable::read(reader)?;
+		let mut prev_holder_commitment: Option<HolderCommitmentTransaction> = Readable::read(reader)?;
 		let prev_holder_htlc_sigs = Readable::read(reader)?;
 
 		let on_holder_tx_csv = Readable::read(reader)?;
+		let opt_anchors = if ver >= 2 { Readable::read(reader)? } else { false };
+		// HolderCommitmentTransactions don't serialize whether they use anchor outputs, we do.
+		if let Some(ref mut tx) = holder_commitment { tx.opt_anchors = opt_anchors; }
+		if let Some(ref mut tx) = prev_holder_commitment { tx.opt_anchors = opt_anchors; }
 
 		let key_storage = Readable::read(reader)?;
 
@@ -395,17 +419,19 @@ impl<ChanSigner: ChannelKeys + Readable> Readable for OnchainTxHandler<ChanSigne
 			prev_holder_commitment,
 			prev_holder_htlc_sigs,
 			on_holder_tx_csv,
+			opt_anchors,
 			key_storage,
 			claimable_outpoints,
 			pending_claim_requests,
 			onchain_events_waiting_threshold_conf,
+			pending_claim_events: Vec::new(),
 			secp_ctx: Secp256k1::new(),
 		})
 	}
 }
 
 impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
-	pub(super) fn new(destination_script: Script, keys: ChanSigner, on_holder_tx_csv: u16) -> Self {
+	pub(super) fn new(destination_script: Script, keys: ChanSigner, on_holder_tx_csv: u16, opt_anchors: bool) -> Self {
 
 		let key_storage = keys;
 
@@ -416,18 +442,32 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 			prev_holder_commitment: None,
 			prev_holder_htlc_sigs: None,
 			on_holder_tx_csv,
+			opt_anchors,
 			key_storage,
 			pending_claim_requests: HashMap::new(),
 			claimable_outpoints: HashMap::new(),
 			onchain_events_waiting_threshold_conf: HashMap::new(),
+			pending_claim_events: Vec::new(),
 
 			secp_ctx: Secp256k1::new(),
 		}
 	}
 
-	pub(super) fn get_witnesses_weight(inputs: &[InputDescriptors]) -> usize {
+	pub(super) fn opt_anchors(&self) -> bool {
+		self.opt_anchors
+	}
+
+	pub(super) fn get_and_clear_pending_claim_events(&mut self) -> Vec<ClaimEvent> {
+		let mut ret = Vec::new();
+		::std::mem::swap(&mut ret, &mut self.pending_claim_events);
+		ret
+	}
+
+	pub(super) fn get_witnesses_weight(inputs: &[InputDescriptors], opt_anchors: bool) -> usize {
 		let mut tx_weight = 2; // count segwit flags
 		for inp in inputs {
+			// HTLC witnessScripts of anchor channels carry an additional 1 OP_CSV OP_DROP
+			if opt_anchors && *inp != InputDescriptors::RevokedOutput { tx_weight += 3; }
 			// We use expected weight (and not actual) as signatures and time lock delays may vary
 			tx_weight +=  match inp {
 				// number_of_witness_elements + sig_length + revocation_sig + pubkey_length + revocationpubkey + witness_script_length + witness_script
@@ -477,12 +517,18 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 	{
 		if cached_claim_datas.per_input_material.len() == 0 { return None } // But don't prune pending claiming request yet, we may have to resurrect HTLCs
 		let mut inputs = Vec::new();
-		for outp in cached_claim_datas.per_input_material.keys() {
+		for (outp, per_outp_material) in cached_claim_datas.per_input_material.iter() {
 			log_trace!(logger, "Outpoint {}:{}", outp.txid, outp.vout);
+			// With anchor outputs, the non-revocation paths of counterparty HTLC outputs are
+			// encumbered by a 1-block CSV.
+			let sequence = match per_outp_material {
+				&InputMaterial::CounterpartyHTLC { .. } if self.opt_anchors => 1,
+				_ => 0xfffffffd,
+			};
 			inputs.push(TxIn {
 				previous_output: *outp,
 				script_sig: Script::new(),
-				sequence: 0xfffffffd,
+				sequence,
 				witness: Vec::new(),
 			});
 		}
@@ -544,11 +590,11 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		for per_outp_material in cached_claim_datas.per_input_material.values() {
 			match per_outp_material {
 				&InputMaterial::Revoked { ref input_descriptor, ref amount, .. } => {
-					inputs_witnesses_weight += Self::get_witnesses_weight(&[*input_descriptor]);
+					inputs_witnesses_weight += Self::get_witnesses_weight(&[*input_descriptor], self.opt_anchors);
 					amt += *amount;
 				},
 				&InputMaterial::CounterpartyHTLC { ref preimage, ref htlc, .. } => {
-					inputs_witnesses_weight += Self::get_witnesses_weight(if preimage.is_some() { &[InputDescriptors::OfferedHTLC] } else { &[InputDescriptors::ReceivedHTLC] });
+					inputs_witnesses_weight += Self::get_witnesses_weight(if preimage.is_some() { &[InputDescriptors::OfferedHTLC] } else { &[InputDescriptors::ReceivedHTLC] }, self.opt_anchors);
 					amt += htlc.amount_msat / 1000;
 				},
 				&InputMaterial::HolderHTLC { .. } => {
@@ -586,7 +632,7 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 						if let Ok(chan_keys) = TxCreationKeys::derive_new(&self.secp_ctx, &per_commitment_point, counterparty_delayed_payment_base_key, counterparty_htlc_base_key, &self.key_storage.pubkeys().revocation_basepoint, &self.key_storage.pubkeys().htlc_basepoint) {
 
 							let witness_script = if let Some(ref htlc) = *htlc {
-								chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key)
+								chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, self.opt_anchors, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key)
 							} else {
 								chan_utils::get_revokeable_redeemscript(&chan_keys.revocation_key, *on_counterparty_tx_csv, &chan_keys.broadcaster_delayed_payment_key)
 							};
@@ -608,7 +654,7 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 					},
 					&InputMaterial::CounterpartyHTLC { ref per_commitment_point, ref counterparty_delayed_payment_base_key, ref counterparty_htlc_base_key, ref preimage, ref htlc } => {
 						if let Ok(chan_keys) = TxCreationKeys::derive_new(&self.secp_ctx, &per_commitment_point, counterparty_delayed_payment_base_key, counterparty_htlc_base_key, &self.key_storage.pubkeys().revocation_basepoint, &self.key_storage.pubkeys().htlc_basepoint) {
-							let witness_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key);
+							let witness_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, self.opt_anchors, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key);
 
 							if !preimage.is_some() { bumped_tx.lock_time = htlc.cltv_expiry }; // Right now we don't aggregate time-locked transaction, if we do we should set lock_time before to avoid breaking hash computation
 							if let Ok(sig) = self.key_storage.sign_counterparty_htlc_transaction(&bumped_tx, i, &htlc.amount_msat / 1000, &per_commitment_point, htlc, &self.secp_ctx) {
@@ -646,9 +692,30 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 					},
 					&InputMaterial::Funding { ref funding_redeemscript } => {
 						let signed_tx = self.get_fully_signed_holder_tx(funding_redeemscript).unwrap();
-						// Timer set to $NEVER given we can't bump tx without anchor outputs
+						let commitment_feerate = self.holder_commitment.as_ref().unwrap().feerate_per_kw;
 						log_trace!(logger, "Going to broadcast Holder Transaction {} claiming funding output {} from {}...", signed_tx.txid(), outp.vout, outp.txid);
-						return Some((None, self.holder_commitment.as_ref().unwrap().feerate_per_kw, signed_tx));
+						if !self.opt_anchors {
+							// Timer set to $NEVER given we can't bump tx without anchor outputs
+							return Some((None, commitment_feerate, signed_tx));
+						}
+						// With anchor outputs, we re-evaluate the feerate at every block and, if it
+						// went up, ask the user to CPFP the commitment transaction through our anchor.
+						let new_timer = Some(Self::get_height_timer(height, cached_claim_datas.soonest_timelock));
+						let current_feerate = cmp::max(commitment_feerate, cached_claim_datas.feerate_previous);
+						let target_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority);
+						if target_feerate > current_feerate {
+							let anchor_script = chan_utils::get_anchor_redeemscript(&self.key_storage.pubkeys().funding_pubkey).to_v0_p2wsh();
+							if let Some(anchor_output_idx) = signed_tx.output.iter().position(|o| o.script_pubkey == anchor_script) {
+								log_trace!(logger, "Requesting CPFP of Holder Transaction {} through anchor output {} to reach feerate {}", signed_tx.txid(), anchor_output_idx, target_feerate);
+								self.pending_claim_events.push(ClaimEvent::BumpCommitment {
+									target_feerate_sat_per_1000_weight: target_feerate,
+									commitment_tx: signed_tx.clone(),
+									anchor_output_idx: anchor_output_idx as u32,
+								});
+								return Some((new_timer, target_feerate, signed_tx));
+							}
+						}
+						return Some((new_timer, current_feerate, signed_tx));
 					}
 					_ => unreachable!()
 				}
@@ -661,6 +728,43 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		where B::Target: BroadcasterInterface,
 		      F::Target: FeeEstimator,
 					L::Target: Logger,
+	{
+		let mut bump_candidates = self.confirm_transactions(txn_matched, claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*logger);
+		self.process_matured_events(height);
+		self.check_height_timers(height, false, &mut bump_candidates);
+		self.bump_claims(bump_candidates, height, &*broadcaster, &*fee_estimator, &*logger);
+	}
+
+	/// Registers the given claim requests and checks the given transactions, confirmed at the
+	/// given height, for spends of outpoints we're claiming, as block_connected but without acting
+	/// on the height itself.
+	pub(super) fn transactions_confirmed<B: Deref, F: Deref, L: Deref>(&mut self, txn_matched: &[&Transaction], claimable_outpoints: Vec<ClaimRequest>, height: u32, broadcaster: B, fee_estimator: F, logger: L)
+		where B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+					L::Target: Logger,
+	{
+		let bump_candidates = self.confirm_transactions(txn_matched, claimable_outpoints, height, &*broadcaster, &*fee_estimator, &*logger);
+		self.bump_claims(bump_candidates, height, &*broadcaster, &*fee_estimator, &*logger);
+	}
+
+	/// Acts on a new best block at the given height, as block_connected but without any
+	/// transactions. As intermediate blocks may have been skipped, any claims due to be bumped at
+	/// or before this height are bumped.
+	pub(super) fn best_block_updated<B: Deref, F: Deref, L: Deref>(&mut self, height: u32, broadcaster: B, fee_estimator: F, logger: L)
+		where B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+					L::Target: Logger,
+	{
+		self.process_matured_events(height);
+		let mut bump_candidates = HashMap::new();
+		self.check_height_timers(height, true, &mut bump_candidates);
+		self.bump_claims(bump_candidates, height, &*broadcaster, &*fee_estimator, &*logger);
+	}
+
+	fn confirm_transactions<B: Deref, F: Deref, L: Deref>(&mut self, txn_matched: &[&Transaction], claimable_outpoints: Vec<ClaimRequest>, height: u32, broadcaster: B, fee_estimator: F, logger: L) -> HashMap<Txid, ClaimTxBumpMaterial>
+		where B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+					L::Target: Logger,
 	{
 		log_trace!(logger, "Block at height {} connected with {} claim requests", height, claimable_outpoints.len());
 		let mut new_claims = Vec::new();
@@ -700,7 +804,7 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 					self.claimable_outpoints.insert(k.clone(), (txid, height));
 				}
 				self.pending_claim_requests.insert(txid, claim_material);
-				log_trace!(logger, "Broadcast onchain {}", log_tx!(tx));
+				log_trace!(logger, "Broadcast onchain {}", log_tx!(tx, self.opt_anchors));
 				broadcaster.broadcast_transaction(&tx);
 			}
 		}
@@ -748,6 +852,11 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 						// ANTI_REORG_DELAY and clean the RBF tracking map.
 						if set_equality {
 							clean_claim_request_after_safety_delay!();
+							// Once a commitment transaction is confirmed, there's no need to keep
+							// asking for it to be bumped through its anchor.
+							if claim_material.per_input_material.values().any(|m| if let &InputMaterial::Funding { .. } = m { true } else { false }) {
+								claim_material.height_timer = None;
+							}
 						} else { // If false, generate new claim request with update outpoint set
 							let mut at_least_one_drop = false;
 							for input in tx.input.iter() {
@@ -786,9 +895,16 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 			}
 		}
 
+		bump_candidates
+	}
+
+	/// Processes any events which have reached ANTI_REORG_DELAY confirmations as of the given
+	/// height, including those from earlier heights which were confirmed late.
+	pub(super) fn process_matured_events(&mut self, height: u32) {
 		// After security delay, either our claim tx got enough confs or outpoint is definetely out of reach
-		if let Some(events) = self.onchain_events_waiting_threshold_conf.remove(&height) {
-			for ev in events {
+		let matured_heights: Vec<u32> = self.onchain_events_waiting_threshold_conf.keys().filter(|h| **h <= height).cloned().collect();
+		for matured_height in matured_heights {
+			for ev in self.onchain_events_waiting_threshold_conf.remove(&matured_height).unwrap() {
 				match ev {
 					OnchainEvent::Claim { claim_request } => {
 						// We may remove a whole set of claim outpoints here, as these one may have
@@ -805,21 +921,31 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 				}
 			}
 		}
+	}
 
+	/// Adds any pending claim requests which are due to be bumped at the given height to
+	/// bump_candidates, as well as those which were due earlier if include_missed is set.
+	fn check_height_timers(&self, height: u32, include_missed: bool, bump_candidates: &mut HashMap<Txid, ClaimTxBumpMaterial>) {
 		// Check if any pending claim request must be rescheduled
 		for (first_claim_txid, ref claim_data) in self.pending_claim_requests.iter() {
 			if let Some(h) = claim_data.height_timer {
-				if h == height {
+				if h == height || (include_missed && h < height) {
 					bump_candidates.insert(*first_claim_txid, (*claim_data).clone());
 				}
 			}
 		}
+	}
 
+	fn bump_claims<B: Deref, F: Deref, L: Deref>(&mut self, bump_candidates: HashMap<Txid, ClaimTxBumpMaterial>, height: u32, broadcaster: B, fee_estimator: F, logger: L)
+		where B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+					L::Target: Logger,
+	{
 		// Build, bump and rebroadcast tx accordingly
 		log_trace!(logger, "Bumping {} candidates", bump_candidates.len());
 		for (first_claim_txid, claim_material) in bump_candidates.iter() {
 			if let Some((new_timer, new_feerate, bump_tx)) = self.generate_claim_tx(height, &claim_material, &*fee_estimator, &*logger) {
-				log_trace!(logger, "Broadcast onchain {}", log_tx!(bump_tx));
+				log_trace!(logger, "Broadcast onchain {}", log_tx!(bump_tx, self.opt_anchors));
 				broadcaster.broadcast_transaction(&bump_tx);
 				if let Some(claim_material) = self.pending_claim_requests.get_mut(first_claim_txid) {
 					claim_material.height_timer = new_timer;
@@ -877,11 +1003,24 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		}
 	}
 
-	pub(super) fn provide_latest_holder_tx(&mut self, tx: HolderCommitmentTransaction) {
+	pub(super) fn provide_latest_holder_tx(&mut self, mut tx: HolderCommitmentTransaction) {
+		tx.opt_anchors = self.opt_anchors;
 		self.prev_holder_commitment = self.holder_commitment.take();
 		self.holder_commitment = Some(tx);
 	}
 
+	/// Replaces our holder commitment transaction with one spending the funding output of a splice
+	/// transaction which has been seen on chain. The previous holder commitment transactions all
+	/// spend the old funding output and thus can no longer be broadcast.
+	pub(super) fn provide_splice_holder_tx(&mut self, mut tx: HolderCommitmentTransaction, channel_value_satoshis: u64) {
+		tx.opt_anchors = self.opt_anchors;
+		self.key_storage.on_splice(channel_value_satoshis);
+		self.prev_holder_commitment = None;
+		self.prev_holder_htlc_sigs = None;
+		self.holder_commitment = Some(tx);
+		self.holder_htlc_sigs = None;
+	}
+
 	fn sign_latest_holder_htlcs(&mut self) {
 		if let Some(ref holder_commitment) = self.holder_commitment {
 			if let Ok(sigs) = self.key_storage.sign_holder_commitment_htlc_transactions(holder_commitment, &self.secp_ctx) {
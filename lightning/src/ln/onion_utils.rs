@@ -7,7 +7,7 @@
 // You may not use this file except in accordance with one or both of these
 // licenses.
 
-use ln::channelmanager::{PaymentHash, PaymentSecret, HTLCSource};
+use ln::channelmanager::{PaymentHash, PaymentPreimage, PaymentSecret, HTLCSource};
 use ln::msgs;
 use routing::router::RouteHop;
 use util::byte_utils;
@@ -117,7 +117,7 @@ pub(super) fn construct_onion_keys<T: secp256k1::Signing>(secp_ctx: &Secp256k1<T
 }
 
 /// returns the hop data, as well as the first-hop value_msat and CLTV value we should send.
-pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, payment_secret_option: &Option<PaymentSecret>, starting_htlc_offset: u32) -> Result<(Vec<msgs::OnionHopData>, u64, u32), APIError> {
+pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, payment_secret_option: &Option<PaymentSecret>, keysend_preimage: &Option<PaymentPreimage>, starting_htlc_offset: u32) -> Result<(Vec<msgs::OnionHopData>, u64, u32), APIError> {
 	let mut cur_value_msat = 0u64;
 	let mut cur_cltv = starting_htlc_offset;
 	let mut last_short_channel_id = 0;
@@ -139,6 +139,7 @@ pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, paymen
 								total_msat,
 							})
 						} else { None },
+						keysend_preimage: keysend_preimage.clone(),
 					}
 				} else {
 					msgs::OnionHopDataFormat::NonFinalNode {
@@ -146,6 +147,9 @@ pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, paymen
 					}
 				}
 			} else {
+				if idx == 0 && keysend_preimage.is_some() {
+					return Err(APIError::RouteError{err: "Destination does not support the variable-length onions required for spontaneous payments"});
+				}
 				msgs::OnionHopDataFormat::Legacy {
 					short_channel_id: last_short_channel_id,
 				}
@@ -325,9 +329,10 @@ pub(super) fn build_first_hop_failure_packet(shared_secret: &[u8], failure_type:
 
 /// Process failure we got back from upstream on a payment we sent (implying htlc_source is an
 /// OutboundRoute).
-/// Returns update, a boolean indicating that the payment itself failed, and the error code.
+/// Returns update, a boolean indicating that the payment itself failed, the short_channel_id of
+/// the channel which (as best we can tell) caused the failure, and the error code.
 #[inline]
-pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &Secp256k1<T>, logger: &L, htlc_source: &HTLCSource, mut packet_decrypted: Vec<u8>) -> (Option<msgs::HTLCFailChannelUpdate>, bool, Option<u16>, Option<Vec<u8>>) where L::Target: Logger {
+pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &Secp256k1<T>, logger: &L, htlc_source: &HTLCSource, mut packet_decrypted: Vec<u8>) -> (Option<msgs::HTLCFailChannelUpdate>, bool, Option<u64>, Option<u16>, Option<Vec<u8>>) where L::Target: Logger {
 	if let &HTLCSource::OutboundRoute { ref path, ref session_priv, ref first_hop_htlc_msat } = htlc_source {
 		let mut res = None;
 		let mut htlc_msat = *first_hop_htlc_msat;
@@ -445,7 +450,15 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 						// TODO: Here (and a few other places) we assume that BADONION errors
 						// are always "sourced" from the node previous to the one which failed
 						// to decode the onion.
-						res = Some((fail_channel_update, !(error_code & PERM == PERM && is_from_final_node)));
+						// The channel we should avoid when retrying: either the one the update
+						// refers to or, if a node failed, the channel we used to reach it.
+						let failing_short_channel_id = match fail_channel_update {
+							Some(msgs::HTLCFailChannelUpdate::ChannelUpdateMessage { ref msg }) => Some(msg.contents.short_channel_id),
+							Some(msgs::HTLCFailChannelUpdate::ChannelClosed { short_channel_id, .. }) => Some(short_channel_id),
+							Some(msgs::HTLCFailChannelUpdate::NodeFailure { .. }) => Some(route_hop.short_channel_id),
+							None => None,
+						};
+						res = Some((fail_channel_update, !(error_code & PERM == PERM && is_from_final_node), failing_short_channel_id));
 
 						let (description, title) = errors::get_onion_error_description(error_code);
 						if debug_field_size > 0 && err_packet.failuremsg.len() >= 4 + debug_field_size {
@@ -460,17 +473,17 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 						res = Some((Some(msgs::HTLCFailChannelUpdate::NodeFailure {
 							node_id: route_hop.pubkey,
 							is_permanent: true,
-						}), !is_from_final_node));
+						}), !is_from_final_node, Some(route_hop.short_channel_id)));
 					}
 				}
 			}
 		}).expect("Route that we sent via spontaneously grew invalid keys in the middle of it?");
-		if let Some((channel_update, payment_retryable)) = res {
-			(channel_update, payment_retryable, error_code_ret, error_packet_ret)
+		if let Some((channel_update, payment_retryable, failing_short_channel_id)) = res {
+			(channel_update, payment_retryable, failing_short_channel_id, error_code_ret, error_packet_ret)
 		} else {
 			// only not set either packet unparseable or hmac does not match with any
 			// payment not retryable only when garbage is from the final node
-			(None, !is_from_final_node, None, None)
+			(None, !is_from_final_node, None, None, None)
 		}
 	} else { unreachable!(); }
 }
@@ -0,0 +1,387 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A payment-retry layer on top of a ChannelManager and the router.
+//!
+//! A PaymentRetrier finds a route for and sends each payment handed to it, remembering what it
+//! needs to route the payment again. It sits in front of your event handler, so that when a path
+//! of the payment fails, the channel which caused the failure is excluded and the payment is
+//! re-routed and re-sent, until it either succeeds, is rejected by the recipient or runs out of
+//! retries or time. Only then is a single Event::PaymentFailed passed on to your event handler.
+//!
+//! The outcome of each path is also reported to a routing::scorer::Score, so that later
+//! payments avoid channels which have been failing.
+
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::secp256k1::key::PublicKey;
+
+use chain::chaininterface::{BroadcasterInterface, ChainWatchInterface, FeeEstimator};
+use chain::keysinterface::{ChannelKeys, KeysInterface};
+use ln::channelmanager::{ChannelDetails, ChannelManager, PaymentHash, PaymentSecret, PaymentSendFailure};
+use ln::channelmonitor::ManyChannelMonitor;
+use ln::features::InvoiceFeatures;
+use ln::msgs::LightningError;
+use routing::network_graph::NetGraphMsgHandler;
+use routing::router::{get_route_with_excluded_channels, Route, RouteHint};
+use routing::scorer::Score;
+use util::errors::APIError;
+use util::events::Event;
+use util::logger::Logger;
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The payment-sending functionality of a ChannelManager which a PaymentRetrier needs. See the
+/// ChannelManager methods of the same names for details.
+pub trait PaymentSender {
+	/// Gets our node id, used as the source of each route.
+	fn get_our_node_id(&self) -> PublicKey;
+	/// Gets the channels which may currently be used as the first hop of a route.
+	fn list_usable_channels(&self) -> Vec<ChannelDetails>;
+	/// Sends a payment along the given route.
+	fn send_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>) -> Result<(), PaymentSendFailure>;
+}
+
+impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref> PaymentSender for ChannelManager<ChanSigner, M, T, K, F, L>
+	where M::Target: ManyChannelMonitor<Keys=ChanSigner>,
+        T::Target: BroadcasterInterface,
+        K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+        F::Target: FeeEstimator,
+        L::Target: Logger,
+{
+	fn get_our_node_id(&self) -> PublicKey {
+		ChannelManager::get_our_node_id(self)
+	}
+
+	fn list_usable_channels(&self) -> Vec<ChannelDetails> {
+		ChannelManager::list_usable_channels(self)
+	}
+
+	fn send_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>) -> Result<(), PaymentSendFailure> {
+		ChannelManager::send_payment(self, route, payment_hash, payment_secret)
+	}
+}
+
+/// Configuration for how hard a PaymentRetrier tries to complete each payment.
+#[derive(Copy, Clone, Debug)]
+pub struct RetryConfig {
+	/// The maximum number of times a payment will be re-routed and re-sent after the first
+	/// attempt failed.
+	///
+	/// Default value: 3
+	pub max_retries: usize,
+	/// The time after the first attempt beyond which we will no longer retry a payment. This is
+	/// checked whenever a payment fails, as well as in PaymentRetrier::timer_tick_occurred for
+	/// payments which are waiting to be retried.
+	///
+	/// Default value: 60 seconds
+	pub timeout: Duration,
+}
+
+impl Default for RetryConfig {
+	fn default() -> Self {
+		RetryConfig {
+			max_retries: 3,
+			timeout: Duration::from_secs(60),
+		}
+	}
+}
+
+/// An error in sending a payment via a PaymentRetrier.
+#[derive(Debug)]
+pub enum PaymentError {
+	/// We were unable to find a route to the payee.
+	Routing(LightningError),
+	/// The payment could not be sent along the route we found. See
+	/// ChannelManager::send_payment for details.
+	Sending(PaymentSendFailure),
+}
+
+/// Everything we need to re-route a payment, as well as the progress of its attempts.
+struct PendingPayment {
+	payee: PublicKey,
+	payee_features: Option<InvoiceFeatures>,
+	last_hops: Vec<RouteHint>,
+	payment_secret: Option<PaymentSecret>,
+	amount_msat: u64,
+	final_cltv: u32,
+	excluded_channels: HashSet<u64>,
+	retries: usize,
+	first_attempt: Instant,
+	route: Route,
+	paths_in_flight: usize,
+	rejected_by_dest: bool,
+	/// Whether any path of the payment has succeeded, in which case the recipient has claimed it
+	/// and we're only waiting for the rest of its paths to resolve.
+	succeeded: bool,
+	/// The Event::PaymentFailed for the last path of the payment to fail, which we pass on if we
+	/// give up on the payment. Set while all paths have failed and we're waiting to retry.
+	failure: Option<Event>,
+}
+
+/// Sends payments via a PaymentSender (generally a ChannelManager), retrying them over new routes
+/// when they fail.
+///
+/// Every event generated by the PaymentSender must be given to PaymentRetrier::handle_event
+/// (eg by using it as the event handler given to a BackgroundProcessor), which passes them on
+/// to the event handler the PaymentRetrier was constructed with, except for those of payments
+/// which are being retried. Each Event::PaymentFailed for a payment sent via
+/// PaymentRetrier::pay is only passed on once we have given up on the payment.
+///
+/// Note that a multi-path payment is only retried once all of its paths have failed, which may
+/// take some time if the recipient has to time out the paths which did arrive.
+pub struct PaymentRetrier<P: Deref, N: Deref, C: Deref, L: Deref, S: Deref, E: Fn(Event)>
+	where P::Target: PaymentSender,
+        N: Deref<Target = NetGraphMsgHandler<C, L>>,
+        C::Target: ChainWatchInterface,
+        L::Target: Logger,
+        S::Target: Score,
+{
+	payer: P,
+	net_graph_msg_handler: N,
+	logger: L,
+	scorer: S,
+	event_handler: E,
+	config: RetryConfig,
+	pending_payments: Mutex<HashMap<PaymentHash, PendingPayment>>,
+}
+
+impl<P: Deref, N: Deref, C: Deref, L: Deref, S: Deref, E: Fn(Event)> PaymentRetrier<P, N, C, L, S, E>
+	where P::Target: PaymentSender,
+        N: Deref<Target = NetGraphMsgHandler<C, L>>,
+        C::Target: ChainWatchInterface,
+        L::Target: Logger,
+        S::Target: Score,
+{
+	/// Constructs a new PaymentRetrier which sends payments via payer, routing them over the
+	/// network graph in net_graph_msg_handler using (and updating) the given scorer, and passing
+	/// on events to event_handler.
+	pub fn new(payer: P, net_graph_msg_handler: N, logger: L, scorer: S, event_handler: E, config: RetryConfig) -> Self {
+		PaymentRetrier {
+			payer,
+			net_graph_msg_handler,
+			logger,
+			scorer,
+			event_handler,
+			config,
+			pending_payments: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Routes and sends a payment of amount_msat to payee, retrying it on failure.
+	///
+	/// The parameters are as for get_route and ChannelManager::send_payment, and should generally
+	/// be taken from the payee's invoice.
+	///
+	/// Returns an error if no route could be found or if the payment could not be sent at all, in
+	/// which case no retries will be attempted. Only one payment per payment_hash may be pending
+	/// at once.
+	pub fn pay(&self, payee: &PublicKey, payment_hash: PaymentHash, payment_secret: Option<PaymentSecret>, payee_features: Option<InvoiceFeatures>, last_hops: Vec<RouteHint>, amount_msat: u64, final_cltv: u32) -> Result<(), PaymentError> {
+		let mut pending_payments = self.pending_payments.lock().unwrap();
+		if pending_payments.contains_key(&payment_hash) {
+			return Err(PaymentError::Sending(PaymentSendFailure::ParameterError(APIError::APIMisuseError {
+				err: "A payment with the given payment_hash is already pending".to_owned()
+			})));
+		}
+		let mut payment = PendingPayment {
+			payee: *payee,
+			payee_features,
+			last_hops,
+			payment_secret,
+			amount_msat,
+			final_cltv,
+			excluded_channels: HashSet::new(),
+			retries: 0,
+			first_attempt: Instant::now(),
+			route: Route { paths: Vec::new() },
+			paths_in_flight: 0,
+			rejected_by_dest: false,
+			succeeded: false,
+			failure: None,
+		};
+		self.send_attempt(&payment_hash, &mut payment)?;
+		pending_payments.insert(payment_hash, payment);
+		Ok(())
+	}
+
+	/// Handles an event generated by the PaymentSender, passing it on to our event handler unless
+	/// it concerns a payment we're still retrying.
+	pub fn handle_event(&self, mut event: Event) {
+		let pass_on = match event {
+			Event::PaymentSent { ref payment_preimage } => {
+				let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
+				self.handle_path_success(&payment_hash);
+				true
+			},
+			Event::PaymentFailed { payment_hash, ref mut rejected_by_dest, short_channel_id, .. } => {
+				match self.handle_path_failure(&payment_hash, rejected_by_dest, short_channel_id) {
+					PathFailureAction::PassOn => true,
+					PathFailureAction::Swallow => false,
+					PathFailureAction::Retry => {
+						self.pending_payments.lock().unwrap().get_mut(&payment_hash).unwrap().failure = Some(event);
+						if let Some(failure) = self.retry_payment(&payment_hash) {
+							(self.event_handler)(failure);
+						}
+						return;
+					},
+				}
+			},
+			_ => true,
+		};
+		if pass_on {
+			(self.event_handler)(event);
+		}
+	}
+
+	/// Retries payments which could not be re-sent when their last path failed, eg because we
+	/// couldn't find a route at the time, and gives up on those which have run out of time,
+	/// passing their Event::PaymentFailed on to our event handler.
+	///
+	/// This should be called roughly once per minute.
+	pub fn timer_tick_occurred(&self) {
+		let awaiting_retry: Vec<PaymentHash> = self.pending_payments.lock().unwrap().iter()
+			.filter(|&(_, payment)| payment.failure.is_some())
+			.map(|(payment_hash, _)| *payment_hash).collect();
+		for payment_hash in awaiting_retry.iter() {
+			if let Some(failure) = self.retry_payment(payment_hash) {
+				(self.event_handler)(failure);
+			}
+		}
+	}
+
+	/// Routes and sends the given payment, updating its route and the number of paths in flight.
+	fn send_attempt(&self, payment_hash: &PaymentHash, payment: &mut PendingPayment) -> Result<(), PaymentError> {
+		let first_hops = self.payer.list_usable_channels();
+		let route = {
+			let network_graph = self.net_graph_msg_handler.network_graph.read().unwrap();
+			get_route_with_excluded_channels(&self.payer.get_our_node_id(), &network_graph, &payment.payee,
+				payment.payee_features.as_ref(), Some(&first_hops.iter().collect::<Vec<_>>()),
+				&payment.last_hops.iter().collect::<Vec<_>>(), payment.amount_msat, payment.final_cltv,
+				&payment.excluded_channels, &*self.logger, &*self.scorer).map_err(PaymentError::Routing)?
+		};
+		payment.paths_in_flight = match self.payer.send_payment(&route, *payment_hash, &payment.payment_secret) {
+			Ok(()) => route.paths.len(),
+			Err(PaymentSendFailure::PartialFailure(results)) => {
+				// Paths which failed with MonitorUpdateFailed will still be sent once the monitor
+				// update completes, so we must wait for them as well.
+				results.iter().filter(|res| match res {
+					Ok(()) => true,
+					Err(APIError::MonitorUpdateFailed) => true,
+					Err(_) => false,
+				}).count()
+			},
+			Err(e) => return Err(PaymentError::Sending(e)),
+		};
+		payment.route = route;
+		Ok(())
+	}
+
+	/// Handles a successful path of one of our payments, forgetting the payment once all of its
+	/// paths have resolved.
+	fn handle_path_success(&self, payment_hash: &PaymentHash) {
+		let mut pending_payments = self.pending_payments.lock().unwrap();
+		let resolved = match pending_payments.get_mut(payment_hash) {
+			None => return,
+			Some(payment) => {
+				if !payment.succeeded {
+					payment.succeeded = true;
+					for path in payment.route.paths.iter() {
+						self.scorer.payment_path_successful(path);
+					}
+				}
+				payment.paths_in_flight = payment.paths_in_flight.saturating_sub(1);
+				payment.paths_in_flight == 0
+			},
+		};
+		if resolved {
+			pending_payments.remove(payment_hash);
+		}
+	}
+
+	/// Handles a path failure for one of our payments, returning what should be done with the
+	/// failure event.
+	fn handle_path_failure(&self, payment_hash: &PaymentHash, rejected_by_dest: &mut bool, short_channel_id: Option<u64>) -> PathFailureAction {
+		let mut pending_payments = self.pending_payments.lock().unwrap();
+		let action = match pending_payments.get_mut(payment_hash) {
+			None => return PathFailureAction::PassOn,
+			Some(payment) => {
+				payment.paths_in_flight = payment.paths_in_flight.saturating_sub(1);
+				if let Some(scid) = short_channel_id {
+					payment.excluded_channels.insert(scid);
+					if let Some(path) = payment.route.paths.iter().find(|path| path.iter().any(|hop| hop.short_channel_id == scid)) {
+						self.scorer.payment_path_failed(path, scid);
+					}
+				}
+				payment.rejected_by_dest |= *rejected_by_dest;
+				*rejected_by_dest = payment.rejected_by_dest;
+				if payment.paths_in_flight > 0 {
+					// Wait for the rest of the payment to resolve before we retry or give up.
+					return PathFailureAction::Swallow;
+				}
+				if payment.succeeded {
+					// The recipient already claimed the payment, so this isn't a failure of it.
+					PathFailureAction::Swallow
+				} else {
+					PathFailureAction::Retry
+				}
+			},
+		};
+		if let PathFailureAction::Swallow = action {
+			pending_payments.remove(payment_hash);
+		}
+		action
+	}
+
+	/// Re-routes and re-sends a payment all of whose paths have failed, returning the failure
+	/// event to pass on if we've given up on it instead.
+	///
+	/// If the payment can't currently be re-sent, it is kept until the next timer tick, unless it
+	/// has run out of retries or time.
+	fn retry_payment(&self, payment_hash: &PaymentHash) -> Option<Event> {
+		let mut pending_payments = self.pending_payments.lock().unwrap();
+		let give_up = match pending_payments.get_mut(payment_hash) {
+			None => return None,
+			Some(payment) => {
+				if payment.rejected_by_dest || payment.retries >= self.config.max_retries || payment.first_attempt.elapsed() >= self.config.timeout {
+					true
+				} else {
+					payment.retries += 1;
+					log_trace!(self.logger, "Retrying payment with payment_hash {} (attempt {})", log_bytes!(payment_hash.0), payment.retries + 1);
+					match self.send_attempt(payment_hash, payment) {
+						Ok(()) => {
+							payment.failure = None;
+						},
+						Err(e) => {
+							log_trace!(self.logger, "Failed to retry payment with payment_hash {}: {:?}", log_bytes!(payment_hash.0), e);
+						},
+					}
+					false
+				}
+			},
+		};
+		if give_up {
+			pending_payments.remove(payment_hash).unwrap().failure
+		} else {
+			None
+		}
+	}
+}
+
+/// What to do with an Event::PaymentFailed for one path of a payment.
+enum PathFailureAction {
+	/// The payment isn't one of ours, pass the event on as-is.
+	PassOn,
+	/// Other paths of the payment are still in flight or it has succeeded, drop the event.
+	Swallow,
+	/// All paths of the payment have failed, retry it or give up on it.
+	Retry,
+}
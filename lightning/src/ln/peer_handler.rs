@@ -119,6 +119,18 @@ enum InitSyncTracker{
 	NodesSyncing(PublicKey),
 }
 
+/// The number of bytes which may be queued in a peer's outbound buffer before we stop reading from
+/// it, so that a peer which doesn't read our responses can't make us buffer an unbounded amount
+/// of them.
+const OUTBOUND_BUFFER_LIMIT_READ_PAUSE: usize = 64 * 1024;
+/// The number of bytes which may be queued in a peer's outbound buffer before we stop sending it
+/// any gossip we broadcast or relay, so that a peer which reads slowly can't make us buffer an
+/// unbounded amount of gossip on its behalf. Channel messages are always queued.
+const OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP: usize = 128 * 1024;
+/// The number of consecutive timer_tick_occured calls for which a peer's outbound buffer may stay
+/// above OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP before we disconnect it.
+const MAX_OUTBOUND_BUFFER_BACKLOGGED_TICKS: u8 = 4;
+
 struct Peer {
 	channel_encryptor: PeerChannelEncryptor,
 	outbound: bool,
@@ -127,6 +139,10 @@ struct Peer {
 
 	pending_outbound_buffer: LinkedList<Vec<u8>>,
 	pending_outbound_buffer_first_msg_offset: usize,
+	/// The total length of the buffers in pending_outbound_buffer.
+	pending_outbound_buffer_bytes: usize,
+	/// The number of consecutive timer ticks for which we've been dropping gossip to this peer.
+	outbound_buffer_backlogged_ticks: u8,
 	awaiting_write_event: bool,
 
 	pending_read_buffer: Vec<u8>,
@@ -134,11 +150,36 @@ struct Peer {
 	pending_read_is_header: bool,
 
 	sync_status: InitSyncTracker,
+	/// The gossip_timestamp_filter this peer last sent us, if any.
+	gossip_timestamp_filter: Option<msgs::GossipTimestampFilter>,
 
 	awaiting_pong: bool,
 }
 
 impl Peer {
+	/// Queues the given (already encrypted, or handshake) data for sending to this peer.
+	fn push_outbound_buffer(&mut self, buff: Vec<u8>) {
+		self.pending_outbound_buffer_bytes += buff.len();
+		self.pending_outbound_buffer.push_back(buff);
+	}
+
+	/// Encrypts the given encoded message and queues it for sending to this peer.
+	fn enqueue_encoded_message(&mut self, encoded_message: &[u8]) {
+		let buff = self.channel_encryptor.encrypt_message(encoded_message);
+		self.push_outbound_buffer(buff);
+	}
+
+	/// Returns true if our outbound buffer for this peer has room for us to keep reading from it.
+	fn should_read(&self) -> bool {
+		self.pending_outbound_buffer_bytes < OUTBOUND_BUFFER_LIMIT_READ_PAUSE
+	}
+
+	/// Returns true if our outbound buffer for this peer is so full that we should stop sending it
+	/// broadcast or relayed gossip.
+	fn should_drop_gossip(&self) -> bool {
+		self.pending_outbound_buffer_bytes >= OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP
+	}
+
 	/// Returns true if the channel announcements/updates for the given channel should be
 	/// forwarded to this peer.
 	/// If we are sending our routing table to this peer and we have not yet sent channel
@@ -161,6 +202,21 @@ impl Peer {
 			InitSyncTracker::NodesSyncing(pk) => pk < node_id,
 		}
 	}
+
+	/// Returns true if gossip we're relaying with the given timestamp should be forwarded to this
+	/// peer given its gossip_timestamp_filter. Peers which negotiated gossip_queries don't get any
+	/// relayed gossip until they set a filter, as required by BOLT 7, whereas all other peers get
+	/// all of it. channel_announcements carry no timestamp so are only checked for a filter.
+	fn should_forward_gossip_timestamp(&self, timestamp: Option<u32>) -> bool {
+		match self.gossip_timestamp_filter {
+			None => !self.their_features.as_ref().unwrap().supports_gossip_queries(),
+			Some(ref filter) => match timestamp {
+				None => true,
+				Some(timestamp) => timestamp >= filter.first_timestamp &&
+					((timestamp - filter.first_timestamp) as u64) < filter.timestamp_range as u64,
+			},
+		}
+	}
 }
 
 struct PeerHolder<Descriptor: SocketDescriptor> {
@@ -321,6 +377,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 			pending_outbound_buffer: LinkedList::new(),
 			pending_outbound_buffer_first_msg_offset: 0,
+			pending_outbound_buffer_bytes: 0,
+			outbound_buffer_backlogged_ticks: 0,
 			awaiting_write_event: false,
 
 			pending_read_buffer: pending_read_buffer,
@@ -328,6 +386,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			pending_read_is_header: false,
 
 			sync_status: InitSyncTracker::NoSyncRequested,
+			gossip_timestamp_filter: None,
 
 			awaiting_pong: false,
 		}).is_some() {
@@ -358,6 +417,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 			pending_outbound_buffer: LinkedList::new(),
 			pending_outbound_buffer_first_msg_offset: 0,
+			pending_outbound_buffer_bytes: 0,
+			outbound_buffer_backlogged_ticks: 0,
 			awaiting_write_event: false,
 
 			pending_read_buffer: pending_read_buffer,
@@ -365,6 +426,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			pending_read_is_header: false,
 
 			sync_status: InitSyncTracker::NoSyncRequested,
+			gossip_timestamp_filter: None,
 
 			awaiting_pong: false,
 		}).is_some() {
@@ -378,7 +440,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			($msg: expr) => {
 				{
 					log_trace!(self.logger, "Encoding and sending sync update message of type {} to {}", $msg.type_id(), log_pubkey!(peer.their_node_id.unwrap()));
-					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!($msg)[..]));
+					peer.enqueue_encoded_message(&encode_msg!($msg)[..]);
 				}
 			}
 		}
@@ -436,14 +498,15 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					Some(buff) => buff,
 				};
 
-				let should_be_reading = peer.pending_outbound_buffer.len() < MSG_BUFF_SIZE;
+				let should_be_reading = peer.should_read();
 				let pending = &next_buff[peer.pending_outbound_buffer_first_msg_offset..];
 				let data_sent = descriptor.send_data(pending, should_be_reading);
 				peer.pending_outbound_buffer_first_msg_offset += data_sent;
 				if peer.pending_outbound_buffer_first_msg_offset == next_buff.len() { true } else { false }
 			} {
 				peer.pending_outbound_buffer_first_msg_offset = 0;
-				peer.pending_outbound_buffer.pop_front();
+				let sent_buff = peer.pending_outbound_buffer.pop_front().unwrap();
+				peer.pending_outbound_buffer_bytes -= sent_buff.len();
 			} else {
 				peer.awaiting_write_event = true;
 			}
@@ -501,7 +564,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 		let encoded_message = buffer.0;
 
 		log_trace!(self.logger, "Enqueueing message of type {} to {}", message.type_id(), log_pubkey!(peer.their_node_id.unwrap()));
-		peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_message[..]));
+		peer.enqueue_encoded_message(&encoded_message[..]);
 		peers_needing_send.insert(descriptor);
 	}
 
@@ -509,6 +572,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 		let pause_read = {
 			let mut peers_lock = self.peers.lock().unwrap();
 			let peers = &mut *peers_lock;
+			let mut msgs_to_forward = Vec::new();
+			let mut peer_node_id = None;
 			let pause_read = match peers.peers.get_mut(peer_descriptor) {
 				None => panic!("Descriptor for read_event is not already known to PeerManager"),
 				Some(peer) => {
@@ -573,12 +638,12 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 							match next_step {
 								NextNoiseStep::ActOne => {
 									let act_two = try_potential_handleerror!(peer.channel_encryptor.process_act_one_with_keys(&peer.pending_read_buffer[..], &self.our_node_secret, self.get_ephemeral_key())).to_vec();
-									peer.pending_outbound_buffer.push_back(act_two);
+									peer.push_outbound_buffer(act_two);
 									peer.pending_read_buffer = [0; 66].to_vec(); // act three is 66 bytes long
 								},
 								NextNoiseStep::ActTwo => {
 									let (act_three, their_node_id) = try_potential_handleerror!(peer.channel_encryptor.process_act_two(&peer.pending_read_buffer[..], &self.our_node_secret));
-									peer.pending_outbound_buffer.push_back(act_three.to_vec());
+									peer.push_outbound_buffer(act_three.to_vec());
 									peer.pending_read_buffer = [0; 18].to_vec(); // Message length header is 18 bytes
 									peer.pending_read_is_header = true;
 
@@ -588,6 +653,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 									if !self.message_handler.route_handler.should_request_full_sync(&peer.their_node_id.unwrap()) {
 										features.clear_initial_routing_sync();
 									}
+									if !self.message_handler.chan_handler.supports_anchor_outputs() {
+										features = features.clear_anchor_outputs();
+									}
 
 									let resp = msgs::Init { features };
 									self.enqueue_message(&mut peers.peers_needing_send, peer, peer_descriptor.clone(), &resp);
@@ -641,13 +709,15 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 											}
 										};
 
-										if let Err(handling_error) = self.handle_message(&mut peers.peers_needing_send, peer, peer_descriptor.clone(), message){
-											match handling_error {
+										match self.handle_message(&mut peers.peers_needing_send, peer, peer_descriptor.clone(), message) {
+											Err(handling_error) => match handling_error {
 												MessageHandlingError::PeerHandleError(e) => { return Err(e) },
 												MessageHandlingError::LightningError(e) => {
 													try_potential_handleerror!(Err(e));
 												},
-											}
+											},
+											Ok(Some(msg)) => msgs_to_forward.push(msg),
+											Ok(None) => {},
 										}
 									}
 								}
@@ -657,18 +727,24 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 					self.do_attempt_write_data(peer_descriptor, peer);
 
-					peer.pending_outbound_buffer.len() > 10 // pause_read
+					peer_node_id = peer.their_node_id;
+					!peer.should_read() // pause_read
 				}
 			};
 
+			for msg in msgs_to_forward.drain(..) {
+				self.forward_gossip_msg(peers, &msg, &peer_node_id.unwrap());
+			}
+
 			pause_read
 		};
 
 		Ok(pause_read)
 	}
 
-	/// Process an incoming message and return a decision (ok, lightning error, peer handling error) regarding the next action with the peer
-	fn handle_message(&self, peers_needing_send: &mut HashSet<Descriptor>, peer: &mut Peer, peer_descriptor: Descriptor, message: wire::Message) -> Result<(), MessageHandlingError> {
+	/// Process an incoming message and return a decision (ok, lightning error, peer handling error) regarding the next action with the peer.
+	/// On success, returns any gossip message which should be relayed to our other peers.
+	fn handle_message(&self, peers_needing_send: &mut HashSet<Descriptor>, peer: &mut Peer, peer_descriptor: Descriptor, message: wire::Message) -> Result<Option<wire::Message>, MessageHandlingError> {
 		log_trace!(self.logger, "Received message of type {} from {}", message.type_id(), log_pubkey!(peer.their_node_id.unwrap()));
 
 		// Need an Init as first message
@@ -694,10 +770,11 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				}
 
 				log_info!(
-					self.logger, "Received peer Init message: data_loss_protect: {}, initial_routing_sync: {}, upfront_shutdown_script: {}, static_remote_key: {}, unknown flags (local and global): {}",
+					self.logger, "Received peer Init message: data_loss_protect: {}, initial_routing_sync: {}, upfront_shutdown_script: {}, gossip_queries: {}, static_remote_key: {}, unknown flags (local and global): {}",
 					if msg.features.supports_data_loss_protect() { "supported" } else { "not supported"},
 					if msg.features.initial_routing_sync() { "requested" } else { "not requested" },
 					if msg.features.supports_upfront_shutdown_script() { "supported" } else { "not supported"},
+					if msg.features.supports_gossip_queries() { "supported" } else { "not supported"},
 					if msg.features.supports_static_remote_key() { "supported" } else { "not supported"},
 					if msg.features.supports_unknown_bits() { "present" } else { "none" }
 				);
@@ -712,16 +789,23 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				}
 
 				if !peer.outbound {
+					// We sync with peers which support gossip_queries using queries instead, so only ask
+					// for a full routing table dump from peers which don't. Outbound peers are sent our
+					// init before we learn their features, so we can't do the same for them.
 					let mut features = InitFeatures::known();
-					if !self.message_handler.route_handler.should_request_full_sync(&peer.their_node_id.unwrap()) {
+					if msg.features.supports_gossip_queries() || !self.message_handler.route_handler.should_request_full_sync(&peer.their_node_id.unwrap()) {
 						features.clear_initial_routing_sync();
 					}
+					if !self.message_handler.chan_handler.supports_anchor_outputs() {
+						features = features.clear_anchor_outputs();
+					}
 
 					let resp = msgs::Init { features };
 					self.enqueue_message(peers_needing_send, peer, peer_descriptor.clone(), &resp);
 				}
 
 				self.message_handler.chan_handler.peer_connected(&peer.their_node_id.unwrap(), &msg);
+				self.message_handler.route_handler.sync_routing_table(&peer.their_node_id.unwrap(), &msg);
 				peer.their_features = Some(msg.features);
 			},
 			wire::Message::Error(msg) => {
@@ -771,6 +855,12 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			wire::Message::FundingLocked(msg) => {
 				self.message_handler.chan_handler.handle_funding_locked(&peer.their_node_id.unwrap(), &msg);
 			},
+			wire::Message::OpenChannelV2(msg) => {
+				self.message_handler.chan_handler.handle_open_channel_v2(&peer.their_node_id.unwrap(), peer.their_features.clone().unwrap(), &msg);
+			},
+			wire::Message::AcceptChannelV2(msg) => {
+				self.message_handler.chan_handler.handle_accept_channel_v2(&peer.their_node_id.unwrap(), peer.their_features.clone().unwrap(), &msg);
+			},
 
 			wire::Message::Shutdown(msg) => {
 				self.message_handler.chan_handler.handle_shutdown(&peer.their_node_id.unwrap(), &msg);
@@ -779,6 +869,41 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				self.message_handler.chan_handler.handle_closing_signed(&peer.their_node_id.unwrap(), &msg);
 			},
 
+			// Quiescence and splicing:
+			wire::Message::Stfu(msg) => {
+				self.message_handler.chan_handler.handle_stfu(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::SpliceInit(msg) => {
+				self.message_handler.chan_handler.handle_splice_init(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::SpliceAck(msg) => {
+				self.message_handler.chan_handler.handle_splice_ack(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::SpliceLocked(msg) => {
+				self.message_handler.chan_handler.handle_splice_locked(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxAddInput(msg) => {
+				self.message_handler.chan_handler.handle_tx_add_input(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxAddOutput(msg) => {
+				self.message_handler.chan_handler.handle_tx_add_output(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxRemoveInput(msg) => {
+				self.message_handler.chan_handler.handle_tx_remove_input(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxRemoveOutput(msg) => {
+				self.message_handler.chan_handler.handle_tx_remove_output(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxComplete(msg) => {
+				self.message_handler.chan_handler.handle_tx_complete(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxSignatures(msg) => {
+				self.message_handler.chan_handler.handle_tx_signatures(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxAbort(msg) => {
+				self.message_handler.chan_handler.handle_tx_abort(&peer.their_node_id.unwrap(), &msg);
+			},
+
 			// Commitment messages:
 			wire::Message::UpdateAddHTLC(msg) => {
 				self.message_handler.chan_handler.handle_update_add_htlc(&peer.their_node_id.unwrap(), &msg);
@@ -817,7 +942,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				};
 
 				if should_forward {
-					// TODO: forward msg along to all our other peers!
+					return Ok(Some(wire::Message::ChannelAnnouncement(msg)));
 				}
 			},
 			wire::Message::NodeAnnouncement(msg) => {
@@ -827,7 +952,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				};
 
 				if should_forward {
-					// TODO: forward msg along to all our other peers!
+					return Ok(Some(wire::Message::NodeAnnouncement(msg)));
 				}
 			},
 			wire::Message::ChannelUpdate(msg) => {
@@ -837,9 +962,34 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				};
 
 				if should_forward {
-					// TODO: forward msg along to all our other peers!
+					return Ok(Some(wire::Message::ChannelUpdate(msg)));
 				}
 			},
+			wire::Message::QueryShortChannelIds(msg) => {
+				if let Err(e) = self.message_handler.route_handler.handle_query_short_channel_ids(&peer.their_node_id.unwrap(), &msg) {
+					return Err(e.into());
+				}
+			},
+			wire::Message::QueryChannelRange(msg) => {
+				if let Err(e) = self.message_handler.route_handler.handle_query_channel_range(&peer.their_node_id.unwrap(), &msg) {
+					return Err(e.into());
+				}
+			},
+			wire::Message::ReplyChannelRange(msg) => {
+				if let Err(e) = self.message_handler.route_handler.handle_reply_channel_range(&peer.their_node_id.unwrap(), &msg) {
+					return Err(e.into());
+				}
+			},
+			wire::Message::ReplyShortChannelIdsEnd(msg) => {
+				if let Err(e) = self.message_handler.route_handler.handle_reply_short_channel_ids_end(&peer.their_node_id.unwrap(), &msg) {
+					return Err(e.into());
+				}
+			},
+			wire::Message::GossipTimestampFilter(msg) => {
+				log_trace!(self.logger, "Received gossip_timestamp_filter from {} with first_timestamp={}, timestamp_range={}",
+						log_pubkey!(peer.their_node_id.unwrap()), msg.first_timestamp, msg.timestamp_range);
+				peer.gossip_timestamp_filter = Some(msg);
+			},
 
 			// Unknown messages:
 			wire::Message::Unknown(msg_type) if msg_type.is_even() => {
@@ -851,7 +1001,52 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				log_trace!(self.logger, "Received unknown odd message of type {}, ignoring", msg_type);
 			}
 		};
-		Ok(())
+		Ok(None)
+	}
+
+	/// Relays a gossip message which we received from the given peer, and which our route handler
+	/// told us to forward, to all of our other peers which want it. Peers whose outbound buffer is
+	/// already at OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP bytes don't get it.
+	///
+	/// The message is only queued here, with the sends happening in the next process_events call.
+	fn forward_gossip_msg(&self, peers: &mut PeerHolder<Descriptor>, msg: &wire::Message, from_node_id: &PublicKey) {
+		let (encoded_msg, timestamp) = match msg {
+			&wire::Message::ChannelAnnouncement(ref msg) => (encode_msg!(msg), None),
+			&wire::Message::NodeAnnouncement(ref msg) => (encode_msg!(msg), Some(msg.contents.timestamp)),
+			&wire::Message::ChannelUpdate(ref msg) => (encode_msg!(msg), Some(msg.contents.timestamp)),
+			_ => unreachable!(),
+		};
+		log_trace!(self.logger, "Relaying gossip message of type {} from {}", msg.type_id(), log_pubkey!(from_node_id));
+
+		for (descriptor, peer) in peers.peers.iter_mut() {
+			if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() {
+				continue;
+			}
+			let their_node_id = peer.their_node_id.unwrap();
+			if their_node_id == *from_node_id {
+				continue;
+			}
+			let should_forward = match msg {
+				&wire::Message::ChannelAnnouncement(ref msg) => {
+					peer.should_forward_channel_announcement(msg.contents.short_channel_id) &&
+						their_node_id != msg.contents.node_id_1 && their_node_id != msg.contents.node_id_2
+				},
+				&wire::Message::NodeAnnouncement(ref msg) => {
+					peer.should_forward_node_announcement(msg.contents.node_id) && their_node_id != msg.contents.node_id
+				},
+				&wire::Message::ChannelUpdate(ref msg) => peer.should_forward_channel_announcement(msg.contents.short_channel_id),
+				_ => unreachable!(),
+			};
+			if !should_forward || !peer.should_forward_gossip_timestamp(timestamp) {
+				continue;
+			}
+			if peer.should_drop_gossip() {
+				log_trace!(self.logger, "Not relaying gossip message of type {} to {} as its outbound buffer is full", msg.type_id(), log_pubkey!(their_node_id));
+				continue;
+			}
+			peer.enqueue_encoded_message(&encoded_msg[..]);
+			peers.peers_needing_send.insert(descriptor.clone());
+		}
 	}
 
 	/// Checks for any events generated by our handlers and processes them. Includes sending most
@@ -859,11 +1054,14 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 	/// functions like ChannelManager::process_pending_htlc_forward or send_payment).
 	pub fn process_events(&self) {
 		{
-			// TODO: There are some DoS attacks here where you can flood someone's outbound send
-			// buffer by doing things like announcing channels on another node. We should be willing to
-			// drop optional-ish messages when send buffers get full!
+			// Peers could flood each other's outbound send buffers by doing things like announcing
+			// channels on another node, so we drop broadcast gossip to peers whose buffers are full
+			// (see Peer::should_drop_gossip). Everything else is queued regardless, with read_event
+			// and timer_tick_occured pushing back on and eventually disconnecting peers which don't
+			// drain their buffers.
 
 			let mut events_generated = self.message_handler.chan_handler.get_and_clear_pending_msg_events();
+			events_generated.append(&mut self.message_handler.route_handler.get_and_clear_pending_msg_events());
 			let mut peers_lock = self.peers.lock().unwrap();
 			let peers = &mut *peers_lock;
 			for event in events_generated.drain(..) {
@@ -898,7 +1096,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
 								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendOpenChannel { ref node_id, ref msg } => {
@@ -908,7 +1106,27 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
 								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendAcceptChannelV2 { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendAcceptChannelV2 event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.temporary_channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendOpenChannelV2 { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendOpenChannelV2 event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.temporary_channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendFundingCreated { ref node_id, ref msg } => {
@@ -917,10 +1135,10 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 								log_bytes!(msg.temporary_channel_id),
 								log_funding_channel_id!(msg.funding_txid, msg.funding_output_index));
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
-								//TODO: generate a DiscardFunding event indicating to the wallet that
-								//they should just throw away this funding transaction
+								// The channel will be dropped in peer_disconnected, generating a
+								// DiscardFunding event if the funding transaction is ours.
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendFundingSigned { ref node_id, ref msg } => {
@@ -928,10 +1146,10 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 								log_pubkey!(node_id),
 								log_bytes!(msg.channel_id));
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
-								//TODO: generate a DiscardFunding event indicating to the wallet that
-								//they should just throw away this funding transaction
+								// The channel will be dropped in peer_disconnected, generating a
+								// DiscardFunding event if the funding transaction is ours.
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendFundingLocked { ref node_id, ref msg } => {
@@ -941,7 +1159,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
 								//TODO: Do whatever we're gonna do for handling dropped messages
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendAnnouncementSignatures { ref node_id, ref msg } => {
@@ -949,10 +1167,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 								log_pubkey!(node_id),
 								log_bytes!(msg.channel_id));
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
-								//TODO: generate a DiscardFunding event indicating to the wallet that
-								//they should just throw away this funding transaction
+								//TODO: Do whatever we're gonna do for handling dropped messages
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::UpdateHTLCs { ref node_id, updates: msgs::CommitmentUpdate { ref update_add_htlcs, ref update_fulfill_htlcs, ref update_fail_htlcs, ref update_fail_malformed_htlcs, ref update_fee, ref commitment_signed } } => {
@@ -966,21 +1183,21 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 								//TODO: Do whatever we're gonna do for handling dropped messages
 							});
 						for msg in update_add_htlcs {
-							peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+							peer.enqueue_encoded_message(&encode_msg!(msg));
 						}
 						for msg in update_fulfill_htlcs {
-							peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+							peer.enqueue_encoded_message(&encode_msg!(msg));
 						}
 						for msg in update_fail_htlcs {
-							peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+							peer.enqueue_encoded_message(&encode_msg!(msg));
 						}
 						for msg in update_fail_malformed_htlcs {
-							peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+							peer.enqueue_encoded_message(&encode_msg!(msg));
 						}
 						if let &Some(ref msg) = update_fee {
-							peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+							peer.enqueue_encoded_message(&encode_msg!(msg));
 						}
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(commitment_signed)));
+						peer.enqueue_encoded_message(&encode_msg!(commitment_signed));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendRevokeAndACK { ref node_id, ref msg } => {
@@ -990,7 +1207,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
 								//TODO: Do whatever we're gonna do for handling dropped messages
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendClosingSigned { ref node_id, ref msg } => {
@@ -1000,7 +1217,117 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
 								//TODO: Do whatever we're gonna do for handling dropped messages
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendStfu { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendStfu event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSpliceInit { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSpliceInit event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSpliceAck { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSpliceAck event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSpliceLocked { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSpliceLocked event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAddInput { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAddInput event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAddOutput { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAddOutput event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxRemoveInput { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxRemoveInput event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxRemoveOutput { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxRemoveOutput event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxComplete { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxComplete event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxSignatures { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxSignatures event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAbort { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAbort event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendShutdown { ref node_id, ref msg } => {
@@ -1010,7 +1337,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
 								//TODO: Do whatever we're gonna do for handling dropped messages
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::SendChannelReestablish { ref node_id, ref msg } => {
@@ -1020,7 +1347,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
 								//TODO: Do whatever we're gonna do for handling dropped messages
 							});
-						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						peer.enqueue_encoded_message(&encode_msg!(msg));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
 					MessageSendEvent::BroadcastChannelAnnouncement { ref msg, ref update_msg } => {
@@ -1031,7 +1358,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
 								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_channel_announcement(msg.contents.short_channel_id) {
+										!peer.should_forward_channel_announcement(msg.contents.short_channel_id) || peer.should_drop_gossip() {
 									continue
 								}
 								match peer.their_node_id {
@@ -1042,8 +1369,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 										}
 									}
 								}
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_update_msg[..]));
+								peer.enqueue_encoded_message(&encoded_msg[..]);
+								peer.enqueue_encoded_message(&encoded_update_msg[..]);
 								self.do_attempt_write_data(&mut (*descriptor).clone(), peer);
 							}
 						}
@@ -1055,10 +1382,10 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
 								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_node_announcement(msg.contents.node_id) {
+										!peer.should_forward_node_announcement(msg.contents.node_id) || peer.should_drop_gossip() {
 									continue
 								}
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
+								peer.enqueue_encoded_message(&encoded_msg[..]);
 								self.do_attempt_write_data(&mut (*descriptor).clone(), peer);
 							}
 						}
@@ -1070,14 +1397,83 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
 								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_channel_announcement(msg.contents.short_channel_id)  {
+										!peer.should_forward_channel_announcement(msg.contents.short_channel_id) || peer.should_drop_gossip() {
 									continue
 								}
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
+								peer.enqueue_encoded_message(&encoded_msg[..]);
 								self.do_attempt_write_data(&mut (*descriptor).clone(), peer);
 							}
 						}
 					},
+					MessageSendEvent::SendChannelAnnouncement { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendChannelAnnouncement event in peer_handler for node {} for short channel id {}",
+								log_pubkey!(node_id),
+								msg.contents.short_channel_id);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendNodeAnnouncement { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendNodeAnnouncement event in peer_handler for node {} for node {}",
+								log_pubkey!(node_id),
+								log_pubkey!(msg.contents.node_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendChannelUpdate { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendChannelUpdate event in peer_handler for node {} for short channel id {}",
+								log_pubkey!(node_id),
+								msg.contents.short_channel_id);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendReplyChannelRange { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendReplyChannelRange event in peer_handler for node {} with first_blocknum={}, number_of_blocks={}, full_information={}, and {} SCIDs",
+								log_pubkey!(node_id),
+								msg.first_blocknum,
+								msg.number_of_blocks,
+								msg.full_information,
+								msg.short_channel_ids.len());
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendReplyShortChannelIdsEnd { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendReplyShortChannelIdsEnd event in peer_handler for node {} with full_information={}",
+								log_pubkey!(node_id),
+								msg.full_information);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendChannelRangeQuery { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendChannelRangeQuery event in peer_handler for node {} with first_blocknum={}, number_of_blocks={}",
+								log_pubkey!(node_id),
+								msg.first_blocknum,
+								msg.number_of_blocks);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendShortIdsQuery { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendShortIdsQuery event in peer_handler for node {} with {} SCIDs",
+								log_pubkey!(node_id),
+								msg.short_channel_ids.len());
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendGossipTimestampFilter { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendGossipTimestampFilter event in peer_handler for node {} with first_timestamp={}, timestamp_range={}",
+								log_pubkey!(node_id),
+								msg.first_timestamp,
+								msg.timestamp_range);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.enqueue_encoded_message(&encode_msg!(msg));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
 					MessageSendEvent::PaymentFailureNetworkUpdate { ref update } => {
 						self.message_handler.route_handler.handle_htlc_fail_channel_update(update);
 					},
@@ -1091,7 +1487,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 											log_trace!(self.logger, "Handling DisconnectPeer HandleError event in peer_handler for node {} with message {}",
 													log_pubkey!(node_id),
 													msg.data);
-											peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+											peer.enqueue_encoded_message(&encode_msg!(msg));
 											// This isn't guaranteed to work, but if there is enough free
 											// room in the send buffer, put the error message there...
 											self.do_attempt_write_data(&mut descriptor, &mut peer);
@@ -1101,6 +1497,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 									}
 									descriptor.disconnect_socket();
 									self.message_handler.chan_handler.peer_disconnected(&node_id, false);
+									self.message_handler.route_handler.peer_disconnected(&node_id);
 								}
 							},
 							msgs::ErrorAction::IgnoreError => {},
@@ -1111,7 +1508,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 								let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
 									//TODO: Do whatever we're gonna do for handling dropped messages
 								});
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+								peer.enqueue_encoded_message(&encode_msg!(msg));
 								self.do_attempt_write_data(&mut descriptor, peer);
 							},
 						}
@@ -1151,6 +1548,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					Some(node_id) => {
 						peers.node_id_to_descriptor.remove(&node_id);
 						self.message_handler.chan_handler.peer_disconnected(&node_id, no_connection_possible);
+						self.message_handler.route_handler.peer_disconnected(&node_id);
 					},
 					None => {}
 				}
@@ -1159,7 +1557,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 	}
 
 	/// This function should be called roughly once every 30 seconds.
-	/// It will send pings to each peer and disconnect those which did not respond to the last round of pings.
+	/// It will send pings to each peer and disconnect those which did not respond to the last round of pings,
+	/// as well as those whose outbound buffer has stayed too full for us to send them gossip for
+	/// several calls in a row.
 
 	/// Will most likely call send_data on all of the registered descriptors, thus, be very careful with reentrancy issues!
 	pub fn timer_tick_occured(&self) {
@@ -1172,14 +1572,22 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			let mut descriptors_needing_disconnect = Vec::new();
 
 			peers.retain(|descriptor, peer| {
-				if peer.awaiting_pong {
+				if peer.should_drop_gossip() {
+					peer.outbound_buffer_backlogged_ticks += 1;
+				} else {
+					peer.outbound_buffer_backlogged_ticks = 0;
+				}
+				let backlogged = peer.outbound_buffer_backlogged_ticks > MAX_OUTBOUND_BUFFER_BACKLOGGED_TICKS;
+				if peer.awaiting_pong || backlogged {
 					peers_needing_send.remove(descriptor);
 					descriptors_needing_disconnect.push(descriptor.clone());
 					match peer.their_node_id {
 						Some(node_id) => {
-							log_trace!(self.logger, "Disconnecting peer with id {} due to ping timeout", node_id);
+							log_trace!(self.logger, "Disconnecting peer with id {} due to {}", node_id,
+								if peer.awaiting_pong { "ping timeout" } else { "its outbound buffer staying full" });
 							node_id_to_descriptor.remove(&node_id);
 							self.message_handler.chan_handler.peer_disconnected(&node_id, false);
+							self.message_handler.route_handler.peer_disconnected(&node_id);
 						}
 						None => {
 							// This can't actually happen as we should have hit
@@ -1199,7 +1607,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					ponglen: 0,
 					byteslen: 64,
 				};
-				peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(&ping)));
+				peer.enqueue_encoded_message(&encode_msg!(&ping));
 
 				let mut descriptor_clone = descriptor.clone();
 				self.do_attempt_write_data(&mut descriptor_clone, peer);
@@ -1219,20 +1627,25 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 mod tests {
 	use ln::peer_handler::{PeerManager, MessageHandler, SocketDescriptor};
 	use ln::msgs;
+	use ln::wire;
 	use util::events;
+	use util::ser::VecWriter;
 	use util::test_utils;
 
+	use bitcoin::blockdata::script::Script;
 	use bitcoin::secp256k1::Secp256k1;
 	use bitcoin::secp256k1::key::{SecretKey, PublicKey};
 
 	use std;
 	use std::sync::{Arc, Mutex};
-	use std::sync::atomic::Ordering;
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 	#[derive(Clone)]
 	struct FileDescriptor {
 		fd: u16,
 		outbound_data: Arc<Mutex<Vec<u8>>>,
+		// While set, send_data accepts no data, as if the peer had stopped reading.
+		hang_writes: Arc<AtomicBool>,
 	}
 	impl PartialEq for FileDescriptor {
 		fn eq(&self, other: &Self) -> bool {
@@ -1248,6 +1661,9 @@ mod tests {
 
 	impl SocketDescriptor for FileDescriptor {
 		fn send_data(&mut self, data: &[u8], _resume_read: bool) -> usize {
+			if self.hang_writes.load(Ordering::Acquire) {
+				return 0;
+			}
 			self.outbound_data.lock().unwrap().extend_from_slice(data);
 			data.len()
 		}
@@ -1289,11 +1705,15 @@ mod tests {
 		peers
 	}
 
+	// Descriptors must be unique per PeerManager, so we hand out fresh ones for each connection.
+	static NEXT_FD: AtomicUsize = AtomicUsize::new(1);
+
 	fn establish_connection<'a>(peer_a: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>, peer_b: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>) -> (FileDescriptor, FileDescriptor) {
 		let secp_ctx = Secp256k1::new();
 		let a_id = PublicKey::from_secret_key(&secp_ctx, &peer_a.our_node_secret);
-		let mut fd_a = FileDescriptor { fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())) };
-		let mut fd_b = FileDescriptor { fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())) };
+		let fd = NEXT_FD.fetch_add(1, Ordering::AcqRel) as u16;
+		let mut fd_a = FileDescriptor { fd, outbound_data: Arc::new(Mutex::new(Vec::new())), hang_writes: Arc::new(AtomicBool::new(false)) };
+		let mut fd_b = FileDescriptor { fd, outbound_data: Arc::new(Mutex::new(Vec::new())), hang_writes: Arc::new(AtomicBool::new(false)) };
 		let initial_data = peer_b.new_outbound_connection(a_id, fd_b.clone()).unwrap();
 		peer_a.new_inbound_connection(fd_a.clone()).unwrap();
 		assert_eq!(peer_a.read_event(&mut fd_a, &initial_data).unwrap(), false);
@@ -1371,16 +1791,18 @@ mod tests {
 		peers[0].read_event(&mut fd_a, &fd_b.outbound_data.lock().unwrap().split_off(0)).unwrap();
 
 		// Check that each peer has received the expected number of channel updates and channel
-		// announcements.
-		assert_eq!(cfgs[0].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 100);
-		assert_eq!(cfgs[0].routing_handler.chan_anns_recvd.load(Ordering::Acquire), 50);
+		// announcements. Inbound peer 0 only requests initial_routing_sync from peers which don't
+		// support gossip_queries, so only outbound peer 1 gets a full dump.
+		assert_eq!(cfgs[0].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 0);
+		assert_eq!(cfgs[0].routing_handler.chan_anns_recvd.load(Ordering::Acquire), 0);
 		assert_eq!(cfgs[1].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 100);
 		assert_eq!(cfgs[1].routing_handler.chan_anns_recvd.load(Ordering::Acquire), 50);
 	}
 
 	#[test]
 	fn limit_initial_routing_sync_requests() {
-		// Inbound peer 0 requests initial_routing_sync, but outbound peer 1 does not.
+		// Inbound peer 0 would request initial_routing_sync, but doesn't as it syncs with outbound
+		// peer 1 using gossip_queries, and outbound peer 1 does not.
 		{
 			let cfgs = create_peermgr_cfgs(2);
 			cfgs[0].routing_handler.request_full_sync.store(true, Ordering::Release);
@@ -1393,7 +1815,7 @@ mod tests {
 			let peer_0_features = peer_1.peers.get(&fd_1_to_0).unwrap().their_features.as_ref();
 			let peer_1_features = peer_0.peers.get(&fd_0_to_1).unwrap().their_features.as_ref();
 
-			assert!(peer_0_features.unwrap().initial_routing_sync());
+			assert!(!peer_0_features.unwrap().initial_routing_sync());
 			assert!(!peer_1_features.unwrap().initial_routing_sync());
 		}
 
@@ -1414,4 +1836,117 @@ mod tests {
 			assert!(peer_1_features.unwrap().initial_routing_sync());
 		}
 	}
+	#[test]
+	fn test_gossip_relay() {
+		// Connect peers 0 and 2 through peer 1, which accepts and relays channel_updates, and check
+		// that channel_updates from peer 0 reach peer 2 only when its gossip_timestamp_filter and
+		// outbound buffer allow.
+		let cfgs = create_peermgr_cfgs(3);
+		cfgs[1].routing_handler.relay_chan_upds.store(true, Ordering::Release);
+		let peers = create_network(3, &cfgs);
+		let (mut fd_1_to_0, mut fd_0_to_1) = establish_connection_and_read_events(&peers[1], &peers[0]);
+		let (mut fd_1_to_2, mut fd_2_to_1) = establish_connection_and_read_events(&peers[1], &peers[2]);
+		let secp_ctx = Secp256k1::new();
+		let id_1 = PublicKey::from_secret_key(&secp_ctx, &peers[1].our_node_secret);
+
+		macro_rules! deliver_update {
+			($timestamp: expr) => { {
+				let mut msg = test_utils::get_dummy_channel_update(42);
+				msg.contents.timestamp = $timestamp;
+				cfgs[0].chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::SendChannelUpdate { node_id: id_1, msg });
+				peers[0].process_events();
+				peers[1].read_event(&mut fd_1_to_0, &fd_0_to_1.outbound_data.lock().unwrap().split_off(0)).unwrap();
+				peers[1].process_events();
+				peers[2].read_event(&mut fd_2_to_1, &fd_1_to_2.outbound_data.lock().unwrap().split_off(0)).unwrap();
+				// The update is never relayed back to the peer we got it from.
+				assert!(fd_1_to_0.outbound_data.lock().unwrap().is_empty());
+			} }
+		}
+		macro_rules! set_filter {
+			($first_timestamp: expr, $timestamp_range: expr) => { {
+				cfgs[2].chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::SendGossipTimestampFilter {
+					node_id: id_1,
+					msg: msgs::GossipTimestampFilter { chain_hash: Default::default(), first_timestamp: $first_timestamp, timestamp_range: $timestamp_range },
+				});
+				peers[2].process_events();
+				peers[1].read_event(&mut fd_1_to_2, &fd_2_to_1.outbound_data.lock().unwrap().split_off(0)).unwrap();
+			} }
+		}
+
+		// Peer 2 negotiated gossip_queries, so gets nothing until it sets a filter.
+		deliver_update!(100);
+		assert_eq!(cfgs[1].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 1);
+		assert_eq!(cfgs[2].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 0);
+
+		set_filter!(1000, 100);
+		deliver_update!(200);
+		assert_eq!(cfgs[2].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 0);
+		set_filter!(0, 1000);
+		deliver_update!(300);
+		assert_eq!(cfgs[2].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 1);
+
+		// Once peer 2's outbound buffer is full, gossip is no longer relayed to it.
+		{
+			let mut peers_lock = peers[1].peers.lock().unwrap();
+			let peer = peers_lock.peers.get_mut(&fd_1_to_2).unwrap();
+			peer.push_outbound_buffer(vec![0; super::OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP]);
+		}
+		deliver_update!(400);
+		assert_eq!(cfgs[1].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 4);
+		assert_eq!(cfgs[2].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 1);
+	}
+	#[test]
+	fn test_outbound_buffer_backpressure() {
+		// Have peer 1 request large pongs from peer 0 while peer 0 can't write to it, checking that
+		// peer 0 stops reading, then stops sending gossip, and finally disconnects peer 1, while
+		// channel messages are always queued.
+		let cfgs = create_peermgr_cfgs(2);
+		cfgs[0].routing_handler.relay_chan_upds.store(true, Ordering::Release);
+		let peers = create_network(2, &cfgs);
+		let (fd_0_to_1, mut fd_1_to_0) = establish_connection_and_read_events(&peers[0], &peers[1]);
+		let secp_ctx = Secp256k1::new();
+		let id_1 = PublicKey::from_secret_key(&secp_ctx, &peers[1].our_node_secret);
+		fd_0_to_1.hang_writes.store(true, Ordering::Release);
+
+		let outbound_buffer_bytes = || peers[0].peers.lock().unwrap().peers.get(&fd_0_to_1).unwrap().pending_outbound_buffer_bytes;
+		let mut send_ping = || {
+			{
+				let mut peers_lock = peers[1].peers.lock().unwrap();
+				let peer = peers_lock.peers.get_mut(&fd_1_to_0).unwrap();
+				peer.enqueue_encoded_message(&encode_msg!(&msgs::Ping { ponglen: 40000, byteslen: 0 }));
+			}
+			peers[1].write_buffer_space_avail(&mut fd_1_to_0).unwrap();
+			peers[0].read_event(&mut fd_0_to_1.clone(), &fd_1_to_0.outbound_data.lock().unwrap().split_off(0)).unwrap()
+		};
+
+		assert!(!send_ping());
+		assert!(send_ping());
+		// A driver would stop calling read_event here, but keep going to fill the buffer further.
+		assert!(send_ping());
+		assert!(send_ping());
+		assert!(outbound_buffer_bytes() > super::OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP);
+
+		let buffer_bytes = outbound_buffer_bytes();
+		cfgs[0].chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::BroadcastChannelUpdate { msg: test_utils::get_dummy_channel_update(42) });
+		peers[0].process_events();
+		assert_eq!(cfgs[0].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 1);
+		assert_eq!(outbound_buffer_bytes(), buffer_bytes);
+
+		cfgs[0].chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::SendShutdown {
+			node_id: id_1,
+			msg: msgs::Shutdown { channel_id: [0; 32], scriptpubkey: Script::new() },
+		});
+		peers[0].process_events();
+		assert!(outbound_buffer_bytes() > buffer_bytes);
+
+		// Peer 1 is disconnected once its buffer has stayed full for too long, even though it
+		// responds to pings.
+		for _ in 0..super::MAX_OUTBOUND_BUFFER_BACKLOGGED_TICKS {
+			peers[0].timer_tick_occured();
+			peers[0].peers.lock().unwrap().peers.get_mut(&fd_0_to_1).unwrap().awaiting_pong = false;
+		}
+		assert_eq!(peers[0].peers.lock().unwrap().peers.len(), 1);
+		peers[0].timer_tick_occured();
+		assert_eq!(peers[0].peers.lock().unwrap().peers.len(), 0);
+	}
 }
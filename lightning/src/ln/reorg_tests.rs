@@ -12,7 +12,7 @@
 use ln::channelmonitor::ANTI_REORG_DELAY;
 use ln::features::InitFeatures;
 use ln::msgs::{ChannelMessageHandler, ErrorAction, HTLCFailChannelUpdate};
-use util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
+use util::events::{ClosureReason, Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
 
 use bitcoin::blockdata::block::{Block, BlockHeader};
 
@@ -65,6 +65,7 @@ fn do_test_onchain_htlc_reorg(local_commitment: bool, claim: bool) {
 		nodes[2].block_notifier.block_connected(&Block { header, txdata: node_1_commitment_txn.clone() }, CHAN_CONFIRM_DEPTH + 1);
 		check_added_monitors!(nodes[2], 1);
 		check_closed_broadcast!(nodes[2], false); // We should get a BroadcastChannelUpdate (and *only* a BroadcstChannelUpdate)
+		check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 		let node_2_commitment_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap();
 		assert_eq!(node_2_commitment_txn.len(), 3); // ChannelMonitor: 1 offered HTLC-Claim, ChannelManger: 1 local commitment tx, 1 Received HTLC-Claim
 		assert_eq!(node_2_commitment_txn[1].output.len(), 2); // to-remote and Received HTLC (to-self is dust)
@@ -101,6 +102,7 @@ fn do_test_onchain_htlc_reorg(local_commitment: bool, claim: bool) {
 	};
 	check_added_monitors!(nodes[1], 1);
 	check_closed_broadcast!(nodes[1], false); // We should get a BroadcastChannelUpdate (and *only* a BroadcstChannelUpdate)
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	headers.push(header.clone());
 	// At CHAN_CONFIRM_DEPTH + 1 we have a confirmation count of 1, so CHAN_CONFIRM_DEPTH +
 	// ANTI_REORG_DELAY - 1 will give us a confirmation count of ANTI_REORG_DELAY - 1.
@@ -122,8 +124,9 @@ fn do_test_onchain_htlc_reorg(local_commitment: bool, claim: bool) {
 		nodes[1].block_notifier.block_connected(&Block { header, txdata: claim_txn }, CHAN_CONFIRM_DEPTH + 1);
 
 		// ChannelManager only polls ManyChannelMonitor::get_and_clear_pending_monitor_events when we
-		// probe it for events, so we probe non-message events here (which should still end up empty):
-		assert_eq!(nodes[1].node.get_and_clear_pending_events().len(), 0);
+		// probe it for events, so we probe non-message events here (which should only tell us about
+		// the forward we just claimed):
+		expect_payment_forwarded!(nodes[1], Some(239), true);
 	} else {
 		// Confirm the timeout tx and check that we fail the HTLC backwards
 		header = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
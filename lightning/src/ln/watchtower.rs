@@ -0,0 +1,314 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Watchtowers let a node which can't stay online hand off the job of punishing counterparties
+//! who broadcast revoked commitment transactions.
+//!
+//! Whenever a counterparty revokes one of their commitment transactions, a WatchtowerClient builds
+//! and signs a justice transaction claiming the outputs of that commitment transaction, and
+//! encrypts it into a JusticeBlob keyed by the commitment transaction's txid. The blob only
+//! reveals its first half (the "hint"), so a tower learns nothing about our channels unless the
+//! revoked commitment transaction is actually broadcast.
+//!
+//! A WatchtowerServer stores the blobs it is sent and watches the chain for transactions whose
+//! txid begins with a stored hint, at which point it can decrypt the matching blob and broadcast
+//! the justice transaction on our behalf.
+//!
+//! How blobs get from clients to towers is left to the user.
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use bitcoin::hash_types::Txid;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
+
+use chain::chaininterface::{BroadcasterInterface, ChainListener, ConfirmationTarget, FeeEstimator};
+use chain::keysinterface::ChannelKeys;
+use chain::transaction::OutPoint;
+use ln::chan_utils::HTLCOutputInCommitment;
+use ln::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateStep};
+use ln::msgs::DecodeError;
+use util::chacha20poly1305rfc::ChaCha20Poly1305RFC;
+use util::logger::Logger;
+use util::ser::{Readable, ReadableArgs, Writeable, Writer};
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+/// The number of bytes of a revoked commitment transaction's txid which are given to a tower in
+/// the clear, to look up blobs by.
+pub const HINT_LEN: usize = 16;
+
+/// The largest encrypted justice transaction we'll read, a standard transaction's maximum size
+/// plus the authentication tag.
+const MAX_ENCRYPTED_JUSTICE_TX_LEN: u32 = 400_000 + 16;
+
+/// A justice transaction for a revoked counterparty commitment transaction, encrypted with a key
+/// derived from the commitment transaction's txid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JusticeBlob {
+	/// The first HINT_LEN bytes of the revoked commitment transaction's txid.
+	pub hint: [u8; HINT_LEN],
+	/// The serialized justice transaction, encrypted with ChaCha20Poly1305, followed by its tag.
+	pub encrypted_justice_tx: Vec<u8>,
+}
+
+impl JusticeBlob {
+	/// Encrypts a justice transaction spending from the given revoked commitment transaction.
+	pub fn new(revoked_commitment_txid: &Txid, justice_tx: &Transaction) -> Self {
+		let plaintext = encode::serialize(justice_tx);
+		let mut encrypted_justice_tx = vec![0; plaintext.len() + 16];
+		let (ciphertext, tag) = encrypted_justice_tx.split_at_mut(plaintext.len());
+		Self::cipher(revoked_commitment_txid).encrypt(&plaintext, ciphertext, tag);
+		Self { hint: Self::hint_for(revoked_commitment_txid), encrypted_justice_tx }
+	}
+
+	/// Gets the hint under which blobs for justice transactions spending from the transaction with
+	/// the given txid are stored.
+	pub fn hint_for(txid: &Txid) -> [u8; HINT_LEN] {
+		let mut hint = [0; HINT_LEN];
+		hint.copy_from_slice(&txid[..HINT_LEN]);
+		hint
+	}
+
+	/// Decrypts the justice transaction, given the txid of the commitment transaction it spends
+	/// from. Returns None if the blob wasn't encrypted for that txid.
+	pub fn decrypt(&self, commitment_txid: &Txid) -> Option<Transaction> {
+		if self.hint != Self::hint_for(commitment_txid) || self.encrypted_justice_tx.len() < 16 {
+			return None;
+		}
+		let (ciphertext, tag) = self.encrypted_justice_tx.split_at(self.encrypted_justice_tx.len() - 16);
+		let mut plaintext = vec![0; ciphertext.len()];
+		if !Self::cipher(commitment_txid).decrypt(ciphertext, &mut plaintext, tag) {
+			return None;
+		}
+		encode::deserialize(&plaintext).ok()
+	}
+
+	fn cipher(commitment_txid: &Txid) -> ChaCha20Poly1305RFC {
+		// Each key is only ever used to encrypt a single justice transaction, so a fixed nonce is
+		// fine.
+		let key = Sha256::hash(&commitment_txid[..]);
+		ChaCha20Poly1305RFC::new(&key[..], &[0; 12], &[])
+	}
+}
+
+impl Writeable for JusticeBlob {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.hint.write(w)?;
+		(self.encrypted_justice_tx.len() as u32).write(w)?;
+		w.write_all(&self.encrypted_justice_tx)
+	}
+}
+
+impl Readable for JusticeBlob {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let hint = Readable::read(r)?;
+		let len: u32 = Readable::read(r)?;
+		if len > MAX_ENCRYPTED_JUSTICE_TX_LEN {
+			return Err(DecodeError::InvalidValue);
+		}
+		let mut encrypted_justice_tx = vec![0; len as usize];
+		r.read_exact(&mut encrypted_justice_tx)?;
+		Ok(Self { hint, encrypted_justice_tx })
+	}
+}
+
+/// Produces JusticeBlobs for a node's channels as their ChannelMonitors are updated.
+///
+/// Each ChannelMonitorUpdate should be passed to process_update once it has been applied to its
+/// ChannelMonitor, eg from a ManyChannelMonitor wrapping a SimpleManyChannelMonitor. Updates which
+/// provide a new counterparty commitment transaction are remembered until the counterparty
+/// revokes it, at which point a justice transaction paying to the ChannelMonitor's destination
+/// script is signed, at the current high-priority feerate, and returned in a JusticeBlob.
+///
+/// Counterparty commitment transactions are only remembered in memory, so the blobs for any which
+/// are unrevoked when the client is dropped will never be produced. This includes the very first
+/// counterparty commitment transaction of each channel, which is never part of an update.
+pub struct WatchtowerClient<F: Deref> where F::Target: FeeEstimator {
+	fee_estimator: F,
+	/// Unrevoked counterparty commitment transactions, along with their HTLC outputs, by the
+	/// channel's funding outpoint and the commitment transaction number.
+	unrevoked_commitments: Mutex<HashMap<OutPoint, HashMap<u64, (Transaction, Vec<HTLCOutputInCommitment>)>>>,
+}
+
+impl<F: Deref> WatchtowerClient<F> where F::Target: FeeEstimator {
+	/// Creates a new WatchtowerClient, using the given FeeEstimator to pick the feerate of justice
+	/// transactions.
+	pub fn new(fee_estimator: F) -> Self {
+		Self { fee_estimator, unrevoked_commitments: Mutex::new(HashMap::new()) }
+	}
+
+	/// Processes an update which has been applied to the given ChannelMonitor, returning a
+	/// JusticeBlob for each counterparty commitment transaction it revoked, to be sent to our
+	/// watchtowers.
+	pub fn process_update<ChanSigner: ChannelKeys>(&self, monitor: &ChannelMonitor<ChanSigner>, update: &ChannelMonitorUpdate) -> Vec<JusticeBlob> {
+		let funding_txo = monitor.get_funding_txo().0;
+		let mut unrevoked_commitments = self.unrevoked_commitments.lock().unwrap();
+		let mut blobs = Vec::new();
+		for step in update.updates.iter() {
+			match step {
+				&ChannelMonitorUpdateStep::LatestCounterpartyCommitmentTXInfo { ref unsigned_commitment_tx, ref htlc_outputs, commitment_number, .. } => {
+					let htlcs = htlc_outputs.iter().map(|&(ref htlc, _)| htlc.clone()).collect();
+					unrevoked_commitments.entry(funding_txo).or_insert_with(HashMap::new)
+						.insert(commitment_number, (unsigned_commitment_tx.clone(), htlcs));
+				},
+				&ChannelMonitorUpdateStep::CommitmentSecret { idx, ref secret } => {
+					if let Some(channel_commitments) = unrevoked_commitments.get_mut(&funding_txo) {
+						if let Some((commitment_tx, htlcs)) = channel_commitments.remove(&idx) {
+							let feerate_per_kw = self.fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority) as u64;
+							if let Some(justice_tx) = monitor.sign_justice_tx(&commitment_tx, &htlcs, secret, feerate_per_kw) {
+								blobs.push(JusticeBlob::new(&commitment_tx.txid(), &justice_tx));
+							}
+						}
+						// Commitment numbers count down, so any earlier commitment transaction is now
+						// revoked as well.
+						channel_commitments.retain(|&commitment_number, _| commitment_number < idx);
+					}
+				},
+				&ChannelMonitorUpdateStep::ChannelForceClosed { .. } => {
+					unrevoked_commitments.remove(&funding_txo);
+				},
+				_ => {},
+			}
+		}
+		blobs
+	}
+}
+
+/// Stores JusticeBlobs sent by WatchtowerClients and broadcasts the justice transactions they hold
+/// if the corresponding revoked commitment transactions appear on chain.
+///
+/// The WatchtowerServer must be registered as a ChainListener with a BlockNotifier whose
+/// ChainWatchInterface watches all transactions (see ChainWatchInterface::watch_all_txn), as the
+/// server can't know which transactions it's looking for ahead of time.
+pub struct WatchtowerServer<B: Deref, L: Deref> where B::Target: BroadcasterInterface, L::Target: Logger {
+	/// Encrypted justice transactions by hint. More than one blob may share a hint, eg if a client
+	/// re-sends a blob after its feerate estimate changed.
+	blobs: Mutex<HashMap<[u8; HINT_LEN], Vec<Vec<u8>>>>,
+	broadcaster: B,
+	logger: L,
+}
+
+impl<B: Deref, L: Deref> WatchtowerServer<B, L> where B::Target: BroadcasterInterface, L::Target: Logger {
+	/// Creates a new WatchtowerServer storing no blobs.
+	pub fn new(broadcaster: B, logger: L) -> Self {
+		Self { blobs: Mutex::new(HashMap::new()), broadcaster, logger }
+	}
+
+	/// Stores a blob sent by a client.
+	pub fn add_blob(&self, blob: JusticeBlob) {
+		let mut blobs = self.blobs.lock().unwrap();
+		let hint_blobs = blobs.entry(blob.hint).or_insert_with(Vec::new);
+		if !hint_blobs.contains(&blob.encrypted_justice_tx) {
+			hint_blobs.push(blob.encrypted_justice_tx);
+		}
+	}
+
+	/// Gets the number of blobs stored.
+	pub fn blob_count(&self) -> usize {
+		self.blobs.lock().unwrap().values().map(|hint_blobs| hint_blobs.len()).sum()
+	}
+}
+
+impl<B: Deref + Sync + Send, L: Deref + Sync + Send> ChainListener for WatchtowerServer<B, L> where B::Target: BroadcasterInterface, L::Target: Logger {
+	fn block_connected(&self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], _indexes_of_txn_matched: &[usize]) {
+		let blobs = self.blobs.lock().unwrap();
+		for tx in txn_matched {
+			let txid = tx.txid();
+			if let Some(hint_blobs) = blobs.get(&JusticeBlob::hint_for(&txid)) {
+				for encrypted_justice_tx in hint_blobs.iter() {
+					// Blobs are kept after broadcasting, in case the revoked commitment transaction is
+					// reorged out and broadcast again later.
+					let blob = JusticeBlob { hint: JusticeBlob::hint_for(&txid), encrypted_justice_tx: encrypted_justice_tx.clone() };
+					if let Some(justice_tx) = blob.decrypt(&txid) {
+						log_info!(self.logger, "Broadcasting justice transaction {} for revoked commitment transaction {} in block {} at height {}", justice_tx.txid(), txid, header.block_hash(), height);
+						self.broadcaster.broadcast_transaction(&justice_tx);
+					}
+				}
+			}
+		}
+	}
+
+	fn block_disconnected(&self, _header: &BlockHeader, _disconnected_height: u32) {}
+}
+
+impl<B: Deref, L: Deref> Writeable for WatchtowerServer<B, L> where B::Target: BroadcasterInterface, L::Target: Logger {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		let blobs = self.blobs.lock().unwrap();
+		(blobs.len() as u64).write(w)?;
+		for (hint, hint_blobs) in blobs.iter() {
+			(hint_blobs.len() as u64).write(w)?;
+			for encrypted_justice_tx in hint_blobs.iter() {
+				JusticeBlob { hint: *hint, encrypted_justice_tx: encrypted_justice_tx.clone() }.write(w)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+impl<B: Deref, L: Deref> ReadableArgs<(B, L)> for WatchtowerServer<B, L> where B::Target: BroadcasterInterface, L::Target: Logger {
+	fn read<R: Read>(r: &mut R, args: (B, L)) -> Result<Self, DecodeError> {
+		let (broadcaster, logger) = args;
+		let server = Self::new(broadcaster, logger);
+		let hint_count: u64 = Readable::read(r)?;
+		for _ in 0..hint_count {
+			let blob_count: u64 = Readable::read(r)?;
+			for _ in 0..blob_count {
+				server.add_blob(Readable::read(r)?);
+			}
+		}
+		Ok(server)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::transaction::{OutPoint as BitcoinOutPoint, Transaction, TxIn, TxOut};
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::hash_types::Txid;
+	use bitcoin::hashes::Hash;
+	use bitcoin::hashes::sha256d::Hash as Sha256dHash;
+	use ln::watchtower::JusticeBlob;
+	use util::ser::{Readable, Writeable};
+
+	fn dummy_tx(value: u64) -> Transaction {
+		Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn { previous_output: BitcoinOutPoint::null(), script_sig: Script::new(), sequence: 0xffffffff, witness: Vec::new() }],
+			output: vec![TxOut { value, script_pubkey: Script::new() }],
+		}
+	}
+
+	#[test]
+	fn blob_round_trip() {
+		let commitment_txid = dummy_tx(1).txid();
+		let justice_tx = dummy_tx(2);
+		let blob = JusticeBlob::new(&commitment_txid, &justice_tx);
+		assert_eq!(&blob.hint[..], &commitment_txid[..16]);
+		assert_eq!(blob.decrypt(&commitment_txid), Some(justice_tx));
+
+		// A txid sharing the hint but not the rest of its bytes can't decrypt the blob.
+		let mut other_txid_bytes = commitment_txid.into_inner();
+		other_txid_bytes[31] ^= 1;
+		assert_eq!(blob.decrypt(&Txid::from_inner(other_txid_bytes)), None);
+		assert_eq!(blob.decrypt(&Txid::from_hash(Sha256dHash::hash(&[]))), None);
+
+		let mut tampered_blob = blob.clone();
+		tampered_blob.encrypted_justice_tx[0] ^= 1;
+		assert_eq!(tampered_blob.decrypt(&commitment_txid), None);
+
+		let encoded = blob.encode();
+		assert_eq!(JusticeBlob::read(&mut &encoded[..]).unwrap(), blob);
+	}
+}
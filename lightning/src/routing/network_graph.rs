@@ -17,26 +17,30 @@ use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 use bitcoin::hashes::Hash;
 use bitcoin::blockdata::script::Builder;
 use bitcoin::blockdata::opcodes;
+use bitcoin::hash_types::BlockHash;
 
 use chain::chaininterface::{ChainError, ChainWatchInterface};
 use ln::features::{ChannelFeatures, NodeFeatures};
 use ln::msgs::{DecodeError, ErrorAction, LightningError, RoutingMessageHandler, NetAddress, MAX_VALUE_MSAT};
 use ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement, OptionalField};
+use ln::msgs::{QueryChannelRange, ReplyChannelRange, QueryShortChannelIds, ReplyShortChannelIdsEnd, GossipTimestampFilter};
 use ln::msgs;
-use util::ser::{Writeable, Readable, Writer};
+use util::ser::{Writeable, Readable, ReadableArgs, Writer};
 use util::logger::Logger;
+use util::events::{MessageSendEvent, MessageSendEventsProvider};
 
 use std::{cmp, fmt};
-use std::sync::{RwLock, RwLockReadGuard};
+use std::sync::{RwLock, RwLockReadGuard, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::collections::btree_map::Entry as BtreeEntry;
-use std::ops::Deref;
+use std::ops::{Bound, Deref};
 use bitcoin::hashes::hex::ToHex;
 
 /// Represents the network as nodes and channels between them
 #[derive(PartialEq)]
 pub struct NetworkGraph {
+	genesis_hash: BlockHash,
 	channels: BTreeMap<u64, ChannelInfo>,
 	nodes: BTreeMap<PublicKey, NodeInfo>,
 }
@@ -57,23 +61,26 @@ pub struct NetGraphMsgHandler<C: Deref, L: Deref> where C::Target: ChainWatchInt
 	pub network_graph: RwLock<NetworkGraph>,
 	chain_monitor: C,
 	full_syncs_requested: AtomicUsize,
+	query_syncs_requested: AtomicUsize,
+	peer_syncs: Mutex<HashMap<PublicKey, PeerSyncState>>,
+	pending_events: Mutex<Vec<MessageSendEvent>>,
 	logger: L,
 }
 
 impl<C: Deref, L: Deref> NetGraphMsgHandler<C, L> where C::Target: ChainWatchInterface, L::Target: Logger {
 	/// Creates a new tracker of the actual state of the network of channels and nodes,
-	/// assuming a fresh network graph.
+	/// assuming a fresh network graph on the chain with the given genesis block hash.
 	/// Chain monitor is used to make sure announced channels exist on-chain,
 	/// channel data is correct, and that the announcement is signed with
 	/// channel owners' keys.
-	pub fn new(chain_monitor: C, logger: L) -> Self {
+	pub fn new(genesis_hash: BlockHash, chain_monitor: C, logger: L) -> Self {
 		NetGraphMsgHandler {
 			secp_ctx: Secp256k1::verification_only(),
-			network_graph: RwLock::new(NetworkGraph {
-				channels: BTreeMap::new(),
-				nodes: BTreeMap::new(),
-			}),
+			network_graph: RwLock::new(NetworkGraph::new(genesis_hash)),
 			full_syncs_requested: AtomicUsize::new(0),
+			query_syncs_requested: AtomicUsize::new(0),
+			peer_syncs: Mutex::new(HashMap::new()),
+			pending_events: Mutex::new(vec![]),
 			chain_monitor,
 			logger,
 		}
@@ -86,6 +93,9 @@ impl<C: Deref, L: Deref> NetGraphMsgHandler<C, L> where C::Target: ChainWatchInt
 			secp_ctx: Secp256k1::verification_only(),
 			network_graph: RwLock::new(network_graph),
 			full_syncs_requested: AtomicUsize::new(0),
+			query_syncs_requested: AtomicUsize::new(0),
+			peer_syncs: Mutex::new(HashMap::new()),
+			pending_events: Mutex::new(vec![]),
 			chain_monitor,
 			logger,
 		}
@@ -98,6 +108,62 @@ impl<C: Deref, L: Deref> NetGraphMsgHandler<C, L> where C::Target: ChainWatchInt
 	pub fn read_locked_graph<'a>(&'a self) -> LockedNetworkGraph<'a> {
 		LockedNetworkGraph(self.network_graph.read().unwrap())
 	}
+
+	/// Sends the next query_short_channel_ids for a sync once the peer has finished replying to our
+	/// query_channel_range and to any previous query_short_channel_ids, or, once there is nothing
+	/// left to query, asks the peer for new gossip via gossip_timestamp_filter.
+	/// Returns true if the sync is complete.
+	///
+	/// Locks are always taken in the order peer_syncs, network_graph, pending_events, so callers
+	/// may hold peer_syncs but must not hold either of the others.
+	fn progress_sync(&self, their_node_id: &PublicKey, sync: &mut PeerSyncState) -> bool {
+		if sync.awaiting_scids_reply || sync.next_blocknum < sync.end_blocknum {
+			return false;
+		}
+		let chain_hash = self.network_graph.read().unwrap().genesis_hash;
+		let mut pending_events = self.pending_events.lock().unwrap();
+		if !sync.scids_to_query.is_empty() {
+			let short_channel_ids: Vec<u64> = sync.scids_to_query.iter().take(MAX_SCIDS_PER_QUERY).cloned().collect();
+			for scid in short_channel_ids.iter() {
+				sync.scids_to_query.remove(scid);
+			}
+			log_trace!(self.logger, "Querying {} short_channel_ids from {}", short_channel_ids.len(), log_pubkey!(their_node_id));
+			pending_events.push(MessageSendEvent::SendShortIdsQuery {
+				node_id: their_node_id.clone(),
+				msg: QueryShortChannelIds {
+					chain_hash,
+					short_channel_ids,
+				},
+			});
+			sync.awaiting_scids_reply = true;
+			return false;
+		}
+		log_trace!(self.logger, "Completed routing table sync with {}", log_pubkey!(their_node_id));
+		pending_events.push(MessageSendEvent::SendGossipTimestampFilter {
+			node_id: their_node_id.clone(),
+			msg: GossipTimestampFilter {
+				chain_hash,
+				first_timestamp: sync.first_timestamp,
+				timestamp_range: u32::max_value(),
+			},
+		});
+		true
+	}
+}
+
+/// Tracks the progress of a gossip_queries-based routing table sync with a single peer.
+struct PeerSyncState {
+	/// The block after the last one covered by the reply_channel_range messages received so far.
+	next_blocknum: u32,
+	/// The block after the last one covered by our query_channel_range.
+	end_blocknum: u32,
+	/// Channels the peer told us about which we don't know, and have yet to query.
+	scids_to_query: BTreeSet<u64>,
+	/// Whether we are waiting on a reply_short_channel_ids_end. BOLT 7 only allows one
+	/// query_short_channel_ids to be outstanding at a time.
+	awaiting_scids_reply: bool,
+	/// The first_timestamp to use in the gossip_timestamp_filter sent once the sync completes.
+	first_timestamp: u32,
 }
 
 impl<'a> LockedNetworkGraph<'a> {
@@ -107,6 +173,33 @@ impl<'a> LockedNetworkGraph<'a> {
 	}
 }
 
+/// The maximum number of short_channel_ids we include in a single reply_channel_range message.
+/// Each encoded short_channel_id takes 8 bytes, so this keeps the message comfortably below the
+/// 65535-byte message size limit.
+const MAX_SCIDS_PER_REPLY: usize = 8000;
+
+/// The maximum number of short_channel_ids we include in a single query_short_channel_ids message.
+const MAX_SCIDS_PER_QUERY: usize = 8000;
+
+/// The maximum number of channels we are willing to queue for querying from a single peer, to
+/// bound the memory a peer can make us use by replying with bogus short_channel_ids.
+const MAX_SCIDS_TO_QUERY: usize = 500_000;
+
+/// The number of peers we sync our routing table with using gossip queries.
+const QUERY_SYNCS_TO_REQUEST: usize = 5;
+
+/// The highest block height which can be encoded in the top three bytes of a short_channel_id.
+const MAX_SCID_BLOCK: u32 = 0x00ff_ffff;
+
+/// Gets the block height encoded in the given short_channel_id.
+fn block_from_scid(short_channel_id: u64) -> u32 {
+	(short_channel_id >> 40) as u32
+}
+
+/// Gets the lowest short_channel_id which could appear in the given block.
+fn scid_from_block(block: u32) -> u64 {
+	(block as u64) << 40
+}
 
 macro_rules! secp_verify_sig {
 	( $secp_ctx: expr, $msg: expr, $sig: expr, $pubkey: expr ) => {
@@ -237,6 +330,263 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 			false
 		}
 	}
+
+	fn handle_query_channel_range(&self, their_node_id: &PublicKey, msg: &QueryChannelRange) -> Result<(), LightningError> {
+		let network_graph = self.network_graph.read().unwrap();
+
+		// We only track channels on a single chain, so only claim to have full information for it.
+		let full_information = msg.chain_hash == network_graph.genesis_hash;
+
+		// Per BOLT 7 we must always reply to a query, so send an empty reply even when it is
+		// invalid.
+		if msg.number_of_blocks == 0 {
+			self.pending_events.lock().unwrap().push(MessageSendEvent::SendReplyChannelRange {
+				node_id: their_node_id.clone(),
+				msg: ReplyChannelRange {
+					chain_hash: msg.chain_hash,
+					first_blocknum: msg.first_blocknum,
+					number_of_blocks: msg.number_of_blocks,
+					full_information,
+					short_channel_ids: Vec::new(),
+				},
+			});
+			return Err(LightningError{err: "Received a query_channel_range we could not process".to_owned(), action: ErrorAction::IgnoreError});
+		}
+
+		// Collect the announced channels in the queried range, splitting them into batches which
+		// each fit in a single reply. Channels for which we've only seen a channel_update but no
+		// channel_announcement are not included as we cannot serve them.
+		let mut batches: Vec<Vec<u64>> = vec![Vec::new()];
+		if msg.first_blocknum <= MAX_SCID_BLOCK {
+			let start_scid = Bound::Included(scid_from_block(msg.first_blocknum));
+			let end_scid = if msg.end_blocknum() > MAX_SCID_BLOCK { Bound::Unbounded } else { Bound::Excluded(scid_from_block(msg.end_blocknum())) };
+			for (scid, chan) in network_graph.get_channels().range((start_scid, end_scid)) {
+				if let Some(ref announcement) = chan.announcement_message {
+					if announcement.contents.chain_hash != msg.chain_hash { continue; }
+					if batches.last().unwrap().len() >= MAX_SCIDS_PER_REPLY {
+						batches.push(Vec::new());
+					}
+					batches.last_mut().unwrap().push(*scid);
+				}
+			}
+		}
+		drop(network_graph);
+
+		// Replies must be in ascending order and, together, must cover the whole queried range.
+		// Each non-final reply ends at the block of its last channel, and the next reply starts
+		// right after it, unless a block's channels were split across two replies, in which case
+		// both replies include that block.
+		let mut pending_events = self.pending_events.lock().unwrap();
+		let batch_count = batches.len();
+		let mut first_blocknum = msg.first_blocknum;
+		for (batch_idx, batch) in batches.into_iter().enumerate() {
+			if let Some(first_scid) = batch.first() {
+				first_blocknum = cmp::min(first_blocknum, block_from_scid(*first_scid));
+			}
+			let number_of_blocks = if batch_idx == batch_count - 1 {
+				msg.end_blocknum() - first_blocknum
+			} else {
+				block_from_scid(*batch.last().unwrap()) + 1 - first_blocknum
+			};
+			let next_first_blocknum = first_blocknum + number_of_blocks;
+			pending_events.push(MessageSendEvent::SendReplyChannelRange {
+				node_id: their_node_id.clone(),
+				msg: ReplyChannelRange {
+					chain_hash: msg.chain_hash,
+					first_blocknum,
+					number_of_blocks,
+					full_information,
+					short_channel_ids: batch,
+				},
+			});
+			first_blocknum = next_first_blocknum;
+		}
+		Ok(())
+	}
+
+	fn handle_query_short_channel_ids(&self, their_node_id: &PublicKey, msg: &QueryShortChannelIds) -> Result<(), LightningError> {
+		let network_graph = self.network_graph.read().unwrap();
+		let mut pending_events = self.pending_events.lock().unwrap();
+
+		// Per BOLT 7, each node_announcement is sent at most once in reply to a single query.
+		let mut nodes_sent = HashSet::new();
+		let mut full_information = true;
+		for scid in msg.short_channel_ids.iter() {
+			let chan = match network_graph.get_channels().get(scid) {
+				Some(chan) => chan,
+				None => continue,
+			};
+			let announcement = match chan.announcement_message {
+				Some(ref announcement) => announcement,
+				None => continue,
+			};
+			if announcement.contents.chain_hash != msg.chain_hash {
+				full_information = false;
+				continue;
+			}
+
+			pending_events.push(MessageSendEvent::SendChannelAnnouncement {
+				node_id: their_node_id.clone(),
+				msg: announcement.clone(),
+			});
+			for directional_info in [&chan.one_to_two, &chan.two_to_one].iter() {
+				if let Some(ref update) = directional_info.as_ref().and_then(|info| info.last_update_message.as_ref()) {
+					pending_events.push(MessageSendEvent::SendChannelUpdate {
+						node_id: their_node_id.clone(),
+						msg: (*update).clone(),
+					});
+				}
+			}
+			for node_id in [&chan.node_one, &chan.node_two].iter() {
+				if !nodes_sent.insert(**node_id) { continue; }
+				let node_announcement = network_graph.get_nodes().get(*node_id)
+					.and_then(|node| node.announcement_info.as_ref())
+					.and_then(|info| info.announcement_message.as_ref());
+				if let Some(node_announcement) = node_announcement {
+					pending_events.push(MessageSendEvent::SendNodeAnnouncement {
+						node_id: their_node_id.clone(),
+						msg: node_announcement.clone(),
+					});
+				}
+			}
+		}
+
+		pending_events.push(MessageSendEvent::SendReplyShortChannelIdsEnd {
+			node_id: their_node_id.clone(),
+			msg: ReplyShortChannelIdsEnd {
+				chain_hash: msg.chain_hash,
+				full_information,
+			},
+		});
+		Ok(())
+	}
+
+	fn sync_routing_table(&self, their_node_id: &PublicKey, init: &msgs::Init) {
+		if !init.features.supports_gossip_queries() {
+			return;
+		}
+		let mut peer_syncs = self.peer_syncs.lock().unwrap();
+		let network_graph = self.network_graph.read().unwrap();
+
+		// Ask for any gossip newer than the newest channel_update we know of. This way, after a
+		// restart, we also learn about updates to the channels we already know which we missed
+		// while offline.
+		let first_timestamp = network_graph.channels.values()
+			.flat_map(|chan| chan.one_to_two.iter().chain(chan.two_to_one.iter()))
+			.map(|info| info.last_update)
+			.max().unwrap_or(0);
+
+		if self.query_syncs_requested.load(Ordering::Acquire) >= QUERY_SYNCS_TO_REQUEST {
+			// Peers which support gossip_queries won't send us any gossip until we ask for it.
+			self.pending_events.lock().unwrap().push(MessageSendEvent::SendGossipTimestampFilter {
+				node_id: their_node_id.clone(),
+				msg: GossipTimestampFilter {
+					chain_hash: network_graph.genesis_hash,
+					first_timestamp,
+					timestamp_range: u32::max_value(),
+				},
+			});
+			return;
+		}
+		self.query_syncs_requested.fetch_add(1, Ordering::AcqRel);
+
+		log_trace!(self.logger, "Starting routing table sync with {}", log_pubkey!(their_node_id));
+		let query = QueryChannelRange {
+			chain_hash: network_graph.genesis_hash,
+			first_blocknum: 0,
+			number_of_blocks: u32::max_value(),
+		};
+		peer_syncs.insert(their_node_id.clone(), PeerSyncState {
+			next_blocknum: query.first_blocknum,
+			end_blocknum: query.end_blocknum(),
+			scids_to_query: BTreeSet::new(),
+			awaiting_scids_reply: false,
+			first_timestamp,
+		});
+		self.pending_events.lock().unwrap().push(MessageSendEvent::SendChannelRangeQuery {
+			node_id: their_node_id.clone(),
+			msg: query,
+		});
+	}
+
+	fn peer_disconnected(&self, their_node_id: &PublicKey) {
+		// Free up the slot of any sync which didn't complete so that we sync with another peer
+		// instead.
+		if self.peer_syncs.lock().unwrap().remove(their_node_id).is_some() {
+			self.query_syncs_requested.fetch_sub(1, Ordering::AcqRel);
+		}
+	}
+
+	fn handle_reply_channel_range(&self, their_node_id: &PublicKey, msg: &ReplyChannelRange) -> Result<(), LightningError> {
+		let mut peer_syncs = self.peer_syncs.lock().unwrap();
+		let sync_complete = {
+			let sync = match peer_syncs.get_mut(their_node_id) {
+				Some(sync) if sync.next_blocknum < sync.end_blocknum => sync,
+				_ => return Err(LightningError{err: "Received an unsolicited reply_channel_range".to_owned(), action: ErrorAction::IgnoreError}),
+			};
+			let network_graph = self.network_graph.read().unwrap();
+			if msg.chain_hash != network_graph.genesis_hash {
+				return Err(LightningError{err: "Received a reply_channel_range for the wrong chain".to_owned(), action: ErrorAction::IgnoreError});
+			}
+			if !msg.full_information {
+				log_debug!(self.logger, "Peer {} does not have full information for blocks {} through {}", log_pubkey!(their_node_id), msg.first_blocknum, msg.first_blocknum.saturating_add(msg.number_of_blocks));
+			}
+
+			let reply_end_blocknum = msg.first_blocknum.saturating_add(msg.number_of_blocks);
+			for scid in msg.short_channel_ids.iter() {
+				let block = block_from_scid(*scid);
+				if block < msg.first_blocknum || block >= reply_end_blocknum {
+					continue;
+				}
+				if !network_graph.channels.contains_key(scid) {
+					sync.scids_to_query.insert(*scid);
+				}
+			}
+			if sync.scids_to_query.len() > MAX_SCIDS_TO_QUERY {
+				peer_syncs.remove(their_node_id);
+				self.query_syncs_requested.fetch_sub(1, Ordering::AcqRel);
+				return Err(LightningError{err: "Peer replied to our query_channel_range with too many short_channel_ids".to_owned(), action: ErrorAction::IgnoreError});
+			}
+			sync.next_blocknum = cmp::max(sync.next_blocknum, reply_end_blocknum);
+			drop(network_graph);
+			self.progress_sync(their_node_id, sync)
+		};
+		if sync_complete {
+			peer_syncs.remove(their_node_id);
+		}
+		Ok(())
+	}
+
+	fn handle_reply_short_channel_ids_end(&self, their_node_id: &PublicKey, msg: &ReplyShortChannelIdsEnd) -> Result<(), LightningError> {
+		let mut peer_syncs = self.peer_syncs.lock().unwrap();
+		let sync_complete = {
+			let sync = match peer_syncs.get_mut(their_node_id) {
+				Some(sync) if sync.awaiting_scids_reply => sync,
+				_ => return Err(LightningError{err: "Received an unsolicited reply_short_channel_ids_end".to_owned(), action: ErrorAction::IgnoreError}),
+			};
+			if msg.chain_hash != self.network_graph.read().unwrap().genesis_hash {
+				return Err(LightningError{err: "Received a reply_short_channel_ids_end for the wrong chain".to_owned(), action: ErrorAction::IgnoreError});
+			}
+			if !msg.full_information {
+				log_debug!(self.logger, "Peer {} does not have full information for the channels we queried", log_pubkey!(their_node_id));
+			}
+			sync.awaiting_scids_reply = false;
+			self.progress_sync(their_node_id, sync)
+		};
+		if sync_complete {
+			peer_syncs.remove(their_node_id);
+		}
+		Ok(())
+	}
+}
+
+impl<C: Deref, L: Deref> MessageSendEventsProvider for NetGraphMsgHandler<C, L> where C::Target: ChainWatchInterface, L::Target: Logger {
+	fn get_and_clear_pending_msg_events(&self) -> Vec<MessageSendEvent> {
+		let mut ret = Vec::new();
+		let mut pending_events = self.pending_events.lock().unwrap();
+		::std::mem::swap(&mut ret, &mut pending_events);
+		ret
+	}
 }
 
 #[derive(PartialEq, Debug)]
@@ -487,8 +837,10 @@ impl Writeable for NetworkGraph {
 	}
 }
 
-impl Readable for NetworkGraph {
-	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<NetworkGraph, DecodeError> {
+/// The genesis block hash is not serialized with the graph, and must be provided when reading it
+/// back.
+impl ReadableArgs<BlockHash> for NetworkGraph {
+	fn read<R: ::std::io::Read>(reader: &mut R, genesis_hash: BlockHash) -> Result<NetworkGraph, DecodeError> {
 		let channels_count: u64 = Readable::read(reader)?;
 		let mut channels = BTreeMap::new();
 		for _ in 0..channels_count {
@@ -504,6 +856,7 @@ impl Readable for NetworkGraph {
 			nodes.insert(node_id, node_info);
 		}
 		Ok(NetworkGraph {
+			genesis_hash,
 			channels,
 			nodes,
 		})
@@ -548,9 +901,10 @@ impl NetworkGraph {
 		None
 	}
 
-	/// Creates a new, empty, network graph.
-	pub fn new() -> NetworkGraph {
+	/// Creates a new, empty, network graph for the chain with the given genesis block hash.
+	pub fn new(genesis_hash: BlockHash) -> NetworkGraph {
 		Self {
+			genesis_hash,
 			channels: BTreeMap::new(),
 			nodes: BTreeMap::new(),
 		}
@@ -829,14 +1183,15 @@ impl NetworkGraph {
 #[cfg(test)]
 mod tests {
 	use chain::chaininterface;
-	use ln::features::{ChannelFeatures, NodeFeatures};
+	use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
 	use routing::network_graph::{NetGraphMsgHandler, NetworkGraph};
 	use ln::msgs::{OptionalField, RoutingMessageHandler, UnsignedNodeAnnouncement, NodeAnnouncement,
 		UnsignedChannelAnnouncement, ChannelAnnouncement, UnsignedChannelUpdate, ChannelUpdate, HTLCFailChannelUpdate,
-		MAX_VALUE_MSAT};
+		QueryChannelRange, QueryShortChannelIds, ReplyChannelRange, ReplyShortChannelIdsEnd, Init, MAX_VALUE_MSAT};
+	use util::events::{MessageSendEvent, MessageSendEventsProvider};
 	use util::test_utils;
 	use util::logger::Logger;
-	use util::ser::{Readable, Writeable};
+	use util::ser::{Readable, ReadableArgs, Writeable};
 
 	use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 	use bitcoin::hashes::Hash;
@@ -856,7 +1211,7 @@ mod tests {
 		let secp_ctx = Secp256k1::new();
 		let logger = Arc::new(test_utils::TestLogger::new());
 		let chain_monitor = Arc::new(chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet));
-		let net_graph_msg_handler = NetGraphMsgHandler::new(chain_monitor, Arc::clone(&logger));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.block_hash(), chain_monitor, Arc::clone(&logger));
 		(secp_ctx, net_graph_msg_handler)
 	}
 
@@ -982,7 +1337,7 @@ mod tests {
 		let secp_ctx = Secp256k1::new();
 		let logger: Arc<Logger> = Arc::new(test_utils::TestLogger::new());
 		let chain_monitor = Arc::new(test_utils::TestChainWatcher::new());
-		let net_graph_msg_handler = NetGraphMsgHandler::new(chain_monitor.clone(), Arc::clone(&logger));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.block_hash(), chain_monitor.clone(), Arc::clone(&logger));
 
 
 		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
@@ -1170,7 +1525,7 @@ mod tests {
 		let secp_ctx = Secp256k1::new();
 		let logger: Arc<Logger> = Arc::new(test_utils::TestLogger::new());
 		let chain_monitor = Arc::new(test_utils::TestChainWatcher::new());
-		let net_graph_msg_handler = NetGraphMsgHandler::new(chain_monitor.clone(), Arc::clone(&logger));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.block_hash(), chain_monitor.clone(), Arc::clone(&logger));
 
 		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
 		let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
@@ -1763,6 +2118,355 @@ mod tests {
 		assert!(!network.get_nodes().is_empty());
 		assert!(!network.get_channels().is_empty());
 		network.write(&mut w).unwrap();
-		assert!(<NetworkGraph>::read(&mut ::std::io::Cursor::new(&w.0)).unwrap() == *network);
+		assert!(<NetworkGraph>::read(&mut ::std::io::Cursor::new(&w.0), genesis_block(Network::Testnet).header.block_hash()).unwrap() == *network);
+	}
+
+	fn get_signed_channel_announcement(short_channel_id: u64, secp_ctx: &Secp256k1<All>) -> ChannelAnnouncement {
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let node_1_btckey = &SecretKey::from_slice(&[40; 32]).unwrap();
+		let node_2_btckey = &SecretKey::from_slice(&[39; 32]).unwrap();
+		let unsigned_announcement = UnsignedChannelAnnouncement {
+			features: ChannelFeatures::known(),
+			chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+			short_channel_id,
+			node_id_1: PublicKey::from_secret_key(&secp_ctx, node_1_privkey),
+			node_id_2: PublicKey::from_secret_key(&secp_ctx, node_2_privkey),
+			bitcoin_key_1: PublicKey::from_secret_key(&secp_ctx, node_1_btckey),
+			bitcoin_key_2: PublicKey::from_secret_key(&secp_ctx, node_2_btckey),
+			excess_data: Vec::new(),
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_announcement.encode()[..])[..]);
+		ChannelAnnouncement {
+			node_signature_1: secp_ctx.sign(&msghash, node_1_privkey),
+			node_signature_2: secp_ctx.sign(&msghash, node_2_privkey),
+			bitcoin_signature_1: secp_ctx.sign(&msghash, node_1_btckey),
+			bitcoin_signature_2: secp_ctx.sign(&msghash, node_2_btckey),
+			contents: unsigned_announcement,
+		}
+	}
+
+	fn scid(block: u32, tx_index: u32, vout: u16) -> u64 {
+		((block as u64) << 40) | ((tx_index as u64) << 16) | vout as u64
+	}
+
+	fn expect_reply_channel_range(events: &[MessageSendEvent], node_id: &PublicKey, expected: &[(u32, u32, bool, Vec<u64>)]) {
+		assert_eq!(events.len(), expected.len());
+		for (event, &(first_blocknum, number_of_blocks, full_information, ref short_channel_ids)) in events.iter().zip(expected.iter()) {
+			match event {
+				&MessageSendEvent::SendReplyChannelRange { node_id: ref event_node_id, ref msg } => {
+					assert_eq!(event_node_id, node_id);
+					assert_eq!(msg.chain_hash, genesis_block(Network::Testnet).header.block_hash());
+					assert_eq!(msg.first_blocknum, first_blocknum);
+					assert_eq!(msg.number_of_blocks, number_of_blocks);
+					assert_eq!(msg.full_information, full_information);
+					assert_eq!(&msg.short_channel_ids, short_channel_ids);
+				},
+				_ => panic!("Expected SendReplyChannelRange"),
+			}
+		}
+	}
+
+	#[test]
+	fn handling_query_channel_range() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+
+		let scids = vec![scid(100, 0, 0), scid(100, 1, 0), scid(108, 0, 0), scid(110, 5, 1), scid(0xffffff, 0, 0)];
+		for short_channel_id in scids.iter() {
+			let announcement = get_signed_channel_announcement(*short_channel_id, &secp_ctx);
+			assert!(net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+		}
+
+		// A range which covers some of the channels gets a single, complete reply.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange {
+			chain_hash, first_blocknum: 100, number_of_blocks: 10,
+		}).unwrap();
+		expect_reply_channel_range(&net_graph_msg_handler.get_and_clear_pending_msg_events(), &node_id,
+			&[(100, 10, true, vec![scids[0], scids[1], scids[2]])]);
+
+		// A range with no channels still gets a reply.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange {
+			chain_hash, first_blocknum: 0, number_of_blocks: 100,
+		}).unwrap();
+		expect_reply_channel_range(&net_graph_msg_handler.get_and_clear_pending_msg_events(), &node_id,
+			&[(0, 100, true, vec![])]);
+
+		// A range which overflows the block height includes the highest possible channel.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange {
+			chain_hash, first_blocknum: 105, number_of_blocks: 0xffffffff,
+		}).unwrap();
+		expect_reply_channel_range(&net_graph_msg_handler.get_and_clear_pending_msg_events(), &node_id,
+			&[(105, 0xffffffff - 105, true, vec![scids[2], scids[3], scids[4]])]);
+
+		// A range which starts past the highest possible channel is empty.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange {
+			chain_hash, first_blocknum: 0x1000000, number_of_blocks: 10,
+		}).unwrap();
+		expect_reply_channel_range(&net_graph_msg_handler.get_and_clear_pending_msg_events(), &node_id,
+			&[(0x1000000, 10, true, vec![])]);
+
+		// An empty range is invalid, but must still be replied to.
+		assert!(net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange {
+			chain_hash, first_blocknum: 100, number_of_blocks: 0,
+		}).is_err());
+		expect_reply_channel_range(&net_graph_msg_handler.get_and_clear_pending_msg_events(), &node_id,
+			&[(100, 0, true, vec![])]);
+	}
+
+	#[test]
+	fn handling_query_channel_range_batches() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+
+		// Fill the graph directly to avoid checking signatures on many thousands of channels. We
+		// put 7999 channels in block 100 and 2 in block 101, so that block 101 is split across the
+		// first two replies, and then add 8000 channels in block 103 to get a third reply.
+		let mut scids = Vec::new();
+		for tx_index in 0..7999 { scids.push(scid(100, tx_index, 0)); }
+		for tx_index in 0..2 { scids.push(scid(101, tx_index, 0)); }
+		for tx_index in 0..8000 { scids.push(scid(103, tx_index, 0)); }
+		{
+			let announcement = get_signed_channel_announcement(0, &secp_ctx);
+			let mut network_graph = net_graph_msg_handler.network_graph.write().unwrap();
+			for short_channel_id in scids.iter() {
+				let mut announcement = announcement.clone();
+				announcement.contents.short_channel_id = *short_channel_id;
+				assert!(network_graph.update_channel_from_announcement(&announcement, None, None).unwrap());
+			}
+		}
+
+		net_graph_msg_handler.handle_query_channel_range(&node_id, &QueryChannelRange {
+			chain_hash, first_blocknum: 50, number_of_blocks: 100,
+		}).unwrap();
+		expect_reply_channel_range(&net_graph_msg_handler.get_and_clear_pending_msg_events(), &node_id, &[
+			(50, 52, true, scids[0..8000].to_vec()),
+			(101, 3, true, scids[8000..16000].to_vec()),
+			(103, 47, true, scids[16000..].to_vec()),
+		]);
+	}
+
+	#[test]
+	fn handling_query_short_channel_ids() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+
+		let announcement_1 = get_signed_channel_announcement(scid(100, 0, 0), &secp_ctx);
+		let announcement_2 = get_signed_channel_announcement(scid(101, 0, 0), &secp_ctx);
+		assert!(net_graph_msg_handler.handle_channel_announcement(&announcement_1).unwrap());
+		assert!(net_graph_msg_handler.handle_channel_announcement(&announcement_2).unwrap());
+
+		let unsigned_update = UnsignedChannelUpdate {
+			chain_hash,
+			short_channel_id: scid(100, 0, 0),
+			timestamp: 100,
+			flags: 0,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000000,
+			htlc_maximum_msat: OptionalField::Absent,
+			fee_base_msat: 10000,
+			fee_proportional_millionths: 20,
+			excess_data: Vec::new()
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_update.encode()[..])[..]);
+		let update = ChannelUpdate {
+			signature: secp_ctx.sign(&msghash, node_1_privkey),
+			contents: unsigned_update,
+		};
+		assert!(net_graph_msg_handler.handle_channel_update(&update).unwrap());
+
+		let unsigned_node_announcement = UnsignedNodeAnnouncement {
+			features: NodeFeatures::known(),
+			timestamp: 100,
+			node_id: PublicKey::from_secret_key(&secp_ctx, node_1_privkey),
+			rgb: [0; 3],
+			alias: [0; 32],
+			addresses: Vec::new(),
+			excess_address_data: Vec::new(),
+			excess_data: Vec::new(),
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_node_announcement.encode()[..])[..]);
+		let node_announcement = NodeAnnouncement {
+			signature: secp_ctx.sign(&msghash, node_1_privkey),
+			contents: unsigned_node_announcement,
+		};
+		assert!(net_graph_msg_handler.handle_node_announcement(&node_announcement).unwrap());
+
+		// Query both channels and an unknown one. The node_announcement is sent only once, and only
+		// the known channels are replied to.
+		net_graph_msg_handler.handle_query_short_channel_ids(&node_id, &QueryShortChannelIds {
+			chain_hash,
+			short_channel_ids: vec![scid(100, 0, 0), scid(102, 0, 0), scid(101, 0, 0)],
+		}).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 5);
+		match &events[0] {
+			&MessageSendEvent::SendChannelAnnouncement { node_id: ref event_node_id, ref msg } => {
+				assert_eq!(event_node_id, &node_id);
+				assert_eq!(msg.contents.short_channel_id, scid(100, 0, 0));
+			},
+			_ => panic!("Expected SendChannelAnnouncement"),
+		}
+		match &events[1] {
+			&MessageSendEvent::SendChannelUpdate { ref msg, .. } => assert_eq!(msg.contents.short_channel_id, scid(100, 0, 0)),
+			_ => panic!("Expected SendChannelUpdate"),
+		}
+		match &events[2] {
+			&MessageSendEvent::SendNodeAnnouncement { ref msg, .. } => assert_eq!(msg.contents.node_id, node_announcement.contents.node_id),
+			_ => panic!("Expected SendNodeAnnouncement"),
+		}
+		match &events[3] {
+			&MessageSendEvent::SendChannelAnnouncement { ref msg, .. } => assert_eq!(msg.contents.short_channel_id, scid(101, 0, 0)),
+			_ => panic!("Expected SendChannelAnnouncement"),
+		}
+		match &events[4] {
+			&MessageSendEvent::SendReplyShortChannelIdsEnd { node_id: ref event_node_id, ref msg } => {
+				assert_eq!(event_node_id, &node_id);
+				assert_eq!(msg.chain_hash, chain_hash);
+				assert!(msg.full_information);
+			},
+			_ => panic!("Expected SendReplyShortChannelIdsEnd"),
+		}
+	}
+
+	#[test]
+	fn syncing_routing_table_with_gossip_queries() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+
+		let announcement = get_signed_channel_announcement(scid(100, 0, 0), &secp_ctx);
+		assert!(net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+		let unsigned_update = UnsignedChannelUpdate {
+			chain_hash,
+			short_channel_id: scid(100, 0, 0),
+			timestamp: 1234,
+			flags: 0,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000000,
+			htlc_maximum_msat: OptionalField::Absent,
+			fee_base_msat: 10000,
+			fee_proportional_millionths: 20,
+			excess_data: Vec::new()
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_update.encode()[..])[..]);
+		assert!(net_graph_msg_handler.handle_channel_update(&ChannelUpdate {
+			signature: secp_ctx.sign(&msghash, node_1_privkey),
+			contents: unsigned_update,
+		}).unwrap());
+
+		// Peers which don't support gossip_queries aren't synced with.
+		net_graph_msg_handler.sync_routing_table(&node_id, &Init { features: InitFeatures::empty() });
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+
+		net_graph_msg_handler.sync_routing_table(&node_id, &Init { features: InitFeatures::known() });
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match &events[0] {
+			&MessageSendEvent::SendChannelRangeQuery { node_id: ref event_node_id, ref msg } => {
+				assert_eq!(event_node_id, &node_id);
+				assert_eq!(msg.chain_hash, chain_hash);
+				assert_eq!(msg.first_blocknum, 0);
+				assert_eq!(msg.number_of_blocks, 0xffffffff);
+			},
+			_ => panic!("Expected SendChannelRangeQuery"),
+		}
+
+		// Nothing is queried until the replies cover the whole range, and only the channels we
+		// don't know about which are within each reply's range are queried.
+		net_graph_msg_handler.handle_reply_channel_range(&node_id, &ReplyChannelRange {
+			chain_hash, first_blocknum: 0, number_of_blocks: 101, full_information: true,
+			short_channel_ids: vec![scid(99, 0, 0), scid(100, 0, 0), scid(100, 1, 0), scid(200, 0, 0)],
+		}).unwrap();
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+		net_graph_msg_handler.handle_reply_channel_range(&node_id, &ReplyChannelRange {
+			chain_hash, first_blocknum: 101, number_of_blocks: 0xffffffff - 101, full_information: true,
+			short_channel_ids: vec![scid(200, 0, 0)],
+		}).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match &events[0] {
+			&MessageSendEvent::SendShortIdsQuery { node_id: ref event_node_id, ref msg } => {
+				assert_eq!(event_node_id, &node_id);
+				assert_eq!(msg.chain_hash, chain_hash);
+				assert_eq!(msg.short_channel_ids, vec![scid(99, 0, 0), scid(100, 1, 0), scid(200, 0, 0)]);
+			},
+			_ => panic!("Expected SendShortIdsQuery"),
+		}
+		assert!(net_graph_msg_handler.handle_reply_channel_range(&node_id, &ReplyChannelRange {
+			chain_hash, first_blocknum: 0, number_of_blocks: 10, full_information: true, short_channel_ids: vec![],
+		}).is_err());
+
+		// Once the peer is done replying, we ask it for gossip newer than our latest update.
+		net_graph_msg_handler.handle_reply_short_channel_ids_end(&node_id, &ReplyShortChannelIdsEnd {
+			chain_hash, full_information: true,
+		}).unwrap();
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match &events[0] {
+			&MessageSendEvent::SendGossipTimestampFilter { node_id: ref event_node_id, ref msg } => {
+				assert_eq!(event_node_id, &node_id);
+				assert_eq!(msg.chain_hash, chain_hash);
+				assert_eq!(msg.first_timestamp, 1234);
+				assert_eq!(msg.timestamp_range, 0xffffffff);
+			},
+			_ => panic!("Expected SendGossipTimestampFilter"),
+		}
+		assert!(net_graph_msg_handler.handle_reply_short_channel_ids_end(&node_id, &ReplyShortChannelIdsEnd {
+			chain_hash, full_information: true,
+		}).is_err());
+	}
+
+	#[test]
+	fn syncing_routing_table_finite_times() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let init = Init { features: InitFeatures::known() };
+
+		for i in 0..6 {
+			let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[i + 1; 32]).unwrap());
+			net_graph_msg_handler.sync_routing_table(&node_id, &init);
+			let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+			assert_eq!(events.len(), 1);
+			match &events[0] {
+				&MessageSendEvent::SendChannelRangeQuery { .. } if i < 5 => {},
+				// Peers we don't sync with must still be asked to send us gossip.
+				&MessageSendEvent::SendGossipTimestampFilter { ref msg, .. } if i == 5 => assert_eq!(msg.first_timestamp, 0),
+				_ => panic!("Unexpected event"),
+			}
+		}
+
+		// A reply with no SCIDs for us to query completes the sync.
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		net_graph_msg_handler.handle_reply_channel_range(&node_id, &ReplyChannelRange {
+			chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+			first_blocknum: 0, number_of_blocks: 0xffffffff, full_information: true, short_channel_ids: vec![],
+		}).unwrap();
+		match &net_graph_msg_handler.get_and_clear_pending_msg_events()[..] {
+			&[MessageSendEvent::SendGossipTimestampFilter { .. }] => {},
+			_ => panic!("Expected SendGossipTimestampFilter"),
+		}
+
+		// Completed syncs still count towards the limit...
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[7; 32]).unwrap());
+		net_graph_msg_handler.peer_disconnected(&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap()));
+		net_graph_msg_handler.sync_routing_table(&node_id, &init);
+		match &net_graph_msg_handler.get_and_clear_pending_msg_events()[..] {
+			&[MessageSendEvent::SendGossipTimestampFilter { .. }] => {},
+			_ => panic!("Expected SendGossipTimestampFilter"),
+		}
+
+		// ...but a peer disconnecting mid-sync frees up its slot for another peer.
+		net_graph_msg_handler.peer_disconnected(&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap()));
+		assert!(net_graph_msg_handler.peer_syncs.lock().unwrap().get(&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap())).is_none());
+		net_graph_msg_handler.sync_routing_table(&node_id, &init);
+		match &net_graph_msg_handler.get_and_clear_pending_msg_events()[..] {
+			&[MessageSendEvent::SendChannelRangeQuery { .. }] => {},
+			_ => panic!("Expected SendChannelRangeQuery"),
+		}
 	}
 }
@@ -15,14 +15,15 @@
 use bitcoin::secp256k1::key::PublicKey;
 
 use ln::channelmanager::ChannelDetails;
-use ln::features::{ChannelFeatures, NodeFeatures};
+use ln::features::{ChannelFeatures, InvoiceFeatures, NodeFeatures};
 use ln::msgs::{DecodeError, ErrorAction, LightningError, MAX_VALUE_MSAT};
-use routing::network_graph::{NetworkGraph, RoutingFees};
+use routing::network_graph::{DirectionalChannelInfo, NetworkGraph, RoutingFees};
+use routing::scorer::Score;
 use util::ser::{Writeable, Readable};
 use util::logger::Logger;
 
 use std::cmp;
-use std::collections::{HashMap,BinaryHeap};
+use std::collections::{HashMap,HashSet,BinaryHeap};
 use std::ops::Deref;
 
 /// A hop in a route
@@ -113,6 +114,7 @@ impl Readable for Route {
 }
 
 /// A channel descriptor which provides a last-hop route to get_route
+#[derive(Eq, PartialEq, Clone, Debug)]
 pub struct RouteHint {
 	/// The node_id of the non-target end of the route
 	pub src_node_id: PublicKey,
@@ -131,6 +133,9 @@ struct RouteGraphNode {
 	pubkey: PublicKey,
 	lowest_fee_to_peer_through_node: u64,
 	lowest_fee_to_node: u64,
+	// The sum of the Score penalties of the channels from this node to the target. Included in
+	// lowest_fee_to_peer_through_node, but never in the fees we actually pay.
+	path_penalty_msat: u64,
 }
 
 impl cmp::Ord for RouteGraphNode {
@@ -152,16 +157,66 @@ struct DummyDirectionalChannelInfo {
 	fees: RoutingFees,
 }
 
+/// The fees and limits of a channel in a candidate path, used to compute how much must be sent
+/// over each channel once we've picked the value the path will carry.
+#[derive(Clone)]
+struct ChannelLimits {
+	fees: RoutingFees,
+	htlc_minimum_msat: u64,
+	available_liquidity_msat: u64,
+}
+
+/// The maximum number of paths a payment will be split over. This matches the limit on the
+/// number of paths ChannelManager::send_payment will accept.
+const MAX_PATHS: usize = 10;
+
+/// Gets the maximum amount we may send over a channel in the given direction, based on its
+/// htlc_maximum_msat and, if known, its capacity.
+fn max_channel_liquidity_msat(directional_info: &DirectionalChannelInfo, capacity_sats: Option<u64>) -> u64 {
+	let mut max_msat = directional_info.htlc_maximum_msat.unwrap_or(MAX_VALUE_MSAT);
+	if let Some(capacity_sats) = capacity_sats {
+		max_msat = cmp::min(max_msat, capacity_sats.saturating_mul(1000));
+	}
+	max_msat
+}
+
+/// Computes the amount which must be sent over each channel in a path (given the limits of each
+/// channel, starting with the one from us) for the last hop to receive value_msat. Returns None
+/// if any channel cannot carry its amount.
+fn compute_path_amounts(limits: &[ChannelLimits], value_msat: u64) -> Option<Vec<u64>> {
+	let mut amounts = vec![0; limits.len()];
+	let mut amount_msat = value_msat;
+	for idx in (0..limits.len()).rev() {
+		if idx != limits.len() - 1 {
+			// The node at the end of this channel charges the fees of the next channel
+			let fees = &limits[idx + 1].fees;
+			let fee_msat = amount_msat.checked_mul(fees.proportional_millionths as u64)
+				.and_then(|part| (fees.base_msat as u64).checked_add(part / 1000000))?;
+			amount_msat = amount_msat.checked_add(fee_msat)?;
+		}
+		if amount_msat < limits[idx].htlc_minimum_msat || amount_msat > limits[idx].available_liquidity_msat {
+			return None;
+		}
+		amounts[idx] = amount_msat;
+	}
+	Some(amounts)
+}
 
 /// Gets a route from us to the given target node.
 ///
+/// If the payee supports basic_mpp (as indicated by payee_features, which should be taken from
+/// the invoice if one is available, or otherwise by the target's node_announcement), the payment
+/// may be split over up to 10 paths when no single path has enough liquidity to carry it all.
+/// Note that multi-path payments require a payment_secret when passed to
+/// ChannelManager::send_payment.
+///
 /// Extra routing hops between known nodes and the target will be used if they are included in
 /// last_hops.
 ///
 /// If some channels aren't announced, it may be useful to fill in a first_hops with the
 /// results from a local ChannelManager::list_usable_channels() call. If it is filled in, our
 /// view of our local channels (from net_graph_msg_handler) will be ignored, and only those in first_hops
-/// will be used.
+/// will be used, limited to their outbound_capacity_msat.
 ///
 /// Panics if first_hops contains channels without short_channel_ids
 /// (ChannelManager::list_usable_channels will never include such channels).
@@ -169,10 +224,24 @@ struct DummyDirectionalChannelInfo {
 /// The fees on channels from us to next-hops are ignored (as they are assumed to all be
 /// equal), however the enabled/disabled bit on such channels as well as the htlc_minimum_msat
 /// *is* checked as they may change based on the receiving node.
-pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[&ChannelDetails]>,
-	last_hops: &[&RouteHint], final_value_msat: u64, final_cltv: u32, logger: L) -> Result<Route, LightningError> where L::Target: Logger {
-	// TODO: Obviously *only* using total fee cost sucks. We should consider weighting by
-	// uptime/success in using a node in the past.
+///
+/// The amount sent over any other channel is limited by its htlc_maximum_msat and, if known, its
+/// capacity.
+///
+/// Paths are picked by their total fees plus the penalty the given scorer applies to each of
+/// their channels (see routing::scorer).
+pub fn get_route<L: Deref, S: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, payee_features: Option<&InvoiceFeatures>, first_hops: Option<&[&ChannelDetails]>,
+	last_hops: &[&RouteHint], final_value_msat: u64, final_cltv: u32, logger: L, scorer: S) -> Result<Route, LightningError> where L::Target: Logger, S::Target: Score {
+	get_route_with_excluded_channels(our_node_id, network, target, payee_features, first_hops, last_hops, final_value_msat, final_cltv, &HashSet::new(), logger, scorer)
+}
+
+/// Gets a route from us to the given target node, as get_route does, but without using any of
+/// the channels (including first_hops and last_hops) in excluded_channels.
+///
+/// This is useful when retrying a failed payment, to avoid the channel which caused the failure
+/// (see Event::PaymentFailed::short_channel_id).
+pub fn get_route_with_excluded_channels<L: Deref, S: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, payee_features: Option<&InvoiceFeatures>, first_hops: Option<&[&ChannelDetails]>,
+	last_hops: &[&RouteHint], final_value_msat: u64, final_cltv: u32, excluded_channels: &HashSet<u64>, logger: L, scorer: S) -> Result<Route, LightningError> where L::Target: Logger, S::Target: Score {
 	if *target == *our_node_id {
 		return Err(LightningError{err: "Cannot generate a route to ourselves".to_owned(), action: ErrorAction::IgnoreError});
 	}
@@ -181,11 +250,23 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 		return Err(LightningError{err: "Cannot generate a route of more value than all existing satoshis".to_owned(), action: ErrorAction::IgnoreError});
 	}
 
+	let allow_mpp = if let Some(features) = payee_features {
+		features.supports_basic_mpp()
+	} else if let Some(node_info) = network.get_nodes().get(target).and_then(|node| node.announcement_info.as_ref()) {
+		node_info.features.supports_basic_mpp()
+	} else { false };
+
 	// We do a dest-to-source Dijkstra's sorting by each node's distance from the destination
 	// plus the minimum per-HTLC fee to get from it to another node (aka "shitty A*").
 	// TODO: There are a few tweaks we could do, including possibly pre-calculating more stuff
 	// to use as the A* heuristic beyond just the cost to get one node further than the current
 	// one.
+	//
+	// Each search finds a single path which can carry at least some minimum value, which is
+	// first the full remaining value. If that fails and the payee supports MPP, we search again
+	// for a path which can carry at least an even share of the remaining value over the paths
+	// we have left. We then send as much as the path can carry and repeat until the full value
+	// has been routed, tracking the liquidity used on each channel as we go.
 
 	let dummy_directional_info = DummyDirectionalChannelInfo { // used for first_hops routes
 		cltv_expiry_delta: 0,
@@ -195,225 +276,330 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 			proportional_millionths: 0,
 		}
 	};
-
-	let mut targets = BinaryHeap::new(); //TODO: Do we care about switching to eg Fibbonaci heap?
-	let mut dist = HashMap::with_capacity(network.get_nodes().len());
+	let dummy_channel_limits = ChannelLimits {
+		fees: dummy_directional_info.fees,
+		htlc_minimum_msat: 0,
+		available_liquidity_msat: 0,
+	};
 
 	let mut first_hop_targets = HashMap::with_capacity(if first_hops.is_some() { first_hops.as_ref().unwrap().len() } else { 0 });
+	let mut direct_channels = Vec::new();
 	if let Some(hops) = first_hops {
 		for chan in hops {
 			let short_channel_id = chan.short_channel_id.expect("first_hops should be filled in with usable channels, not pending ones");
 			if chan.remote_network_id == *target {
-				return Ok(Route {
-					paths: vec![vec![RouteHop {
-						pubkey: chan.remote_network_id,
-						node_features: chan.counterparty_features.to_context(),
-						short_channel_id,
-						channel_features: chan.counterparty_features.to_context(),
-						fee_msat: final_value_msat,
-						cltv_expiry_delta: final_cltv,
-					}]],
-				});
+				direct_channels.push((short_channel_id, chan.counterparty_features.clone(), chan.outbound_capacity_msat));
+			} else {
+				first_hop_targets.insert(chan.remote_network_id, (short_channel_id, chan.counterparty_features.clone(), chan.outbound_capacity_msat));
 			}
-			first_hop_targets.insert(chan.remote_network_id, (short_channel_id, chan.counterparty_features.clone()));
 		}
-		if first_hop_targets.is_empty() {
+		if first_hop_targets.is_empty() && direct_channels.is_empty() {
 			return Err(LightningError{err: "Cannot route when there are no outbound routes away from us".to_owned(), action: ErrorAction::IgnoreError});
 		}
 	}
 
-	macro_rules! add_entry {
-		// Adds entry which goes from $src_node_id to $dest_node_id
-		// over the channel with id $chan_id with fees described in
-		// $directional_info.
-		( $chan_id: expr, $src_node_id: expr, $dest_node_id: expr, $directional_info: expr, $chan_features: expr, $starting_fee_msat: expr ) => {
-			//TODO: Explore simply adding fee to hit htlc_minimum_msat
-			if $starting_fee_msat as u64 + final_value_msat >= $directional_info.htlc_minimum_msat {
-				let proportional_fee_millions = ($starting_fee_msat + final_value_msat).checked_mul($directional_info.fees.proportional_millionths as u64);
-				if let Some(new_fee) = proportional_fee_millions.and_then(|part| {
-						($directional_info.fees.base_msat as u64).checked_add(part / 1000000) })
-				{
-					let mut total_fee = $starting_fee_msat as u64;
-					let hm_entry = dist.entry(&$src_node_id);
-					let old_entry = hm_entry.or_insert_with(|| {
-						let node = network.get_nodes().get(&$src_node_id).unwrap();
-						let mut fee_base_msat = u32::max_value();
-						let mut fee_proportional_millionths = u32::max_value();
-						if let Some(fees) = node.lowest_inbound_channel_fees {
-							fee_base_msat = fees.base_msat;
-							fee_proportional_millionths = fees.proportional_millionths;
-						};
-						(u64::max_value(),
-							fee_base_msat,
-							fee_proportional_millionths,
-							RouteHop {
-								pubkey: $dest_node_id.clone(),
-								node_features: NodeFeatures::empty(),
-								short_channel_id: 0,
-								channel_features: $chan_features.clone(),
-								fee_msat: 0,
-								cltv_expiry_delta: 0,
-							},
-						)
-					});
-					if $src_node_id != *our_node_id {
-						// Ignore new_fee for channel-from-us as we assume all channels-from-us
-						// will have the same effective-fee
-						total_fee += new_fee;
-						if let Some(fee_inc) = final_value_msat.checked_add(total_fee).and_then(|inc| { (old_entry.2 as u64).checked_mul(inc) }) {
-							total_fee += fee_inc / 1000000 + (old_entry.1 as u64);
-						} else {
-							// max_value means we'll always fail the old_entry.0 > total_fee check
-							total_fee = u64::max_value();
-						}
-					}
-					let new_graph_node = RouteGraphNode {
-						pubkey: $src_node_id,
-						lowest_fee_to_peer_through_node: total_fee,
-						lowest_fee_to_node: $starting_fee_msat as u64 + new_fee,
-					};
-					if old_entry.0 > total_fee {
-						targets.push(new_graph_node);
-						old_entry.0 = total_fee;
-						old_entry.3 = RouteHop {
-							pubkey: $dest_node_id.clone(),
-							node_features: NodeFeatures::empty(),
-							short_channel_id: $chan_id.clone(),
-							channel_features: $chan_features.clone(),
-							fee_msat: new_fee, // This field is ignored on the last-hop anyway
-							cltv_expiry_delta: $directional_info.cltv_expiry_delta as u32,
-						}
-					}
-				}
+	// The value we've already routed over each (short_channel_id, source node) pair. Excluded
+	// channels are treated as having no liquidity at all.
+	let mut used_liquidity_msat: HashMap<(u64, PublicKey), u64> = HashMap::new();
+	macro_rules! available_liquidity {
+		( $chan_id: expr, $src_node_id: expr, $max_liquidity_msat: expr ) => {
+			if excluded_channels.contains(&$chan_id) { 0 } else {
+				($max_liquidity_msat as u64).saturating_sub(*used_liquidity_msat.get(&($chan_id, $src_node_id)).unwrap_or(&0))
 			}
-		};
+		}
 	}
 
-	macro_rules! add_entries_to_cheapest_to_target_node {
-		( $node: expr, $node_id: expr, $fee_to_target_msat: expr ) => {
-			if first_hops.is_some() {
-				if let Some(&(ref first_hop, ref features)) = first_hop_targets.get(&$node_id) {
-					add_entry!(first_hop, *our_node_id, $node_id, dummy_directional_info, features.to_context(), $fee_to_target_msat);
+	let mut paths = Vec::new();
+	let mut value_sent_msat = 0;
+	'paths: while paths.is_empty() || value_sent_msat < final_value_msat {
+		if paths.len() == MAX_PATHS {
+			break;
+		}
+		let remaining_value_msat = final_value_msat - value_sent_msat;
+		let mut min_value_msat = remaining_value_msat;
+		loop {
+			for &(short_channel_id, ref features, outbound_capacity_msat) in direct_channels.iter() {
+				let available_liquidity_msat = available_liquidity!(short_channel_id, *our_node_id, outbound_capacity_msat);
+				if available_liquidity_msat >= min_value_msat {
+					let value_msat = cmp::min(remaining_value_msat, available_liquidity_msat);
+					*used_liquidity_msat.entry((short_channel_id, *our_node_id)).or_insert(0) += value_msat;
+					paths.push(vec![RouteHop {
+						pubkey: *target,
+						node_features: features.to_context(),
+						short_channel_id,
+						channel_features: features.to_context(),
+						fee_msat: value_msat,
+						cltv_expiry_delta: final_cltv,
+					}]);
+					value_sent_msat += value_msat;
+					continue 'paths;
 				}
 			}
 
-			let features;
-			if let Some(node_info) = $node.announcement_info.as_ref() {
-				features = node_info.features.clone();
-			} else {
-				features = NodeFeatures::empty();
+			let mut targets = BinaryHeap::new(); //TODO: Do we care about switching to eg Fibbonaci heap?
+			let mut dist = HashMap::with_capacity(network.get_nodes().len());
+
+			macro_rules! add_entry {
+				// Adds entry which goes from $src_node_id to $dest_node_id
+				// over the channel with id $chan_id with fees described in
+				// $directional_info, if it has enough of $available_liquidity_msat left.
+				// $next_hops_path_penalty_msat is the sum of the scorer's penalties for the
+				// channels from $dest_node_id to the target.
+				( $chan_id: expr, $src_node_id: expr, $dest_node_id: expr, $directional_info: expr, $chan_features: expr, $starting_fee_msat: expr, $available_liquidity_msat: expr, $next_hops_path_penalty_msat: expr ) => {
+					//TODO: Explore simply adding fee to hit htlc_minimum_msat
+					let amount_to_transfer_msat = $starting_fee_msat as u64 + min_value_msat;
+					let available_liquidity_msat = $available_liquidity_msat;
+					if amount_to_transfer_msat >= $directional_info.htlc_minimum_msat && amount_to_transfer_msat <= available_liquidity_msat {
+						let proportional_fee_millions = amount_to_transfer_msat.checked_mul($directional_info.fees.proportional_millionths as u64);
+						if let Some(new_fee) = proportional_fee_millions.and_then(|part| {
+								($directional_info.fees.base_msat as u64).checked_add(part / 1000000) })
+						{
+							let mut total_fee = $starting_fee_msat as u64;
+							let hm_entry = dist.entry(&$src_node_id);
+							let old_entry = hm_entry.or_insert_with(|| {
+								let node = network.get_nodes().get(&$src_node_id).unwrap();
+								let mut fee_base_msat = u32::max_value();
+								let mut fee_proportional_millionths = u32::max_value();
+								if let Some(fees) = node.lowest_inbound_channel_fees {
+									fee_base_msat = fees.base_msat;
+									fee_proportional_millionths = fees.proportional_millionths;
+								};
+								(u64::max_value(),
+									fee_base_msat,
+									fee_proportional_millionths,
+									RouteHop {
+										pubkey: $dest_node_id.clone(),
+										node_features: NodeFeatures::empty(),
+										short_channel_id: 0,
+										channel_features: $chan_features.clone(),
+										fee_msat: 0,
+										cltv_expiry_delta: 0,
+									},
+									dummy_channel_limits.clone(),
+								)
+							});
+							if $src_node_id != *our_node_id {
+								// Ignore new_fee for channel-from-us as we assume all channels-from-us
+								// will have the same effective-fee
+								total_fee += new_fee;
+								if let Some(fee_inc) = min_value_msat.checked_add(total_fee).and_then(|inc| { (old_entry.2 as u64).checked_mul(inc) }) {
+									total_fee += fee_inc / 1000000 + (old_entry.1 as u64);
+								} else {
+									// max_value means we'll always fail the old_entry.0 > total_fee check
+									total_fee = u64::max_value();
+								}
+							}
+							let dest_node_id: PublicKey = $dest_node_id.clone();
+							let path_penalty_msat = ($next_hops_path_penalty_msat as u64).saturating_add(
+								scorer.channel_penalty_msat($chan_id.clone(), amount_to_transfer_msat, &$src_node_id, &dest_node_id));
+							let total_cost = total_fee.saturating_add(path_penalty_msat);
+							let new_graph_node = RouteGraphNode {
+								pubkey: $src_node_id,
+								lowest_fee_to_peer_through_node: total_cost,
+								lowest_fee_to_node: $starting_fee_msat as u64 + new_fee,
+								path_penalty_msat,
+							};
+							if old_entry.0 > total_cost {
+								targets.push(new_graph_node);
+								old_entry.0 = total_cost;
+								old_entry.3 = RouteHop {
+									pubkey: $dest_node_id.clone(),
+									node_features: NodeFeatures::empty(),
+									short_channel_id: $chan_id.clone(),
+									channel_features: $chan_features.clone(),
+									fee_msat: new_fee, // This field is ignored on the last-hop anyway
+									cltv_expiry_delta: $directional_info.cltv_expiry_delta as u32,
+								};
+								old_entry.4 = ChannelLimits {
+									fees: $directional_info.fees,
+									htlc_minimum_msat: $directional_info.htlc_minimum_msat,
+									available_liquidity_msat,
+								};
+							}
+						}
+					}
+				};
 			}
 
-			if !features.requires_unknown_bits() {
-				for chan_id in $node.channels.iter() {
-					let chan = network.get_channels().get(chan_id).unwrap();
-					if !chan.features.requires_unknown_bits() {
-						if chan.node_one == *$node_id {
-							// ie $node is one, ie next hop in A* is two, via the two_to_one channel
-							if first_hops.is_none() || chan.node_two != *our_node_id {
-								if let Some(two_to_one) = chan.two_to_one.as_ref() {
-									if two_to_one.enabled {
-										add_entry!(chan_id, chan.node_two, chan.node_one, two_to_one, chan.features, $fee_to_target_msat);
+			macro_rules! add_entries_to_cheapest_to_target_node {
+				( $node: expr, $node_id: expr, $fee_to_target_msat: expr, $path_penalty_msat: expr ) => {
+					if first_hops.is_some() {
+						if let Some(&(ref first_hop, ref features, outbound_capacity_msat)) = first_hop_targets.get(&$node_id) {
+							add_entry!(first_hop, *our_node_id, $node_id, dummy_directional_info, features.to_context(), $fee_to_target_msat,
+								available_liquidity!(*first_hop, *our_node_id, outbound_capacity_msat), $path_penalty_msat);
+						}
+					}
+
+					let features;
+					if let Some(node_info) = $node.announcement_info.as_ref() {
+						features = node_info.features.clone();
+					} else {
+						features = NodeFeatures::empty();
+					}
+
+					if !features.requires_unknown_bits() {
+						for chan_id in $node.channels.iter() {
+							let chan = network.get_channels().get(chan_id).unwrap();
+							if !chan.features.requires_unknown_bits() {
+								if chan.node_one == *$node_id {
+									// ie $node is one, ie next hop in A* is two, via the two_to_one channel
+									if first_hops.is_none() || chan.node_two != *our_node_id {
+										if let Some(two_to_one) = chan.two_to_one.as_ref() {
+											if two_to_one.enabled {
+												add_entry!(chan_id, chan.node_two, chan.node_one, two_to_one, chan.features, $fee_to_target_msat,
+													available_liquidity!(*chan_id, chan.node_two, max_channel_liquidity_msat(two_to_one, chan.capacity_sats)), $path_penalty_msat);
+											}
+										}
 									}
-								}
-							}
-						} else {
-							if first_hops.is_none() || chan.node_one != *our_node_id {
-								if let Some(one_to_two) = chan.one_to_two.as_ref() {
-									if one_to_two.enabled {
-										add_entry!(chan_id, chan.node_one, chan.node_two, one_to_two, chan.features, $fee_to_target_msat);
+								} else {
+									if first_hops.is_none() || chan.node_one != *our_node_id {
+										if let Some(one_to_two) = chan.one_to_two.as_ref() {
+											if one_to_two.enabled {
+												add_entry!(chan_id, chan.node_one, chan.node_two, one_to_two, chan.features, $fee_to_target_msat,
+													available_liquidity!(*chan_id, chan.node_one, max_channel_liquidity_msat(one_to_two, chan.capacity_sats)), $path_penalty_msat);
+											}
+										}
+
 									}
 								}
-
 							}
 						}
 					}
-				}
+				};
 			}
-		};
-	}
 
-	match network.get_nodes().get(target) {
-		None => {},
-		Some(node) => {
-			add_entries_to_cheapest_to_target_node!(node, target, 0);
-		},
-	}
+			match network.get_nodes().get(target) {
+				None => {},
+				Some(node) => {
+					add_entries_to_cheapest_to_target_node!(node, target, 0, 0);
+				},
+			}
 
-	for hop in last_hops.iter() {
-		if first_hops.is_none() || hop.src_node_id != *our_node_id { // first_hop overrules last_hops
-			if network.get_nodes().get(&hop.src_node_id).is_some() {
-				if first_hops.is_some() {
-					if let Some(&(ref first_hop, ref features)) = first_hop_targets.get(&hop.src_node_id) {
-						// Currently there are no channel-context features defined, so we are a
-						// bit lazy here. In the future, we should pull them out via our
-						// ChannelManager, but there's no reason to waste the space until we
-						// need them.
-						add_entry!(first_hop, *our_node_id , hop.src_node_id, dummy_directional_info, features.to_context(), 0);
+			for hop in last_hops.iter() {
+				if first_hops.is_none() || hop.src_node_id != *our_node_id { // first_hop overrules last_hops
+					if network.get_nodes().get(&hop.src_node_id).is_some() {
+						if first_hops.is_some() {
+							if let Some(&(ref first_hop, ref features, outbound_capacity_msat)) = first_hop_targets.get(&hop.src_node_id) {
+								// Currently there are no channel-context features defined, so we are a
+								// bit lazy here. In the future, we should pull them out via our
+								// ChannelManager, but there's no reason to waste the space until we
+								// need them.
+								add_entry!(first_hop, *our_node_id , hop.src_node_id, dummy_directional_info, features.to_context(), 0,
+									available_liquidity!(*first_hop, *our_node_id, outbound_capacity_msat), 0);
+							}
+						}
+						// BOLT 11 doesn't allow inclusion of features for the last hop hints, which
+						// really sucks, cause we're gonna need that eventually.
+						add_entry!(hop.short_channel_id, hop.src_node_id, target, hop, ChannelFeatures::empty(), 0,
+							available_liquidity!(hop.short_channel_id, hop.src_node_id, MAX_VALUE_MSAT), 0);
 					}
 				}
-				// BOLT 11 doesn't allow inclusion of features for the last hop hints, which
-				// really sucks, cause we're gonna need that eventually.
-				add_entry!(hop.short_channel_id, hop.src_node_id, target, hop, ChannelFeatures::empty(), 0);
 			}
-		}
-	}
 
-	while let Some(RouteGraphNode { pubkey, lowest_fee_to_node, .. }) = targets.pop() {
-		if pubkey == *our_node_id {
-			let mut res = vec!(dist.remove(&our_node_id).unwrap().3);
-			loop {
-				if let Some(&(_, ref features)) = first_hop_targets.get(&res.last().unwrap().pubkey) {
-					res.last_mut().unwrap().node_features = features.to_context();
-				} else if let Some(node) = network.get_nodes().get(&res.last().unwrap().pubkey) {
-					if let Some(node_info) = node.announcement_info.as_ref() {
-						res.last_mut().unwrap().node_features = node_info.features.clone();
-					} else {
-						res.last_mut().unwrap().node_features = NodeFeatures::empty();
+			while let Some(RouteGraphNode { pubkey, lowest_fee_to_node, path_penalty_msat, .. }) = targets.pop() {
+				if pubkey == *our_node_id {
+					let first_entry = dist.remove(&our_node_id).unwrap();
+					let mut res = vec!(first_entry.3);
+					let mut limits = vec!(first_entry.4);
+					loop {
+						if let Some(&(_, ref features, _)) = first_hop_targets.get(&res.last().unwrap().pubkey) {
+							res.last_mut().unwrap().node_features = features.to_context();
+						} else if let Some(node) = network.get_nodes().get(&res.last().unwrap().pubkey) {
+							if let Some(node_info) = node.announcement_info.as_ref() {
+								res.last_mut().unwrap().node_features = node_info.features.clone();
+							} else {
+								res.last_mut().unwrap().node_features = NodeFeatures::empty();
+							}
+						} else {
+							// We should be able to fill in features for everything except the last
+							// hop, if the last hop was provided via a BOLT 11 invoice (though we
+							// should be able to extend it further as BOLT 11 does have feature
+							// flags for the last hop node itself).
+							assert!(res.last().unwrap().pubkey == *target);
+						}
+						if res.last().unwrap().pubkey == *target {
+							break;
+						}
+
+						let new_entry = match dist.remove(&res.last().unwrap().pubkey) {
+							Some(hop) => hop,
+							None => return Err(LightningError{err: "Failed to find a non-fee-overflowing path to the given destination".to_owned(), action: ErrorAction::IgnoreError}),
+						};
+						res.last_mut().unwrap().cltv_expiry_delta = new_entry.3.cltv_expiry_delta;
+						res.push(new_entry.3);
+						limits.push(new_entry.4);
+					}
+
+					// Find the largest value up to the remaining value which this path can carry.
+					if compute_path_amounts(&limits, min_value_msat).is_none() {
+						return Err(LightningError{err: "Failed to find a non-fee-overflowing path to the given destination".to_owned(), action: ErrorAction::IgnoreError});
+					}
+					let mut value_msat = min_value_msat;
+					let mut max_value_msat = remaining_value_msat;
+					while value_msat < max_value_msat {
+						let candidate_value_msat = value_msat + (max_value_msat - value_msat + 1) / 2;
+						if compute_path_amounts(&limits, candidate_value_msat).is_some() {
+							value_msat = candidate_value_msat;
+						} else {
+							max_value_msat = candidate_value_msat - 1;
+						}
+					}
+
+					let amounts = compute_path_amounts(&limits, value_msat).unwrap();
+					for idx in 0..res.len() {
+						let src_node_id = if idx == 0 { *our_node_id } else { res[idx - 1].pubkey };
+						*used_liquidity_msat.entry((res[idx].short_channel_id, src_node_id)).or_insert(0) += amounts[idx];
+						if idx != res.len() - 1 {
+							res[idx].fee_msat = amounts[idx] - amounts[idx + 1];
+						}
+					}
+					res.last_mut().unwrap().fee_msat = value_msat;
+					res.last_mut().unwrap().cltv_expiry_delta = final_cltv;
+					if let Some(features) = payee_features {
+						res.last_mut().unwrap().node_features = features.to_context();
 					}
-				} else {
-					// We should be able to fill in features for everything except the last
-					// hop, if the last hop was provided via a BOLT 11 invoice (though we
-					// should be able to extend it further as BOLT 11 does have feature
-					// flags for the last hop node itself).
-					assert!(res.last().unwrap().pubkey == *target);
+					paths.push(res);
+					value_sent_msat += value_msat;
+					continue 'paths;
 				}
-				if res.last().unwrap().pubkey == *target {
-					break;
+
+				match network.get_nodes().get(&pubkey) {
+					None => {},
+					Some(node) => {
+						add_entries_to_cheapest_to_target_node!(node, &pubkey, lowest_fee_to_node, path_penalty_msat);
+					},
 				}
+			}
 
-				let new_entry = match dist.remove(&res.last().unwrap().pubkey) {
-					Some(hop) => hop.3,
-					None => return Err(LightningError{err: "Failed to find a non-fee-overflowing path to the given destination".to_owned(), action: ErrorAction::IgnoreError}),
-				};
-				res.last_mut().unwrap().fee_msat = new_entry.fee_msat;
-				res.last_mut().unwrap().cltv_expiry_delta = new_entry.cltv_expiry_delta;
-				res.push(new_entry);
+			// We couldn't find a path which can carry min_value_msat. If we can split the payment,
+			// retry with the smallest share which still lets us route the full value in MAX_PATHS.
+			let paths_left = MAX_PATHS - paths.len();
+			let share_value_msat = (remaining_value_msat + paths_left as u64 - 1) / paths_left as u64;
+			if !allow_mpp || share_value_msat >= min_value_msat {
+				break 'paths;
 			}
-			res.last_mut().unwrap().fee_msat = final_value_msat;
-			res.last_mut().unwrap().cltv_expiry_delta = final_cltv;
-			let route = Route { paths: vec![res] };
-			log_trace!(logger, "Got route: {}", log_route!(route));
-			return Ok(route);
+			min_value_msat = share_value_msat;
 		}
+	}
 
-		match network.get_nodes().get(&pubkey) {
-			None => {},
-			Some(node) => {
-				add_entries_to_cheapest_to_target_node!(node, &pubkey, lowest_fee_to_node);
-			},
-		}
+	if paths.is_empty() {
+		return Err(LightningError{err: "Failed to find a path to the given destination".to_owned(), action: ErrorAction::IgnoreError});
+	}
+	if value_sent_msat < final_value_msat {
+		return Err(LightningError{err: "Failed to find a sufficient route to the given destination".to_owned(), action: ErrorAction::IgnoreError});
 	}
 
-	Err(LightningError{err: "Failed to find a path to the given destination".to_owned(), action: ErrorAction::IgnoreError})
+	let route = Route { paths };
+	log_trace!(logger, "Got route: {}", log_route!(route));
+	Ok(route)
 }
 
 #[cfg(test)]
 mod tests {
 	use chain::chaininterface;
-	use routing::router::{get_route, RouteHint, RoutingFees};
+	use routing::router::{get_route, get_route_with_excluded_channels, RouteHint, RoutingFees};
 	use routing::network_graph::NetGraphMsgHandler;
-	use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
+	use ln::features::{ChannelFeatures, InitFeatures, InvoiceFeatures, NodeFeatures};
 	use ln::msgs::{ErrorAction, LightningError, OptionalField, UnsignedChannelAnnouncement, ChannelAnnouncement, RoutingMessageHandler,
 	   NodeAnnouncement, UnsignedNodeAnnouncement, ChannelUpdate, UnsignedChannelUpdate};
 	use ln::channelmanager;
@@ -430,6 +616,7 @@ mod tests {
 	use bitcoin::secp256k1::key::{PublicKey,SecretKey};
 	use bitcoin::secp256k1::{Secp256k1, All};
 
+	use std::collections::HashSet;
 	use std::sync::Arc;
 
 	// Using the same keys for LN and BTC ids
@@ -535,7 +722,7 @@ mod tests {
 		let secp_ctx = Secp256k1::new();
 		let logger = Arc::new(test_utils::TestLogger::new());
 		let chain_monitor = Arc::new(chaininterface::ChainWatchInterfaceUtil::new(Network::Testnet));
-		let net_graph_msg_handler = NetGraphMsgHandler::new(chain_monitor, Arc::clone(&logger));
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.block_hash(), chain_monitor, Arc::clone(&logger));
 		// Build network from our_id to node7:
 		//
 		//        -1(1)2-  node0  -1(3)2-
@@ -840,7 +1027,7 @@ mod tests {
 		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
 
 		// Simple route to 3 via 2
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, None, &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -858,6 +1045,48 @@ mod tests {
 		assert_eq!(route.paths[0][1].channel_features.le_flags(), &id_to_feature_flags(4));
 	}
 
+	#[test]
+	fn scorer_penalty_test() {
+		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+
+		// Without a penalty, the cheapest route to 3 is via 2 over channels 2 and 4
+		let scorer = test_utils::TestScorer::new();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0].iter().map(|hop| hop.short_channel_id).collect::<Vec<_>>(), vec![2, 4]);
+
+		// A large enough penalty on channel 4 pushes us onto a more expensive route, though the
+		// penalty itself is not included in the fees we pay.
+		scorer.channel_penalties_msat.lock().unwrap().insert(4, 1_000_000);
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert!(route.paths[0].iter().all(|hop| hop.short_channel_id != 4));
+		assert_eq!(route.paths[0].last().unwrap().pubkey, nodes[2]);
+		assert!(route.paths[0].iter().map(|hop| hop.fee_msat).sum::<u64>() < 1_000_000);
+	}
+
+	#[test]
+	fn excluded_channels_test() {
+		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+
+		// Excluding the channel to 2 forces us to route to 3 some other way
+		let mut excluded_channels = HashSet::new();
+		excluded_channels.insert(2);
+		let route = get_route_with_excluded_channels(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, None, &Vec::new(), 100, 42, &excluded_channels, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
+		assert!(route.paths[0].iter().all(|hop| hop.short_channel_id != 2));
+		assert_eq!(route.paths[0].last().unwrap().pubkey, nodes[2]);
+
+		// ...and excluding every channel into 3 leaves us with no route at all
+		excluded_channels.insert(3);
+		excluded_channels.insert(4);
+		excluded_channels.insert(6);
+		excluded_channels.insert(13);
+		let network_graph = net_graph_msg_handler.network_graph.read().unwrap();
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route_with_excluded_channels(&our_id, &network_graph, &nodes[2], None, None, &Vec::new(), 100, 42, &excluded_channels, Arc::clone(&logger), &test_utils::TestScorer::new()) {
+			assert_eq!(err, "Failed to find a path to the given destination");
+		} else { panic!(); }
+	}
+
 	#[test]
 	fn disable_channels_test() {
 		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
@@ -890,7 +1119,7 @@ mod tests {
 		});
 
 		// If all the channels require some features we don't understand, route should fail
-		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger)) {
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, None, &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()) {
 			assert_eq!(err, "Failed to find a path to the given destination");
 		} else { panic!(); }
 
@@ -902,11 +1131,11 @@ mod tests {
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 0,
 			user_id: 0,
-			outbound_capacity_msat: 0,
+			outbound_capacity_msat: 250_000_000,
 			inbound_capacity_msat: 0,
 			is_live: true,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()),  &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, Some(&our_chans.iter().collect::<Vec<_>>()),  &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[7]);
@@ -937,7 +1166,7 @@ mod tests {
 		add_or_update_node(&net_graph_msg_handler, &secp_ctx, &privkeys[7], unknown_features.clone(), 1);
 
 		// If all nodes require some features we don't understand, route should fail
-		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger)) {
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, None, &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()) {
 			assert_eq!(err, "Failed to find a path to the given destination");
 		} else { panic!(); }
 
@@ -949,11 +1178,11 @@ mod tests {
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 0,
 			user_id: 0,
-			outbound_capacity_msat: 0,
+			outbound_capacity_msat: 250_000_000,
 			inbound_capacity_msat: 0,
 			is_live: true,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[7]);
@@ -981,7 +1210,7 @@ mod tests {
 		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
 
 		// Route to 1 via 2 and 3 because our channel to 1 is disabled
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0], None, &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0], None, None, &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 3);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -1013,11 +1242,11 @@ mod tests {
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 0,
 			user_id: 0,
-			outbound_capacity_msat: 0,
+			outbound_capacity_msat: 250_000_000,
 			inbound_capacity_msat: 0,
 			is_live: true,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[7]);
@@ -1070,7 +1299,7 @@ mod tests {
 		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
 
 		// Simple test across 2, 3, 5, and 4 via a last_hop channel
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops(&nodes).iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, None, &last_hops(&nodes).iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 5);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -1124,12 +1353,12 @@ mod tests {
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 0,
 			user_id: 0,
-			outbound_capacity_msat: 0,
+			outbound_capacity_msat: 250_000_000,
 			inbound_capacity_msat: 0,
 			is_live: true,
 		}];
 		let mut last_hops = last_hops(&nodes);
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], Some(&our_chans.iter().collect::<Vec<_>>()), &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, Some(&our_chans.iter().collect::<Vec<_>>()), &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[3]);
@@ -1149,7 +1378,7 @@ mod tests {
 		last_hops[0].fees.base_msat = 1000;
 
 		// Revert to via 6 as the fee on 8 goes up
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, None, &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 4);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -1183,7 +1412,7 @@ mod tests {
 		assert_eq!(route.paths[0][3].channel_features.le_flags(), &Vec::<u8>::new()); // We can't learn any flags from invoices, sadly
 
 		// ...but still use 8 for larger payments as 6 has a variable feerate
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops.iter().collect::<Vec<_>>(), 2000, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, None, &last_hops.iter().collect::<Vec<_>>(), 2000, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
 		assert_eq!(route.paths[0].len(), 5);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -1223,4 +1452,102 @@ mod tests {
 		assert_eq!(route.paths[0][4].node_features.le_flags(), &Vec::<u8>::new()); // We dont pass flags in from invoices yet
 		assert_eq!(route.paths[0][4].channel_features.le_flags(), &Vec::<u8>::new()); // We can't learn any flags from invoices, sadly
 	}
+
+	#[test]
+	fn first_hop_liquidity_test() {
+		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+
+		// Route to 3 via our channel to 8, which must be able to carry the payment plus chan13's
+		// 200% fee
+		let mut our_chans = vec![channelmanager::ChannelDetails {
+			channel_id: [0; 32],
+			short_channel_id: Some(42),
+			remote_network_id: nodes[7].clone(),
+			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
+			channel_value_satoshis: 0,
+			user_id: 0,
+			outbound_capacity_msat: 299,
+			inbound_capacity_msat: 0,
+			is_live: true,
+		}];
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()) {
+			assert_eq!(err, "Failed to find a path to the given destination");
+		} else { panic!(); }
+
+		our_chans[0].outbound_capacity_msat = 300;
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
+		assert_eq!(route.paths.len(), 1);
+		assert_eq!(route.paths[0][0].short_channel_id, 42);
+		assert_eq!(route.paths[0][0].fee_msat, 200);
+		assert_eq!(route.paths[0][1].short_channel_id, 13);
+		assert_eq!(route.paths[0][1].fee_msat, 100);
+	}
+
+	#[test]
+	fn mpp_route_test() {
+		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let (_, our_id, privkeys, nodes) = get_nodes(&secp_ctx);
+
+		// Limit chan4 and chan13, the last hops of our two paths to 3, so that neither can carry
+		// the full payment alone.
+		update_channel(&net_graph_msg_handler, &secp_ctx, &privkeys[1], UnsignedChannelUpdate {
+			chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+			short_channel_id: 4,
+			timestamp: 2,
+			flags: 0,
+			cltv_expiry_delta: (4 << 8) | 1,
+			htlc_minimum_msat: 0,
+			htlc_maximum_msat: OptionalField::Present(60),
+			fee_base_msat: 0,
+			fee_proportional_millionths: 1000000,
+			excess_data: Vec::new()
+		});
+		update_channel(&net_graph_msg_handler, &secp_ctx, &privkeys[7], UnsignedChannelUpdate {
+			chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+			short_channel_id: 13,
+			timestamp: 2,
+			flags: 0,
+			cltv_expiry_delta: (13 << 8) | 1,
+			htlc_minimum_msat: 0,
+			htlc_maximum_msat: OptionalField::Present(60),
+			fee_base_msat: 0,
+			fee_proportional_millionths: 2000000,
+			excess_data: Vec::new()
+		});
+
+		// Node 3 doesn't announce basic_mpp support, so we can't split the payment...
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, None, &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()) {
+			assert_eq!(err, "Failed to find a path to the given destination");
+		} else { panic!(); }
+
+		// ...or route more than both paths can carry combined...
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&InvoiceFeatures::known()), None, &Vec::new(), 121, 42, Arc::clone(&logger), &test_utils::TestScorer::new()) {
+			assert_eq!(err, "Failed to find a sufficient route to the given destination");
+		} else { panic!(); }
+
+		// ...but if its invoice supports it and both paths can carry it, we fill up the cheaper path via node 2 and send the rest
+		// via node 8.
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&InvoiceFeatures::known()), None, &Vec::new(), 100, 42, Arc::clone(&logger), &test_utils::TestScorer::new()).unwrap();
+		assert_eq!(route.paths.len(), 2);
+
+		assert_eq!(route.paths[0].len(), 2);
+		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
+		assert_eq!(route.paths[0][0].short_channel_id, 2);
+		assert_eq!(route.paths[0][0].fee_msat, 60);
+		assert_eq!(route.paths[0][1].pubkey, nodes[2]);
+		assert_eq!(route.paths[0][1].short_channel_id, 4);
+		assert_eq!(route.paths[0][1].fee_msat, 60);
+		assert_eq!(route.paths[0][1].cltv_expiry_delta, 42);
+		assert_eq!(route.paths[0][1].node_features.le_flags(), InvoiceFeatures::known().le_flags());
+
+		assert_eq!(route.paths[1].len(), 2);
+		assert_eq!(route.paths[1][0].pubkey, nodes[7]);
+		assert_eq!(route.paths[1][0].short_channel_id, 12);
+		assert_eq!(route.paths[1][0].fee_msat, 80);
+		assert_eq!(route.paths[1][1].pubkey, nodes[2]);
+		assert_eq!(route.paths[1][1].short_channel_id, 13);
+		assert_eq!(route.paths[1][1].fee_msat, 40);
+		assert_eq!(route.paths[1][1].cltv_expiry_delta, 42);
+	}
 }
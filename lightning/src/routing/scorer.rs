@@ -0,0 +1,288 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Utilities for scoring channels when finding routes.
+//!
+//! get_route consults a Score for a penalty, in msat, for each channel it considers routing
+//! through, in addition to the channel's fees. This lets the router steer payments away from
+//! channels which are likely to fail, even if they are the cheapest. The default Scorer penalizes
+//! channels which recently failed to relay one of our payments, decaying the penalty over time.
+
+use bitcoin::secp256k1::key::PublicKey;
+
+use routing::router::RouteHop;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A scorer for channels, used by get_route to weigh channels against each other in addition to
+/// their fees.
+///
+/// Penalties are given in msat and are only used to pick between routes: they are never paid.
+pub trait Score {
+	/// Returns the penalty, in msat, for routing amount_msat over the channel with the given
+	/// short_channel_id, in the direction from source to target.
+	fn channel_penalty_msat(&self, short_channel_id: u64, amount_msat: u64, source: &PublicKey, target: &PublicKey) -> u64;
+
+	/// Informs the scorer that a payment along the given path failed because of the channel with
+	/// the given short_channel_id (see Event::PaymentFailed::short_channel_id).
+	fn payment_path_failed(&self, path: &[RouteHop], short_channel_id: u64);
+
+	/// Informs the scorer that a payment along the given path succeeded.
+	fn payment_path_successful(&self, path: &[RouteHop]);
+}
+
+/// Parameters for the default Scorer.
+#[derive(Copy, Clone, Debug)]
+pub struct ScoringParameters {
+	/// A fixed penalty, in msat, applied to each channel. This makes the router prefer shorter
+	/// routes when their fees are similar.
+	///
+	/// Default value: 500 msat
+	pub base_penalty_msat: u64,
+	/// The penalty, in msat, applied to a channel each time it fails to relay one of our
+	/// payments. Penalties from repeated failures accumulate.
+	///
+	/// Default value: 1,024,000 msat
+	pub failure_penalty_msat: u64,
+	/// The time after which a channel's failure penalty has decayed to half its value.
+	///
+	/// Default value: 1 hour
+	pub failure_penalty_half_life: Duration,
+}
+
+impl Default for ScoringParameters {
+	fn default() -> Self {
+		ScoringParameters {
+			base_penalty_msat: 500,
+			failure_penalty_msat: 1024 * 1000,
+			failure_penalty_half_life: Duration::from_secs(3600),
+		}
+	}
+}
+
+/// A measurement of time, used by ScorerUsingTime to decay failure penalties. Implemented for
+/// std::time::Instant, but may be implemented differently, eg to control time in tests.
+pub trait Time {
+	/// Returns an instance corresponding to the current moment.
+	fn now() -> Self;
+	/// Returns the amount of time elapsed since self was created.
+	fn elapsed(&self) -> Duration;
+}
+
+impl Time for Instant {
+	fn now() -> Self {
+		Instant::now()
+	}
+
+	fn elapsed(&self) -> Duration {
+		Instant::elapsed(self)
+	}
+}
+
+/// The accumulated failure penalty of a channel in one direction, as of its last failure.
+struct ChannelFailure<T: Time> {
+	undecayed_penalty_msat: u64,
+	last_failed: T,
+}
+
+/// The default Score, which applies a fixed penalty to every channel plus a penalty for channels
+/// which recently failed to relay our payments. Failure penalties halve every
+/// failure_penalty_half_life and are cleared once a payment through the channel succeeds.
+///
+/// Note that the amount being routed is not currently considered.
+pub type Scorer = ScorerUsingTime<Instant>;
+
+/// A Scorer which measures the decay of failure penalties using the given Time. See Scorer for
+/// details.
+pub struct ScorerUsingTime<T: Time> {
+	params: ScoringParameters,
+	// Keyed by short_channel_id and the node the channel leads to, ie the direction.
+	channel_failures: Mutex<HashMap<(u64, PublicKey), ChannelFailure<T>>>,
+}
+
+impl<T: Time> ScorerUsingTime<T> {
+	/// Constructs a new Scorer using the given parameters.
+	pub fn new(params: ScoringParameters) -> Self {
+		ScorerUsingTime {
+			params,
+			channel_failures: Mutex::new(HashMap::new()),
+		}
+	}
+
+	fn decayed_penalty_msat(&self, failure: &ChannelFailure<T>) -> u64 {
+		let half_life_millis = duration_millis(self.params.failure_penalty_half_life);
+		if half_life_millis == 0 {
+			return 0;
+		}
+		let half_lives = duration_millis(failure.last_failed.elapsed()) / half_life_millis;
+		if half_lives >= 64 {
+			0
+		} else {
+			failure.undecayed_penalty_msat >> half_lives
+		}
+	}
+}
+
+impl<T: Time> Default for ScorerUsingTime<T> {
+	fn default() -> Self {
+		ScorerUsingTime::new(ScoringParameters::default())
+	}
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+	duration.as_secs().saturating_mul(1000).saturating_add(duration.subsec_millis() as u64)
+}
+
+impl<T: Time> Score for ScorerUsingTime<T> {
+	fn channel_penalty_msat(&self, short_channel_id: u64, _amount_msat: u64, _source: &PublicKey, target: &PublicKey) -> u64 {
+		let failure_penalty_msat = match self.channel_failures.lock().unwrap().get(&(short_channel_id, *target)) {
+			Some(failure) => self.decayed_penalty_msat(failure),
+			None => 0,
+		};
+		self.params.base_penalty_msat.saturating_add(failure_penalty_msat)
+	}
+
+	fn payment_path_failed(&self, path: &[RouteHop], short_channel_id: u64) {
+		let target = match path.iter().find(|hop| hop.short_channel_id == short_channel_id) {
+			Some(hop) => hop.pubkey,
+			None => return,
+		};
+		let mut channel_failures = self.channel_failures.lock().unwrap();
+		let decayed_penalty_msat = match channel_failures.get(&(short_channel_id, target)) {
+			Some(failure) => self.decayed_penalty_msat(failure),
+			None => 0,
+		};
+		channel_failures.insert((short_channel_id, target), ChannelFailure {
+			undecayed_penalty_msat: decayed_penalty_msat.saturating_add(self.params.failure_penalty_msat),
+			last_failed: T::now(),
+		});
+	}
+
+	fn payment_path_successful(&self, path: &[RouteHop]) {
+		let mut channel_failures = self.channel_failures.lock().unwrap();
+		for hop in path.iter() {
+			channel_failures.remove(&(hop.short_channel_id, hop.pubkey));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Score, ScorerUsingTime, ScoringParameters, Time};
+	use ln::features::{ChannelFeatures, NodeFeatures};
+	use routing::router::RouteHop;
+
+	use bitcoin::secp256k1::key::{PublicKey, SecretKey};
+	use bitcoin::secp256k1::Secp256k1;
+
+	use std::cell::Cell;
+	use std::time::Duration;
+
+	thread_local! {
+		static ELAPSED: Cell<Duration> = Cell::new(Duration::from_secs(0));
+	}
+
+	/// Time that only passes when a test advances it.
+	struct SinceEpoch(Duration);
+
+	impl SinceEpoch {
+		fn advance(duration: Duration) {
+			ELAPSED.with(|elapsed| elapsed.set(elapsed.get() + duration))
+		}
+	}
+
+	impl Time for SinceEpoch {
+		fn now() -> Self {
+			SinceEpoch(ELAPSED.with(|elapsed| elapsed.get()))
+		}
+
+		fn elapsed(&self) -> Duration {
+			Self::now().0 - self.0
+		}
+	}
+
+	type Scorer = ScorerUsingTime<SinceEpoch>;
+
+	fn pubkey(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	fn path() -> Vec<RouteHop> {
+		vec![RouteHop {
+			pubkey: pubkey(2),
+			node_features: NodeFeatures::empty(),
+			short_channel_id: 1,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: 0,
+			cltv_expiry_delta: 0,
+		}, RouteHop {
+			pubkey: pubkey(3),
+			node_features: NodeFeatures::empty(),
+			short_channel_id: 2,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: 1000,
+			cltv_expiry_delta: 0,
+		}]
+	}
+
+	#[test]
+	fn penalizes_failed_channels() {
+		let scorer = Scorer::new(ScoringParameters {
+			base_penalty_msat: 500,
+			failure_penalty_msat: 1000,
+			failure_penalty_half_life: Duration::from_secs(3600),
+		});
+		assert_eq!(scorer.channel_penalty_msat(2, 1000, &pubkey(2), &pubkey(3)), 500);
+
+		// Failures accumulate, but only in the direction the channel failed in
+		scorer.payment_path_failed(&path(), 2);
+		assert_eq!(scorer.channel_penalty_msat(2, 1000, &pubkey(2), &pubkey(3)), 1500);
+		scorer.payment_path_failed(&path(), 2);
+		assert_eq!(scorer.channel_penalty_msat(2, 1000, &pubkey(2), &pubkey(3)), 2500);
+		assert_eq!(scorer.channel_penalty_msat(2, 1000, &pubkey(3), &pubkey(2)), 500);
+		assert_eq!(scorer.channel_penalty_msat(1, 1000, &pubkey(1), &pubkey(2)), 500);
+
+		// A failure of a channel which isn't in the path is ignored
+		scorer.payment_path_failed(&path(), 42);
+		assert_eq!(scorer.channel_penalty_msat(42, 1000, &pubkey(2), &pubkey(3)), 500);
+
+		// A success clears the failure penalties along the path
+		scorer.payment_path_successful(&path());
+		assert_eq!(scorer.channel_penalty_msat(2, 1000, &pubkey(2), &pubkey(3)), 500);
+	}
+
+	#[test]
+	fn decays_failure_penalty() {
+		let scorer = Scorer::new(ScoringParameters {
+			base_penalty_msat: 0,
+			failure_penalty_msat: 1024,
+			failure_penalty_half_life: Duration::from_millis(100),
+		});
+		scorer.payment_path_failed(&path(), 1);
+		assert_eq!(scorer.channel_penalty_msat(1, 1000, &pubkey(1), &pubkey(2)), 1024);
+
+		// The penalty only halves once a full half-life has passed
+		SinceEpoch::advance(Duration::from_millis(99));
+		assert_eq!(scorer.channel_penalty_msat(1, 1000, &pubkey(1), &pubkey(2)), 1024);
+		SinceEpoch::advance(Duration::from_millis(1));
+		assert_eq!(scorer.channel_penalty_msat(1, 1000, &pubkey(1), &pubkey(2)), 512);
+		SinceEpoch::advance(Duration::from_millis(150));
+		assert_eq!(scorer.channel_penalty_msat(1, 1000, &pubkey(1), &pubkey(2)), 256);
+
+		// New failures add to the decayed penalty
+		scorer.payment_path_failed(&path(), 1);
+		assert_eq!(scorer.channel_penalty_msat(1, 1000, &pubkey(1), &pubkey(2)), 256 + 1024);
+
+		// Eventually the penalty decays completely
+		SinceEpoch::advance(Duration::from_secs(64));
+		assert_eq!(scorer.channel_penalty_msat(1, 1000, &pubkey(1), &pubkey(2)), 0);
+	}
+}
@@ -44,6 +44,16 @@ pub struct ChannelHandshakeConfig {
 	/// Default value: 1. If the value is less than 1, it is ignored and set to 1, as is required
 	/// by the protocol.
 	pub our_htlc_minimum_msat: u64,
+	/// If set, we advertise option_anchor_outputs to our peers and use the anchor output
+	/// commitment transaction format for new channels with peers which advertise it as well.
+	///
+	/// Anchor outputs allow the fee of a commitment transaction to be bumped via
+	/// child-pays-for-parent after it has been broadcast, see Event::BumpCommitmentTransaction.
+	/// As this requires the user to provide on-chain funds to pay for the bump, it is off by
+	/// default.
+	///
+	/// Default value: false.
+	pub negotiate_anchor_outputs: bool,
 }
 
 impl Default for ChannelHandshakeConfig {
@@ -52,6 +62,7 @@ impl Default for ChannelHandshakeConfig {
 			minimum_depth: 6,
 			our_to_self_delay: BREAKDOWN_TIMEOUT,
 			our_htlc_minimum_msat: 1,
+			negotiate_anchor_outputs: false,
 		}
 	}
 }
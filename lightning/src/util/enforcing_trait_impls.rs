@@ -14,7 +14,7 @@ use chain::keysinterface::{ChannelKeys, InMemoryChannelKeys};
 use std::cmp;
 use std::sync::{Mutex, Arc};
 
-use bitcoin::blockdata::transaction::{Transaction, SigHashType};
+use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::util::bip143;
 
 use bitcoin::secp256k1;
@@ -104,11 +104,12 @@ impl ChannelKeys for EnforcingChannelKeys {
 
 		for this_htlc in holder_commitment_tx.per_htlc.iter() {
 			if this_htlc.0.transaction_output_index.is_some() {
-				let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, holder_commitment_tx.feerate_per_kw, holder_csv, &this_htlc.0, &holder_commitment_tx.keys.broadcaster_delayed_payment_key, &holder_commitment_tx.keys.revocation_key);
+				let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, holder_commitment_tx.feerate_per_kw, holder_csv, &this_htlc.0, holder_commitment_tx.opt_anchors, &holder_commitment_tx.keys.broadcaster_delayed_payment_key, &holder_commitment_tx.keys.revocation_key);
 
-				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&this_htlc.0, &holder_commitment_tx.keys);
+				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&this_htlc.0, holder_commitment_tx.opt_anchors, &holder_commitment_tx.keys);
 
-				let sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, this_htlc.0.amount_msat / 1000, SigHashType::All)[..]);
+				let sighashtype = chan_utils::htlc_counterparty_sighash_type(holder_commitment_tx.opt_anchors);
+				let sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, this_htlc.0.amount_msat / 1000, sighashtype)[..]);
 				secp_ctx.verify(&sighash, this_htlc.1.as_ref().unwrap(), &holder_commitment_tx.keys.countersignatory_htlc_key).unwrap();
 			}
 		}
@@ -132,8 +133,20 @@ impl ChannelKeys for EnforcingChannelKeys {
 		self.inner.sign_channel_announcement(msg, secp_ctx)
 	}
 
-	fn on_accept(&mut self, channel_pubkeys: &ChannelPublicKeys, counterparty_selected_delay: u16, holder_selected_delay: u16) {
-		self.inner.on_accept(channel_pubkeys, counterparty_selected_delay, holder_selected_delay)
+	fn sign_holder_anchor_input<T: secp256k1::Signing>(&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		self.inner.sign_holder_anchor_input(anchor_tx, input, secp_ctx)
+	}
+
+	fn sign_splice_shared_input<T: secp256k1::Signing>(&self, splice_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		self.inner.sign_splice_shared_input(splice_tx, input, secp_ctx)
+	}
+
+	fn on_accept(&mut self, channel_pubkeys: &ChannelPublicKeys, counterparty_selected_delay: u16, holder_selected_delay: u16, opt_anchors: bool) {
+		self.inner.on_accept(channel_pubkeys, counterparty_selected_delay, holder_selected_delay, opt_anchors)
+	}
+
+	fn on_splice(&mut self, channel_value_satoshis: u64) {
+		self.inner.on_splice(channel_value_satoshis)
 	}
 }
 
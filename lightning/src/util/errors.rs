@@ -9,6 +9,8 @@
 
 //! Error types live here.
 
+use bitcoin::blockdata::script::Script;
+
 use std::fmt;
 
 /// Indicates an error on the client's part (usually some variant of attempting to use too-low or
@@ -45,6 +47,12 @@ pub enum APIError {
 	/// An attempt to call add/update_monitor returned an Err (ie you did this!), causing the
 	/// attempted action to fail.
 	MonitorUpdateFailed,
+	/// The given shutdown script is either not of a standard form accepted in BOLT 2 shutdown
+	/// messages, or is a segwit v1+ script which our counterparty has not indicated support for.
+	IncompatibleShutdownScript {
+		/// The script which was rejected.
+		script: Script
+	},
 }
 
 impl fmt::Debug for APIError {
@@ -55,6 +63,7 @@ impl fmt::Debug for APIError {
 			APIError::RouteError {ref err} => f.write_str(err),
 			APIError::ChannelUnavailable {ref err} => f.write_str(err),
 			APIError::MonitorUpdateFailed => f.write_str("Client indicated a channel monitor update failed"),
+			APIError::IncompatibleShutdownScript {ref script} => write!(f, "Provided a scriptpubkey format not accepted by peer: {}", script),
 		}
 	}
 }
@@ -21,6 +21,7 @@ use chain::keysinterface::SpendableOutputDescriptor;
 use util::ser::{Writeable, Writer, MaybeReadable, Readable};
 
 use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
 
 use bitcoin::secp256k1::key::PublicKey;
 
@@ -82,6 +83,10 @@ pub enum Event {
 		/// Note that, in any case, this value must be passed as-is to any fail or claim calls as
 		/// the HTLC index includes this value.
 		payment_secret: Option<PaymentSecret>,
+		/// For spontaneous (keysend) payments, the preimage the sender included in the onion. It
+		/// has already been checked against payment_hash and should be passed to claim_funds.
+		/// None for payments made against an invoice we provided.
+		payment_preimage: Option<PaymentPreimage>,
 		/// The value, in thousandths of a satoshi, that this payment is for. Note that you must
 		/// compare this to the expected value before accepting the payment (as otherwise you are
 		/// providing proof-of-payment for less than the value you expected!).
@@ -108,6 +113,10 @@ pub enum Event {
 		/// the payment has failed, not just the route in question. If this is not set, you may
 		/// retry the payment via a different route.
 		rejected_by_dest: bool,
+		/// The short_channel_id of the channel which caused the failure, if we could work it out.
+		/// When retrying, you should avoid routing through this channel (see
+		/// ln::payment_retry::PaymentRetrier, which does this for you).
+		short_channel_id: Option<u64>,
 #[cfg(test)]
 		error_code: Option<u16>,
 #[cfg(test)]
@@ -126,10 +135,201 @@ pub enum Event {
 	/// Such an output will *not* ever be spent by rust-lightning, and are not at risk of your
 	/// counterparty spending them due to some kind of timeout. Thus, you need to store them
 	/// somewhere and spend them when you create on-chain transactions.
+	///
+	/// If you use KeysManager, KeysManager::spend_spendable_outputs will build a signed
+	/// transaction sweeping any batch of them to a script of your choosing.
 	SpendableOutputs {
 		/// The outputs which you should store as spendable by you.
 		outputs: Vec<SpendableOutputDescriptor>,
 	},
+	/// Used to indicate that a holder commitment transaction of a channel using anchor outputs
+	/// (see ChannelHandshakeConfig::negotiate_anchor_outputs) has been broadcast but its feerate is
+	/// below what the FeeEstimator currently returns for ConfirmationTarget::HighPriority.
+	///
+	/// To get it confirmed, you should build a child transaction spending the given anchor output
+	/// (along with inputs of your own to pay for fees), such that the package of the commitment
+	/// transaction and the child reaches target_feerate_sat_per_1000_weight, and broadcast it. The
+	/// anchor input can be signed with ChannelKeys::sign_holder_anchor_input using the keys
+	/// derived from key_derivation_params, with the witness built by
+	/// chan_utils::build_anchor_input_witness.
+	///
+	/// This event will be generated again on new blocks if the estimated feerate keeps increasing
+	/// while the commitment transaction remains unconfirmed.
+	BumpCommitmentTransaction {
+		/// The holder commitment transaction which should be bumped.
+		commitment_tx: Transaction,
+		/// The fee the commitment transaction already pays, in satoshis.
+		commitment_tx_fee_satoshis: u64,
+		/// The feerate the commitment transaction and its child should reach together.
+		target_feerate_sat_per_1000_weight: u32,
+		/// Our anchor output on the commitment transaction.
+		anchor_outpoint: OutPoint,
+		/// The witnessScript of our anchor output.
+		anchor_witness_script: Script,
+		/// The channel keys state used to proceed to derivation of signing key. Must
+		/// be pass to KeysInterface::derive_channel_keys.
+		key_derivation_params: (u64, u64),
+	},
+	/// Used to indicate that the splice transaction we negotiated with our counterparty after a
+	/// call to ChannelManager::splice_channel has been constructed and the inputs we contributed to
+	/// it must now be signed. The client should add witnesses for those inputs, leaving all others
+	/// untouched, and pass the result to ChannelManager::splice_transaction_signed.
+	/// Generated in ChannelManager message handling.
+	SpliceTransactionReadyForSigning {
+		/// The channel_id of the channel being spliced.
+		channel_id: [u8; 32],
+		/// The value passed in to ChannelManager::create_channel, or the user_id the channel was
+		/// accepted with.
+		user_channel_id: u64,
+		/// The splice transaction, without any witnesses.
+		unsigned_transaction: Transaction,
+	},
+	/// Indicates a peer has asked to open a dual-funded channel with us. The client should call
+	/// ChannelManager::accept_dual_funded_channel with the amount (if any) it wishes to contribute
+	/// to the channel, or ChannelManager::reject_dual_funded_channel to refuse it.
+	/// Generated in ChannelManager message handling.
+	OpenChannelRequest {
+		/// The temporary channel_id to pass to ChannelManager::accept_dual_funded_channel or
+		/// ChannelManager::reject_dual_funded_channel.
+		temporary_channel_id: [u8; 32],
+		/// The node_id of the peer which wishes to open the channel.
+		counterparty_node_id: PublicKey,
+		/// The amount, in satoshis, the peer is contributing to the channel.
+		counterparty_funding_satoshis: u64,
+		/// The feerate for the funding transaction, which we have to pay for any inputs and
+		/// outputs we contribute.
+		funding_feerate_per_kw: u32,
+	},
+	/// Used to indicate that the funding transaction of a dual-funded channel has been constructed
+	/// and the inputs we contributed to it must now be signed. The client should add witnesses for
+	/// those inputs, leaving all others untouched, and pass the result to
+	/// ChannelManager::funding_transaction_signed.
+	/// Generated in ChannelManager message handling.
+	FundingTransactionReadyForSigning {
+		/// The channel_id of the channel being opened.
+		channel_id: [u8; 32],
+		/// The value passed in to ChannelManager::create_dual_funded_channel or
+		/// ChannelManager::accept_dual_funded_channel.
+		user_channel_id: u64,
+		/// The funding transaction, without any witnesses.
+		unsigned_transaction: Transaction,
+	},
+	/// Used to indicate that a channel was closed before its funding transaction could have been
+	/// broadcast, ie after the funding transaction was passed to
+	/// ChannelManager::funding_transaction_generated but before FundingBroadcastSafe was generated,
+	/// or, for a dual-funded channel, after the funding transaction was constructed but before we
+	/// sent our signatures for it. The funding transaction will never be used, so any UTXOs reserved
+	/// for it may be spent elsewhere.
+	/// Generated in ChannelManager whenever such a channel is closed.
+	DiscardFunding {
+		/// The channel_id of the channel which was closed.
+		channel_id: [u8; 32],
+		/// The value passed in to ChannelManager::create_channel, or the user_id the channel was
+		/// created or accepted with for dual-funded channels.
+		user_channel_id: u64,
+		/// The funding output of the funding transaction which should be discarded.
+		funding_txo: OutPoint,
+	},
+	/// Used to indicate that a channel was closed, for whatever reason. No further action is
+	/// required, though any funds in the channel will be claimed on-chain by our ChannelMonitor and
+	/// may later be handed to you via SpendableOutputs.
+	/// Generated in ChannelManager whenever a channel is removed.
+	ChannelClosed {
+		/// The channel_id of the channel which was closed.
+		channel_id: [u8; 32],
+		/// The value passed in to ChannelManager::create_channel, or the user_id the channel was
+		/// created or accepted with for dual-funded channels. Inbound channels opened with
+		/// open_channel always have a user_channel_id of 0.
+		user_channel_id: u64,
+		/// Why the channel was closed.
+		reason: ClosureReason,
+	},
+	/// Indicates that an HTLC we forwarded was claimed by the next hop, and that we have claimed
+	/// (or will claim, if the previous channel has since closed) the corresponding inbound HTLC,
+	/// earning a forwarding fee.
+	/// Note that duplicative PaymentForwarded Events may be generated in rare cases where the
+	/// preimage is learned both off-chain and on-chain.
+	PaymentForwarded {
+		/// The channel_id of the channel over which the HTLC was received, or None if the channel
+		/// has since closed and we no longer know it.
+		prev_channel_id: Option<[u8; 32]>,
+		/// The channel_id of the channel over which the HTLC was forwarded.
+		next_channel_id: [u8; 32],
+		/// The fee, in msat, we earned for forwarding the HTLC, ie the amount of the inbound HTLC
+		/// less the amount of the outbound HTLC. None if the previous channel has since closed or
+		/// the outbound HTLC was claimed on-chain in a way which didn't tell us its value.
+		fee_earned_msat: Option<u64>,
+		/// Whether the next hop claimed the outbound HTLC on-chain (in which case we learned the
+		/// preimage from the claiming transaction) rather than via an update_fulfill_htlc.
+		claim_from_onchain_tx: bool,
+	},
+}
+
+/// The reason a channel was closed, as given in Event::ChannelClosed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClosureReason {
+	/// We called ChannelManager::force_close_channel (or force_close_all_channels).
+	HolderForceClosed,
+	/// Our counterparty sent us an error message (or a tx_abort for a dual-funded channel which
+	/// had yet to be funded), closing the channel.
+	CounterpartyForceClosed {
+		/// The error message our counterparty sent us. Note that this is untrusted data and may
+		/// contain arbitrary (or no) text.
+		peer_msg: String,
+	},
+	/// We failed to handle a message or event for the channel (eg our counterparty violated the
+	/// protocol or a ChannelMonitor update failed permanently), closing it.
+	ProcessingError {
+		/// A description of the error.
+		err: String,
+	},
+	/// The channel was closed cooperatively, either via a mutual closing transaction or, if it
+	/// had yet to be funded, by simply forgetting about it.
+	CooperativeClosure,
+	/// A transaction spending the funding output, almost certainly a commitment transaction, was
+	/// confirmed on-chain, closing the channel.
+	CommitmentTxConfirmed,
+	/// The funding transaction of an inbound channel failed to confirm within 2016 blocks (about
+	/// two weeks) of our receiving funding_created, so we gave up on it.
+	FundingTimedOut,
+	/// Our counterparty disconnected before the channel was funded, or we can no longer connect
+	/// to them at all.
+	DisconnectedPeer,
+}
+
+impl Writeable for ClosureReason {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		match self {
+			&ClosureReason::HolderForceClosed => 0u8.write(writer)?,
+			&ClosureReason::CounterpartyForceClosed { ref peer_msg } => {
+				1u8.write(writer)?;
+				peer_msg.write(writer)?;
+			},
+			&ClosureReason::ProcessingError { ref err } => {
+				2u8.write(writer)?;
+				err.write(writer)?;
+			},
+			&ClosureReason::CooperativeClosure => 3u8.write(writer)?,
+			&ClosureReason::CommitmentTxConfirmed => 4u8.write(writer)?,
+			&ClosureReason::FundingTimedOut => 5u8.write(writer)?,
+			&ClosureReason::DisconnectedPeer => 6u8.write(writer)?,
+		}
+		Ok(())
+	}
+}
+impl Readable for ClosureReason {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, msgs::DecodeError> {
+		match <u8 as Readable>::read(reader)? {
+			0 => Ok(ClosureReason::HolderForceClosed),
+			1 => Ok(ClosureReason::CounterpartyForceClosed { peer_msg: Readable::read(reader)? }),
+			2 => Ok(ClosureReason::ProcessingError { err: Readable::read(reader)? }),
+			3 => Ok(ClosureReason::CooperativeClosure),
+			4 => Ok(ClosureReason::CommitmentTxConfirmed),
+			5 => Ok(ClosureReason::FundingTimedOut),
+			6 => Ok(ClosureReason::DisconnectedPeer),
+			_ => Err(msgs::DecodeError::InvalidValue),
+		}
+	}
 }
 
 impl Writeable for Event {
@@ -145,25 +345,28 @@ impl Writeable for Event {
 				funding_txo.write(writer)?;
 				user_channel_id.write(writer)?;
 			},
-			&Event::PaymentReceived { ref payment_hash, ref payment_secret, ref amt } => {
+			&Event::PaymentReceived { ref payment_hash, ref payment_secret, ref payment_preimage, ref amt } => {
 				2u8.write(writer)?;
 				payment_hash.write(writer)?;
 				payment_secret.write(writer)?;
+				payment_preimage.write(writer)?;
 				amt.write(writer)?;
 			},
 			&Event::PaymentSent { ref payment_preimage } => {
 				3u8.write(writer)?;
 				payment_preimage.write(writer)?;
 			},
-			&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest,
+			&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, ref short_channel_id,
 				#[cfg(test)]
 				ref error_code,
 				#[cfg(test)]
 				ref error_data,
 			} => {
-				4u8.write(writer)?;
+				// PaymentFailed events written before short_channel_id was added use type 4.
+				14u8.write(writer)?;
 				payment_hash.write(writer)?;
 				rejected_by_dest.write(writer)?;
+				short_channel_id.write(writer)?;
 				#[cfg(test)]
 				error_code.write(writer)?;
 				#[cfg(test)]
@@ -181,6 +384,51 @@ impl Writeable for Event {
 					output.write(writer)?;
 				}
 			},
+			&Event::BumpCommitmentTransaction { ref commitment_tx, ref commitment_tx_fee_satoshis, ref target_feerate_sat_per_1000_weight, ref anchor_outpoint, ref anchor_witness_script, ref key_derivation_params } => {
+				7u8.write(writer)?;
+				commitment_tx.write(writer)?;
+				commitment_tx_fee_satoshis.write(writer)?;
+				target_feerate_sat_per_1000_weight.write(writer)?;
+				anchor_outpoint.write(writer)?;
+				anchor_witness_script.write(writer)?;
+				key_derivation_params.0.write(writer)?;
+				key_derivation_params.1.write(writer)?;
+			},
+			&Event::SpliceTransactionReadyForSigning { .. } => {
+				8u8.write(writer)?;
+				// We never write out SpliceTransactionReadyForSigning events as, upon
+				// disconnection, any splice which we've yet to send tx_signatures for is aborted.
+			},
+			&Event::OpenChannelRequest { .. } => {
+				9u8.write(writer)?;
+				// We never write out OpenChannelRequest events as, upon disconnection, peers drop
+				// any channels which have not yet exchanged commitment_signed.
+			},
+			&Event::FundingTransactionReadyForSigning { .. } => {
+				10u8.write(writer)?;
+				// We never write out FundingTransactionReadyForSigning events as we don't persist
+				// the interactive transaction construction state needed to sign the funding
+				// transaction across restarts.
+			},
+			&Event::DiscardFunding { ref channel_id, ref user_channel_id, ref funding_txo } => {
+				11u8.write(writer)?;
+				channel_id.write(writer)?;
+				user_channel_id.write(writer)?;
+				funding_txo.write(writer)?;
+			},
+			&Event::ChannelClosed { ref channel_id, ref user_channel_id, ref reason } => {
+				12u8.write(writer)?;
+				channel_id.write(writer)?;
+				user_channel_id.write(writer)?;
+				reason.write(writer)?;
+			},
+			&Event::PaymentForwarded { ref prev_channel_id, ref next_channel_id, ref fee_earned_msat, ref claim_from_onchain_tx } => {
+				13u8.write(writer)?;
+				prev_channel_id.write(writer)?;
+				next_channel_id.write(writer)?;
+				fee_earned_msat.write(writer)?;
+				claim_from_onchain_tx.write(writer)?;
+			},
 		}
 		Ok(())
 	}
@@ -196,6 +444,7 @@ impl MaybeReadable for Event {
 			2u8 => Ok(Some(Event::PaymentReceived {
 					payment_hash: Readable::read(reader)?,
 					payment_secret: Readable::read(reader)?,
+					payment_preimage: Readable::read(reader)?,
 					amt: Readable::read(reader)?,
 				})),
 			3u8 => Ok(Some(Event::PaymentSent {
@@ -204,6 +453,7 @@ impl MaybeReadable for Event {
 			4u8 => Ok(Some(Event::PaymentFailed {
 					payment_hash: Readable::read(reader)?,
 					rejected_by_dest: Readable::read(reader)?,
+					short_channel_id: None,
 					#[cfg(test)]
 					error_code: Readable::read(reader)?,
 					#[cfg(test)]
@@ -220,6 +470,42 @@ impl MaybeReadable for Event {
 				}
 				Ok(Some(Event::SpendableOutputs { outputs }))
 			},
+			7u8 => Ok(Some(Event::BumpCommitmentTransaction {
+					commitment_tx: Readable::read(reader)?,
+					commitment_tx_fee_satoshis: Readable::read(reader)?,
+					target_feerate_sat_per_1000_weight: Readable::read(reader)?,
+					anchor_outpoint: Readable::read(reader)?,
+					anchor_witness_script: Readable::read(reader)?,
+					key_derivation_params: (Readable::read(reader)?, Readable::read(reader)?),
+				})),
+			8u8 => Ok(None),
+			9u8 => Ok(None),
+			10u8 => Ok(None),
+			11u8 => Ok(Some(Event::DiscardFunding {
+					channel_id: Readable::read(reader)?,
+					user_channel_id: Readable::read(reader)?,
+					funding_txo: Readable::read(reader)?,
+				})),
+			12u8 => Ok(Some(Event::ChannelClosed {
+					channel_id: Readable::read(reader)?,
+					user_channel_id: Readable::read(reader)?,
+					reason: Readable::read(reader)?,
+				})),
+			13u8 => Ok(Some(Event::PaymentForwarded {
+					prev_channel_id: Readable::read(reader)?,
+					next_channel_id: Readable::read(reader)?,
+					fee_earned_msat: Readable::read(reader)?,
+					claim_from_onchain_tx: Readable::read(reader)?,
+				})),
+			14u8 => Ok(Some(Event::PaymentFailed {
+					payment_hash: Readable::read(reader)?,
+					rejected_by_dest: Readable::read(reader)?,
+					short_channel_id: Readable::read(reader)?,
+					#[cfg(test)]
+					error_code: Readable::read(reader)?,
+					#[cfg(test)]
+					error_data: Readable::read(reader)?,
+				})),
 			_ => Err(msgs::DecodeError::InvalidValue)
 		}
 	}
@@ -246,6 +532,22 @@ pub enum MessageSendEvent {
 		/// The message which should be sent.
 		msg: msgs::OpenChannel,
 	},
+	/// Used to indicate that we've accepted a dual-funded channel open and should send the
+	/// accept_channel2 message provided to the given peer.
+	SendAcceptChannelV2 {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::AcceptChannelV2,
+	},
+	/// Used to indicate that we've initiated a dual-funded channel open and should send the
+	/// open_channel2 message provided to the given peer.
+	SendOpenChannelV2 {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::OpenChannelV2,
+	},
 	/// Used to indicate that a funding_created message should be sent to the peer with the given node_id.
 	SendFundingCreated {
 		/// The node_id of the node which should receive this message
@@ -296,6 +598,83 @@ pub enum MessageSendEvent {
 		/// The message which should be sent.
 		msg: msgs::ClosingSigned,
 	},
+	/// Used to indicate that a stfu message should be sent to the peer with the given node_id.
+	SendStfu {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::Stfu,
+	},
+	/// Used to indicate that a splice_init message should be sent to the peer with the given node_id.
+	SendSpliceInit {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::SpliceInit,
+	},
+	/// Used to indicate that a splice_ack message should be sent to the peer with the given node_id.
+	SendSpliceAck {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::SpliceAck,
+	},
+	/// Used to indicate that a splice_locked message should be sent to the peer with the given node_id.
+	SendSpliceLocked {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::SpliceLocked,
+	},
+	/// Used to indicate that a tx_add_input message should be sent to the peer with the given node_id.
+	SendTxAddInput {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAddInput,
+	},
+	/// Used to indicate that a tx_add_output message should be sent to the peer with the given node_id.
+	SendTxAddOutput {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAddOutput,
+	},
+	/// Used to indicate that a tx_remove_input message should be sent to the peer with the given node_id.
+	SendTxRemoveInput {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxRemoveInput,
+	},
+	/// Used to indicate that a tx_remove_output message should be sent to the peer with the given node_id.
+	SendTxRemoveOutput {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxRemoveOutput,
+	},
+	/// Used to indicate that a tx_complete message should be sent to the peer with the given node_id.
+	SendTxComplete {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxComplete,
+	},
+	/// Used to indicate that a tx_signatures message should be sent to the peer with the given node_id.
+	SendTxSignatures {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxSignatures,
+	},
+	/// Used to indicate that a tx_abort message should be sent to the peer with the given node_id.
+	SendTxAbort {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAbort,
+	},
 	/// Used to indicate that a shutdown message should be sent to the peer with the given node_id.
 	SendShutdown {
 		/// The node_id of the node which should receive this message
@@ -334,6 +713,70 @@ pub enum MessageSendEvent {
 		/// The channel_update which should be sent.
 		msg: msgs::ChannelUpdate,
 	},
+	/// Used to indicate that a channel_announcement should be sent to a single peer, generally in
+	/// response to a gossip query.
+	SendChannelAnnouncement {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The channel_announcement which should be sent.
+		msg: msgs::ChannelAnnouncement,
+	},
+	/// Used to indicate that a node_announcement should be sent to a single peer, generally in
+	/// response to a gossip query.
+	SendNodeAnnouncement {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The node_announcement which should be sent.
+		msg: msgs::NodeAnnouncement,
+	},
+	/// Used to indicate that a channel_update should be sent to a single peer, generally in
+	/// response to a gossip query.
+	SendChannelUpdate {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The channel_update which should be sent.
+		msg: msgs::ChannelUpdate,
+	},
+	/// Used to indicate that a reply_channel_range message should be sent to the peer with the
+	/// given node_id, in response to a query_channel_range message.
+	SendReplyChannelRange {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The reply_channel_range which should be sent.
+		msg: msgs::ReplyChannelRange,
+	},
+	/// Used to indicate that a reply_short_channel_ids_end message should be sent to the peer with
+	/// the given node_id, after all gossip replying to a query_short_channel_ids has been sent.
+	SendReplyShortChannelIdsEnd {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The reply_short_channel_ids_end which should be sent.
+		msg: msgs::ReplyShortChannelIdsEnd,
+	},
+	/// Used to indicate that a query_channel_range message should be sent to the peer with the
+	/// given node_id, to learn which channels it knows about.
+	SendChannelRangeQuery {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The query_channel_range which should be sent.
+		msg: msgs::QueryChannelRange,
+	},
+	/// Used to indicate that a query_short_channel_ids message should be sent to the peer with the
+	/// given node_id, to request the gossip for channels we don't yet know about.
+	SendShortIdsQuery {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The query_short_channel_ids which should be sent.
+		msg: msgs::QueryShortChannelIds,
+	},
+	/// Used to indicate that a gossip_timestamp_filter message should be sent to the peer with the
+	/// given node_id, asking it to forward us gossip in the given timestamp range.
+	SendGossipTimestampFilter {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The gossip_timestamp_filter which should be sent.
+		msg: msgs::GossipTimestampFilter,
+	},
 	/// Broadcast an error downstream to be handled
 	HandleError {
 		/// The node_id of the node which should receive this message
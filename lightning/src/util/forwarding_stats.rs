@@ -0,0 +1,114 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A ForwardingStats accumulates the Event::PaymentForwarded events generated by a ChannelManager
+//! into per-channel totals, letting routing nodes see how much each of their channels has earned.
+
+use ln::msgs::DecodeError;
+use util::events::Event;
+use util::ser::{Readable, Writeable, Writer};
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io::Read;
+
+/// The forwarding totals for a single channel.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChannelForwardingStats {
+	/// The number of forwarded HTLCs we received over this channel.
+	pub inbound_forwards: u64,
+	/// The number of forwarded HTLCs we sent out over this channel.
+	pub outbound_forwards: u64,
+	/// The number of forwarded HTLCs we sent out over this channel which the next hop claimed
+	/// on-chain.
+	pub outbound_onchain_claims: u64,
+	/// The total fees, in msat, we earned forwarding HTLCs out over this channel. Forwards for
+	/// which we couldn't work out the fee earned are counted in outbound_forwards but not here.
+	pub fee_earned_msat: u64,
+}
+impl_writeable!(ChannelForwardingStats, 8*4, {
+	inbound_forwards,
+	outbound_forwards,
+	outbound_onchain_claims,
+	fee_earned_msat
+});
+
+/// Accumulates Event::PaymentForwarded events into ChannelForwardingStats for each channel.
+///
+/// Fees are attributed to the channel we forwarded the HTLC out over, as that is the channel
+/// whose liquidity we were paid for. Every Event::PaymentForwarded should be passed to
+/// process_event (other events are ignored), and the ForwardingStats persisted alongside your
+/// ChannelManager if you want the totals to survive restarts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ForwardingStats {
+	channels: HashMap<[u8; 32], ChannelForwardingStats>,
+}
+
+impl ForwardingStats {
+	/// Constructs a new, empty, ForwardingStats.
+	pub fn new() -> Self {
+		Self { channels: HashMap::new() }
+	}
+
+	/// Adds the given event to our totals if it is an Event::PaymentForwarded.
+	pub fn process_event(&mut self, event: &Event) {
+		if let &Event::PaymentForwarded { ref prev_channel_id, ref next_channel_id, fee_earned_msat, claim_from_onchain_tx } = event {
+			if let Some(prev_channel_id) = prev_channel_id {
+				let prev_stats = self.channels.entry(*prev_channel_id).or_insert_with(Default::default);
+				prev_stats.inbound_forwards = prev_stats.inbound_forwards.saturating_add(1);
+			}
+			let next_stats = self.channels.entry(*next_channel_id).or_insert_with(Default::default);
+			next_stats.outbound_forwards = next_stats.outbound_forwards.saturating_add(1);
+			if claim_from_onchain_tx {
+				next_stats.outbound_onchain_claims = next_stats.outbound_onchain_claims.saturating_add(1);
+			}
+			next_stats.fee_earned_msat = next_stats.fee_earned_msat.saturating_add(fee_earned_msat.unwrap_or(0));
+		}
+	}
+
+	/// Gets the totals for the given channel, if we've seen any forwards over it.
+	pub fn channel_stats(&self, channel_id: &[u8; 32]) -> Option<&ChannelForwardingStats> {
+		self.channels.get(channel_id)
+	}
+
+	/// Gets the total fees, in msat, we've earned forwarding HTLCs over all channels.
+	pub fn total_fee_earned_msat(&self) -> u64 {
+		self.channels.values().fold(0, |total, stats| total.saturating_add(stats.fee_earned_msat))
+	}
+
+	/// Forgets the totals for the given channel, eg once it has been closed, returning them.
+	pub fn remove_channel(&mut self, channel_id: &[u8; 32]) -> Option<ChannelForwardingStats> {
+		self.channels.remove(channel_id)
+	}
+}
+
+impl Writeable for ForwardingStats {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		(self.channels.len() as u64).write(writer)?;
+		for (channel_id, stats) in self.channels.iter() {
+			channel_id.write(writer)?;
+			stats.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for ForwardingStats {
+	fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		let channel_count: u64 = Readable::read(reader)?;
+		let mut channels = HashMap::with_capacity(cmp::min(channel_count as usize, 128));
+		for _ in 0..channel_count {
+			let channel_id: [u8; 32] = Readable::read(reader)?;
+			if channels.insert(channel_id, Readable::read(reader)?).is_some() {
+				return Err(DecodeError::InvalidValue);
+			}
+		}
+		Ok(Self { channels })
+	}
+}
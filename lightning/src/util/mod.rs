@@ -31,6 +31,8 @@ pub(crate) mod macro_logger;
 // These have to come after macro_logger to build
 pub mod logger;
 pub mod config;
+pub mod sweep;
+pub mod forwarding_stats;
 
 #[cfg(test)]
 pub(crate) mod test_utils;
@@ -375,6 +375,20 @@ impl Readable for u8 {
 	}
 }
 
+impl Writeable for i64 {
+	#[inline]
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		(*self as u64).write(writer)
+	}
+}
+impl Readable for i64 {
+	#[inline]
+	fn read<R: Read>(reader: &mut R) -> Result<i64, DecodeError> {
+		let v: u64 = Readable::read(reader)?;
+		Ok(v as i64)
+	}
+}
+
 impl Writeable for bool {
 	#[inline]
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
@@ -476,6 +490,28 @@ impl Readable for Vec<u8> {
 		Ok(ret)
 	}
 }
+impl Writeable for String {
+	/// Strings longer than the u16 length prefix allows are truncated to the longest prefix which
+	/// fits and ends on a char boundary.
+	#[inline]
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		let mut len = ::std::cmp::min(self.len(), ::std::u16::MAX as usize);
+		while !self.is_char_boundary(len) {
+			len -= 1;
+		}
+		(len as u16).write(w)?;
+		w.write_all(self[..len].as_bytes())
+	}
+}
+
+impl Readable for String {
+	#[inline]
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let v: Vec<u8> = Readable::read(r)?;
+		String::from_utf8(v).map_err(|_| DecodeError::InvalidValue)
+	}
+}
+
 impl Writeable for Vec<Signature> {
 	#[inline]
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
@@ -752,3 +788,24 @@ impl<A: Writeable, B: Writeable> Writeable for (A, B) {
 		self.1.write(w)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use util::ser::{Readable, Writeable};
+
+	#[test]
+	fn test_oversized_string_round_trip() {
+		// A two-byte char straddles the u16::MAX boundary, so it must be dropped entirely.
+		let mut s = "a".repeat(::std::u16::MAX as usize - 1);
+		s.push('é');
+		s.push_str("trailing");
+		let encoded = s.encode();
+		assert_eq!(encoded.len(), 2 + ::std::u16::MAX as usize - 1);
+		let decoded: String = Readable::read(&mut ::std::io::Cursor::new(&encoded)).unwrap();
+		assert_eq!(decoded, s[..::std::u16::MAX as usize - 1]);
+
+		let s = "short string".to_owned();
+		let decoded: String = Readable::read(&mut ::std::io::Cursor::new(&s.encode())).unwrap();
+		assert_eq!(decoded, s);
+	}
+}
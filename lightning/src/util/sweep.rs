@@ -0,0 +1,309 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! An OutputSweeper tracks the outputs handed to the user in Event::SpendableOutputs and sweeps
+//! them to a script of the user's choosing once they can be spent, taking care of rebroadcasting
+//! and fee-bumping the sweep transaction until it is buried deep enough to be forgotten.
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
+
+use chain::chaininterface::{BroadcasterInterface, ChainListener, ChainWatchInterface, ConfirmationTarget, FeeEstimator};
+use chain::keysinterface::{OutputSpender, SpendableOutputDescriptor};
+use ln::channelmonitor::ANTI_REORG_DELAY;
+use ln::msgs::DecodeError;
+use util::logger::Logger;
+use util::ser::{Readable, ReadableArgs, Writeable, Writer};
+
+use std::cmp;
+use std::io::Read;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+/// The number of blocks a sweep transaction may remain unconfirmed before we bump its feerate.
+const SWEEP_BUMP_BLOCKS: u32 = 3;
+
+struct TrackedOutput {
+	descriptor: SpendableOutputDescriptor,
+	/// The best block height when we started tracking the output.
+	added_height: u32,
+	/// The height of the block containing a transaction spending the output, if any.
+	spend_height: Option<u32>,
+}
+
+impl TrackedOutput {
+	fn is_mature(&self, best_height: u32) -> bool {
+		match self.descriptor {
+			SpendableOutputDescriptor::DynamicOutputP2WSH { to_self_delay, .. } => {
+				// SpendableOutputs events are only generated once the transaction holding the output
+				// has ANTI_REORG_DELAY confirmations, so it confirmed no later than
+				// ANTI_REORG_DELAY - 1 blocks before we were handed the output. Our sweep may be
+				// included in the block which gives it to_self_delay confirmations.
+				let confirmation_height = self.added_height.saturating_sub(ANTI_REORG_DELAY - 1);
+				best_height + 1 >= confirmation_height + to_self_delay as u32
+			},
+			_ => true,
+		}
+	}
+}
+
+struct PendingSweep {
+	tx: Transaction,
+	feerate_sat_per_1000_weight: u32,
+	/// The best block height when the transaction was (first) broadcast.
+	broadcast_height: u32,
+}
+
+struct SweeperState {
+	best_height: u32,
+	outputs: Vec<TrackedOutput>,
+	pending_sweep: Option<PendingSweep>,
+}
+
+/// Tracks the outputs given to us in Event::SpendableOutputs and sweeps them to a destination
+/// script once they are spendable.
+///
+/// The outputs of each Event::SpendableOutputs should be passed to track_spendable_outputs. All
+/// mature outputs are then spent, at the FeeEstimator's ConfirmationTarget::Normal feerate, in a
+/// single transaction which is rebroadcast on each new block and has its feerate bumped if it
+/// doesn't confirm within a few blocks. Outputs are forgotten once the transaction spending them
+/// has ANTI_REORG_DELAY confirmations.
+///
+/// The OutputSweeper must be registered as a ChainListener with a BlockNotifier using the same
+/// ChainWatchInterface as is passed here, as it relies on it to be told about transactions
+/// spending the outputs it tracks. Its state should be persisted (via Writeable) after each call
+/// to track_spendable_outputs, as the outputs will not be handed out again.
+pub struct OutputSweeper<B: Deref, F: Deref, K: Deref, C: Deref, L: Deref>
+	where B::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      K::Target: OutputSpender,
+	      C::Target: ChainWatchInterface,
+	      L::Target: Logger,
+{
+	destination_script: Script,
+	state: Mutex<SweeperState>,
+	broadcaster: B,
+	fee_estimator: F,
+	output_spender: K,
+	chain_watch: C,
+	logger: L,
+}
+
+impl<B: Deref, F: Deref, K: Deref, C: Deref, L: Deref> OutputSweeper<B, F, K, C, L>
+	where B::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      K::Target: OutputSpender,
+	      C::Target: ChainWatchInterface,
+	      L::Target: Logger,
+{
+	/// Creates a new OutputSweeper tracking no outputs, which will sweep to destination_script.
+	/// best_height must be the height of the latest block connected to the BlockNotifier.
+	pub fn new(destination_script: Script, best_height: u32, broadcaster: B, fee_estimator: F, output_spender: K, chain_watch: C, logger: L) -> Self {
+		Self {
+			destination_script,
+			state: Mutex::new(SweeperState { best_height, outputs: Vec::new(), pending_sweep: None }),
+			broadcaster,
+			fee_estimator,
+			output_spender,
+			chain_watch,
+			logger,
+		}
+	}
+
+	/// Starts tracking the given outputs, as provided in Event::SpendableOutputs, sweeping any
+	/// which are already spendable.
+	///
+	/// Outputs which are already tracked are ignored, as are outputs which can't be spent even
+	/// without paying any fee (eg because they are dust or weren't derived from our
+	/// OutputSpender's keys).
+	pub fn track_spendable_outputs(&self, outputs: Vec<SpendableOutputDescriptor>) {
+		let mut state = self.state.lock().unwrap();
+		for descriptor in outputs {
+			if state.outputs.iter().any(|output| output.descriptor.outpoint() == descriptor.outpoint()) { continue; }
+			if self.output_spender.spend_spendable_outputs(&[descriptor.clone()], self.destination_script.clone(), 0).is_err() {
+				log_error!(self.logger, "Unable to spend output {}:{}, not tracking it", descriptor.outpoint().txid, descriptor.outpoint().index);
+				continue;
+			}
+			self.watch_output(&descriptor);
+			let added_height = state.best_height;
+			state.outputs.push(TrackedOutput { descriptor, added_height, spend_height: None });
+		}
+		self.sweep(&mut state);
+	}
+
+	/// Gets the outputs which are currently being tracked, ie which haven't been spent by a
+	/// transaction with ANTI_REORG_DELAY confirmations yet.
+	pub fn tracked_outputs(&self) -> Vec<SpendableOutputDescriptor> {
+		self.state.lock().unwrap().outputs.iter().map(|output| output.descriptor.clone()).collect()
+	}
+
+	fn watch_output(&self, descriptor: &SpendableOutputDescriptor) {
+		let (outpoint, script_pubkey) = match descriptor {
+			&SpendableOutputDescriptor::StaticOutput { ref outpoint, ref output } => (outpoint, &output.script_pubkey),
+			&SpendableOutputDescriptor::DynamicOutputP2WSH { ref outpoint, ref output, .. } => (outpoint, &output.script_pubkey),
+			&SpendableOutputDescriptor::StaticOutputCounterpartyPayment { ref outpoint, ref output, .. } => (outpoint, &output.script_pubkey),
+		};
+		self.chain_watch.install_watch_outpoint((outpoint.txid, outpoint.index as u32), script_pubkey);
+	}
+
+	/// (Re)broadcasts a transaction spending all mature, unspent outputs, replacing the pending
+	/// sweep at a higher feerate if its inputs changed or it has been stuck for too long.
+	fn sweep(&self, state: &mut SweeperState) {
+		let best_height = state.best_height;
+		let descriptors: Vec<SpendableOutputDescriptor> = state.outputs.iter()
+			.filter(|output| output.spend_height.is_none() && output.is_mature(best_height))
+			.map(|output| output.descriptor.clone()).collect();
+		if descriptors.is_empty() {
+			// Keep the pending sweep (and its feerate) around while its spends may be reorged out,
+			// as any new sweep of the same outputs has to replace it.
+			if state.outputs.is_empty() { state.pending_sweep = None; }
+			return;
+		}
+
+		let estimated_feerate = self.fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+		let feerate_sat_per_1000_weight = match state.pending_sweep {
+			Some(ref pending_sweep) => {
+				let inputs_changed = pending_sweep.tx.input.len() != descriptors.len() || descriptors.iter().any(|descriptor| {
+					let outpoint = descriptor.outpoint().into_bitcoin_outpoint();
+					!pending_sweep.tx.input.iter().any(|input| input.previous_output == outpoint)
+				});
+				if !inputs_changed && best_height < pending_sweep.broadcast_height + SWEEP_BUMP_BLOCKS {
+					log_trace!(self.logger, "Rebroadcasting sweep transaction {}", pending_sweep.tx.txid());
+					self.broadcaster.broadcast_transaction(&pending_sweep.tx);
+					return;
+				}
+				// The replacement must pay a higher feerate than the transaction it replaces, and at
+				// least 1 sat/vbyte more to relay.
+				let old_feerate = pending_sweep.feerate_sat_per_1000_weight;
+				cmp::max(estimated_feerate, cmp::max(old_feerate * 5 / 4, old_feerate + 250))
+			},
+			None => estimated_feerate,
+		};
+
+		match self.output_spender.spend_spendable_outputs(&descriptors, self.destination_script.clone(), feerate_sat_per_1000_weight) {
+			Ok(tx) => {
+				log_info!(self.logger, "Broadcasting sweep transaction {} spending {} outputs at {} sat per 1000 weight", tx.txid(), descriptors.len(), feerate_sat_per_1000_weight);
+				self.broadcaster.broadcast_transaction(&tx);
+				state.pending_sweep = Some(PendingSweep { tx, feerate_sat_per_1000_weight, broadcast_height: best_height });
+			},
+			Err(()) => {
+				log_warn!(self.logger, "Unable to build a sweep transaction spending {} outputs at {} sat per 1000 weight", descriptors.len(), feerate_sat_per_1000_weight);
+				if let Some(ref pending_sweep) = state.pending_sweep {
+					self.broadcaster.broadcast_transaction(&pending_sweep.tx);
+				}
+			},
+		}
+	}
+}
+
+impl<B: Deref + Sync + Send, F: Deref + Sync + Send, K: Deref + Sync + Send, C: Deref + Sync + Send, L: Deref + Sync + Send> ChainListener for OutputSweeper<B, F, K, C, L>
+	where B::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      K::Target: OutputSpender,
+	      C::Target: ChainWatchInterface,
+	      L::Target: Logger,
+{
+	fn block_connected(&self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], _indexes_of_txn_matched: &[usize]) {
+		let mut state = self.state.lock().unwrap();
+		state.best_height = height;
+		for tx in txn_matched {
+			for input in tx.input.iter() {
+				for output in state.outputs.iter_mut() {
+					if output.spend_height.is_none() && output.descriptor.outpoint().into_bitcoin_outpoint() == input.previous_output {
+						log_info!(self.logger, "Output {} spent by transaction {} in block {} at height {}", input.previous_output, tx.txid(), header.block_hash(), height);
+						output.spend_height = Some(height);
+					}
+				}
+			}
+		}
+		state.outputs.retain(|output| match output.spend_height {
+			Some(spend_height) => height < spend_height + ANTI_REORG_DELAY - 1,
+			None => true,
+		});
+		self.sweep(&mut state);
+	}
+
+	fn block_disconnected(&self, _header: &BlockHeader, disconnected_height: u32) {
+		let mut state = self.state.lock().unwrap();
+		state.best_height = disconnected_height.saturating_sub(1);
+		for output in state.outputs.iter_mut() {
+			if output.spend_height.map(|spend_height| spend_height >= disconnected_height).unwrap_or(false) {
+				output.spend_height = None;
+			}
+		}
+	}
+}
+
+impl<B: Deref, F: Deref, K: Deref, C: Deref, L: Deref> Writeable for OutputSweeper<B, F, K, C, L>
+	where B::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      K::Target: OutputSpender,
+	      C::Target: ChainWatchInterface,
+	      L::Target: Logger,
+{
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		let state = self.state.lock().unwrap();
+		self.destination_script.write(w)?;
+		state.best_height.write(w)?;
+		(state.outputs.len() as u64).write(w)?;
+		for output in state.outputs.iter() {
+			output.descriptor.write(w)?;
+			output.added_height.write(w)?;
+			output.spend_height.write(w)?;
+		}
+		match state.pending_sweep {
+			Some(ref pending_sweep) => {
+				1u8.write(w)?;
+				pending_sweep.tx.write(w)?;
+				pending_sweep.feerate_sat_per_1000_weight.write(w)?;
+				pending_sweep.broadcast_height.write(w)?;
+			},
+			None => 0u8.write(w)?,
+		}
+		Ok(())
+	}
+}
+
+impl<B: Deref, F: Deref, K: Deref, C: Deref, L: Deref> ReadableArgs<(B, F, K, C, L)> for OutputSweeper<B, F, K, C, L>
+	where B::Target: BroadcasterInterface,
+	      F::Target: FeeEstimator,
+	      K::Target: OutputSpender,
+	      C::Target: ChainWatchInterface,
+	      L::Target: Logger,
+{
+	fn read<R: Read>(r: &mut R, args: (B, F, K, C, L)) -> Result<Self, DecodeError> {
+		let (broadcaster, fee_estimator, output_spender, chain_watch, logger) = args;
+		let destination_script = Readable::read(r)?;
+		let best_height = Readable::read(r)?;
+		let sweeper = Self::new(destination_script, best_height, broadcaster, fee_estimator, output_spender, chain_watch, logger);
+		{
+			let mut state = sweeper.state.lock().unwrap();
+			let output_count: u64 = Readable::read(r)?;
+			for _ in 0..output_count {
+				let descriptor = Readable::read(r)?;
+				let added_height = Readable::read(r)?;
+				let spend_height = Readable::read(r)?;
+				// The ChainWatchInterface doesn't persist watched outpoints, so we re-register ours.
+				sweeper.watch_output(&descriptor);
+				state.outputs.push(TrackedOutput { descriptor, added_height, spend_height });
+			}
+			state.pending_sweep = match <u8 as Readable>::read(r)? {
+				0 => None,
+				1 => Some(PendingSweep {
+					tx: Readable::read(r)?,
+					feerate_sat_per_1000_weight: Readable::read(r)?,
+					broadcast_height: Readable::read(r)?,
+				}),
+				_ => return Err(DecodeError::InvalidValue),
+			};
+		}
+		Ok(sweeper)
+	}
+}
@@ -16,6 +16,8 @@ use ln::features::{ChannelFeatures, InitFeatures};
 use ln::msgs;
 use ln::msgs::OptionalField;
 use ln::channelmonitor::MonitorEvent;
+use routing::router::RouteHop;
+use routing::scorer::Score;
 use util::enforcing_trait_impls::EnforcingChannelKeys;
 use util::events;
 use util::logger::{Logger, Level, Record};
@@ -62,6 +64,8 @@ impl chaininterface::FeeEstimator for TestFeeEstimator {
 pub struct TestChannelMonitor<'a> {
 	pub added_monitors: Mutex<Vec<(OutPoint, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>>,
 	pub latest_monitor_update_id: Mutex<HashMap<[u8; 32], (OutPoint, u64)>>,
+	/// Every update passed to update_monitor, in order.
+	pub monitor_updates: Mutex<Vec<(OutPoint, channelmonitor::ChannelMonitorUpdate)>>,
 	pub simple_monitor: channelmonitor::SimpleManyChannelMonitor<OutPoint, EnforcingChannelKeys, &'a chaininterface::BroadcasterInterface, &'a TestFeeEstimator, &'a TestLogger, &'a ChainWatchInterface>,
 	pub update_ret: Mutex<Result<(), channelmonitor::ChannelMonitorUpdateErr>>,
 	// If this is set to Some(), after the next return, we'll always return this until update_ret
@@ -73,6 +77,7 @@ impl<'a> TestChannelMonitor<'a> {
 		Self {
 			added_monitors: Mutex::new(Vec::new()),
 			latest_monitor_update_id: Mutex::new(HashMap::new()),
+			monitor_updates: Mutex::new(Vec::new()),
 			simple_monitor: channelmonitor::SimpleManyChannelMonitor::new(chain_monitor, broadcaster, logger, fee_estimator),
 			update_ret: Mutex::new(Ok(())),
 			next_update_ret: Mutex::new(None),
@@ -109,6 +114,7 @@ impl<'a> channelmonitor::ManyChannelMonitor for TestChannelMonitor<'a> {
 				&mut ::std::io::Cursor::new(&w.0)).unwrap() == update);
 
 		self.latest_monitor_update_id.lock().unwrap().insert(funding_txo.to_channel_id(), (funding_txo, update.update_id));
+		self.monitor_updates.lock().unwrap().push((funding_txo, update.clone()));
 		assert!(self.simple_monitor.update_monitor(funding_txo, update).is_ok());
 		// At every point where we get a monitor update, we should be able to send a useful monitor
 		// to a watchtower and disk...
@@ -160,8 +166,21 @@ impl msgs::ChannelMessageHandler for TestChannelMessageHandler {
 	fn handle_funding_created(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingCreated) {}
 	fn handle_funding_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingSigned) {}
 	fn handle_funding_locked(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingLocked) {}
+	fn handle_open_channel_v2(&self, _their_node_id: &PublicKey, _their_features: InitFeatures, _msg: &msgs::OpenChannelV2) {}
+	fn handle_accept_channel_v2(&self, _their_node_id: &PublicKey, _their_features: InitFeatures, _msg: &msgs::AcceptChannelV2) {}
 	fn handle_shutdown(&self, _their_node_id: &PublicKey, _msg: &msgs::Shutdown) {}
 	fn handle_closing_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::ClosingSigned) {}
+	fn handle_stfu(&self, _their_node_id: &PublicKey, _msg: &msgs::Stfu) {}
+	fn handle_splice_init(&self, _their_node_id: &PublicKey, _msg: &msgs::SpliceInit) {}
+	fn handle_splice_ack(&self, _their_node_id: &PublicKey, _msg: &msgs::SpliceAck) {}
+	fn handle_splice_locked(&self, _their_node_id: &PublicKey, _msg: &msgs::SpliceLocked) {}
+	fn handle_tx_add_input(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAddInput) {}
+	fn handle_tx_add_output(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAddOutput) {}
+	fn handle_tx_remove_input(&self, _their_node_id: &PublicKey, _msg: &msgs::TxRemoveInput) {}
+	fn handle_tx_remove_output(&self, _their_node_id: &PublicKey, _msg: &msgs::TxRemoveOutput) {}
+	fn handle_tx_complete(&self, _their_node_id: &PublicKey, _msg: &msgs::TxComplete) {}
+	fn handle_tx_signatures(&self, _their_node_id: &PublicKey, _msg: &msgs::TxSignatures) {}
+	fn handle_tx_abort(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAbort) {}
 	fn handle_update_add_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateAddHTLC) {}
 	fn handle_update_fulfill_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFulfillHTLC) {}
 	fn handle_update_fail_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFailHTLC) {}
@@ -213,7 +232,7 @@ fn get_dummy_channel_announcement(short_chan_id: u64) -> msgs::ChannelAnnounceme
 	}
 }
 
-fn get_dummy_channel_update(short_chan_id: u64) -> msgs::ChannelUpdate {
+pub fn get_dummy_channel_update(short_chan_id: u64) -> msgs::ChannelUpdate {
 	use bitcoin::secp256k1::ffi::Signature as FFISignature;
 	let network = Network::Testnet;
 	msgs::ChannelUpdate {
@@ -238,6 +257,8 @@ pub struct TestRoutingMessageHandler {
 	pub chan_anns_recvd: AtomicUsize,
 	pub chan_anns_sent: AtomicUsize,
 	pub request_full_sync: AtomicBool,
+	/// Whether channel_updates should be accepted and relayed, rather than ignored.
+	pub relay_chan_upds: AtomicBool,
 }
 
 impl TestRoutingMessageHandler {
@@ -247,6 +268,7 @@ impl TestRoutingMessageHandler {
 			chan_anns_recvd: AtomicUsize::new(0),
 			chan_anns_sent: AtomicUsize::new(0),
 			request_full_sync: AtomicBool::new(false),
+			relay_chan_upds: AtomicBool::new(false),
 		}
 	}
 }
@@ -260,6 +282,9 @@ impl msgs::RoutingMessageHandler for TestRoutingMessageHandler {
 	}
 	fn handle_channel_update(&self, _msg: &msgs::ChannelUpdate) -> Result<bool, msgs::LightningError> {
 		self.chan_upds_recvd.fetch_add(1, Ordering::AcqRel);
+		if self.relay_chan_upds.load(Ordering::Acquire) {
+			return Ok(true);
+		}
 		Err(msgs::LightningError { err: "".to_owned(), action: msgs::ErrorAction::IgnoreError })
 	}
 	fn handle_htlc_fail_channel_update(&self, _update: &msgs::HTLCFailChannelUpdate) {}
@@ -286,6 +311,51 @@ impl msgs::RoutingMessageHandler for TestRoutingMessageHandler {
 	fn should_request_full_sync(&self, _node_id: &PublicKey) -> bool {
 		self.request_full_sync.load(Ordering::Acquire)
 	}
+
+	fn handle_query_channel_range(&self, _their_node_id: &PublicKey, _msg: &msgs::QueryChannelRange) -> Result<(), msgs::LightningError> {
+		Ok(())
+	}
+
+	fn handle_query_short_channel_ids(&self, _their_node_id: &PublicKey, _msg: &msgs::QueryShortChannelIds) -> Result<(), msgs::LightningError> {
+		Ok(())
+	}
+
+	fn sync_routing_table(&self, _their_node_id: &PublicKey, _init: &msgs::Init) {}
+
+	fn peer_disconnected(&self, _their_node_id: &PublicKey) {}
+
+	fn handle_reply_channel_range(&self, _their_node_id: &PublicKey, _msg: &msgs::ReplyChannelRange) -> Result<(), msgs::LightningError> {
+		Ok(())
+	}
+
+	fn handle_reply_short_channel_ids_end(&self, _their_node_id: &PublicKey, _msg: &msgs::ReplyShortChannelIdsEnd) -> Result<(), msgs::LightningError> {
+		Ok(())
+	}
+}
+
+impl events::MessageSendEventsProvider for TestRoutingMessageHandler {
+	fn get_and_clear_pending_msg_events(&self) -> Vec<events::MessageSendEvent> {
+		Vec::new()
+	}
+}
+
+/// A Score which applies no penalties unless told to, so that routes are picked by fees alone.
+pub struct TestScorer {
+	pub channel_penalties_msat: Mutex<HashMap<u64, u64>>,
+}
+
+impl TestScorer {
+	pub fn new() -> Self {
+		TestScorer { channel_penalties_msat: Mutex::new(HashMap::new()) }
+	}
+}
+
+impl Score for TestScorer {
+	fn channel_penalty_msat(&self, short_channel_id: u64, _amount_msat: u64, _source: &PublicKey, _target: &PublicKey) -> u64 {
+		*self.channel_penalties_msat.lock().unwrap().get(&short_channel_id).unwrap_or(&0)
+	}
+	fn payment_path_failed(&self, _path: &[RouteHop], _short_channel_id: u64) {}
+	fn payment_path_successful(&self, _path: &[RouteHop]) {}
 }
 
 pub struct TestLogger {
@@ -393,6 +463,12 @@ impl TestKeysInterface {
 	}
 }
 
+impl keysinterface::OutputSpender for TestKeysInterface {
+	fn spend_spendable_outputs(&self, descriptors: &[keysinterface::SpendableOutputDescriptor], destination_script: Script, feerate_sat_per_1000_weight: u32) -> Result<Transaction, ()> {
+		self.backing.spend_spendable_outputs(descriptors, destination_script, feerate_sat_per_1000_weight)
+	}
+}
+
 pub struct TestChainWatcher {
 	pub utxo_ret: Mutex<Result<(Script, u64), ChainError>>,
 }